@@ -0,0 +1,204 @@
+//! Benchmarks for parsing and settling transactions. The `process_csv`
+//! benchmarks run on synthetic files of one and ten million rows, which are
+//! written to the temporary directory on first use. Other sizes can be
+//! picked with `PAYMENTS_BENCH_ROWS`, e.g. `PAYMENTS_BENCH_ROWS=100000`.
+//! The `process_files` benchmarks run on the same files, comparing reading
+//! them with the `csv::Reader` to mapping them into memory, which needs the
+//! `mmap` feature: `cargo bench --features mmap -- process_files`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use csv::StringRecord;
+use payments::amount::Amount;
+use payments::id::{ClientId, TxId};
+use payments::input::{make_input_record, CsvReader, InputRecord, TransactionType};
+use payments::output::make_client_output_records;
+use payments::{process_csv, process_files_with, ProcessOptions};
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// A mix of every transaction type over a thousand clients, with disputes,
+/// resolves and chargebacks referring back to earlier deposits.
+fn write_rows<W: Write>(mut writer: W, rows: u64) -> std::io::Result<()> {
+    writeln!(writer, "type,client,tx,amount")?;
+    for tx in 1..=rows {
+        let client = tx % 1000;
+        match tx % 10 {
+            7 => writeln!(writer, "withdrawal,{},{},1.5", client, tx)?,
+            8 => writeln!(writer, "dispute,{},{},", client, tx.saturating_sub(3000))?,
+            9 if tx % 20 == 9 => {
+                writeln!(writer, "resolve,{},{},", client, tx.saturating_sub(3001))?
+            }
+            9 => writeln!(writer, "chargeback,{},{},", client, tx.saturating_sub(3001))?,
+            _ => writeln!(
+                writer,
+                "deposit,{},{},{}.{:04}",
+                client,
+                tx,
+                tx % 100,
+                tx % 10_000
+            )?,
+        }
+    }
+    writer.flush()
+}
+
+/// The synthetic input file with `rows` rows, created if it doesn't exist.
+fn input_file(rows: u64) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("payments-bench-{}.csv", rows));
+    if !path.exists() {
+        let tmp = path.with_extension("tmp");
+        write_rows(BufWriter::new(File::create(&tmp).unwrap()), rows).unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+    }
+    path
+}
+
+fn bench_rows() -> Vec<u64> {
+    match std::env::var("PAYMENTS_BENCH_ROWS") {
+        Ok(rows) => rows
+            .split(',')
+            .map(|n| {
+                n.trim()
+                    .parse()
+                    .expect("PAYMENTS_BENCH_ROWS expects numbers")
+            })
+            .collect(),
+        Err(_) => vec![1_000_000, 10_000_000],
+    }
+}
+
+fn bench_make_input_record(c: &mut Criterion) {
+    let deposit = StringRecord::from(vec!["deposit", "1", "1", "123.4567"]);
+    let dispute = StringRecord::from(vec!["dispute", "1", "1", ""]);
+    c.bench_function("make_input_record/deposit", |b| {
+        b.iter(|| make_input_record(black_box(&deposit)))
+    });
+    c.bench_function("make_input_record/dispute", |b| {
+        b.iter(|| make_input_record(black_box(&dispute)))
+    });
+}
+
+/// Only reads and parses the rows, without applying them, from memory so
+/// the disk does not get in the way.
+fn bench_read_csv(c: &mut Criterion) {
+    let mut data = Vec::new();
+    write_rows(&mut data, 1_000_000).unwrap();
+    let mut group = c.benchmark_group("read_csv");
+    group.throughput(Throughput::Elements(1_000_000));
+    group.bench_function("1000000", |b| {
+        b.iter(|| {
+            CsvReader::new(data.as_slice())
+                .filter(Result::is_ok)
+                .count()
+        })
+    });
+    group.finish();
+}
+
+fn bench_process_csv(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_csv");
+    group.sample_size(10);
+    for rows in bench_rows() {
+        let path = input_file(rows);
+        group.throughput(Throughput::Elements(rows));
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &path, |b, path| {
+            b.iter(|| process_csv(path).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Reads and maps the input files with as many parser threads as there are
+/// cores, so the reading is what tells them apart.
+fn bench_process_files(c: &mut Criterion) {
+    let parse_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut modes = vec![("read", Some(u64::MAX))];
+    if cfg!(feature = "mmap") {
+        modes.push(("mapped", Some(0)));
+    }
+
+    let mut group = c.benchmark_group("process_files");
+    group.sample_size(10);
+    for rows in bench_rows() {
+        let path = input_file(rows);
+        group.throughput(Throughput::Elements(rows));
+        for (name, mmap_threshold) in &modes {
+            let options = ProcessOptions {
+                parse_threads,
+                mmap_threshold: *mmap_threshold,
+                ..ProcessOptions::default()
+            };
+            group.bench_with_input(BenchmarkId::new(*name, rows), &path, |b, path| {
+                b.iter(|| process_files_with(&[path], &options).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_make_client_output_records(c: &mut Criterion) {
+    let mut data = Vec::new();
+    write_rows(&mut data, 100_000).unwrap();
+    let records: Vec<InputRecord> = CsvReader::new(&data[..]).map(Result::unwrap).collect();
+
+    let mut group = c.benchmark_group("make_client_output_records");
+    group.throughput(Throughput::Elements(records.len() as u64));
+    group.bench_function("100000", |b| {
+        b.iter(|| make_client_output_records(records.iter().cloned()))
+    });
+    group.finish();
+}
+
+/// Disputes and resolves every deposit of an input, always going for the
+/// oldest ones first. The engine looks transactions up in an index, so the
+/// time per record stays the same as the input grows; with a scan over all
+/// earlier transactions it would grow with the input.
+fn bench_disputes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("disputes");
+    for deposits in [10_000u32, 100_000, 1_000_000] {
+        let record = |r#type, tx, amount| InputRecord {
+            r#type,
+            client: ClientId::from(tx % 1000),
+            tx: TxId::from(tx),
+            amount,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
+        };
+        let mut records: Vec<InputRecord> = (1..=deposits)
+            .map(|tx| {
+                record(
+                    TransactionType::Deposit,
+                    tx,
+                    Some(Amount::from_scaled(10_000)),
+                )
+            })
+            .collect();
+        for tx in 1..=deposits {
+            records.push(record(TransactionType::Dispute, tx, None));
+            records.push(record(TransactionType::Resolve, tx, None));
+        }
+
+        group.throughput(Throughput::Elements(records.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(deposits),
+            &records,
+            |b, records| b.iter(|| make_client_output_records(records.iter().cloned())),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_make_input_record,
+    bench_read_csv,
+    bench_process_csv,
+    bench_process_files,
+    bench_make_client_output_records,
+    bench_disputes
+);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+/// Generates the gRPC service from `proto/payments.proto`. `protoc` comes
+/// with the build dependencies, so it does not have to be installed.
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/payments.proto");
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("no protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/payments.proto"], &["proto"])
+        .expect("failed to compile proto/payments.proto");
+}
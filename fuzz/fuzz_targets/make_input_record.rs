@@ -0,0 +1,18 @@
+#![no_main]
+
+//! Feeds arbitrary bytes, read as CSV rows, into `make_input_record`. Any
+//! input has to come back as either a record or an `Error`, never a panic.
+
+use libfuzzer_sys::fuzz_target;
+use payments::input::make_input_record;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    for s_record in reader.records().flatten() {
+        let _ = make_input_record(&s_record);
+    }
+});
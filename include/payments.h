@@ -0,0 +1,50 @@
+/*
+ * The C API of the payments engine, built with the ffi feature:
+ *
+ *     cargo rustc --lib --crate-type cdylib --release --features ffi
+ *
+ * See src/ffi.rs for the details of every function.
+ */
+#ifndef PAYMENTS_H
+#define PAYMENTS_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* What payments_engine_apply_csv_line returns. */
+#define PAYMENTS_APPLIED 0
+#define PAYMENTS_INVALID 1
+#define PAYMENTS_REJECTED 2
+#define PAYMENTS_BAD_ARGUMENT (-1)
+/* The engine panicked, which is a bug. Free the engine afterwards. */
+#define PAYMENTS_PANICKED (-2)
+
+typedef struct PaymentsEngine PaymentsEngine;
+
+/* Creates an engine with the default policies. Free it with
+ * payments_engine_free. */
+PaymentsEngine *payments_engine_new(void);
+
+/* Parses a CSV row without a header, type,client,tx,amount, and applies it.
+ * Returns PAYMENTS_APPLIED, or another code and payments_last_error tells
+ * why. An engine must not be used by two threads at the same time. */
+int payments_engine_apply_csv_line(PaymentsEngine *engine, const char *line);
+
+/* The balances of every client as a JSON array, or NULL on error, such as
+ * a panic of the engine. Free it with payments_string_free. */
+char *payments_engine_dump_json(const PaymentsEngine *engine);
+
+void payments_string_free(char *s);
+
+void payments_engine_free(PaymentsEngine *engine);
+
+/* Why the last call on this thread that failed did so, or NULL. Owned by
+ * the library and valid until the next call that fails on the thread. */
+const char *payments_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,550 @@
+use super::amount::Amount;
+use super::currency::Currency;
+use super::engine::{OverdraftPolicy, RejectReason};
+use super::id::{ClientId, TxId};
+use super::output::OutputRecord;
+use super::tenant::Tenant;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Where an account stands in its lifecycle. Money only moves in and out
+/// of an open account. A frozen account keeps its funds where they are until
+/// it is unfrozen, though disputes on it still run their course, as they
+/// are not up to the client. A closed account has no funds left and takes
+/// no transactions at all until it is opened again.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    Open,
+    Frozen,
+    Closed,
+}
+
+impl AccountStatus {
+    fn is_open(&self) -> bool {
+        *self == AccountStatus::Open
+    }
+
+    /// The name of the status as it is stored.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccountStatus::Open => "open",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Closed => "closed",
+        }
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(AccountStatus::Open),
+            "frozen" => Ok(AccountStatus::Frozen),
+            "closed" => Ok(AccountStatus::Closed),
+            _ => Err(format!("unknown account status '{}'", s)),
+        }
+    }
+}
+
+/// Why an account is locked: the chargeback that locked it, and when that
+/// happened, if the chargeback had a timestamp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lock {
+    /// The transaction that was charged back.
+    pub tx: TxId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_at: Option<DateTime<Utc>>,
+}
+
+/// An `Account` holds the funds of a single client in a single currency, or
+/// in no currency in particular if the input doesn't say, and of a single
+/// tenant, if the run is shared by several. All of the rules about
+/// how money may move in and out of an account live here, so the `Engine`
+/// only has to decide which of these operations a record calls for.
+///
+/// The total of an account is not stored, but always derived from the
+/// available and held funds, so the three can never disagree. Every
+/// operation either succeeds completely or leaves the account untouched.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    client: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<Tenant>,
+    available: Amount,
+    held: Amount,
+    locked: bool,
+    /// Why the account is locked. Unknown for an account locked before this
+    /// was kept track of, such as in an older snapshot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lock: Option<Lock>,
+    #[serde(default, skip_serializing_if = "AccountStatus::is_open")]
+    status: AccountStatus,
+    /// The last month the monthly fee was charged for, see `FeeSchedule`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    billed_month: Option<i32>,
+    /// The number of deposits and withdrawals applied to the account, see
+    /// `DisputeWindow::Transactions`.
+    #[serde(default)]
+    transactions: u64,
+}
+
+impl Account {
+    /// Opens a new, empty and unlocked account for `client`.
+    pub fn new(client: ClientId) -> Self {
+        Account::with_currency(client, None)
+    }
+
+    /// Opens a new, empty and unlocked account for `client` in `currency`.
+    pub fn with_currency(client: ClientId, currency: Option<Currency>) -> Self {
+        Account::with_tenant(client, currency, None)
+    }
+
+    /// Opens a new, empty and unlocked account for `client` of `tenant` in
+    /// `currency`.
+    pub fn with_tenant(
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Self {
+        Account {
+            client,
+            currency,
+            tenant,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            locked: false,
+            lock: None,
+            status: AccountStatus::Open,
+            billed_month: None,
+            transactions: 0,
+        }
+    }
+
+    /// Puts an account back together from its parts, as written out by a
+    /// store.
+    #[cfg(feature = "sqlite")]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_parts(
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+        available: Amount,
+        held: Amount,
+        locked: bool,
+        lock: Option<Lock>,
+        status: AccountStatus,
+        billed_month: Option<i32>,
+        transactions: u64,
+    ) -> Self {
+        Account {
+            client,
+            currency,
+            tenant,
+            available,
+            held,
+            locked,
+            lock,
+            status,
+            billed_month,
+            transactions,
+        }
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Why the account is locked, if it is and that is known.
+    pub fn lock(&self) -> Option<Lock> {
+        self.lock
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    pub(crate) fn billed_month(&self) -> Option<i32> {
+        self.billed_month
+    }
+
+    /// Notes that the monthly fee has been charged up to and including
+    /// `month`. An earlier month than the last one changes nothing.
+    pub(crate) fn bill(&mut self, month: i32) {
+        self.billed_month = self.billed_month.max(Some(month));
+    }
+
+    pub(crate) fn transactions(&self) -> u64 {
+        self.transactions
+    }
+
+    /// Counts a deposit or withdrawal applied to the account.
+    pub(crate) fn count_transaction(&mut self) {
+        self.transactions += 1;
+    }
+
+    /// Credits `amount` to the available funds, which has to be above zero.
+    pub fn deposit(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.check_open()?;
+        check_positive(amount)?;
+        self.update(add(self.available, amount)?, self.held)
+    }
+
+    /// Debits `amount`, which has to be above zero, from the available
+    /// funds, as long as `overdraft` allows the available funds to end up
+    /// where they would.
+    pub fn withdraw(
+        &mut self,
+        amount: Amount,
+        overdraft: OverdraftPolicy,
+    ) -> Result<(), RejectReason> {
+        self.check_open()?;
+        check_positive(amount)?;
+        let available = sub(self.available, amount)?;
+        if !overdraft.allows(available) {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        self.update(available, self.held)
+    }
+
+    /// Moves `amount` from the available to the held funds while a dispute
+    /// is open. The available funds may go negative if the disputed money
+    /// has already been spent. A negative amount moves funds the other way,
+    /// which is what disputing a withdrawal does.
+    pub fn hold(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.update(sub(self.available, amount)?, add(self.held, amount)?)
+    }
+
+    /// Moves `amount` back from the held to the available funds once a
+    /// dispute has been resolved. This undoes a `hold` of the same amount.
+    pub fn release(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.update(add(self.available, amount)?, sub(self.held, amount)?)
+    }
+
+    /// Removes the held `amount` from the account for good and locks it.
+    pub fn chargeback(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.update(self.available, sub(self.held, amount)?)?;
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Holds `amount` as a credit pending from the bank while a dispute on a
+    /// withdrawal is open, see `WithdrawalDisputes::Pending`. The available
+    /// funds are left alone.
+    pub fn credit_pending(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.update(self.available, add(self.held, amount)?)
+    }
+
+    /// Drops the pending credit of `amount` once the dispute on a withdrawal
+    /// has been resolved. This undoes a `credit_pending` of the same amount.
+    pub fn drop_pending(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.update(self.available, sub(self.held, amount)?)
+    }
+
+    /// Refunds the pending credit of `amount` to the available funds once a
+    /// withdrawal has been charged back, and locks the account like any
+    /// other chargeback does.
+    pub fn refund_pending(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.update(add(self.available, amount)?, sub(self.held, amount)?)?;
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Notes why the account was locked, unless it is not locked or was
+    /// locked before, in which case the first lock is what counts.
+    pub(crate) fn note_lock(&mut self, lock: Lock) {
+        if self.locked && self.lock.is_none() {
+            self.lock = Some(lock);
+        }
+    }
+
+    /// Lifts the lock a chargeback put on the account, such as once the
+    /// client has been cleared, so money can move again.
+    pub fn unlock(&mut self) -> Result<(), RejectReason> {
+        if !self.locked {
+            return Err(RejectReason::AccountNotLocked);
+        }
+        self.locked = false;
+        self.lock = None;
+        Ok(())
+    }
+
+    /// Stops money from moving in or out of an open account.
+    pub fn freeze(&mut self) -> Result<(), RejectReason> {
+        self.check_open()?;
+        self.status = AccountStatus::Frozen;
+        Ok(())
+    }
+
+    /// Lets money move again after a `freeze`.
+    pub fn unfreeze(&mut self) -> Result<(), RejectReason> {
+        match self.status {
+            AccountStatus::Frozen => {
+                self.status = AccountStatus::Open;
+                Ok(())
+            }
+            AccountStatus::Open => Err(RejectReason::AccountNotFrozen),
+            AccountStatus::Closed => Err(RejectReason::AccountClosed),
+        }
+    }
+
+    /// Closes the account, which is only possible once nothing is left in
+    /// it, neither available nor held.
+    pub fn close(&mut self) -> Result<(), RejectReason> {
+        if self.status == AccountStatus::Closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        if self.available != Amount::ZERO || self.held != Amount::ZERO {
+            return Err(RejectReason::NonZeroBalance);
+        }
+        self.status = AccountStatus::Closed;
+        Ok(())
+    }
+
+    /// Opens a closed account again.
+    pub fn reopen(&mut self) -> Result<(), RejectReason> {
+        match self.status {
+            AccountStatus::Closed => {
+                self.status = AccountStatus::Open;
+                Ok(())
+            }
+            _ => Err(RejectReason::AccountExists),
+        }
+    }
+
+    fn check_open(&self) -> Result<(), RejectReason> {
+        match self.status {
+            AccountStatus::Open => Ok(()),
+            AccountStatus::Frozen => Err(RejectReason::AccountFrozen),
+            AccountStatus::Closed => Err(RejectReason::AccountClosed),
+        }
+    }
+
+    /// Stores the new balances, but only if their total can still be
+    /// computed. If it cannot, the account would no longer be reportable.
+    fn update(&mut self, available: Amount, held: Amount) -> Result<(), RejectReason> {
+        add(available, held)?;
+        self.available = available;
+        self.held = held;
+        Ok(())
+    }
+}
+
+impl From<&Account> for OutputRecord {
+    fn from(account: &Account) -> Self {
+        OutputRecord {
+            currency: account.currency,
+            tenant: account.tenant,
+            ..OutputRecord::new(
+                account.client,
+                account.available,
+                account.held,
+                account.total(),
+                account.locked,
+            )
+        }
+    }
+}
+
+fn add(a: Amount, b: Amount) -> Result<Amount, RejectReason> {
+    a.checked_add(b).ok_or(RejectReason::Overflow)
+}
+
+fn sub(a: Amount, b: Amount) -> Result<Amount, RejectReason> {
+    a.checked_sub(b).ok_or(RejectReason::Overflow)
+}
+
+/// Money only moves one way in a deposit or withdrawal, so a negative
+/// amount cannot turn one into the other, nor can a zero one slip through.
+fn check_positive(amount: Amount) -> Result<(), RejectReason> {
+    match amount > Amount::ZERO {
+        true => Ok(()),
+        false => Err(RejectReason::NonPositiveAmount),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Account, AccountStatus, Lock};
+    use crate::amount::Amount;
+    use crate::engine::{OverdraftPolicy, RejectReason};
+    use crate::id::{ClientId, TxId};
+
+    fn funded(scaled: i64) -> Account {
+        let mut account = Account::new(ClientId::new(1));
+        account.deposit(Amount::from_scaled(scaled)).unwrap();
+        account
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw() {
+        let mut account = funded(100_000);
+        account
+            .withdraw(Amount::from_scaled(25_000), OverdraftPolicy::Reject)
+            .unwrap();
+        assert_eq!(account.available(), Amount::from_scaled(75_000));
+        assert_eq!(account.total(), Amount::from_scaled(75_000));
+    }
+
+    #[test]
+    fn test_withdraw_insufficient_funds() {
+        let mut account = funded(10_000);
+        assert_eq!(
+            account.withdraw(Amount::from_scaled(10_001), OverdraftPolicy::Reject),
+            Err(RejectReason::InsufficientFunds)
+        );
+        assert_eq!(account, funded(10_000));
+    }
+
+    #[test]
+    fn test_withdraw_into_overdraft() {
+        let mut account = funded(10_000);
+        account
+            .withdraw(Amount::from_scaled(30_000), OverdraftPolicy::AllowNegative)
+            .unwrap();
+        assert_eq!(account.available(), Amount::from_scaled(-20_000));
+
+        let limit = OverdraftPolicy::AllowUpToLimit(Amount::from_scaled(25_000));
+        account.withdraw(Amount::from_scaled(5_000), limit).unwrap();
+        assert_eq!(
+            account.withdraw(Amount::from_scaled(1), limit),
+            Err(RejectReason::InsufficientFunds)
+        );
+        assert_eq!(account.total(), Amount::from_scaled(-25_000));
+    }
+
+    #[test]
+    fn test_hold_and_release() {
+        let mut account = funded(100_000);
+        account.hold(Amount::from_scaled(40_000)).unwrap();
+        assert_eq!(account.available(), Amount::from_scaled(60_000));
+        assert_eq!(account.held(), Amount::from_scaled(40_000));
+        assert_eq!(account.total(), Amount::from_scaled(100_000));
+
+        account.release(Amount::from_scaled(40_000)).unwrap();
+        assert_eq!(account, funded(100_000));
+    }
+
+    #[test]
+    fn test_chargeback_removes_held_funds_and_locks() {
+        let mut account = funded(100_000);
+        account.hold(Amount::from_scaled(40_000)).unwrap();
+        account.chargeback(Amount::from_scaled(40_000)).unwrap();
+        assert_eq!(account.held(), Amount::ZERO);
+        assert_eq!(account.total(), Amount::from_scaled(60_000));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut account = funded(100_000);
+        assert_eq!(account.unlock(), Err(RejectReason::AccountNotLocked));
+        let lock = |tx| Lock {
+            tx: TxId::new(tx),
+            locked_at: None,
+        };
+        account.note_lock(lock(1));
+        assert_eq!(account.lock(), None);
+
+        account.hold(Amount::from_scaled(40_000)).unwrap();
+        account.chargeback(Amount::from_scaled(40_000)).unwrap();
+        account.note_lock(lock(2));
+        account.note_lock(lock(3));
+        assert_eq!(account.lock(), Some(lock(2)));
+        account.unlock().unwrap();
+        assert!(!account.is_locked());
+        assert_eq!(account.lock(), None);
+        assert_eq!(account.available(), Amount::from_scaled(60_000));
+    }
+
+    #[test]
+    fn test_pending_credit() {
+        let mut account = funded(100_000);
+        account.credit_pending(Amount::from_scaled(40_000)).unwrap();
+        assert_eq!(account.available(), Amount::from_scaled(100_000));
+        assert_eq!(account.held(), Amount::from_scaled(40_000));
+        assert_eq!(account.total(), Amount::from_scaled(140_000));
+
+        account.drop_pending(Amount::from_scaled(40_000)).unwrap();
+        assert_eq!(account, funded(100_000));
+
+        account.credit_pending(Amount::from_scaled(40_000)).unwrap();
+        account.refund_pending(Amount::from_scaled(40_000)).unwrap();
+        assert_eq!(account.available(), Amount::from_scaled(140_000));
+        assert_eq!(account.held(), Amount::ZERO);
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut account = funded(10_000);
+        account.freeze().unwrap();
+        assert_eq!(
+            account.deposit(Amount::from_scaled(1)),
+            Err(RejectReason::AccountFrozen)
+        );
+        // Disputes still go through on a frozen account.
+        account.hold(Amount::from_scaled(10_000)).unwrap();
+        account.release(Amount::from_scaled(10_000)).unwrap();
+        account.unfreeze().unwrap();
+        assert_eq!(account.unfreeze(), Err(RejectReason::AccountNotFrozen));
+
+        assert_eq!(account.close(), Err(RejectReason::NonZeroBalance));
+        account
+            .withdraw(Amount::from_scaled(10_000), OverdraftPolicy::Reject)
+            .unwrap();
+        account.close().unwrap();
+        assert_eq!(account.status(), AccountStatus::Closed);
+        assert_eq!(
+            account.withdraw(Amount::ZERO, OverdraftPolicy::Reject),
+            Err(RejectReason::AccountClosed)
+        );
+        assert_eq!(account.freeze(), Err(RejectReason::AccountClosed));
+        account.reopen().unwrap();
+        assert_eq!(account.reopen(), Err(RejectReason::AccountExists));
+        assert_eq!(account, Account::new(ClientId::new(1)));
+    }
+
+    #[test]
+    fn test_overflow_leaves_account_untouched() {
+        let mut account = funded(i64::MAX);
+        assert_eq!(
+            account.deposit(Amount::from_scaled(1)),
+            Err(RejectReason::Overflow)
+        );
+        assert_eq!(
+            account.hold(Amount::from_scaled(-1)),
+            Err(RejectReason::Overflow)
+        );
+        assert_eq!(account, funded(i64::MAX));
+    }
+}
@@ -0,0 +1,753 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{ser::Serializer, Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+/// The number of decimal places every `Amount` is stored with.
+pub const DECIMAL_PLACES: u32 = 4;
+
+/// The factor between a whole unit of currency and the integer stored
+/// inside an `Amount`.
+const SCALE: i64 = 10_i64.pow(DECIMAL_PLACES);
+
+/// An `Amount` is a fixed-point decimal number with four decimal places,
+/// backed by a scaled `i64`. Using integers rather than `f64` means that
+/// balances never drift (think `0.1 + 0.2`) and that what is stored
+/// internally is exactly what gets reported.
+///
+/// The range is symmetric, from `-Amount::MAX` to `Amount::MAX`, leaving out
+/// `i64::MIN`, so every amount can be negated and parsed back from what it
+/// is written as. The operators saturate at either end of the range rather
+/// than panic or wrap around. Balances are kept with `checked_add` and
+/// `checked_sub` instead, so what does not fit is refused as
+/// `RejectReason::Overflow`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+/// Returned when a string cannot be parsed into an `Amount`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAmountError {
+    value: String,
+    too_precise: bool,
+}
+
+impl ParseAmountError {
+    fn new(value: &str) -> Self {
+        ParseAmountError {
+            value: value.to_string(),
+            too_precise: false,
+        }
+    }
+
+    /// Whether the amount was fine, but had more decimal places than
+    /// `Precision::Reject` allows.
+    pub fn is_too_precise(&self) -> bool {
+        self.too_precise
+    }
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.too_precise {
+            true => write!(
+                f,
+                "amount '{}' has more than {} decimal places",
+                self.value, DECIMAL_PLACES
+            ),
+            false => write!(f, "invalid amount '{}'", self.value),
+        }
+    }
+}
+
+/// What to do with an amount that has more decimal places than an `Amount`
+/// holds. Whatever the policy, it is applied as the amount is read, so the
+/// balances never hold more than what gets reported.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Precision {
+    /// The extra digits are dropped, rounding towards zero.
+    Truncate,
+    /// The amount is rounded half to even, also known as banker's rounding,
+    /// so rounding does not favor either side over many transactions.
+    #[default]
+    RoundHalfEven,
+    /// The amount is invalid, unless the extra digits are all zeros.
+    Reject,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "truncate" => Ok(Precision::Truncate),
+            "round-half-even" => Ok(Precision::RoundHalfEven),
+            "reject" => Ok(Precision::Reject),
+            _ => Err(format!("unknown precision policy '{}'", s)),
+        }
+    }
+}
+
+/// The character that separates the whole units from the fraction in
+/// amounts read leniently, see `Amount::parse_lenient`. The other one of
+/// `.` and `,` then separates groups of thousands.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DecimalSeparator {
+    /// `1,234.56`, as written in English.
+    #[default]
+    Point,
+    /// `1.234,56`, as written in much of continental Europe.
+    Comma,
+}
+
+impl FromStr for DecimalSeparator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "point" | "." => Ok(DecimalSeparator::Point),
+            "comma" | "," => Ok(DecimalSeparator::Comma),
+            _ => Err(format!("unknown decimal separator '{}'", s)),
+        }
+    }
+}
+
+/// How amounts are written out, see `Amount::formatted`. The default
+/// writes them just like `Display` does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DecimalFormat {
+    /// The number of decimal places to round to, which is 2, 4 or 8.
+    /// Rounding is half to even, just like when amounts are read. As
+    /// amounts are stored with four decimal places, eight only pads them
+    /// with zeros.
+    pub places: u32,
+    /// Always write all `places` decimal places, so `1.5` is written as
+    /// `1.5000`, rather than as few as possible.
+    pub fixed: bool,
+}
+
+impl DecimalFormat {
+    /// The numbers of decimal places amounts can be written with.
+    pub const PLACES: [u32; 3] = [2, 4, 8];
+
+    /// Parses a number of decimal places, which has to be one of `PLACES`.
+    pub fn parse_places(s: &str) -> Result<u32, String> {
+        match s.parse() {
+            Ok(places) if DecimalFormat::PLACES.contains(&places) => Ok(places),
+            _ => Err(format!("unknown number of decimal places '{}'", s)),
+        }
+    }
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        DecimalFormat {
+            places: DECIMAL_PLACES,
+            fixed: false,
+        }
+    }
+}
+
+/// An `Amount` written with a `DecimalFormat`, as a string wherever it is
+/// serialized to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Formatted {
+    amount: Amount,
+    format: DecimalFormat,
+}
+
+impl fmt::Display for Formatted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let DecimalFormat { places, fixed } = self.format;
+        let mut value = self.amount.0.unsigned_abs();
+        // Fewer places than are stored round the amount, half to even.
+        let mut width = DECIMAL_PLACES;
+        if places < DECIMAL_PLACES {
+            let factor = 10_u64.pow(DECIMAL_PLACES - places);
+            let (quotient, rest) = (value / factor, value % factor);
+            let round_up = rest > factor / 2 || (rest == factor / 2 && quotient % 2 == 1);
+            value = quotient + u64::from(round_up);
+            width = places;
+        }
+        let scale = 10_u64.pow(width);
+        let sign = if self.amount.0 < 0 && value != 0 {
+            "-"
+        } else {
+            ""
+        };
+        let fraction = format!("{:0width$}", value % scale, width = width as usize);
+        let fraction = match fixed {
+            // Places beyond those stored can only be zeros.
+            true => format!("{:0<width$}", fraction, width = places as usize),
+            false => match fraction.trim_end_matches('0') {
+                "" => "0".to_string(),
+                trimmed => trimmed.to_string(),
+            },
+        };
+        write!(f, "{}{}.{}", sign, value / scale, fraction)
+    }
+}
+
+impl Serialize for Formatted {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+/// How the amounts of the input are to be read, which the readers hand down
+/// to wherever an amount is parsed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub(crate) struct AmountFormat {
+    pub(crate) precision: Precision,
+    /// Set to accept amounts as people write them, with the given decimal
+    /// separator, rather than only plain decimal numbers.
+    pub(crate) lenient: Option<DecimalSeparator>,
+}
+
+impl AmountFormat {
+    pub(crate) fn parse(self, s: &str) -> Result<Amount, ParseAmountError> {
+        match self.lenient {
+            Some(separator) => Amount::parse_lenient(s, separator, self.precision),
+            None => Amount::parse_with(s, self.precision),
+        }
+    }
+
+    /// The same format without leniency, for amounts that are numbers to
+    /// begin with, such as those of JSON input.
+    pub(crate) fn plain(self) -> Self {
+        AmountFormat {
+            lenient: None,
+            ..self
+        }
+    }
+}
+
+/// Currency symbols that may be written before or after a lenient amount.
+const CURRENCY_SYMBOLS: &[char] = &[
+    '$', '¢', '£', '¥', '€', '₹', '₩', '₽', '₺', '₪', '₫', '₦', '₱', '₴', '₿',
+];
+
+/// Characters other than the thousands separator itself that commonly
+/// group the digits of a lenient amount: spaces, as in French, and
+/// apostrophes, as in Swiss German.
+const GROUP_SEPARATORS: &[char] = &[' ', '\u{a0}', '\u{202f}', '\''];
+
+/// Strips a currency symbol, an ISO 4217 code such as `USD`, or both as in
+/// `US$`, from either end of `s`, along with any whitespace around them.
+fn strip_currency(s: &str) -> &str {
+    let is_code = |code: &str| code.len() <= 3 && code.chars().all(|c| c.is_ascii_alphabetic());
+    let start = s.trim_start();
+    let letters = start.len()
+        - start
+            .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+            .len();
+    let start = match is_code(&start[..letters]) {
+        true => &start[letters..],
+        false => start,
+    };
+    let s = start.trim_start_matches(CURRENCY_SYMBOLS).trim();
+    let letters = s.len() - s.trim_end_matches(|c: char| c.is_ascii_alphabetic()).len();
+    let s = match is_code(&s[s.len() - letters..]) {
+        true => &s[..s.len() - letters],
+        false => s,
+    };
+    s.trim_end().trim_end_matches(CURRENCY_SYMBOLS).trim_end()
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// The largest amount there is, and, negated, the smallest.
+    pub const MAX: Amount = Amount(i64::MAX);
+
+    /// Builds an `Amount` from its scaled integer representation, i.e. the
+    /// number of ten-thousandths of a unit. `i64::MIN` is out of range and
+    /// taken for `-Amount::MAX`.
+    pub const fn from_scaled(value: i64) -> Self {
+        Amount(if value == i64::MIN { -i64::MAX } else { value })
+    }
+
+    /// Returns the scaled integer representation of this `Amount`.
+    pub const fn scaled(self) -> i64 {
+        self.0
+    }
+
+    /// The amount as `format` writes it, such as `1.5000` for four fixed
+    /// decimal places.
+    pub fn formatted(self, format: DecimalFormat) -> Formatted {
+        Formatted {
+            amount: self,
+            format,
+        }
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0
+            .checked_add(other.0)
+            .filter(|v| *v != i64::MIN)
+            .map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0
+            .checked_sub(other.0)
+            .filter(|v| *v != i64::MIN)
+            .map(Amount)
+    }
+
+    /// Parses a plain decimal string such as `"20"`, `"-1.5"` or `".25"`,
+    /// handling digits beyond the fourth decimal place as `precision` says.
+    pub fn parse_with(s: &str, precision: Precision) -> Result<Amount, ParseAmountError> {
+        Amount::parse_ascii(s.as_bytes(), precision).map_err(|too_precise| ParseAmountError {
+            too_precise,
+            ..ParseAmountError::new(s)
+        })
+    }
+
+    /// Parses an amount just like `parse_with`, but straight from the bytes
+    /// of a CSV field, which saves making sure they are text first. An
+    /// amount is plain ASCII, so anything else is refused anyway. The error
+    /// is whether the amount was only refused for being too precise.
+    pub(crate) fn parse_ascii(s: &[u8], precision: Precision) -> Result<Amount, bool> {
+        let (negative, unsigned) = match s.split_first() {
+            Some((b'-', rest)) => (true, rest),
+            Some((b'+', rest)) => (false, rest),
+            _ => (false, s),
+        };
+        let (whole, fraction) = match unsigned.iter().position(|b| *b == b'.') {
+            Some(point) => (&unsigned[..point], &unsigned[point + 1..]),
+            None => (unsigned, &[][..]),
+        };
+
+        // At least one digit has to be present, and nothing but digits
+        // may appear on either side of the decimal point.
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(false);
+        }
+        if !whole.iter().chain(fraction).all(|b| b.is_ascii_digit()) {
+            return Err(false);
+        }
+
+        let mut value: i64 = 0;
+        for b in whole {
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(i64::from(b - b'0')))
+                .ok_or(false)?;
+        }
+
+        for i in 0..DECIMAL_PLACES as usize {
+            let digit = fraction.get(i).map_or(0, |b| i64::from(b - b'0'));
+            value = value
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(digit))
+                .ok_or(false)?;
+        }
+
+        // Deal with whatever did not fit into four decimal places.
+        let rest = fraction.get(DECIMAL_PLACES as usize..).unwrap_or(&[]);
+        match (precision, rest.split_first()) {
+            (_, None) | (Precision::Truncate, _) => (),
+            (Precision::RoundHalfEven, Some((first, tail))) => {
+                let round_up = match first {
+                    b'6'..=b'9' => true,
+                    b'5' => tail.iter().any(|b| *b != b'0') || value % 2 == 1,
+                    _ => false,
+                };
+                if round_up {
+                    value = value.checked_add(1).ok_or(false)?;
+                }
+            }
+            (Precision::Reject, Some(_)) => {
+                if rest.iter().any(|b| *b != b'0') {
+                    return Err(true);
+                }
+            }
+        }
+
+        Ok(Amount(if negative { -value } else { value }))
+    }
+}
+
+impl Amount {
+    /// Parses an amount as people tend to write it, such as `"$1,234.56"`,
+    /// `"1.234,56 €"` or `"-USD 20"`, handling extra decimal places as
+    /// `precision` says. A currency symbol or code may come before or after
+    /// the number, and the digits of the whole units may be grouped in
+    /// threes by the separator that is not `separator`, or by spaces or
+    /// apostrophes. Groups of any other size are refused rather than
+    /// guessed at, so `"1,5"` is not taken for fifteen when the decimal
+    /// separator is a point. Plain amounts parse just like `parse_with`.
+    pub fn parse_lenient(
+        s: &str,
+        separator: DecimalSeparator,
+        precision: Precision,
+    ) -> Result<Amount, ParseAmountError> {
+        let err = || ParseAmountError::new(s);
+        let (decimal, thousands) = match separator {
+            DecimalSeparator::Point => ('.', ','),
+            DecimalSeparator::Comma => (',', '.'),
+        };
+
+        // The sign may come before or after the currency.
+        let mut rest = s.trim();
+        let mut negative = false;
+        for _ in 0..2 {
+            if let Some(r) = rest.strip_prefix('-') {
+                negative = true;
+                rest = r;
+                break;
+            }
+            rest = strip_currency(rest.strip_prefix('+').unwrap_or(rest));
+        }
+        let rest = strip_currency(rest);
+
+        let (whole, fraction) = match rest.split_once(decimal) {
+            Some((w, f)) => (w, Some(f)),
+            None => (rest, None),
+        };
+        let mut groups = whole.split(|c| c == thousands || GROUP_SEPARATORS.contains(&c));
+        let first = groups.next().unwrap_or("");
+        let mut plain = String::with_capacity(rest.len() + 1);
+        if negative {
+            plain.push('-');
+        }
+        plain.push_str(first);
+        for group in groups {
+            if first.is_empty() || first.len() > 3 || group.len() != 3 {
+                return Err(err());
+            }
+            plain.push_str(group);
+        }
+        if let Some(fraction) = fraction {
+            plain.push('.');
+            plain.push_str(fraction);
+        }
+        Amount::parse_with(&plain, precision).map_err(|e| ParseAmountError {
+            value: s.to_string(),
+            ..e
+        })
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    /// Parses a plain decimal string such as `"20"`, `"-1.5"` or `".25"`.
+    /// Digits beyond the fourth decimal place are rounded half to even,
+    /// which is the same thing the old `{:.4}` formatting of floats did.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse_with(s, Precision::RoundHalfEven)
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Writes the amount with as few decimal places as possible, but always
+    /// at least one, so `2` is written as `2.0` and `1.5000` as `1.5`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let whole = abs / SCALE as u64;
+        let fraction = format!(
+            "{:0width$}",
+            abs % SCALE as u64,
+            width = DECIMAL_PLACES as usize
+        );
+        let fraction = fraction.trim_end_matches('0');
+        let fraction = if fraction.is_empty() { "0" } else { fraction };
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    /// Amounts are preferably given as strings so no precision is lost on
+    /// the way in, but plain numbers are accepted as well.
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AmountVisitor;
+
+        impl Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal amount as a string or number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+        }
+
+        d.deserialize_any(AmountVisitor)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount::from_scaled(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount::from_scaled(self.0.saturating_sub(other.0))
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        // Out of `i64::MIN`, the range is symmetric, so this cannot overflow.
+        Amount(-self.0)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Amount, DecimalFormat, DecimalSeparator, Precision};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_whole_number() {
+        assert_eq!("20".parse::<Amount>(), Ok(Amount::from_scaled(200_000)));
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        assert_eq!("1.5".parse::<Amount>(), Ok(Amount::from_scaled(15_000)));
+        assert_eq!(".25".parse::<Amount>(), Ok(Amount::from_scaled(2_500)));
+        assert_eq!("3.".parse::<Amount>(), Ok(Amount::from_scaled(30_000)));
+    }
+
+    #[test]
+    fn test_parse_negative() {
+        assert_eq!("-1.5".parse::<Amount>(), Ok(Amount::from_scaled(-15_000)));
+    }
+
+    #[test]
+    fn test_parse_rounds_half_to_even() {
+        assert_eq!(
+            "20.987654321".parse::<Amount>(),
+            Ok(Amount::from_scaled(209_877))
+        );
+        assert_eq!("0.00005".parse::<Amount>(), Ok(Amount::from_scaled(0)));
+        assert_eq!("0.00015".parse::<Amount>(), Ok(Amount::from_scaled(2)));
+        assert_eq!("0.000050001".parse::<Amount>(), Ok(Amount::from_scaled(1)));
+    }
+
+    #[test]
+    fn test_parse_with_precision() {
+        let parse = |s, precision| Amount::parse_with(s, precision).map(Amount::scaled);
+        assert_eq!(parse("1.23456", Precision::Truncate), Ok(12_345));
+        assert_eq!(parse("-1.23459", Precision::Truncate), Ok(-12_345));
+        assert_eq!(parse("1.23455", Precision::RoundHalfEven), Ok(12_346));
+        assert_eq!(parse("1.23450", Precision::Reject), Ok(12_345));
+        let err = parse("1.23451", Precision::Reject).unwrap_err();
+        assert!(err.is_too_precise());
+        assert_eq!(
+            err.to_string(),
+            "amount '1.23451' has more than 4 decimal places"
+        );
+        assert!(!parse("x", Precision::Reject).unwrap_err().is_too_precise());
+        assert_eq!("Round-Half-Even".parse(), Ok(Precision::RoundHalfEven));
+        assert!("round".parse::<Precision>().is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in ["", ".", "-", "abc", "1.2.3", "1e5", "NaN", " 1", "--1"] {
+            assert!(s.parse::<Amount>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        assert!("99999999999999999999".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_stays_in_range() {
+        let one = Amount::from_scaled(1);
+        assert_eq!(Amount::MAX + one, Amount::MAX);
+        assert_eq!(-Amount::MAX - one, -Amount::MAX);
+        let mut amount = -Amount::MAX;
+        amount -= Amount::MAX;
+        assert_eq!(amount, -Amount::MAX);
+        assert_eq!(Amount::from_scaled(i64::MIN), -Amount::MAX);
+        assert_eq!(-Amount::from_scaled(i64::MIN), Amount::MAX);
+        assert_eq!(Amount::MAX.checked_add(one), None);
+        assert_eq!((-Amount::MAX).checked_sub(one), None);
+        assert_eq!((-Amount::MAX).checked_add(one), Some(one - Amount::MAX));
+        assert_eq!(
+            (-Amount::MAX).to_string().parse::<Amount>(),
+            Ok(-Amount::MAX)
+        );
+    }
+
+    #[test]
+    fn test_no_floating_point_drift() {
+        let a: Amount = "0.1".parse().unwrap();
+        let b: Amount = "0.2".parse().unwrap();
+        assert_eq!(a + b, "0.3".parse().unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_string_and_number() {
+        let a: Amount = serde_json::from_str("\"1.5\"").unwrap();
+        let b: Amount = serde_json::from_str("1.5").unwrap();
+        let c: Amount = serde_json::from_str("2").unwrap();
+        assert_eq!(a, Amount::from_scaled(15_000));
+        assert_eq!(b, Amount::from_scaled(15_000));
+        assert_eq!(c, Amount::from_scaled(20_000));
+        assert!(serde_json::from_str::<Amount>("\"abc\"").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Amount::from_scaled(20_000).to_string(), "2.0");
+        assert_eq!(Amount::from_scaled(15_000).to_string(), "1.5");
+        assert_eq!(Amount::from_scaled(209_877).to_string(), "20.9877");
+        assert_eq!(Amount::from_scaled(-5_000).to_string(), "-0.5");
+        assert_eq!(Amount::ZERO.to_string(), "0.0");
+    }
+
+    #[test]
+    fn test_formatted() {
+        let format = |scaled, places, fixed| {
+            Amount::from_scaled(scaled)
+                .formatted(DecimalFormat { places, fixed })
+                .to_string()
+        };
+        assert_eq!(format(15_000, 4, false), "1.5");
+        assert_eq!(format(15_000, 4, true), "1.5000");
+        assert_eq!(format(15_000, 8, true), "1.50000000");
+        assert_eq!(format(15_000, 2, true), "1.50");
+        assert_eq!(format(12_345, 2, false), "1.23");
+        assert_eq!(format(12_350, 2, true), "1.24");
+        assert_eq!(format(12_250, 2, true), "1.22");
+        assert_eq!(format(-12_351, 2, true), "-1.24");
+        assert_eq!(format(-49, 2, true), "0.00");
+        assert_eq!(format(0, 2, false), "0.0");
+        assert_eq!(format(209_877, 8, false), "20.9877");
+        assert_eq!(
+            serde_json::to_string(&Amount::from_scaled(20_000).formatted(DecimalFormat {
+                places: 4,
+                fixed: true
+            }))
+            .unwrap(),
+            "\"2.0000\""
+        );
+        assert_eq!(DecimalFormat::parse_places("8"), Ok(8));
+        assert!(DecimalFormat::parse_places("3").is_err());
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let parse = |s, separator| {
+            Amount::parse_lenient(s, separator, Precision::RoundHalfEven).map(Amount::scaled)
+        };
+        let point = DecimalSeparator::Point;
+        let comma = DecimalSeparator::Comma;
+        assert_eq!(parse("1,234.56", point), Ok(12_345_600));
+        assert_eq!(parse("$20.00", point), Ok(200_000));
+        assert_eq!(parse("-$1,000", point), Ok(-10_000_000));
+        assert_eq!(parse("$-1,000", point), Ok(-10_000_000));
+        assert_eq!(parse("USD 1 000 000.5", point), Ok(10_000_005_000));
+        assert_eq!(parse("US$ 7", point), Ok(70_000));
+        assert_eq!(parse("1'234.5 CHF", point), Ok(12_345_000));
+        assert_eq!(parse("1.234,56 €", comma), Ok(12_345_600));
+        assert_eq!(parse("€1,5", comma), Ok(15_000));
+        assert_eq!(parse("20 kr", comma), Ok(200_000));
+        assert_eq!(parse(".25", point), Ok(2_500));
+        for s in [
+            "",
+            "$",
+            "1,5",
+            "1,2345.0",
+            "1234,567.0",
+            ",123",
+            "1.234,56",
+            "1e5",
+            "--1",
+            "abc",
+        ] {
+            assert!(parse(s, point).is_err(), "{} should not parse", s);
+        }
+        assert!(parse("1.234.5", comma).is_err());
+
+        let err = Amount::parse_lenient("$1.00001", point, Precision::Reject).unwrap_err();
+        assert!(err.is_too_precise());
+        assert_eq!(
+            err.to_string(),
+            "amount '$1.00001' has more than 4 decimal places"
+        );
+        assert_eq!("Comma".parse(), Ok(DecimalSeparator::Comma));
+        assert!("semicolon".parse::<DecimalSeparator>().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_display_parses_back(scaled in -i64::MAX..=i64::MAX) {
+            let amount = Amount::from_scaled(scaled);
+            prop_assert_eq!(amount.to_string().parse::<Amount>(), Ok(amount));
+        }
+
+        #[test]
+        fn test_default_format_is_display(scaled in -i64::MAX..=i64::MAX) {
+            let amount = Amount::from_scaled(scaled);
+            prop_assert_eq!(
+                amount.formatted(DecimalFormat::default()).to_string(),
+                amount.to_string()
+            );
+        }
+    }
+}
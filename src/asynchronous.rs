@@ -0,0 +1,212 @@
+use super::engine::Engine;
+use super::error::Error;
+use super::input::json::parse_json_record;
+use super::input::{CsvTextParser, InputFormat, InputRecord, Source};
+use super::{ProcessOptions, Processed};
+use futures_util::{pin_mut, Stream, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// The asynchronous counterpart of `process_reader_with`, for services that
+/// receive transactions over the network. The input is read line by line
+/// without blocking, and every complete record is applied to the engine as
+/// soon as it arrives.
+///
+/// Applying a record never waits on anything, so the engine runs on the
+/// task calling this function. `ProcessOptions::threads` is ignored.
+/// Parquet input cannot be read this way, as a Parquet file cannot be read
+/// front to back, and neither can XML, which does not come line by line.
+pub async fn process_async<R>(reader: R, options: &ProcessOptions) -> Result<Processed, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    #[cfg(feature = "parquet")]
+    if options.format == InputFormat::Parquet {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Parquet input cannot be processed asynchronously",
+        )));
+    }
+    #[cfg(feature = "xml")]
+    if options.format == InputFormat::Xml {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "XML input cannot be processed asynchronously",
+        )));
+    }
+    let mut res = Processed {
+        engine: Engine::with_config(options.engine.clone()),
+        ..Processed::default()
+    };
+    let mut lines = BufReader::new(reader).lines();
+    let mut csv = CsvTextParser::new(
+        options.strict_columns,
+        options.csv,
+        options.aliases.clone(),
+        options.columns.clone(),
+        options.amounts(),
+    );
+    let mut line = 0;
+    // The text of a CSV record that may continue on the next line, and the
+    // line it started on.
+    let mut pending = String::new();
+    let mut start = 0;
+
+    while let Some(text) = lines.next_line().await? {
+        line += 1;
+        match options.format {
+            InputFormat::JsonLines if text.trim().is_empty() => (),
+            InputFormat::JsonLines => {
+                let source = Source {
+                    line: Some(line),
+                    raw: text.trim_end().to_string(),
+                };
+                res.push(
+                    source,
+                    parse_json_record(&text, Some(line), &options.aliases, options.amounts()),
+                )?;
+            }
+            InputFormat::Csv => {
+                if pending.is_empty() {
+                    start = line;
+                } else {
+                    pending.push('\n');
+                }
+                pending.push_str(&text);
+                // An odd number of quotes means a quoted field continues on
+                // the next line.
+                if pending.matches(char::from(options.csv.quote)).count() % 2 == 1 {
+                    continue;
+                }
+                if let Some((source, record)) = csv.parse(&pending, start) {
+                    res.push(source, record)?;
+                }
+                pending.clear();
+            }
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => unreachable!(),
+            #[cfg(feature = "xml")]
+            InputFormat::Xml => unreachable!(),
+        }
+    }
+    // An unterminated quote runs to the end of the input.
+    if !pending.is_empty() {
+        if let Some((source, record)) = csv.parse(&pending, start) {
+            res.push(source, record)?;
+        }
+    }
+    res.log_summary();
+    Ok(res)
+}
+
+/// Feeds records that have already been parsed, e.g. from a message queue,
+/// into `engine` as they come in. As there is no input to read, nothing can
+/// go wrong beyond records being rejected.
+pub async fn process_stream<S>(records: S, engine: Engine) -> Processed
+where
+    S: Stream<Item = InputRecord>,
+{
+    let mut res = Processed {
+        engine,
+        ..Processed::default()
+    };
+    pin_mut!(records);
+    while let Some(record) = records.next().await {
+        // Only reading input can fail, which a parsed record never does.
+        let _ = res.push(Source::default(), Ok(record));
+    }
+    res
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{process_async, process_stream};
+    use crate::amount::Amount;
+    use crate::engine::{Engine, RejectReason};
+    use crate::error::Error;
+    use crate::id::{ClientId, TxId};
+    use crate::input::{InputFormat, InputRecord, TransactionType};
+    use crate::ProcessOptions;
+    use std::future::Future;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_process_async_csv() {
+        let data = "type,client,tx,amount,note\n\
+                    deposit,1,1,1.0,\"spans\ntwo lines\"\n\
+                    bogus,1,2,1.0,\n\
+                    withdrawal,1,3,0.25,";
+        let processed =
+            block_on(process_async(data.as_bytes(), &ProcessOptions::default())).unwrap();
+
+        assert_eq!(
+            processed.engine.balances()[0].available,
+            Amount::from_scaled(7_500)
+        );
+        assert_eq!(processed.errors.len(), 1);
+        assert!(matches!(
+            processed.errors[0],
+            Error::UnknownTransactionType { line: Some(4), .. }
+        ));
+    }
+
+    #[test]
+    fn test_process_async_json_lines() {
+        let data = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}\n\
+                    \n\
+                    {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n";
+        let options = ProcessOptions {
+            format: InputFormat::JsonLines,
+            ..ProcessOptions::default()
+        };
+        let processed = block_on(process_async(data.as_bytes(), &options)).unwrap();
+        assert_eq!(
+            processed.engine.balances()[0].held,
+            Amount::from_scaled(15_000)
+        );
+    }
+
+    #[test]
+    fn test_process_async_missing_column() {
+        let data = "type,tx,amount\ndeposit,1,1.0\n";
+        let res = block_on(process_async(data.as_bytes(), &ProcessOptions::default()));
+        assert!(matches!(res, Err(Error::InvalidHeader { .. })));
+    }
+
+    #[test]
+    fn test_process_stream() {
+        let records = futures_util::stream::iter(vec![
+            InputRecord {
+                r#type: TransactionType::Deposit,
+                client: ClientId::new(1),
+                tx: TxId::new(1),
+                amount: Some(Amount::from_scaled(10_000)),
+                timestamp: None,
+                currency: None,
+                reason: None,
+                tenant: None,
+            },
+            InputRecord {
+                r#type: TransactionType::Withdrawal,
+                client: ClientId::new(1),
+                tx: TxId::new(2),
+                amount: Some(Amount::from_scaled(20_000)),
+                timestamp: None,
+                currency: None,
+                reason: None,
+                tenant: None,
+            },
+        ]);
+        let processed = block_on(process_stream(records, Engine::new()));
+        assert_eq!(processed.rejected.len(), 1);
+        assert_eq!(
+            processed.rejected[0].reason,
+            RejectReason::InsufficientFunds
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use super::engine::Engine;
+use super::error::Error;
+use super::input::encoding::Skipped;
+use super::input::json::JsonLinesReader;
+use super::input::{CsvReader, InputFormat, InputPosition, RecordSource};
+use super::output::write_atomically;
+use super::{ProcessOptions, Processed};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tracing::{info, info_span};
+
+/// The version of the checkpoint format. It has to be bumped whenever the
+/// format changes in an incompatible way.
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// A `Checkpoint` records how far processing a list of files got: the file
+/// it was in, the position just past the last record processed in it, and
+/// a snapshot of the engine right after that record. The two are written
+/// together, so the state always matches the position, and processing can
+/// be picked up from there after a crash with `process_files_checkpointed`.
+///
+/// Like a snapshot, a checkpoint does not hold the journal or statements,
+/// nor the records that were invalid or rejected before it was written.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    version: u32,
+    /// The index of the file among the inputs.
+    pub input: usize,
+    /// The file itself, to make sure the inputs did not change.
+    pub path: PathBuf,
+    pub position: InputPosition,
+    /// The number of records read up to here, over all files.
+    pub records: u64,
+    engine: serde_json::Value,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint at `path`, or returns `None` if there is none.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Checkpoint>, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| invalid_data(format!("invalid checkpoint: {}", e)))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported checkpoint version {}",
+                checkpoint.version
+            )));
+        }
+        Ok(Some(checkpoint))
+    }
+
+    /// Writes the checkpoint to `path`, replacing the previous one
+    /// atomically, so a crash half way through leaves that one intact.
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        write_atomically(path, |file| {
+            serde_json::to_writer(file, self).map_err(|e| std::io::Error::from(e).into())
+        })
+    }
+}
+
+/// Where checkpoints go and how often they are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointOptions {
+    pub path: PathBuf,
+    /// Write a checkpoint every this many records, as well as at the end of
+    /// every file.
+    pub every: u64,
+    /// Pick up from the checkpoint at `path`, if there is one.
+    pub resume: bool,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::Io(std::io::Error::new(ErrorKind::InvalidInput, msg))
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::Io(std::io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+/// Processes several files like `process_files_from`, writing a checkpoint
+/// every so often. With `resume`, an existing checkpoint takes the place of
+/// `engine`, apart from its policies, journal, handlers, validators and
+/// observers, and the files are read on from where it was written, skipping
+/// what was already processed. Only the files it covers have to be given
+/// again, in the same order.
+///
+/// Once every file has been processed, the checkpoint is removed, so the
+/// next run starts from scratch.
+///
+/// Files are read one record at a time, on the calling thread, and have to
+/// be CSV or JSON Lines, as those can be read from any position.
+pub fn process_files_checkpointed<P: AsRef<Path>>(
+    mut engine: Engine,
+    paths: &[P],
+    options: &ProcessOptions,
+    checkpoint: &CheckpointOptions,
+) -> Result<Processed, Error> {
+    if !matches!(options.format, InputFormat::Csv | InputFormat::JsonLines) {
+        return Err(Error::Io(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "checkpoints need CSV or JSON Lines input",
+        )));
+    }
+
+    let resume_from = match checkpoint.resume {
+        true => Checkpoint::load(&checkpoint.path)?,
+        false => None,
+    };
+    let mut records = 0;
+    if let Some(resume_from) = &resume_from {
+        match paths.get(resume_from.input) {
+            Some(path) if path.as_ref() == resume_from.path => (),
+            _ => {
+                return Err(invalid(format!(
+                    "checkpoint {} is for {}, which is not among the input files at the same place",
+                    checkpoint.path.display(),
+                    resume_from.path.display()
+                )))
+            }
+        }
+        let mut restored =
+            Engine::restore_from_value(resume_from.engine.clone(), engine.config().clone())?;
+        if let Some(journal) = engine.journal() {
+            restored.set_journal(journal.clone());
+        }
+        restored.handlers = engine.handlers.clone();
+        restored.validators = engine.validators.clone();
+        restored.observers = engine.observers.clone();
+        engine = restored;
+        records = resume_from.records;
+        info!(
+            path = %resume_from.path.display(),
+            line = resume_from.position.line,
+            records,
+            "resuming from checkpoint"
+        );
+    }
+
+    let mut res = Processed {
+        records,
+        ..Processed::new(engine, options)
+    };
+    let first = resume_from.as_ref().map_or(0, |c| c.input);
+    for (input, path) in paths.iter().enumerate().skip(first) {
+        let path = path.as_ref();
+        let _span = info_span!("file", path = %path.display()).entered();
+        let start = resume_from
+            .as_ref()
+            .filter(|c| c.input == input)
+            .map(|c| c.position);
+        let mut reader = open_at(path, options, start).map_err(|e| e.in_file(path))?;
+
+        let mut since_checkpoint = 0;
+        while let Some(record) = reader.next() {
+            res.push(reader.source(), record.map_err(|e| e.in_file(path)))?;
+            since_checkpoint += 1;
+            if since_checkpoint == checkpoint.every {
+                save(&res, input, path, reader.input_position(), &checkpoint.path)?;
+                since_checkpoint = 0;
+            }
+        }
+        save(&res, input, path, reader.input_position(), &checkpoint.path)?;
+    }
+
+    match std::fs::remove_file(&checkpoint.path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => (),
+    }
+    res.log_summary();
+    Ok(res)
+}
+
+/// A reader that can tell how far it got, so a checkpoint can be written.
+trait Resumable: RecordSource {
+    fn input_position(&self) -> InputPosition;
+}
+
+impl<R: std::io::Read> Resumable for CsvReader<R> {
+    fn input_position(&self) -> InputPosition {
+        self.position()
+    }
+}
+
+impl<R: std::io::BufRead> Resumable for JsonLinesReader<R> {
+    fn input_position(&self) -> InputPosition {
+        self.position()
+    }
+}
+
+/// Opens `path` in the configured input format, moved to `start` if given.
+fn open_at(
+    path: &Path,
+    options: &ProcessOptions,
+    start: Option<InputPosition>,
+) -> Result<Box<dyn Resumable>, Error> {
+    // Positions are bytes of the input, so it is read as is, only past a
+    // byte order mark.
+    let mut file = Skipped::utf8(File::open(path)?, options.encoding)?;
+    let aliases = options.aliases.clone();
+    match options.format {
+        InputFormat::JsonLines => {
+            let start = start.unwrap_or_default();
+            file.seek(SeekFrom::Start(start.byte))?;
+            let mut reader = JsonLinesReader::new(BufReader::new(file))
+                .with_aliases(aliases)
+                .with_precision(options.precision)
+                .starting_at(start);
+            if let Some(separator) = options.lenient_amounts {
+                reader = reader.with_lenient_amounts(separator);
+            }
+            Ok(Box::new(reader))
+        }
+        _ => {
+            let mut reader = CsvReader::with_options(file, &options.csv)
+                .with_aliases(aliases)
+                .with_columns(options.columns.clone())
+                .with_precision(options.precision);
+            if let Some(separator) = options.lenient_amounts {
+                reader = reader.with_lenient_amounts(separator);
+            }
+            if options.strict_columns {
+                reader = reader.with_strict_columns();
+            }
+            if let Some(start) = start {
+                reader.seek(start)?;
+            }
+            Ok(Box::new(reader))
+        }
+    }
+}
+
+fn save(
+    res: &Processed,
+    input: usize,
+    path: &Path,
+    position: InputPosition,
+    checkpoint_path: &Path,
+) -> Result<(), Error> {
+    let checkpoint = Checkpoint {
+        version: CHECKPOINT_VERSION,
+        input,
+        path: path.to_path_buf(),
+        position,
+        records: res.records,
+        engine: res.engine.snapshot_value()?,
+    };
+    checkpoint.save(checkpoint_path)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{process_files_checkpointed, Checkpoint, CheckpointOptions};
+    use crate::engine::{Engine, EngineConfig, ErrorMode};
+    use crate::input::InputFormat;
+    use crate::{process_files_with, ProcessOptions};
+    use std::path::PathBuf;
+
+    /// Processes `broken` with a checkpoint every two records, which stops
+    /// at its invalid row, then resumes on `fixed` and compares the outcome
+    /// with processing `fixed` in one go.
+    fn resume_after_failure(format: InputFormat, name: &str, broken: &str, fixed: &str) {
+        let dir = std::env::temp_dir().join(format!(
+            "payments-checkpoint-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join(name);
+        let checkpoint = CheckpointOptions {
+            path: dir.join("checkpoint"),
+            every: 2,
+            resume: true,
+        };
+        let options = ProcessOptions {
+            format,
+            engine: EngineConfig {
+                error_mode: ErrorMode::Strict,
+                ..EngineConfig::default()
+            },
+            ..ProcessOptions::default()
+        };
+        let engine = || Engine::with_config(options.engine.clone());
+
+        std::fs::write(&input, broken).unwrap();
+        let paths = [&input];
+        assert!(process_files_checkpointed(engine(), &paths, &options, &checkpoint).is_err());
+        let saved = Checkpoint::load(&checkpoint.path).unwrap().unwrap();
+        assert_eq!(saved.records, 2);
+
+        std::fs::write(&input, fixed).unwrap();
+        let resumed = process_files_checkpointed(engine(), &paths, &options, &checkpoint).unwrap();
+        let expected = process_files_with(&paths, &options).unwrap();
+        let mut balances = resumed.engine.balances();
+        balances.sort_by_key(|b| b.client);
+        let mut expected_balances = expected.engine.balances();
+        expected_balances.sort_by_key(|b| b.client);
+        assert_eq!(balances, expected_balances);
+        assert_eq!(resumed.records, expected.records);
+        // Finishing removes the checkpoint.
+        assert!(Checkpoint::load(&checkpoint.path).unwrap().is_none());
+
+        // A checkpoint for other input files is refused.
+        std::fs::write(&input, broken).unwrap();
+        assert!(process_files_checkpointed(engine(), &paths, &options, &checkpoint).is_err());
+        let other: [PathBuf; 1] = [dir.join("other")];
+        assert!(process_files_checkpointed(engine(), &other, &options, &checkpoint).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume_csv_with_bom() {
+        resume_after_failure(
+            InputFormat::Csv,
+            "bom.csv",
+            "\u{feff}type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,2,2,5.0\n\
+             deposit,1,3,x\n",
+            "\u{feff}type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,2,2,5.0\n\
+             deposit,1,3,1\n",
+        );
+    }
+
+    #[test]
+    fn test_resume_csv() {
+        resume_after_failure(
+            InputFormat::Csv,
+            "in.csv",
+            "client,type,tx,amount\n\
+             1,deposit,1,10.0\n\
+             2,deposit,2,5.0\n\
+             1,withdrawal,3,1.0\n\
+             2,withdrawal,4,x\n\
+             2,dispute,2,\n",
+            "client,type,tx,amount\n\
+             1,deposit,1,10.0\n\
+             2,deposit,2,5.0\n\
+             1,withdrawal,3,1.0\n\
+             2,withdrawal,4,1\n\
+             2,dispute,2,\n",
+        );
+    }
+
+    #[test]
+    fn test_resume_json_lines() {
+        resume_after_failure(
+            InputFormat::JsonLines,
+            "in.jsonl",
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.0\"}\n\
+             \n\
+             {\"type\":\"deposit\",\"client\":2,\"tx\":2,\"amount\":\"5.0\"}\n\
+             {\"type\":\"withdrawal\",\"client\":1,\"tx\":3,\"amount\":\"1.0\"}\n\
+             {\"type\":\"withdrawal\",\"client\":2,\"tx\":4,\"amount\":\"x\"}\n",
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.0\"}\n\
+             \n\
+             {\"type\":\"deposit\",\"client\":2,\"tx\":2,\"amount\":\"5.0\"}\n\
+             {\"type\":\"withdrawal\",\"client\":1,\"tx\":3,\"amount\":\"1.0\"}\n\
+             {\"type\":\"withdrawal\",\"client\":2,\"tx\":4,\"amount\":\"1\"}\n",
+        );
+    }
+}
@@ -0,0 +1,2143 @@
+use chrono::NaiveDate;
+use payments::amount::{Amount, DecimalFormat, DecimalSeparator};
+use payments::emit::EmitInterval;
+use payments::engine::{
+    AnomalyThresholds, DisputeEscalation, DisputePolicy, ErrorMode, StatementPolicy, TotalsPeriod,
+    WithdrawalDisputes,
+};
+use payments::error::Column;
+use payments::generate::GenerateOptions;
+use payments::id::ClientId;
+use payments::input::{parse_timestamp, ColumnSource, InputFormat};
+use payments::metrics::MetricsTarget;
+use payments::output::compress::Compression;
+use payments::output::{OutputFormat, OutputSchema, Sharding};
+use payments::tenant::Tenant;
+use payments::ProcessOptions;
+use std::path::{Path, PathBuf};
+
+/// The usage text printed whenever the arguments cannot be parsed.
+pub const USAGE: &str = "\
+Usage: payments [process | validate | report --client <id>] [options] [<input file>... | -]
+       payments report disputes [--client <id>] [options] [<input file>... | -]
+       payments report totals [--from <date>] [--to <date>] [--client <id>] [options]
+                              [<input file>... | -]
+       payments report history [--client <id>] [options] [<input file>... | -]
+       payments report locks [--client <id>] [options] [<input file>... | -]
+       payments report escalations [--client <id>] [options] [<input file>... | -]
+       payments report anomalies [--withdrawal-ratio <n>] [--max-disputes <n>]
+                                 [--client <id>] [options] [<input file>... | -]
+       payments consume --brokers <list> --topic <name> [options]
+       payments serve [--grpc] [--listen <address>] [options]
+       payments watch [options] <dir>
+       payments replay [options] [<event log>... | -]
+       payments query --snapshot <file> --client <id> [options]
+       payments diff [options] <old> <new>
+       payments gen [--clients <n>] [--rows <n>] [--dispute-rate <p>] [--seed <n>]
+                    [-o <file>]
+       payments repl [options] [<input file>...]
+       payments config print-default
+
+Reads transactions from the input files, or from standard input if no file
+(or `-`) is given, and writes the balance of every client to standard out.
+Several files are processed one after the other as a single ledger. File
+names may contain the wildcards `*` and `?`, matching files are taken in
+alphabetical order.
+
+With `validate`, the input is only checked: every row that cannot be parsed
+is listed, and the exit code is non-zero if there are any. No balances are
+computed.
+
+With `report`, the statement of a single client is written instead of the
+balances: every transaction applied to the account, in order, along with the
+balances after it.
+
+With `report disputes`, every transaction that was disputed is listed
+instead, for all clients or only the one given with `--client`: its amount,
+whether the dispute is still open, resolved or charged back, and, if the
+dispute had a timestamp, when it was opened and for how many days an open
+one has been open. The age is taken at `--as-of` if given, or else now.
+
+With `report totals`, the amounts every client deposited, withdrew and had
+charged back from `--from` up to and including `--to` are written instead,
+for all clients or only the one given with `--client`, e.g. for tax and
+compliance tooling. Transactions without a timestamp only count if neither
+date is given.
+
+With `report history`, the balances of every client at the end of every day
+are written instead, for all clients or only the one given with `--client`,
+from the first day a client had a transaction with a timestamp up to the
+last day of the input. A day without transactions repeats the balances of
+the day before. Days are taken in UTC.
+
+With `report locks`, every locked account is listed instead, for all clients
+or only the one given with `--client`: the transaction whose chargeback
+locked it, when that happened if the chargeback had a timestamp, and its
+balances. An `unlock` transaction lifts the lock.
+
+With `report escalations`, every time an open dispute reached one of the
+ages of `--escalate-disputes` is listed instead, for all clients or only the
+one given with `--client`, so compliance can chase it before it has to be
+charged back: the dispute, its amount, when it was opened, the age it
+reached and when. Ages are reached as the timestamps of the input go by, and
+by `--as-of` if given, or else now, at the end. Without
+`--escalate-disputes`, the ages are 30, 60 and 90 days.
+
+With `report anomalies`, the accounts the risk team may want a closer look
+at are listed instead, for all clients or only the one given with
+`--client`: those that withdrew more than `--withdrawal-ratio` times what
+they deposited, or had more than `--max-disputes` transactions disputed.
+Every account comes with its deposits, withdrawals and disputes, and a
+`flags` column naming the heuristics it tripped. At least one of them has to
+be given.
+
+With `consume`, transactions are read from a Kafka topic instead, one per
+message, as a JSON object or a CSV row without a header. The balances, and
+the snapshot and journal if asked for, are written out at every interval.
+This needs payments to be built with the `kafka` feature.
+
+With `serve`, transactions are submitted and balances queried over HTTP. The
+balances, and the snapshot and journal if asked for, are written out once
+the server is stopped with Ctrl-C. This needs the `server` feature. With
+`--grpc`, the gRPC API of proto/payments.proto is served instead, which needs
+the `grpc` feature.
+
+With `watch`, the files dropped into a directory are processed as they land,
+one after the other as a single ledger, in the order of their names. Only
+files with the extension of `--format`, such as `.csv`, are picked up, once
+nothing in the directory changed for a second. After every file, the
+balances are written out and the state saved as asked for with
+`--save-snapshot`, `--sqlite` or `--journal`, and the file is moved to
+`<dir>/archive` along with its run report, `<file>.report.json`. A file that
+cannot be processed is left where it is, and payments stops. This needs the
+`watch` feature.
+
+With `replay`, the state is rebuilt from event logs written with
+`--event-log` instead, by applying their transactions once more with the
+policies given, which have to be those they were first applied with. The
+balances are written out as usual.
+
+With `query`, the balances and open disputes of a single client are looked
+up in a snapshot saved with `--save-snapshot`, without processing any input.
+In CSV, the disputes follow the balances as a second table. The ages of the
+disputes are taken at `--as-of` if given, or else now.
+
+With `diff`, two sets of balances are compared, such as the output of the
+same input before and after an upgrade. Each is either balances written as
+CSV or a snapshot. Every account that differs is written out with what
+became of it (`added`, `removed` or `changed`), its balances in the new set
+minus those in the old one, and whether it was and is locked. Nothing is
+written if the two are the same.
+
+With `gen`, a CSV file of random transactions is made up instead, for load
+tests and benchmarks: deposits and withdrawals of `--clients` clients
+(default: 1000), some of the deposits disputed and the disputes resolved or
+charged back, `--rows` rows in all (default: 100000, e.g. 1e7 for ten
+million). `--dispute-rate` is the share of rows that open a dispute (default:
+0.01), and just as many close one. The same `--seed` (default: 0) always
+makes the same file.
+
+With `repl`, commands are read from standard input instead, one per line,
+to look into a case by hand: `load <file>` processes a file, `balance
+<client>` and `tx <tx>` show an account and a transaction, `apply <type>
+<client> <tx> [amount]` applies a single record, such as `apply dispute 42
+1001`, and `export <file>` writes the balances to a CSV file. The input files
+given are loaded first. Once the input ends, or on `quit`, the state is saved
+as asked for with `--save-snapshot`, `--sqlite` or `--journal`.
+
+With `config print-default`, a config file with every setting at its default
+is printed. Settings are read from payments.toml in the current directory if
+there is one, or from the file given with `--config`, and take the names of
+the flags below. Flags given on the command line win over the file.
+
+Options:
+    --config <file>         Read the settings from this file instead of
+                            payments.toml
+    --no-config             Do not read any config file
+    --format <csv|jsonl|parquet|xml>
+                            Format of the input (default: csv). Parquet needs
+                            the parquet feature, and camt.053-style XML the
+                            xml feature
+    --encoding <label>      The character encoding of the input, e.g.
+                            windows-1252 or utf-16le (default: utf-8). A
+                            byte order mark is dropped, and wins over this
+    --strict-columns        Expect exactly the CSV columns type, client, tx,
+                            amount in that order instead of going by the
+                            header names
+    --strict                Stop at the first row that cannot be parsed
+                            instead of skipping it
+    --delimiter <char>      The CSV field delimiter, e.g. ';' or tab
+                            (default: ,)
+    --quote <char>          The CSV quote character (default: \")
+    --no-headers            The CSV input has no header row, so the columns
+                            are taken in the order of --strict-columns
+    --flexible              Allow CSV rows with more or fewer fields than
+                            the first row, such as disputes without an
+                            amount or rows with trailing commas
+    --column <column>=<name|#n>
+                            Read a column from the CSV column of another
+                            name, or from the nth column, e.g. type=txn_kind
+                            or amount=#4. May be repeated
+    --output-format <csv|json|jsonl|parquet>
+                            Format of the output (default: csv). Parquet
+                            needs the parquet feature
+    --output-columns <list> Write only these balance columns, in this order,
+                            e.g. client,total,disputed_count. The columns
+                            are client, currency, available, held, total,
+                            locked, disputed_count and disputed_amount, and
+                            tenant with --tenants
+    --no-output-header      Leave out the header row of CSV balances
+    --extended              Add the number of open disputes of every account
+                            and the amount they hold, disputed_count and
+                            disputed_amount, after the usual columns
+    --decimal-places <2|4|8>
+                            Round the balances to this many decimal places
+                            (default: 4)
+    --fixed-decimals        Write every decimal place of the balances, e.g.
+                            1.5000 rather than 1.5
+    --client <id>           The client to report on or to query, or to
+                            list the disputes, totals, history, locks or
+                            anomalies of
+    --withdrawal-ratio <n>  Flag accounts in the anomalies report that
+                            withdrew more than n times what they deposited,
+                            e.g. 3 or 1.5
+    --max-disputes <n>      Flag accounts in the anomalies report with more
+                            than n transactions disputed
+    --from <date>           The first day of the totals report, e.g.
+                            2024-01-01
+    --to <date>             The last day of the totals report, e.g.
+                            2024-12-31
+    --brokers <list>        The Kafka brokers to consume from, as a comma
+                            separated list of host:port pairs
+    --topic <name>          The Kafka topic to consume
+    --group <id>            The Kafka consumer group (default: payments)
+    --interval <seconds>    How often to write out the state while consuming
+                            (default: 60)
+    --grpc                  Serve the gRPC API instead of the HTTP one
+    --listen <address>      The address to serve the API on (default:
+                            127.0.0.1:8080, or 127.0.0.1:50051 with --grpc)
+    --alias <name>=<type>   Accept name as another name for a transaction
+                            type, e.g. wd=withdrawal. May be repeated
+    --tenants               Keep the accounts of every tenant apart, by the
+                            tenant column of the input, so the same client
+                            ID of two tenants is two clients. The balances
+                            and reports start with a tenant column once any
+                            row has a tenant
+    --tenant <name>=<file>  Process the file as the input of a tenant, for
+                            the rows without a tenant column of their own,
+                            e.g. eu=eu.csv. Implies --tenants. May be
+                            repeated
+    --threads <n>           Process clients on n threads in parallel. Needs
+                            --relaxed-tx-checks
+    --relaxed-tx-checks     Accept that with --threads, a transaction ID is
+                            only checked against those of the clients of
+                            the same thread, so an ID reused by another
+                            client may not be rejected as a duplicate, and
+                            rejects may differ from a run on one thread
+    --parse-threads <n>     Parse CSV files on n threads, with another one
+                            reading them, while the records are applied in
+                            order
+    -o, --output <file>     Write the output to a file instead of standard out.
+                            Files named *.gz or *.zst are compressed with
+                            gzip or zstd
+    --compress <gzip|zstd>  Compress the output, whether written to a file
+                            or to standard out. Compressing needs the
+                            compress feature
+    --shard-output <n|range:<n>>
+                            Split the balances over n files, by client ID
+                            modulo n, or over one file for every n client
+                            IDs, named after --output as <name>_000.csv and
+                            so on, along with <name>.manifest.json listing
+                            them. Needs --output
+    --emit-every <n|<n>s>   Write the balances so far to the output file every
+                            n records or every n seconds while processing,
+                            replacing the previous ones. Needs --output
+    --metrics <file|statsd://<host>[:<port>]>
+                            Write the metrics of the run, such as the
+                            records processed and rejected and how long
+                            applying them took, to a Prometheus textfile, or
+                            send them to statsd
+    --dedup-files           Drop the rows of an input file that were in the
+                            file before as well, going by their type, client
+                            and tx, such as where daily exports overlap
+    --dedup-report <file>   Write every row --dedup-files dropped, with its
+                            file and line and the file it was first seen in,
+                            to a CSV file
+    --rejects <file>        Write every invalid or rejected row, with its line
+                            number and the reason, to a CSV file
+    --summary <file>        Write the figures of the run, such as the number
+                            of records rejected for every reason and the
+                            volume of deposits and withdrawals, to a JSON
+                            file
+    --reconciliation <file> Write every balance_check record, the total it
+                            states and the total the account held, to a
+                            reconciliation report in the output format
+    --double-entry <file>   Book every record that moves money in a
+                            double-entry journal, and write it in the output
+                            format for the accounting system
+    --unmatched-disputes <file>
+                            Write every dispute --pending-disputes kept that
+                            never matched a transaction, in the output
+                            format
+    --run-report <file>     Write how the run ended, the number of records
+                            applied, invalid and rejected, and every row that
+                            was not applied, to a JSON file. Written even if
+                            the run fails
+    --load-snapshot <file>  Carry on from the state saved in a snapshot
+    --snapshot <file>       The snapshot to query
+    --save-snapshot <file>  Save the state of the engine to a snapshot once
+                            all input has been processed
+    --journal <file>        Skip transactions the journal shows were applied
+                            in an earlier run, and add the ones applied now
+    --event-log <file>      Append every transaction applied or rejected, and
+                            what it led to, to an audit log of events
+    --sqlite <file>         Keep the state of the engine in a SQLite
+                            database, carrying on from what it holds and
+                            writing back what changed. Needs the sqlite
+                            feature
+    --accounts <implicit|explicit>
+                            Whether a first deposit opens an account, or
+                            only an `open` record does (default: implicit)
+    --checkpoint <file>     Write a checkpoint while processing the input
+                            files, to pick up from with --resume should the
+                            run not finish. Removed once it does
+    --checkpoint-every <n>  Write a checkpoint every n records (default:
+                            1000000), as well as after every file
+    --resume                Pick up from the checkpoint, if there is one
+    --locked-accounts <reject|allow-deposits|allow>
+                            What to do with transactions for an account
+                            locked by a chargeback (default: reject)
+    --disputes <deposits|all>
+                            Which transactions may be disputed (default:
+                            deposits)
+    --withdrawal-disputes <credit|pending>
+                            How a disputed withdrawal moves funds: credit
+                            it back to the available funds, or hold it as
+                            a credit pending from the bank until it is
+                            resolved or charged back (default: credit).
+                            Needs --disputes all
+    --pending-disputes      Keep disputes of transactions not seen yet until
+                            the transaction comes along, for feeds that may
+                            deliver them out of order, rather than rejecting
+                            them
+    --dispute-window <unlimited|<n>d|<n>tx>
+                            How long a transaction may be disputed: for n
+                            days, or until n more transactions were applied
+                            to its account (default: unlimited)
+    --dispute-expiry <<n>d:<resolve|chargeback>>
+                            Close disputes left open for n days with a
+                            resolve or a chargeback (default: never)
+    --escalate-disputes <<n>d,...>
+                            Escalate disputes as they are open for n days,
+                            as an event in the event log and in report
+                            escalations (default: never)
+    --duplicates <reject|keep-first|keep-last>
+                            What to do with deposits and withdrawals reusing
+                            an earlier transaction ID (default: reject)
+    --overdraft <reject|allow-negative|limit:<amount>>
+                            How far withdrawals may take the available funds
+                            below zero (default: reject)
+    --negative-balances <log|reject|abort>
+                            What to do with records that take the funds of
+                            an account below zero otherwise (default: log)
+    --precision <truncate|round-half-even|reject>
+                            What to do with amounts that have more than four
+                            decimal places (default: round-half-even)
+    --lenient-amounts[=<point|comma>]
+                            Accept amounts such as $1,234.56 or 1.234,56 €
+                            rather than rejecting the rows, with a point or
+                            a comma before the decimals (default: point)
+    --fees <schedule>       Charge fees, e.g. withdrawal=1,deposit=0.5%,monthly=5
+                            for a flat fee per withdrawal, a percentage of
+                            every deposit and a monthly fee per account
+    --limits <list>         Reject deposits and withdrawals that break a
+                            limit, e.g. amount=10000,daily=25000,transactions=500
+                            for the largest single amount, the most moved per
+                            account and day, and the most per account
+    --rules <list>          Reject records that break a rule, e.g.
+                            types=deposit+withdrawal,clients=1..5000,
+                            amount:withdrawal=..1000 for the types taken, the
+                            range of clients, and the range of amounts for
+                            one type or all of them
+    --as-of <timestamp>     Only apply transactions up to this RFC 3339 time,
+                            e.g. 2024-06-30T23:59:59Z
+    -v, --verbose           Log more detail on standard error; give twice to
+                            log every record. RUST_LOG takes precedence
+    -h, --help              Print this message";
+
+/// What the program is asked to do.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    /// Process the input and write out the balances.
+    #[default]
+    Process,
+    /// Only check that every row of the input can be parsed.
+    Validate,
+    /// Process the input and write out the statement of one client.
+    Report,
+    /// Process the input and write out every disputed transaction.
+    DisputeReport,
+    /// Process the input and write out what every client took in and paid
+    /// out during a period.
+    TotalsReport,
+    /// Process the input and write out the balances of every client at the
+    /// end of every day.
+    HistoryReport,
+    /// Process the input and write out every locked account, along with
+    /// what locked it.
+    LockReport,
+    /// Process the input and write out every account that trips any of the
+    /// anomaly heuristics.
+    AnomalyReport,
+    /// Process the input and write out every dispute escalated for its age.
+    EscalationReport,
+    /// Apply transactions from a Kafka topic as they arrive.
+    Consume,
+    /// Serve an HTTP API to submit transactions and query balances.
+    Serve,
+    /// Apply the files dropped into a directory as they land.
+    Watch,
+    /// Rebuild the state from event logs and write out the balances.
+    Replay,
+    /// Look up the balances and open disputes of a client in a snapshot.
+    Query,
+    /// Compare two sets of balances and write out how they differ.
+    Diff,
+    /// Make up a file of random transactions.
+    Generate,
+    /// Read commands from standard input to look into a case by hand.
+    Repl,
+    /// Print a config file with every setting at its default.
+    PrintDefaultConfig,
+}
+
+/// Everything that can be set from the command line. Anything not given
+/// explicitly keeps its default value.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    /// The client to report on, or to list the disputes of.
+    pub client: Option<ClientId>,
+    /// What the anomalies report flags.
+    pub anomalies: AnomalyThresholds,
+    /// What `gen` makes up.
+    pub generate: GenerateOptions,
+    /// The Kafka brokers to consume from.
+    pub brokers: Option<String>,
+    /// The Kafka topic to consume.
+    pub topic: Option<String>,
+    /// The Kafka consumer group.
+    pub group: Option<String>,
+    /// How often to write out the state while consuming, in seconds.
+    pub interval: Option<u64>,
+    /// The address to serve the API on.
+    pub listen: Option<String>,
+    /// Serve the gRPC API instead of the HTTP one.
+    pub grpc: bool,
+    /// The input files, in the order they are processed. No files means
+    /// standard input.
+    pub inputs: Vec<String>,
+    pub output_format: OutputFormat,
+    /// The columns of the balances, and whether they have a header.
+    pub output_schema: OutputSchema,
+    /// The output file. `None` means standard out.
+    pub output: Option<String>,
+    /// How to compress the output. `None` leaves it to the name of the
+    /// output file.
+    pub compress: Option<Compression>,
+    /// How to split the balances over several files, if at all.
+    pub shard_output: Option<Sharding>,
+    /// How often to write the balances so far while processing, if at all.
+    pub emit_every: Option<EmitInterval>,
+    /// Where to write the rows that could not be processed, if anywhere.
+    pub rejects: Option<String>,
+    /// Where to write the rows dropped by `--dedup-files`, if anywhere.
+    pub dedup_report: Option<String>,
+    /// Where to write the figures of the run, if anywhere.
+    pub summary: Option<String>,
+    /// Where the metrics of the run go, if anywhere.
+    pub metrics: Option<MetricsTarget>,
+    /// Where to write the reconciliation report, if anywhere.
+    pub reconciliation: Option<String>,
+    /// Where to write the double-entry journal, if anywhere.
+    pub double_entry: Option<String>,
+    /// Where to write the disputes that never matched, if anywhere.
+    pub unmatched_disputes: Option<String>,
+    /// Where to write the report of the run, if anywhere.
+    pub run_report: Option<String>,
+    /// A snapshot to restore the engine from before processing.
+    pub load_snapshot: Option<String>,
+    /// The snapshot to query.
+    pub snapshot: Option<String>,
+    /// Where to save a snapshot of the engine after processing.
+    pub save_snapshot: Option<String>,
+    /// The journal of transactions applied in earlier runs, if any.
+    pub journal: Option<String>,
+    /// The event log to append to, if any.
+    pub event_log: Option<String>,
+    /// The SQLite database the state of the engine is kept in, if any.
+    pub sqlite: Option<String>,
+    /// Where to write checkpoints while processing, if anywhere.
+    pub checkpoint: Option<String>,
+    /// How many records to process between checkpoints.
+    pub checkpoint_every: Option<u64>,
+    /// Pick up from the checkpoint of a run that did not finish.
+    pub resume: bool,
+    /// How the input is read and processed.
+    pub process: ProcessOptions,
+    /// How many times `--verbose` was given.
+    pub verbose: u8,
+    pub help: bool,
+}
+
+/// Parses the command line arguments, not including the program name.
+/// Flags taking a value accept both `--flag value` and `--flag=value`.
+/// The settings of the config file, see `config::load`, are taken as flags
+/// ahead of the command line ones, so a flag given on the command line wins
+/// over the file, apart from those that can be repeated, which add to it.
+pub fn parse_args<I>(config: Vec<String>, args: I) -> Result<Options, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    let mut stdin = false;
+
+    let command = match args.peek().map(String::as_str) {
+        // Processing is what happens without a command, but it can be
+        // spelled out as well.
+        Some("process") => Some(Command::Process),
+        Some("validate") => Some(Command::Validate),
+        Some("report") => Some(Command::Report),
+        Some("consume") => Some(Command::Consume),
+        Some("serve") => Some(Command::Serve),
+        Some("watch") => Some(Command::Watch),
+        Some("replay") => Some(Command::Replay),
+        Some("query") => Some(Command::Query),
+        Some("diff") => Some(Command::Diff),
+        Some("gen") => Some(Command::Generate),
+        Some("repl") => Some(Command::Repl),
+        Some("config") => Some(Command::PrintDefaultConfig),
+        _ => None,
+    };
+    if let Some(command) = command {
+        options.command = command;
+        args.next();
+    }
+    if options.command == Command::PrintDefaultConfig
+        && args.next().as_deref() != Some("print-default")
+    {
+        return Err("config needs print-default".to_string());
+    }
+    if options.command == Command::Report {
+        let report = match args.peek().map(String::as_str) {
+            Some("disputes") => Some(Command::DisputeReport),
+            Some("totals") => Some(Command::TotalsReport),
+            Some("history") => Some(Command::HistoryReport),
+            Some("locks") => Some(Command::LockReport),
+            Some("anomalies") => Some(Command::AnomalyReport),
+            Some("escalations") => Some(Command::EscalationReport),
+            _ => None,
+        };
+        if let Some(report) = report {
+            options.command = report;
+            args.next();
+        }
+    }
+    let mut period = TotalsPeriod::default();
+    let mut args = config.into_iter().chain(args).peekable();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {}", flag))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => options.help = true,
+            "-v" | "--verbose" => options.verbose = options.verbose.saturating_add(1),
+            "-vv" => options.verbose = options.verbose.saturating_add(2),
+            // Already read by `config::load`.
+            "--config" => drop(value()?),
+            "--no-config" => (),
+            "--format" => options.process.format = value()?.parse()?,
+            "--encoding" => options.process.encoding = value()?.parse()?,
+            "--strict-columns" => options.process.strict_columns = true,
+            "--strict" => options.process.engine.error_mode = ErrorMode::Strict,
+            "--delimiter" => options.process.csv.delimiter = parse_csv_char(&flag, &value()?)?,
+            "--quote" => options.process.csv.quote = parse_csv_char(&flag, &value()?)?,
+            "--no-headers" => options.process.csv.has_headers = false,
+            "--flexible" => options.process.csv.flexible = true,
+            "--column" => {
+                let mapping = value()?;
+                let (name, source) = mapping.split_once('=').ok_or_else(|| {
+                    format!("--column expects <column>=<name|#n>, not '{}'", mapping)
+                })?;
+                let column = Column::ALL
+                    .into_iter()
+                    .find(|c| c.name().eq_ignore_ascii_case(name.trim()))
+                    .ok_or_else(|| format!("unknown column '{}'", name))?;
+                options.process.columns.insert(column, source.parse()?);
+            }
+            "--alias" => {
+                let alias = value()?;
+                let (name, transaction_type) = alias
+                    .split_once('=')
+                    .ok_or_else(|| format!("--alias expects <name>=<type>, not '{}'", alias))?;
+                options
+                    .process
+                    .aliases
+                    .insert(name, transaction_type.parse()?);
+            }
+            "--tenants" => options.process.engine.tenants = true,
+            "--relaxed-tx-checks" => options.process.relaxed_tx_checks = true,
+            "--tenant" => {
+                let tag = value()?;
+                let (tenant, pattern) = tag
+                    .split_once('=')
+                    .ok_or_else(|| format!("--tenant expects <name>=<file>, not '{}'", tag))?;
+                let tenant = tenant.parse::<Tenant>().map_err(|e| e.to_string())?;
+                for input in expand_wildcards(pattern)? {
+                    options
+                        .process
+                        .file_tenants
+                        .insert(PathBuf::from(&input), tenant);
+                    options.inputs.push(input);
+                }
+                options.process.engine.tenants = true;
+            }
+            "--output-format" => options.output_format = value()?.parse()?,
+            "--output-columns" => {
+                options.output_schema.columns = Some(OutputSchema::parse_columns(&value()?)?)
+            }
+            "--no-output-header" => options.output_schema.header = false,
+            "--extended" => options.output_schema.extended = true,
+            "--decimal-places" => {
+                options.output_schema.decimals.places = DecimalFormat::parse_places(&value()?)?
+            }
+            "--fixed-decimals" => options.output_schema.decimals.fixed = true,
+            "--threads" => {
+                options.process.threads = value()?
+                    .parse()
+                    .map_err(|_| "--threads expects a number".to_string())?
+            }
+            "--parse-threads" => {
+                options.process.parse_threads = value()?
+                    .parse()
+                    .map_err(|_| "--parse-threads expects a number".to_string())?
+            }
+            "--client" => {
+                options.client = Some(
+                    value()?
+                        .parse()
+                        .map_err(|_| "--client expects a client ID".to_string())?,
+                )
+            }
+            "--listen" => options.listen = Some(value()?),
+            "--grpc" => options.grpc = true,
+            "--brokers" => options.brokers = Some(value()?),
+            "--topic" => options.topic = Some(value()?),
+            "--group" => options.group = Some(value()?),
+            "--interval" => {
+                options.interval = Some(
+                    value()?
+                        .parse()
+                        .map_err(|_| "--interval expects a number of seconds".to_string())?,
+                )
+            }
+            "-o" | "--output" => options.output = Some(value()?),
+            "--compress" => options.compress = Some(value()?.parse()?),
+            "--shard-output" => options.shard_output = Some(value()?.parse()?),
+            "--emit-every" => options.emit_every = Some(value()?.parse()?),
+            "--rejects" => options.rejects = Some(value()?),
+            "--dedup-files" => options.process.dedup_files = true,
+            "--dedup-report" => options.dedup_report = Some(value()?),
+            "--summary" => options.summary = Some(value()?),
+            "--metrics" => {
+                options.metrics = Some(value()?.parse()?);
+                options.process.measure_latency = true;
+            }
+            "--reconciliation" => options.reconciliation = Some(value()?),
+            "--double-entry" => {
+                options.double_entry = Some(value()?);
+                options.process.engine.double_entry = true;
+            }
+            "--unmatched-disputes" => options.unmatched_disputes = Some(value()?),
+            "--run-report" => options.run_report = Some(value()?),
+            "--load-snapshot" => options.load_snapshot = Some(value()?),
+            "--snapshot" => options.snapshot = Some(value()?),
+            "--save-snapshot" => options.save_snapshot = Some(value()?),
+            "--journal" => options.journal = Some(value()?),
+            "--event-log" => options.event_log = Some(value()?),
+            "--sqlite" => options.sqlite = Some(value()?),
+            "--checkpoint" => options.checkpoint = Some(value()?),
+            "--checkpoint-every" => {
+                options.checkpoint_every =
+                    Some(value()?.parse().ok().filter(|n| *n > 0).ok_or_else(|| {
+                        "--checkpoint-every expects a number of records".to_string()
+                    })?)
+            }
+            "--resume" => options.resume = true,
+            "--disputes" => options.process.engine.disputes = value()?.parse()?,
+            "--withdrawal-disputes" => {
+                options.process.engine.withdrawal_disputes = value()?.parse()?
+            }
+            "--pending-disputes" => options.process.engine.pending_disputes = true,
+            "--dispute-window" => options.process.engine.dispute_window = value()?.parse()?,
+            "--dispute-expiry" => options.process.engine.dispute_expiry = Some(value()?.parse()?),
+            "--escalate-disputes" => {
+                options.process.engine.dispute_escalation = value()?.parse()?
+            }
+            "--duplicates" => options.process.engine.duplicates = value()?.parse()?,
+            "--as-of" => {
+                let value = value()?;
+                let as_of = parse_timestamp(&value)
+                    .map_err(|e| format!("invalid --as-of timestamp '{}': {}", value, e))?;
+                options.process.engine.as_of = Some(as_of);
+            }
+            "--overdraft" => options.process.engine.overdraft = value()?.parse()?,
+            "--negative-balances" => options.process.engine.negative_balances = value()?.parse()?,
+            "--fees" => options.process.engine.fees = value()?.parse()?,
+            "--limits" => options.process.engine.limits = value()?.parse()?,
+            "--rules" => options.process.engine.rules = value()?.parse()?,
+            "--precision" => options.process.precision = value()?.parse()?,
+            "--lenient-amounts" => {
+                let separator = match &inline_value {
+                    Some(separator) => separator.parse()?,
+                    None => DecimalSeparator::Point,
+                };
+                options.process.lenient_amounts = Some(separator);
+            }
+            "--accounts" => options.process.engine.accounts = value()?.parse()?,
+            "--locked-accounts" => options.process.engine.locked_accounts = value()?.parse()?,
+            "--withdrawal-ratio" => {
+                let ratio = value()?;
+                options.anomalies.withdrawal_ratio = match ratio.parse::<Amount>() {
+                    Ok(ratio) if !ratio.is_negative() => Some(ratio),
+                    _ => {
+                        return Err(format!(
+                            "--withdrawal-ratio expects a positive number, not '{}'",
+                            ratio
+                        ))
+                    }
+                };
+            }
+            "--max-disputes" => {
+                options.anomalies.max_disputes = Some(
+                    value()?
+                        .parse()
+                        .map_err(|_| "--max-disputes expects a number".to_string())?,
+                )
+            }
+            "--clients" => {
+                options.generate.clients =
+                    Some(parse_count(&flag, &value()?)?)
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| "--clients needs at least one client".to_string())?
+            }
+            "--rows" => options.generate.rows = parse_count(&flag, &value()?)?,
+            "--dispute-rate" => {
+                let rate = value()?;
+                options.generate.dispute_rate = match rate.parse::<f64>() {
+                    Ok(rate) if (0.0..=0.5).contains(&rate) => rate,
+                    _ => {
+                        return Err(format!(
+                            "--dispute-rate expects a share from 0 to 0.5, not '{}'",
+                            rate
+                        ))
+                    }
+                };
+            }
+            "--seed" => {
+                options.generate.seed = value()?
+                    .parse()
+                    .map_err(|_| "--seed expects a number".to_string())?
+            }
+            "--from" => period.from = Some(parse_date(&flag, &value()?)?),
+            "--to" => period.to = Some(parse_date(&flag, &value()?)?),
+            "-" => stdin = true,
+            s if s.starts_with('-') => return Err(format!("unknown option {}", s)),
+            _ => options.inputs.extend(expand_wildcards(&arg)?),
+        }
+    }
+
+    if options.process.csv.delimiter == options.process.csv.quote {
+        return Err("--delimiter and --quote cannot be the same character".to_string());
+    }
+    if stdin && !options.inputs.is_empty() {
+        return Err("standard input cannot be combined with input files".to_string());
+    }
+    // Only the statement asked for is kept, as statements grow with the
+    // input.
+    match (&options.command, options.client) {
+        (Command::Report, Some(client)) => {
+            options.process.engine.statements = StatementPolicy::Clients(vec![client])
+        }
+        (Command::Report, None) if !options.help => return Err("report needs --client".to_string()),
+        (Command::Query, None) if !options.help => return Err("query needs --client".to_string()),
+        (Command::Report | Command::Query, _)
+        | (
+            Command::DisputeReport
+            | Command::TotalsReport
+            | Command::HistoryReport
+            | Command::LockReport
+            | Command::AnomalyReport
+            | Command::EscalationReport,
+            _,
+        ) => (),
+        (_, Some(_)) => return Err("--client is only valid with report and query".to_string()),
+        (_, None) => (),
+    }
+    match options.command {
+        Command::TotalsReport => {
+            if let (Some(from), Some(to)) = (period.from, period.to) {
+                if from > to {
+                    return Err("--from cannot be after --to".to_string());
+                }
+            }
+            options.process.engine.totals = Some(period);
+        }
+        Command::HistoryReport if period != TotalsPeriod::default() => {
+            return Err("--from and --to are only valid with report totals".to_string())
+        }
+        Command::HistoryReport => options.process.engine.history = true,
+        Command::EscalationReport if options.process.engine.dispute_escalation.days.is_empty() => {
+            options.process.engine.dispute_escalation = DisputeEscalation {
+                days: vec![30, 60, 90],
+            }
+        }
+        _ if period != TotalsPeriod::default() => {
+            return Err("--from and --to are only valid with report totals".to_string())
+        }
+        _ => (),
+    }
+    if options.command == Command::AnomalyReport {
+        if options.anomalies == AnomalyThresholds::default() && !options.help {
+            return Err("report anomalies needs --withdrawal-ratio or --max-disputes".to_string());
+        }
+    } else if options.anomalies != AnomalyThresholds::default() {
+        return Err(
+            "--withdrawal-ratio and --max-disputes are only valid with report anomalies"
+                .to_string(),
+        );
+    }
+    if matches!(options.command, Command::Consume | Command::Serve)
+        && (stdin || !options.inputs.is_empty())
+    {
+        return Err("consume and serve do not take input files".to_string());
+    }
+    if matches!(
+        options.command,
+        Command::Consume | Command::Serve | Command::Watch | Command::Validate | Command::Replay
+    ) && options.reconciliation.is_some()
+    {
+        return Err(
+            "--reconciliation is not valid with validate, consume, serve, watch and replay"
+                .to_string(),
+        );
+    }
+    if matches!(
+        options.command,
+        Command::Consume | Command::Serve | Command::Watch | Command::Validate | Command::Replay
+    ) && options.double_entry.is_some()
+    {
+        return Err(
+            "--double-entry is not valid with validate, consume, serve, watch and replay"
+                .to_string(),
+        );
+    }
+    if options.unmatched_disputes.is_some() {
+        if !options.process.engine.pending_disputes {
+            return Err("--unmatched-disputes needs --pending-disputes".to_string());
+        }
+        if matches!(
+            options.command,
+            Command::Consume
+                | Command::Serve
+                | Command::Watch
+                | Command::Validate
+                | Command::Replay
+        ) {
+            return Err(
+                "--unmatched-disputes is not valid with validate, consume, serve, watch and replay"
+                    .to_string(),
+            );
+        }
+    }
+    if matches!(
+        options.command,
+        Command::Consume | Command::Serve | Command::Watch
+    ) && options.summary.is_some()
+    {
+        return Err("--summary is not valid with consume, serve and watch".to_string());
+    }
+    if matches!(
+        options.command,
+        Command::Consume | Command::Serve | Command::Watch | Command::Validate | Command::Replay
+    ) && options.metrics.is_some()
+    {
+        return Err(
+            "--metrics is not valid with validate, consume, serve, watch and replay".to_string(),
+        );
+    }
+    if options.process.threads > 1 && !options.process.relaxed_tx_checks {
+        return Err(
+            "--threads needs --relaxed-tx-checks, as transaction IDs are then only checked \
+             within the clients of a thread"
+                .to_string(),
+        );
+    }
+    if options.command == Command::Watch {
+        if (stdin || options.inputs.len() != 1) && !options.help {
+            return Err("watch needs a single directory to watch".to_string());
+        }
+        if options.process.threads > 1
+            || options.process.parse_threads > 0
+            || options.checkpoint.is_some()
+            || options.emit_every.is_some()
+        {
+            return Err(
+                "--threads, --parse-threads, --checkpoint and --emit-every are not valid with watch"
+                    .to_string(),
+            );
+        }
+    }
+    if options.process.engine.withdrawal_disputes != WithdrawalDisputes::default()
+        && options.process.engine.disputes != DisputePolicy::DepositsAndWithdrawals
+    {
+        return Err("--withdrawal-disputes needs --disputes all".to_string());
+    }
+    if options.output_schema.extended && options.output_schema.columns.is_some() {
+        return Err(
+            "--extended cannot be combined with --output-columns, which can list \
+             disputed_count and disputed_amount"
+                .to_string(),
+        );
+    }
+    if options.process.strict_columns && !options.process.columns.is_empty() {
+        return Err("--column cannot be combined with --strict-columns".to_string());
+    }
+    if !options.process.csv.has_headers
+        && Column::ALL
+            .into_iter()
+            .any(|c| matches!(options.process.columns.get(c), Some(ColumnSource::Name(_))))
+    {
+        return Err(
+            "without a header row, --column can only map columns to a position".to_string(),
+        );
+    }
+    if options.emit_every.is_some() {
+        if options.command != Command::Process {
+            return Err("--emit-every is only valid when processing balances".to_string());
+        }
+        if options.output.is_none() && !options.help {
+            return Err("--emit-every needs --output".to_string());
+        }
+        if options.process.threads > 1 || options.checkpoint.is_some() {
+            return Err(
+                "--emit-every cannot be combined with --threads or --checkpoint".to_string(),
+            );
+        }
+    }
+    if options.dedup_report.is_some() && !options.process.dedup_files {
+        return Err("--dedup-report needs --dedup-files".to_string());
+    }
+    if options.process.dedup_files
+        && (options.checkpoint.is_some()
+            || matches!(
+                options.command,
+                Command::Validate
+                    | Command::Consume
+                    | Command::Serve
+                    | Command::Watch
+                    | Command::Replay
+                    | Command::Query
+                    | Command::Diff
+                    | Command::Repl
+            ))
+    {
+        return Err(
+            "--dedup-files is only valid when processing input files, without --checkpoint"
+                .to_string(),
+        );
+    }
+    if options.shard_output.is_some() {
+        if !matches!(
+            options.command,
+            Command::Process | Command::Consume | Command::Serve | Command::Watch | Command::Replay
+        ) {
+            return Err("--shard-output is only valid when writing balances".to_string());
+        }
+        if options.output.is_none() && !options.help {
+            return Err("--shard-output needs --output".to_string());
+        }
+    }
+    if options.compress.is_some() {
+        if matches!(options.command, Command::Validate | Command::Repl) {
+            return Err("--compress is not valid with validate and repl".to_string());
+        }
+        if options.shard_output.is_some() {
+            return Err("--compress is not valid with --shard-output".to_string());
+        }
+    }
+    if options.process.parse_threads > 0 {
+        if options.process.format != InputFormat::Csv {
+            return Err("--parse-threads only works with CSV input".to_string());
+        }
+        if options.inputs.is_empty() && !options.help {
+            return Err("--parse-threads needs input files, not standard input".to_string());
+        }
+        if options.process.threads > 1
+            || options.checkpoint.is_some()
+            || options.emit_every.is_some()
+        {
+            return Err(
+                "--parse-threads cannot be combined with --threads, --checkpoint or --emit-every"
+                    .to_string(),
+            );
+        }
+    }
+    if options.sqlite.is_some() && options.load_snapshot.is_some() {
+        return Err("--sqlite cannot be combined with --load-snapshot".to_string());
+    }
+    if options.checkpoint.is_none() && (options.resume || options.checkpoint_every.is_some()) {
+        return Err("--resume and --checkpoint-every need --checkpoint".to_string());
+    }
+    if options.checkpoint.is_some() {
+        if options.inputs.is_empty() && !options.help {
+            return Err("--checkpoint needs input files, not standard input".to_string());
+        }
+        if options.process.threads > 1
+            || options.sqlite.is_some()
+            || options.journal.is_some()
+            || options.event_log.is_some()
+        {
+            return Err(
+                "--checkpoint cannot be combined with --threads, --sqlite, --journal or --event-log"
+                    .to_string(),
+            );
+        }
+        if !options.process.encoding.is_utf8() {
+            return Err("--checkpoint only works with UTF-8 input".to_string());
+        }
+    }
+    if options.command == Command::Replay
+        && (options.load_snapshot.is_some()
+            || options.sqlite.is_some()
+            || options.journal.is_some()
+            || options.event_log.is_some())
+    {
+        return Err(
+            "replay cannot be combined with --load-snapshot, --sqlite, --journal or --event-log"
+                .to_string(),
+        );
+    }
+    if options.command == Command::Query {
+        if options.snapshot.is_none() && !options.help {
+            return Err("query needs --snapshot".to_string());
+        }
+        if stdin || !options.inputs.is_empty() {
+            return Err("query does not take input files".to_string());
+        }
+        if !matches!(
+            options.output_format,
+            OutputFormat::Csv | OutputFormat::Json | OutputFormat::JsonLines
+        ) {
+            return Err("query only writes csv, json or jsonl".to_string());
+        }
+    } else if options.snapshot.is_some() {
+        return Err("--snapshot is only valid with query".to_string());
+    }
+    if options.command == Command::Diff && (stdin || options.inputs.len() != 2) && !options.help {
+        return Err("diff needs two files to compare".to_string());
+    }
+    if options.command == Command::Generate {
+        if stdin || !options.inputs.is_empty() {
+            return Err("gen does not take input files".to_string());
+        }
+        if options.output_format != OutputFormat::Csv {
+            return Err("gen only writes csv".to_string());
+        }
+    } else if options.generate != GenerateOptions::default() {
+        return Err(
+            "--clients, --rows, --dispute-rate and --seed are only valid with gen".to_string(),
+        );
+    }
+    if options.command == Command::Repl {
+        if stdin {
+            return Err("repl reads its commands from standard input, not input".to_string());
+        }
+        if options.checkpoint.is_some()
+            || options.reconciliation.is_some()
+            || options.double_entry.is_some()
+            || options.unmatched_disputes.is_some()
+            || options.summary.is_some()
+            || options.metrics.is_some()
+        {
+            return Err(
+                "--checkpoint, --reconciliation, --double-entry, --unmatched-disputes, \
+                 --summary and --metrics are not valid with repl"
+                    .to_string(),
+            );
+        }
+    }
+    if options.command != Command::Serve && (options.listen.is_some() || options.grpc) {
+        return Err("--listen and --grpc are only valid with serve".to_string());
+    }
+    if options.command == Command::Consume {
+        if (options.brokers.is_none() || options.topic.is_none()) && !options.help {
+            return Err("consume needs --brokers and --topic".to_string());
+        }
+    } else if options.brokers.is_some()
+        || options.topic.is_some()
+        || options.group.is_some()
+        || options.interval.is_some()
+    {
+        return Err(
+            "--brokers, --topic, --group and --interval are only valid with consume".to_string(),
+        );
+    }
+    Ok(options)
+}
+
+/// Parses the value of `--delimiter` or `--quote`, which has to be a single
+/// ASCII character. A tab can be given as `\t` or `tab`, as it is hard to
+/// type into a shell.
+fn parse_csv_char(flag: &str, value: &str) -> Result<u8, String> {
+    match value {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "{} expects a single ASCII character, not '{}'",
+            flag, value
+        )),
+    }
+}
+
+/// Parses the value of `--from` or `--to`, a day in the form `YYYY-MM-DD`.
+/// Parses a number of things, which may be written like `1e7` as well.
+fn parse_count(flag: &str, value: &str) -> Result<u64, String> {
+    if let Ok(count) = value.parse() {
+        return Ok(count);
+    }
+    match value.parse::<f64>() {
+        Ok(count) if count >= 0.0 && count.fract() == 0.0 && count < u64::MAX as f64 => {
+            Ok(count as u64)
+        }
+        _ => Err(format!("{} expects a number, not '{}'", flag, value)),
+    }
+}
+
+fn parse_date(flag: &str, value: &str) -> Result<NaiveDate, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a date like 2024-12-31, not '{}'", flag, value))
+}
+
+/// Expands `*` and `?` in the file name part of `pattern` into the matching
+/// files, sorted by name. Anything without wildcards is taken as is, so a
+/// missing file is only reported once it is opened. Shells usually expand
+/// wildcards themselves; this is for the ones that don't, and for patterns
+/// that would exceed the length of a command line.
+fn expand_wildcards(pattern: &str) -> Result<Vec<String>, String> {
+    let is_wildcard = |s: &str| s.contains(['*', '?']);
+    if !is_wildcard(pattern) {
+        return Ok(vec![pattern.to_string()]);
+    }
+
+    let path = Path::new(pattern);
+    let file_pattern = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+    let dir = match path.parent() {
+        Some(p) if p.as_os_str().is_empty() => Path::new("."),
+        Some(p) => p,
+        None => Path::new("."),
+    };
+    if is_wildcard(&dir.to_string_lossy()) || !is_wildcard(file_pattern) {
+        return Err(format!(
+            "{}: wildcards are only supported in file names",
+            pattern
+        ));
+    }
+
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut matches: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| wildcard_match(file_pattern, name))
+        .map(|name| match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.join(name).to_string_lossy().into_owned(),
+            _ => name,
+        })
+        .collect();
+    if matches.is_empty() {
+        return Err(format!("no files match {}", pattern));
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Matches `name` against a pattern where `*` stands for any number of
+/// characters and `?` for exactly one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was seen, and how much of the name it covered.
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, covered)) => {
+                    p = star + 1;
+                    n = covered + 1;
+                    backtrack = Some((star, covered + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{parse_args, wildcard_match, Command, Options};
+    use payments::amount::{Amount, DecimalFormat, DecimalSeparator, Precision};
+    use payments::emit::EmitInterval;
+    use payments::engine::{
+        AccountPolicy, DisputeExpiry, DisputeOutcome, DisputePolicy, DisputeWindow,
+        DuplicatePolicy, ErrorMode, LockedAccountPolicy, NegativeBalancePolicy, OverdraftPolicy,
+        StatementPolicy, TotalsPeriod, WithdrawalDisputes,
+    };
+    use payments::error::Column;
+    use payments::generate::GenerateOptions;
+    use payments::id::ClientId;
+    use payments::input::{
+        ColumnSource, CsvOptions, InputFormat, TransactionType, TransactionTypeAliases,
+    };
+    use payments::metrics::MetricsTarget;
+    use payments::output::compress::Compression;
+    use payments::output::{OutputColumn, OutputFormat, Sharding};
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(Vec::new(), args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_no_arguments_reads_stdin() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+        assert_eq!(parse(&["-"]), Ok(Options::default()));
+    }
+
+    #[test]
+    fn test_input_file() {
+        let options = parse(&["in.csv"]).unwrap();
+        assert_eq!(options.inputs, vec!["in.csv"]);
+        assert_eq!(options.process.format, InputFormat::Csv);
+    }
+
+    #[test]
+    fn test_validate_command() {
+        let options = parse(&["validate", "--format", "jsonl", "in.jsonl"]).unwrap();
+        assert_eq!(options.command, Command::Validate);
+        assert_eq!(options.inputs, vec!["in.jsonl"]);
+        // Only the first argument can be a command.
+        assert_eq!(
+            parse(&["in.csv", "validate"]).unwrap().command,
+            Command::Process
+        );
+    }
+
+    #[test]
+    fn test_report_command() {
+        let options = parse(&["report", "--client", "70000", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::Report);
+        assert_eq!(
+            options.process.engine.statements,
+            StatementPolicy::Clients(vec![ClientId::new(70_000)])
+        );
+        assert!(parse(&["report", "in.csv"]).is_err());
+        assert!(parse(&["--client", "42", "in.csv"]).is_err());
+        assert!(parse(&["report", "--client", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_dispute_report_command() {
+        let options = parse(&["report", "disputes", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::DisputeReport);
+        assert_eq!(options.client, None);
+        assert_eq!(options.inputs, vec!["in.csv"]);
+        // No statements are needed for it.
+        assert_eq!(options.process.engine.statements, StatementPolicy::Off);
+        let options = parse(&["report", "disputes", "--client", "7"]).unwrap();
+        assert_eq!(options.client, Some(ClientId::new(7)));
+        assert_eq!(options.process.engine.statements, StatementPolicy::Off);
+    }
+
+    #[test]
+    fn test_totals_report_command() {
+        let options = parse(&["report", "totals", "--from", "2024-01-01", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::TotalsReport);
+        assert_eq!(
+            options.process.engine.totals,
+            Some(TotalsPeriod {
+                from: "2024-01-01".parse().ok(),
+                to: None,
+            })
+        );
+        let options = parse(&["report", "totals", "--client=7"]).unwrap();
+        assert_eq!(options.client, Some(ClientId::new(7)));
+        assert_eq!(options.process.engine.totals, Some(TotalsPeriod::default()));
+
+        assert!(parse(&["report", "totals", "--to", "31/12/2024"]).is_err());
+        assert!(parse(&[
+            "report",
+            "totals",
+            "--from",
+            "2024-02-01",
+            "--to",
+            "2024-01-31"
+        ])
+        .is_err());
+        assert!(parse(&["--from", "2024-01-01"]).is_err());
+        assert!(parse(&["report", "disputes", "--to", "2024-01-01"]).is_err());
+        assert_eq!(parse(&[]).unwrap().process.engine.totals, None);
+    }
+
+    #[test]
+    fn test_history_report_command() {
+        let options = parse(&["report", "history", "--client", "7", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::HistoryReport);
+        assert_eq!(options.client, Some(ClientId::new(7)));
+        assert_eq!(options.inputs, vec!["in.csv"]);
+        assert!(options.process.engine.history);
+        assert_eq!(options.process.engine.totals, None);
+        assert!(parse(&["report", "history", "--from", "2024-01-01"]).is_err());
+        assert!(!parse(&["report", "totals"]).unwrap().process.engine.history);
+    }
+
+    #[test]
+    fn test_anomaly_report_command() {
+        let options = parse(&["report", "anomalies", "--withdrawal-ratio=1.5", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::AnomalyReport);
+        assert_eq!(
+            options.anomalies.withdrawal_ratio,
+            Some(Amount::from_scaled(15_000))
+        );
+        assert_eq!(options.anomalies.max_disputes, None);
+        let options = parse(&[
+            "report",
+            "anomalies",
+            "--max-disputes",
+            "3",
+            "--client",
+            "7",
+        ]);
+        assert_eq!(options.unwrap().anomalies.max_disputes, Some(3));
+
+        assert!(parse(&["report", "anomalies"]).is_err());
+        assert!(parse(&["report", "anomalies", "--withdrawal-ratio", "-1"]).is_err());
+        assert!(parse(&["report", "anomalies", "--max-disputes", "many"]).is_err());
+        assert!(parse(&["--max-disputes", "3", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_lock_report_command() {
+        let options = parse(&["report", "locks", "--client", "7", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::LockReport);
+        assert_eq!(options.client, Some(ClientId::new(7)));
+        assert_eq!(options.inputs, vec!["in.csv"]);
+        assert_eq!(parse(&["report", "locks"]).unwrap().client, None);
+        assert!(parse(&["report", "locks", "--from", "2024-01-01"]).is_err());
+    }
+
+    #[test]
+    fn test_escalation_report_command() {
+        let options = parse(&["report", "escalations", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::EscalationReport);
+        assert_eq!(options.process.engine.dispute_escalation.days, [30, 60, 90]);
+        let options = parse(&["report", "escalations", "--escalate-disputes=45d,7d"]).unwrap();
+        assert_eq!(options.process.engine.dispute_escalation.days, [7, 45]);
+        let options = parse(&["--escalate-disputes", "30d", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::Process);
+        assert_eq!(options.process.engine.dispute_escalation.days, [30]);
+        assert!(parse(&["--escalate-disputes", "30"]).is_err());
+        assert!(parse(&["--escalate-disputes", "0d"]).is_err());
+    }
+
+    #[test]
+    fn test_gen_command() {
+        let options =
+            parse(&["gen", "--clients", "10000", "--rows", "1e7", "-o", "t.csv"]).unwrap();
+        assert_eq!(options.command, Command::Generate);
+        assert_eq!(
+            options.generate,
+            GenerateOptions {
+                clients: 10_000,
+                rows: 10_000_000,
+                ..GenerateOptions::default()
+            }
+        );
+        let options = parse(&["gen", "--dispute-rate=0.05", "--seed", "42"]).unwrap();
+        assert_eq!(options.generate.dispute_rate, 0.05);
+        assert_eq!(options.generate.seed, 42);
+
+        assert!(parse(&["gen", "--rows", "1.5"]).is_err());
+        assert!(parse(&["gen", "--clients", "0"]).is_err());
+        assert!(parse(&["gen", "--dispute-rate", "2"]).is_err());
+        assert!(parse(&["gen", "in.csv"]).is_err());
+        assert!(parse(&["gen", "--output-format", "json"]).is_err());
+        assert!(parse(&["--rows", "10", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_repl_command() {
+        let options = parse(&["repl", "--save-snapshot", "s.json", "in.csv"]).unwrap();
+        assert_eq!(options.command, Command::Repl);
+        assert_eq!(options.inputs, ["in.csv"]);
+        assert!(parse(&["repl"]).is_ok());
+
+        assert!(parse(&["repl", "-"]).is_err());
+        assert!(parse(&["repl", "--summary", "s.json"]).is_err());
+        assert!(parse(&["repl", "--checkpoint", "c.json", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_diff_command() {
+        let options = parse(&["diff", "old.csv", "new.json", "-o", "diff.csv"]).unwrap();
+        assert_eq!(options.command, Command::Diff);
+        assert_eq!(options.inputs, ["old.csv", "new.json"]);
+        assert!(parse(&["diff", "old.csv"]).is_err());
+        assert!(parse(&["diff", "old.csv", "-"]).is_err());
+        assert!(parse(&["diff", "a.csv", "b.csv", "c.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_query_command() {
+        let options = parse(&["query", "--snapshot", "state.json", "--client", "42"]).unwrap();
+        assert_eq!(options.command, Command::Query);
+        assert_eq!(options.snapshot.as_deref(), Some("state.json"));
+        assert_eq!(options.client, Some(ClientId::new(42)));
+        assert!(parse(&["query", "--snapshot", "state.json"]).is_err());
+        assert!(parse(&["query", "--client", "42"]).is_err());
+        assert!(parse(&["query", "--snapshot=s.json", "--client=1", "in.csv"]).is_err());
+        assert!(parse(&["--snapshot", "state.json", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_consume_command() {
+        let options = parse(&[
+            "consume",
+            "--brokers",
+            "kafka1:9092,kafka2:9092",
+            "--topic=transactions",
+            "--interval",
+            "10",
+        ])
+        .unwrap();
+        assert_eq!(options.command, Command::Consume);
+        assert_eq!(options.brokers.as_deref(), Some("kafka1:9092,kafka2:9092"));
+        assert_eq!(options.topic.as_deref(), Some("transactions"));
+        assert_eq!(options.group, None);
+        assert_eq!(options.interval, Some(10));
+        assert!(parse(&["consume", "--brokers", "kafka:9092"]).is_err());
+        assert!(parse(&["consume", "--brokers", "k:9092", "--topic", "t", "in.csv"]).is_err());
+        assert!(parse(&["--topic", "transactions", "in.csv"]).is_err());
+        assert!(parse(&["consume", "--interval", "soon"]).is_err());
+    }
+
+    #[test]
+    fn test_watch_command() {
+        let options = parse(&["watch", "--save-snapshot", "state.json", "drop"]).unwrap();
+        assert_eq!(options.command, Command::Watch);
+        assert_eq!(options.inputs, ["drop"]);
+        assert!(parse(&["watch"]).is_err());
+        assert!(parse(&["watch", "-"]).is_err());
+        assert!(parse(&["watch", "drop", "other"]).is_err());
+        assert!(parse(&["watch", "--threads", "4", "drop"]).is_err());
+        assert!(parse(&["watch", "--summary", "summary.json", "drop"]).is_err());
+    }
+
+    #[test]
+    fn test_serve_command() {
+        let options = parse(&["serve", "--listen", "0.0.0.0:80"]).unwrap();
+        assert_eq!(options.command, Command::Serve);
+        assert_eq!(options.listen.as_deref(), Some("0.0.0.0:80"));
+        assert_eq!(parse(&["serve"]).unwrap().listen, None);
+        assert!(parse(&["serve", "in.csv"]).is_err());
+        assert!(parse(&["--listen", "0.0.0.0:80"]).is_err());
+        assert!(parse(&["serve", "--grpc"]).unwrap().grpc);
+        assert!(parse(&["--grpc"]).is_err());
+    }
+
+    #[test]
+    fn test_several_input_files() {
+        let options = parse(&["b.csv", "a.csv"]).unwrap();
+        assert_eq!(options.inputs, vec!["b.csv", "a.csv"]);
+        assert!(parse(&["a.csv", "-"]).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.csv", "2024-01-01.csv"));
+        assert!(wildcard_match("2024-01-??.csv", "2024-01-31.csv"));
+        assert!(wildcard_match("a*b*c", "aXXbYbc"));
+        assert!(!wildcard_match("*.csv", "notes.txt"));
+        assert!(!wildcard_match("?.csv", "10.csv"));
+    }
+
+    #[test]
+    fn test_wildcard_input_files() {
+        let dir = std::env::temp_dir().join(format!("payments-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["day2.csv", "day1.csv", "notes.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let pattern = dir.join("day*.csv").to_string_lossy().into_owned();
+
+        let options = parse(&[&pattern]).unwrap();
+        assert_eq!(
+            options.inputs,
+            vec![
+                dir.join("day1.csv").to_string_lossy(),
+                dir.join("day2.csv").to_string_lossy()
+            ]
+        );
+        let pattern = dir.join("month*.csv").to_string_lossy().into_owned();
+        assert!(parse(&[&pattern]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_flag() {
+        let options = parse(&["--format", "jsonl", "in.jsonl"]).unwrap();
+        assert_eq!(options.process.format, InputFormat::JsonLines);
+        let options = parse(&["in.jsonl", "--format=jsonl"]).unwrap();
+        assert_eq!(options.process.format, InputFormat::JsonLines);
+        #[cfg(feature = "xml")]
+        assert_eq!(
+            parse(&["--format", "xml", "in.xml"])
+                .unwrap()
+                .process
+                .format,
+            InputFormat::Xml
+        );
+        #[cfg(not(feature = "xml"))]
+        assert!(parse(&["--format", "xml", "in.xml"]).is_err());
+    }
+
+    #[test]
+    fn test_csv_dialect_flags() {
+        assert_eq!(parse(&[]).unwrap().process.csv, CsvOptions::default());
+        let options = parse(&[
+            "--delimiter",
+            ";",
+            "--quote=\'",
+            "--no-headers",
+            "--flexible",
+        ])
+        .unwrap();
+        let csv = options.process.csv;
+        assert_eq!((csv.delimiter, csv.quote), (b';', b'\''));
+        assert!(!csv.has_headers && csv.flexible);
+        assert_eq!(
+            parse(&["--delimiter", "tab"])
+                .unwrap()
+                .process
+                .csv
+                .delimiter,
+            b'\t'
+        );
+        assert!(parse(&["--delimiter", ";;"]).is_err());
+        assert!(parse(&["--quote", "é"]).is_err());
+        assert!(parse(&["--quote", ","]).is_err());
+    }
+
+    #[test]
+    fn test_strict_flag() {
+        assert_eq!(
+            parse(&[]).unwrap().process.engine.error_mode,
+            ErrorMode::Lenient
+        );
+        let options = parse(&["--strict", "in.csv"]).unwrap();
+        assert_eq!(options.process.engine.error_mode, ErrorMode::Strict);
+        assert!(!options.process.strict_columns);
+    }
+
+    #[test]
+    fn test_column_flag() {
+        let options = parse(&["--column", "type=txn_kind", "--column=Amount=#4"]).unwrap();
+        assert_eq!(
+            options.process.columns.get(Column::Type),
+            Some(&ColumnSource::Name("txn_kind".to_string()))
+        );
+        assert_eq!(
+            options.process.columns.get(Column::Amount),
+            Some(&ColumnSource::Position(3))
+        );
+        assert_eq!(options.process.columns.get(Column::Client), None);
+        assert!(parse(&["--column", "type"]).is_err());
+        assert!(parse(&["--column", "kind=txn_kind"]).is_err());
+        assert!(parse(&["--column", "amount=#0"]).is_err());
+        assert!(parse(&["--column", "type=kind", "--strict-columns"]).is_err());
+        assert!(parse(&["--column", "type=kind", "--no-headers"]).is_err());
+        assert!(parse(&["--column", "type=#2", "--no-headers"]).is_ok());
+    }
+
+    #[test]
+    fn test_strict_columns_flag() {
+        assert!(!parse(&[]).unwrap().process.strict_columns);
+        assert!(parse(&["--strict-columns"]).unwrap().process.strict_columns);
+    }
+
+    #[test]
+    fn test_alias_flag() {
+        let options = parse(&["--alias", "wd=withdrawal", "--alias=DEP=Deposit"]).unwrap();
+        let mut expected = TransactionTypeAliases::new();
+        expected.insert("wd", TransactionType::Withdrawal);
+        expected.insert("dep", TransactionType::Deposit);
+        assert_eq!(options.process.aliases, expected);
+        assert!(parse(&["--alias", "wd"]).is_err());
+        assert!(parse(&["--alias", "wd=refund"]).is_err());
+    }
+
+    #[test]
+    fn test_process_command() {
+        let options = parse(&["process", "--format", "jsonl", "in.jsonl"]).unwrap();
+        assert_eq!(options.command, Command::Process);
+        assert_eq!(options.inputs, vec!["in.jsonl"]);
+    }
+
+    #[test]
+    fn test_metrics_flag() {
+        let options = parse(&["--metrics", "statsd://localhost"]).unwrap();
+        assert_eq!(
+            options.metrics,
+            Some(MetricsTarget::Statsd("localhost:8125".to_string()))
+        );
+        assert!(options.process.measure_latency);
+        assert!(parse(&["validate", "--metrics", "metrics.prom"]).is_err());
+    }
+
+    #[test]
+    fn test_config_command() {
+        let options = parse(&["config", "print-default"]).unwrap();
+        assert_eq!(options.command, Command::PrintDefaultConfig);
+        assert!(parse(&["config"]).is_err());
+        // The file itself is read before the arguments are parsed.
+        assert_eq!(
+            parse(&["--config", "other.toml", "--no-config"]),
+            Ok(Options::default())
+        );
+    }
+
+    #[test]
+    fn test_output_columns_flag() {
+        let options = parse(&["--output-columns", "client,total,disputed_count"]).unwrap();
+        assert_eq!(
+            options.output_schema.columns,
+            Some(vec![
+                OutputColumn::Client,
+                OutputColumn::Total,
+                OutputColumn::DisputedCount
+            ])
+        );
+        assert!(options.output_schema.header);
+        assert!(!parse(&["--no-output-header"]).unwrap().output_schema.header);
+        assert!(parse(&["--output-columns", "client,balance"]).is_err());
+        assert!(parse(&["--extended"]).unwrap().output_schema.extended);
+        assert!(parse(&["--extended", "--output-columns", "client,total"]).is_err());
+
+        let options = parse(&["--decimal-places", "8", "--fixed-decimals"]).unwrap();
+        assert_eq!(
+            options.output_schema.decimals,
+            DecimalFormat {
+                places: 8,
+                fixed: true
+            }
+        );
+        assert!(parse(&["--decimal-places", "3"]).is_err());
+    }
+
+    #[test]
+    fn test_output_format_flag() {
+        let options = parse(&["--output-format", "json"]).unwrap();
+        assert_eq!(options.output_format, OutputFormat::Json);
+        assert_eq!(options.process.format, InputFormat::Csv);
+    }
+
+    #[test]
+    fn test_output_flag() {
+        let options = parse(&["-o", "out.csv", "in.csv"]).unwrap();
+        assert_eq!(options.output.as_deref(), Some("out.csv"));
+        assert_eq!(options.inputs, vec!["in.csv"]);
+        let options = parse(&["--output=out.csv"]).unwrap();
+        assert_eq!(options.output.as_deref(), Some("out.csv"));
+    }
+
+    #[test]
+    fn test_rejects_flag() {
+        assert_eq!(parse(&[]).unwrap().rejects, None);
+        let options = parse(&["--rejects", "rejects.csv"]).unwrap();
+        assert_eq!(options.rejects.as_deref(), Some("rejects.csv"));
+    }
+
+    #[test]
+    fn test_snapshot_flags() {
+        let options = parse(&[
+            "--load-snapshot",
+            "monday.json",
+            "--save-snapshot=tuesday.json",
+        ])
+        .unwrap();
+        assert_eq!(options.load_snapshot.as_deref(), Some("monday.json"));
+        assert_eq!(options.save_snapshot.as_deref(), Some("tuesday.json"));
+    }
+
+    #[test]
+    fn test_journal_flag() {
+        assert_eq!(parse(&[]).unwrap().journal, None);
+        let options = parse(&["--journal", "journal.csv"]).unwrap();
+        assert_eq!(options.journal.as_deref(), Some("journal.csv"));
+    }
+
+    #[test]
+    fn test_event_log_flag() {
+        assert_eq!(parse(&[]).unwrap().event_log, None);
+        let options = parse(&["--event-log", "events.jsonl"]).unwrap();
+        assert_eq!(options.event_log.as_deref(), Some("events.jsonl"));
+        assert!(parse(&[
+            "--event-log",
+            "events.jsonl",
+            "--checkpoint",
+            "c.json",
+            "in.csv"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_replay_command() {
+        let options = parse(&["replay", "events.jsonl", "--save-snapshot", "s.json"]).unwrap();
+        assert_eq!(options.command, Command::Replay);
+        assert_eq!(options.inputs, ["events.jsonl"]);
+        assert!(parse(&["replay", "--event-log", "events.jsonl"]).is_err());
+        assert!(parse(&["replay", "--load-snapshot", "s.json"]).is_err());
+    }
+
+    #[test]
+    fn test_sqlite_flag() {
+        assert_eq!(parse(&[]).unwrap().sqlite, None);
+        let options = parse(&["--sqlite=state.db", "--save-snapshot", "s.json"]).unwrap();
+        assert_eq!(options.sqlite.as_deref(), Some("state.db"));
+        assert!(parse(&["--sqlite", "state.db", "--load-snapshot", "s.json"]).is_err());
+    }
+
+    #[test]
+    fn test_accounts_flag() {
+        let options = parse(&["--accounts=explicit"]).unwrap();
+        assert_eq!(options.process.engine.accounts, AccountPolicy::Explicit);
+        assert!(parse(&["--accounts", "lazy"]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_flags() {
+        let options = parse(&[
+            "--checkpoint",
+            "run.checkpoint",
+            "--checkpoint-every=500",
+            "--resume",
+            "in.csv",
+        ])
+        .unwrap();
+        assert_eq!(options.checkpoint.as_deref(), Some("run.checkpoint"));
+        assert_eq!(options.checkpoint_every, Some(500));
+        assert!(options.resume);
+        assert!(parse(&["--resume", "in.csv"]).is_err());
+        assert!(parse(&["--checkpoint", "run.checkpoint"]).is_err());
+        assert!(parse(&["--checkpoint", "c", "--checkpoint-every", "0", "in.csv"]).is_err());
+        assert!(parse(&["--checkpoint", "c", "--threads", "4", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_summary_flag() {
+        let options = parse(&["--summary", "summary.json", "in.csv"]).unwrap();
+        assert_eq!(options.summary.as_deref(), Some("summary.json"));
+        assert!(parse(&["serve", "--summary", "summary.json"]).is_err());
+    }
+
+    #[test]
+    fn test_run_report_flag() {
+        let options = parse(&["--run-report", "report.json", "in.csv"]).unwrap();
+        assert_eq!(options.run_report.as_deref(), Some("report.json"));
+        let options = parse(&["validate", "--run-report=report.json", "in.csv"]).unwrap();
+        assert_eq!(options.run_report.as_deref(), Some("report.json"));
+    }
+
+    #[test]
+    fn test_emit_every_flag() {
+        let options = parse(&["--emit-every", "1000", "-o", "out.csv", "in.csv"]).unwrap();
+        assert_eq!(options.emit_every, Some(EmitInterval::Records(1000)));
+        let options = parse(&["process", "--emit-every=30s", "-o", "out.csv"]).unwrap();
+        assert_eq!(
+            options.emit_every,
+            Some(EmitInterval::Time(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            parse(&["--emit-every", "1000", "in.csv"]).unwrap_err(),
+            "--emit-every needs --output"
+        );
+        assert!(parse(&["--emit-every", "1000", "-o", "out.csv", "--threads", "4"]).is_err());
+        assert!(parse(&["report", "--client", "1", "--emit-every", "10", "-o", "x"]).is_err());
+        assert!(parse(&["--emit-every", "0", "-o", "out.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_dedup_flags() {
+        let options = parse(&["--dedup-files", "--dedup-report=dups.csv", "a.csv", "b.csv"]);
+        let options = options.unwrap();
+        assert!(options.process.dedup_files);
+        assert_eq!(options.dedup_report.as_deref(), Some("dups.csv"));
+        assert!(parse(&["report", "totals", "--dedup-files", "a.csv"]).is_ok());
+        assert_eq!(
+            parse(&["--dedup-report", "dups.csv", "a.csv"]).unwrap_err(),
+            "--dedup-report needs --dedup-files"
+        );
+        assert!(parse(&["--dedup-files", "--checkpoint", "run.checkpoint", "a.csv"]).is_err());
+        assert!(parse(&["validate", "--dedup-files", "a.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_compress_flag() {
+        let options = parse(&["--compress", "zstd", "in.csv"]).unwrap();
+        assert_eq!(options.compress, Some(Compression::Zstd));
+        let options = parse(&["report", "locks", "--compress=gzip", "-o", "locks.csv"]);
+        assert_eq!(options.unwrap().compress, Some(Compression::Gzip));
+        assert_eq!(
+            parse(&["-o", "out.csv.gz", "in.csv"]).unwrap().compress,
+            None
+        );
+        assert!(parse(&["--compress", "bzip2", "in.csv"]).is_err());
+        assert!(parse(&["validate", "--compress", "gzip", "in.csv"]).is_err());
+        assert_eq!(
+            parse(&["--compress", "gzip", "--shard-output", "4", "-o", "out.csv"]).unwrap_err(),
+            "--compress is not valid with --shard-output"
+        );
+    }
+
+    #[test]
+    fn test_tenant_flags() {
+        let options = parse(&["--tenants", "in.csv"]).unwrap();
+        assert!(options.process.engine.tenants);
+        assert!(options.process.file_tenants.is_empty());
+        let options = parse(&["--tenant", "eu=eu.csv", "--tenant=us=us.csv", "all.csv"]).unwrap();
+        assert!(options.process.engine.tenants);
+        assert_eq!(options.inputs, ["eu.csv", "us.csv", "all.csv"]);
+        assert_eq!(
+            options.process.file_tenants.get(Path::new("us.csv")),
+            Some(&"us".parse().unwrap())
+        );
+        assert!(!options
+            .process
+            .file_tenants
+            .contains_key(Path::new("all.csv")));
+        assert!(parse(&["--tenant", "eu.csv"]).is_err());
+        assert!(parse(&["--tenant", "eu west=eu.csv"]).is_err());
+        let options = parse(&["report", "locks", "--tenants", "--sqlite", "state.db"]).unwrap();
+        assert!(options.process.engine.tenants);
+    }
+
+    #[test]
+    fn test_shard_output_flag() {
+        let options = parse(&["--shard-output", "16", "-o", "out.csv", "in.csv"]).unwrap();
+        assert_eq!(options.shard_output, Some(Sharding::Buckets(16)));
+        let options = parse(&[
+            "watch",
+            "--shard-output=range:100000",
+            "-o",
+            "out.csv",
+            "in",
+        ]);
+        assert_eq!(
+            options.unwrap().shard_output,
+            Some(Sharding::Range(100_000))
+        );
+        assert_eq!(
+            parse(&["--shard-output", "16", "in.csv"]).unwrap_err(),
+            "--shard-output needs --output"
+        );
+        assert!(parse(&["report", "locks", "--shard-output", "4", "-o", "x.csv"]).is_err());
+        assert!(parse(&["--shard-output", "0", "-o", "out.csv"]).is_err());
+        assert!(parse(&["--shard-output", "range:", "-o", "out.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_reconciliation_flag() {
+        let options = parse(&["--reconciliation", "checks.csv", "in.csv"]).unwrap();
+        assert_eq!(options.reconciliation.as_deref(), Some("checks.csv"));
+        let options = parse(&["report", "disputes", "--reconciliation=checks.csv"]).unwrap();
+        assert_eq!(options.reconciliation.as_deref(), Some("checks.csv"));
+        assert_eq!(
+            parse(&["validate", "--reconciliation", "checks.csv"]).unwrap_err(),
+            "--reconciliation is not valid with validate, consume, serve, watch and replay"
+        );
+    }
+
+    #[test]
+    fn test_double_entry_flag() {
+        let options = parse(&["in.csv"]).unwrap();
+        assert!(!options.process.engine.double_entry);
+        let options = parse(&["--double-entry", "journal.csv", "in.csv"]).unwrap();
+        assert_eq!(options.double_entry.as_deref(), Some("journal.csv"));
+        assert!(options.process.engine.double_entry);
+        assert!(parse(&["replay", "--double-entry=journal.csv", "events.jsonl"]).is_err());
+    }
+
+    #[test]
+    fn test_pending_disputes_flags() {
+        let options = parse(&["in.csv"]).unwrap();
+        assert!(!options.process.engine.pending_disputes);
+        let options = parse(&[
+            "--pending-disputes",
+            "--unmatched-disputes",
+            "unmatched.csv",
+            "in.csv",
+        ])
+        .unwrap();
+        assert!(options.process.engine.pending_disputes);
+        assert_eq!(options.unmatched_disputes.as_deref(), Some("unmatched.csv"));
+        assert!(parse(&[
+            "consume",
+            "--pending-disputes",
+            "--brokers",
+            "b",
+            "--topic",
+            "t"
+        ])
+        .is_ok());
+        assert!(parse(&["--unmatched-disputes", "unmatched.csv", "in.csv"]).is_err());
+        assert!(parse(&[
+            "serve",
+            "--pending-disputes",
+            "--unmatched-disputes=unmatched.csv"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_locked_accounts_flag() {
+        let options = parse(&["--locked-accounts", "allow-deposits"]).unwrap();
+        assert_eq!(
+            options.process.engine.locked_accounts,
+            LockedAccountPolicy::AllowDeposits
+        );
+    }
+
+    #[test]
+    fn test_duplicates_flag() {
+        let options = parse(&["--duplicates=keep-last"]).unwrap();
+        assert_eq!(options.process.engine.duplicates, DuplicatePolicy::KeepLast);
+    }
+
+    #[test]
+    fn test_disputes_flag() {
+        let options = parse(&["--disputes", "all"]).unwrap();
+        assert_eq!(
+            options.process.engine.disputes,
+            DisputePolicy::DepositsAndWithdrawals
+        );
+        assert_eq!(
+            options.process.engine.withdrawal_disputes,
+            WithdrawalDisputes::Credit
+        );
+        let options = parse(&["--disputes", "all", "--withdrawal-disputes", "pending"]).unwrap();
+        assert_eq!(
+            options.process.engine.withdrawal_disputes,
+            WithdrawalDisputes::Pending
+        );
+        assert!(parse(&["--withdrawal-disputes", "pending"]).is_err());
+        assert!(parse(&["--disputes", "all", "--withdrawal-disputes", "bank"]).is_err());
+    }
+
+    #[test]
+    fn test_dispute_window_flags() {
+        let options =
+            parse(&["--dispute-window", "30d", "--dispute-expiry=90d:chargeback"]).unwrap();
+        assert_eq!(
+            options.process.engine.dispute_window,
+            DisputeWindow::Days(30)
+        );
+        assert_eq!(
+            options.process.engine.dispute_expiry,
+            Some(DisputeExpiry {
+                days: 90,
+                outcome: DisputeOutcome::Chargeback
+            })
+        );
+        let options = parse(&["--dispute-window", "100tx"]).unwrap();
+        assert_eq!(
+            options.process.engine.dispute_window,
+            DisputeWindow::Transactions(100)
+        );
+        assert!(parse(&["--dispute-window", "30"]).is_err());
+        assert!(parse(&["--dispute-expiry", "90d"]).is_err());
+        assert!(parse(&["--dispute-expiry", "90d:ignore"]).is_err());
+    }
+
+    #[test]
+    fn test_overdraft_flag() {
+        let options = parse(&["--overdraft", "allow-negative"]).unwrap();
+        assert_eq!(
+            options.process.engine.overdraft,
+            OverdraftPolicy::AllowNegative
+        );
+        let options = parse(&["--overdraft=limit:100"]).unwrap();
+        assert_eq!(
+            options.process.engine.overdraft,
+            OverdraftPolicy::AllowUpToLimit(Amount::from_scaled(1_000_000))
+        );
+        assert!(parse(&["--overdraft", "limit:-1"]).is_err());
+        assert!(parse(&["--overdraft", "limit:"]).is_err());
+        assert!(parse(&["--overdraft", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn test_negative_balances_flag() {
+        assert_eq!(
+            parse(&[]).unwrap().process.engine.negative_balances,
+            NegativeBalancePolicy::Log
+        );
+        let options = parse(&["--negative-balances", "Abort"]).unwrap();
+        assert_eq!(
+            options.process.engine.negative_balances,
+            NegativeBalancePolicy::Abort
+        );
+        assert!(parse(&["--negative-balances", "ignore"]).is_err());
+    }
+
+    #[test]
+    fn test_encoding_flag() {
+        assert!(parse(&[]).unwrap().process.encoding.is_utf8());
+        let options = parse(&["--encoding", "latin1", "in.csv"]).unwrap();
+        assert_eq!(options.process.encoding.name(), "windows-1252");
+        assert!(parse(&["--encoding", "ebcdic"]).is_err());
+        assert!(parse(&["--encoding=utf-16le", "--checkpoint", "c", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_precision_flag() {
+        let options = parse(&["--precision", "reject"]).unwrap();
+        assert_eq!(options.process.precision, Precision::Reject);
+        assert!(parse(&["--precision", "round"]).is_err());
+    }
+
+    #[test]
+    fn test_lenient_amounts_flag() {
+        assert_eq!(parse(&[]).unwrap().process.lenient_amounts, None);
+        let options = parse(&["--lenient-amounts", "in.csv"]).unwrap();
+        assert_eq!(
+            options.process.lenient_amounts,
+            Some(DecimalSeparator::Point)
+        );
+        assert_eq!(options.inputs, ["in.csv"]);
+        let options = parse(&["--lenient-amounts=comma"]).unwrap();
+        assert_eq!(
+            options.process.lenient_amounts,
+            Some(DecimalSeparator::Comma)
+        );
+        assert!(parse(&["--lenient-amounts=semicolon"]).is_err());
+    }
+
+    #[test]
+    fn test_limits_flag() {
+        let options = parse(&["--limits", "amount=100,transactions=3"]).unwrap();
+        let limits = options.process.engine.limits;
+        assert_eq!(limits.amount, Some(Amount::from_scaled(1_000_000)));
+        assert_eq!(limits.daily, None);
+        assert_eq!(limits.transactions, Some(3));
+        assert!(parse(&["--limits", "amount=lots"]).is_err());
+    }
+
+    #[test]
+    fn test_rules_flag() {
+        let options = parse(&["--rules", "types=deposit+withdrawal,clients=1..5"]).unwrap();
+        assert_eq!(options.process.engine.rules.0.len(), 2);
+        assert!(parse(&["--rules", "clients=5..1"]).is_err());
+    }
+
+    #[test]
+    fn test_fees_flag() {
+        let options = parse(&["--fees", "withdrawal=1.5"]).unwrap();
+        assert_eq!(
+            options.process.engine.fees.withdrawal,
+            Amount::from_scaled(15_000)
+        );
+        assert_eq!(options.process.engine.fees.monthly, Amount::ZERO);
+        assert!(parse(&["--fees", "withdrawal"]).is_err());
+    }
+
+    #[test]
+    fn test_as_of_flag() {
+        let options = parse(&["--as-of", "2024-06-30T23:59:59Z"]).unwrap();
+        assert_eq!(
+            options.process.engine.as_of.unwrap().to_rfc3339(),
+            "2024-06-30T23:59:59+00:00"
+        );
+        assert!(parse(&["--as-of", "2024-06-30"]).is_err());
+    }
+
+    #[test]
+    fn test_verbose_flag() {
+        assert_eq!(parse(&[]).unwrap().verbose, 0);
+        assert_eq!(parse(&["-v"]).unwrap().verbose, 1);
+        assert_eq!(parse(&["-v", "--verbose"]).unwrap().verbose, 2);
+        assert_eq!(parse(&["-vv"]).unwrap().verbose, 2);
+    }
+
+    #[test]
+    fn test_threads_flag() {
+        let options = parse(&["--threads", "8", "--relaxed-tx-checks"]).unwrap();
+        assert_eq!(options.process.threads, 8);
+        assert!(options.process.relaxed_tx_checks);
+        assert!(parse(&["--threads", "8"]).is_err());
+        assert!(parse(&["--threads", "1"]).is_ok());
+        assert!(parse(&["--threads", "many"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_threads_flag() {
+        let options = parse(&["--parse-threads", "3", "in.csv"]).unwrap();
+        assert_eq!(options.process.parse_threads, 3);
+        assert!(parse(&["--parse-threads", "some", "in.csv"]).is_err());
+        assert!(parse(&["--parse-threads", "3"]).is_err());
+        assert!(parse(&["--parse-threads", "3", "--format", "jsonl", "in.jsonl"]).is_err());
+        assert!(parse(&["--parse-threads", "3", "--threads", "4", "in.csv"]).is_err());
+        assert!(parse(&["--parse-threads", "3", "--checkpoint", "c", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(parse(&["--format"]).is_err());
+        assert!(parse(&["-o"]).is_err());
+        assert!(parse(&["--format", "yaml"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::path::Path;
+
+/// The config file read from the current directory if `--config` does not
+/// name another one.
+pub const DEFAULT_FILE: &str = "payments.toml";
+
+/// What `payments config print-default` prints: every setting with its
+/// default value, or commented out if it has none.
+pub const DEFAULT_CONFIG: &str = r#"# Settings for payments, read from payments.toml in the current directory or
+# from the file given with --config. Every setting is named after the command
+# line flag it stands for, and flags given on the command line win over the
+# file. Flags that can be repeated take an array.
+
+# Input
+format = "csv"
+encoding = "utf-8"
+strict-columns = false
+strict = false
+delimiter = ","
+quote = '"'
+no-headers = false
+flexible = false
+# column = ["type=txn_kind", "amount=#4"]
+# alias = ["wd=withdrawal"]
+tenants = false
+# tenant = ["eu=eu.csv", "us=us.csv"]
+precision = "round-half-even"
+# lenient-amounts = "point"
+# threads = 4
+relaxed-tx-checks = false
+# parse-threads = 2
+
+# Policies
+accounts = "implicit"
+locked-accounts = "reject"
+disputes = "deposits"
+withdrawal-disputes = "credit"
+pending-disputes = false
+dispute-window = "unlimited"
+# dispute-expiry = "90d:chargeback"
+# escalate-disputes = "30d,60d,90d"
+duplicates = "reject"
+overdraft = "reject"
+negative-balances = "log"
+# fees = "withdrawal=1,deposit=0.5%,monthly=5"
+# limits = "amount=10000,daily=25000,transactions=500"
+# rules = "types=deposit+withdrawal,clients=1..65535,amount:withdrawal=..5000"
+# as-of = "2024-06-30T23:59:59Z"
+
+# Output
+output-format = "csv"
+# output-columns = "client,available,held,total,locked"
+no-output-header = false
+extended = false
+decimal-places = 4
+fixed-decimals = false
+# output = "balances.csv"
+# compress = "gzip"
+# shard-output = "range:100000"
+# emit-every = "60s"
+dedup-files = false
+# dedup-report = "duplicates.csv"
+# rejects = "rejects.csv"
+# summary = "summary.json"
+# metrics = "/var/lib/node_exporter/payments.prom"
+# reconciliation = "reconciliation.csv"
+# double-entry = "journal.csv"
+# unmatched-disputes = "unmatched-disputes.csv"
+# run-report = "run-report.json"
+
+# State
+# load-snapshot = "snapshot.json"
+# save-snapshot = "snapshot.json"
+# journal = "journal.jsonl"
+# event-log = "events.jsonl"
+# sqlite = "payments.db"
+# checkpoint = "run.checkpoint"
+# checkpoint-every = 1000000
+
+# report anomalies
+# withdrawal-ratio = 3
+# max-disputes = 5
+
+# consume
+# brokers = "localhost:9092"
+# topic = "transactions"
+# group = "payments"
+# interval = 60
+
+# serve
+grpc = false
+# listen = "127.0.0.1:8080"
+"#;
+
+/// Settings that only make sense on the command line.
+const COMMAND_LINE_ONLY: [&str; 4] = ["config", "no-config", "help", "verbose"];
+
+/// Reads the config file the command line asks for, `--config <file>`, or
+/// else `payments.toml` if there is one, unless `--no-config` is given.
+/// Returns its settings as flags, see `config_args`. The `config` command
+/// does not read any file, so a broken one can still be replaced.
+pub fn load(args: &[String]) -> Result<Vec<String>, String> {
+    if args.first().is_some_and(|a| a == "config") {
+        return Ok(Vec::new());
+    }
+    let mut path = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--no-config" => return Ok(Vec::new()),
+            "--config" => path = iter.next().cloned(),
+            _ => {
+                if let Some(value) = arg.strip_prefix("--config=") {
+                    path = Some(value.to_string());
+                }
+            }
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_FILE).is_file() => DEFAULT_FILE.to_string(),
+        None => return Ok(Vec::new()),
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+    config_args(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Turns the settings of a config file into the command line flags they
+/// stand for, to be parsed ahead of the actual command line so that flags
+/// given there win. A string or number is the value of the flag, `true`
+/// gives a flag without a value and `false` leaves it out, and an array
+/// repeats the flag for every element.
+pub fn config_args(text: &str) -> Result<Vec<String>, String> {
+    let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        if COMMAND_LINE_ONLY.contains(&key.as_str()) {
+            return Err(format!("'{}' can only be given on the command line", key));
+        }
+        match value {
+            toml::Value::Array(values) => {
+                for value in values {
+                    args.extend(flag(key, value)?);
+                }
+            }
+            value => args.extend(flag(key, value)?),
+        }
+    }
+    Ok(args)
+}
+
+/// The flag a single value of a setting stands for, if any.
+fn flag(key: &str, value: &toml::Value) -> Result<Option<String>, String> {
+    let value = match value {
+        toml::Value::Boolean(true) => return Ok(Some(format!("--{}", key))),
+        toml::Value::Boolean(false) => return Ok(None),
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(n) => n.to_string(),
+        toml::Value::Float(n) => n.to_string(),
+        toml::Value::Datetime(d) => d.to_string(),
+        toml::Value::Array(_) | toml::Value::Table(_) => {
+            return Err(format!("unexpected value for '{}'", key))
+        }
+    };
+    Ok(Some(format!("--{}={}", key, value)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{config_args, COMMAND_LINE_ONLY, DEFAULT_CONFIG};
+    use crate::cli::{parse_args, Options, USAGE};
+    use payments::engine::DisputePolicy;
+    use payments::input::TransactionType;
+
+    #[test]
+    fn test_default_config() {
+        let args = config_args(DEFAULT_CONFIG).unwrap();
+        let options = parse_args(args, Vec::new()).unwrap();
+        assert_eq!(options, Options::default());
+
+        // Every flag of the usage text can be set, or is left out on
+        // purpose.
+        let flags = USAGE
+            .lines()
+            .filter(|line| line.starts_with("    -"))
+            .filter_map(|line| line.split(' ').find(|w| w.starts_with("--")));
+        for flag in flags {
+            let name = flag.trim_start_matches('-').split('[').next().unwrap();
+            let per_run = ["client", "from", "to", "resume", "snapshot"];
+            if per_run.contains(&name) || COMMAND_LINE_ONLY.contains(&name) {
+                continue;
+            }
+            assert!(
+                DEFAULT_CONFIG.contains(&format!("\n{} = ", name))
+                    || DEFAULT_CONFIG.contains(&format!("\n# {} = ", name)),
+                "{} is missing from the default config",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_command_line_wins() {
+        let config = "disputes = \"all\"\nalias = [\"wd=withdrawal\", \"dep=deposit\"]\n\
+                      threads = 4\nrelaxed-tx-checks = true\nstrict = true\nflexible = false\n";
+        let args = config_args(config).unwrap();
+        assert_eq!(
+            args,
+            [
+                "--alias=wd=withdrawal",
+                "--alias=dep=deposit",
+                "--disputes=all",
+                "--relaxed-tx-checks",
+                "--strict",
+                "--threads=4"
+            ]
+        );
+
+        let options = parse_args(args.clone(), vec!["--threads".to_string(), "2".into()]);
+        let options = options.unwrap();
+        assert_eq!(options.process.threads, 2);
+        assert_eq!(
+            options.process.engine.disputes,
+            DisputePolicy::DepositsAndWithdrawals
+        );
+        assert_eq!(
+            options.process.aliases.get("dep"),
+            Some(TransactionType::Deposit)
+        );
+        assert!(!options.process.csv.flexible);
+
+        // The command still comes first.
+        let options = parse_args(args, vec!["validate".to_string()]).unwrap();
+        assert_eq!(options.command, crate::cli::Command::Validate);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(config_args("disputes = ").is_err());
+        assert!(config_args("verbose = 2").is_err());
+        assert!(config_args("[engine]\ndisputes = \"all\"").is_err());
+        let args = config_args("bogus = 1").unwrap();
+        assert_eq!(
+            parse_args(args, Vec::new()),
+            Err("unknown option --bogus".to_string())
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{ser::Serializer, Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A `Currency` is a three letter currency code such as `USD` or `EUR`.
+/// Codes are matched case-insensitively and always stored in upper case.
+/// Being three bytes long, a `Currency` can be copied around as freely as
+/// an `Amount`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+/// Returned when a string is not a three letter currency code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCurrencyError(String);
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid currency code '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters ever make it in, see `from_str`.
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b, c] if s.bytes().all(|b| b.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(ParseCurrencyError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CurrencyVisitor;
+
+        impl Visitor<'_> for CurrencyVisitor {
+            type Value = Currency;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a three letter currency code")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Currency, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        d.deserialize_str(CurrencyVisitor)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Currency;
+
+    #[test]
+    fn test_parse_currency() {
+        let usd: Currency = "usd".parse().unwrap();
+        assert_eq!(usd.to_string(), "USD");
+        assert_eq!("USD".parse(), Ok(usd));
+        for s in ["", "US", "USDX", "U$D", "€UR"] {
+            assert!(s.parse::<Currency>().is_err(), "{} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use super::error::Error;
+use super::id::{ClientId, TxId};
+use super::input::{InputRecord, Source, TransactionType};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// What makes two rows the same transaction. The type is part of it since
+/// a dispute, resolve and chargeback share the ID of the transaction they
+/// refer to, just like in a `Journal`.
+type Key = (TransactionType, ClientId, TxId);
+
+/// A row that was dropped for being in the file before as well, as listed
+/// in the report of `--dedup-report`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DroppedDuplicate {
+    pub file: String,
+    pub line: Option<u64>,
+    pub r#type: TransactionType,
+    pub client: ClientId,
+    pub tx: TxId,
+    /// The file the row was first seen in.
+    pub first_file: String,
+    pub raw: String,
+}
+
+/// Drops the rows of a file that were in the file before it as well, which
+/// happens when daily exports overlap. A row counts as the same if it has
+/// the same type, client and transaction ID. Only the file right before is
+/// looked at, so only two files' worth of keys are ever kept, and rows
+/// repeated within a file are left to the engine and its `DuplicatePolicy`.
+///
+/// Files are told apart by their path, so the rows have to be handed to
+/// `keep` one file after the other.
+#[derive(Debug, Default)]
+pub struct FileDedup {
+    enabled: bool,
+    file: Option<PathBuf>,
+    previous_file: Option<PathBuf>,
+    current: HashSet<Key>,
+    previous: HashSet<Key>,
+    /// Every row dropped so far, in order.
+    pub dropped: Vec<DroppedDuplicate>,
+}
+
+impl FileDedup {
+    /// A deduper that only drops anything if `enabled`, so it can be put
+    /// in the way of the records either way.
+    pub fn new(enabled: bool) -> Self {
+        FileDedup {
+            enabled,
+            ..FileDedup::default()
+        }
+    }
+
+    /// Whether the row read from `path` is to be processed, or was in the
+    /// file before as well. Rows that could not be parsed are always kept,
+    /// so they are reported as usual.
+    pub fn keep(
+        &mut self,
+        path: &Path,
+        source: &Source,
+        record: &Result<InputRecord, Error>,
+    ) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        if self.file.as_deref() != Some(path) {
+            self.previous = std::mem::take(&mut self.current);
+            self.previous_file = self.file.replace(path.to_path_buf());
+        }
+        let Ok(record) = record else {
+            return true;
+        };
+        let key = (record.r#type, record.client, record.tx);
+        if !self.previous.contains(&key) {
+            self.current.insert(key);
+            return true;
+        }
+        debug!(
+            line = source.line,
+            tx = %record.tx,
+            client = %record.client,
+            "dropped transaction that was in the file before as well"
+        );
+        let first_file = self.previous_file.as_deref().unwrap_or(Path::new(""));
+        self.dropped.push(DroppedDuplicate {
+            file: path.display().to_string(),
+            line: source.line,
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            first_file: first_file.display().to_string(),
+            raw: source.raw.clone(),
+        });
+        // The file after may overlap with this one just the same.
+        self.current.insert(key);
+        false
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::FileDedup;
+    use crate::input::{make_input_record, Source};
+    use csv::StringRecord;
+    use std::path::Path;
+
+    #[test]
+    fn test_drops_rows_of_the_file_before() {
+        let mut dedup = FileDedup::new(true);
+        let mut keep = |file: &str, row: Vec<&str>| {
+            let record = make_input_record(&StringRecord::from(row));
+            dedup.keep(Path::new(file), &Source::default(), &record)
+        };
+        assert!(keep("mon.csv", vec!["deposit", "1", "1", "1.0"]));
+        assert!(keep("mon.csv", vec!["deposit", "1", "2", "1.0"]));
+        // Repeated within a file, which is up to the engine.
+        assert!(keep("mon.csv", vec!["deposit", "1", "2", "1.0"]));
+        assert!(!keep("tue.csv", vec!["deposit", "1", "2", "1.0"]));
+        assert!(keep("tue.csv", vec!["dispute", "1", "2", ""]));
+        assert!(keep("tue.csv", vec!["deposit", "2", "2", "1.0"]));
+        assert!(keep("tue.csv", vec!["bogus", "1", "1", "1.0"]));
+        // Only the file right before counts.
+        assert!(keep("wed.csv", vec!["deposit", "1", "1", "1.0"]));
+        assert!(!keep("wed.csv", vec!["dispute", "1", "2", ""]));
+
+        assert_eq!(dedup.dropped.len(), 2);
+        assert_eq!(dedup.dropped[0].file, "tue.csv");
+        assert_eq!(dedup.dropped[0].first_file, "mon.csv");
+        assert_eq!(dedup.dropped[1].first_file, "tue.csv");
+
+        let mut dedup = FileDedup::new(false);
+        let record = make_input_record(&StringRecord::from(vec!["deposit", "1", "1", "1.0"]));
+        for file in ["mon.csv", "tue.csv"] {
+            assert!(dedup.keep(Path::new(file), &Source::default(), &record));
+        }
+    }
+}
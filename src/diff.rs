@@ -0,0 +1,230 @@
+use super::amount::Amount;
+use super::currency::Currency;
+use super::engine::{Engine, EngineConfig};
+use super::error::Error;
+use super::id::ClientId;
+use super::output::OutputRecord;
+use super::tenant::Tenant;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// What became of an account from one set of balances to the next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// The account is only in the new balances.
+    Added,
+    /// The account is only in the old balances.
+    Removed,
+    /// The account is in both, with other balances or lock state.
+    Changed,
+}
+
+/// How the balances of an account differ from one set of balances to the
+/// next, as found by `diff_balances`: the new balances minus the old ones,
+/// and whether the account was and is locked. An account that is only in
+/// one of them is taken to hold nothing in the other.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceDiff {
+    pub client: ClientId,
+    /// Only written out if any account has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any account has a tenant, just like the tenant
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub change: Change,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub was_locked: bool,
+    pub locked: bool,
+}
+
+/// Compares two sets of balances, such as the output of the same input
+/// before and after an upgrade of the engine, and returns every account
+/// that differs, sorted by tenant, client and currency. Accounts with the same
+/// balances in both are left out, so nothing is returned if the two are
+/// the same.
+pub fn diff_balances(old: &[OutputRecord], new: &[OutputRecord]) -> Vec<BalanceDiff> {
+    let by_account = |balances: &[OutputRecord]| -> BTreeMap<_, OutputRecord> {
+        balances
+            .iter()
+            .map(|b| ((b.tenant, b.client, b.currency), *b))
+            .collect()
+    };
+    let (old, new) = (by_account(old), by_account(new));
+    let mut keys: Vec<_> = old.keys().chain(new.keys()).copied().collect();
+    keys.sort();
+    keys.dedup();
+
+    let delta = |new: Amount, old: Amount| {
+        new.checked_sub(old)
+            .unwrap_or_else(|| Amount::from_scaled(new.scaled().saturating_sub(old.scaled())))
+    };
+    keys.into_iter()
+        .filter_map(|key| {
+            let (tenant, client, currency) = key;
+            let (before, after) = (old.get(&key), new.get(&key));
+            let change = match (before, after) {
+                (Some(before), Some(after)) if before == after => return None,
+                (Some(_), Some(_)) => Change::Changed,
+                (None, _) => Change::Added,
+                (_, None) => Change::Removed,
+            };
+            let empty = OutputRecord {
+                client,
+                currency,
+                tenant,
+                ..OutputRecord::default()
+            };
+            let (before, after) = (before.unwrap_or(&empty), after.unwrap_or(&empty));
+            Some(BalanceDiff {
+                client,
+                currency,
+                tenant,
+                change,
+                available: delta(after.available, before.available),
+                held: delta(after.held, before.held),
+                total: delta(after.total, before.total),
+                was_locked: before.locked,
+                locked: after.locked,
+            })
+        })
+        .collect()
+}
+
+/// A row of balances as written in CSV, with or without a currency or a
+/// tenant column. Any other columns are ignored.
+#[derive(Deserialize)]
+struct BalanceRow {
+    client: ClientId,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    tenant: Option<Tenant>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+/// Reads the balances of every account from a snapshot, see
+/// `Engine::snapshot`, or from balances written as CSV, see
+/// `output::dump_result_to_writer`. A snapshot is told apart by being a
+/// JSON object.
+pub fn read_balances<R: Read>(reader: R) -> Result<Vec<OutputRecord>, Error> {
+    let mut reader = BufReader::new(reader);
+    let start = reader.fill_buf()?;
+    if start.trim_ascii_start().starts_with(b"{") {
+        let engine = Engine::restore_from_reader(reader, EngineConfig::default())?;
+        return Ok(engine.balances());
+    }
+    let mut balances = Vec::new();
+    for row in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+    {
+        let row: BalanceRow = row?;
+        balances.push(OutputRecord {
+            client: row.client,
+            currency: row.currency,
+            tenant: row.tenant,
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+        });
+    }
+    Ok(balances)
+}
+
+/// Reads the balances of every account from a file, see `read_balances`.
+pub fn load_balances<P: AsRef<Path>>(path: P) -> Result<Vec<OutputRecord>, Error> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).map_err(|e| Error::from(e).in_file(path))?;
+    read_balances(file).map_err(|e| e.in_file(path))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{diff_balances, read_balances, BalanceDiff, Change};
+    use crate::amount::Amount;
+    use crate::engine::Engine;
+    use crate::id::ClientId;
+    use crate::input::make_input_record;
+    use csv::StringRecord;
+
+    #[test]
+    fn test_diff_balances() {
+        let old = read_balances(
+            "client,available,held,total,locked\n\
+             1,1.0,0.0,1.0,false\n\
+             2,5.0,0.0,5.0,false\n\
+             3,2.0,0.0,2.0,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let new = read_balances(
+            "client,available,held,total,locked,disputed_count\n\
+             1,1.0,0.0,1.0,false,0\n\
+             2,4.5,0.0,4.5,true,0\n\
+             4,0.5,0.5,1.0,false,1\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(diff_balances(&old, &old).is_empty());
+
+        let amount = |n: i64| Amount::from_scaled(n * 1000);
+        let diff = |client, change, available, held, total, was_locked, locked| BalanceDiff {
+            client: ClientId::new(client),
+            currency: None,
+            tenant: None,
+            change,
+            available: amount(available),
+            held: amount(held),
+            total: amount(total),
+            was_locked,
+            locked,
+        };
+        assert_eq!(
+            diff_balances(&old, &new),
+            [
+                diff(2, Change::Changed, -5, 0, -5, false, true),
+                diff(3, Change::Removed, -20, 0, -20, false, false),
+                diff(4, Change::Added, 5, 5, 10, false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_balances_from_snapshot() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.5", "", "EUR"],
+            vec!["deposit", "2", "2", "1.0"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let mut buf = Vec::new();
+        engine.snapshot_to_writer(&mut buf).unwrap();
+        let mut balances = read_balances(buf.as_slice()).unwrap();
+        balances.sort_by_key(|b| (b.client, b.currency));
+        let mut expected = engine.balances();
+        expected.sort_by_key(|b| (b.client, b.currency));
+        assert_eq!(balances, expected);
+
+        let csv = "client,currency,available,held,total,locked\n\
+                   1,EUR,2.5,0.0,2.5,false\n\
+                   2,,1.0,0.0,1.0,false\n";
+        assert_eq!(read_balances(csv.as_bytes()).unwrap(), balances);
+        assert!(read_balances("client,total\n1,1.0\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use super::amount::Amount;
+use super::currency::Currency;
+use super::id::{ClientId, TxId};
+use super::input::{InputRecord, TransactionType};
+use super::tenant::Tenant;
+use serde::Serialize;
+use std::fmt;
+
+/// The accounts of the double-entry book the `Engine` keeps when
+/// `EngineConfig::double_entry` is on. The funds of the clients are owed to
+/// them, so their accounts are credited when they grow, while the bank
+/// clearing account, which stands for the money actually held at the bank,
+/// is debited when money comes in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookAccount {
+    /// The available funds of the clients.
+    ClientAvailable,
+    /// The funds of the clients held while disputes are open.
+    ClientHeld,
+    /// Money coming in from and going out to the bank.
+    BankClearing,
+    /// Refunds of disputed withdrawals that were charged back, which the
+    /// operator pays for, as the money already left the bank.
+    ChargebackLosses,
+    /// The fees charged to the clients.
+    FeeIncome,
+    /// Adjustments and custom transactions, which move money for reasons of
+    /// their own.
+    Adjustments,
+}
+
+impl BookAccount {
+    /// The name of the account as it is written out.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BookAccount::ClientAvailable => "client_available",
+            BookAccount::ClientHeld => "client_held",
+            BookAccount::BankClearing => "bank_clearing",
+            BookAccount::ChargebackLosses => "chargeback_losses",
+            BookAccount::FeeIncome => "fee_income",
+            BookAccount::Adjustments => "adjustments",
+        }
+    }
+}
+
+impl fmt::Display for BookAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A single line of the double-entry journal, as listed by
+/// `Engine::book`: a debit or a credit to one of the `BookAccount`s. Every
+/// record that moves money is booked as a number of entries whose debits
+/// and credits add up to the same amount, which share the `tx` and `type`
+/// of the record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    pub client: ClientId,
+    /// Only written out if any entry has a currency, just like the currency
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any entry has a tenant, just like the tenant of
+    /// an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub account: BookAccount,
+    pub debit: Amount,
+    pub credit: Amount,
+}
+
+/// The balances of an account before or after a record, as far as the book
+/// goes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Funds {
+    pub(crate) available: Amount,
+    pub(crate) held: Amount,
+}
+
+/// Books `record`, which took the funds of its account from `before` to
+/// `after` and charged `fees` on top. The funds of the client are booked as
+/// they changed, the fees as income, and whatever it takes to balance them
+/// against the account the money came from or went to: the bank for
+/// deposits and withdrawals, and for disputes on withdrawals held as a
+/// pending credit, the losses for chargebacks of disputed withdrawals, and
+/// the adjustments account for adjustments and custom transactions. `kind`
+/// is the type of the transaction a dispute, resolve or chargeback refers
+/// to.
+pub(crate) fn book(
+    record: &InputRecord,
+    currency: Option<Currency>,
+    kind: Option<TransactionType>,
+    before: Funds,
+    after: Funds,
+    fees: Amount,
+) -> Vec<Entry> {
+    let counter = match record.r#type {
+        TransactionType::Chargeback if kind == Some(TransactionType::Withdrawal) => {
+            BookAccount::ChargebackLosses
+        }
+        TransactionType::Fee => BookAccount::FeeIncome,
+        TransactionType::CreditAdjustment
+        | TransactionType::DebitAdjustment
+        | TransactionType::Custom(_) => BookAccount::Adjustments,
+        _ => BookAccount::BankClearing,
+    };
+    // Every amount is what is credited to the account, so they have to add
+    // up to nothing.
+    let available = after.available - before.available;
+    let held = after.held - before.held;
+    let balance = -(available + held + fees);
+    [
+        (BookAccount::ClientAvailable, available),
+        (BookAccount::ClientHeld, held),
+        (BookAccount::FeeIncome, fees),
+        (counter, balance),
+    ]
+    .into_iter()
+    .filter(|(_, amount)| *amount != Amount::ZERO)
+    .map(|(account, amount)| Entry {
+        tx: record.tx,
+        r#type: record.r#type,
+        client: record.client,
+        currency,
+        tenant: record.tenant,
+        account,
+        debit: (-amount).max(Amount::ZERO),
+        credit: amount.max(Amount::ZERO),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{book, BookAccount, Funds};
+    use crate::amount::Amount;
+    use crate::input::{make_input_record, TransactionType};
+    use csv::StringRecord;
+
+    fn funds(available: i64, held: i64) -> Funds {
+        Funds {
+            available: Amount::from_scaled(available * 10_000),
+            held: Amount::from_scaled(held * 10_000),
+        }
+    }
+
+    #[test]
+    fn test_book() {
+        let book_row = |row: Vec<&str>, kind, before, after, fees: i64| {
+            let record = make_input_record(&StringRecord::from(row)).unwrap();
+            let entries = book(
+                &record,
+                None,
+                kind,
+                before,
+                after,
+                Amount::from_scaled(fees * 10_000),
+            );
+            let debits: Amount = entries
+                .iter()
+                .map(|e| e.debit)
+                .fold(Amount::ZERO, |a, b| a + b);
+            let credits = entries
+                .iter()
+                .map(|e| e.credit)
+                .fold(Amount::ZERO, |a, b| a + b);
+            assert_eq!(debits, credits);
+            entries
+                .iter()
+                .map(|e| (e.account, (e.debit - e.credit).scaled() / 10_000))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            book_row(
+                vec!["deposit", "1", "1", "10"],
+                None,
+                funds(0, 0),
+                funds(9, 0),
+                1
+            ),
+            [
+                (BookAccount::ClientAvailable, -9),
+                (BookAccount::FeeIncome, -1),
+                (BookAccount::BankClearing, 10),
+            ]
+        );
+        assert_eq!(
+            book_row(
+                vec!["withdrawal", "1", "2", "5"],
+                None,
+                funds(9, 0),
+                funds(4, 0),
+                0
+            ),
+            [
+                (BookAccount::ClientAvailable, 5),
+                (BookAccount::BankClearing, -5),
+            ]
+        );
+        assert_eq!(
+            book_row(
+                vec!["dispute", "1", "1", ""],
+                Some(TransactionType::Deposit),
+                funds(4, 0),
+                funds(-6, 10),
+                0
+            ),
+            [
+                (BookAccount::ClientAvailable, 10),
+                (BookAccount::ClientHeld, -10),
+            ]
+        );
+        assert_eq!(
+            book_row(
+                vec!["chargeback", "1", "1", ""],
+                Some(TransactionType::Deposit),
+                funds(-6, 10),
+                funds(-6, 0),
+                0
+            ),
+            [
+                (BookAccount::ClientHeld, 10),
+                (BookAccount::BankClearing, -10),
+            ]
+        );
+        assert_eq!(
+            book_row(
+                vec!["chargeback", "1", "2", ""],
+                Some(TransactionType::Withdrawal),
+                funds(9, -5),
+                funds(9, 0),
+                0
+            ),
+            [
+                (BookAccount::ClientHeld, -5),
+                (BookAccount::ChargebackLosses, 5),
+            ]
+        );
+        assert!(book_row(
+            vec!["freeze", "1", "3", ""],
+            None,
+            funds(9, 0),
+            funds(9, 0),
+            0
+        )
+        .is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+use super::dedup::FileDedup;
+use super::engine::Engine;
+use super::error::Error;
+use super::input::{InputFile, InputRecord, Source};
+use super::{read_files, read_records, ProcessOptions, Processed};
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often the engine is handed out for interim results while the input
+/// is processed, see `process_files_emitting`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EmitInterval {
+    /// Every this many records read, whether they applied or not.
+    Records(u64),
+    /// Whenever this much time has passed since the last time. The time is
+    /// only looked at as records come in, so nothing is emitted while the
+    /// input stalls.
+    Time(Duration),
+}
+
+/// Parses `<n>` for every n records, or `<n>s` for every n seconds.
+impl FromStr for EmitInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let interval = s.to_lowercase();
+        let parsed = match interval.strip_suffix('s') {
+            Some(seconds) => seconds
+                .parse()
+                .ok()
+                .map(|n| EmitInterval::Time(Duration::from_secs(n))),
+            None => interval.parse().ok().map(EmitInterval::Records),
+        };
+        match parsed {
+            Some(EmitInterval::Records(n)) if n > 0 => Ok(EmitInterval::Records(n)),
+            Some(EmitInterval::Time(t)) if !t.is_zero() => Ok(EmitInterval::Time(t)),
+            _ => Err(format!("unknown emit interval '{}'", s)),
+        }
+    }
+}
+
+/// Processes CSV or other input from `reader` like `process_reader_from`,
+/// and hands the engine to `emit` at every `every`, so long runs can write
+/// out interim balances. The engine is not handed out once more at the end;
+/// the final results are up to the caller, as usual.
+///
+/// Records are processed on the calling thread, whatever the number of
+/// threads in `options`. An error returned by `emit` stops processing.
+pub fn process_reader_emitting<R, F>(
+    engine: Engine,
+    reader: R,
+    options: &ProcessOptions,
+    every: EmitInterval,
+    emit: F,
+) -> Result<Processed, Error>
+where
+    R: Read,
+    F: FnMut(&Engine) -> Result<(), Error>,
+{
+    process_emitting(read_records(reader, options), engine, options, every, emit)
+}
+
+/// Processes several files like `process_files_from`, handing the engine to
+/// `emit` at every `every`, just like `process_reader_emitting`. The count
+/// of records and the time carry on from one file to the next.
+pub fn process_files_emitting<P, F>(
+    engine: Engine,
+    paths: &[P],
+    options: &ProcessOptions,
+    every: EmitInterval,
+    emit: F,
+) -> Result<Processed, Error>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Engine) -> Result<(), Error>,
+{
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = path.as_ref();
+        match InputFile::open(path) {
+            Ok(file) => files.push((path.to_path_buf(), file)),
+            Err(e) => return Err(Error::Io(e).in_file(path)),
+        }
+    }
+    let mut dedup = FileDedup::new(options.dedup_files);
+    let records = read_files(files, options, &mut dedup);
+    let mut res = process_emitting(records, engine, options, every, emit)?;
+    res.duplicates = dedup.dropped;
+    Ok(res)
+}
+
+fn process_emitting<I, F>(
+    records: I,
+    engine: Engine,
+    options: &ProcessOptions,
+    every: EmitInterval,
+    mut emit: F,
+) -> Result<Processed, Error>
+where
+    I: IntoIterator<Item = (Source, Result<InputRecord, Error>)>,
+    F: FnMut(&Engine) -> Result<(), Error>,
+{
+    let mut res = Processed::new(engine, options);
+    let mut since_emitted = 0;
+    let mut emitted_at = Instant::now();
+    for (source, record) in records {
+        res.push(source, record)?;
+        since_emitted += 1;
+        let due = match every {
+            EmitInterval::Records(n) => since_emitted >= n,
+            EmitInterval::Time(interval) => emitted_at.elapsed() >= interval,
+        };
+        if due {
+            info!(records = res.records, "emitting interim results");
+            emit(&res.engine)?;
+            since_emitted = 0;
+            emitted_at = Instant::now();
+        }
+    }
+    res.log_summary();
+    Ok(res)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{process_reader_emitting, EmitInterval};
+    use crate::amount::Amount;
+    use crate::engine::Engine;
+    use crate::ProcessOptions;
+    use std::time::Duration;
+
+    const DATA: &str = "type,client,tx,amount\n\
+                        deposit,1,1,1.0\n\
+                        deposit,1,2,1.0\n\
+                        bogus,1,3,1.0\n\
+                        deposit,1,4,1.0\n\
+                        deposit,1,5,1.0\n";
+
+    #[test]
+    fn test_emit_every_n_records() {
+        let mut emitted = Vec::new();
+        let processed = process_reader_emitting(
+            Engine::new(),
+            DATA.as_bytes(),
+            &ProcessOptions::default(),
+            EmitInterval::Records(2),
+            |engine| {
+                emitted.push(engine.balances()[0].total);
+                Ok(())
+            },
+        )
+        .unwrap();
+        // The invalid row counts towards the interval as well.
+        assert_eq!(
+            emitted,
+            [Amount::from_scaled(20_000), Amount::from_scaled(30_000)]
+        );
+        assert_eq!(processed.records, 5);
+        assert_eq!(processed.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_emit_error_stops_processing() {
+        let mut calls = 0;
+        let res = process_reader_emitting(
+            Engine::new(),
+            DATA.as_bytes(),
+            &ProcessOptions::default(),
+            EmitInterval::Time(Duration::ZERO),
+            |_| {
+                calls += 1;
+                Err(std::io::Error::other("disk full").into())
+            },
+        );
+        assert!(res.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_parse_emit_interval() {
+        assert_eq!("1000".parse(), Ok(EmitInterval::Records(1000)));
+        assert_eq!(
+            "30s".parse(),
+            Ok(EmitInterval::Time(Duration::from_secs(30)))
+        );
+        for s in ["", "0", "0s", "s", "-1", "5m", "1.5s"] {
+            assert!(s.parse::<EmitInterval>().is_err(), "{} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,5731 @@
+use super::account::{Account, AccountStatus, Lock};
+use super::amount::Amount;
+use super::currency::Currency;
+use super::double_entry::{self, Entry, Funds};
+use super::error::Error;
+use super::events::{Event, EventLog};
+use super::fees::{month, FeeSchedule};
+use super::handler::{Handlers, TransactionHandler};
+use super::id::{ClientId, TxId};
+use super::input::{CustomType, InputRecord, ReasonCode, TransactionType};
+use super::journal::Journal;
+use super::limits::Limits;
+use super::observer::{EngineObserver, Notification, Observers};
+use super::output::{write_atomically, OutputRecord};
+use super::tenant::Tenant;
+use super::validator::{Rules, Validator, Validators};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::warn;
+
+pub mod ledger;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use ledger::{Ledger, MemoryLedger};
+
+/// The dispute state of a stored transaction. Every transaction starts out
+/// as `Normal` and can only move forward:
+///
+/// ```text
+/// Normal --dispute--> Disputed --resolve----> Resolved
+///                              \--chargeback--> ChargedBack
+/// ```
+///
+/// `Resolved` and `ChargedBack` are final, so a transaction can be disputed
+/// at most once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionState {
+    Normal,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TransactionState {
+    /// The name of the state as it is written out.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionState::Normal => "normal",
+            TransactionState::Disputed => "disputed",
+            TransactionState::Resolved => "resolved",
+            TransactionState::ChargedBack => "charged_back",
+        }
+    }
+}
+
+/// A `StoredTransaction` keeps only the details of an applied deposit or
+/// withdrawal that are needed to dispute it later on. Nothing else from the
+/// input row has to be remembered.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    client: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<Tenant>,
+    kind: TransactionType,
+    amount: Amount,
+    state: TransactionState,
+    /// The part of the amount under dispute, if the dispute named less than
+    /// all of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disputed: Option<Amount>,
+    /// When the dispute was opened, if the dispute had a timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disputed_at: Option<DateTime<Utc>>,
+    /// When the transaction happened, if its record had a timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+    /// The number of deposits and withdrawals its account had seen with
+    /// this one, see `DisputeWindow::Transactions`.
+    #[serde(default)]
+    seq: u64,
+}
+
+impl StoredTransaction {
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    /// Whether this was a deposit or a withdrawal.
+    pub fn kind(&self) -> TransactionType {
+        self.kind
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    /// When the dispute was opened, if it was and the dispute had a
+    /// timestamp.
+    pub fn disputed_at(&self) -> Option<DateTime<Utc>> {
+        self.disputed_at
+    }
+
+    /// How much of the transaction is under dispute: all of it, unless the
+    /// dispute carried a smaller amount. A resolve or chargeback settles
+    /// just that much.
+    fn in_dispute(&self) -> Amount {
+        self.disputed.unwrap_or(self.amount)
+    }
+
+    /// The amount a dispute on this transaction moves around. Disputing a
+    /// withdrawal has to move funds the opposite way to disputing a deposit,
+    /// which is why its amount is negated.
+    fn disputed_amount(&self) -> Amount {
+        match self.kind {
+            TransactionType::Withdrawal => -self.in_dispute(),
+            _ => self.in_dispute(),
+        }
+    }
+}
+
+/// The reasons the `Engine` can refuse to apply a record. A rejected record
+/// never changes the state of the engine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// A deposit or withdrawal did not carry an amount.
+    MissingAmount,
+    /// The record refers to a client that has no account yet.
+    UnknownClient,
+    /// A withdrawal exceeds the funds available to the client.
+    InsufficientFunds,
+    /// A dispute, resolve or chargeback refers to a transaction that was
+    /// never applied for this client.
+    UnknownTransaction,
+    /// A dispute refers to a transaction that is already disputed.
+    AlreadyDisputed,
+    /// A dispute refers to a kind of transaction the configured
+    /// `DisputePolicy` does not allow to be disputed.
+    NotDisputable,
+    /// A resolve or chargeback refers to a transaction that is not disputed.
+    NotDisputed,
+    /// The dispute on the referenced transaction has already been resolved
+    /// or charged back.
+    DisputeClosed,
+    /// A deposit or withdrawal reuses the ID of an earlier transaction and
+    /// the configured `DuplicatePolicy` rejects duplicates.
+    DuplicateTransaction,
+    /// The client's account has been locked by a chargeback and the
+    /// configured `LockedAccountPolicy` does not allow this record.
+    AccountLocked,
+    /// Applying the record would take a balance beyond what an `Amount` can
+    /// represent.
+    Overflow,
+    /// The record happened after the cutoff in `EngineConfig::as_of`.
+    AfterCutoff,
+    /// A dispute, resolve or chargeback names a different currency than the
+    /// transaction it refers to.
+    CurrencyMismatch,
+    /// The `Journal` shows the record was already applied in an earlier run.
+    Replayed,
+    /// The account has been frozen, so no money may move in or out of it.
+    AccountFrozen,
+    /// The account has been closed and takes no transactions until it is
+    /// opened again.
+    AccountClosed,
+    /// An unfreeze refers to an account that is not frozen.
+    AccountNotFrozen,
+    /// An unlock refers to an account that is not locked.
+    AccountNotLocked,
+    /// A dispute, resolve or chargeback refers to a transaction of another
+    /// client.
+    ClientMismatch,
+    /// An account can only be closed once nothing is left in it.
+    NonZeroBalance,
+    /// An open refers to an account that is already open.
+    AccountExists,
+    /// The transaction is too old to be disputed, see `DisputeWindow`.
+    DisputeWindowClosed,
+    /// A dispute carries an amount that is not above zero, or is larger
+    /// than that of the transaction it disputes.
+    InvalidDisputeAmount,
+    /// The record is of a custom type the engine has no handler for, see
+    /// `Engine::set_handler`.
+    UnhandledTransactionType,
+    /// An adjustment did not say why it was made.
+    MissingReason,
+    /// A deposit or withdrawal is larger than `Limits::amount` allows.
+    AmountOverLimit,
+    /// A deposit or withdrawal would take what the account moved that day
+    /// beyond `Limits::daily`.
+    DailyLimitExceeded,
+    /// The account has taken as many deposits and withdrawals as
+    /// `Limits::transactions` allows.
+    TransactionLimitReached,
+    /// The `Rules` of the configuration do not take records of this type.
+    TypeNotAllowed,
+    /// The `Rules` of the configuration do not take records of this client.
+    ClientOutOfRange,
+    /// The amount is outside the range the `Rules` of the configuration
+    /// allow for the type of the record.
+    AmountOutOfRange,
+    /// The record would leave the account with negative funds, and the
+    /// `NegativeBalancePolicy` does not allow that.
+    NegativeBalance,
+    /// A deposit or withdrawal carries an amount that is not above zero,
+    /// which would move money the other way.
+    NonPositiveAmount,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RejectReason::MissingAmount => "missing amount",
+            RejectReason::UnknownClient => "unknown client",
+            RejectReason::InsufficientFunds => "insufficient funds",
+            RejectReason::UnknownTransaction => "unknown transaction",
+            RejectReason::AlreadyDisputed => "transaction is already disputed",
+            RejectReason::NotDisputable => "transaction cannot be disputed",
+            RejectReason::NotDisputed => "transaction is not disputed",
+            RejectReason::DisputeClosed => "dispute has already been closed",
+            RejectReason::DuplicateTransaction => "duplicate transaction ID",
+            RejectReason::AccountLocked => "account is locked",
+            RejectReason::Overflow => "balance would overflow",
+            RejectReason::AfterCutoff => "transaction is after the cutoff",
+            RejectReason::CurrencyMismatch => "currency differs from the disputed transaction",
+            RejectReason::Replayed => "already applied in an earlier run",
+            RejectReason::AccountFrozen => "account is frozen",
+            RejectReason::AccountClosed => "account is closed",
+            RejectReason::AccountNotFrozen => "account is not frozen",
+            RejectReason::AccountNotLocked => "account is not locked",
+            RejectReason::ClientMismatch => "transaction belongs to another client",
+            RejectReason::NonZeroBalance => "account still holds funds",
+            RejectReason::AccountExists => "account is already open",
+            RejectReason::DisputeWindowClosed => "dispute window has closed",
+            RejectReason::InvalidDisputeAmount => "invalid dispute amount",
+            RejectReason::UnhandledTransactionType => "no handler for transaction type",
+            RejectReason::MissingReason => "missing reason code",
+            RejectReason::AmountOverLimit => "amount is over the limit",
+            RejectReason::DailyLimitExceeded => "daily limit exceeded",
+            RejectReason::TransactionLimitReached => "transaction limit reached",
+            RejectReason::TypeNotAllowed => "transaction type is not allowed",
+            RejectReason::ClientOutOfRange => "client is out of range",
+            RejectReason::AmountOutOfRange => "amount is out of range",
+            RejectReason::NegativeBalance => "balance would go negative",
+            RejectReason::NonPositiveAmount => "amount is not positive",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::error::Error for RejectReason {}
+
+/// What the engine does with records for an account that has been locked by
+/// a chargeback.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum LockedAccountPolicy {
+    /// The account is frozen and every later record for it is rejected.
+    #[default]
+    RejectAll,
+    /// Money may still be paid into a locked account, but nothing else.
+    AllowDeposits,
+    /// Locking an account has no effect on later records. This is how the
+    /// engine originally behaved.
+    AllowAll,
+}
+
+impl LockedAccountPolicy {
+    fn allows(self, transaction_type: &TransactionType) -> bool {
+        match self {
+            LockedAccountPolicy::RejectAll => false,
+            LockedAccountPolicy::AllowDeposits => *transaction_type == TransactionType::Deposit,
+            LockedAccountPolicy::AllowAll => true,
+        }
+    }
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(LockedAccountPolicy::RejectAll),
+            "allow-deposits" => Ok(LockedAccountPolicy::AllowDeposits),
+            "allow" => Ok(LockedAccountPolicy::AllowAll),
+            _ => Err(format!("unknown locked account policy '{}'", s)),
+        }
+    }
+}
+
+/// How accounts come into existence.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum AccountPolicy {
+    /// The first deposit of a client opens an account, as does an `open`
+    /// record. This is how the engine originally behaved.
+    #[default]
+    Implicit,
+    /// Only an `open` record opens an account, and a deposit to an account
+    /// that was never opened is rejected like any other transaction for an
+    /// unknown client.
+    Explicit,
+}
+
+impl FromStr for AccountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "implicit" => Ok(AccountPolicy::Implicit),
+            "explicit" => Ok(AccountPolicy::Explicit),
+            _ => Err(format!("unknown account policy '{}'", s)),
+        }
+    }
+}
+
+/// What the engine does with a deposit or withdrawal whose transaction ID
+/// has been seen before. Transaction IDs are expected to be globally unique,
+/// so this also covers IDs reused by a different client.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// The duplicate is rejected and never touches any balance.
+    #[default]
+    Reject,
+    /// The duplicate is applied, but later disputes keep referring to the
+    /// first transaction with that ID.
+    KeepFirst,
+    /// The duplicate is applied, and later disputes refer to it rather than
+    /// to the earlier transaction. A transaction that is or has been under
+    /// dispute is never replaced, as that would corrupt the held funds.
+    KeepLast,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "keep-first" => Ok(DuplicatePolicy::KeepFirst),
+            "keep-last" => Ok(DuplicatePolicy::KeepLast),
+            _ => Err(format!("unknown duplicate policy '{}'", s)),
+        }
+    }
+}
+
+/// How far a withdrawal may take the available funds of an account.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum OverdraftPolicy {
+    /// A withdrawal must be covered by the available funds.
+    #[default]
+    Reject,
+    /// The available funds may go negative without limit.
+    AllowNegative,
+    /// The available funds may go negative by at most the given amount.
+    AllowUpToLimit(Amount),
+}
+
+impl OverdraftPolicy {
+    /// Whether an account may be left with `available` funds.
+    pub(crate) fn allows(self, available: Amount) -> bool {
+        match self {
+            OverdraftPolicy::Reject => available >= Amount::ZERO,
+            OverdraftPolicy::AllowNegative => true,
+            OverdraftPolicy::AllowUpToLimit(limit) => available >= -limit,
+        }
+    }
+}
+
+/// Besides `reject` and `allow-negative`, a limit is given as `limit:` and
+/// the amount, e.g. `limit:100`.
+impl FromStr for OverdraftPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(OverdraftPolicy::Reject),
+            "allow-negative" => Ok(OverdraftPolicy::AllowNegative),
+            policy => match policy.strip_prefix("limit:").map(str::parse::<Amount>) {
+                Some(Ok(limit)) if limit >= Amount::ZERO => {
+                    Ok(OverdraftPolicy::AllowUpToLimit(limit))
+                }
+                Some(_) => Err(format!("invalid overdraft limit in '{}'", s)),
+                None => Err(format!("unknown overdraft policy '{}'", s)),
+            },
+        }
+    }
+}
+
+/// What the `Engine` does with a record that takes the funds of an account
+/// below zero, which nothing else stops: a dispute on money that has
+/// already been spent, a resolve or chargeback of more than is held, a
+/// deposit fee larger than the deposit, or a custom transaction. A
+/// withdrawal, fee or debit adjustment, or anything else, that takes the
+/// available funds below zero only as far as the `OverdraftPolicy` allows
+/// is always applied, and held funds do not count while a withdrawal is
+/// disputed under `WithdrawalDisputes::Credit`, which leaves them negative
+/// on purpose. A record that leaves funds less negative than they were is
+/// always fine.
+///
+/// Whatever the policy, every such record is kept track of, see
+/// `Engine::negative_balances`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum NegativeBalancePolicy {
+    /// The record is applied, with a warning.
+    #[default]
+    Log,
+    /// The record is rejected.
+    Reject,
+    /// The record is rejected, and processing stops with an error. The
+    /// `Engine` rejects it just like `Reject` does, it is up to whoever
+    /// feeds it records to stop, as the `process_*` functions do.
+    Abort,
+}
+
+impl FromStr for NegativeBalancePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "log" => Ok(NegativeBalancePolicy::Log),
+            "reject" => Ok(NegativeBalancePolicy::Reject),
+            "abort" => Ok(NegativeBalancePolicy::Abort),
+            _ => Err(format!("unknown negative balance policy '{}'", s)),
+        }
+    }
+}
+
+/// Which kinds of transactions may be disputed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// Only deposits can be disputed.
+    #[default]
+    DepositsOnly,
+    /// Withdrawals can be disputed as well. As the money of a withdrawal has
+    /// already left the account, a dispute on it works the opposite way: the
+    /// amount is credited back to the client while the dispute is open, a
+    /// resolve takes it away again, and a chargeback makes the refund final.
+    /// `WithdrawalDisputes` decides whether the credit is available while
+    /// the dispute is open.
+    DepositsAndWithdrawals,
+}
+
+impl DisputePolicy {
+    fn allows(self, transaction_type: &TransactionType) -> bool {
+        match self {
+            DisputePolicy::DepositsOnly => *transaction_type == TransactionType::Deposit,
+            DisputePolicy::DepositsAndWithdrawals => true,
+        }
+    }
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "deposits" => Ok(DisputePolicy::DepositsOnly),
+            "all" => Ok(DisputePolicy::DepositsAndWithdrawals),
+            _ => Err(format!("unknown dispute policy '{}'", s)),
+        }
+    }
+}
+
+/// How funds move when a withdrawal is disputed, which
+/// `DisputePolicy::DepositsAndWithdrawals` allows.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum WithdrawalDisputes {
+    /// The amount is credited back to the available funds while the dispute
+    /// is open, leaving the held funds negative by as much.
+    #[default]
+    Credit,
+    /// The amount is held as a credit pending from the bank: the held funds
+    /// and the total grow by it, while the available funds stay as they
+    /// are, so the client cannot spend money that may never come back. A
+    /// resolve drops the pending credit again, and a chargeback refunds it
+    /// to the available funds.
+    Pending,
+}
+
+impl FromStr for WithdrawalDisputes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "credit" => Ok(WithdrawalDisputes::Credit),
+            "pending" => Ok(WithdrawalDisputes::Pending),
+            _ => Err(format!("unknown withdrawal dispute flow '{}'", s)),
+        }
+    }
+}
+
+/// How long after a deposit or withdrawal it can still be disputed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// A transaction can be disputed at any time.
+    #[default]
+    Unlimited,
+    /// A transaction can be disputed for this many days after it happened.
+    /// This goes by the timestamps of the records, so a dispute is allowed
+    /// if either the transaction or the dispute has none.
+    Days(u32),
+    /// A transaction can be disputed until this many more deposits and
+    /// withdrawals have been applied to its account.
+    Transactions(u64),
+}
+
+impl DisputeWindow {
+    /// Whether `transaction` of `account` can still be disputed `at` the
+    /// time of the dispute.
+    fn allows(
+        self,
+        transaction: &StoredTransaction,
+        account: &Account,
+        at: Option<DateTime<Utc>>,
+    ) -> bool {
+        match self {
+            DisputeWindow::Unlimited => true,
+            DisputeWindow::Days(days) => match (transaction.timestamp, at) {
+                (Some(timestamp), Some(at)) => at - timestamp <= days_duration(days),
+                _ => true,
+            },
+            DisputeWindow::Transactions(count) => {
+                account.transactions().saturating_sub(transaction.seq) <= count
+            }
+        }
+    }
+}
+
+/// Besides `unlimited`, a window is given in days as e.g. `30d`, or in
+/// transactions as e.g. `100tx`.
+impl FromStr for DisputeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let window = s.to_lowercase();
+        if window == "unlimited" {
+            return Ok(DisputeWindow::Unlimited);
+        }
+        if let Some(days) = parse_days(&window) {
+            return Ok(DisputeWindow::Days(days));
+        }
+        match window.strip_suffix("tx").map(str::parse) {
+            Some(Ok(count)) => Ok(DisputeWindow::Transactions(count)),
+            _ => Err(format!("unknown dispute window '{}'", s)),
+        }
+    }
+}
+
+/// How an expired dispute is closed, see `DisputeExpiry`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeOutcome {
+    /// The held funds are released to the client.
+    Resolve,
+    /// The held funds are taken away, and the account is locked.
+    Chargeback,
+}
+
+/// Closes disputes that have been open for too long, as though a resolve or
+/// a chargeback had come in for them `days` after the dispute was opened.
+/// This goes by the timestamps of the records: a dispute without one never
+/// expires.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DisputeExpiry {
+    pub days: u32,
+    pub outcome: DisputeOutcome,
+}
+
+/// Written as the number of days and the outcome, e.g. `90d:chargeback`.
+impl FromStr for DisputeExpiry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let (days, outcome) = lower
+            .split_once(':')
+            .ok_or_else(|| format!("invalid dispute expiry '{}'", s))?;
+        let days = parse_days(days).ok_or_else(|| format!("invalid dispute expiry '{}'", s))?;
+        let outcome = match outcome {
+            "resolve" => DisputeOutcome::Resolve,
+            "chargeback" => DisputeOutcome::Chargeback,
+            _ => return Err(format!("unknown dispute outcome '{}'", outcome)),
+        };
+        Ok(DisputeExpiry { days, outcome })
+    }
+}
+
+/// The ages at which open disputes are escalated, so compliance can chase
+/// them before they have to be charged back. Like `DisputeExpiry`, this
+/// goes by the timestamps of the records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DisputeEscalation {
+    /// The ages in days, in ascending order.
+    pub days: Vec<u32>,
+}
+
+/// Written as a comma-separated list of ages in days, e.g. `30d,60d,90d`,
+/// in any order.
+impl FromStr for DisputeEscalation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut days = s
+            .split(',')
+            .map(|age| {
+                parse_days(&age.trim().to_lowercase())
+                    .filter(|days| *days > 0)
+                    .ok_or_else(|| format!("invalid dispute age '{}'", age))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        days.sort_unstable();
+        days.dedup();
+        Ok(DisputeEscalation { days })
+    }
+}
+
+/// Parses a number of days written as e.g. `30d`.
+fn parse_days(s: &str) -> Option<u32> {
+    s.strip_suffix('d')?.parse().ok()
+}
+
+fn days_duration(days: u32) -> chrono::Duration {
+    chrono::Duration::days(i64::from(days))
+}
+
+/// What processing an input does with a record that cannot be parsed.
+/// Records the engine rejects are a matter of the policies above, and never
+/// stop processing. The one exception is `Engine::apply_batch`, which under
+/// `Strict` applies a batch only if it can apply every record of it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ErrorMode {
+    /// The record is skipped, and processing carries on. Every skipped
+    /// record ends up in `Processed::errors`, see `Processed::summary`.
+    #[default]
+    Lenient,
+    /// Processing stops at the first invalid record, which is returned as
+    /// the error.
+    Strict,
+}
+
+impl FromStr for ErrorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lenient" => Ok(ErrorMode::Lenient),
+            "strict" => Ok(ErrorMode::Strict),
+            _ => Err(format!("unknown error mode '{}'", s)),
+        }
+    }
+}
+
+/// Which clients the `Engine` keeps a statement for. A statement lists every
+/// transaction applied to an account, so unlike the rest of the state it
+/// grows with the input. That is why no statements are kept by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StatementPolicy {
+    #[default]
+    Off,
+    /// A statement is kept for every client.
+    All,
+    /// A statement is kept only for the listed clients.
+    Clients(Vec<ClientId>),
+}
+
+impl StatementPolicy {
+    fn includes(&self, client: ClientId) -> bool {
+        match self {
+            StatementPolicy::Off => false,
+            StatementPolicy::All => true,
+            StatementPolicy::Clients(clients) => clients.contains(&client),
+        }
+    }
+}
+
+/// The days the `Engine` adds up the money moved in and out of every
+/// account for, see `Engine::totals`. Both ends are included, and a missing
+/// one leaves the period open on that side. Days are taken in UTC.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TotalsPeriod {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl TotalsPeriod {
+    /// Whether a record at `timestamp` falls into the period. A record
+    /// without a timestamp could have happened at any time, so it only
+    /// counts towards a period that is open on both sides.
+    fn contains(&self, timestamp: Option<DateTime<Utc>>) -> bool {
+        match timestamp.map(|t| t.date_naive()) {
+            Some(day) => {
+                self.from.is_none_or(|from| day >= from) && self.to.is_none_or(|to| day <= to)
+            }
+            None => self.from.is_none() && self.to.is_none(),
+        }
+    }
+}
+
+/// A single line of a client's statement: a transaction that was applied to
+/// the account, and the balances right after it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct StatementLine {
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    /// Only written out if any line has a currency, just like the currency
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any line has a tenant, just like the tenant of
+    /// an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    /// The amount of the transaction. For a dispute, resolve or chargeback
+    /// this is the amount of the transaction it refers to.
+    pub amount: Amount,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// Why the balance was adjusted, for an adjustment. Only written out if
+    /// any line has a reason.
+    #[serde(skip)]
+    pub reason: Option<ReasonCode>,
+}
+
+/// A transaction that has been disputed, as listed by `Engine::disputes`.
+/// Only an open dispute, one in the `Disputed` state, still holds funds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dispute {
+    pub client: ClientId,
+    pub tx: TxId,
+    /// Whether a deposit or a withdrawal was disputed.
+    pub r#type: TransactionType,
+    pub currency: Option<Currency>,
+    pub tenant: Option<Tenant>,
+    /// The amount under dispute, which is less than that of the transaction
+    /// if the dispute named a smaller amount.
+    pub amount: Amount,
+    pub status: TransactionState,
+    /// When the dispute was opened, if the dispute had a timestamp.
+    pub disputed_at: Option<DateTime<Utc>>,
+}
+
+impl Dispute {
+    /// How long the dispute has been open at `now`. `None` if it is closed
+    /// or it is not known when it was opened.
+    pub fn age(&self, now: DateTime<Utc>) -> Option<chrono::Duration> {
+        match self.status {
+            TransactionState::Disputed => self.disputed_at.map(|t| now - t),
+            _ => None,
+        }
+    }
+}
+
+/// An open dispute that reached one of the ages of a `DisputeEscalation`,
+/// as listed by `Engine::escalations`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct Escalation {
+    pub client: ClientId,
+    pub tx: TxId,
+    /// Only written out if any escalation has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any escalation has a tenant, just like the
+    /// tenant of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    /// The amount under dispute.
+    pub amount: Amount,
+    pub disputed_at: DateTime<Utc>,
+    /// The age the dispute reached, in days, and when it reached it.
+    pub days: u32,
+    pub escalated_at: DateTime<Utc>,
+}
+
+/// A record that took the funds of an account below zero, as listed by
+/// `Engine::negative_balances`, with the funds it left the account with, or
+/// would have, had it not been rejected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct NegativeBalance {
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<Tenant>,
+    pub r#type: TransactionType,
+    pub available: Amount,
+    pub held: Amount,
+    /// Whether the record was applied anyway, which it only is under
+    /// `NegativeBalancePolicy::Log`.
+    pub applied: bool,
+}
+
+/// A dispute that referenced a transaction the `Engine` had not seen, as
+/// listed by `Engine::pending_disputes`, which waits for the transaction to
+/// come along, see `EngineConfig::pending_disputes`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingDispute {
+    pub client: ClientId,
+    pub tx: TxId,
+    /// Only written out if any pending dispute has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any pending dispute has a tenant, just like the
+    /// tenant of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// A `balance_check` record the `Engine` applied, as listed by
+/// `Engine::balance_checks`: the total its account should have held at that
+/// point, and the total it did hold.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BalanceCheck {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub currency: Option<Currency>,
+    pub tenant: Option<Tenant>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub expected: Amount,
+    pub actual: Amount,
+}
+
+impl BalanceCheck {
+    /// Whether the account held what it should have.
+    pub fn matches(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    /// How much more the account held than it should have, which is
+    /// negative if it held less.
+    pub fn difference(&self) -> Amount {
+        Amount::from_scaled(self.actual.scaled().saturating_sub(self.expected.scaled()))
+    }
+}
+
+/// How much money went in and out of the account of a client in a single
+/// currency during the `TotalsPeriod`, as listed by `Engine::totals`. Fees
+/// are left out. The sums stop at the largest `Amount` rather than overflow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClientTotals {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    pub tenant: Option<Tenant>,
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    /// The amounts of the transactions charged back, whether by a
+    /// `chargeback` record or by an expired dispute.
+    pub charged_back: Amount,
+}
+
+impl ClientTotals {
+    fn new((client, currency, tenant): AccountKey) -> Self {
+        ClientTotals {
+            client,
+            currency,
+            tenant,
+            deposited: Amount::ZERO,
+            withdrawn: Amount::ZERO,
+            charged_back: Amount::ZERO,
+        }
+    }
+}
+
+/// The balances of the account of a client in a single currency at the end
+/// of a day, as listed by `Engine::history`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct DailyBalance {
+    pub client: ClientId,
+    /// Only written out if any account has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any account has a tenant, just like the tenant
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub date: NaiveDate,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// An account locked by a chargeback, as listed by `Engine::locks`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct LockedAccount {
+    pub client: ClientId,
+    /// Only written out if any account has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any account has a tenant, just like the tenant
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    /// The transaction whose chargeback locked the account. Unknown for an
+    /// account locked before this was kept track of.
+    pub chargeback: Option<TxId>,
+    /// When the account was locked, if the chargeback had a timestamp.
+    pub locked_at: Option<DateTime<Utc>>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+/// When an account looks unusual enough for a closer look by the risk team,
+/// see `Engine::anomalies`. A heuristic that is not set never flags
+/// anything.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct AnomalyThresholds {
+    /// Flag accounts that withdrew more than this many times what they
+    /// deposited, e.g. 3 or 1.5.
+    pub withdrawal_ratio: Option<Amount>,
+    /// Flag accounts with more than this many transactions disputed.
+    pub max_disputes: Option<u64>,
+}
+
+impl AnomalyThresholds {
+    /// The heuristics `anomaly` trips.
+    fn flags(&self, anomaly: &Anomaly) -> Vec<AnomalyFlag> {
+        let mut flags = Vec::new();
+        if let Some(ratio) = self.withdrawal_ratio {
+            // Both sides are scaled once too often, so they compare alike,
+            // and i128 leaves plenty of room for that.
+            let scale = 10_i128.pow(crate::amount::DECIMAL_PLACES);
+            let withdrawn = i128::from(anomaly.withdrawn.scaled()) * scale;
+            let limit = i128::from(anomaly.deposited.scaled()) * i128::from(ratio.scaled());
+            if withdrawn > limit {
+                flags.push(AnomalyFlag::Withdrawals);
+            }
+        }
+        if self.max_disputes.is_some_and(|max| anomaly.disputes > max) {
+            flags.push(AnomalyFlag::Disputes);
+        }
+        flags
+    }
+}
+
+/// A heuristic of `AnomalyThresholds` an account tripped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyFlag {
+    /// It withdrew too much compared to what it deposited.
+    Withdrawals,
+    /// Too many of its transactions were disputed.
+    Disputes,
+}
+
+impl fmt::Display for AnomalyFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AnomalyFlag::Withdrawals => "withdrawals",
+            AnomalyFlag::Disputes => "disputes",
+        })
+    }
+}
+
+/// An account flagged by `Engine::anomalies`, along with the figures that
+/// got it flagged. The sums stop at the largest `Amount` rather than
+/// overflow.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Anomaly {
+    pub client: ClientId,
+    /// Only written out if any account has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any account has a tenant, just like the tenant
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    /// The number of transactions that were ever disputed, whatever came
+    /// of the dispute.
+    pub disputes: u64,
+    /// Written out as a single column, separated by spaces, e.g.
+    /// `withdrawals disputes`.
+    #[serde(serialize_with = "serialize_flags")]
+    pub flags: Vec<AnomalyFlag>,
+}
+
+fn serialize_flags<S: serde::Serializer>(
+    flags: &[AnomalyFlag],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let names: Vec<String> = flags.iter().map(AnomalyFlag::to_string).collect();
+    serializer.serialize_str(&names.join(" "))
+}
+
+/// How many deposits and withdrawals in a single currency an engine applied,
+/// and how much money they moved, as listed by `Engine::volumes`. The sums
+/// stop at the largest `Amount` rather than overflow.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Volume {
+    pub currency: Option<Currency>,
+    pub deposits: u64,
+    pub deposited: Amount,
+    pub withdrawals: u64,
+    pub withdrawn: Amount,
+    /// The fees charged, whether by the `FeeSchedule` or by `fee` records.
+    pub fees: Amount,
+}
+
+impl Volume {
+    fn add(&mut self, other: &Volume) {
+        self.deposits += other.deposits;
+        self.deposited = saturating_add(self.deposited, other.deposited);
+        self.withdrawals += other.withdrawals;
+        self.withdrawn = saturating_add(self.withdrawn, other.withdrawn);
+        self.fees = saturating_add(self.fees, other.fees);
+    }
+}
+
+/// A new, empty account at `key`.
+fn new_account((client, currency, tenant): AccountKey) -> Account {
+    Account::with_tenant(client, currency, tenant)
+}
+
+fn saturating_add(a: Amount, b: Amount) -> Amount {
+    Amount::from_scaled(a.scaled().saturating_add(b.scaled()))
+}
+
+/// Remarks about a record that was applied, but deserves a second look.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The record reused the ID of an earlier transaction and was applied
+    /// anyway because the `DuplicatePolicy` allows it.
+    DuplicateTransaction,
+    /// A balance check found the account holding another total than the one
+    /// it states.
+    BalanceMismatch { expected: Amount, actual: Amount },
+    /// The record took the funds of the account below zero, see
+    /// `NegativeBalancePolicy`.
+    NegativeBalance { available: Amount, held: Amount },
+    /// The record is a dispute of a transaction not seen yet, which waits
+    /// for it, see `EngineConfig::pending_disputes`.
+    PendingDispute,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::DuplicateTransaction => f.write_str("duplicate transaction ID"),
+            Warning::BalanceMismatch { expected, actual } => {
+                write!(f, "total is {}, expected {}", actual, expected)
+            }
+            Warning::NegativeBalance { available, held } => {
+                write!(f, "available is {}, held is {}", available, held)
+            }
+            Warning::PendingDispute => f.write_str("disputed transaction not seen yet"),
+        }
+    }
+}
+
+/// The policies the `Engine` applies while processing records. The defaults
+/// are the safest choice for each of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub accounts: AccountPolicy,
+    pub locked_accounts: LockedAccountPolicy,
+    pub duplicates: DuplicatePolicy,
+    pub disputes: DisputePolicy,
+    pub withdrawal_disputes: WithdrawalDisputes,
+    pub dispute_window: DisputeWindow,
+    /// Whether a dispute of a transaction not seen yet waits for it rather
+    /// than being rejected, for feeds that may deliver them out of order.
+    /// Once the transaction is applied, the dispute is applied right after
+    /// it, as of the timestamp of the transaction if it has one. Not by
+    /// default, see `Engine::pending_disputes`.
+    pub pending_disputes: bool,
+    /// When open disputes are closed automatically. Never by default.
+    pub dispute_expiry: Option<DisputeExpiry>,
+    /// When open disputes are escalated, see `Engine::escalate_disputes`.
+    /// Never by default.
+    pub dispute_escalation: DisputeEscalation,
+    pub overdraft: OverdraftPolicy,
+    pub negative_balances: NegativeBalancePolicy,
+    pub statements: StatementPolicy,
+    /// The period to keep the totals of every account for. As statements,
+    /// they are not kept by default.
+    pub totals: Option<TotalsPeriod>,
+    /// Whether to keep the balances of every account at the end of every
+    /// day, see `Engine::history`. Not by default either.
+    pub history: bool,
+    /// Whether to book every record that moves money in a double-entry
+    /// journal, see `Engine::book`. Not by default either.
+    pub double_entry: bool,
+    /// Whether to keep the accounts and transactions of every tenant apart,
+    /// by the tenant of the records, so the same client or transaction ID
+    /// of two tenants are two different ones. Not by default, when the
+    /// tenant of a record is of no account.
+    pub tenants: bool,
+    /// The fees charged on top of the transactions. None by default.
+    pub fees: FeeSchedule,
+    /// The limits on deposits and withdrawals. None by default either.
+    pub limits: Limits,
+    /// The rules every record has to pass before anything else. None by
+    /// default, see `Validator`.
+    pub rules: Rules,
+    /// Only apply records up to and including this point in time, so the
+    /// balances are those as of the cutoff. Records without a timestamp are
+    /// always applied.
+    pub as_of: Option<DateTime<Utc>>,
+    pub error_mode: ErrorMode,
+}
+
+/// What became of a record the `Engine` applied: the balances of the
+/// account it was applied to, right after it, and the warning it raised, if
+/// any. For a dispute, resolve or chargeback, that is the account of the
+/// transaction it refers to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Applied {
+    pub balance: OutputRecord,
+    pub warning: Option<Warning>,
+}
+
+/// What `Engine::execute` did besides applying a record: the warning it
+/// raised and the fees it charged for it.
+struct Executed {
+    warning: Option<Warning>,
+    fees: Amount,
+}
+
+/// A record the `Engine` refused to apply, along with the reason why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejected {
+    pub record: InputRecord,
+    pub reason: RejectReason,
+}
+
+/// What a batch of records would do to the accounts, as found by
+/// `Engine::simulate`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Simulation {
+    /// The balances every account whose balances would change would end up
+    /// with, sorted by client and currency.
+    pub balances: Vec<OutputRecord>,
+    /// The records that would be refused, and why.
+    pub rejected: Vec<Rejected>,
+}
+
+/// What `Engine::apply_batch` made of a batch of records: the outcome of
+/// every record, in the order of the batch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchResult {
+    pub results: Vec<Result<Applied, RejectReason>>,
+}
+
+impl BatchResult {
+    /// The number of records that were applied.
+    pub fn applied(&self) -> usize {
+        self.results.iter().filter(|r| r.is_ok()).count()
+    }
+
+    /// The number of records that were refused.
+    pub fn rejected(&self) -> usize {
+        self.results.len() - self.applied()
+    }
+}
+
+/// Why `Engine::apply_batch` left a strict batch out altogether: the first
+/// record of it that was refused, at `index` in the batch, and the reason.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchError {
+    pub index: usize,
+    pub reason: RejectReason,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "record {} of the batch was rejected: {}",
+            self.index, self.reason
+        )
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+/// A record the `Engine` applied, along with the warning it raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warned {
+    pub record: InputRecord,
+    pub warning: Warning,
+}
+
+/// The version of the snapshot format written by `Engine::snapshot`. It has
+/// to be bumped whenever the format changes in an incompatible way.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The state of an `Engine` as it is written to a snapshot and read back.
+/// Accounts and transactions are sorted so that snapshots of the same state
+/// are identical and can be compared with standard tools.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    accounts: Vec<Account>,
+    transactions: BTreeMap<TxId, StoredTransaction>,
+    /// The transactions of every tenant, whose IDs may well be the same as
+    /// those of another tenant.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tenants: BTreeMap<Tenant, BTreeMap<TxId, StoredTransaction>>,
+}
+
+/// Accounts are kept per client, currency and tenant.
+type AccountKey = (ClientId, Option<Currency>, Option<Tenant>);
+
+/// Statements are kept per client and tenant, covering every currency.
+type ClientKey = (ClientId, Option<Tenant>);
+
+/// Transactions are kept per ID and tenant, as tenants pick their IDs
+/// independently of each other.
+type TxKey = (TxId, Option<Tenant>);
+
+/// The accounts and transactions that changed since the state of the engine
+/// was last written to a store, so only those have to be written next time.
+#[derive(Debug, Clone, Default)]
+struct Changes {
+    accounts: HashSet<AccountKey>,
+    transactions: HashSet<TxKey>,
+}
+
+/// What `Engine::apply_batch` needs to undo a strict batch that fails
+/// halfway. The accounts and transactions the batch changes are kept aside
+/// until it is done, so the ledger never sees them unless it succeeds, and
+/// the bookkeeping is remembered as it was before the batch, or before the
+/// batch first changed it.
+#[derive(Debug, Default)]
+struct Undo {
+    ledger: MemoryLedger,
+    statements: HashMap<ClientKey, usize>,
+    volumes: HashMap<Option<Currency>, Volume>,
+    balance_checks: usize,
+    negative_balances: usize,
+    pending_disputes: HashMap<TxKey, InputRecord>,
+    escalations: usize,
+    escalated_to: Option<DateTime<Utc>>,
+    totals: HashMap<AccountKey, Option<ClientTotals>>,
+    daily: HashMap<AccountKey, Option<(NaiveDate, Amount)>>,
+    history: HashMap<(AccountKey, NaiveDate), Option<OutputRecord>>,
+    book: usize,
+    journal: Vec<InputRecord>,
+    events: usize,
+    /// What to tell the observers once the batch went through.
+    notifications: Vec<Notification>,
+    expiring: BTreeSet<(DateTime<Utc>, TxKey)>,
+    unexpirable: HashMap<AccountKey, BTreeSet<(DateTime<Utc>, TxKey)>>,
+    changes: Option<Changes>,
+}
+
+/// The `Engine` consumes `InputRecord`s one at a time and keeps the state of
+/// every client incrementally. Unlike the original implementation, the input
+/// never has to be held in memory in its entirety: the only things remembered
+/// are the client balances and the amounts of transactions that could be
+/// disputed in the future.
+#[derive(Debug, Default)]
+pub struct Engine {
+    config: EngineConfig,
+    ledger: Box<dyn Ledger>,
+    statements: HashMap<ClientKey, Vec<StatementLine>>,
+    volumes: HashMap<Option<Currency>, Volume>,
+    balance_checks: Vec<BalanceCheck>,
+    negative_balances: Vec<NegativeBalance>,
+    /// The disputes waiting for the transaction they refer to, by its ID.
+    pending_disputes: HashMap<TxKey, InputRecord>,
+    escalations: Vec<Escalation>,
+    /// How far `escalate_disputes` has got, so every dispute is escalated
+    /// only once for every age it reaches.
+    escalated_to: Option<DateTime<Utc>>,
+    totals: HashMap<AccountKey, ClientTotals>,
+    /// The day every account last moved money on, and how much it moved on
+    /// it, for `Limits::daily`.
+    daily: HashMap<AccountKey, (NaiveDate, Amount)>,
+    /// The balances of every account after the last record of every day it
+    /// had any.
+    history: HashMap<AccountKey, BTreeMap<NaiveDate, OutputRecord>>,
+    /// The double-entry journal, in the order the records were applied.
+    book: Vec<Entry>,
+    journal: Option<Journal>,
+    events: Option<EventLog>,
+    /// Shared with `checkpoint`, which hands them on to a restored engine.
+    pub(crate) handlers: Handlers,
+    pub(crate) validators: Validators,
+    pub(crate) observers: Observers,
+    /// The open disputes that have a timestamp, by when they were opened,
+    /// so `expire_disputes` finds the expired ones right away.
+    expiring: BTreeSet<(DateTime<Utc>, TxKey)>,
+    /// The disputes taken off `expiring` as they could not be closed when
+    /// they expired, by their account. They are only tried again once the
+    /// account changes, as nothing else would make a difference.
+    unexpirable: HashMap<AccountKey, BTreeSet<(DateTime<Utc>, TxKey)>>,
+    /// Only kept track of while there is a store to write them to.
+    changes: Option<Changes>,
+    /// What it takes to undo the strict batch that runs, if any.
+    batch: Option<Undo>,
+    #[cfg(feature = "sqlite")]
+    store: Option<sqlite::SqliteStore>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::default()
+    }
+
+    /// Builds an `Engine` that applies the policies in `config`.
+    pub fn with_config(config: EngineConfig) -> Self {
+        Engine {
+            config,
+            ..Engine::default()
+        }
+    }
+
+    /// Builds an `Engine` that keeps its accounts and transactions in
+    /// `ledger` rather than in memory. The ledger may already hold the state
+    /// of an earlier run, which the engine then picks up where it left off.
+    pub fn with_ledger<L: Ledger + 'static>(config: EngineConfig, ledger: L) -> Self {
+        let expiring = ledger
+            .transactions()
+            .filter(|(_, t)| t.state == TransactionState::Disputed)
+            .filter_map(|(tx, t)| t.disputed_at.map(|at| (at, (tx, t.tenant))))
+            .collect();
+        Engine {
+            config,
+            ledger: Box::new(ledger),
+            expiring,
+            ..Engine::default()
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// The ledger the engine keeps its accounts and transactions in.
+    pub fn ledger(&self) -> &dyn Ledger {
+        &*self.ledger
+    }
+
+    /// Keeps track of every applied record in `journal`, and rejects the
+    /// records it shows were already applied in an earlier run. Like the
+    /// policies, the journal is not part of a snapshot.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// The journal set with `set_journal`, including the records applied
+    /// since, ready to be saved for the next run.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Records everything that happens to the engine from now on in `log`,
+    /// see `Event`. Like the journal, the log is not part of a snapshot.
+    pub fn set_event_log(&mut self, log: EventLog) {
+        self.events = Some(log);
+    }
+
+    /// The event log set with `set_event_log`.
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.events.as_ref()
+    }
+
+    /// The event log set with `set_event_log`, so the events recorded so far
+    /// can be written out.
+    pub fn event_log_mut(&mut self) -> Option<&mut EventLog> {
+        self.events.as_mut()
+    }
+
+    /// Applies the records of the custom type `transaction_type` with
+    /// `handler`, replacing any handler it had before. Without a handler,
+    /// records of a custom type are rejected. Like the policies, handlers are
+    /// not part of a snapshot, so they have to be set again on a restored
+    /// engine.
+    pub fn set_handler<H>(&mut self, transaction_type: CustomType, handler: H)
+    where
+        H: TransactionHandler + 'static,
+    {
+        self.handlers.insert(transaction_type, Arc::new(handler));
+    }
+
+    /// Holds every record to `validator` as well, after the `Rules` of the
+    /// configuration and the validators added before. Like handlers,
+    /// validators are not part of a snapshot.
+    pub fn add_validator<V>(&mut self, validator: V)
+    where
+        V: Validator + 'static,
+    {
+        self.validators.push(Arc::new(validator));
+    }
+
+    /// Tells `observer` of every record applied or rejected from now on,
+    /// and of every dispute opened and account locked, see
+    /// `EngineObserver`. Like validators, observers are not part of a
+    /// snapshot.
+    pub fn add_observer<O>(&mut self, observer: O)
+    where
+        O: EngineObserver + 'static,
+    {
+        self.observers.push(Arc::new(observer));
+    }
+
+    /// Rebuilds an engine from the state kept in `store`, which is empty for
+    /// a new database, and keeps track of every account and transaction it
+    /// changes from then on, for `save_to_store` to write back.
+    #[cfg(feature = "sqlite")]
+    pub fn restore_from_store(
+        store: sqlite::SqliteStore,
+        config: EngineConfig,
+    ) -> Result<Engine, Error> {
+        let mut engine = store.load(config)?;
+        engine.changes = Some(Changes::default());
+        engine.store = Some(store);
+        Ok(engine)
+    }
+
+    /// Writes every account and transaction that changed since the last call
+    /// to the store, all in one database transaction, so the store always
+    /// holds a consistent state. Does nothing if there is no store.
+    #[cfg(feature = "sqlite")]
+    pub fn save_to_store(&mut self) -> Result<(), Error> {
+        let (Some(store), Some(changes)) = (&mut self.store, &self.changes) else {
+            return Ok(());
+        };
+        store.save(&*self.ledger, changes)?;
+        self.changes = Some(Changes::default());
+        Ok(())
+    }
+
+    /// Applies a single record to the state of the engine. Records are
+    /// expected to be applied in chronological order, so a dispute can only
+    /// ever reference a transaction that has already been seen. If the record
+    /// cannot be applied, the reason is returned and nothing is changed.
+    /// Otherwise the new balances of the account are returned, along with a
+    /// `Warning` if the record looks suspicious.
+    ///
+    /// The timestamp of a record also tells how much time has passed, so
+    /// before the record itself, the disputes that expired by then are
+    /// closed, whether or not the record applies. See `expire_disputes`.
+    /// The tenant of a record is of no account unless `EngineConfig::tenants`
+    /// keeps tenants apart.
+    ///
+    /// This is all it takes to embed the engine in another program: records
+    /// can come from anywhere, not just from the readers in `input`.
+    pub fn apply(&mut self, mut record: InputRecord) -> Result<Applied, RejectReason> {
+        if !self.config.tenants {
+            record.tenant = None;
+        }
+        if let Some(timestamp) = record.timestamp {
+            let now = self
+                .config
+                .as_of
+                .map_or(timestamp, |as_of| as_of.min(timestamp));
+            self.escalate_disputes(now);
+            self.expire_disputes(now);
+        }
+        let (tx, timestamp) = ((record.tx, record.tenant), record.timestamp);
+        let mut applied = self.apply_logged(record)?;
+        if let Some(mut dispute) = self.take_pending_dispute(tx) {
+            dispute.timestamp = timestamp.or(dispute.timestamp);
+            // Should the dispute be rejected now, it is just as if it had
+            // come along in order.
+            if let Ok(disputed) = self.apply(dispute) {
+                applied.balance = disputed.balance;
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Applies a record just like `apply`, apart from the disputes that
+    /// expire and those that wait for it, recording it in the event log if
+    /// there is one.
+    fn apply_logged(&mut self, record: InputRecord) -> Result<Applied, RejectReason> {
+        if self.events.is_none() && self.observers.is_empty() {
+            return self.apply_record(&record).map(|(applied, _)| applied);
+        }
+        let key = self.account_key(&record);
+        let locked = self.get_account(key).is_some_and(|a| a.is_locked());
+        let result = self.apply_record(&record);
+        if !self.observers.is_empty() {
+            self.observe(&record, &result, locked);
+        }
+        if let Some(mut log) = self.events.take() {
+            match &result {
+                Ok((_, fees)) => self.log_applied(&mut log, record, *fees, locked),
+                Err(reason) => log.push(Event::TransactionRejected {
+                    record,
+                    reason: reason.to_string(),
+                }),
+            }
+            self.events = Some(log);
+        }
+        result.map(|(applied, _)| applied)
+    }
+
+    /// Tells the observers what became of `record`, and whatever it led to,
+    /// just like `log_applied` records it. `was_locked` is whether its
+    /// account was locked before.
+    fn observe(
+        &mut self,
+        record: &InputRecord,
+        result: &Result<(Applied, Amount), RejectReason>,
+        was_locked: bool,
+    ) {
+        let applied = match result {
+            Ok((applied, _)) => applied,
+            Err(reason) => {
+                return self.notify(Notification::Rejected(record.clone(), *reason));
+            }
+        };
+        self.notify(Notification::Applied(record.clone(), *applied));
+        let key = self.account_key(record);
+        // A dispute waiting for its transaction opens nothing yet.
+        if record.r#type == TransactionType::Dispute {
+            if let Some(transaction) = self.lookup_tx((record.tx, record.tenant)) {
+                self.notify(Notification::DisputeOpened {
+                    client: record.client,
+                    tx: record.tx,
+                    currency: key.1,
+                    tenant: key.2,
+                    amount: transaction.in_dispute(),
+                });
+            }
+        }
+        if !was_locked && self.get_account(key).is_some_and(|a| a.is_locked()) {
+            self.notify(Notification::AccountLocked {
+                client: record.client,
+                currency: key.1,
+                tenant: key.2,
+            });
+        }
+    }
+
+    /// Tells the observers of `notification`, or holds on to it until the
+    /// strict batch that runs is done.
+    fn notify(&mut self, notification: Notification) {
+        match &mut self.batch {
+            Some(batch) => batch.notifications.push(notification),
+            None => self.observers.notify(&notification),
+        }
+    }
+
+    /// Applies a batch of records, such as those of a file or of a message,
+    /// and returns what became of every one of them.
+    ///
+    /// Under `ErrorMode::Lenient`, the default, this is the same as
+    /// applying the records one by one: those that are refused are simply
+    /// left out. Under `ErrorMode::Strict`, the batch is applied all or
+    /// nothing. Once a record is refused, everything the batch did up to it
+    /// is undone, in the journal, the event log and the rest of the
+    /// bookkeeping as much as in the accounts, and the record is returned
+    /// as the error. Until the batch is done, the accounts and transactions
+    /// it changes are kept aside in memory, and only then written to the
+    /// ledger, so a ledger in a database never sees a batch that failed.
+    pub fn apply_batch(&mut self, records: &[InputRecord]) -> Result<BatchResult, BatchError> {
+        if self.config.error_mode == ErrorMode::Lenient {
+            let results = records.iter().map(|r| self.apply(r.clone())).collect();
+            return Ok(BatchResult { results });
+        }
+        self.batch = Some(Undo {
+            volumes: self.volumes.clone(),
+            balance_checks: self.balance_checks.len(),
+            negative_balances: self.negative_balances.len(),
+            pending_disputes: self.pending_disputes.clone(),
+            book: self.book.len(),
+            escalations: self.escalations.len(),
+            escalated_to: self.escalated_to,
+            events: self.events.as_ref().map_or(0, |log| log.events().len()),
+            expiring: self.expiring.clone(),
+            unexpirable: self.unexpirable.clone(),
+            changes: self.changes.clone(),
+            ..Undo::default()
+        });
+        let mut results = Vec::with_capacity(records.len());
+        for (index, record) in records.iter().enumerate() {
+            match self.apply(record.clone()) {
+                Ok(applied) => results.push(Ok(applied)),
+                Err(reason) => {
+                    self.roll_back();
+                    if !self.observers.is_empty() {
+                        self.notify(Notification::Rejected(record.clone(), reason));
+                    }
+                    return Err(BatchError { index, reason });
+                }
+            }
+        }
+        if let Some(batch) = self.batch.take() {
+            batch.ledger.write_to(&mut *self.ledger);
+            for notification in &batch.notifications {
+                self.observers.notify(notification);
+            }
+        }
+        Ok(BatchResult { results })
+    }
+
+    /// Undoes everything the strict batch that runs did so far.
+    fn roll_back(&mut self) {
+        let Some(batch) = self.batch.take() else {
+            return;
+        };
+        for (key, len) in batch.statements {
+            match len {
+                0 => {
+                    self.statements.remove(&key);
+                }
+                len => {
+                    if let Some(statement) = self.statements.get_mut(&key) {
+                        statement.truncate(len);
+                    }
+                }
+            }
+        }
+        self.volumes = batch.volumes;
+        self.balance_checks.truncate(batch.balance_checks);
+        self.negative_balances.truncate(batch.negative_balances);
+        self.pending_disputes = batch.pending_disputes;
+        self.book.truncate(batch.book);
+        self.escalations.truncate(batch.escalations);
+        self.escalated_to = batch.escalated_to;
+        for (key, totals) in batch.totals {
+            match totals {
+                Some(totals) => self.totals.insert(key, totals),
+                None => self.totals.remove(&key),
+            };
+        }
+        for (key, daily) in batch.daily {
+            match daily {
+                Some(daily) => self.daily.insert(key, daily),
+                None => self.daily.remove(&key),
+            };
+        }
+        for ((key, date), balances) in batch.history {
+            let Some(days) = self.history.get_mut(&key) else {
+                continue;
+            };
+            match balances {
+                Some(balances) => {
+                    days.insert(date, balances);
+                }
+                None => {
+                    days.remove(&date);
+                    if days.is_empty() {
+                        self.history.remove(&key);
+                    }
+                }
+            }
+        }
+        if let Some(journal) = &mut self.journal {
+            for record in &batch.journal {
+                journal.remove(record);
+            }
+        }
+        if let Some(log) = &mut self.events {
+            log.truncate(batch.events);
+        }
+        self.expiring = batch.expiring;
+        self.unexpirable = batch.unexpirable;
+        self.changes = batch.changes;
+    }
+
+    /// Works out what applying `records` would do, without changing the
+    /// engine: they are applied to a copy of the accounts and of the
+    /// transactions that may be disputed, such as to see what a batch of
+    /// disputes and chargebacks would cost before they are received. Every
+    /// policy, fee and handler applies just like it would for real, but
+    /// nothing is written to the journal, the event log or the store.
+    ///
+    /// The copy takes as much memory as the state of the engine itself, so
+    /// records are best simulated in batches rather than one by one.
+    pub fn simulate<I>(&self, records: I) -> Simulation
+    where
+        I: IntoIterator<Item = InputRecord>,
+    {
+        let mut scratch = Engine {
+            config: EngineConfig {
+                statements: StatementPolicy::Off,
+                totals: None,
+                history: false,
+                double_entry: false,
+                ..self.config.clone()
+            },
+            ledger: Box::new(MemoryLedger::copy_of(&*self.ledger)),
+            handlers: self.handlers.clone(),
+            validators: self.validators.clone(),
+            expiring: self.expiring.clone(),
+            unexpirable: self.unexpirable.clone(),
+            daily: self.daily.clone(),
+            pending_disputes: self.pending_disputes.clone(),
+            ..Engine::default()
+        };
+        let mut rejected = Vec::new();
+        for record in records {
+            if let Err(reason) = scratch.apply(record.clone()) {
+                rejected.push(Rejected { record, reason });
+            }
+        }
+
+        // Expired disputes may change accounts the records never mention,
+        // so the accounts are compared rather than remembered.
+        let mut balances: Vec<OutputRecord> = scratch
+            .ledger
+            .accounts()
+            .filter(|a| {
+                self.get_account((a.client(), a.currency(), a.tenant()))
+                    .as_ref()
+                    != Some(a)
+            })
+            .map(|account| OutputRecord::from(&account))
+            .collect();
+        balances.sort_by_key(|b| (b.tenant, b.client, b.currency));
+        Simulation { balances, rejected }
+    }
+
+    /// Does the bookkeeping of `apply`, returning the fees charged for the
+    /// record along with the outcome.
+    fn apply_record(&mut self, record: &InputRecord) -> Result<(Applied, Amount), RejectReason> {
+        if self.journal.as_ref().is_some_and(|j| j.is_replay(record)) {
+            return Err(RejectReason::Replayed);
+        }
+        self.config.rules.validate(record)?;
+        if !self.validators.is_empty() {
+            self.validators.validate(record)?;
+        }
+        if self.config.pending_disputes
+            && record.r#type == TransactionType::Dispute
+            && self.lookup_tx((record.tx, record.tenant)).is_none()
+            && !self
+                .pending_disputes
+                .contains_key(&(record.tx, record.tenant))
+        {
+            // Only applied once the transaction is, so it is not in the
+            // journal until then either.
+            self.pending_disputes
+                .insert((record.tx, record.tenant), record.clone());
+            let key = self.account_key(record);
+            let account = self.get_account(key).unwrap_or_else(|| new_account(key));
+            let balance = OutputRecord::from(&account);
+            let warning = Some(Warning::PendingDispute);
+            return Ok((Applied { balance, warning }, Amount::ZERO));
+        }
+        let negative_balances = self.negative_balances.len();
+        let before = match self.config.double_entry {
+            true => self.get_account(self.account_key(record)),
+            false => None,
+        };
+        let Executed { mut warning, fees } = self.execute(record)?;
+        if let Some(negative) = self.negative_balances.get(negative_balances) {
+            warning = warning.or(Some(Warning::NegativeBalance {
+                available: negative.available,
+                held: negative.held,
+            }));
+        }
+        // A balance check leaves the account as it is, if there even is one.
+        let changed = record.r#type != TransactionType::BalanceCheck;
+        if changed && self.config.statements.includes(record.client) {
+            self.add_to_statement(record, fees);
+        }
+        self.add_to_totals(record);
+        if changed {
+            self.add_to_history(record);
+        }
+        if self.config.double_entry {
+            self.add_to_book(record, before, fees);
+        }
+        if let Some(journal) = &mut self.journal {
+            if journal.insert(record) {
+                if let Some(batch) = &mut self.batch {
+                    batch.journal.push(record.clone());
+                }
+            }
+        }
+        let key = self.account_key(record);
+        let stored = self.lookup_tx((record.tx, record.tenant)).is_some();
+        if let Some(changes) = self.changes.as_mut().filter(|_| changed) {
+            changes.accounts.insert(key);
+            if stored {
+                changes.transactions.insert((record.tx, record.tenant));
+            }
+        }
+        let account = self.get_account(key).unwrap_or_else(|| new_account(key));
+        let balance = OutputRecord::from(&account);
+        Ok((Applied { balance, warning }, fees))
+    }
+
+    /// Records an applied record in `log`, followed by whatever it led to.
+    /// `was_locked` is whether its account was locked before.
+    fn log_applied(&self, log: &mut EventLog, record: InputRecord, fees: Amount, was_locked: bool) {
+        let key = self.account_key(&record);
+        let (client, tx, currency, tenant) = (record.client, record.tx, key.1, key.2);
+        let amount = self.lookup_tx((tx, tenant)).map(|t| t.in_dispute());
+        let event = match record.r#type {
+            TransactionType::Dispute => amount.map(|amount| Event::DisputeOpened {
+                client,
+                tx,
+                currency,
+                tenant,
+                amount,
+            }),
+            TransactionType::Resolve => amount.map(|amount| Event::DisputeResolved {
+                client,
+                tx,
+                currency,
+                tenant,
+                amount,
+            }),
+            TransactionType::Chargeback => amount.map(|amount| Event::DisputeChargedBack {
+                client,
+                tx,
+                currency,
+                tenant,
+                amount,
+            }),
+            TransactionType::Open => Some(Event::AccountOpened {
+                client,
+                currency,
+                tenant,
+            }),
+            TransactionType::Close => Some(Event::AccountClosed {
+                client,
+                currency,
+                tenant,
+            }),
+            TransactionType::Freeze => Some(Event::AccountFrozen {
+                client,
+                currency,
+                tenant,
+            }),
+            TransactionType::Unfreeze => Some(Event::AccountUnfrozen {
+                client,
+                currency,
+                tenant,
+            }),
+            TransactionType::Unlock => Some(Event::AccountUnlocked {
+                client,
+                currency,
+                tenant,
+            }),
+            TransactionType::BalanceCheck => self
+                .balance_checks
+                .last()
+                .filter(|check| !check.matches())
+                .map(|check| Event::BalanceMismatch {
+                    client,
+                    tx,
+                    currency,
+                    tenant,
+                    expected: check.expected,
+                    actual: check.actual,
+                }),
+            TransactionType::CreditAdjustment | TransactionType::DebitAdjustment => {
+                match (record.amount, record.reason) {
+                    (Some(amount), Some(reason)) => Some(Event::BalanceAdjusted {
+                        client,
+                        tx,
+                        currency,
+                        tenant,
+                        amount: match record.r#type {
+                            TransactionType::DebitAdjustment => -amount,
+                            _ => amount,
+                        },
+                        reason,
+                    }),
+                    _ => None,
+                }
+            }
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Fee
+            | TransactionType::Custom(_) => None,
+        };
+        let locked = !was_locked && self.get_account(key).is_some_and(|a| a.is_locked());
+        log.push(Event::TransactionApplied { record });
+        if fees > Amount::ZERO {
+            log.push(Event::FeeCharged {
+                client,
+                tx,
+                currency,
+                tenant,
+                amount: fees,
+            });
+        }
+        if let Some(event) = event {
+            log.push(event);
+        }
+        if locked {
+            log.push(Event::AccountLocked {
+                client,
+                currency,
+                tenant,
+            });
+        }
+    }
+
+    /// Dumps the current balance of each client as a vector.
+    pub fn balances(&self) -> Vec<OutputRecord> {
+        self.ledger
+            .accounts()
+            .map(|account| OutputRecord::from(&account))
+            .collect()
+    }
+
+    /// The current balances of a single client, one for every currency the
+    /// client deals in, sorted by currency. After a run shared by tenants,
+    /// the client of that ID of every tenant is listed, sorted by tenant
+    /// first. Empty for an unknown client.
+    pub fn client_balances(&self, client: ClientId) -> Vec<OutputRecord> {
+        let mut balances: Vec<OutputRecord> = self
+            .ledger
+            .accounts()
+            .filter(|a| a.client() == client)
+            .map(|account| OutputRecord::from(&account))
+            .collect();
+        balances.sort_by_key(|b| (b.tenant, b.currency));
+        balances
+    }
+
+    /// The transactions applied to the accounts of `client` of `tenant` so
+    /// far, in the order they were applied, with the balances after each of
+    /// them. This is empty unless the `StatementPolicy` asks for the
+    /// client's statement to be kept. Statements are not part of a
+    /// snapshot, so they only cover what this engine processed itself.
+    pub fn statement(&self, client: ClientId, tenant: Option<Tenant>) -> &[StatementLine] {
+        self.statements
+            .get(&(client, tenant))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The deposits and withdrawals applied so far, per currency, sorted by
+    /// currency. Like statements, these are not part of a snapshot, so they
+    /// only cover what this engine applied itself.
+    pub fn volumes(&self) -> Vec<Volume> {
+        let mut volumes: Vec<Volume> = self.volumes.values().copied().collect();
+        volumes.sort_by_key(|v| v.currency);
+        volumes
+    }
+
+    /// Every balance check applied so far, sorted by tenant and client, and
+    /// in the order they were applied for each client. Like statements,
+    /// these are not part of a snapshot, so they only cover what this engine
+    /// applied itself.
+    pub fn balance_checks(&self) -> Vec<BalanceCheck> {
+        let mut checks = self.balance_checks.clone();
+        checks.sort_by_key(|c| (c.tenant, c.client));
+        checks
+    }
+
+    /// Every escalation so far, sorted by tenant, client and transaction ID,
+    /// and by age for each dispute. Like balance checks, these are not part
+    /// of a snapshot, so they only cover what this engine applied itself.
+    pub fn escalations(&self) -> Vec<Escalation> {
+        let mut escalations = self.escalations.clone();
+        escalations.sort_by_key(|e| (e.tenant, e.client, e.tx, e.days));
+        escalations
+    }
+
+    /// The double-entry journal of every record applied so far that moved
+    /// money, if `EngineConfig::double_entry` asks for it, in the order they
+    /// were applied. After a parallel run, the entries of every shard come
+    /// one after the other. Like statements, the journal is not part of a
+    /// snapshot.
+    pub fn book(&self) -> &[Entry] {
+        &self.book
+    }
+
+    /// Every record so far that took the funds of an account below zero,
+    /// whether or not it was applied, see `NegativeBalancePolicy`. Sorted by
+    /// tenant and client, and in the order they came along for each client.
+    /// Like escalations, these are not part of a snapshot.
+    pub fn negative_balances(&self) -> Vec<NegativeBalance> {
+        let mut negative_balances = self.negative_balances.clone();
+        negative_balances.sort_by_key(|n| (n.tenant, n.client));
+        negative_balances
+    }
+
+    /// Every dispute still waiting for the transaction it refers to, see
+    /// `EngineConfig::pending_disputes`, sorted by tenant, client and
+    /// transaction ID. At the end of a run, these are the disputes that
+    /// never matched. Like escalations, these are not part of a snapshot.
+    pub fn pending_disputes(&self) -> Vec<PendingDispute> {
+        let mut pending: Vec<PendingDispute> = self
+            .pending_disputes
+            .values()
+            .map(|record| PendingDispute {
+                client: record.client,
+                tx: record.tx,
+                currency: record.currency,
+                tenant: record.tenant,
+                timestamp: record.timestamp,
+            })
+            .collect();
+        pending.sort_by_key(|p| (p.tenant, p.client, p.tx));
+        pending
+    }
+
+    /// Takes the dispute waiting for transaction `tx`, once it is known.
+    fn take_pending_dispute(&mut self, tx: TxKey) -> Option<InputRecord> {
+        if self.pending_disputes.is_empty() || self.lookup_tx(tx).is_none() {
+            return None;
+        }
+        self.pending_disputes.remove(&tx)
+    }
+
+    /// What every account took in and paid out during the `TotalsPeriod` of
+    /// the configuration, sorted by tenant, client and currency. Only
+    /// accounts that saw any of it are listed, and nothing at all without a
+    /// period. Like statements, these are not part of a snapshot, so they
+    /// only cover what this engine applied itself.
+    pub fn totals(&self) -> Vec<ClientTotals> {
+        let mut totals: Vec<ClientTotals> = self.totals.values().copied().collect();
+        totals.sort_by_key(|t| (t.tenant, t.client, t.currency));
+        totals
+    }
+
+    /// Adds an applied deposit, withdrawal or chargeback to the totals of
+    /// its account, if it falls into the `TotalsPeriod`.
+    fn add_to_totals(&mut self, record: &InputRecord) {
+        let Some(period) = self.config.totals else {
+            return;
+        };
+        if !period.contains(record.timestamp) {
+            return;
+        }
+        let key = self.account_key(record);
+        let amount = match record.r#type {
+            TransactionType::Deposit | TransactionType::Withdrawal => record.amount,
+            TransactionType::Chargeback => self
+                .lookup_tx((record.tx, record.tenant))
+                .map(|t| t.in_dispute()),
+            _ => None,
+        };
+        let Some(amount) = amount else {
+            return;
+        };
+        if let Some(batch) = &mut self.batch {
+            let totals = self.totals.get(&key).copied();
+            batch.totals.entry(key).or_insert(totals);
+        }
+        let totals = self
+            .totals
+            .entry(key)
+            .or_insert_with(|| ClientTotals::new(key));
+        let total = match record.r#type {
+            TransactionType::Deposit => &mut totals.deposited,
+            TransactionType::Withdrawal => &mut totals.withdrawn,
+            _ => &mut totals.charged_back,
+        };
+        *total = saturating_add(*total, amount);
+    }
+
+    /// The balances of every account at the end of every day, from the day
+    /// of the first timestamp the account saw up to the day of the last
+    /// timestamp of all, sorted by tenant, client, currency and day. A day
+    /// without any records for an account carries over the balances of the
+    /// day before, so every account has a line for every day of the input.
+    /// Days are taken in UTC, and a record without a timestamp counts
+    /// towards the next day there is a record of the account for. Nothing
+    /// is listed unless `EngineConfig::history` is set, and, like
+    /// statements, the history only covers what this engine applied itself.
+    pub fn history(&self) -> Vec<DailyBalance> {
+        let last = self
+            .history
+            .values()
+            .filter_map(|days| days.last_key_value())
+            .map(|(day, _)| *day)
+            .max();
+        let Some(last) = last else {
+            return Vec::new();
+        };
+        let mut history = Vec::new();
+        for ((client, currency, tenant), days) in &self.history {
+            let Some((first, _)) = days.first_key_value() else {
+                continue;
+            };
+            let mut balances = None;
+            for date in first.iter_days().take_while(|day| *day <= last) {
+                let Some(b) = days.get(&date).or(balances) else {
+                    continue;
+                };
+                balances = Some(b);
+                history.push(DailyBalance {
+                    client: *client,
+                    currency: *currency,
+                    tenant: *tenant,
+                    date,
+                    available: b.available,
+                    held: b.held,
+                    total: b.total,
+                    locked: b.locked,
+                });
+            }
+        }
+        history.sort_by_key(|d| (d.tenant, d.client, d.currency, d.date));
+        history
+    }
+
+    /// Remembers the balances of the account an applied record changed as
+    /// those at the end of the day of the record, until a later record of
+    /// the same day replaces them.
+    fn add_to_history(&mut self, record: &InputRecord) {
+        if !self.config.history {
+            return;
+        }
+        let Some(timestamp) = record.timestamp else {
+            return;
+        };
+        let key = self.account_key(record);
+        let Some(account) = self.get_account(key) else {
+            return;
+        };
+        let balances = OutputRecord::from(&account);
+        let date = timestamp.date_naive();
+        let day = self.history.entry(key).or_default();
+        if let Some(batch) = &mut self.batch {
+            batch
+                .history
+                .entry((key, date))
+                .or_insert(day.get(&date).copied());
+        }
+        day.insert(date, balances);
+    }
+
+    /// The number of disputes still open.
+    pub fn open_disputes(&self) -> usize {
+        self.ledger
+            .transactions()
+            .filter(|(_, t)| t.state == TransactionState::Disputed)
+            .count()
+    }
+
+    fn add_volume(&mut self, currency: Option<Currency>, volume: &Volume) {
+        self.volumes
+            .entry(currency)
+            .or_insert_with(|| Volume {
+                currency,
+                ..Volume::default()
+            })
+            .add(volume);
+    }
+
+    /// Every transaction that has been disputed, open or closed, sorted by
+    /// tenant, client and transaction ID. Unlike statements, these are part
+    /// of the state, so they include disputes from before a snapshot.
+    pub fn disputes(&self) -> Vec<Dispute> {
+        let mut disputes: Vec<Dispute> = self
+            .ledger
+            .transactions()
+            .filter(|(_, t)| t.state != TransactionState::Normal)
+            .map(|(tx, t)| Dispute {
+                client: t.client,
+                tx,
+                r#type: t.kind,
+                currency: t.currency,
+                tenant: t.tenant,
+                amount: t.in_dispute(),
+                status: t.state,
+                disputed_at: t.disputed_at,
+            })
+            .collect();
+        disputes.sort_by_key(|d| (d.tenant, d.client, d.tx));
+        disputes
+    }
+
+    /// Escalates every open dispute that reached one of the ages of the
+    /// `DisputeEscalation` of the configuration by `now`, since the last
+    /// time, and returns how many escalations there were. Each of them is
+    /// kept for `escalations` and written to the event log as a
+    /// `DisputeEscalated` event, so the dispute can be chased before it has
+    /// to be charged back. A dispute that expires by an age, see
+    /// `DisputeExpiry`, is not escalated for it.
+    ///
+    /// Only disputes with a timestamp age. Like `expire_disputes`, `apply`
+    /// does this as records come in. An engine that starts from a snapshot
+    /// or a ledger does not know which escalations were raised before, so
+    /// it raises those again.
+    pub fn escalate_disputes(&mut self, now: DateTime<Utc>) -> usize {
+        if self.config.dispute_escalation.days.is_empty() || self.escalated_to >= Some(now) {
+            return 0;
+        }
+        let since = self.escalated_to.replace(now);
+        let expiry = self.config.dispute_expiry.map(|expiry| expiry.days);
+        let mut due = Vec::new();
+        for &days in &self.config.dispute_escalation.days {
+            if expiry.is_some_and(|expiry| days >= expiry) {
+                break;
+            }
+            let age = days_duration(days);
+            // The disputes opened after the last time minus the age, and
+            // by now minus the age. The lowest transaction ID, of no
+            // tenant, is a bound that leaves none of those out.
+            let from = since.map(|since| since - age);
+            let opened = match from {
+                Some(from) => self.expiring.range((from, (TxId::new(0), None))..),
+                None => self.expiring.range(..),
+            };
+            due.extend(
+                opened
+                    .skip_while(|&&(at, _)| Some(at) == from)
+                    .take_while(|&&(at, _)| at + age <= now)
+                    .map(|&(at, tx)| (at + age, tx, days)),
+            );
+        }
+        // They are raised in the order they were reached.
+        due.sort_unstable();
+        let mut escalated = 0;
+        for (escalated_at, tx, days) in due {
+            let Some(transaction) = self.lookup_tx(tx) else {
+                continue;
+            };
+            let escalation = Escalation {
+                client: transaction.client,
+                tx: tx.0,
+                currency: transaction.currency,
+                tenant: transaction.tenant,
+                amount: transaction.in_dispute(),
+                disputed_at: escalated_at - days_duration(days),
+                days,
+                escalated_at,
+            };
+            if let Some(log) = &mut self.events {
+                log.push(Event::DisputeEscalated {
+                    client: escalation.client,
+                    tx: tx.0,
+                    currency: escalation.currency,
+                    tenant: escalation.tenant,
+                    amount: escalation.amount,
+                    days,
+                });
+            }
+            self.escalations.push(escalation);
+            escalated += 1;
+        }
+        escalated
+    }
+
+    /// Closes every open dispute that expired by `now`, as the
+    /// `DisputeExpiry` of the configuration asks for, and returns how many
+    /// there were. Each of them is closed as though a resolve or chargeback
+    /// had come in right when it expired: it shows up as such on statements,
+    /// and as a `DisputeExpired` event in the event log. A dispute that
+    /// cannot be closed that way is logged once and left open, to be tried
+    /// again once its account changes.
+    ///
+    /// `apply` does this as records come in. It only has to be called when
+    /// time moves on without any, such as at the end of the input.
+    pub fn expire_disputes(&mut self, now: DateTime<Utc>) -> usize {
+        let Some(expiry) = self.config.dispute_expiry else {
+            return 0;
+        };
+        let age = days_duration(expiry.days);
+        let due: Vec<(DateTime<Utc>, TxKey)> = self
+            .expiring
+            .iter()
+            .take_while(|&&(disputed_at, _)| disputed_at + age <= now)
+            .copied()
+            .collect();
+        let mut expired = 0;
+        for (disputed_at, tx) in due {
+            let expires_at = disputed_at + age;
+            let Some(transaction) = self.lookup_tx(tx) else {
+                self.expiring.remove(&(disputed_at, tx));
+                continue;
+            };
+            let key = (transaction.client, transaction.currency, transaction.tenant);
+            let record = InputRecord {
+                r#type: match expiry.outcome {
+                    DisputeOutcome::Resolve => TransactionType::Resolve,
+                    DisputeOutcome::Chargeback => TransactionType::Chargeback,
+                },
+                client: transaction.client,
+                tx: tx.0,
+                amount: None,
+                timestamp: Some(expires_at),
+                currency: transaction.currency,
+                reason: None,
+                tenant: transaction.tenant,
+            };
+            let before = self.get_account(key);
+            let was_locked = before.is_some_and(|a| a.is_locked());
+            let result = match expiry.outcome {
+                DisputeOutcome::Resolve => self.transition(&record, TransactionState::Resolved),
+                DisputeOutcome::Chargeback => {
+                    self.transition(&record, TransactionState::ChargedBack)
+                }
+            };
+            // Closing the dispute takes it off `expiring`. One that cannot be
+            // closed, such as after a restore with a `DisputePolicy` that no
+            // longer allows it, waits for its account to change instead.
+            if let Err(reason) = result {
+                self.expiring.remove(&(disputed_at, tx));
+                self.unexpirable
+                    .entry(key)
+                    .or_default()
+                    .insert((disputed_at, tx));
+                warn!(
+                    client = %record.client,
+                    tx = %record.tx,
+                    tenant = record.tenant.map(tracing::field::display),
+                    %reason,
+                    "expired dispute could not be closed"
+                );
+                continue;
+            }
+            if self.config.statements.includes(record.client) {
+                self.add_to_statement(&record, Amount::ZERO);
+            }
+            self.add_to_totals(&record);
+            self.add_to_history(&record);
+            if self.config.double_entry {
+                self.add_to_book(&record, before, Amount::ZERO);
+            }
+            if let Some(changes) = &mut self.changes {
+                changes.accounts.insert(key);
+                changes.transactions.insert(tx);
+            }
+            let is_locked = self.get_account(key).is_some_and(|a| a.is_locked());
+            if !was_locked && is_locked && !self.observers.is_empty() {
+                self.notify(Notification::AccountLocked {
+                    client: record.client,
+                    currency: record.currency,
+                    tenant: record.tenant,
+                });
+            }
+            if let Some(log) = &mut self.events {
+                log.push(Event::DisputeExpired {
+                    client: record.client,
+                    tx: record.tx,
+                    currency: record.currency,
+                    tenant: record.tenant,
+                    amount: transaction.in_dispute(),
+                    outcome: expiry.outcome,
+                });
+                if !was_locked && is_locked {
+                    log.push(Event::AccountLocked {
+                        client: record.client,
+                        currency: record.currency,
+                        tenant: record.tenant,
+                    });
+                }
+            }
+            expired += 1;
+        }
+        expired
+    }
+
+    /// Does the actual work of `apply`.
+    fn execute(&mut self, record: &InputRecord) -> Result<Executed, RejectReason> {
+        if let (Some(as_of), Some(timestamp)) = (self.config.as_of, record.timestamp) {
+            if timestamp > as_of {
+                return Err(RejectReason::AfterCutoff);
+            }
+        }
+        // Checking a balance moves no money, so it is fine for any account.
+        if record.r#type == TransactionType::BalanceCheck {
+            return self.check_balance(record);
+        }
+        let key = self.account_key(record);
+        if let Some(account) = self.get_account(key) {
+            if account.status() == AccountStatus::Closed && record.r#type != TransactionType::Open {
+                return Err(RejectReason::AccountClosed);
+            }
+            // A lock only keeps money from moving, the account can still be
+            // managed.
+            if account.is_locked()
+                && !record.r#type.manages_account()
+                && !self.config.locked_accounts.allows(&record.r#type)
+            {
+                return Err(RejectReason::AccountLocked);
+            }
+        }
+
+        let duplicate =
+            record.r#type.requires_amount() && self.lookup_tx((record.tx, record.tenant)).is_some();
+        if duplicate && self.config.duplicates == DuplicatePolicy::Reject {
+            return Err(RejectReason::DuplicateTransaction);
+        }
+
+        let mut fees = Amount::ZERO;
+        match record.r#type {
+            TransactionType::Deposit => {
+                let amount = record.amount.ok_or(RejectReason::MissingAmount)?;
+                self.check_limits(key, record, amount)?;
+                // The account is only opened once the deposit went through,
+                // so a rejected deposit leaves no empty account behind.
+                let mut account = match self.get_account(key) {
+                    Some(account) => account,
+                    None if self.config.accounts == AccountPolicy::Explicit => {
+                        return Err(RejectReason::UnknownClient)
+                    }
+                    None => new_account(key),
+                };
+                let before = account;
+                account.deposit(amount)?;
+                account.count_transaction();
+                // The fee is part of the deposit, so it is taken even if the
+                // deposit does not make up for funds already spent.
+                fees = self.config.fees.deposit_fee(amount);
+                if fees > Amount::ZERO {
+                    account.withdraw(fees, OverdraftPolicy::AllowNegative)?;
+                }
+                self.check_negative_balance(record, &before, &account, false)?;
+                self.upsert_account(account);
+                self.store(record, amount);
+                self.add_to_daily(key, record, amount);
+                fees = saturating_add(fees, self.charge_monthly_fee(key, record.timestamp));
+                self.add_volume(
+                    record.currency,
+                    &Volume {
+                        deposits: 1,
+                        deposited: amount,
+                        fees,
+                        ..Volume::default()
+                    },
+                );
+            }
+            TransactionType::Withdrawal => {
+                let amount = record.amount.ok_or(RejectReason::MissingAmount)?;
+                self.check_limits(key, record, amount)?;
+                fees = self.config.fees.withdrawal;
+                let overdraft = self.config.overdraft;
+                self.update_account_checked(record, key, |account| {
+                    account.withdraw(
+                        amount.checked_add(fees).ok_or(RejectReason::Overflow)?,
+                        overdraft,
+                    )?;
+                    account.count_transaction();
+                    Ok(())
+                })?;
+                self.store(record, amount);
+                self.add_to_daily(key, record, amount);
+                fees = saturating_add(fees, self.charge_monthly_fee(key, record.timestamp));
+                self.add_volume(
+                    record.currency,
+                    &Volume {
+                        withdrawals: 1,
+                        withdrawn: amount,
+                        fees,
+                        ..Volume::default()
+                    },
+                );
+            }
+            TransactionType::Fee => {
+                let amount = record.amount.ok_or(RejectReason::MissingAmount)?;
+                let overdraft = self.config.overdraft;
+                self.update_account_checked(record, key, |account| {
+                    account.withdraw(amount, overdraft)
+                })?;
+                self.add_volume(
+                    record.currency,
+                    &Volume {
+                        fees: amount,
+                        ..Volume::default()
+                    },
+                );
+            }
+            TransactionType::CreditAdjustment => {
+                let amount = record.amount.ok_or(RejectReason::MissingAmount)?;
+                record.reason.ok_or(RejectReason::MissingReason)?;
+                self.update_account_checked(record, key, |account| account.deposit(amount))?;
+            }
+            TransactionType::DebitAdjustment => {
+                let amount = record.amount.ok_or(RejectReason::MissingAmount)?;
+                record.reason.ok_or(RejectReason::MissingReason)?;
+                let overdraft = self.config.overdraft;
+                self.update_account_checked(record, key, |account| {
+                    account.withdraw(amount, overdraft)
+                })?;
+            }
+            TransactionType::Dispute => self.transition(record, TransactionState::Disputed)?,
+            TransactionType::Resolve => self.transition(record, TransactionState::Resolved)?,
+            TransactionType::Chargeback => {
+                self.transition(record, TransactionState::ChargedBack)?
+            }
+            TransactionType::Open => self.open(key)?,
+            TransactionType::Close => self.manage(key, Account::close)?,
+            TransactionType::Freeze => self.manage(key, Account::freeze)?,
+            TransactionType::Unfreeze => self.manage(key, Account::unfreeze)?,
+            TransactionType::Unlock => self.manage(key, Account::unlock)?,
+            TransactionType::BalanceCheck => unreachable!("balance checks are done above"),
+            TransactionType::Custom(custom) => {
+                let handler = self
+                    .handlers
+                    .get(&custom)
+                    .ok_or(RejectReason::UnhandledTransactionType)?;
+                let mut account = match self.get_account(key) {
+                    Some(account) => account,
+                    None if self.config.accounts == AccountPolicy::Explicit => {
+                        return Err(RejectReason::UnknownClient)
+                    }
+                    None => new_account(key),
+                };
+                let before = account;
+                handler.apply(record, &mut account)?;
+                self.check_negative_balance(record, &before, &account, false)?;
+                self.upsert_account(account);
+            }
+        }
+        Ok(Executed {
+            warning: duplicate.then_some(Warning::DuplicateTransaction),
+            fees,
+        })
+    }
+
+    /// Compares the total of the account of `record` with the amount of the
+    /// record, and keeps the outcome for `balance_checks`. An account that
+    /// was never opened holds nothing, so a client whose transactions all
+    /// went missing is caught as well. A mismatch is not a reason to reject
+    /// the record, but it raises a warning.
+    fn check_balance(&mut self, record: &InputRecord) -> Result<Executed, RejectReason> {
+        let expected = record.amount.ok_or(RejectReason::MissingAmount)?;
+        let key = self.account_key(record);
+        let actual = self.get_account(key).map_or(Amount::ZERO, |a| a.total());
+        self.balance_checks.push(BalanceCheck {
+            client: record.client,
+            tx: record.tx,
+            currency: key.1,
+            tenant: key.2,
+            timestamp: record.timestamp,
+            expected,
+            actual,
+        });
+        Ok(Executed {
+            warning: (expected != actual).then_some(Warning::BalanceMismatch { expected, actual }),
+            fees: Amount::ZERO,
+        })
+    }
+
+    /// Holds a record that changed an account from `before` to `after` to
+    /// the `NegativeBalancePolicy`, before anything is stored. `credit` is
+    /// whether the record moves a withdrawal dispute under
+    /// `WithdrawalDisputes::Credit`, which may leave the held funds negative.
+    /// Available funds taken below zero as far as the `OverdraftPolicy`
+    /// allows are kept track of, but always applied.
+    fn check_negative_balance(
+        &mut self,
+        record: &InputRecord,
+        before: &Account,
+        after: &Account,
+        credit: bool,
+    ) -> Result<(), RejectReason> {
+        let available = after.available() < before.available() && after.available().is_negative();
+        let held = !credit && after.held() < before.held() && after.held() < Amount::ZERO;
+        if !available && !held {
+            return Ok(());
+        }
+        let policy = match held || !self.config.overdraft.allows(after.available()) {
+            true => self.config.negative_balances,
+            false => NegativeBalancePolicy::Log,
+        };
+        self.negative_balances.push(NegativeBalance {
+            client: after.client(),
+            tx: record.tx,
+            currency: after.currency(),
+            tenant: after.tenant(),
+            r#type: record.r#type,
+            available: after.available(),
+            held: after.held(),
+            applied: policy == NegativeBalancePolicy::Log,
+        });
+        match policy {
+            NegativeBalancePolicy::Log => Ok(()),
+            NegativeBalancePolicy::Reject | NegativeBalancePolicy::Abort => {
+                Err(RejectReason::NegativeBalance)
+            }
+        }
+    }
+
+    /// Refuses a deposit or withdrawal of `amount` to the account at `key`
+    /// that would break any of the `Limits`.
+    fn check_limits(
+        &self,
+        key: AccountKey,
+        record: &InputRecord,
+        amount: Amount,
+    ) -> Result<(), RejectReason> {
+        let limits = &self.config.limits;
+        if limits.amount.is_some_and(|max| amount > max) {
+            return Err(RejectReason::AmountOverLimit);
+        }
+        if let Some(max) = limits.transactions {
+            let count = self.get_account(key).map_or(0, |a| a.transactions());
+            if count >= max {
+                return Err(RejectReason::TransactionLimitReached);
+            }
+        }
+        if let (Some(max), Some(timestamp)) = (limits.daily, record.timestamp) {
+            let moved = match self.daily.get(&key) {
+                Some((day, moved)) if *day == timestamp.date_naive() => *moved,
+                _ => Amount::ZERO,
+            };
+            if moved.checked_add(amount).is_none_or(|moved| moved > max) {
+                return Err(RejectReason::DailyLimitExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds an applied deposit or withdrawal of `amount` to what the account
+    /// at `key` moved on the day of its timestamp. Only the last day counts,
+    /// so a record of an earlier day than the one before starts that day
+    /// afresh.
+    fn add_to_daily(&mut self, key: AccountKey, record: &InputRecord, amount: Amount) {
+        let (Some(_), Some(timestamp)) = (self.config.limits.daily, record.timestamp) else {
+            return;
+        };
+        if let Some(batch) = &mut self.batch {
+            let daily = self.daily.get(&key).copied();
+            batch.daily.entry(key).or_insert(daily);
+        }
+        let day = timestamp.date_naive();
+        let moved = match self.daily.get(&key) {
+            Some((last, moved)) if *last == day => saturating_add(*moved, amount),
+            _ => amount,
+        };
+        self.daily.insert(key, (day, moved));
+    }
+
+    /// Charges the monthly fees the account at `key` owes as of `timestamp`,
+    /// as far as its available funds cover them, and returns what was
+    /// charged.
+    fn charge_monthly_fee(&mut self, key: AccountKey, timestamp: Option<DateTime<Utc>>) -> Amount {
+        let timestamp = match timestamp {
+            Some(timestamp) if self.config.fees.monthly > Amount::ZERO => timestamp,
+            _ => return Amount::ZERO,
+        };
+        let Some(mut account) = self.get_account(key) else {
+            return Amount::ZERO;
+        };
+        let fee = self
+            .config
+            .fees
+            .monthly_fees(account.billed_month(), timestamp)
+            .min(account.available().max(Amount::ZERO));
+        account.bill(month(timestamp));
+        let charged = match account.withdraw(fee, OverdraftPolicy::Reject) {
+            Ok(()) => fee,
+            Err(_) => Amount::ZERO,
+        };
+        self.upsert_account(account);
+        charged
+    }
+
+    /// Opens an account for `client` of `tenant` in `currency`, or opens a
+    /// closed one again. This is what an `open` record does, and the only
+    /// way to open an account under `AccountPolicy::Explicit`. The tenant
+    /// is of no account unless `EngineConfig::tenants` keeps tenants apart.
+    pub fn open_account(
+        &mut self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Result<(), RejectReason> {
+        self.open((client, currency, self.tenant(tenant)))
+    }
+
+    /// Opens the account at `key`, or opens it again, see `open_account`.
+    fn open(&mut self, key: AccountKey) -> Result<(), RejectReason> {
+        let account = match self.get_account(key) {
+            Some(mut account) => {
+                account.reopen()?;
+                account
+            }
+            None => new_account(key),
+        };
+        self.upsert_account(account);
+        self.mark_changed(key);
+        Ok(())
+    }
+
+    /// Closes the account of `client` of `tenant` in `currency`, which has
+    /// to be empty. A closed account takes no transactions until it is
+    /// opened again.
+    pub fn close_account(
+        &mut self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Result<(), RejectReason> {
+        self.manage((client, currency, self.tenant(tenant)), Account::close)
+    }
+
+    /// Freezes the account of `client` of `tenant` in `currency`, so no
+    /// money moves in or out of it until it is unfrozen.
+    pub fn freeze(
+        &mut self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Result<(), RejectReason> {
+        self.manage((client, currency, self.tenant(tenant)), Account::freeze)
+    }
+
+    /// Undoes a `freeze`.
+    pub fn unfreeze(
+        &mut self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Result<(), RejectReason> {
+        self.manage((client, currency, self.tenant(tenant)), Account::unfreeze)
+    }
+
+    /// Lifts the lock a chargeback put on the account of `client` of
+    /// `tenant` in `currency`. This is what an `unlock` record does.
+    pub fn unlock(
+        &mut self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Result<(), RejectReason> {
+        self.manage((client, currency, self.tenant(tenant)), Account::unlock)
+    }
+
+    /// `tenant`, if `EngineConfig::tenants` keeps tenants apart, just like
+    /// `apply` takes the tenant of a record.
+    fn tenant(&self, tenant: Option<Tenant>) -> Option<Tenant> {
+        tenant.filter(|_| self.config.tenants)
+    }
+
+    /// Every locked account, with the chargeback that locked it, sorted by
+    /// tenant, client and currency. Like the balances, these are part of the
+    /// state, so they include locks from before a snapshot.
+    pub fn locks(&self) -> Vec<LockedAccount> {
+        let mut locks: Vec<LockedAccount> = self
+            .ledger
+            .accounts()
+            .filter(|a| a.is_locked())
+            .map(|a| LockedAccount {
+                client: a.client(),
+                currency: a.currency(),
+                tenant: a.tenant(),
+                chargeback: a.lock().map(|lock| lock.tx),
+                locked_at: a.lock().and_then(|lock| lock.locked_at),
+                available: a.available(),
+                held: a.held(),
+                total: a.total(),
+            })
+            .collect();
+        locks.sort_by_key(|l| (l.tenant, l.client, l.currency));
+        locks
+    }
+
+    /// Every account that trips any of the heuristics of `thresholds`,
+    /// sorted by tenant, client and currency. The figures are taken from the
+    /// transactions the ledger keeps, which is every deposit and withdrawal
+    /// ever applied, so, like the balances, they include those from before
+    /// a snapshot.
+    pub fn anomalies(&self, thresholds: &AnomalyThresholds) -> Vec<Anomaly> {
+        let mut accounts: HashMap<AccountKey, Anomaly> = HashMap::new();
+        for (_, transaction) in self.ledger.transactions() {
+            let anomaly = accounts
+                .entry((transaction.client, transaction.currency, transaction.tenant))
+                .or_insert_with(|| Anomaly {
+                    client: transaction.client,
+                    currency: transaction.currency,
+                    tenant: transaction.tenant,
+                    deposited: Amount::ZERO,
+                    withdrawn: Amount::ZERO,
+                    disputes: 0,
+                    flags: Vec::new(),
+                });
+            match transaction.kind {
+                TransactionType::Deposit => {
+                    anomaly.deposited = saturating_add(anomaly.deposited, transaction.amount)
+                }
+                TransactionType::Withdrawal => {
+                    anomaly.withdrawn = saturating_add(anomaly.withdrawn, transaction.amount)
+                }
+                _ => (),
+            }
+            if transaction.state != TransactionState::Normal {
+                anomaly.disputes += 1;
+            }
+        }
+        let mut anomalies: Vec<Anomaly> = accounts
+            .into_values()
+            .filter_map(|mut anomaly| {
+                anomaly.flags = thresholds.flags(&anomaly);
+                (!anomaly.flags.is_empty()).then_some(anomaly)
+            })
+            .collect();
+        anomalies.sort_by_key(|a| (a.tenant, a.client, a.currency));
+        anomalies
+    }
+
+    /// The account of `client` of `tenant` in `currency`, if it was ever
+    /// opened.
+    pub fn account(
+        &self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Option<Account> {
+        self.get_account((client, currency, self.tenant(tenant)))
+    }
+
+    /// The account at `key`, as changed by the strict batch that runs, if
+    /// any.
+    fn get_account(&self, (client, currency, tenant): AccountKey) -> Option<Account> {
+        self.batch
+            .as_ref()
+            .and_then(|batch| batch.ledger.get_account(client, currency, tenant))
+            .or_else(|| self.ledger.get_account(client, currency, tenant))
+    }
+
+    /// Stores `account`, aside from the ledger while a strict batch runs.
+    /// The disputes of the account that could not be closed when they
+    /// expired are due to be tried again.
+    fn upsert_account(&mut self, account: Account) {
+        if !self.unexpirable.is_empty() {
+            let key = (account.client(), account.currency(), account.tenant());
+            if let Some(disputes) = self.unexpirable.remove(&key) {
+                self.expiring.extend(disputes);
+            }
+        }
+        match &mut self.batch {
+            Some(batch) => batch.ledger.upsert_account(account),
+            None => self.ledger.upsert_account(account),
+        }
+    }
+
+    /// The transaction stored under `tx`, as changed by the strict batch
+    /// that runs, if any.
+    fn lookup_tx(&self, (tx, tenant): TxKey) -> Option<StoredTransaction> {
+        self.batch
+            .as_ref()
+            .and_then(|batch| batch.ledger.lookup_tx(tx, tenant))
+            .or_else(|| self.ledger.lookup_tx(tx, tenant))
+    }
+
+    /// Stores `transaction`, aside from the ledger while a strict batch
+    /// runs.
+    fn record_tx(&mut self, tx: TxId, transaction: StoredTransaction) {
+        match &mut self.batch {
+            Some(batch) => batch.ledger.record_tx(tx, transaction),
+            None => self.ledger.record_tx(tx, transaction),
+        }
+    }
+
+    /// Applies `operation` to an existing account, which is only stored
+    /// again if the operation succeeds.
+    fn update_account<F>(&mut self, key: AccountKey, operation: F) -> Result<(), RejectReason>
+    where
+        F: FnOnce(&mut Account) -> Result<(), RejectReason>,
+    {
+        let mut account = self.get_account(key).ok_or(RejectReason::UnknownClient)?;
+        operation(&mut account)?;
+        self.upsert_account(account);
+        Ok(())
+    }
+
+    /// Applies `operation` to an existing account on behalf of `record`,
+    /// just like `update_account`, holding the outcome to the
+    /// `NegativeBalancePolicy` before it is stored.
+    fn update_account_checked<F>(
+        &mut self,
+        record: &InputRecord,
+        key: AccountKey,
+        operation: F,
+    ) -> Result<(), RejectReason>
+    where
+        F: FnOnce(&mut Account) -> Result<(), RejectReason>,
+    {
+        let before = self.get_account(key).ok_or(RejectReason::UnknownClient)?;
+        let mut account = before;
+        operation(&mut account)?;
+        self.check_negative_balance(record, &before, &account, false)?;
+        self.upsert_account(account);
+        Ok(())
+    }
+
+    /// Applies `operation` to an existing account on behalf of the caller.
+    fn manage(
+        &mut self,
+        key: AccountKey,
+        operation: fn(&mut Account) -> Result<(), RejectReason>,
+    ) -> Result<(), RejectReason> {
+        self.update_account(key, operation)?;
+        self.mark_changed(key);
+        Ok(())
+    }
+
+    /// Notes that an account changed outside of `apply`, for the store.
+    fn mark_changed(&mut self, key: AccountKey) {
+        if let Some(changes) = &mut self.changes {
+            changes.accounts.insert(key);
+        }
+    }
+
+    /// The account a record applies to. Most records name their currency,
+    /// while a dispute, resolve or chargeback goes to the account of the
+    /// transaction it refers to. Either way, it is the account of the tenant
+    /// of the record.
+    fn account_key(&self, record: &InputRecord) -> AccountKey {
+        let transaction = self
+            .lookup_tx((record.tx, record.tenant))
+            .filter(|t| t.client == record.client && record.r#type.refers_to_transaction());
+        match transaction {
+            Some(t) => (record.client, t.currency, record.tenant),
+            None => (record.client, record.currency, record.tenant),
+        }
+    }
+
+    /// Writes the complete state of the engine, every account as well as
+    /// every transaction that may still be disputed, to a JSON file. The
+    /// file is replaced atomically, so an earlier snapshot survives a crash.
+    /// Processing can later be picked up where it left off with `restore`,
+    /// instead of replaying all of history.
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        write_atomically(path.as_ref(), |file| self.snapshot_to_writer(file))
+    }
+
+    /// Writes a snapshot of the engine to any writer.
+    pub fn snapshot_to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        serde_json::to_writer(writer, &self.to_snapshot()).map_err(Error::Snapshot)
+    }
+
+    /// A snapshot of the engine as a JSON value, for a checkpoint to hold.
+    pub(crate) fn snapshot_value(&self) -> Result<serde_json::Value, Error> {
+        serde_json::to_value(self.to_snapshot()).map_err(Error::Snapshot)
+    }
+
+    fn to_snapshot(&self) -> Snapshot {
+        let mut accounts: Vec<Account> = self.ledger.accounts().collect();
+        accounts.sort_by_key(|a| (a.tenant(), a.client(), a.currency()));
+        let mut transactions = BTreeMap::new();
+        let mut tenants: BTreeMap<Tenant, BTreeMap<TxId, StoredTransaction>> = BTreeMap::new();
+        for (tx, transaction) in self.ledger.transactions() {
+            match transaction.tenant {
+                Some(tenant) => tenants.entry(tenant).or_default().insert(tx, transaction),
+                None => transactions.insert(tx, transaction),
+            };
+        }
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts,
+            transactions,
+            tenants,
+        }
+    }
+
+    /// Rebuilds an engine from a snapshot written by `snapshot`. The
+    /// policies are not part of the snapshot and are taken from `config`.
+    pub fn restore<P: AsRef<Path>>(path: P, config: EngineConfig) -> Result<Engine, Error> {
+        let file = BufReader::new(File::open(path)?);
+        Engine::restore_from_reader(file, config)
+    }
+
+    /// Rebuilds an engine from a snapshot read from any reader.
+    pub fn restore_from_reader<R: Read>(reader: R, config: EngineConfig) -> Result<Engine, Error> {
+        let snapshot: Snapshot = serde_json::from_reader(reader).map_err(Error::Snapshot)?;
+        Engine::from_snapshot(snapshot, config)
+    }
+
+    /// Rebuilds an engine from a snapshot made by `snapshot_value`.
+    pub(crate) fn restore_from_value(
+        value: serde_json::Value,
+        config: EngineConfig,
+    ) -> Result<Engine, Error> {
+        let snapshot: Snapshot = serde_json::from_value(value).map_err(Error::Snapshot)?;
+        Engine::from_snapshot(snapshot, config)
+    }
+
+    fn from_snapshot(snapshot: Snapshot, config: EngineConfig) -> Result<Engine, Error> {
+        let invalid = |msg: String| Error::Snapshot(serde::de::Error::custom(msg));
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(invalid(format!(
+                "unsupported snapshot version {}",
+                snapshot.version
+            )));
+        }
+
+        let tenants = snapshot
+            .tenants
+            .into_iter()
+            .flat_map(|(tenant, transactions)| {
+                transactions.into_iter().map(move |(tx, transaction)| {
+                    let tenant = Some(tenant);
+                    (
+                        tx,
+                        StoredTransaction {
+                            tenant,
+                            ..transaction
+                        },
+                    )
+                })
+            });
+        let transactions = snapshot.transactions.into_iter().chain(tenants);
+        Engine::from_state(config, snapshot.accounts, transactions).map_err(invalid)
+    }
+
+    /// Builds an engine from restored accounts and transactions, making sure
+    /// they are consistent. Returns what is wrong with them otherwise.
+    fn from_state<T>(
+        config: EngineConfig,
+        accounts: Vec<Account>,
+        transactions: T,
+    ) -> Result<Engine, String>
+    where
+        T: IntoIterator<Item = (TxId, StoredTransaction)>,
+    {
+        let mut ledger = MemoryLedger::default();
+        for account in accounts {
+            if ledger
+                .get_account(account.client(), account.currency(), account.tenant())
+                .is_some()
+            {
+                return Err(format!("client {} appears twice", account.client()));
+            }
+            ledger.upsert_account(account);
+        }
+        // Disputes rely on every stored transaction having an account.
+        for (tx, transaction) in transactions {
+            if ledger
+                .get_account(transaction.client, transaction.currency, transaction.tenant)
+                .is_none()
+            {
+                return Err(format!(
+                    "transaction {} refers to unknown client {}",
+                    tx, transaction.client
+                ));
+            }
+            ledger.record_tx(tx, transaction);
+        }
+        Ok(Engine::with_ledger(config, ledger))
+    }
+
+    /// Folds the state of another engine into this one. This is only
+    /// meaningful when both engines saw disjoint sets of clients, as is the
+    /// case for the shards of a parallel run.
+    pub(crate) fn merge(&mut self, other: Engine) {
+        for account in other.ledger.accounts() {
+            self.ledger.upsert_account(account);
+        }
+        for (tx, transaction) in other.ledger.transactions() {
+            self.ledger.record_tx(tx, transaction);
+        }
+        self.expiring.extend(other.expiring);
+        self.unexpirable.extend(other.unexpirable);
+        self.statements.extend(other.statements);
+        self.balance_checks.extend(other.balance_checks);
+        self.negative_balances.extend(other.negative_balances);
+        self.pending_disputes.extend(other.pending_disputes);
+        self.book.extend(other.book);
+        self.escalations.extend(other.escalations);
+        self.escalated_to = self.escalated_to.max(other.escalated_to);
+        self.totals.extend(other.totals);
+        self.daily.extend(other.daily);
+        self.history.extend(other.history);
+        self.handlers.extend(other.handlers);
+        if self.validators.is_empty() {
+            self.validators = other.validators;
+        }
+        if self.observers.is_empty() {
+            self.observers = other.observers;
+        }
+        for (currency, volume) in other.volumes {
+            self.add_volume(currency, &volume);
+        }
+        match (&mut self.journal, other.journal) {
+            (Some(journal), Some(other)) => journal.merge(other),
+            (journal @ None, other) => *journal = other,
+            (Some(_), None) => (),
+        }
+        match (&mut self.events, other.events) {
+            (Some(events), Some(other)) => events.merge(other),
+            (events @ None, other) => *events = other,
+            (Some(_), None) => (),
+        }
+        match (&mut self.changes, other.changes) {
+            (Some(changes), Some(other)) => {
+                changes.accounts.extend(other.accounts);
+                changes.transactions.extend(other.transactions);
+            }
+            (changes @ None, other) => *changes = other,
+            (Some(_), None) => (),
+        }
+        #[cfg(feature = "sqlite")]
+        if self.store.is_none() {
+            self.store = other.store;
+        }
+    }
+
+    /// Splits the engine into `shards` engines, the `n`th of which holds the
+    /// clients whose ID modulo `shards` is `n`, along with their
+    /// transactions. This is the inverse of `merge`.
+    pub(crate) fn split(self, shards: usize) -> Vec<Engine> {
+        let mut engines: Vec<Engine> = (0..shards)
+            .map(|_| Engine::with_config(self.config.clone()))
+            .collect();
+        // The changed transactions are assigned by client, so this has to
+        // happen before the transactions are moved.
+        if let Some(changes) = self.changes {
+            for engine in &mut engines {
+                engine.changes = Some(Changes::default());
+            }
+            for key in changes.accounts {
+                let engine = &mut engines[key.0.shard(shards)];
+                engine.changes.as_mut().unwrap().accounts.insert(key);
+            }
+            for tx in changes.transactions {
+                let Some(transaction) = self.ledger.lookup_tx(tx.0, tx.1) else {
+                    continue;
+                };
+                let engine = &mut engines[transaction.client.shard(shards)];
+                engine.changes.as_mut().unwrap().transactions.insert(tx);
+            }
+        }
+        for (at, tx) in self.expiring {
+            let Some(transaction) = self.ledger.lookup_tx(tx.0, tx.1) else {
+                continue;
+            };
+            engines[transaction.client.shard(shards)]
+                .expiring
+                .insert((at, tx));
+        }
+        for (key, disputes) in self.unexpirable {
+            engines[key.0.shard(shards)]
+                .unexpirable
+                .insert(key, disputes);
+        }
+        // The first engine keeps the ledger, which `merge` writes the state
+        // of the others back into.
+        let mut ledger = self.ledger;
+        let accounts: Vec<Account> = ledger.accounts().collect();
+        let transactions: Vec<(TxId, StoredTransaction)> = ledger.transactions().collect();
+        ledger.clear();
+        engines[0].ledger = ledger;
+        for account in accounts {
+            engines[account.client().shard(shards)]
+                .ledger
+                .upsert_account(account);
+        }
+        for (tx, transaction) in transactions {
+            engines[transaction.client.shard(shards)]
+                .ledger
+                .record_tx(tx, transaction);
+        }
+        // The volumes are summed up again by `merge`.
+        engines[0].volumes = self.volumes;
+        for (key, statement) in self.statements {
+            engines[key.0.shard(shards)]
+                .statements
+                .insert(key, statement);
+        }
+        for check in self.balance_checks {
+            engines[check.client.shard(shards)]
+                .balance_checks
+                .push(check);
+        }
+        for entry in self.book {
+            engines[entry.client.shard(shards)].book.push(entry);
+        }
+        for negative in self.negative_balances {
+            engines[negative.client.shard(shards)]
+                .negative_balances
+                .push(negative);
+        }
+        for (tx, dispute) in self.pending_disputes {
+            engines[dispute.client.shard(shards)]
+                .pending_disputes
+                .insert(tx, dispute);
+        }
+        for escalation in self.escalations {
+            engines[escalation.client.shard(shards)]
+                .escalations
+                .push(escalation);
+        }
+        for (key, totals) in self.totals {
+            engines[key.0.shard(shards)].totals.insert(key, totals);
+        }
+        for (key, daily) in self.daily {
+            engines[key.0.shard(shards)].daily.insert(key, daily);
+        }
+        for (key, days) in self.history {
+            engines[key.0.shard(shards)].history.insert(key, days);
+        }
+        for engine in &mut engines {
+            engine.handlers = self.handlers.clone();
+            engine.validators = self.validators.clone();
+            engine.observers = self.observers.clone();
+            engine.escalated_to = self.escalated_to;
+        }
+        if let Some(journal) = self.journal {
+            for (engine, journal) in engines.iter_mut().zip(journal.split(shards)) {
+                engine.journal = Some(journal);
+            }
+        }
+        // The events of the other engines are numbered after those of the
+        // first by `merge`.
+        if let Some(events) = self.events {
+            for engine in &mut engines[1..] {
+                engine.events = Some(EventLog::new());
+            }
+            engines[0].events = Some(events);
+        }
+        // The store is handed back by `merge`.
+        #[cfg(feature = "sqlite")]
+        {
+            engines[0].store = self.store;
+        }
+        engines
+    }
+
+    /// Books an applied record in the double-entry journal, given the
+    /// account it was applied to as it was `before` the record, if there
+    /// was one, and the `fees` charged for it.
+    fn add_to_book(&mut self, record: &InputRecord, before: Option<Account>, fees: Amount) {
+        let key = self.account_key(record);
+        let Some(after) = self.get_account(key) else {
+            return;
+        };
+        let funds = |account: &Account| Funds {
+            available: account.available(),
+            held: account.held(),
+        };
+        let kind = self
+            .lookup_tx((record.tx, record.tenant))
+            .filter(|_| record.r#type.refers_to_transaction())
+            .map(|t| t.kind);
+        self.book.extend(double_entry::book(
+            record,
+            key.1,
+            kind,
+            before.as_ref().map_or(Funds::default(), funds),
+            funds(&after),
+            fees,
+        ));
+    }
+
+    /// Adds an applied record to the statement of its client. A record can
+    /// only be applied to an existing account, and a dispute, resolve or
+    /// chargeback only to a known transaction.
+    ///
+    /// The `fees` charged for the record are listed on a line of their own
+    /// right after it, so the line of the record itself shows the balances
+    /// before them.
+    fn add_to_statement(&mut self, record: &InputRecord, fees: Amount) {
+        let amount = record
+            .amount
+            .filter(|_| record.r#type.requires_amount() || record.r#type.is_custom())
+            .or_else(|| {
+                self.lookup_tx((record.tx, record.tenant))
+                    .filter(|_| record.r#type.refers_to_transaction())
+                    .map(|t| t.in_dispute())
+            })
+            .unwrap_or(Amount::ZERO);
+        let key = self.account_key(record);
+        let Some(account) = self.get_account(key) else {
+            return;
+        };
+        let statement = self
+            .statements
+            .entry((record.client, record.tenant))
+            .or_default();
+        if let Some(batch) = &mut self.batch {
+            batch
+                .statements
+                .entry((record.client, record.tenant))
+                .or_insert(statement.len());
+        }
+        statement.push(StatementLine {
+            tx: record.tx,
+            r#type: record.r#type,
+            currency: account.currency(),
+            tenant: account.tenant(),
+            amount,
+            available: account.available() + fees,
+            held: account.held(),
+            total: account.total() + fees,
+            reason: record.reason,
+        });
+        if fees != Amount::ZERO {
+            statement.push(StatementLine {
+                tx: record.tx,
+                r#type: TransactionType::Fee,
+                currency: account.currency(),
+                tenant: account.tenant(),
+                amount: fees,
+                available: account.available(),
+                held: account.held(),
+                total: account.total(),
+                reason: None,
+            });
+        }
+    }
+
+    /// Remembers an applied transaction so it can be disputed later. If a
+    /// transaction ID is seen more than once, the `DuplicatePolicy` decides
+    /// which of them is kept.
+    fn store(&mut self, record: &InputRecord, amount: Amount) {
+        let transaction = StoredTransaction {
+            client: record.client,
+            currency: record.currency,
+            tenant: record.tenant,
+            kind: record.r#type,
+            amount,
+            state: TransactionState::Normal,
+            disputed: None,
+            disputed_at: None,
+            timestamp: record.timestamp,
+            seq: self
+                .get_account((record.client, record.currency, record.tenant))
+                .map_or(0, |a| a.transactions()),
+        };
+        let replace = match self.lookup_tx((record.tx, record.tenant)) {
+            None => true,
+            Some(existing) => {
+                self.config.duplicates == DuplicatePolicy::KeepLast
+                    && existing.state == TransactionState::Normal
+            }
+        };
+        if replace {
+            self.record_tx(record.tx, transaction);
+        }
+    }
+
+    /// Moves the transaction referenced by `record` into the `next` dispute
+    /// state and moves the disputed funds of the account accordingly, as
+    /// long as the state machine and the `DisputePolicy` allow it. The state
+    /// only changes if the account operation succeeds as well.
+    /// Since a transaction is only ever stored after it was applied to an
+    /// account, a known transaction guarantees the account exists.
+    fn transition(
+        &mut self,
+        record: &InputRecord,
+        next: TransactionState,
+    ) -> Result<(), RejectReason> {
+        let mut transaction = match self.lookup_tx((record.tx, record.tenant)) {
+            Some(t) if t.client == record.client => t,
+            Some(_) => return Err(RejectReason::ClientMismatch),
+            None => return Err(RejectReason::UnknownTransaction),
+        };
+        if !self.config.disputes.allows(&transaction.kind) {
+            return Err(RejectReason::NotDisputable);
+        }
+        if record.currency.is_some() && record.currency != transaction.currency {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+
+        match (transaction.state, next) {
+            (TransactionState::Normal, TransactionState::Disputed)
+            | (TransactionState::Disputed, TransactionState::Resolved)
+            | (TransactionState::Disputed, TransactionState::ChargedBack) => {
+                let key = (record.client, transaction.currency, record.tenant);
+                let mut account = self.get_account(key).ok_or(RejectReason::UnknownClient)?;
+                if next == TransactionState::Disputed
+                    && !self
+                        .config
+                        .dispute_window
+                        .allows(&transaction, &account, record.timestamp)
+                {
+                    return Err(RejectReason::DisputeWindowClosed);
+                }
+                // A dispute may hold only part of the transaction, which
+                // its resolve or chargeback then settles. The amount of any
+                // other record is of no account.
+                if let (TransactionState::Disputed, Some(amount)) = (next, record.amount) {
+                    if amount <= Amount::ZERO || amount > transaction.amount {
+                        return Err(RejectReason::InvalidDisputeAmount);
+                    }
+                    transaction.disputed = (amount < transaction.amount).then_some(amount);
+                }
+                let pending = transaction.kind == TransactionType::Withdrawal
+                    && self.config.withdrawal_disputes == WithdrawalDisputes::Pending;
+                let before = account;
+                match (next, pending) {
+                    (TransactionState::Disputed, false) => {
+                        account.hold(transaction.disputed_amount())?
+                    }
+                    (TransactionState::Resolved, false) => {
+                        account.release(transaction.disputed_amount())?
+                    }
+                    (_, false) => account.chargeback(transaction.disputed_amount())?,
+                    (TransactionState::Disputed, true) => {
+                        account.credit_pending(transaction.in_dispute())?
+                    }
+                    (TransactionState::Resolved, true) => {
+                        account.drop_pending(transaction.in_dispute())?
+                    }
+                    (_, true) => account.refund_pending(transaction.in_dispute())?,
+                }
+                let credit = transaction.kind == TransactionType::Withdrawal && !pending;
+                self.check_negative_balance(record, &before, &account, credit)?;
+                if next == TransactionState::ChargedBack {
+                    account.note_lock(Lock {
+                        tx: record.tx,
+                        locked_at: record.timestamp,
+                    });
+                }
+                transaction.state = next;
+                if next == TransactionState::Disputed {
+                    transaction.disputed_at = record.timestamp;
+                    if let Some(at) = record.timestamp {
+                        self.expiring.insert((at, (record.tx, record.tenant)));
+                    }
+                } else if let Some(at) = transaction.disputed_at {
+                    let dispute = (at, (record.tx, record.tenant));
+                    self.expiring.remove(&dispute);
+                    if let Some(disputes) = self.unexpirable.get_mut(&key) {
+                        disputes.remove(&dispute);
+                    }
+                }
+                self.upsert_account(account);
+                self.record_tx(record.tx, transaction);
+                Ok(())
+            }
+            (TransactionState::Disputed, TransactionState::Disputed) => {
+                Err(RejectReason::AlreadyDisputed)
+            }
+            (TransactionState::Normal, _) => Err(RejectReason::NotDisputed),
+            _ => Err(RejectReason::DisputeClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::super::input::make_input_record;
+    use super::{
+        AccountPolicy, AnomalyFlag, AnomalyThresholds, BatchError, DisputeEscalation,
+        DisputeExpiry, DisputeOutcome, DisputePolicy, DisputeWindow, DuplicatePolicy, Engine,
+        EngineConfig, ErrorMode, Ledger, LockedAccountPolicy, MemoryLedger, NegativeBalancePolicy,
+        OverdraftPolicy, RejectReason, StatementPolicy, StoredTransaction, TotalsPeriod,
+        TransactionState, Warning, WithdrawalDisputes,
+    };
+    use crate::account::{Account, AccountStatus};
+    use crate::amount::Amount;
+    use crate::currency::Currency;
+    use crate::events::{Event, EventLog};
+    use crate::id::{ClientId, TxId};
+    use crate::input::{parse_timestamp, InputRecord, TransactionType};
+    use crate::journal::Journal;
+    use crate::tenant::Tenant;
+    use csv::StringRecord;
+    use proptest::prelude::*;
+
+    fn apply_rows(
+        engine: &mut Engine,
+        rows: Vec<Vec<&str>>,
+    ) -> Vec<Result<Option<Warning>, RejectReason>> {
+        rows.into_iter()
+            .map(|row| {
+                engine
+                    .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                    .map(|applied| applied.warning)
+            })
+            .collect()
+    }
+
+    fn state(engine: &Engine, tx: u64) -> TransactionState {
+        engine.ledger.lookup_tx(TxId::new(tx), None).unwrap().state
+    }
+
+    #[test]
+    fn test_valid_dispute() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+            ],
+        );
+        assert_eq!(state(&engine, 1), TransactionState::Disputed);
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::ZERO);
+        assert_eq!(balances[0].held, Amount::from_scaled(200_000));
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "1", "2", "5.00"],
+                vec!["dispute", "1", "1", "25.00"],
+                vec!["dispute", "1", "1", "0"],
+                vec!["dispute", "1", "1", "7.50"],
+                vec!["dispute", "1", "1", "7.50"],
+                vec!["dispute", "1", "2", "5.00"],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::InvalidDisputeAmount));
+        assert_eq!(results[3], Err(RejectReason::InvalidDisputeAmount));
+        assert_eq!(results[4], Ok(None));
+        assert_eq!(results[5], Err(RejectReason::AlreadyDisputed));
+        assert_eq!(results[6], Ok(None));
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(125_000));
+        assert_eq!(balances[0].held, Amount::from_scaled(125_000));
+
+        // Resolves and chargebacks settle only the disputed part, whatever
+        // amount they carry themselves.
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["resolve", "1", "2", "1.00"],
+                vec!["chargeback", "1", "1", ""],
+            ],
+        );
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(175_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert!(balances[0].locked);
+        let amounts: Vec<_> = engine.disputes().iter().map(|d| d.amount).collect();
+        assert_eq!(
+            amounts,
+            [Amount::from_scaled(75_000), Amount::from_scaled(50_000)]
+        );
+    }
+
+    #[test]
+    fn test_dispute_wrong_client() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "2", "2", "20.00"],
+                vec!["dispute", "2", "1", ""],
+                vec!["dispute", "1", "1", ""],
+                vec!["resolve", "2", "1", ""],
+                vec!["chargeback", "2", "1", ""],
+                vec!["dispute", "3", "1", ""],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::ClientMismatch));
+        assert_eq!(results[3], Ok(None));
+        assert_eq!(results[4], Err(RejectReason::ClientMismatch));
+        assert_eq!(results[5], Err(RejectReason::ClientMismatch));
+        // Not even a client without an account gets to see it.
+        assert_eq!(results[6], Err(RejectReason::ClientMismatch));
+        assert_eq!(state(&engine, 1), TransactionState::Disputed);
+        assert_eq!(engine.balances().len(), 2);
+    }
+
+    #[test]
+    fn test_dispute_before_deposit_is_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["dispute", "1", "1", ""],
+                vec!["deposit", "1", "1", "20.00"],
+            ],
+        );
+        assert_eq!(results[0], Err(RejectReason::UnknownTransaction));
+        assert_eq!(state(&engine, 1), TransactionState::Normal);
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(200_000));
+    }
+
+    #[test]
+    fn test_double_dispute_is_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["dispute", "1", "1", ""],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::AlreadyDisputed));
+        assert_eq!(engine.balances()[0].held, Amount::from_scaled(200_000));
+    }
+
+    #[test]
+    fn test_resolve_without_dispute_is_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["resolve", "1", "1", ""],
+            ],
+        );
+        assert_eq!(results[1], Err(RejectReason::NotDisputed));
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(200_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_resolve_releases_funds() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["resolve", "1", "1", ""],
+            ],
+        );
+        assert_eq!(state(&engine, 1), TransactionState::Resolved);
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(200_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_double_resolve_is_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["resolve", "1", "1", ""],
+                vec!["resolve", "1", "1", ""],
+            ],
+        );
+        assert_eq!(results[3], Err(RejectReason::DisputeClosed));
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(200_000));
+    }
+
+    #[test]
+    fn test_chargeback_after_resolve_is_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["resolve", "1", "1", ""],
+                vec!["chargeback", "1", "1", ""],
+            ],
+        );
+        assert_eq!(results[3], Err(RejectReason::DisputeClosed));
+        let balances = engine.balances();
+        assert_eq!(balances[0].total, Amount::from_scaled(200_000));
+        assert!(!balances[0].locked);
+    }
+
+    #[test]
+    fn test_redispute_after_resolve_is_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["resolve", "1", "1", ""],
+                vec!["dispute", "1", "1", ""],
+            ],
+        );
+        assert_eq!(results[3], Err(RejectReason::DisputeClosed));
+        assert_eq!(engine.balances()[0].held, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_chargeback_locks_account() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["chargeback", "1", "1", ""],
+            ],
+        );
+        assert_eq!(state(&engine, 1), TransactionState::ChargedBack);
+        let balances = engine.balances();
+        assert_eq!(balances[0].total, Amount::ZERO);
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert!(balances[0].locked);
+    }
+
+    #[test]
+    fn test_simulate_leaves_engine_alone() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "1", "2", "5.00"],
+                vec!["deposit", "2", "3", "7.50"],
+                vec!["deposit", "3", "4", "1.00"],
+            ],
+        );
+        let records = [
+            vec!["dispute", "1", "1", ""],
+            vec!["chargeback", "1", "1", ""],
+            vec!["dispute", "2", "3", ""],
+            vec!["dispute", "2", "99", ""],
+        ]
+        .into_iter()
+        .map(|row| make_input_record(&StringRecord::from(row)).unwrap());
+        let simulation = engine.simulate(records);
+
+        // Client 3 is not affected, so it is left out.
+        assert_eq!(simulation.balances.len(), 2);
+        assert_eq!(simulation.balances[0].client, ClientId::new(1));
+        assert_eq!(simulation.balances[0].total, Amount::from_scaled(50_000));
+        assert!(simulation.balances[0].locked);
+        assert_eq!(simulation.balances[1].held, Amount::from_scaled(75_000));
+        assert_eq!(simulation.rejected.len(), 1);
+        assert_eq!(
+            simulation.rejected[0].reason,
+            RejectReason::UnknownTransaction
+        );
+
+        // Nothing happened for real.
+        assert_eq!(state(&engine, 1), TransactionState::Normal);
+        let mut balances = engine.balances();
+        balances.sort_by_key(|b| b.client);
+        assert_eq!(balances[0].total, Amount::from_scaled(250_000));
+        assert!(!balances[0].locked);
+        assert_eq!(balances[1].held, Amount::ZERO);
+        assert!(apply_rows(&mut engine, vec![vec!["dispute", "1", "1", ""]])[0].is_ok());
+    }
+
+    #[test]
+    fn test_every_ordering_of_dispute_steps() {
+        // Tries every sequence of up to five dispute, resolve and chargeback
+        // records against a single deposit, and checks the engine against
+        // the state machine: only a dispute opens a dispute, only an open
+        // dispute can be resolved or charged back, and once closed nothing
+        // changes anymore.
+        let steps = ["dispute", "resolve", "chargeback"];
+        for len in 1..=5u32 {
+            for n in 0..3usize.pow(len) {
+                let sequence: Vec<&str> = (0..len).map(|i| steps[n / 3usize.pow(i) % 3]).collect();
+
+                let mut engine = Engine::with_config(EngineConfig {
+                    locked_accounts: LockedAccountPolicy::AllowAll,
+                    ..EngineConfig::default()
+                });
+                apply_rows(&mut engine, vec![vec!["deposit", "1", "1", "10.00"]]);
+                let mut expected = TransactionState::Normal;
+                for step in &sequence {
+                    let result = apply_rows(&mut engine, vec![vec![step, "1", "1", ""]]).remove(0);
+                    let next = match (expected, *step) {
+                        (TransactionState::Normal, "dispute") => Ok(TransactionState::Disputed),
+                        (TransactionState::Disputed, "resolve") => Ok(TransactionState::Resolved),
+                        (TransactionState::Disputed, "chargeback") => {
+                            Ok(TransactionState::ChargedBack)
+                        }
+                        (TransactionState::Disputed, _) => Err(RejectReason::AlreadyDisputed),
+                        (TransactionState::Normal, _) => Err(RejectReason::NotDisputed),
+                        _ => Err(RejectReason::DisputeClosed),
+                    };
+                    assert_eq!(result.map(|_| ()), next.map(|_| ()), "{:?}", sequence);
+                    expected = next.unwrap_or(expected);
+                }
+
+                let balance = &engine.balances()[0];
+                let (available, held, total) = match expected {
+                    TransactionState::Normal | TransactionState::Resolved => (100_000, 0, 100_000),
+                    TransactionState::Disputed => (0, 100_000, 100_000),
+                    TransactionState::ChargedBack => (0, 0, 0),
+                };
+                assert_eq!(state(&engine, 1), expected, "{:?}", sequence);
+                assert_eq!(balance.available, Amount::from_scaled(available));
+                assert_eq!(balance.held, Amount::from_scaled(held));
+                assert_eq!(balance.total, Amount::from_scaled(total));
+                assert_eq!(balance.locked, expected == TransactionState::ChargedBack);
+            }
+        }
+    }
+
+    #[test]
+    fn test_statement() {
+        let mut engine = Engine::with_config(EngineConfig {
+            statements: StatementPolicy::Clients(vec![ClientId::new(1)]),
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00"],
+                vec!["deposit", "2", "2", "5.00"],
+                vec!["withdrawal", "1", "3", "20.00"],
+                vec!["withdrawal", "1", "4", "2.50"],
+                vec!["dispute", "1", "1", ""],
+            ],
+        );
+
+        // The rejected withdrawal and the other client are left out.
+        let statement = engine.statement(ClientId::new(1), None);
+        let lines: Vec<_> = statement
+            .iter()
+            .map(|l| (l.tx, l.amount, l.available, l.held, l.total))
+            .collect();
+        let amount = Amount::from_scaled;
+        assert_eq!(
+            lines,
+            vec![
+                (
+                    TxId::new(1),
+                    amount(100_000),
+                    amount(100_000),
+                    amount(0),
+                    amount(100_000)
+                ),
+                (
+                    TxId::new(4),
+                    amount(25_000),
+                    amount(75_000),
+                    amount(0),
+                    amount(75_000)
+                ),
+                (
+                    TxId::new(1),
+                    amount(100_000),
+                    amount(-25_000),
+                    amount(100_000),
+                    amount(75_000)
+                ),
+            ]
+        );
+        assert!(engine.statement(ClientId::new(2), None).is_empty());
+    }
+
+    #[test]
+    fn test_adjustments() {
+        let mut engine = Engine::with_config(EngineConfig {
+            statements: StatementPolicy::All,
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["credit_adjustment", "1", "1", "1.0", "", "", "BANK_ERROR"],
+                vec!["deposit", "1", "2", "10.00"],
+                vec!["credit_adjustment", "1", "3", "2.5", "", "", "bank_error"],
+                vec![
+                    "debit_adjustment",
+                    "1",
+                    "4",
+                    "20.0",
+                    "",
+                    "",
+                    "FX_CORRECTION",
+                ],
+                vec!["debit_adjustment", "1", "5", "0.5", "", "", "FX_CORRECTION"],
+                vec!["dispute", "1", "3", ""],
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Err(RejectReason::UnknownClient),
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::InsufficientFunds),
+                Ok(None),
+                Err(RejectReason::UnknownTransaction),
+            ]
+        );
+        assert_eq!(engine.balances()[0].total, Amount::from_scaled(120_000));
+
+        // Adjustments show up on the statement along with their reason.
+        let statement = engine.statement(ClientId::new(1), None);
+        let lines: Vec<_> = statement
+            .iter()
+            .map(|l| (l.r#type, l.amount, l.reason.map(|r| r.to_string())))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (TransactionType::Deposit, Amount::from_scaled(100_000), None),
+                (
+                    TransactionType::CreditAdjustment,
+                    Amount::from_scaled(25_000),
+                    Some("BANK_ERROR".to_string())
+                ),
+                (
+                    TransactionType::DebitAdjustment,
+                    Amount::from_scaled(5_000),
+                    Some("FX_CORRECTION".to_string())
+                ),
+            ]
+        );
+
+        // A record made up without a reason is still refused.
+        let mut record = make_input_record(&StringRecord::from(vec![
+            "credit_adjustment",
+            "1",
+            "6",
+            "1.0",
+            "",
+            "",
+            "BANK_ERROR",
+        ]))
+        .unwrap();
+        record.reason = None;
+        assert_eq!(
+            engine.apply(record).unwrap_err(),
+            RejectReason::MissingReason
+        );
+    }
+
+    #[test]
+    fn test_validators() {
+        let mut engine = Engine::with_config(EngineConfig {
+            rules: "clients=1..10,amount:withdrawal=..100".parse().unwrap(),
+            ..EngineConfig::default()
+        });
+        // Deployments can add rules of their own, which run after those of
+        // the configuration.
+        engine.add_validator(|record: &InputRecord| match record.tx.as_u64() {
+            Some(tx) if tx >= 1000 => Err(RejectReason::TypeNotAllowed),
+            _ => Ok(()),
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "500"],
+                vec!["withdrawal", "1", "2", "150"],
+                vec!["deposit", "11", "3", "1"],
+                vec!["deposit", "1", "1000", "1"],
+                vec!["deposit", "11", "1001", "1"],
+                vec!["withdrawal", "1", "4", "100"],
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Ok(None),
+                Err(RejectReason::AmountOutOfRange),
+                Err(RejectReason::ClientOutOfRange),
+                Err(RejectReason::TypeNotAllowed),
+                Err(RejectReason::ClientOutOfRange),
+                Ok(None),
+            ]
+        );
+        assert_eq!(engine.balances().len(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut engine = Engine::with_config(EngineConfig {
+            limits: "amount=50,daily=80,transactions=4".parse().unwrap(),
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "60", "2024-01-15T09:00:00Z"],
+                vec!["deposit", "1", "2", "50", "2024-01-15T09:00:00Z"],
+                vec!["withdrawal", "1", "3", "30", "2024-01-15T10:00:00Z"],
+                vec!["withdrawal", "1", "4", "0.5", "2024-01-15T11:00:00Z"],
+                // A new day, or no day at all, starts afresh.
+                vec!["withdrawal", "1", "5", "15", "2024-01-16T00:00:00Z"],
+                vec!["deposit", "1", "6", "40"],
+                vec!["deposit", "1", "7", "1"],
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Err(RejectReason::AmountOverLimit),
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::DailyLimitExceeded),
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::TransactionLimitReached),
+            ]
+        );
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(450_000));
+
+        // A strict batch that fails gives back what it moved that day.
+        let mut engine = Engine::with_config(EngineConfig {
+            limits: "daily=10".parse().unwrap(),
+            error_mode: ErrorMode::Strict,
+            ..EngineConfig::default()
+        });
+        let at = |row: Vec<&str>| {
+            let mut row = row;
+            row.push("2024-01-15T09:00:00Z");
+            make_input_record(&StringRecord::from(row)).unwrap()
+        };
+        let failing = [
+            at(vec!["deposit", "1", "1", "6"]),
+            at(vec!["withdrawal", "1", "2", "9"]),
+        ];
+        assert!(engine.apply_batch(&failing).is_err());
+        assert!(engine.apply(at(vec!["deposit", "1", "3", "10"])).is_ok());
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut engine = Engine::with_config(EngineConfig {
+            statements: StatementPolicy::All,
+            fees: "withdrawal=1,deposit=1%,monthly=2".parse().unwrap(),
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "100", "2024-01-15T00:00:00Z"],
+                vec!["withdrawal", "1", "2", "10", "2024-01-20T00:00:00Z"],
+                // Only 86.0 is left, so this cannot cover the fee.
+                vec!["withdrawal", "1", "3", "85.5"],
+                vec!["withdrawal", "1", "4", "5", "2024-03-01T00:00:00Z"],
+                vec!["fee", "1", "5", "0.5"],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::InsufficientFunds));
+
+        let lines: Vec<_> = engine
+            .statement(ClientId::new(1), None)
+            .iter()
+            .map(|l| {
+                (
+                    l.tx,
+                    l.r#type,
+                    l.amount.to_string(),
+                    l.available.to_string(),
+                )
+            })
+            .collect();
+        let line = |tx, r#type, amount: &str, available: &str| {
+            (
+                TxId::new(tx),
+                r#type,
+                amount.to_string(),
+                available.to_string(),
+            )
+        };
+        assert_eq!(
+            lines,
+            vec![
+                line(1, TransactionType::Deposit, "100.0", "100.0"),
+                // 1% of the deposit and the fee for January.
+                line(1, TransactionType::Fee, "3.0", "97.0"),
+                line(2, TransactionType::Withdrawal, "10.0", "87.0"),
+                line(2, TransactionType::Fee, "1.0", "86.0"),
+                // February and March are charged for together.
+                line(4, TransactionType::Withdrawal, "5.0", "81.0"),
+                line(4, TransactionType::Fee, "5.0", "76.0"),
+                line(5, TransactionType::Fee, "0.5", "75.5"),
+            ]
+        );
+        assert_eq!(engine.volumes()[0].fees, "9.5".parse().unwrap());
+    }
+
+    #[test]
+    fn test_disputes() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "2", "1", "10.00"],
+                vec!["deposit", "1", "2", "5.00"],
+                vec!["deposit", "1", "3", "1.00"],
+                vec!["dispute", "2", "1", "", "2024-06-01T00:00:00Z"],
+                vec!["dispute", "1", "2", ""],
+                vec!["chargeback", "1", "2", ""],
+            ],
+        );
+
+        let disputes = engine.disputes();
+        let listed: Vec<_> = disputes
+            .iter()
+            .map(|d| (d.client, d.tx, d.status))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (
+                    ClientId::new(1),
+                    TxId::new(2),
+                    TransactionState::ChargedBack
+                ),
+                (ClientId::new(2), TxId::new(1), TransactionState::Disputed),
+            ]
+        );
+        let now = parse_timestamp("2024-06-11T12:00:00Z").unwrap();
+        assert_eq!(disputes[0].age(now), None);
+        assert_eq!(disputes[1].age(now).unwrap().num_days(), 10);
+    }
+
+    #[test]
+    fn test_dispute_window_in_days() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_window: DisputeWindow::Days(30),
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00", "2024-06-01T00:00:00Z"],
+                vec!["deposit", "1", "2", "5.00", "2024-06-01T00:00:00Z"],
+                vec!["deposit", "1", "3", "1.00"],
+                vec!["dispute", "1", "1", "", "2024-07-01T00:00:00Z"],
+                vec!["dispute", "1", "2", "", "2024-07-01T00:00:01Z"],
+                vec!["dispute", "1", "3", "", "2025-01-01T00:00:00Z"],
+            ],
+        );
+        assert_eq!(results[3], Ok(None));
+        assert_eq!(results[4], Err(RejectReason::DisputeWindowClosed));
+        // Without a timestamp, there is no telling how old it is.
+        assert_eq!(results[5], Ok(None));
+        assert_eq!(state(&engine, 2), TransactionState::Normal);
+    }
+
+    #[test]
+    fn test_dispute_window_in_transactions() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_window: DisputeWindow::Transactions(1),
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00"],
+                vec!["deposit", "1", "2", "5.00"],
+                vec!["deposit", "2", "3", "1.00"],
+                vec!["withdrawal", "1", "4", "1.00"],
+                vec!["dispute", "1", "2", ""],
+                vec!["dispute", "1", "1", ""],
+            ],
+        );
+        assert_eq!(results[4], Ok(None));
+        assert_eq!(results[5], Err(RejectReason::DisputeWindowClosed));
+    }
+
+    #[test]
+    fn test_disputes_expire() {
+        let config = EngineConfig {
+            dispute_expiry: Some(DisputeExpiry {
+                days: 10,
+                outcome: DisputeOutcome::Chargeback,
+            }),
+            statements: StatementPolicy::All,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00", "2024-06-01T00:00:00Z"],
+                vec!["deposit", "2", "2", "5.00", "2024-06-01T00:00:00Z"],
+                vec!["dispute", "1", "1", "", "2024-06-02T00:00:00Z"],
+                vec!["dispute", "2", "2", "", "2024-06-05T00:00:00Z"],
+                vec!["deposit", "1", "3", "1.00", "2024-06-12T00:00:00Z"],
+            ],
+        );
+        // The deposit went to an account locked by the expired dispute.
+        assert_eq!(results[4], Err(RejectReason::AccountLocked));
+        assert_eq!(state(&engine, 1), TransactionState::ChargedBack);
+        assert!(engine
+            .account(ClientId::new(1), None, None)
+            .unwrap()
+            .is_locked());
+        let line = engine.statement(ClientId::new(1), None).last().unwrap();
+        assert_eq!(
+            (line.r#type, line.amount, line.total),
+            (
+                TransactionType::Chargeback,
+                Amount::from_scaled(100_000),
+                Amount::ZERO
+            )
+        );
+
+        // The other one is still open, and still is after a restore.
+        let mut engine = snapshot_round_trip(&engine, config);
+        let now = parse_timestamp("2024-06-14T23:59:59Z").unwrap();
+        assert_eq!(engine.expire_disputes(now), 0);
+        let now = parse_timestamp("2024-06-15T00:00:00Z").unwrap();
+        assert_eq!(engine.expire_disputes(now), 1);
+        assert_eq!(state(&engine, 2), TransactionState::ChargedBack);
+        assert_eq!(engine.open_disputes(), 0);
+    }
+
+    #[test]
+    fn test_failed_expiry_is_retried() {
+        let expiry = Some(DisputeExpiry {
+            days: 10,
+            outcome: DisputeOutcome::Resolve,
+        });
+        let mut engine = Engine::with_config(EngineConfig {
+            disputes: DisputePolicy::DepositsAndWithdrawals,
+            dispute_expiry: expiry,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00", "2024-06-01T00:00:00Z"],
+                vec!["withdrawal", "1", "2", "5.00", "2024-06-01T00:00:00Z"],
+                vec!["dispute", "1", "2", "", "2024-06-02T00:00:00Z"],
+            ],
+        );
+
+        // Restored without withdrawal disputes, the dispute cannot be closed.
+        let config = EngineConfig {
+            dispute_expiry: expiry,
+            ..EngineConfig::default()
+        };
+        let mut engine = snapshot_round_trip(&engine, config);
+        let now = parse_timestamp("2024-06-20T00:00:00Z").unwrap();
+        let log = logged(|| {
+            assert_eq!(engine.expire_disputes(now), 0);
+            // Records of other accounts leave it be, rather than try it
+            // again and again.
+            for tx in 3..100 {
+                let tx = tx.to_string();
+                let row = vec!["deposit", "2", &tx, "1.00", "2024-06-21T00:00:00Z"];
+                engine
+                    .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                    .unwrap();
+            }
+        });
+        assert_eq!(
+            log.matches("expired dispute could not be closed").count(),
+            1
+        );
+        assert_eq!(state(&engine, 2), TransactionState::Disputed);
+        assert_eq!(engine.open_disputes(), 1);
+        assert!(engine.expiring.is_empty());
+
+        // It is tried again once its account changes, and closed once it
+        // can be.
+        engine.config.disputes = DisputePolicy::DepositsAndWithdrawals;
+        assert_eq!(engine.expire_disputes(now), 0);
+        apply_rows(
+            &mut engine,
+            vec![vec!["deposit", "1", "100", "1.00", "2024-06-21T00:00:00Z"]],
+        );
+        assert_eq!(engine.expire_disputes(now), 1);
+        assert_eq!(state(&engine, 2), TransactionState::Resolved);
+        assert_eq!(engine.open_disputes(), 0);
+        assert!(engine.unexpirable.is_empty());
+    }
+
+    /// What is logged while `f` runs.
+    fn logged(f: impl FnOnce()) -> String {
+        #[derive(Clone, Default)]
+        struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let log = buffer.0.lock().unwrap();
+        String::from_utf8_lossy(&log).into_owned()
+    }
+
+    #[test]
+    fn test_disputes_escalate() {
+        let config = EngineConfig {
+            dispute_escalation: "30d,60d,90d".parse().unwrap(),
+            dispute_expiry: Some(DisputeExpiry {
+                days: 90,
+                outcome: DisputeOutcome::Resolve,
+            }),
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config);
+        engine.set_event_log(EventLog::new());
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00", "2024-01-01T00:00:00Z"],
+                vec!["deposit", "2", "2", "5.00", "2024-01-01T00:00:00Z"],
+                vec!["deposit", "3", "3", "5.00"],
+                vec!["dispute", "1", "1", "", "2024-01-02T00:00:00Z"],
+                vec!["dispute", "2", "2", "", "2024-01-20T00:00:00Z"],
+                vec!["dispute", "3", "3", ""],
+                // 30 days for the first dispute, right at it.
+                vec!["deposit", "1", "4", "1.00", "2024-02-01T00:00:00Z"],
+                // 60 days for the first one, and 30 for the second.
+                vec!["deposit", "1", "5", "1.00", "2024-03-10T00:00:00Z"],
+                vec!["resolve", "2", "2", "", "2024-03-11T00:00:00Z"],
+            ],
+        );
+        let escalated = |engine: &Engine| -> Vec<(u64, u32)> {
+            engine
+                .escalations()
+                .iter()
+                .map(|e| (e.tx.as_u64().unwrap(), e.days))
+                .collect()
+        };
+        assert_eq!(escalated(&engine), [(1, 30), (1, 60), (2, 30)]);
+        let first = engine.escalations()[0];
+        assert_eq!(first.amount, Amount::from_scaled(100_000));
+        assert_eq!(
+            first.escalated_at,
+            parse_timestamp("2024-02-01T00:00:00Z").unwrap()
+        );
+
+        // Time going back does not escalate anything again, and the dispute
+        // expires at 90 days rather than being escalated for it, while the
+        // resolved one is left alone.
+        let now = parse_timestamp("2024-03-01T00:00:00Z").unwrap();
+        assert_eq!(engine.escalate_disputes(now), 0);
+        let now = parse_timestamp("2024-12-31T00:00:00Z").unwrap();
+        assert_eq!(engine.escalate_disputes(now), 0);
+        assert_eq!(engine.escalations().len(), 3);
+        let days: Vec<u32> = engine
+            .event_log()
+            .unwrap()
+            .events()
+            .iter()
+            .filter_map(|e| match e.event {
+                Event::DisputeEscalated { days, .. } => Some(days),
+                _ => None,
+            })
+            .collect();
+        // The second dispute was 30 days old before the first was 60.
+        assert_eq!(days, [30, 30, 60]);
+    }
+
+    #[test]
+    fn test_totals() {
+        let config = EngineConfig {
+            dispute_expiry: Some(DisputeExpiry {
+                days: 10,
+                outcome: DisputeOutcome::Chargeback,
+            }),
+            totals: Some(TotalsPeriod {
+                from: "2024-01-01".parse().ok(),
+                to: "2024-12-31".parse().ok(),
+            }),
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config);
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.0", "2023-12-31T23:59:59Z"],
+                vec!["deposit", "1", "2", "20.0", "2024-01-01T00:00:00Z"],
+                vec!["withdrawal", "1", "3", "5.0", "2024-12-31T23:59:59Z"],
+                vec!["withdrawal", "1", "4", "1.0"],
+                vec!["dispute", "1", "1", "", "2024-03-01T00:00:00Z"],
+                vec!["chargeback", "1", "1", "", "2024-03-02T00:00:00Z"],
+                vec!["deposit", "2", "5", "3.0", "2024-12-01T00:00:00Z"],
+                vec!["dispute", "2", "5", "", "2024-12-25T00:00:00Z"],
+                vec!["deposit", "3", "6", "1.0", "2025-01-10T00:00:00Z"],
+                vec!["deposit", "4", "7", "1.0", "2025-01-01T00:00:00Z"],
+            ],
+        );
+        let totals: Vec<_> = engine
+            .totals()
+            .iter()
+            .map(|t| (t.client, t.deposited, t.withdrawn, t.charged_back))
+            .collect();
+        // The dispute of client 2 expired in the new year, after the period.
+        assert_eq!(
+            totals,
+            [
+                (
+                    ClientId::new(1),
+                    Amount::from_scaled(200_000),
+                    Amount::from_scaled(50_000),
+                    Amount::from_scaled(100_000)
+                ),
+                (
+                    ClientId::new(2),
+                    Amount::from_scaled(30_000),
+                    Amount::ZERO,
+                    Amount::ZERO
+                ),
+            ]
+        );
+        assert!(Engine::new().totals().is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut engine = Engine::with_config(EngineConfig {
+            history: true,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.0", "2024-01-01T08:00:00Z"],
+                vec!["withdrawal", "1", "2", "4.0", "2024-01-01T23:59:59Z"],
+                vec!["deposit", "1", "3", "1.0"],
+                vec!["deposit", "2", "4", "5.0", "2024-01-02T12:00:00Z"],
+                vec!["deposit", "1", "5", "2.0", "2024-01-03T00:00:00Z"],
+                vec!["dispute", "2", "4", "", "2024-01-04T09:00:00Z"],
+                vec!["withdrawal", "1", "6", "100.0", "2024-01-04T10:00:00Z"],
+            ],
+        );
+        let history: Vec<_> = engine
+            .history()
+            .iter()
+            .map(|d| (d.client.get(), d.date.to_string(), d.available, d.held))
+            .collect();
+        let amount = |n: i64| Amount::from_scaled(n * 10_000);
+        // The rejected withdrawal leaves the balances of the day before.
+        assert_eq!(
+            history,
+            [
+                (1, "2024-01-01".to_string(), amount(6), Amount::ZERO),
+                (1, "2024-01-02".to_string(), amount(6), Amount::ZERO),
+                (1, "2024-01-03".to_string(), amount(9), Amount::ZERO),
+                (1, "2024-01-04".to_string(), amount(9), Amount::ZERO),
+                (2, "2024-01-02".to_string(), amount(5), Amount::ZERO),
+                (2, "2024-01-03".to_string(), amount(5), Amount::ZERO),
+                (2, "2024-01-04".to_string(), Amount::ZERO, amount(5)),
+            ]
+        );
+
+        let shards = engine.split(3);
+        let mut engine = Engine::new();
+        for shard in shards {
+            engine.merge(shard);
+        }
+        assert_eq!(engine.history().len(), 7);
+        assert!(Engine::new().history().is_empty());
+    }
+
+    #[test]
+    fn test_parse_dispute_window() {
+        assert_eq!("unlimited".parse(), Ok(DisputeWindow::Unlimited));
+        assert_eq!("30D".parse(), Ok(DisputeWindow::Days(30)));
+        assert_eq!("5tx".parse(), Ok(DisputeWindow::Transactions(5)));
+        assert!("-1d".parse::<DisputeWindow>().is_err());
+        assert_eq!(
+            "1d:resolve".parse(),
+            Ok(DisputeExpiry {
+                days: 1,
+                outcome: DisputeOutcome::Resolve
+            })
+        );
+        assert!("resolve".parse::<DisputeExpiry>().is_err());
+        assert_eq!(
+            "90d, 30D,60d,30d"
+                .parse::<DisputeEscalation>()
+                .unwrap()
+                .days,
+            [30, 60, 90]
+        );
+        assert!("30d,".parse::<DisputeEscalation>().is_err());
+        assert!("0d".parse::<DisputeEscalation>().is_err());
+    }
+
+    #[test]
+    fn test_balance_checks() {
+        let mut engine = Engine::with_config(EngineConfig {
+            statements: StatementPolicy::All,
+            ..EngineConfig::default()
+        });
+        let mismatch = |expected, actual| {
+            Ok(Some(Warning::BalanceMismatch {
+                expected: Amount::from_scaled(expected),
+                actual: Amount::from_scaled(actual),
+            }))
+        };
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.0"],
+                vec!["withdrawal", "1", "2", "2.5"],
+                vec!["balance_check", "1", "3", "7.5"],
+                vec!["balance_check", "1", "4", "10.0"],
+                // Checks have no transaction ID of their own to collide.
+                vec!["balance_check", "1", "1", "7.5"],
+                // A client that was never seen holds nothing.
+                vec!["balance_check", "2", "5", "3.0"],
+                vec!["dispute", "1", "1", ""],
+                vec!["chargeback", "1", "1", ""],
+                vec!["balance_check", "1", "6", "-2.5"],
+            ],
+        );
+        assert_eq!(results[2], Ok(None));
+        assert_eq!(results[3], mismatch(100_000, 75_000));
+        assert_eq!(results[4], Ok(None));
+        assert_eq!(results[5], mismatch(30_000, 0));
+        // Checking a locked account moves no money, so it is allowed.
+        assert_eq!(results[8], Ok(None));
+
+        let checks = engine.balance_checks();
+        assert_eq!(checks.len(), 5);
+        assert_eq!(checks[1].difference(), Amount::from_scaled(-25_000));
+        assert_eq!(checks[4].client, ClientId::new(2));
+        assert!(!checks[4].matches());
+        // Nothing else changed: no account for client 2, no statement lines.
+        assert_eq!(engine.balances().len(), 1);
+        assert_eq!(engine.statement(ClientId::new(1), None).len(), 4);
+        assert!(engine.statement(ClientId::new(2), None).is_empty());
+    }
+
+    #[test]
+    fn test_failed_withdrawal_is_not_stored() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["withdrawal", "1", "2", "50.00"],
+            ],
+        );
+        assert_eq!(results[1], Err(RejectReason::InsufficientFunds));
+        assert!(engine.ledger.lookup_tx(TxId::new(2), None).is_none());
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(200_000));
+    }
+
+    #[test]
+    fn test_overdraft_limit() {
+        let mut engine = Engine::with_config(EngineConfig {
+            overdraft: OverdraftPolicy::AllowUpToLimit(Amount::from_scaled(100_000)),
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["withdrawal", "1", "2", "25.00"],
+                vec!["withdrawal", "1", "3", "5.01"],
+                vec!["withdrawal", "1", "4", "5.00"],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::InsufficientFunds));
+        assert!(results[3].is_ok());
+        assert_eq!(
+            engine.balances()[0].available,
+            Amount::from_scaled(-100_000)
+        );
+    }
+
+    #[test]
+    fn test_negative_balances() {
+        let rows = || {
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["withdrawal", "1", "2", "15.00"],
+                // Takes the available funds to -15.
+                vec!["dispute", "1", "1", ""],
+                vec!["deposit", "1", "3", "5.00"],
+                // Leaves the held funds negative on purpose.
+                vec!["dispute", "1", "2", ""],
+            ]
+        };
+        let config = EngineConfig {
+            disputes: DisputePolicy::DepositsAndWithdrawals,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        let results = apply_rows(&mut engine, rows());
+        let warning = Warning::NegativeBalance {
+            available: Amount::from_scaled(-150_000),
+            held: Amount::from_scaled(200_000),
+        };
+        assert_eq!(results[2], Ok(Some(warning)));
+        assert_eq!(results[3], Ok(None));
+        assert_eq!(results[4], Ok(None));
+        let negative = engine.negative_balances();
+        assert_eq!(negative.len(), 1);
+        assert_eq!(negative[0].tx, TxId::new(1));
+        assert_eq!(negative[0].r#type, TransactionType::Dispute);
+        assert!(negative[0].applied);
+
+        for policy in [NegativeBalancePolicy::Reject, NegativeBalancePolicy::Abort] {
+            let mut engine = Engine::with_config(EngineConfig {
+                negative_balances: policy,
+                ..config.clone()
+            });
+            let results = apply_rows(&mut engine, rows());
+            assert_eq!(results[2], Err(RejectReason::NegativeBalance));
+            assert_eq!(state(&engine, 1), TransactionState::Normal);
+            assert!(results[3..].iter().all(|r| r.is_ok()));
+            assert_eq!(engine.balances()[0].available, Amount::from_scaled(250_000));
+            assert!(!engine.negative_balances()[0].applied);
+        }
+
+        // What the overdraft allows is applied whatever the policy, but
+        // kept track of all the same.
+        let mut engine = Engine::with_config(EngineConfig {
+            overdraft: OverdraftPolicy::AllowNegative,
+            negative_balances: NegativeBalancePolicy::Reject,
+            ..config
+        });
+        assert!(apply_rows(&mut engine, rows()).iter().all(|r| r.is_ok()));
+        assert_eq!(engine.negative_balances().len(), 1);
+        assert!(engine.negative_balances()[0].applied);
+    }
+
+    /// Applies a debit of 15 to an account holding 10, with an overdraft of
+    /// up to 10, and checks it is kept track of as a negative balance.
+    fn assert_negative_debit(row: Vec<&str>, r#type: TransactionType) {
+        let mut engine = Engine::with_config(EngineConfig {
+            overdraft: OverdraftPolicy::AllowUpToLimit(Amount::from_scaled(100_000)),
+            negative_balances: NegativeBalancePolicy::Reject,
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(&mut engine, vec![vec!["deposit", "1", "1", "10.00"], row]);
+        // Within the overdraft, the debit goes through, with a warning.
+        let warning = Warning::NegativeBalance {
+            available: Amount::from_scaled(-50_000),
+            held: Amount::ZERO,
+        };
+        assert_eq!(results[1], Ok(Some(warning)));
+        let negative = engine.negative_balances();
+        assert_eq!(negative.len(), 1);
+        assert_eq!(negative[0].r#type, r#type);
+        assert_eq!(negative[0].tx, TxId::new(2));
+        assert!(negative[0].applied);
+
+        // A deposit leaves the funds less negative, which is fine.
+        let results = apply_rows(&mut engine, vec![vec!["deposit", "1", "3", "1.00"]]);
+        assert_eq!(results[0], Ok(None));
+        assert_eq!(engine.negative_balances().len(), 1);
+    }
+
+    #[test]
+    fn test_negative_balance_of_withdrawal() {
+        assert_negative_debit(
+            vec!["withdrawal", "1", "2", "15.00"],
+            TransactionType::Withdrawal,
+        );
+    }
+
+    #[test]
+    fn test_negative_balance_of_fee() {
+        assert_negative_debit(vec!["fee", "1", "2", "15.00"], TransactionType::Fee);
+    }
+
+    #[test]
+    fn test_negative_balance_of_debit_adjustment() {
+        assert_negative_debit(
+            vec!["debit_adjustment", "1", "2", "15.00", "", "", "FIX"],
+            TransactionType::DebitAdjustment,
+        );
+    }
+
+    #[test]
+    fn test_pending_disputes() {
+        let rows = || {
+            vec![
+                vec!["dispute", "1", "1", "", "2024-01-01T00:00:00Z"],
+                vec!["dispute", "1", "1", "", "2024-01-01T00:00:00Z"],
+                vec!["dispute", "2", "2", "", "2024-01-01T00:00:00Z"],
+                vec!["dispute", "3", "3", "", "2024-01-01T00:00:00Z"],
+                vec!["deposit", "1", "1", "10.0", "2024-01-02T00:00:00Z"],
+                vec!["deposit", "4", "2", "5.0", "2024-01-02T00:00:00Z"],
+            ]
+        };
+        let mut engine = Engine::new();
+        let results = apply_rows(&mut engine, rows());
+        assert_eq!(results[0], Err(RejectReason::UnknownTransaction));
+        assert!(engine.pending_disputes().is_empty());
+
+        let mut engine = Engine::with_config(EngineConfig {
+            pending_disputes: true,
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(&mut engine, rows());
+        assert_eq!(results[0], Ok(Some(Warning::PendingDispute)));
+        assert_eq!(results[1], Err(RejectReason::UnknownTransaction));
+        assert_eq!(results[4], Ok(None));
+        // Disputed as of the deposit, which it could not be before.
+        assert_eq!(state(&engine, 1), TransactionState::Disputed);
+        let transaction = engine.lookup_tx((TxId::new(1), None)).unwrap();
+        assert_eq!(
+            transaction.disputed_at,
+            Some(parse_timestamp("2024-01-02T00:00:00Z").unwrap())
+        );
+        let account = engine.client_balances(ClientId::new(1))[0];
+        assert_eq!(account.held, Amount::from_scaled(100_000));
+        // The dispute of client 2 matched another client's deposit, which it
+        // could not dispute, so it is dropped.
+        assert_eq!(state(&engine, 2), TransactionState::Normal);
+        let pending = engine.pending_disputes();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(
+            (pending[0].client, pending[0].tx),
+            (ClientId::new(3), TxId::new(3))
+        );
+    }
+
+    #[test]
+    fn test_as_of_cutoff() {
+        let mut engine = Engine::with_config(EngineConfig {
+            as_of: Some(parse_timestamp("2024-06-30T23:59:59Z").unwrap()),
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00", "2024-06-30T23:59:59Z"],
+                vec!["deposit", "1", "2", "5.00", "2024-07-01T01:59:59+02:00"],
+                vec!["deposit", "1", "3", "1.00", "2024-07-01T00:00:00Z"],
+                vec!["deposit", "1", "4", "1.00", ""],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::AfterCutoff));
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(260_000));
+    }
+
+    #[test]
+    fn test_accounts_per_currency() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00", "", "USD"],
+                vec!["deposit", "1", "2", "5.00", "", "eur"],
+                vec!["withdrawal", "1", "3", "10.00", "", "EUR"],
+                vec!["dispute", "1", "1", "", "", "EUR"],
+                vec!["dispute", "1", "1", "", "", ""],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::InsufficientFunds));
+        assert_eq!(results[3], Err(RejectReason::CurrencyMismatch));
+        assert!(results[4].is_ok());
+
+        let mut balances = engine.balances();
+        balances.sort_by_key(|b| b.currency);
+        let balances: Vec<_> = balances
+            .iter()
+            .map(|b| (b.currency.unwrap().to_string(), b.available, b.held))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![
+                ("EUR".to_string(), Amount::from_scaled(50_000), Amount::ZERO),
+                (
+                    "USD".to_string(),
+                    Amount::ZERO,
+                    Amount::from_scaled(200_000)
+                ),
+            ]
+        );
+
+        let restored = snapshot_round_trip(&engine, EngineConfig::default());
+        assert_eq!(
+            MemoryLedger::copy_of(restored.ledger()),
+            MemoryLedger::copy_of(engine.ledger())
+        );
+    }
+
+    #[test]
+    fn test_journal_skips_replays() {
+        let rows = || {
+            vec![
+                vec!["deposit", "1", "1", "10.00"],
+                vec!["withdrawal", "1", "2", "50.00"],
+                vec!["dispute", "1", "1", ""],
+            ]
+        };
+        let mut engine = Engine::new();
+        engine.set_journal(Journal::new());
+        let results = apply_rows(&mut engine, rows());
+        assert_eq!(results[1], Err(RejectReason::InsufficientFunds));
+
+        let mut buf = Vec::new();
+        engine.journal().unwrap().save_to_writer(&mut buf).unwrap();
+        let mut engine = Engine::new();
+        engine.set_journal(Journal::load_from_reader(buf.as_slice()).unwrap());
+        let results = apply_rows(&mut engine, rows());
+        assert_eq!(results[0], Err(RejectReason::Replayed));
+        // The rejected withdrawal was never journaled, so it is tried again.
+        assert_eq!(results[1], Err(RejectReason::UnknownClient));
+        assert_eq!(results[2], Err(RejectReason::Replayed));
+        assert!(engine.balances().is_empty());
+    }
+
+    fn batch(rows: Vec<Vec<&str>>) -> Vec<InputRecord> {
+        rows.into_iter()
+            .map(|row| make_input_record(&StringRecord::from(row)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let config = EngineConfig {
+            statements: StatementPolicy::All,
+            dispute_expiry: Some(DisputeExpiry {
+                days: 10,
+                outcome: DisputeOutcome::Chargeback,
+            }),
+            totals: Some(TotalsPeriod::default()),
+            history: true,
+            error_mode: ErrorMode::Strict,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config);
+        engine.set_journal(Journal::new());
+        engine.set_event_log(EventLog::new());
+        let result = engine.apply_batch(&batch(vec![
+            vec!["deposit", "1", "1", "10.0", "2024-01-01T00:00:00Z"],
+            vec!["deposit", "2", "2", "5.0", "2024-01-01T00:00:00Z"],
+            vec!["dispute", "2", "2", "", "2024-01-02T00:00:00Z"],
+        ]));
+        assert_eq!(result.unwrap().applied(), 3);
+
+        let state = |engine: &Engine| {
+            let bookkeeping = format!(
+                "{:?}",
+                (
+                    engine.balances(),
+                    engine.statement(ClientId::new(1), None),
+                    engine.totals(),
+                    engine.history(),
+                    engine.volumes(),
+                    engine.disputes(),
+                    engine.journal().unwrap().len(),
+                    engine.event_log().unwrap().events(),
+                )
+            );
+            (bookkeeping, MemoryLedger::copy_of(engine.ledger()))
+        };
+        let before = state(&engine);
+        // The dispute of client 2 expires, and client 1 is charged
+        // back, before the withdrawal turns out to be too large.
+        let failing = batch(vec![
+            vec!["deposit", "1", "3", "1.0", "2024-01-20T00:00:00Z"],
+            vec!["deposit", "3", "4", "1.0", "2024-01-20T00:00:00Z"],
+            vec!["dispute", "1", "1", "", "2024-01-20T00:00:00Z"],
+            vec!["chargeback", "1", "1", "", "2024-01-20T00:00:00Z"],
+            vec!["withdrawal", "3", "5", "2.0", "2024-01-20T00:00:00Z"],
+        ]);
+        let err = engine.apply_batch(&failing).unwrap_err();
+        assert_eq!(
+            err,
+            BatchError {
+                index: 4,
+                reason: RejectReason::InsufficientFunds,
+            }
+        );
+        assert_eq!(state(&engine), before);
+        assert_eq!(engine.open_disputes(), 1);
+
+        // Nothing of the failed batch was journaled, and the events carry
+        // on where they left off.
+        let result = engine.apply_batch(&failing[..4]).unwrap();
+        assert_eq!(result.applied(), 4);
+        assert!(engine
+            .account(ClientId::new(1), None, None)
+            .unwrap()
+            .is_locked());
+        assert_eq!(engine.open_disputes(), 0);
+        let log = engine.event_log().unwrap().events();
+        let seqs: Vec<u64> = log.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, (1..=seqs.len() as u64).collect::<Vec<_>>());
+
+        // Without strict errors, the refused records are simply left out,
+        // here the dispute and chargeback of a transaction this engine never
+        // saw as well.
+        let mut engine = Engine::new();
+        let result = engine.apply_batch(&failing).unwrap();
+        assert_eq!((result.applied(), result.rejected()), (2, 3));
+        assert_eq!(result.results[4], Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.balances().len(), 2);
+    }
+
+    #[test]
+    fn test_apply_returns_balance() {
+        let mut engine = Engine::new();
+        let record = |row: Vec<&str>| make_input_record(&StringRecord::from(row)).unwrap();
+        engine
+            .apply(record(vec!["deposit", "1", "1", "10.00"]))
+            .unwrap();
+        let applied = engine.apply(record(vec!["dispute", "1", "1", ""])).unwrap();
+        assert_eq!(applied.warning, None);
+        assert_eq!(applied.balance.client, ClientId::new(1));
+        assert_eq!(applied.balance.available, Amount::ZERO);
+        assert_eq!(applied.balance.held, Amount::from_scaled(100_000));
+    }
+
+    #[test]
+    fn test_withdrawal_unknown_client() {
+        let mut engine = Engine::new();
+        let results = apply_rows(&mut engine, vec![vec!["withdrawal", "1", "1", "5.00"]]);
+        assert_eq!(results[0], Err(RejectReason::UnknownClient));
+        assert!(engine.balances().is_empty());
+    }
+
+    #[test]
+    fn test_non_positive_amounts_are_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.0"],
+                vec!["withdrawal", "1", "2", "-50"],
+                vec!["withdrawal", "1", "3", "0"],
+                vec!["deposit", "1", "4", "0.0"],
+                vec!["deposit", "2", "5", "-5"],
+            ],
+        );
+        for result in &results[1..] {
+            assert_eq!(*result, Err(RejectReason::NonPositiveAmount));
+        }
+        // Neither an account for client 2 nor the rejected transactions
+        // are left behind.
+        let balances = engine.balances();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].available, Amount::from_scaled(100_000));
+        for tx in 2..=5 {
+            assert!(engine.ledger.lookup_tx(TxId::new(tx), None).is_none());
+        }
+    }
+
+    #[test]
+    fn test_overflowing_deposit_is_rejected() {
+        let mut engine = Engine::new();
+        let max = Amount::from_scaled(i64::MAX).to_string();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", &max],
+                vec!["deposit", "1", "2", "1.00"],
+            ],
+        );
+        assert_eq!(results[1], Err(RejectReason::Overflow));
+        assert!(engine.ledger.lookup_tx(TxId::new(2), None).is_none());
+        assert_eq!(engine.balances()[0].total, Amount::from_scaled(i64::MAX));
+    }
+
+    fn snapshot_round_trip(engine: &Engine, config: EngineConfig) -> Engine {
+        let mut buf = Vec::new();
+        engine.snapshot_to_writer(&mut buf).unwrap();
+        Engine::restore_from_reader(&buf[..], config).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "2", "2", "5.00"],
+                vec!["dispute", "1", "1", ""],
+            ],
+        );
+        let mut restored = snapshot_round_trip(&engine, EngineConfig::default());
+        let mut expected = engine.balances();
+        let mut actual = restored.balances();
+        expected.sort_by_key(|r| r.client);
+        actual.sort_by_key(|r| r.client);
+        assert_eq!(actual, expected);
+
+        // The open dispute and the transaction IDs seen so far carry over.
+        let results = apply_rows(
+            &mut restored,
+            vec![
+                vec!["resolve", "1", "1", ""],
+                vec!["deposit", "2", "2", "1.00"],
+            ],
+        );
+        assert_eq!(results[0], Ok(None));
+        assert_eq!(results[1], Err(RejectReason::DuplicateTransaction));
+    }
+
+    #[test]
+    fn test_snapshot_keeps_tenants_apart() {
+        let config = EngineConfig {
+            tenants: true,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_config(config.clone());
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00", "", "", "", "eu"],
+                vec!["deposit", "1", "1", "5.00", "", "", "", "us"],
+                vec!["dispute", "1", "1", "", "", "", "", "us"],
+            ],
+        );
+        assert!(results.iter().all(Result::is_ok));
+        let mut restored = snapshot_round_trip(&engine, config);
+        assert_eq!(
+            MemoryLedger::copy_of(restored.ledger()),
+            MemoryLedger::copy_of(engine.ledger())
+        );
+
+        // Only the transaction of us is under dispute, while the ID is
+        // taken for both tenants.
+        let results = apply_rows(
+            &mut restored,
+            vec![
+                vec!["resolve", "1", "1", "", "", "", "", "eu"],
+                vec!["resolve", "1", "1", "", "", "", "", "us"],
+                vec!["deposit", "1", "1", "1.00", "", "", "", "eu"],
+                vec!["deposit", "1", "1", "1.00"],
+            ],
+        );
+        assert_eq!(
+            results,
+            [
+                Err(RejectReason::NotDisputed),
+                Ok(None),
+                Err(RejectReason::DuplicateTransaction),
+                Ok(None),
+            ]
+        );
+        assert_eq!(restored.balances().len(), 3);
+    }
+
+    #[test]
+    fn test_tenants_have_statements_and_accounts_of_their_own() {
+        let mut engine = Engine::with_config(EngineConfig {
+            tenants: true,
+            statements: StatementPolicy::All,
+            ..EngineConfig::default()
+        });
+        let (eu, us) = (Some("eu".parse().unwrap()), Some("us".parse().unwrap()));
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00", "", "", "", "eu"],
+                vec!["deposit", "1", "1", "5.00", "", "", "", "us"],
+                vec!["dispute", "1", "1", "", "", "", "", "us"],
+                vec!["chargeback", "1", "1", "", "", "", "", "us"],
+            ],
+        );
+        assert!(results.iter().all(Result::is_ok));
+        let client = ClientId::new(1);
+        let statement = engine.statement(client, eu);
+        assert_eq!(statement.len(), 1);
+        assert_eq!(statement[0].total, Amount::from_scaled(200_000));
+        let statement = engine.statement(client, us);
+        assert_eq!(statement.len(), 3);
+        assert!(statement.iter().all(|line| line.tenant == us));
+        assert!(engine.statement(client, None).is_empty());
+
+        // Only the account of us was locked, and only it is unlocked.
+        assert!(!engine.account(client, None, eu).unwrap().is_locked());
+        assert_eq!(
+            engine.unlock(client, None, eu),
+            Err(RejectReason::AccountNotLocked)
+        );
+        engine.unlock(client, None, us).unwrap();
+        assert!(!engine.account(client, None, us).unwrap().is_locked());
+        assert!(engine.account(client, None, None).is_none());
+
+        engine.freeze(client, None, eu).unwrap();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["withdrawal", "1", "2", "1.00", "", "", "", "eu"],
+                vec!["deposit", "1", "2", "1.00", "", "", "", "us"],
+            ],
+        );
+        assert_eq!(results, [Err(RejectReason::AccountFrozen), Ok(None)]);
+        engine.unfreeze(client, None, eu).unwrap();
+        assert_eq!(
+            engine.account(client, None, eu).unwrap().total(),
+            Amount::from_scaled(200_000)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_is_deterministic() {
+        let mut engine = Engine::new();
+        let rows = (1..50)
+            .map(|i| {
+                vec![
+                    "deposit".to_string(),
+                    (i % 7).to_string(),
+                    i.to_string(),
+                    "1".to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        apply_rows(
+            &mut engine,
+            rows.iter()
+                .map(|r| r.iter().map(String::as_str).collect())
+                .collect(),
+        );
+        let restored = snapshot_round_trip(&engine, EngineConfig::default());
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        engine.snapshot_to_writer(&mut a).unwrap();
+        restored.snapshot_to_writer(&mut b).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_restore_invalid_snapshot() {
+        let restore = |s: &str| Engine::restore_from_reader(s.as_bytes(), EngineConfig::default());
+        assert!(restore("not json").is_err());
+        assert!(restore(r#"{"version":99,"accounts":[],"transactions":{}}"#).is_err());
+        let orphan = r#"{"version":1,"accounts":[],"transactions":{"1":{"client":1,"kind":"deposit","amount":"1.0","state":"normal"}}}"#;
+        assert!(restore(orphan).is_err());
+    }
+
+    fn locked_engine(policy: LockedAccountPolicy) -> Engine {
+        let mut engine = Engine::with_config(EngineConfig {
+            locked_accounts: policy,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "1", "2", "5.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["chargeback", "1", "1", ""],
+            ],
+        );
+        engine
+    }
+
+    #[test]
+    fn test_locked_account_rejects_everything_by_default() {
+        let mut engine = locked_engine(LockedAccountPolicy::default());
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "3", "10.00"],
+                vec!["withdrawal", "1", "4", "1.00"],
+                vec!["dispute", "1", "2", ""],
+            ],
+        );
+        assert!(results
+            .iter()
+            .all(|r| *r == Err(RejectReason::AccountLocked)));
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(50_000));
+    }
+
+    #[test]
+    fn test_locked_account_allow_deposits() {
+        let mut engine = locked_engine(LockedAccountPolicy::AllowDeposits);
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "3", "10.00"],
+                vec!["withdrawal", "1", "4", "1.00"],
+            ],
+        );
+        assert_eq!(results[0], Ok(None));
+        assert_eq!(results[1], Err(RejectReason::AccountLocked));
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(150_000));
+    }
+
+    #[test]
+    fn test_locked_account_allow_all() {
+        let mut engine = locked_engine(LockedAccountPolicy::AllowAll);
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "3", "10.00"],
+                vec!["withdrawal", "1", "4", "1.00"],
+            ],
+        );
+        assert_eq!(results, vec![Ok(None), Ok(None)]);
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(140_000));
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let mut engine = Engine::with_config(EngineConfig {
+            disputes: DisputePolicy::DepositsAndWithdrawals,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.0"],
+                vec!["withdrawal", "1", "2", "9.0"],
+                vec!["deposit", "2", "3", "10.0"],
+                vec!["withdrawal", "2", "4", "2.0"],
+                vec!["dispute", "2", "3", ""],
+                vec!["resolve", "2", "3", ""],
+                vec!["dispute", "2", "4", ""],
+            ],
+        );
+        assert!(engine.anomalies(&AnomalyThresholds::default()).is_empty());
+
+        let anomalies = engine.anomalies(&AnomalyThresholds {
+            withdrawal_ratio: Some("0.5".parse().unwrap()),
+            max_disputes: Some(1),
+        });
+        assert_eq!(anomalies.len(), 2);
+        assert_eq!(anomalies[0].client, ClientId::new(1));
+        assert_eq!(anomalies[0].withdrawn, Amount::from_scaled(90_000));
+        assert_eq!(anomalies[0].flags, [AnomalyFlag::Withdrawals]);
+        assert_eq!(anomalies[1].disputes, 2);
+        assert_eq!(anomalies[1].flags, [AnomalyFlag::Disputes]);
+
+        // Withdrawing exactly the multiple is not enough.
+        let anomalies = engine.anomalies(&AnomalyThresholds {
+            withdrawal_ratio: Some("0.9".parse().unwrap()),
+            max_disputes: None,
+        });
+        assert!(anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_unlock() {
+        let mut engine = locked_engine(LockedAccountPolicy::default());
+        let lock = engine.locks()[0];
+        assert_eq!(lock.chargeback, Some(TxId::new(1)));
+        assert_eq!(lock.locked_at, None);
+        assert_eq!(lock.total, Amount::from_scaled(50_000));
+        let restored = snapshot_round_trip(&engine, EngineConfig::default());
+        assert_eq!(restored.locks(), engine.locks());
+
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["unlock", "1", "5", ""],
+                vec!["deposit", "1", "3", "10.00"],
+                vec!["unlock", "1", "6", ""],
+                vec!["unlock", "2", "7", ""],
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::AccountNotLocked),
+                Err(RejectReason::UnknownClient),
+            ]
+        );
+        assert!(engine.locks().is_empty());
+
+        // A new chargeback locks the account again, for its own reasons.
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["dispute", "1", "3", "", "2024-01-01T00:00:00Z"],
+                vec!["chargeback", "1", "3", "", "2024-01-02T00:00:00Z"],
+            ],
+        );
+        let lock = engine.locks()[0];
+        assert_eq!(lock.chargeback, Some(TxId::new(3)));
+        assert_eq!(
+            lock.locked_at,
+            Some(parse_timestamp("2024-01-02T00:00:00Z").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_account_lifecycle() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["open", "1", "1", ""],
+                vec!["open", "1", "2", ""],
+                vec!["deposit", "1", "3", "10.00"],
+                vec!["freeze", "1", "4", ""],
+                vec!["withdrawal", "1", "5", "10.00"],
+                vec!["dispute", "1", "3", ""],
+                vec!["resolve", "1", "3", ""],
+                vec!["close", "1", "6", ""],
+                vec!["unfreeze", "1", "7", ""],
+                vec!["withdrawal", "1", "8", "10.00"],
+                vec!["close", "1", "9", ""],
+                vec!["deposit", "1", "10", "1.00"],
+                vec!["dispute", "1", "3", ""],
+                vec!["open", "1", "11", ""],
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![
+                Ok(None),
+                Err(RejectReason::AccountExists),
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::AccountFrozen),
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::NonZeroBalance),
+                Ok(None),
+                Ok(None),
+                Ok(None),
+                Err(RejectReason::AccountClosed),
+                Err(RejectReason::AccountClosed),
+                Ok(None),
+            ]
+        );
+        assert_eq!(
+            engine
+                .account(ClientId::new(1), None, None)
+                .unwrap()
+                .status(),
+            AccountStatus::Open
+        );
+        // The transaction IDs of these records are not kept.
+        assert!(engine.ledger.lookup_tx(TxId::new(11), None).is_none());
+    }
+
+    #[test]
+    fn test_explicit_accounts() {
+        let mut engine = Engine::with_config(EngineConfig {
+            accounts: AccountPolicy::Explicit,
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00"],
+                vec!["open", "1", "2", "", "", "EUR"],
+                vec!["deposit", "1", "3", "10.00", "", "EUR"],
+            ],
+        );
+        assert_eq!(
+            results,
+            vec![Err(RejectReason::UnknownClient), Ok(None), Ok(None)]
+        );
+        assert!(engine.account(ClientId::new(1), None, None).is_none());
+        assert_eq!(
+            engine.freeze(ClientId::new(2), None, None),
+            Err(RejectReason::UnknownClient)
+        );
+        engine
+            .freeze(ClientId::new(1), Some("EUR".parse().unwrap()), None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_locked_account_can_be_closed() {
+        let mut engine = Engine::new();
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "10.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["chargeback", "1", "1", ""],
+            ],
+        );
+        engine.close_account(ClientId::new(1), None, None).unwrap();
+        assert_eq!(
+            engine
+                .account(ClientId::new(1), None, None)
+                .unwrap()
+                .status(),
+            AccountStatus::Closed
+        );
+    }
+
+    #[test]
+    fn test_parse_locked_account_policy() {
+        assert_eq!("allow".parse(), Ok(LockedAccountPolicy::AllowAll));
+        assert_eq!(
+            "allow-deposits".parse(),
+            Ok(LockedAccountPolicy::AllowDeposits)
+        );
+        assert!("freeze".parse::<LockedAccountPolicy>().is_err());
+    }
+
+    fn duplicate_engine(
+        policy: DuplicatePolicy,
+    ) -> (Engine, Vec<Result<Option<Warning>, RejectReason>>) {
+        let mut engine = Engine::with_config(EngineConfig {
+            duplicates: policy,
+            ..EngineConfig::default()
+        });
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "1", "1", "5.00"],
+            ],
+        );
+        (engine, results)
+    }
+
+    #[test]
+    fn test_duplicate_rejected_by_default() {
+        let (engine, results) = duplicate_engine(DuplicatePolicy::default());
+        assert_eq!(results[1], Err(RejectReason::DuplicateTransaction));
+        assert_eq!(engine.balances()[0].total, Amount::from_scaled(200_000));
+        assert_eq!(
+            engine.ledger.lookup_tx(TxId::new(1), None).unwrap().amount,
+            Amount::from_scaled(200_000)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_from_other_client_rejected() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["deposit", "2", "1", "5.00"],
+            ],
+        );
+        assert_eq!(results[1], Err(RejectReason::DuplicateTransaction));
+        assert_eq!(engine.balances().len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_keep_first() {
+        let (engine, results) = duplicate_engine(DuplicatePolicy::KeepFirst);
+        assert_eq!(results[1], Ok(Some(Warning::DuplicateTransaction)));
+        assert_eq!(engine.balances()[0].total, Amount::from_scaled(250_000));
+        assert_eq!(
+            engine.ledger.lookup_tx(TxId::new(1), None).unwrap().amount,
+            Amount::from_scaled(200_000)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keep_last() {
+        let (engine, results) = duplicate_engine(DuplicatePolicy::KeepLast);
+        assert_eq!(results[1], Ok(Some(Warning::DuplicateTransaction)));
+        assert_eq!(engine.balances()[0].total, Amount::from_scaled(250_000));
+        assert_eq!(
+            engine.ledger.lookup_tx(TxId::new(1), None).unwrap().amount,
+            Amount::from_scaled(50_000)
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keep_last_never_replaces_disputed() {
+        let mut engine = Engine::with_config(EngineConfig {
+            duplicates: DuplicatePolicy::KeepLast,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["dispute", "1", "1", ""],
+                vec!["deposit", "1", "1", "5.00"],
+                vec!["resolve", "1", "1", ""],
+            ],
+        );
+        let balances = engine.balances();
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert_eq!(balances[0].available, Amount::from_scaled(250_000));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_rejected_by_default() {
+        let mut engine = Engine::new();
+        let results = apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["withdrawal", "1", "2", "5.00"],
+                vec!["dispute", "1", "2", ""],
+            ],
+        );
+        assert_eq!(results[2], Err(RejectReason::NotDisputable));
+        assert_eq!(state(&engine, 2), TransactionState::Normal);
+        assert_eq!(engine.balances()[0].available, Amount::from_scaled(150_000));
+    }
+
+    fn withdrawal_dispute_engine() -> Engine {
+        let mut engine = Engine::with_config(EngineConfig {
+            disputes: DisputePolicy::DepositsAndWithdrawals,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["withdrawal", "1", "2", "5.00"],
+                vec!["dispute", "1", "2", ""],
+            ],
+        );
+        engine
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_credits_available() {
+        let engine = withdrawal_dispute_engine();
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(200_000));
+        assert_eq!(balances[0].held, Amount::from_scaled(-50_000));
+        assert_eq!(balances[0].total, Amount::from_scaled(150_000));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_resolve() {
+        let mut engine = withdrawal_dispute_engine();
+        apply_rows(&mut engine, vec![vec!["resolve", "1", "2", ""]]);
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(150_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert_eq!(balances[0].total, Amount::from_scaled(150_000));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_chargeback_refunds_client() {
+        let mut engine = withdrawal_dispute_engine();
+        apply_rows(&mut engine, vec![vec!["chargeback", "1", "2", ""]]);
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(200_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert_eq!(balances[0].total, Amount::from_scaled(200_000));
+        assert!(balances[0].locked);
+    }
+
+    fn pending_withdrawal_dispute_engine() -> Engine {
+        let mut engine = Engine::with_config(EngineConfig {
+            disputes: DisputePolicy::DepositsAndWithdrawals,
+            withdrawal_disputes: WithdrawalDisputes::Pending,
+            ..EngineConfig::default()
+        });
+        apply_rows(
+            &mut engine,
+            vec![
+                vec!["deposit", "1", "1", "20.00"],
+                vec!["withdrawal", "1", "2", "5.00"],
+                vec!["dispute", "1", "2", ""],
+            ],
+        );
+        engine
+    }
+
+    #[test]
+    fn test_pending_withdrawal_dispute_holds_credit() {
+        let mut engine = pending_withdrawal_dispute_engine();
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(150_000));
+        assert_eq!(balances[0].held, Amount::from_scaled(50_000));
+        assert_eq!(balances[0].total, Amount::from_scaled(200_000));
+
+        // The pending credit cannot be spent yet.
+        let results = apply_rows(&mut engine, vec![vec!["withdrawal", "1", "3", "15.01"]]);
+        assert_eq!(results[0], Err(RejectReason::InsufficientFunds));
+    }
+
+    #[test]
+    fn test_pending_withdrawal_dispute_resolve() {
+        let mut engine = pending_withdrawal_dispute_engine();
+        apply_rows(&mut engine, vec![vec!["resolve", "1", "2", ""]]);
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(150_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert_eq!(balances[0].total, Amount::from_scaled(150_000));
+    }
+
+    #[test]
+    fn test_pending_withdrawal_dispute_chargeback_refunds_client() {
+        let mut engine = pending_withdrawal_dispute_engine();
+        apply_rows(&mut engine, vec![vec!["chargeback", "1", "2", ""]]);
+        let balances = engine.balances();
+        assert_eq!(balances[0].available, Amount::from_scaled(200_000));
+        assert_eq!(balances[0].held, Amount::ZERO);
+        assert_eq!(balances[0].total, Amount::from_scaled(200_000));
+        assert!(balances[0].locked);
+    }
+
+    /// Any record the engine could be fed: a handful of clients and
+    /// transaction IDs, so disputes often hit an earlier transaction, and
+    /// amounts that are either everyday ones or close to the limits.
+    fn arbitrary_record() -> impl Strategy<Value = InputRecord> {
+        let r#type = prop_oneof![
+            Just(TransactionType::Deposit),
+            Just(TransactionType::Withdrawal),
+            Just(TransactionType::Dispute),
+            Just(TransactionType::Resolve),
+            Just(TransactionType::Chargeback),
+        ];
+        let amount = prop_oneof![0..10_000_000i64, (i64::MAX / 4)..=i64::MAX];
+        (r#type, 1..4u16, 1..20u32, amount).prop_map(|(r#type, client, tx, amount)| InputRecord {
+            r#type,
+            client: client.into(),
+            tx: tx.into(),
+            amount: r#type
+                .requires_amount()
+                .then_some(Amount::from_scaled(amount)),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
+        })
+    }
+
+    /// A ledger that is not the default one, to show that the engine keeps
+    /// everything in whatever ledger it is given.
+    #[derive(Debug, Default)]
+    struct VecLedger {
+        accounts: Vec<Account>,
+        transactions: Vec<(TxId, StoredTransaction)>,
+    }
+
+    impl Ledger for VecLedger {
+        fn get_account(
+            &self,
+            client: ClientId,
+            currency: Option<Currency>,
+            tenant: Option<Tenant>,
+        ) -> Option<Account> {
+            self.accounts
+                .iter()
+                .find(|a| (a.client(), a.currency(), a.tenant()) == (client, currency, tenant))
+                .copied()
+        }
+
+        fn upsert_account(&mut self, account: Account) {
+            let key = (account.client(), account.currency(), account.tenant());
+            self.accounts
+                .retain(|a| (a.client(), a.currency(), a.tenant()) != key);
+            self.accounts.push(account);
+        }
+
+        fn record_tx(&mut self, tx: TxId, transaction: StoredTransaction) {
+            let tenant = transaction.tenant;
+            self.transactions
+                .retain(|(t, stored)| (*t, stored.tenant) != (tx, tenant));
+            self.transactions.push((tx, transaction));
+        }
+
+        fn lookup_tx(&self, tx: TxId, tenant: Option<Tenant>) -> Option<StoredTransaction> {
+            self.transactions
+                .iter()
+                .find(|(t, stored)| (*t, stored.tenant) == (tx, tenant))
+                .map(|(_, transaction)| *transaction)
+        }
+
+        fn accounts(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+            Box::new(self.accounts.iter().copied())
+        }
+
+        fn transactions(&self) -> Box<dyn Iterator<Item = (TxId, StoredTransaction)> + '_> {
+            Box::new(self.transactions.iter().copied())
+        }
+
+        fn clear(&mut self) {
+            self.accounts.clear();
+            self.transactions.clear();
+        }
+    }
+
+    #[test]
+    fn test_custom_ledger() {
+        let rows = || {
+            vec![
+                vec!["deposit", "1", "1", "10.0"],
+                vec!["deposit", "2", "2", "5.0", "", "EUR"],
+                vec!["withdrawal", "1", "3", "2.5"],
+                vec!["dispute", "1", "1", "", "2024-01-01T00:00:00Z"],
+                vec!["withdrawal", "1", "4", "1.0"],
+            ]
+        };
+        let mut expected = Engine::new();
+        apply_rows(&mut expected, rows());
+        let mut engine = Engine::with_ledger(EngineConfig::default(), VecLedger::default());
+        let results = apply_rows(&mut engine, rows());
+        assert_eq!(results[4], Err(RejectReason::InsufficientFunds));
+        assert_eq!(engine.ledger().accounts().count(), 2);
+        assert_eq!(
+            MemoryLedger::copy_of(engine.ledger()),
+            MemoryLedger::copy_of(expected.ledger())
+        );
+
+        // A parallel run hands the ledger back to the first shard.
+        let mut shards = engine.split(2).into_iter();
+        let mut engine = shards.next().unwrap();
+        for shard in shards {
+            engine.merge(shard);
+        }
+        assert!(format!("{:?}", engine.ledger()).starts_with("VecLedger"));
+        assert_eq!(
+            MemoryLedger::copy_of(engine.ledger()),
+            MemoryLedger::copy_of(expected.ledger())
+        );
+
+        // A ledger that already holds open disputes has them expire.
+        let config = EngineConfig {
+            dispute_expiry: Some(DisputeExpiry {
+                days: 10,
+                outcome: DisputeOutcome::Resolve,
+            }),
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::with_ledger(config, MemoryLedger::copy_of(expected.ledger()));
+        let now = parse_timestamp("2024-02-01T00:00:00Z").unwrap();
+        assert_eq!(engine.expire_disputes(now), 1);
+        assert_eq!(state(&engine, 1), TransactionState::Resolved);
+    }
+
+    proptest! {
+        #[test]
+        fn test_invariants_hold_for_any_records(
+            records in proptest::collection::vec(arbitrary_record(), 1..200)
+        ) {
+            let mut engine = Engine::new();
+            for record in records {
+                let before = MemoryLedger::copy_of(engine.ledger());
+                let _ = engine.apply(record);
+
+                for account in engine.ledger.accounts() {
+                    prop_assert_eq!(
+                        account.available().checked_add(account.held()),
+                        Some(account.total())
+                    );
+                    // Only deposits can be disputed by default, so nothing
+                    // but money that came in is ever held.
+                    prop_assert!(account.held() >= Amount::ZERO);
+                    let old = before.get_account(account.client(), account.currency(), account.tenant());
+                    if let Some(old) = old.filter(|a| a.is_locked()) {
+                        prop_assert_eq!(old, account);
+                    }
+                }
+            }
+        }
+    }
+}
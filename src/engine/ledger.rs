@@ -0,0 +1,131 @@
+use super::{AccountKey, StoredTransaction, TxKey};
+use crate::account::Account;
+use crate::currency::Currency;
+use crate::id::{ClientId, TxId};
+use crate::tenant::Tenant;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Where an `Engine` keeps its state: the account of every client in every
+/// currency, of every tenant, and every transaction that may still be
+/// disputed. The engine
+/// does all of the settling itself and only ever reads and writes whole
+/// accounts and transactions, so a ledger merely has to store them, be it in
+/// memory, as `MemoryLedger` does, or in a database.
+///
+/// Accounts and transactions are handed over by value, so a ledger is free
+/// to store them in any form. Both can be serialized with serde, such as to
+/// keep them as JSON in a key-value store.
+pub trait Ledger: fmt::Debug + Send + Sync {
+    /// The account of `client` of `tenant` in `currency`, if it was ever
+    /// opened.
+    fn get_account(
+        &self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Option<Account>;
+
+    /// Stores `account`, replacing the account of the same client of the
+    /// same tenant in the same currency, if there is one.
+    fn upsert_account(&mut self, account: Account);
+
+    /// Stores `transaction` under `tx` for its tenant, replacing the
+    /// transaction stored under it, if there is one. Tenants may use the
+    /// same IDs for transactions of their own.
+    fn record_tx(&mut self, tx: TxId, transaction: StoredTransaction);
+
+    /// The transaction of `tenant` stored under `tx`, if any.
+    fn lookup_tx(&self, tx: TxId, tenant: Option<Tenant>) -> Option<StoredTransaction>;
+
+    /// Every account, in any order, for balances and snapshots.
+    fn accounts(&self) -> Box<dyn Iterator<Item = Account> + '_>;
+
+    /// Every transaction, in any order, for disputes and snapshots.
+    fn transactions(&self) -> Box<dyn Iterator<Item = (TxId, StoredTransaction)> + '_>;
+
+    /// Forgets every account and transaction. This only happens when a
+    /// parallel run splits the state of the engine between its threads, see
+    /// `Engine::split`, and everything is written back once they are done.
+    fn clear(&mut self);
+}
+
+/// The `Ledger` an `Engine` uses unless it is given another one, which
+/// keeps everything in memory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryLedger {
+    accounts: HashMap<AccountKey, Account>,
+    transactions: HashMap<TxKey, StoredTransaction>,
+}
+
+impl MemoryLedger {
+    /// A copy of everything `ledger` holds, such as for `Engine::simulate`
+    /// to work on.
+    pub fn copy_of(ledger: &dyn Ledger) -> Self {
+        MemoryLedger {
+            accounts: ledger
+                .accounts()
+                .map(|a| ((a.client(), a.currency(), a.tenant()), a))
+                .collect(),
+            transactions: ledger
+                .transactions()
+                .map(|(tx, t)| ((tx, t.tenant()), t))
+                .collect(),
+        }
+    }
+
+    /// Writes everything this ledger holds to `ledger`, such as what a
+    /// strict batch changed once it succeeded.
+    pub(crate) fn write_to(self, ledger: &mut dyn Ledger) {
+        for account in self.accounts.into_values() {
+            ledger.upsert_account(account);
+        }
+        for ((tx, _), transaction) in self.transactions {
+            ledger.record_tx(tx, transaction);
+        }
+    }
+}
+
+impl Ledger for MemoryLedger {
+    fn get_account(
+        &self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) -> Option<Account> {
+        self.accounts.get(&(client, currency, tenant)).copied()
+    }
+
+    fn upsert_account(&mut self, account: Account) {
+        let key = (account.client(), account.currency(), account.tenant());
+        self.accounts.insert(key, account);
+    }
+
+    fn record_tx(&mut self, tx: TxId, transaction: StoredTransaction) {
+        self.transactions
+            .insert((tx, transaction.tenant()), transaction);
+    }
+
+    fn lookup_tx(&self, tx: TxId, tenant: Option<Tenant>) -> Option<StoredTransaction> {
+        self.transactions.get(&(tx, tenant)).copied()
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+        Box::new(self.accounts.values().copied())
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = (TxId, StoredTransaction)> + '_> {
+        Box::new(self.transactions.iter().map(|((tx, _), t)| (*tx, *t)))
+    }
+
+    fn clear(&mut self) {
+        self.accounts.clear();
+        self.transactions.clear();
+    }
+}
+
+impl Default for Box<dyn Ledger> {
+    fn default() -> Self {
+        Box::new(MemoryLedger::default())
+    }
+}
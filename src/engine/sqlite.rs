@@ -0,0 +1,403 @@
+use super::{Changes, Engine, EngineConfig, Ledger, StoredTransaction, TransactionState};
+use crate::account::{Account, AccountStatus, Lock};
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::error::Error;
+use crate::id::TxId;
+use crate::input::parse_timestamp;
+use crate::tenant::Tenant;
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The tables the state of an engine is kept in. Amounts are stored as
+/// integers in units of the smallest amount an `Amount` can hold, so they
+/// are exact and can be summed up in SQL. Accounts and transactions without
+/// a currency or a tenant have an empty one, as a primary key cannot hold a
+/// NULL. Timestamps are RFC 3339 strings.
+///
+/// Client IDs, and transaction IDs that are numbers, are stored as integers,
+/// see `id_value`. Other transaction IDs are stored as text, which is why
+/// the `tx` and `locked_by` columns have no type.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        tenant TEXT NOT NULL DEFAULT '',
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        locked INTEGER NOT NULL,
+        locked_by,
+        locked_at TEXT,
+        status TEXT NOT NULL DEFAULT 'open',
+        billed_month INTEGER,
+        transactions INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (client, currency, tenant)
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx NOT NULL,
+        tenant TEXT NOT NULL DEFAULT '',
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        type TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        disputed INTEGER,
+        disputed_at TEXT,
+        timestamp TEXT,
+        seq INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (tx, tenant)
+    );
+";
+
+/// A `SqliteStore` keeps the state of an `Engine` in a SQLite database: every
+/// account, and every transaction that may be disputed. Unlike a snapshot,
+/// which is written in full every time, the store is updated with only what
+/// changed, so it can be written often, and the database can be queried
+/// with any SQLite client while the engine runs.
+///
+/// The engine still holds its entire state in memory as well.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+/// SQLite errors are passed on as I/O errors, as the database is just a
+/// file as far as the caller is concerned.
+fn sqlite_error(e: rusqlite::Error) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+fn invalid(msg: String) -> Error {
+    Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+fn state_name(state: TransactionState) -> &'static str {
+    match state {
+        TransactionState::Normal => "normal",
+        TransactionState::Disputed => "disputed",
+        TransactionState::Resolved => "resolved",
+        TransactionState::ChargedBack => "charged_back",
+    }
+}
+
+fn parse_state(s: &str) -> Option<TransactionState> {
+    match s {
+        "normal" => Some(TransactionState::Normal),
+        "disputed" => Some(TransactionState::Disputed),
+        "resolved" => Some(TransactionState::Resolved),
+        "charged_back" => Some(TransactionState::ChargedBack),
+        _ => None,
+    }
+}
+
+/// IDs up to `u64::MAX` are stored as SQLite integers, which are signed,
+/// so the largest ones come out negative but are read back exactly.
+fn id_value(number: Option<u64>, id: impl ToString) -> Value {
+    match number {
+        Some(n) => Value::Integer(n as i64),
+        None => Value::Text(id.to_string()),
+    }
+}
+
+/// Reads back an ID written with `id_value`.
+fn parse_id<T: FromStr>(value: Value) -> Result<T, Error> {
+    let text = match value {
+        Value::Integer(n) => (n as u64).to_string(),
+        Value::Text(s) => s,
+        value => return Err(invalid(format!("invalid ID {:?}", value))),
+    };
+    text.parse()
+        .map_err(|_| invalid(format!("invalid ID '{}'", text)))
+}
+
+fn currency_name(currency: Option<Currency>) -> String {
+    currency.map(|c| c.to_string()).unwrap_or_default()
+}
+
+fn parse_currency(s: &str) -> Result<Option<Currency>, Error> {
+    match s {
+        "" => Ok(None),
+        s => s.parse().map(Some).map_err(|e| invalid(format!("{}", e))),
+    }
+}
+
+fn tenant_name(tenant: Option<Tenant>) -> String {
+    tenant.map(|t| t.to_string()).unwrap_or_default()
+}
+
+fn parse_tenant(s: &str) -> Result<Option<Tenant>, Error> {
+    match s {
+        "" => Ok(None),
+        s => s.parse().map(Some).map_err(|e| invalid(format!("{}", e))),
+    }
+}
+
+fn parse_stored_timestamp(s: String) -> Result<DateTime<Utc>, Error> {
+    parse_timestamp(&s).map_err(|_| invalid(format!("invalid timestamp '{}'", s)))
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and its tables if need be.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, Error> {
+        let connection = Connection::open(path).map_err(sqlite_error)?;
+        connection.execute_batch(SCHEMA).map_err(sqlite_error)?;
+        Ok(SqliteStore { connection })
+    }
+
+    /// Reads the state written by `save` back into an engine.
+    pub(super) fn load(&self, config: EngineConfig) -> Result<Engine, Error> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT client, currency, tenant, available, held, locked, locked_by, locked_at, \
+                 status, billed_month, transactions FROM accounts",
+            )
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, Value>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, bool>(5)?,
+                    row.get::<_, Value>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, String>(8)?,
+                    row.get::<_, Option<i32>>(9)?,
+                    row.get::<_, i64>(10)?,
+                ))
+            })
+            .map_err(sqlite_error)?;
+        let mut accounts = Vec::new();
+        for row in rows {
+            let (
+                client,
+                currency,
+                tenant,
+                available,
+                held,
+                locked,
+                locked_by,
+                locked_at,
+                status,
+                billed_month,
+                transactions,
+            ) = row.map_err(sqlite_error)?;
+            let lock = match locked_by {
+                Value::Null => None,
+                tx => Some(Lock {
+                    tx: parse_id(tx)?,
+                    locked_at: locked_at.map(parse_stored_timestamp).transpose()?,
+                }),
+            };
+            accounts.push(Account::from_parts(
+                parse_id(client)?,
+                parse_currency(&currency)?,
+                parse_tenant(&tenant)?,
+                Amount::from_scaled(available),
+                Amount::from_scaled(held),
+                locked,
+                lock,
+                status.parse::<AccountStatus>().map_err(invalid)?,
+                billed_month,
+                transactions as u64,
+            ));
+        }
+
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT tx, tenant, client, currency, type, amount, state, disputed, disputed_at, \
+                 timestamp, seq \
+                 FROM transactions",
+            )
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, Value>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Value>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, Option<i64>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get::<_, Option<String>>(9)?,
+                    row.get::<_, i64>(10)?,
+                ))
+            })
+            .map_err(sqlite_error)?;
+        let mut transactions = Vec::new();
+        for row in rows {
+            let (
+                tx,
+                tenant,
+                client,
+                currency,
+                kind,
+                amount,
+                state,
+                disputed,
+                disputed_at,
+                timestamp,
+                seq,
+            ) = row.map_err(sqlite_error)?;
+            let transaction = StoredTransaction {
+                client: parse_id(client)?,
+                currency: parse_currency(&currency)?,
+                tenant: parse_tenant(&tenant)?,
+                kind: kind.parse().map_err(invalid)?,
+                amount: Amount::from_scaled(amount),
+                state: parse_state(&state)
+                    .ok_or_else(|| invalid(format!("unknown transaction state '{}'", state)))?,
+                disputed: disputed.map(Amount::from_scaled),
+                disputed_at: disputed_at.map(parse_stored_timestamp).transpose()?,
+                timestamp: timestamp.map(parse_stored_timestamp).transpose()?,
+                seq: seq as u64,
+            };
+            transactions.push((parse_id::<TxId>(tx)?, transaction));
+        }
+
+        Engine::from_state(config, accounts, transactions).map_err(invalid)
+    }
+
+    /// Writes the accounts and transactions listed in `changes`, all in one
+    /// database transaction.
+    pub(super) fn save(&mut self, ledger: &dyn Ledger, changes: &Changes) -> Result<(), Error> {
+        let db = self.connection.transaction().map_err(sqlite_error)?;
+        {
+            let mut upsert = db
+                .prepare(
+                    "INSERT OR REPLACE INTO accounts
+                     (client, currency, tenant, available, held, locked, locked_by, locked_at,
+                      status, billed_month, transactions)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                )
+                .map_err(sqlite_error)?;
+            for account in changes
+                .accounts
+                .iter()
+                .filter_map(|key| ledger.get_account(key.0, key.1, key.2))
+            {
+                upsert
+                    .execute(params![
+                        id_value(Some(account.client().get()), account.client()),
+                        currency_name(account.currency()),
+                        tenant_name(account.tenant()),
+                        account.available().scaled(),
+                        account.held().scaled(),
+                        account.is_locked(),
+                        account
+                            .lock()
+                            .map_or(Value::Null, |lock| id_value(lock.tx.as_u64(), lock.tx)),
+                        account
+                            .lock()
+                            .and_then(|lock| lock.locked_at)
+                            .map(|t| t.to_rfc3339()),
+                        account.status().name(),
+                        account.billed_month(),
+                        account.transactions() as i64,
+                    ])
+                    .map_err(sqlite_error)?;
+            }
+
+            let mut upsert = db
+                .prepare(
+                    "INSERT OR REPLACE INTO transactions
+                     (tx, tenant, client, currency, type, amount, state, disputed, disputed_at,
+                      timestamp, seq)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                )
+                .map_err(sqlite_error)?;
+            for (tx, transaction) in changes
+                .transactions
+                .iter()
+                .filter_map(|&(tx, tenant)| ledger.lookup_tx(tx, tenant).map(|t| (tx, t)))
+            {
+                upsert
+                    .execute(params![
+                        id_value(tx.as_u64(), tx),
+                        tenant_name(transaction.tenant),
+                        id_value(Some(transaction.client.get()), transaction.client),
+                        currency_name(transaction.currency),
+                        transaction.kind.name(),
+                        transaction.amount.scaled(),
+                        state_name(transaction.state),
+                        transaction.disputed.map(|a| a.scaled()),
+                        transaction.disputed_at.map(|t| t.to_rfc3339()),
+                        transaction.timestamp.map(|t| t.to_rfc3339()),
+                        transaction.seq as i64,
+                    ])
+                    .map_err(sqlite_error)?;
+            }
+        }
+        db.commit().map_err(sqlite_error)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::SqliteStore;
+    use crate::engine::{Engine, EngineConfig, MemoryLedger};
+    use crate::id::TxId;
+    use crate::input::make_input_record;
+    use csv::StringRecord;
+
+    #[test]
+    fn test_store_round_trip() {
+        let path = std::env::temp_dir().join(format!("payments-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let config = EngineConfig {
+            tenants: true,
+            ..EngineConfig::default()
+        };
+        let mut engine =
+            Engine::restore_from_store(SqliteStore::open(&path).unwrap(), config.clone()).unwrap();
+        for row in [
+            vec!["deposit", "1", "1", "10.0"],
+            vec!["deposit", "1", "1", "2.0", "", "", "", "eu"],
+            vec!["deposit", "2", "2", "5.0", "", "EUR"],
+            vec!["freeze", "2", "3", "", "", "EUR"],
+            vec!["dispute", "1", "1", "", "2024-01-01T00:00:00Z"],
+            vec!["deposit", "3", "4", "3.0", "", "EUR"],
+            vec!["dispute", "3", "4", "1.0", "", "EUR"],
+            vec!["chargeback", "3", "4", "", "2024-01-02T00:00:00Z", "EUR"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        engine.save_to_store().unwrap();
+        // Nothing changed since, so this writes nothing.
+        engine.save_to_store().unwrap();
+        engine
+            .apply(make_input_record(&StringRecord::from(vec!["resolve", "1", "1", ""])).unwrap())
+            .unwrap();
+        engine.save_to_store().unwrap();
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let total: i64 = connection
+            .query_row("SELECT SUM(available + held) FROM accounts", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(total, 190_000);
+        let store = SqliteStore::open(&path).unwrap();
+        let restored = Engine::restore_from_store(store, config).unwrap();
+        assert_eq!(
+            MemoryLedger::copy_of(restored.ledger()),
+            MemoryLedger::copy_of(engine.ledger())
+        );
+        assert_eq!(restored.locks(), engine.locks());
+        assert_eq!(restored.locks()[0].chargeback, Some(TxId::new(4)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
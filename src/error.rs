@@ -0,0 +1,380 @@
+use super::id::{ClientId, TxId};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The columns every input row is made of. These are used to tell the
+/// caller exactly which part of a row could not be processed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Column {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    /// When the transaction happened. Like the amount, this column may be
+    /// left out altogether.
+    Timestamp,
+    /// The currency of the transaction, which may be left out as well.
+    Currency,
+    /// Why an adjustment was made, as a code such as `FX_CORRECTION`. Only
+    /// adjustments need one.
+    Reason,
+    /// The tenant the transaction belongs to, see `tenant::Tenant`, which
+    /// may be left out like the currency.
+    Tenant,
+}
+
+impl Column {
+    /// Every column, in the order they appear in a row.
+    pub const ALL: [Column; 8] = [
+        Column::Type,
+        Column::Client,
+        Column::Tx,
+        Column::Amount,
+        Column::Timestamp,
+        Column::Currency,
+        Column::Reason,
+        Column::Tenant,
+    ];
+
+    /// Whether a row has to have this column at all.
+    pub fn is_required(self) -> bool {
+        !matches!(
+            self,
+            Column::Amount | Column::Timestamp | Column::Currency | Column::Reason | Column::Tenant
+        )
+    }
+
+    /// The zero-based position of the column in an input row.
+    pub fn index(self) -> usize {
+        match self {
+            Column::Type => 0,
+            Column::Client => 1,
+            Column::Tx => 2,
+            Column::Amount => 3,
+            Column::Timestamp => 4,
+            Column::Currency => 5,
+            Column::Reason => 6,
+            Column::Tenant => 7,
+        }
+    }
+
+    /// The name of the column as it appears in the input header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Type => "type",
+            Column::Client => "client",
+            Column::Tx => "tx",
+            Column::Amount => "amount",
+            Column::Timestamp => "timestamp",
+            Column::Currency => "currency",
+            Column::Reason => "reason",
+            Column::Tenant => "tenant",
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What is wrong with the header row of CSV input, see
+/// `Error::InvalidHeader`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderProblem {
+    /// A column every row needs is not there.
+    MissingColumn(Column),
+    /// A column is named almost like one we know, such as `amout`, so it is
+    /// most likely meant to be that one and would otherwise be ignored.
+    Misspelt { column: Column, name: String },
+    /// The header row holds a transaction type, so it is most likely a row
+    /// of data, and the input has no header row at all.
+    NoHeaderRow,
+}
+
+/// All errors that can occur while reading, parsing or writing records.
+/// Errors tied to a specific row carry the line number of that row (when it
+/// is known) so the caller can point at the offending input.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// The CSV reader or writer failed, e.g. because of malformed quoting.
+    Csv(csv::Error),
+    /// A line of JSON input could not be deserialized.
+    Json {
+        line: Option<u64>,
+        source: serde_json::Error,
+    },
+    /// A field of a row, counted from 1, is not valid UTF-8. Like any other
+    /// row the CSV reader cannot make sense of, this ends the input.
+    InvalidUtf8 { line: Option<u64>, field: usize },
+    /// A row did not have the number of columns we expect.
+    ColumnCount {
+        line: Option<u64>,
+        expected: usize,
+        found: usize,
+    },
+    /// A required column was missing or empty.
+    MissingField { line: Option<u64>, column: Column },
+    /// The transaction type column held something we don't know about.
+    UnknownTransactionType { line: Option<u64>, value: String },
+    /// A column could not be parsed into the type it should hold.
+    Parse {
+        line: Option<u64>,
+        column: Column,
+        value: String,
+    },
+    /// A deposit or withdrawal came without an amount.
+    MissingAmount { line: Option<u64> },
+    /// An adjustment came without a reason code.
+    MissingReason { line: Option<u64> },
+    /// An amount had more decimal places than `Precision::Reject` allows.
+    TooPrecise { line: Option<u64>, value: String },
+    /// The header of the input lacks a column every row needs.
+    MissingColumn { column: Column },
+    /// The header row of CSV input does not name the columns we need. Holds
+    /// the header row we expected, as far as the type, client, tx and amount
+    /// columns go, and the one we found.
+    InvalidHeader {
+        expected: Vec<String>,
+        found: Vec<String>,
+        problem: HeaderProblem,
+    },
+    /// A snapshot of the engine could not be written or read back.
+    Snapshot(serde_json::Error),
+    /// A transaction would have left the funds of a client negative, and
+    /// `NegativeBalancePolicy::Abort` stopped processing there.
+    NegativeBalance {
+        line: Option<u64>,
+        client: ClientId,
+        tx: TxId,
+    },
+    /// Any of the above, in one of several input files.
+    InFile { path: PathBuf, source: Box<Error> },
+}
+
+impl Error {
+    /// Tags this error with the file it occurred in.
+    pub fn in_file<P: AsRef<Path>>(self, path: P) -> Self {
+        Error::InFile {
+            path: path.as_ref().to_path_buf(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Returns the line of the input this error relates to, if any.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Error::InFile { source, .. } => source.line(),
+            Error::Io(_)
+            | Error::MissingColumn { .. }
+            | Error::InvalidHeader { .. }
+            | Error::Snapshot(_) => None,
+            Error::Csv(e) => e.position().map(|p| p.line()),
+            Error::Json { line, .. }
+            | Error::InvalidUtf8 { line, .. }
+            | Error::ColumnCount { line, .. }
+            | Error::MissingField { line, .. }
+            | Error::UnknownTransactionType { line, .. }
+            | Error::Parse { line, .. }
+            | Error::TooPrecise { line, .. }
+            | Error::MissingAmount { line }
+            | Error::MissingReason { line }
+            | Error::NegativeBalance { line, .. } => *line,
+        }
+    }
+
+    /// Returns the column of the input this error relates to, if any.
+    pub fn column(&self) -> Option<Column> {
+        match self {
+            Error::MissingField { column, .. }
+            | Error::Parse { column, .. }
+            | Error::MissingColumn { column } => Some(*column),
+            Error::InvalidHeader { problem, .. } => match problem {
+                HeaderProblem::MissingColumn(column) | HeaderProblem::Misspelt { column, .. } => {
+                    Some(*column)
+                }
+                HeaderProblem::NoHeaderRow => None,
+            },
+            Error::UnknownTransactionType { .. } => Some(Column::Type),
+            Error::MissingAmount { .. } | Error::TooPrecise { .. } => Some(Column::Amount),
+            Error::MissingReason { .. } => Some(Column::Reason),
+            Error::InFile { source, .. } => source.column(),
+            _ => None,
+        }
+    }
+
+    /// Whether this error only affects a single record. Such errors allow
+    /// processing to carry on with the next record, while all others mean
+    /// the input as a whole can no longer be read.
+    pub fn is_record_error(&self) -> bool {
+        match self {
+            Error::Io(_)
+            | Error::Csv(_)
+            | Error::InvalidUtf8 { .. }
+            | Error::MissingColumn { .. }
+            | Error::InvalidHeader { .. }
+            | Error::Snapshot(_)
+            | Error::NegativeBalance { .. } => false,
+            Error::InFile { source, .. } => source.is_record_error(),
+            _ => true,
+        }
+    }
+
+    /// What is wrong with the header row of the input, if that is what
+    /// this error is about.
+    pub fn header_problem(&self) -> Option<&HeaderProblem> {
+        match self {
+            Error::InvalidHeader { problem, .. } => Some(problem),
+            Error::InFile { source, .. } => source.header_problem(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => return write!(f, "I/O error: {}", e),
+            Error::Csv(e) => return write!(f, "CSV error: {}", e),
+            Error::Snapshot(e) => return write!(f, "Invalid snapshot: {}", e),
+            Error::InFile { path, source } => return write!(f, "{}: {}", path.display(), source),
+            Error::MissingColumn { column } => {
+                return write!(f, "The input has no '{}' column", column)
+            }
+            Error::NegativeBalance { line, client, tx } => {
+                if let Some(line) = line {
+                    write!(f, "Line {}: ", line)?;
+                }
+                return write!(
+                    f,
+                    "transaction {} would leave the funds of client {} negative",
+                    tx, client
+                );
+            }
+            Error::InvalidHeader {
+                expected,
+                found,
+                problem,
+            } => {
+                write!(
+                    f,
+                    "Invalid header: expected '{}', found '{}': ",
+                    expected.join(", "),
+                    found.join(", ")
+                )?;
+                return match problem {
+                    HeaderProblem::MissingColumn(column) => {
+                        write!(f, "there is no '{}' column", column)
+                    }
+                    HeaderProblem::Misspelt { column, name } => {
+                        write!(f, "'{}' looks like a misspelling of '{}'", name, column)
+                    }
+                    HeaderProblem::NoHeaderRow => {
+                        write!(f, "that looks like a row of data rather than a header")
+                    }
+                };
+            }
+            _ => (),
+        }
+
+        match self.line() {
+            Some(line) => write!(f, "Invalid record on line {}: ", line)?,
+            None => write!(f, "Invalid record: ")?,
+        }
+
+        match self {
+            Error::InvalidUtf8 { field, .. } => write!(f, "field {} is not valid UTF-8", field),
+            Error::ColumnCount {
+                expected, found, ..
+            } => write!(f, "expected {} columns, found {}", expected, found),
+            Error::MissingField { column, .. } => write!(f, "column '{}' is empty", column),
+            Error::UnknownTransactionType { value, .. } => {
+                write!(f, "unknown transaction type '{}'", value)
+            }
+            Error::Parse { column, value, .. } => {
+                write!(f, "could not parse '{}' in column '{}'", value, column)
+            }
+            Error::MissingAmount { .. } => write!(f, "an amount is required"),
+            Error::MissingReason { .. } => write!(f, "an adjustment needs a reason code"),
+            Error::TooPrecise { value, .. } => write!(
+                f,
+                "amount '{}' has more than {} decimal places",
+                value,
+                crate::amount::DECIMAL_PLACES
+            ),
+            Error::Json { source, .. } => write!(f, "{}", source),
+            Error::Io(_)
+            | Error::Csv(_)
+            | Error::MissingColumn { .. }
+            | Error::InvalidHeader { .. }
+            | Error::Snapshot(_)
+            | Error::NegativeBalance { .. }
+            | Error::InFile { .. } => unreachable!(),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Csv(e) => Some(e),
+            Error::Json { source, .. } => Some(source),
+            Error::Snapshot(e) => Some(e),
+            Error::InFile { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Column, Error};
+
+    #[test]
+    fn test_display_with_line() {
+        let e = Error::UnknownTransactionType {
+            line: Some(8),
+            value: "bogus".to_string(),
+        };
+        assert_eq!(
+            e.to_string(),
+            "Invalid record on line 8: unknown transaction type 'bogus'"
+        );
+    }
+
+    #[test]
+    fn test_display_without_line() {
+        let e = Error::Parse {
+            line: None,
+            column: Column::Client,
+            value: "abc".to_string(),
+        };
+        assert_eq!(
+            e.to_string(),
+            "Invalid record: could not parse 'abc' in column 'client'"
+        );
+    }
+
+    #[test]
+    fn test_error_context() {
+        let e = Error::MissingAmount { line: Some(3) };
+        assert_eq!(e.line(), Some(3));
+        assert_eq!(e.column(), Some(Column::Amount));
+    }
+}
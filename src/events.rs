@@ -0,0 +1,465 @@
+use super::amount::Amount;
+use super::currency::Currency;
+use super::engine::{DisputeOutcome, Engine, EngineConfig};
+use super::error::Error;
+use super::id::{ClientId, TxId};
+use super::input::{InputRecord, ReasonCode};
+use super::tenant::Tenant;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Something that happened to the state of an `Engine`. Every record the
+/// engine is given ends up as either a `TransactionApplied` or a
+/// `TransactionRejected` event, and an applied record is followed by the
+/// events it led to, such as a dispute being opened or an account being
+/// locked.
+///
+/// Only the applied records are needed to rebuild the state, see `replay`.
+/// The other events are there for whoever reads the log as an audit trail,
+/// so they do not have to work out what a record did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TransactionApplied {
+        record: InputRecord,
+    },
+    TransactionRejected {
+        record: InputRecord,
+        reason: String,
+    },
+    /// A fee was charged along with a deposit or withdrawal, see
+    /// `FeeSchedule`.
+    FeeCharged {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+    },
+    /// The balance was corrected by hand, by an adjustment. A debit comes
+    /// with a negative amount.
+    BalanceAdjusted {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+        reason: ReasonCode,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+    },
+    DisputeChargedBack {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+    },
+    /// A dispute was closed because it had been open for too long, see
+    /// `DisputeExpiry`. Unlike the other events, this does not follow a
+    /// record, but comes before the first record after the dispute expired.
+    DisputeExpired {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+        outcome: DisputeOutcome,
+    },
+    /// A dispute has been open for one of the ages of a `DisputeEscalation`.
+    /// Like an expired dispute, this comes before the first record after it
+    /// reached the age.
+    DisputeEscalated {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        amount: Amount,
+        days: u32,
+    },
+    AccountOpened {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+    },
+    AccountClosed {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+    },
+    AccountFrozen {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+    },
+    AccountUnfrozen {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+    },
+    /// The lock a chargeback put on the account was lifted.
+    AccountUnlocked {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+    },
+    /// A balance check found the account holding another total than the one
+    /// it states.
+    BalanceMismatch {
+        client: ClientId,
+        tx: TxId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+        expected: Amount,
+        actual: Amount,
+    },
+    /// The account was locked by a chargeback.
+    AccountLocked {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<Tenant>,
+    },
+}
+
+/// An `Event` as it is kept in the log: numbered, and stamped with the time
+/// it was recorded, which has nothing to do with the timestamp of the record
+/// behind it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub seq: u64,
+    pub recorded_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// An `EventLog` collects the events of an `Engine` it is attached to with
+/// `Engine::set_event_log`. The log is append-only: events are never changed
+/// or removed once written, and `append_to` only ever adds to the end of the
+/// file.
+///
+/// On disk, a log is a JSON Lines file with one `EventRecord` per line, the
+/// kind of event in its `event` field. Sequence numbers start at 1 and carry
+/// on from one run to the next.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventLog {
+    /// The sequence number of the next event.
+    next: u64,
+    /// The events that have not been written out yet.
+    events: Vec<EventRecord>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog {
+            next: 1,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog::default()
+    }
+
+    /// Prepares to append to the log at `path`, numbering the events after
+    /// the ones already in it. A log that does not exist yet is simply empty.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<EventLog, Error> {
+        let events = match File::open(path) {
+            Ok(file) => read_events(file)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(EventLog {
+            next: events.last().map_or(1, |e| e.seq + 1),
+            events: Vec::new(),
+        })
+    }
+
+    /// The events recorded since the log was opened or last written out.
+    pub fn events(&self) -> &[EventRecord] {
+        &self.events
+    }
+
+    pub(crate) fn push(&mut self, event: Event) {
+        self.events.push(EventRecord {
+            seq: self.next,
+            recorded_at: DateTime::<Utc>::from(SystemTime::now()),
+            event,
+        });
+        self.next += 1;
+    }
+
+    /// Forgets the events recorded after the first `len`, numbering the
+    /// next event as though they never were, for a strict batch that is
+    /// undone, see `Engine::apply_batch`.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len < self.events.len() {
+            self.next -= (self.events.len() - len) as u64;
+            self.events.truncate(len);
+        }
+    }
+
+    /// Appends the events recorded so far to the file at `path`, creating it
+    /// if need be, and forgets about them.
+    pub fn append_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        if self.events.is_empty() {
+            return Ok(());
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        self.events.clear();
+        Ok(())
+    }
+
+    /// Writes the events recorded so far to any writer, one per line.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        for event in &self.events {
+            serde_json::to_writer(&mut writer, event).map_err(std::io::Error::from)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Adds the events of `other` after those of this log, numbering them
+    /// anew. Used to put the logs of `Engine::split` back together.
+    pub(crate) fn merge(&mut self, other: EventLog) {
+        for record in other.events {
+            self.events.push(EventRecord {
+                seq: self.next,
+                ..record
+            });
+            self.next += 1;
+        }
+    }
+}
+
+/// Reads every event of a log written by `EventLog::append_to`, from any
+/// reader. Empty lines are skipped.
+pub fn read_events<R: Read>(reader: R) -> Result<Vec<EventRecord>, Error> {
+    let mut events = Vec::new();
+    for (n, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|source| Error::Json {
+            line: Some(n as u64 + 1),
+            source,
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Rebuilds the state of an engine from the events of a log, by applying
+/// every applied record in it once more, in order, to an engine with
+/// `config`. This has to be the configuration the records were applied
+/// with in the first place: a record that no longer applies means the log
+/// and the configuration do not match, and is an error.
+pub fn replay<I>(events: I, config: EngineConfig) -> Result<Engine, Error>
+where
+    I: IntoIterator<Item = EventRecord>,
+{
+    let mut engine = Engine::with_config(config);
+    for EventRecord { seq, event, .. } in events {
+        let Event::TransactionApplied { record } = event else {
+            continue;
+        };
+        engine.apply(record).map_err(|reason| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("event {} no longer applies: {}", seq, reason),
+            )
+        })?;
+    }
+    Ok(engine)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{read_events, replay, Event, EventLog};
+    use crate::amount::Amount;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::id::ClientId;
+    use crate::input::{make_input_record, InputRecord};
+
+    fn record(s: &str) -> InputRecord {
+        make_input_record(&csv::StringRecord::from(s.split(',').collect::<Vec<_>>())).unwrap()
+    }
+
+    fn kinds(log: &EventLog) -> Vec<String> {
+        log.events()
+            .iter()
+            .map(|e| {
+                serde_json::to_value(e).unwrap()["event"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_engine_logs_events() {
+        let mut engine = Engine::new();
+        engine.set_event_log(EventLog::new());
+        engine.apply(record("deposit,1,1,5.0")).unwrap();
+        engine.apply(record("withdrawal,1,2,9.0")).unwrap_err();
+        engine.apply(record("dispute,1,1,")).unwrap();
+        engine.apply(record("chargeback,1,1,")).unwrap();
+        engine.apply(record("unlock,1,3,")).unwrap();
+
+        let log = engine.event_log().unwrap();
+        assert_eq!(
+            kinds(log),
+            [
+                "transaction_applied",
+                "transaction_rejected",
+                "transaction_applied",
+                "dispute_opened",
+                "transaction_applied",
+                "dispute_charged_back",
+                "account_locked",
+                "transaction_applied",
+                "account_unlocked",
+            ]
+        );
+        let seqs: Vec<u64> = log.events().iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        match &log.events()[1].event {
+            Event::TransactionRejected { reason, .. } => assert_eq!(reason, "insufficient funds"),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_engine_logs_adjustments() {
+        let mut engine = Engine::new();
+        engine.set_event_log(EventLog::new());
+        engine.apply(record("deposit,1,1,5.0")).unwrap();
+        engine
+            .apply(record("debit_adjustment,1,2,1.5,,,DUPLICATE_PAYOUT"))
+            .unwrap();
+
+        let log = engine.event_log().unwrap();
+        assert_eq!(kinds(log)[1..], ["transaction_applied", "balance_adjusted"]);
+        match &log.events()[2].event {
+            Event::BalanceAdjusted { amount, reason, .. } => {
+                assert_eq!(*amount, Amount::from_scaled(-15_000));
+                assert_eq!(reason.code(), "DUPLICATE_PAYOUT");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // The reason is kept with the record, so a replay applies it again.
+        let replayed = replay(log.events().to_vec(), EngineConfig::default()).unwrap();
+        assert_eq!(replayed.balances(), engine.balances());
+    }
+
+    #[test]
+    fn test_replay_rebuilds_state() {
+        let mut engine = Engine::new();
+        engine.set_event_log(EventLog::new());
+        for s in [
+            "deposit,1,1,5.0",
+            "deposit,2,2,3.0",
+            "withdrawal,1,3,1.5",
+            "withdrawal,2,4,9.0",
+            "dispute,2,2,",
+        ] {
+            let _ = engine.apply(record(s));
+        }
+
+        let mut buf = Vec::new();
+        engine.event_log().unwrap().write_to(&mut buf).unwrap();
+        let events = read_events(buf.as_slice()).unwrap();
+        assert_eq!(events, engine.event_log().unwrap().events());
+
+        let replayed = replay(events, EngineConfig::default()).unwrap();
+        let mut expected = engine.balances();
+        let mut balances = replayed.balances();
+        expected.sort_by_key(|b| b.client);
+        balances.sort_by_key(|b| b.client);
+        assert_eq!(balances, expected);
+        assert_eq!(replayed.open_disputes(), 1);
+    }
+
+    #[test]
+    fn test_open_continues_numbering() {
+        let dir = std::env::temp_dir().join(format!("payments-events-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let mut engine = Engine::new();
+        engine.set_event_log(EventLog::open(&path).unwrap());
+        engine.apply(record("deposit,1,1,5.0")).unwrap();
+        engine.event_log_mut().unwrap().append_to(&path).unwrap();
+        assert!(engine.event_log().unwrap().events().is_empty());
+
+        let mut log = EventLog::open(&path).unwrap();
+        log.push(Event::AccountOpened {
+            client: ClientId::new(2),
+            currency: None,
+            tenant: None,
+        });
+        assert_eq!(log.events()[0].seq, 2);
+        log.append_to(&path).unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let events = read_events(file).unwrap();
+        assert_eq!(events.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
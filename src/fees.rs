@@ -0,0 +1,162 @@
+use super::amount::Amount;
+use chrono::{DateTime, Datelike, Utc};
+use std::str::FromStr;
+
+/// The fees the `Engine` charges on its own, as set in `EngineConfig::fees`.
+/// Every fee is taken from the available funds of the account and shows up
+/// as a `fee` line in its statement, right after the transaction that led
+/// to it.
+///
+/// * A flat fee on every withdrawal. A withdrawal is only applied if the
+///   account can cover the fee as well.
+/// * A percentage of every deposit, rounded half to even to the precision of
+///   an `Amount`.
+/// * A monthly fee per account, charged with the first deposit or
+///   withdrawal of every calendar month, going by their timestamps. Months
+///   without any are charged for with the next one, while records without a
+///   timestamp never are. Unlike the other fees, it never takes the
+///   available funds below zero: whatever they cannot cover is waived.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub withdrawal: Amount,
+    /// The percentage of a deposit taken as a fee, e.g. `0.5` for half a
+    /// percent. Between 0 and 100.
+    pub deposit_percent: Amount,
+    pub monthly: Amount,
+}
+
+impl FeeSchedule {
+    /// The fee on a deposit of `amount`.
+    pub fn deposit_fee(&self, amount: Amount) -> Amount {
+        let product = i128::from(amount.scaled()) * i128::from(self.deposit_percent.scaled());
+        // The percentage is scaled like an `Amount`, by ten thousand, and a
+        // percent is a hundredth on top of that.
+        let divisor: i128 = 10_000 * 100;
+        let (quotient, remainder) = (product / divisor, product % divisor);
+        let round_up = match (2 * remainder).cmp(&divisor) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Equal => quotient % 2 == 1,
+            std::cmp::Ordering::Less => false,
+        };
+        // The fee is never more than the deposit itself, so it fits.
+        Amount::from_scaled((quotient + i128::from(round_up)) as i64)
+    }
+
+    /// The monthly fees owed at `timestamp` by an account last charged in
+    /// `billed`, a month as returned by `month`. An account that was never
+    /// charged owes the fee of the current month only.
+    pub(crate) fn monthly_fees(&self, billed: Option<i32>, timestamp: DateTime<Utc>) -> Amount {
+        let months = match billed {
+            Some(billed) => month(timestamp) - billed,
+            None => 1,
+        };
+        if months <= 0 {
+            return Amount::ZERO;
+        }
+        Amount::from_scaled(self.monthly.scaled().saturating_mul(i64::from(months)))
+    }
+}
+
+/// The calendar month `timestamp` falls in, counted from the year 0, so
+/// consecutive months are consecutive numbers.
+pub(crate) fn month(timestamp: DateTime<Utc>) -> i32 {
+    timestamp.year() * 12 + timestamp.month0() as i32
+}
+
+/// A fee schedule is given as a comma-separated list of the fees to charge,
+/// e.g. `withdrawal=1,deposit=0.5%,monthly=5`. Fees that are left out are
+/// not charged.
+impl FromStr for FeeSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut schedule = FeeSchedule::default();
+        for fee in s.split(',') {
+            let (name, value) = fee
+                .split_once('=')
+                .ok_or_else(|| format!("invalid fee '{}', expected <name>=<amount>", fee))?;
+            let name = name.trim().to_lowercase();
+            let value = value.trim();
+            let amount = |value: &str| match value.parse::<Amount>() {
+                Ok(amount) if !amount.is_negative() => Ok(amount),
+                _ => Err(format!("invalid {} fee '{}'", name, value)),
+            };
+            match name.as_str() {
+                "withdrawal" => schedule.withdrawal = amount(value)?,
+                "deposit" => {
+                    let percent = amount(value.strip_suffix('%').unwrap_or(value))?;
+                    if percent > Amount::from_scaled(1_000_000) {
+                        return Err(format!("deposit fee '{}' is over 100%", value));
+                    }
+                    schedule.deposit_percent = percent;
+                }
+                "monthly" => schedule.monthly = amount(value)?,
+                _ => return Err(format!("unknown fee '{}'", name)),
+            }
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::FeeSchedule;
+    use crate::amount::Amount;
+    use crate::input::parse_timestamp;
+
+    #[test]
+    fn test_parse_fee_schedule() {
+        let schedule: FeeSchedule = "withdrawal=1, deposit=0.5%,Monthly=2.5".parse().unwrap();
+        assert_eq!(
+            schedule,
+            FeeSchedule {
+                withdrawal: Amount::from_scaled(10_000),
+                deposit_percent: Amount::from_scaled(5_000),
+                monthly: Amount::from_scaled(25_000),
+            }
+        );
+        for s in [
+            "",
+            "withdrawal",
+            "withdrawal=-1",
+            "deposit=101%",
+            "yearly=1",
+        ] {
+            assert!(s.parse::<FeeSchedule>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn test_deposit_fee_rounds_half_to_even() {
+        let schedule: FeeSchedule = "deposit=1%".parse().unwrap();
+        assert_eq!(
+            schedule.deposit_fee("200".parse().unwrap()),
+            "2".parse().unwrap()
+        );
+        assert_eq!(schedule.deposit_fee(Amount::from_scaled(50)), Amount::ZERO);
+        assert_eq!(
+            schedule.deposit_fee(Amount::from_scaled(150)),
+            Amount::from_scaled(2)
+        );
+        assert_eq!(
+            schedule.deposit_fee(Amount::from_scaled(151)),
+            Amount::from_scaled(2)
+        );
+    }
+
+    #[test]
+    fn test_monthly_fees() {
+        let schedule: FeeSchedule = "monthly=2".parse().unwrap();
+        let march = parse_timestamp("2024-03-31T23:59:59Z").unwrap();
+        let january = super::month(parse_timestamp("2024-01-01T00:00:00Z").unwrap());
+        assert_eq!(schedule.monthly_fees(None, march), "2".parse().unwrap());
+        assert_eq!(
+            schedule.monthly_fees(Some(january), march),
+            "4".parse().unwrap()
+        );
+        assert_eq!(
+            schedule.monthly_fees(Some(super::month(march)), march),
+            Amount::ZERO
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use super::engine::Engine;
+use super::input::{parse_message, TransactionTypeAliases};
+use super::output::{dump_result_to_writer, OutputFormat};
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// `payments_engine_apply_csv_line` applied the transaction.
+pub const PAYMENTS_APPLIED: c_int = 0;
+/// `payments_engine_apply_csv_line` could not parse the line.
+pub const PAYMENTS_INVALID: c_int = 1;
+/// The engine refused the transaction, such as a withdrawal without the
+/// funds for it.
+pub const PAYMENTS_REJECTED: c_int = 2;
+/// A pointer handed in was null, or the line was not valid UTF-8.
+pub const PAYMENTS_BAD_ARGUMENT: c_int = -1;
+/// The engine panicked, which is a bug. The state of the engine is not to
+/// be trusted afterwards, so it is best freed.
+pub const PAYMENTS_PANICKED: c_int = -2;
+
+thread_local! {
+    /// Why the last call on this thread did not succeed, see
+    /// `payments_last_error`.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    // A C string cannot hold a NUL byte, even if a line quoted in the
+    // message does.
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `body`, or returns `failed` if it panics, as unwinding into C code
+/// would abort the program that called it. The panic is told by
+/// `payments_last_error` like any other failure.
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|panic| {
+        set_last_error(format!("the engine panicked: {}", panic_message(&*panic)));
+        failed
+    })
+}
+
+/// What a panic said, if it said it with a string, as `panic!` does.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", |m| m),
+    }
+}
+
+/// An engine owned by C code, along with the names the transaction types
+/// are known by, which are the built-in ones only.
+pub struct PaymentsEngine {
+    engine: Engine,
+    aliases: TransactionTypeAliases,
+}
+
+/// Creates an engine with the default policies. It has to be handed back
+/// to `payments_engine_free` once it is no longer needed.
+#[no_mangle]
+pub extern "C" fn payments_engine_new() -> *mut PaymentsEngine {
+    Box::into_raw(Box::new(PaymentsEngine {
+        engine: Engine::new(),
+        aliases: TransactionTypeAliases::new(),
+    }))
+}
+
+/// Parses a single CSV row without a header, with the columns type, client,
+/// tx and amount, and applies it to `engine`. Returns `PAYMENTS_APPLIED`,
+/// or else one of the other codes, and `payments_last_error` tells why.
+/// A panic is caught and returned as `PAYMENTS_PANICKED`.
+///
+/// # Safety
+///
+/// `engine` has to come from `payments_engine_new` and not be freed yet,
+/// nor used by another thread at the same time, and `line` has to point
+/// at a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_apply_csv_line(
+    engine: *mut PaymentsEngine,
+    line: *const c_char,
+) -> c_int {
+    guard(PAYMENTS_PANICKED, || {
+        // SAFETY: the caller promises both are valid if they are not null.
+        let (Some(engine), false) = (unsafe { engine.as_mut() }, line.is_null()) else {
+            set_last_error("engine and line cannot be null");
+            return PAYMENTS_BAD_ARGUMENT;
+        };
+        let line = match unsafe { CStr::from_ptr(line) }.to_str() {
+            Ok(line) => line,
+            Err(e) => {
+                set_last_error(e);
+                return PAYMENTS_BAD_ARGUMENT;
+            }
+        };
+        let record = match parse_message(line.trim_end_matches(['\r', '\n']), &engine.aliases) {
+            Ok(record) => record,
+            Err(e) => {
+                set_last_error(e);
+                return PAYMENTS_INVALID;
+            }
+        };
+        match engine.engine.apply(record) {
+            Ok(_) => PAYMENTS_APPLIED,
+            Err(reason) => {
+                set_last_error(reason);
+                PAYMENTS_REJECTED
+            }
+        }
+    })
+}
+
+/// Returns the balances of every client as a JSON array, just as
+/// `--output-format json` writes them, or null if they cannot be written
+/// or the engine panicked. The string has to be handed back to
+/// `payments_string_free`.
+///
+/// # Safety
+///
+/// `engine` has to come from `payments_engine_new` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_dump_json(engine: *const PaymentsEngine) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        // SAFETY: the caller promises it is valid if it is not null.
+        let Some(engine) = (unsafe { engine.as_ref() }) else {
+            set_last_error("engine cannot be null");
+            return ptr::null_mut();
+        };
+        let mut buf = Vec::new();
+        let balances = engine.engine.balances();
+        if let Err(e) = dump_result_to_writer(&mut buf, balances, OutputFormat::Json) {
+            set_last_error(e);
+            return ptr::null_mut();
+        }
+        // JSON never holds a NUL byte, it would be escaped.
+        match CString::new(buf) {
+            Ok(json) => json.into_raw(),
+            Err(e) => {
+                set_last_error(e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Frees a string returned by `payments_engine_dump_json`. Null is ignored.
+///
+/// # Safety
+///
+/// `s` has to come from `payments_engine_dump_json` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn payments_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: the caller promises it came from `CString::into_raw`.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+/// Frees an engine created by `payments_engine_new`. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` has to come from `payments_engine_new` and not be freed yet,
+/// and is no longer valid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_free(engine: *mut PaymentsEngine) {
+    if !engine.is_null() {
+        // SAFETY: the caller promises it came from `Box::into_raw`.
+        drop(unsafe { Box::from_raw(engine) });
+    }
+}
+
+/// Why the last call on this thread that did not succeed failed, or null
+/// if none did. The string belongs to the library and stays valid until
+/// the next call that fails on the same thread.
+#[no_mangle]
+pub extern "C" fn payments_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        guard, payments_engine_apply_csv_line, payments_engine_dump_json, payments_engine_free,
+        payments_engine_new, payments_last_error, payments_string_free, PAYMENTS_APPLIED,
+        PAYMENTS_BAD_ARGUMENT, PAYMENTS_INVALID, PAYMENTS_PANICKED, PAYMENTS_REJECTED,
+    };
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    #[test]
+    fn test_engine_through_c_api() {
+        let engine = payments_engine_new();
+        let apply = |line: &str| {
+            let line = CString::new(line).unwrap();
+            unsafe { payments_engine_apply_csv_line(engine, line.as_ptr()) }
+        };
+        let last_error = || unsafe { CStr::from_ptr(payments_last_error()) }.to_str();
+
+        assert_eq!(apply("deposit,1,1,2.5\n"), PAYMENTS_APPLIED);
+        assert_eq!(apply("withdrawal, 1, 2, 1.0"), PAYMENTS_APPLIED);
+        assert_eq!(apply("withdrawal,1,3,10.0"), PAYMENTS_REJECTED);
+        assert_eq!(last_error(), Ok("insufficient funds"));
+        assert_eq!(apply("bogus,1,4,1.0"), PAYMENTS_INVALID);
+        assert_eq!(
+            unsafe { payments_engine_apply_csv_line(engine, ptr::null()) },
+            PAYMENTS_BAD_ARGUMENT
+        );
+
+        let json = unsafe { payments_engine_dump_json(engine) };
+        assert_eq!(
+            unsafe { CStr::from_ptr(json) }.to_str(),
+            Ok("[{\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false}]\n")
+        );
+        unsafe {
+            payments_string_free(json);
+            payments_engine_free(engine);
+        }
+        assert!(unsafe { payments_engine_dump_json(ptr::null()) }.is_null());
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        let last_error = || unsafe { CStr::from_ptr(payments_last_error()) }.to_str();
+        assert_eq!(
+            guard(PAYMENTS_PANICKED, || PAYMENTS_APPLIED),
+            PAYMENTS_APPLIED
+        );
+        let code = guard(PAYMENTS_PANICKED, || panic!("balances out of {}", "step"));
+        assert_eq!(code, PAYMENTS_PANICKED);
+        assert_eq!(
+            last_error(),
+            Ok("the engine panicked: balances out of step")
+        );
+        assert!(guard(ptr::null_mut::<u8>(), || panic!("oops")).is_null());
+        assert_eq!(last_error(), Ok("the engine panicked: oops"));
+    }
+}
@@ -0,0 +1,187 @@
+use super::amount::Amount;
+use super::error::Error;
+use super::id::{ClientId, TxId};
+use super::input::TransactionType;
+use super::output::write_to_path;
+use std::io::Write;
+use std::path::Path;
+
+/// What `generate` makes up: how many clients and rows, how often a
+/// deposit is disputed, and the seed every choice follows from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GenerateOptions {
+    pub clients: u64,
+    pub rows: u64,
+    /// The share of rows that dispute an earlier deposit, from 0 to 1. Just
+    /// as many rows close a dispute again.
+    pub dispute_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            clients: 1000,
+            rows: 100_000,
+            dispute_rate: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// A SplitMix64 generator. It is not much of a random number generator,
+/// but it is more than enough for test data, and unlike those of a crate
+/// its sequence never changes, so a seed makes the same file forever.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, which has to be above zero. The slight bias
+    /// towards small numbers does not matter here.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Whether something with the probability `p` happens.
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// The most deposits kept around to be disputed. Disputes pick from the
+/// most recent ones, as they tend to in real life.
+const DISPUTABLE: usize = 4096;
+
+/// Makes up a CSV file of random transactions, with the columns type,
+/// client, tx and amount, for load tests and benchmarks. Deposits and
+/// withdrawals come from clients picked at random, and amounts have up to
+/// four decimal places. Withdrawals mostly stay within what the client
+/// deposited, but now and then one does not. A share of the deposits is
+/// disputed, and every dispute is resolved or, less often, charged back a
+/// while later, which locks the account. The same options always make the
+/// same file.
+pub fn generate<W: Write>(writer: W, options: &GenerateOptions) -> Result<(), Error> {
+    let mut rng = SplitMix64(options.seed);
+    let clients = options.clients.max(1);
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(["type", "client", "tx", "amount"])?;
+    // What every client holds, as far as deposits and withdrawals go, which
+    // is all it takes to keep most withdrawals covered, and whether it was
+    // locked by a chargeback, after which it is left alone.
+    let mut balances = vec![0i64; clients as usize];
+    let mut locked = vec![false; clients as usize];
+    let mut disputable: Vec<(u64, u64)> = Vec::with_capacity(DISPUTABLE);
+    let mut disputed: Vec<(u64, u64)> = Vec::new();
+    let mut next_tx = 1;
+    for _ in 0..options.rows {
+        let (r#type, client, tx, amount) = if !disputed.is_empty()
+            && rng.chance(options.dispute_rate)
+        {
+            let (client, tx) = disputed.swap_remove(rng.below(disputed.len() as u64) as usize);
+            let r#type = if rng.chance(0.2) {
+                // Nothing of a locked client is worth disputing any more.
+                locked[client as usize] = true;
+                disputable.retain(|&(c, _)| c != client);
+                disputed.retain(|&(c, _)| c != client);
+                TransactionType::Chargeback
+            } else {
+                TransactionType::Resolve
+            };
+            (r#type, client, tx, None)
+        } else if !disputable.is_empty() && rng.chance(options.dispute_rate) {
+            let (client, tx) = disputable.swap_remove(rng.below(disputable.len() as u64) as usize);
+            disputed.push((client, tx));
+            (TransactionType::Dispute, client, tx, None)
+        } else {
+            // Until most clients are locked, that is.
+            let mut client = rng.below(clients);
+            for _ in 0..8 {
+                if !locked[client as usize] {
+                    break;
+                }
+                client = rng.below(clients);
+            }
+            let balance = &mut balances[client as usize];
+            let tx = next_tx;
+            next_tx += 1;
+            if *balance > 0 && rng.chance(0.4) {
+                // One in twenty is more than the client has.
+                let amount = match rng.chance(0.05) {
+                    true => *balance + 1 + rng.below(100_000) as i64,
+                    false => 1 + rng.below(*balance as u64) as i64,
+                };
+                *balance -= amount.min(*balance);
+                (TransactionType::Withdrawal, client, tx, Some(amount))
+            } else {
+                let amount = 1 + rng.below(10_000_000) as i64;
+                *balance += amount;
+                if disputable.len() == DISPUTABLE {
+                    disputable.swap_remove(rng.below(DISPUTABLE as u64) as usize);
+                }
+                disputable.push((client, tx));
+                (TransactionType::Deposit, client, tx, Some(amount))
+            }
+        };
+        let amount = amount.map(|a| Amount::from_scaled(a).to_string());
+        writer.write_record([
+            r#type.to_string(),
+            ClientId::new(client + 1).to_string(),
+            TxId::new(tx).to_string(),
+            amount.unwrap_or_default(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes what `generate` makes up to a file. The file is replaced just
+/// like `output::dump_result_to_path` does, compressed if its name asks
+/// for it.
+pub fn generate_to_path<P: AsRef<Path>>(path: P, options: &GenerateOptions) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| generate(file, options))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{generate, GenerateOptions};
+    use crate::process_reader;
+
+    #[test]
+    fn test_generate() {
+        let options = GenerateOptions {
+            clients: 50,
+            rows: 5000,
+            dispute_rate: 0.05,
+            seed: 7,
+        };
+        let mut buf = Vec::new();
+        generate(&mut buf, &options).unwrap();
+        let mut again = Vec::new();
+        generate(&mut again, &options).unwrap();
+        assert_eq!(buf, again);
+        let mut other = Vec::new();
+        generate(&mut other, &GenerateOptions { seed: 8, ..options }).unwrap();
+        assert_ne!(buf, other);
+
+        let csv = String::from_utf8(buf).unwrap();
+        assert_eq!(csv.lines().count(), 5001);
+        assert!(csv.starts_with("type,client,tx,amount\n"));
+        for r#type in ["dispute", "resolve", "chargeback", "withdrawal"] {
+            assert!(csv.contains(&format!("\n{},", r#type)), "no {}", r#type);
+        }
+
+        // Every row parses, and most of them apply.
+        let processed = process_reader(csv.as_bytes()).unwrap();
+        let summary = processed.summary();
+        assert_eq!(summary.records, 5000);
+        assert_eq!(summary.invalid, 0);
+        assert!(summary.rejected < 250, "{} rejected", summary.rejected);
+    }
+}
@@ -0,0 +1,268 @@
+use super::error::Error;
+use super::input::InputFormat;
+use super::output::{dump_rejects_to_writer, dump_result_to_writer, OutputFormat};
+use super::{process_reader_with, ProcessOptions};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Set to any value but `0` to write what the scenarios produce to their
+/// expected files, rather than comparing against them.
+pub const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+/// A scenario of the golden-file tests: an input file, `<name>.csv` or
+/// `<name>.jsonl`, and next to it the balances it is expected to result
+/// in, `<name>.expected.csv`. If there is a `<name>.rejects.csv` as well,
+/// the rows that were skipped, and why, are checked too.
+///
+/// Both are compared as written by the program, with the balances sorted
+/// by client, so the outcome does not depend on how the engine happens to
+/// store the accounts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scenario {
+    pub name: String,
+    pub input: PathBuf,
+    pub format: InputFormat,
+}
+
+/// What processing the input of a scenario resulted in, as CSV.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    pub balances: String,
+    pub rejects: String,
+}
+
+/// An expected file of a scenario that does not match what the scenario
+/// produces, or does not exist at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub scenario: String,
+    pub path: PathBuf,
+    /// `None` if the expected file is missing.
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+impl Scenario {
+    /// The file holding the balances the scenario is expected to result in.
+    pub fn expected_path(&self) -> PathBuf {
+        self.input
+            .with_file_name(format!("{}.expected.csv", self.name))
+    }
+
+    /// The file holding the rejected rows the scenario is expected to
+    /// result in, which need not exist.
+    pub fn rejects_path(&self) -> PathBuf {
+        self.input
+            .with_file_name(format!("{}.rejects.csv", self.name))
+    }
+
+    /// Processes the input of the scenario with `options`, reading it in
+    /// the format of the scenario whatever `options` says.
+    pub fn run(&self, options: &ProcessOptions) -> Result<Outcome, Error> {
+        let options = ProcessOptions {
+            format: self.format,
+            ..options.clone()
+        };
+        let file = File::open(&self.input).map_err(|e| Error::Io(e).in_file(&self.input))?;
+        let mut processed = process_reader_with(file, &options)?;
+
+        let mut balances = processed.engine.balances();
+        balances.sort_by_key(|b| (b.client, b.currency));
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, balances, OutputFormat::Csv)?;
+        let mut outcome = Outcome {
+            balances: String::from_utf8_lossy(&buf).into_owned(),
+            ..Outcome::default()
+        };
+
+        processed.rejects.sort_by_key(|r| r.line);
+        buf.clear();
+        dump_rejects_to_writer(&mut buf, &processed.rejects)?;
+        outcome.rejects = String::from_utf8_lossy(&buf).into_owned();
+        Ok(outcome)
+    }
+
+    /// Runs the scenario and compares the outcome with the expected files,
+    /// or writes it to them if `UPDATE_VAR` is set. Returns the files that
+    /// did not match.
+    pub fn check(&self, options: &ProcessOptions) -> Result<Vec<Mismatch>, Error> {
+        let outcome = self.run(options)?;
+        let rejects = self.rejects_path();
+        let mut files = vec![(self.expected_path(), outcome.balances)];
+        if rejects.exists() {
+            files.push((rejects, outcome.rejects));
+        }
+
+        let update = std::env::var(UPDATE_VAR).is_ok_and(|v| !v.is_empty() && v != "0");
+        let mut mismatches = Vec::new();
+        for (path, actual) in files {
+            if update {
+                fs::write(&path, &actual).map_err(|e| Error::Io(e).in_file(&path))?;
+                continue;
+            }
+            // A checkout may have turned the line endings into CRLF.
+            let expected = fs::read_to_string(&path)
+                .ok()
+                .map(|s| s.replace("\r\n", "\n"));
+            if expected.as_deref() != Some(actual.as_str()) {
+                mismatches.push(Mismatch {
+                    scenario: self.name.clone(),
+                    path,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/// Finds the scenarios in `dir`, sorted by name. Every CSV or JSON Lines
+/// file is the input of a scenario, apart from the expected files.
+pub fn scenarios<P: AsRef<Path>>(dir: P) -> Result<Vec<Scenario>, Error> {
+    let dir = dir.as_ref();
+    let entries = fs::read_dir(dir).map_err(|e| Error::Io(e).in_file(dir))?;
+    let mut scenarios = Vec::new();
+    for entry in entries {
+        let input = entry.map_err(|e| Error::Io(e).in_file(dir))?.path();
+        let Some(file_name) = input.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let (name, format) = match file_name.rsplit_once('.') {
+            Some((name, "csv")) => (name, InputFormat::Csv),
+            Some((name, "jsonl")) => (name, InputFormat::JsonLines),
+            _ => continue,
+        };
+        if name.ends_with(".expected") || name.ends_with(".rejects") {
+            continue;
+        }
+        scenarios.push(Scenario {
+            name: name.to_string(),
+            input,
+            format,
+        });
+    }
+    scenarios.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(scenarios)
+}
+
+/// Checks every scenario in `dir`, see `Scenario::check`, and returns all
+/// the files that did not match.
+pub fn check_dir<P: AsRef<Path>>(dir: P, options: &ProcessOptions) -> Result<Vec<Mismatch>, Error> {
+    let mut mismatches = Vec::new();
+    for scenario in scenarios(dir)? {
+        mismatches.extend(scenario.check(options)?);
+    }
+    Ok(mismatches)
+}
+
+/// Checks every scenario in `dir` and panics with a description of every
+/// mismatch, for use in a `#[test]`. A directory without any scenarios is
+/// taken for a mistake, so it fails as well.
+pub fn assert_dir<P: AsRef<Path>>(dir: P, options: &ProcessOptions) {
+    let dir = dir.as_ref();
+    match scenarios(dir) {
+        Ok(scenarios) if scenarios.is_empty() => {
+            panic!("no golden-file scenarios in {}", dir.display())
+        }
+        Ok(_) => (),
+        Err(e) => panic!("{}", e),
+    }
+    let mismatches = check_dir(dir, options).unwrap_or_else(|e| panic!("{}", e));
+    if !mismatches.is_empty() {
+        let report: Vec<String> = mismatches.iter().map(Mismatch::to_string).collect();
+        panic!(
+            "{}\n{} golden file(s) did not match; run with {}=1 to update them",
+            report.join("\n"),
+            mismatches.len(),
+            UPDATE_VAR
+        );
+    }
+}
+
+/// Describes the mismatch line by line, with `-` for what was expected and
+/// `+` for what was produced instead.
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(expected) = &self.expected else {
+            return writeln!(
+                f,
+                "scenario '{}': {} is missing",
+                self.scenario,
+                self.path.display()
+            );
+        };
+        writeln!(
+            f,
+            "scenario '{}': {} differs",
+            self.scenario,
+            self.path.display()
+        )?;
+        let expected: Vec<&str> = expected.lines().collect();
+        let actual: Vec<&str> = self.actual.lines().collect();
+        for i in 0..expected.len().max(actual.len()) {
+            match (expected.get(i), actual.get(i)) {
+                (Some(e), Some(a)) if e == a => writeln!(f, "  {}", e)?,
+                (e, a) => {
+                    if let Some(e) = e {
+                        writeln!(f, "- {}", e)?;
+                    }
+                    if let Some(a) = a {
+                        writeln!(f, "+ {}", a)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{check_dir, scenarios};
+    use crate::input::InputFormat;
+    use crate::ProcessOptions;
+    use std::fs;
+
+    #[test]
+    fn test_check_dir() {
+        let dir = std::env::temp_dir().join(format!("payments-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("b.csv"),
+            "type,client,tx,amount\ndeposit,2,1,1.0\ndeposit,1,2,2.0\nbogus,1,3,1.0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("b.expected.csv"),
+            "client,available,held,total,locked\n\
+             1,2.0,0.0,2.0,false\n\
+             2,1.0,0.0,1.0,false\n",
+        )
+        .unwrap();
+        fs::write(dir.join("b.rejects.csv"), "line,raw,reason\n").unwrap();
+        fs::write(dir.join("a.jsonl"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let found = scenarios(&dir).unwrap();
+        let names: Vec<_> = found.iter().map(|s| (s.name.as_str(), s.format)).collect();
+        assert_eq!(
+            names,
+            [("a", InputFormat::JsonLines), ("b", InputFormat::Csv)]
+        );
+
+        let mismatches = check_dir(&dir, &ProcessOptions::default()).unwrap();
+        // The expected balances of `a` are missing, and `b` did skip a row.
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].expected, None);
+        assert!(mismatches[0]
+            .to_string()
+            .contains("a.expected.csv is missing"));
+        assert!(mismatches[1].path.ends_with("b.rejects.csv"));
+        assert!(mismatches[1]
+            .to_string()
+            .contains("+ 4,\"bogus,1,3,1.0\",Invalid record on line 4"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use super::apply_logged;
+use super::engine::Engine;
+use super::error::Error;
+use super::id::ClientId;
+use super::input::{make_input_record, InputRecord, Source, TransactionType};
+use super::output::OutputRecord;
+use csv::StringRecord;
+use futures_util::stream::{self, Iter};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::vec::IntoIter;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+/// The messages and service generated from `proto/payments.proto`.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("payments");
+}
+
+use proto::payments_server::{Payments, PaymentsServer};
+
+/// The gRPC counterpart of `server::router`: the `Payments` service of
+/// `proto/payments.proto`, backed by a shared engine. The engine is only
+/// locked for the duration of a single call.
+pub struct PaymentsService {
+    engine: Arc<Mutex<Engine>>,
+}
+
+impl PaymentsService {
+    pub fn new(engine: Arc<Mutex<Engine>>) -> Self {
+        PaymentsService { engine }
+    }
+
+    /// Wraps the service so it can be added to a `tonic` server.
+    pub fn into_server(self) -> PaymentsServer<Self> {
+        PaymentsServer::new(self)
+    }
+
+    fn engine(&self) -> MutexGuard<'_, Engine> {
+        // Applying a record either completes or changes nothing, so the
+        // engine is consistent even if another call panicked.
+        self.engine.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Serves the `Payments` service on `listener` until `shutdown` completes.
+/// Calls still in flight are finished first, so once this returns the
+/// engine is no longer touched and its state can be saved.
+pub async fn serve<F>(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine>>,
+    shutdown: F,
+) -> Result<(), Error>
+where
+    F: std::future::Future<Output = ()>,
+{
+    info!(address = %listener.local_addr()?, "serving the gRPC API");
+    Server::builder()
+        .add_service(PaymentsService::new(engine).into_server())
+        .serve_with_incoming_shutdown(TcpIncoming::from(listener), shutdown)
+        .await
+        .map_err(|e| Error::Io(std::io::Error::other(e)))
+}
+
+/// Turns a transaction into a CSV row and parses that, so a transaction is
+/// checked exactly like a row of a file and fails with the same messages.
+/// The row is returned as well, as the source of the record.
+fn parse_transaction(transaction: &proto::Transaction) -> Result<(InputRecord, Source), Status> {
+    let transaction_type = match transaction.r#type() {
+        proto::TransactionType::Unspecified => {
+            return Err(Status::invalid_argument("missing transaction type"))
+        }
+        proto::TransactionType::Deposit => TransactionType::Deposit,
+        proto::TransactionType::Withdrawal => TransactionType::Withdrawal,
+        proto::TransactionType::Dispute => TransactionType::Dispute,
+        proto::TransactionType::Resolve => TransactionType::Resolve,
+        proto::TransactionType::Chargeback => TransactionType::Chargeback,
+        proto::TransactionType::Open => TransactionType::Open,
+        proto::TransactionType::Close => TransactionType::Close,
+        proto::TransactionType::Freeze => TransactionType::Freeze,
+        proto::TransactionType::Unfreeze => TransactionType::Unfreeze,
+        proto::TransactionType::Unlock => TransactionType::Unlock,
+        proto::TransactionType::Fee => TransactionType::Fee,
+        proto::TransactionType::BalanceCheck => TransactionType::BalanceCheck,
+        proto::TransactionType::CreditAdjustment => TransactionType::CreditAdjustment,
+        proto::TransactionType::DebitAdjustment => TransactionType::DebitAdjustment,
+    };
+    let client = transaction.client.to_string();
+    let tx = match transaction.tx_id.as_str() {
+        "" => transaction.tx.to_string(),
+        tx_id => tx_id.to_string(),
+    };
+    let row = StringRecord::from(vec![
+        transaction_type.name(),
+        &client,
+        &tx,
+        &transaction.amount,
+        &transaction.timestamp,
+        &transaction.currency,
+        &transaction.reason,
+    ]);
+    let record = make_input_record(&row).map_err(|e| Status::invalid_argument(e.to_string()))?;
+    let source = Source {
+        line: None,
+        raw: row.iter().collect::<Vec<_>>().join(","),
+    };
+    Ok((record, source))
+}
+
+impl From<&OutputRecord> for proto::Balance {
+    fn from(balance: &OutputRecord) -> Self {
+        proto::Balance {
+            client: balance.client.get(),
+            currency: balance.currency.map(|c| c.to_string()).unwrap_or_default(),
+            available: balance.available.to_string(),
+            held: balance.held.to_string(),
+            total: balance.total.to_string(),
+            locked: balance.locked,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Payments for PaymentsService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let (record, source) = parse_transaction(request.get_ref())?;
+        let applied = apply_logged(&mut self.engine(), record, &source)
+            .map_err(|reason| Status::failed_precondition(reason.to_string()))?;
+        Ok(Response::new(proto::SubmitTransactionResponse {
+            balance: Some(proto::Balance::from(&applied.balance)),
+            warning: applied.warning.map(|w| w.to_string()).unwrap_or_default(),
+        }))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::GetAccountResponse>, Status> {
+        let client = ClientId::new(request.get_ref().client);
+        let balances = self.engine().client_balances(client);
+        if balances.is_empty() {
+            return Err(Status::not_found("unknown client"));
+        }
+        Ok(Response::new(proto::GetAccountResponse {
+            balances: balances.iter().map(proto::Balance::from).collect(),
+        }))
+    }
+
+    type StreamBalancesStream = Iter<IntoIter<Result<proto::Balance, Status>>>;
+
+    async fn stream_balances(
+        &self,
+        _: Request<proto::StreamBalancesRequest>,
+    ) -> Result<Response<Self::StreamBalancesStream>, Status> {
+        let mut balances = self.engine().balances();
+        balances.sort_by_key(|b| (b.client, b.currency));
+        let balances: Vec<_> = balances
+            .iter()
+            .map(|b| Ok(proto::Balance::from(b)))
+            .collect();
+        Ok(Response::new(stream::iter(balances)))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::proto::{self, payments_server::Payments};
+    use super::PaymentsService;
+    use crate::engine::Engine;
+    use futures_util::StreamExt;
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
+    use tonic::{Code, Request};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn transaction(
+        r#type: proto::TransactionType,
+        client: u64,
+        tx: u64,
+        amount: &str,
+    ) -> proto::Transaction {
+        proto::Transaction {
+            r#type: r#type.into(),
+            client,
+            tx,
+            amount: amount.to_string(),
+            ..proto::Transaction::default()
+        }
+    }
+
+    #[test]
+    fn test_service() {
+        let service = PaymentsService::new(Arc::new(Mutex::new(Engine::new())));
+        let submit = |t| block_on(service.submit_transaction(Request::new(t)));
+
+        let response = submit(transaction(proto::TransactionType::Deposit, 1, 1, "2.5"))
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.balance.unwrap().available, "2.5");
+        assert_eq!(response.warning, "");
+        let status =
+            submit(transaction(proto::TransactionType::Withdrawal, 1, 2, "5")).unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        let status = submit(proto::Transaction {
+            tx_id: "not an ID".to_string(),
+            ..transaction(proto::TransactionType::Deposit, 70000, 3, "1")
+        })
+        .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status =
+            submit(transaction(proto::TransactionType::Unspecified, 1, 4, "1")).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        submit(transaction(proto::TransactionType::Deposit, 2, 5, "1")).unwrap();
+        submit(proto::Transaction {
+            tx_id: "67e55044-10b1-426f-9247-bb680e5fe0c8".to_string(),
+            ..transaction(proto::TransactionType::Deposit, 70000, 0, "1")
+        })
+        .unwrap();
+
+        let account =
+            block_on(service.get_account(Request::new(proto::GetAccountRequest { client: 1 })))
+                .unwrap()
+                .into_inner();
+        assert_eq!(account.balances.len(), 1);
+        assert_eq!(account.balances[0].total, "2.5");
+        let status =
+            block_on(service.get_account(Request::new(proto::GetAccountRequest { client: 3 })))
+                .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let stream =
+            block_on(service.stream_balances(Request::new(proto::StreamBalancesRequest {})))
+                .unwrap()
+                .into_inner();
+        let clients: Vec<u64> = block_on(stream.map(|b| b.unwrap().client).collect());
+        assert_eq!(clients, vec![1, 2, 70000]);
+    }
+}
@@ -0,0 +1,169 @@
+use super::account::Account;
+use super::engine::RejectReason;
+use super::input::{CustomType, InputRecord};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A `TransactionHandler` applies the records of a custom transaction type,
+/// such as a `bonus` or an `adjustment`, which the engine knows nothing about
+/// by itself. Handlers are registered with `Engine::set_handler`, and the
+/// names of their types as aliases of the input readers, see
+/// `TransactionTypeAliases`.
+///
+/// A handler is given the account of the client of the record, which is
+/// opened for it as for a deposit if need be, and moves money with the
+/// methods of `Account`, just like the engine does for the built-in types.
+/// The handler works on a copy: only if it returns `Ok` does the account
+/// change, so a rejected record still never changes the state of the engine.
+///
+/// The engine does not remember records of custom types, so they cannot be
+/// disputed, and they count towards neither the volumes nor duplicate
+/// detection. Nor are they kept in the journal, so they are applied again
+/// if the same input is fed in twice. They do show up on statements and in
+/// the event log, like any other record.
+pub trait TransactionHandler: Send + Sync {
+    fn apply(&self, record: &InputRecord, account: &mut Account) -> Result<(), RejectReason>;
+}
+
+/// Any function with the right signature is a handler, which is handy for
+/// handlers that do not need any state of their own.
+impl<F> TransactionHandler for F
+where
+    F: Fn(&InputRecord, &mut Account) -> Result<(), RejectReason> + Send + Sync,
+{
+    fn apply(&self, record: &InputRecord, account: &mut Account) -> Result<(), RejectReason> {
+        self(record, account)
+    }
+}
+
+/// The handlers registered with an `Engine`, by the type they handle. They
+/// are shared rather than copied, as every shard of a parallel run needs all
+/// of them.
+#[derive(Clone, Default)]
+pub(crate) struct Handlers(HashMap<CustomType, Arc<dyn TransactionHandler>>);
+
+impl Handlers {
+    pub(crate) fn insert(&mut self, custom: CustomType, handler: Arc<dyn TransactionHandler>) {
+        self.0.insert(custom, handler);
+    }
+
+    pub(crate) fn get(&self, custom: &CustomType) -> Option<Arc<dyn TransactionHandler>> {
+        self.0.get(custom).cloned()
+    }
+
+    pub(crate) fn extend(&mut self, other: Handlers) {
+        self.0.extend(other.0);
+    }
+}
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::TransactionHandler;
+    use crate::account::Account;
+    use crate::amount::Amount;
+    use crate::engine::{AccountPolicy, Engine, EngineConfig, RejectReason};
+    use crate::id::{ClientId, TxId};
+    use crate::input::{CustomType, InputRecord, TransactionType};
+
+    /// Pays out a bonus, which is only ever a positive amount.
+    struct Bonus;
+
+    impl TransactionHandler for Bonus {
+        fn apply(&self, record: &InputRecord, account: &mut Account) -> Result<(), RejectReason> {
+            match record.amount {
+                Some(amount) if amount > Amount::ZERO => account.deposit(amount),
+                _ => Err(RejectReason::MissingAmount),
+            }
+        }
+    }
+
+    fn record(name: &str, client: u64, tx: u64, amount: Option<i64>) -> InputRecord {
+        InputRecord {
+            r#type: TransactionType::Custom(name.parse().unwrap()),
+            client: ClientId::new(client),
+            tx: TxId::new(tx),
+            amount: amount.map(Amount::from_scaled),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
+        }
+    }
+
+    #[test]
+    fn test_handler_applies_custom_type() {
+        let mut engine = Engine::new();
+        engine.set_handler("bonus".parse().unwrap(), Bonus);
+        let applied = engine.apply(record("bonus", 1, 1, Some(50_000))).unwrap();
+        assert_eq!(applied.balance.available, Amount::from_scaled(50_000));
+
+        assert_eq!(
+            engine.apply(record("bonus", 1, 2, Some(-10_000))),
+            Err(RejectReason::MissingAmount)
+        );
+        assert_eq!(
+            engine.apply(record("adjustment", 1, 3, Some(10_000))),
+            Err(RejectReason::UnhandledTransactionType)
+        );
+        assert_eq!(engine.balances()[0].total, Amount::from_scaled(50_000));
+        // Records of custom types are not kept, so they cannot be disputed.
+        let dispute = InputRecord {
+            r#type: TransactionType::Dispute,
+            ..record("bonus", 1, 1, None)
+        };
+        assert_eq!(engine.apply(dispute), Err(RejectReason::UnknownTransaction));
+    }
+
+    #[test]
+    fn test_rejected_handler_changes_nothing() {
+        let mut engine = Engine::new();
+        let adjustment: CustomType = "adjustment".parse().unwrap();
+        // Takes money out, then changes its mind.
+        engine.set_handler(adjustment, |record: &InputRecord, account: &mut Account| {
+            account.deposit(record.amount.unwrap_or(Amount::ZERO))?;
+            Err(RejectReason::NotDisputable)
+        });
+        assert!(engine
+            .apply(record("adjustment", 1, 1, Some(10_000)))
+            .is_err());
+        assert!(engine.balances().is_empty());
+    }
+
+    #[test]
+    fn test_handlers_follow_policies_and_shards() {
+        let mut engine = Engine::with_config(EngineConfig {
+            accounts: AccountPolicy::Explicit,
+            ..EngineConfig::default()
+        });
+        engine.set_handler("bonus".parse().unwrap(), Bonus);
+        assert_eq!(
+            engine.apply(record("bonus", 1, 1, Some(10_000))),
+            Err(RejectReason::UnknownClient)
+        );
+        engine.open_account(ClientId::new(1), None, None).unwrap();
+
+        let mut shards = engine.split(3);
+        for (n, shard) in shards.iter_mut().enumerate() {
+            shard
+                .apply(record("bonus", 3 + n as u64, n as u64, Some(10_000)))
+                .unwrap_err();
+        }
+        let shard = ClientId::new(1).shard(3);
+        shards[shard]
+            .apply(record("bonus", 1, 1, Some(10_000)))
+            .unwrap();
+        let mut merged = Engine::new();
+        for shard in shards {
+            merged.merge(shard);
+        }
+        assert_eq!(merged.balances()[0].total, Amount::from_scaled(10_000));
+        assert!(merged.apply(record("bonus", 1, 2, Some(10_000))).is_ok());
+    }
+}
@@ -0,0 +1,340 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Returned when a string is not a valid client or transaction ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseIdError {
+    kind: &'static str,
+    value: String,
+}
+
+impl fmt::Display for ParseIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} ID '{}'", self.kind, self.value)
+    }
+}
+
+impl std::error::Error for ParseIdError {}
+
+/// A `ClientId` identifies a client, and with it every account the client
+/// holds. Client IDs are numbers up to `u64::MAX`, which is plenty for any
+/// customer base, while keeping an ID as cheap to copy and hash as a number.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClientId(u64);
+
+impl ClientId {
+    pub const fn new(id: u64) -> Self {
+        ClientId(id)
+    }
+
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// The shard out of `shards` the client goes to when the engine is
+    /// split up, see `Engine::split`.
+    pub(crate) fn shard(self, shards: usize) -> usize {
+        (self.0 % shards as u64) as usize
+    }
+}
+
+impl From<u16> for ClientId {
+    fn from(id: u16) -> Self {
+        ClientId(u64::from(id))
+    }
+}
+
+impl From<u32> for ClientId {
+    fn from(id: u32) -> Self {
+        ClientId(u64::from(id))
+    }
+}
+
+impl From<u64> for ClientId {
+    fn from(id: u64) -> Self {
+        ClientId(id)
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ParseIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ClientId).map_err(|_| ParseIdError {
+            kind: "client",
+            value: s.to_string(),
+        })
+    }
+}
+
+impl Serialize for ClientId {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(self.0)
+    }
+}
+
+/// Client IDs are read from numbers as well as from strings, which is what
+/// they are in CSV files and URLs.
+impl<'de> Deserialize<'de> for ClientId {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct ClientIdVisitor;
+
+        impl Visitor<'_> for ClientIdVisitor {
+            type Value = ClientId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a client ID")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(ClientId(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(ClientId)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.trim().parse().map_err(E::custom)
+            }
+        }
+
+        d.deserialize_any(ClientIdVisitor)
+    }
+}
+
+/// The longest text a `TxId` can hold.
+pub const MAX_TX_ID_LEN: usize = 32;
+
+/// A `TxId` identifies a transaction. Three kinds of IDs are told apart by
+/// their format, so transactions from different systems can be mixed:
+///
+/// * Numbers up to `u64::MAX`, such as `17`.
+/// * UUIDs such as `67e55044-10b1-426f-9247-bb680e5fe0c8`, in any case.
+///   They are written out in lower case.
+/// * Anything else made up of up to `MAX_TX_ID_LEN` printable ASCII
+///   characters without spaces, such as `EU-2024-000017`, taken as is.
+///
+/// IDs of different kinds never match, so `17` and `017` are the same
+/// transaction while `EU-17` is another one. Like a `Currency`, a `TxId`
+/// is held inline, so it can be copied around freely.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxId(Repr);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Repr {
+    Number(u64),
+    Uuid(u128),
+    /// The bytes come first so IDs sort by their text. Unused bytes are
+    /// zero, which sorts before any character.
+    Text([u8; MAX_TX_ID_LEN], u8),
+}
+
+impl TxId {
+    /// A numeric transaction ID.
+    pub const fn new(id: u64) -> Self {
+        TxId(Repr::Number(id))
+    }
+
+    /// The ID as a number, if it is one.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.0 {
+            Repr::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+impl From<u32> for TxId {
+    fn from(id: u32) -> Self {
+        TxId(Repr::Number(u64::from(id)))
+    }
+}
+
+impl From<u64> for TxId {
+    fn from(id: u64) -> Self {
+        TxId(Repr::Number(id))
+    }
+}
+
+/// Parses a UUID in its usual form of 32 hexadecimal digits in groups of 8,
+/// 4, 4, 4 and 12, separated by hyphens.
+fn parse_uuid(s: &str) -> Option<u128> {
+    let groups: Vec<&str> = s.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+    if lengths != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    let digits = groups.concat();
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u128::from_str_radix(&digits, 16).ok()
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Repr::Number(n) => n.fmt(f),
+            Repr::Uuid(uuid) => {
+                let hex = format!("{:032x}", uuid);
+                write!(
+                    f,
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+            }
+            // Only ASCII ever makes it in, see `from_str`.
+            Repr::Text(bytes, len) => {
+                f.write_str(std::str::from_utf8(&bytes[..usize::from(*len)]).unwrap())
+            }
+        }
+    }
+}
+
+impl FromStr for TxId {
+    type Err = ParseIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if is_number(s) {
+            if let Ok(n) = s.parse() {
+                return Ok(TxId(Repr::Number(n)));
+            }
+        }
+        if let Some(uuid) = parse_uuid(s) {
+            return Ok(TxId(Repr::Uuid(uuid)));
+        }
+        // A signed number is a mistake rather than an ID.
+        let signed = s.strip_prefix(['+', '-']).is_some_and(is_number);
+        if signed
+            || s.is_empty()
+            || s.len() > MAX_TX_ID_LEN
+            || !s.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err(ParseIdError {
+                kind: "transaction",
+                value: s.to_string(),
+            });
+        }
+        let mut bytes = [0; MAX_TX_ID_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(TxId(Repr::Text(bytes, s.len() as u8)))
+    }
+}
+
+/// Numeric IDs are written as numbers, so output with only those looks
+/// just like it did when they were the only kind, and the rest as strings.
+impl Serialize for TxId {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Repr::Number(n) => s.serialize_u64(n),
+            _ => s.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct TxIdVisitor;
+
+        impl Visitor<'_> for TxIdVisitor {
+            type Value = TxId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a transaction ID")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(TxId::from(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(TxId::from)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.trim().parse().map_err(E::custom)
+            }
+        }
+
+        d.deserialize_any(TxIdVisitor)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{ClientId, TxId};
+
+    #[test]
+    fn test_parse_client_id() {
+        assert_eq!("70000".parse(), Ok(ClientId::new(70_000)));
+        assert_eq!("18446744073709551615".parse(), Ok(ClientId::new(u64::MAX)));
+        for s in ["", "-1", "1.5", "x", "18446744073709551616"] {
+            assert!(s.parse::<ClientId>().is_err(), "{} should not parse", s);
+        }
+        let id: ClientId = serde_json::from_str("\"12\"").unwrap();
+        assert_eq!(id, ClientId::new(12));
+        assert_eq!(serde_json::to_string(&id).unwrap(), "12");
+    }
+
+    #[test]
+    fn test_parse_tx_id() {
+        let number: TxId = "4294967296".parse().unwrap();
+        assert_eq!(number.as_u64(), Some(4_294_967_296));
+        assert_eq!("007".parse::<TxId>().unwrap(), TxId::from(7u32));
+
+        let uuid: TxId = "67E55044-10B1-426F-9247-BB680E5FE0C8".parse().unwrap();
+        assert_eq!(uuid.to_string(), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+        assert_eq!(uuid.as_u64(), None);
+        assert_eq!(
+            "67e55044-10b1-426f-9247-bb680e5fe0c8".parse::<TxId>(),
+            Ok(uuid)
+        );
+
+        let text: TxId = "EU-2024-000017".parse().unwrap();
+        assert_eq!(text.to_string(), "EU-2024-000017");
+        assert_ne!("EU-17".parse::<TxId>().unwrap(), TxId::from(17u32));
+        // Too big to be a number, but fine as text.
+        let big: TxId = "123456789012345678901234567890".parse().unwrap();
+        assert_eq!(big.to_string(), "123456789012345678901234567890");
+
+        for s in ["", "+1", "-1", "has space", &"x".repeat(33), "näh"] {
+            assert!(s.parse::<TxId>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn test_tx_id_serde() {
+        let ids: Vec<TxId> = serde_json::from_str("[1, \"2\", \"EU-3\"]").unwrap();
+        assert_eq!(ids[1], TxId::from(2u32));
+        assert_eq!(serde_json::to_string(&ids).unwrap(), "[1,2,\"EU-3\"]");
+    }
+
+    #[test]
+    fn test_tx_ids_sort_by_kind() {
+        let mut ids: Vec<TxId> = ["b", "10", "a", "9"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        ids.sort();
+        let ids: Vec<String> = ids.iter().map(TxId::to_string).collect();
+        assert_eq!(ids, ["9", "10", "a", "b"]);
+    }
+}
@@ -1,114 +1,1538 @@
-use csv::StringRecord;
+use super::amount::{Amount, AmountFormat, DecimalSeparator, Precision};
+use super::currency::Currency;
+use super::error::{Column, Error, HeaderProblem};
+use super::id::{ClientId, TxId};
+use super::tenant::Tenant;
+use chrono::{DateTime, Utc};
+use csv::{ByteRecord, Position, StringRecord};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "object-store")]
+pub mod cloud;
+pub mod encoding;
+pub mod json;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "xml")]
+pub mod xml;
 
 /// An `InputRecord` is used to store data from a single
-/// row in the input CSV file.
-#[derive(Debug, PartialEq)]
+/// row in the input CSV file. Rows are deserialized by header name, so the
+/// columns may come in any order and unknown columns are ignored.
+///
+/// With serde, a record is a flat object tagged by its `type`, e.g.
+/// `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`, with the optional
+/// fields left out when they are empty. That is the shape of a CSV row and
+/// of a line of JSON Lines input alike, so records can be written with any
+/// serde format and read back as they were, such as by a crate that embeds
+/// the engine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InputRecord {
     pub r#type: TransactionType,
-    pub client: u16,
-    pub tx: u32, // ideally this would be a type with more entropy such as a UUID.
-    pub amount: Option<f64>,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_amount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub amount: Option<Amount>,
+    /// When the transaction happened, if the input says so.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_timestamp",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The currency of the transaction. Every currency a client deals in
+    /// has an account of its own.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_currency",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub currency: Option<Currency>,
+    /// Why the balance was adjusted, which every adjustment has to say.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_reason",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reason: Option<ReasonCode>,
+    /// The tenant the transaction belongs to, if the run is shared by
+    /// several, see `EngineConfig::tenants`. The engine keeps the
+    /// accounts and transactions of every tenant apart from those of the
+    /// others.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_tenant",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tenant: Option<Tenant>,
+}
+
+impl InputRecord {
+    /// The record with `tenant` as its tenant, unless it names one itself.
+    pub(crate) fn or_tenant(self, tenant: Option<Tenant>) -> Self {
+        InputRecord {
+            tenant: self.tenant.or(tenant),
+            ..self
+        }
+    }
 }
 
-/// All possible transaction types.
-#[derive(Debug, Eq, PartialEq)]
+/// All possible transaction types. Besides the ones moving money, `Open`,
+/// `Close`, `Freeze`, `Unfreeze` and `Unlock` manage the account of the
+/// client itself, and `BalanceCheck` states what the account should hold at
+/// that point.
+/// `CreditAdjustment` and `DebitAdjustment` correct a balance by hand and
+/// have to give a `ReasonCode` for doing so.
+/// Anything else is a `Custom` type, left to a `TransactionHandler`.
+///
+/// A transaction type is written as its name in lower case, e.g.
+/// `balance_check`, see `TransactionType::name`, in text and with serde
+/// alike.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Open,
+    Close,
+    Freeze,
+    Unfreeze,
+    /// Lifts the lock a chargeback put on the account, which is up to the
+    /// operator rather than the client.
+    Unlock,
+    /// A fee taken from the available funds. Besides being read from the
+    /// input, these are made up by the engine for the fees in its
+    /// `FeeSchedule`, to show up in statements.
+    Fee,
+    /// The total the account should hold at this point, in its amount. It
+    /// leaves the account as it is, see `Engine::balance_checks`.
+    BalanceCheck,
+    /// Money added to the available funds to correct the balance, such as
+    /// after a bank error. It cannot be disputed.
+    CreditAdjustment,
+    /// Money taken from the available funds to correct the balance, subject
+    /// to the overdraft policy like a withdrawal. It cannot be disputed.
+    DebitAdjustment,
+    /// A type the engine does not know by itself, such as `bonus`. It is
+    /// only read from the input if it is registered as an alias, and only
+    /// applied if the engine has a handler for it, see `Engine::set_handler`.
+    Custom(CustomType),
 }
 
-/// This function processes each column in the incoming `StringRecord`.
-/// If any column cannot be read, we return `None`. In a production
-/// scenario, this would be coupled with logging and error handling
-pub fn make_input_record(s_record: &StringRecord) -> Option<InputRecord> {
-    let transaction_type = match s_record.get(0) {
-        Some(s) => match s.to_lowercase().as_str() {
-            "deposit" => TransactionType::Deposit,
-            "withdrawal" => TransactionType::Withdrawal,
-            "dispute" => TransactionType::Dispute,
-            "resolve" => TransactionType::Resolve,
-            "chargeback" => TransactionType::Chargeback,
-            // If none of the above 5 transaction types were seen, this
-            // is an invalid row and cannot be further processed
-            _ => return None,
-        },
-        None => return None, // If the transaction type field is empty,
-                             // this is an invalid row and cannot be
-                             // further processed
-    };
+/// Every transaction type but `Custom`, by name. This is the only place
+/// names are mapped onto types, for `name`, `FromStr` and serde alike.
+const BUILT_IN: [(&str, TransactionType); 14] = [
+    ("deposit", TransactionType::Deposit),
+    ("withdrawal", TransactionType::Withdrawal),
+    ("dispute", TransactionType::Dispute),
+    ("resolve", TransactionType::Resolve),
+    ("chargeback", TransactionType::Chargeback),
+    ("open", TransactionType::Open),
+    ("close", TransactionType::Close),
+    ("freeze", TransactionType::Freeze),
+    ("unfreeze", TransactionType::Unfreeze),
+    ("unlock", TransactionType::Unlock),
+    ("fee", TransactionType::Fee),
+    ("balance_check", TransactionType::BalanceCheck),
+    ("credit_adjustment", TransactionType::CreditAdjustment),
+    ("debit_adjustment", TransactionType::DebitAdjustment),
+];
 
-    // Check that the number of columns in the row
-    // is correct. We should always have 4 columns,
-    // regardless of transaction type.
-    match transaction_type {
-        TransactionType::Deposit
-        | TransactionType::Withdrawal
-        | TransactionType::Dispute
-        | TransactionType::Resolve
-        | TransactionType::Chargeback => match s_record.len() {
-            4 => (),
-            _ => return None,
-        },
-    }
-
-    let client_id = match s_record.get(1) {
-        Some(s) => match s.parse::<u16>() {
-            Ok(s) => s,
-            _ => return None, // If the client ID could not
-                              // be parsed as a `u16`, the column
-                              // must have invalid data in it.
-                              // The row cannot be processed
-                              // any further.
-        },
-        None => return None, // If the client ID field is empty,
-                             // this is an invalid row and cannot be
-                             // further processed
-    };
-
-    let transaction_id = match s_record.get(2) {
-        Some(s) => match s.parse::<u32>() {
-            Ok(s) => s,
-            _ => return None, // If the transaction ID could not
-                              // be parsed as a `u32`, the column
-                              // must have invalid data in it.
-                              // The row cannot be processed
-                              // any further.
-        },
-        None => return None, // If the transaction ID field is empty,
-                             // this is an invalid row and cannot be
-                             // further processed
-    };
-
-    let amount = match s_record.get(3) {
-        Some(s) => match s.parse::<f64>() {
-            Ok(s) => Some(s),
-            // If the amount could not be parsed as an `f64`,
-            // check to see what type of transaction this is.
-            // If it's a transaction type that does not require
-            // an amount, the amount is simply `None`. Anything
-            // else means that the row is invalid and cannot be
-            // processed any further.
-            _ => match transaction_type {
-                TransactionType::Dispute
-                | TransactionType::Resolve
-                | TransactionType::Chargeback => None,
-                _ => return None,
+/// The names of `BUILT_IN`, for serde to list when it is given another.
+const BUILT_IN_NAMES: [&str; BUILT_IN.len()] = {
+    let mut names = [""; BUILT_IN.len()];
+    let mut i = 0;
+    while i < names.len() {
+        names[i] = BUILT_IN[i].0;
+        i += 1;
+    }
+    names
+};
+
+impl TransactionType {
+    /// Every transaction type the engine knows by itself, that is every one
+    /// but `Custom`.
+    pub fn built_in() -> impl Iterator<Item = TransactionType> {
+        BUILT_IN
+            .into_iter()
+            .map(|(_, transaction_type)| transaction_type)
+    }
+
+    /// Whether a record of this type has to carry an amount.
+    pub fn requires_amount(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Fee
+                | TransactionType::BalanceCheck
+                | TransactionType::CreditAdjustment
+                | TransactionType::DebitAdjustment
+        )
+    }
+
+    /// Whether this is an adjustment, which has to carry a reason code.
+    pub fn is_adjustment(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::CreditAdjustment | TransactionType::DebitAdjustment
+        )
+    }
+
+    /// Whether this is a custom type, left to a `TransactionHandler`.
+    pub fn is_custom(&self) -> bool {
+        matches!(self, TransactionType::Custom(_))
+    }
+
+    /// Whether a record of this type refers to an earlier transaction by its
+    /// ID rather than having an ID of its own.
+    pub fn refers_to_transaction(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+    }
+
+    /// Whether a record of this type manages the account rather than moving
+    /// money in or out of it.
+    pub fn manages_account(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Open
+                | TransactionType::Close
+                | TransactionType::Freeze
+                | TransactionType::Unfreeze
+                | TransactionType::Unlock
+        )
+    }
+
+    /// The name of the transaction type as it appears in the input.
+    pub fn name(&self) -> &str {
+        match self {
+            TransactionType::Custom(custom) => custom.name(),
+            _ => BUILT_IN
+                .iter()
+                .find(|(_, transaction_type)| transaction_type == self)
+                .map_or("", |(name, _)| name),
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.name())
+    }
+}
+
+/// The longest name a `CustomType` can have.
+pub const MAX_CUSTOM_TYPE_LEN: usize = 16;
+
+/// The name of a custom transaction type, see `TransactionType::Custom`.
+/// Names are made up of up to `MAX_CUSTOM_TYPE_LEN` ASCII letters, digits,
+/// `_` and `-`, and are kept in lower case, as transaction types are matched
+/// case-insensitively. Like a `TxId`, the name is held inline, so a
+/// `TransactionType` can still be copied around freely.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CustomType {
+    bytes: [u8; MAX_CUSTOM_TYPE_LEN],
+    len: u8,
+}
+
+impl CustomType {
+    pub fn name(&self) -> &str {
+        // Only ASCII ever makes it in, see `from_str`.
+        std::str::from_utf8(&self.bytes[..usize::from(self.len)]).unwrap()
+    }
+}
+
+impl fmt::Display for CustomType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CustomType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'-';
+        if s.is_empty() || s.len() > MAX_CUSTOM_TYPE_LEN || !s.bytes().all(valid) {
+            return Err(format!("invalid custom transaction type '{}'", s));
+        }
+        let mut bytes = [0; MAX_CUSTOM_TYPE_LEN];
+        bytes[..s.len()].copy_from_slice(s.to_ascii_lowercase().as_bytes());
+        Ok(CustomType {
+            bytes,
+            len: s.len() as u8,
+        })
+    }
+}
+
+/// The longest code a `ReasonCode` can have.
+pub const MAX_REASON_CODE_LEN: usize = 32;
+
+/// Why an adjustment was made, as a code such as `FX_CORRECTION` that can be
+/// searched for in statements and the audit log. Codes are made up of up to
+/// `MAX_REASON_CODE_LEN` ASCII letters, digits, `_` and `-`, and are kept in
+/// upper case. Like a `CustomType`, the code is held inline so records can
+/// still be moved around cheaply.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReasonCode {
+    bytes: [u8; MAX_REASON_CODE_LEN],
+    len: u8,
+}
+
+impl ReasonCode {
+    pub fn code(&self) -> &str {
+        // Only ASCII ever makes it in, see `from_str`.
+        std::str::from_utf8(&self.bytes[..usize::from(self.len)]).unwrap()
+    }
+}
+
+impl fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for ReasonCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'-';
+        if s.is_empty() || s.len() > MAX_REASON_CODE_LEN || !s.bytes().all(valid) {
+            return Err(format!("invalid reason code '{}'", s));
+        }
+        let mut bytes = [0; MAX_REASON_CODE_LEN];
+        bytes[..s.len()].copy_from_slice(s.to_ascii_uppercase().as_bytes());
+        Ok(ReasonCode {
+            bytes,
+            len: s.len() as u8,
+        })
+    }
+}
+
+impl Serialize for ReasonCode {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ReasonCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .trim()
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Transaction types are matched case-insensitively by their name. Other
+/// names can be mapped onto them with `TransactionTypeAliases`.
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BUILT_IN
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, transaction_type)| *transaction_type)
+            .ok_or_else(|| format!("unknown transaction type '{}'", s))
+    }
+}
+
+/// Transaction types are matched case-insensitively, using the same
+/// vocabulary as `FromStr`. Any other valid name is taken as a custom type,
+/// so records of custom types can be read back, such as from an event log.
+/// Readers of the input only accept the custom types registered as aliases.
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TransactionTypeVisitor;
+
+        impl<'de> de::Visitor<'de> for TransactionTypeVisitor {
+            type Value = TransactionType;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a transaction type")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                if let Ok(transaction_type) = parse_transaction_type(Some(v.trim()), None) {
+                    return Ok(transaction_type);
+                }
+                v.trim()
+                    .parse()
+                    .map(TransactionType::Custom)
+                    .map_err(|_| E::unknown_variant(v, &BUILT_IN_NAMES))
+            }
+        }
+
+        deserializer.deserialize_str(TransactionTypeVisitor)
+    }
+}
+
+/// Additional names for the transaction types, so the input of a system
+/// with a slightly different vocabulary, say `wd` for a withdrawal, can be
+/// read without changing any code. Aliases are matched case-insensitively
+/// and take precedence over the names of the transaction types.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionTypeAliases {
+    aliases: HashMap<String, TransactionType>,
+}
+
+impl TransactionTypeAliases {
+    pub fn new() -> Self {
+        TransactionTypeAliases::default()
+    }
+
+    /// Makes `alias` stand for `transaction_type`.
+    pub fn insert(&mut self, alias: &str, transaction_type: TransactionType) {
+        self.aliases.insert(alias.to_lowercase(), transaction_type);
+    }
+
+    /// The transaction type `alias` stands for, if it is an alias.
+    pub fn get(&self, alias: &str) -> Option<TransactionType> {
+        // Most inputs don't need any aliases, so skip the lowercasing.
+        if self.aliases.is_empty() {
+            return None;
+        }
+        self.aliases.get(&alias.trim().to_lowercase()).copied()
+    }
+
+    /// Replaces an alias in the transaction type column of a CSV row by the
+    /// name it stands for, leaving everything else alone. A custom type has
+    /// no name the row could be read back with, so it is left as well.
+    fn resolve<'a>(&self, s_record: &'a StringRecord, column: usize) -> Cow<'a, StringRecord> {
+        match s_record.get(column).and_then(|s| self.get(s)) {
+            Some(TransactionType::Custom(_)) | None => Cow::Borrowed(s_record),
+            Some(transaction_type) => {
+                let mut resolved: StringRecord = s_record
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| match i == column {
+                        true => transaction_type.name(),
+                        false => field,
+                    })
+                    .collect();
+                resolved.set_position(s_record.position().cloned());
+                Cow::Owned(resolved)
+            }
+        }
+    }
+}
+
+/// Where a column of the input is found, see `ColumnMapping`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnSource {
+    /// The column with this header name, matched case-insensitively.
+    Name(String),
+    /// The column at this position, counted from 0.
+    Position(usize),
+}
+
+/// A position is written as `#` and the number of the column counted from
+/// 1, e.g. `#3`, and anything else is a header name.
+impl FromStr for ColumnSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('#') {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(ColumnSource::Position(n - 1)),
+                _ => Err(format!("invalid column position '{}'", s)),
             },
-        },
-        // If the amount is empty, check to see what type of
-        // transaction this is. If it's a transaction type
-        // that does not require an amount, the amount is
-        // simply `None`. Anything else means that the row
-        // is invalid and cannot be processed any further.
-        None => match transaction_type {
-            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+            None if s.trim().is_empty() => Err("a column name cannot be empty".to_string()),
+            None => Ok(ColumnSource::Name(s.trim().to_string())),
+        }
+    }
+}
+
+/// Where the columns of CSV input are found, for files that name them
+/// differently, say `txn_kind,cust_id,transaction,value`, so they can be read
+/// without preprocessing. A column that is not mapped is found by its own
+/// name, or at its usual position in input without a header row.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    sources: [Option<ColumnSource>; Column::ALL.len()],
+}
+
+impl ColumnMapping {
+    pub fn new() -> Self {
+        ColumnMapping::default()
+    }
+
+    /// Makes `column` be read from `source`.
+    pub fn insert(&mut self, column: Column, source: ColumnSource) {
+        self.sources[column.index()] = Some(source);
+    }
+
+    /// Where `column` is read from, if it is mapped.
+    pub fn get(&self, column: Column) -> Option<&ColumnSource> {
+        self.sources[column.index()].as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.iter().all(Option::is_none)
+    }
+
+    /// Puts the fields of a row without a header into the order of
+    /// `make_input_record`, going by the positions in the mapping. Header
+    /// names mean nothing without a header, so they are left out.
+    fn reorder<'a>(&self, s_record: &'a StringRecord) -> Cow<'a, StringRecord> {
+        if !self
+            .sources
+            .iter()
+            .any(|s| matches!(s, Some(ColumnSource::Position(_))))
+        {
+            return Cow::Borrowed(s_record);
+        }
+        let mut reordered: StringRecord = Column::ALL
+            .iter()
+            .map(|column| {
+                let position = match self.get(*column) {
+                    Some(ColumnSource::Position(i)) => *i,
+                    _ => column.index(),
+                };
+                s_record.get(position).unwrap_or("")
+            })
+            .collect();
+        reordered.set_position(s_record.position().cloned());
+        Cow::Owned(reordered)
+    }
+}
+
+/// Amounts are always read from their textual form so they never go through
+/// a float on the way in. An empty column means there is no amount.
+fn deserialize_optional_amount<'de, D>(deserializer: D) -> Result<Option<Amount>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => s.trim().parse().map(Some).map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Timestamps are read like amounts: an empty column means there is none.
+fn deserialize_optional_timestamp<'de, D>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => parse_timestamp(s.trim())
+            .map(Some)
+            .map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Currencies are read like amounts as well.
+fn deserialize_optional_currency<'de, D>(deserializer: D) -> Result<Option<Currency>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => s.trim().parse().map(Some).map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Reason codes are read like amounts too.
+fn deserialize_optional_reason<'de, D>(deserializer: D) -> Result<Option<ReasonCode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => s.trim().parse().map(Some).map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Tenants are read like amounts as well.
+fn deserialize_optional_tenant<'de, D>(deserializer: D) -> Result<Option<Tenant>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => s.trim().parse().map(Some).map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Parses an RFC 3339 timestamp such as `2024-06-30T23:59:59Z`. Any offset
+/// is accepted and converted to UTC.
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc))
+}
+
+/// The formats transactions can be read from.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    JsonLines,
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// A simplified camt.053 bank statement, see `xml::XmlReader`.
+    #[cfg(feature = "xml")]
+    Xml,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::JsonLines),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(InputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("parquet input needs the parquet feature".to_string()),
+            #[cfg(feature = "xml")]
+            "xml" => Ok(InputFormat::Xml),
+            #[cfg(not(feature = "xml"))]
+            "xml" => Err("xml input needs the xml feature".to_string()),
+            _ => Err(format!("unknown input format '{}'", s)),
+        }
+    }
+}
+
+/// The dialect of CSV input. The defaults are those of RFC 4180: fields
+/// separated by commas and quoted with double quotes where need be, a
+/// header row, and as many fields in every row as in the header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Whether the first row names the columns. Without a header, the
+    /// columns are taken by position, as with `CsvReader::strict`.
+    pub has_headers: bool,
+    /// Whether rows may have fewer or more fields than the first one, as
+    /// in exports that leave out the amount of a dispute or end some rows
+    /// with trailing commas. Such rows are still checked: a row may only
+    /// leave out the amount if its type takes none, and may only have more
+    /// fields than there are columns if the extra ones are empty.
+    pub flexible: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            quote: b'"',
+            has_headers: true,
+            flexible: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// A `csv::ReaderBuilder` for this dialect. Fields are always trimmed.
+    pub(crate) fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .trim(csv::Trim::All)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .has_headers(self.has_headers)
+            .flexible(self.flexible);
+        builder
+    }
+}
+
+/// Whether `path` is the URL of an object in cloud storage, such as
+/// `s3://bucket/file.csv` or `gs://bucket/file.csv`, rather than the name
+/// of a local file.
+pub fn is_object_url(path: &str) -> bool {
+    path.starts_with("s3://") || path.starts_with("gs://")
+}
+
+/// An input file, either on disk or, with the `object-store` feature, an
+/// object in S3 or Google Cloud Storage given by its URL, see
+/// `is_object_url`.
+pub enum InputFile {
+    Local(File),
+    #[cfg(feature = "object-store")]
+    Object(cloud::ObjectReader),
+}
+
+impl InputFile {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        match path.to_str().filter(|p| is_object_url(p)) {
+            #[cfg(feature = "object-store")]
+            Some(url) => cloud::ObjectReader::open(url).map(InputFile::Object),
+            #[cfg(not(feature = "object-store"))]
+            Some(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "reading from cloud storage needs the object-store feature",
+            )),
+            None => File::open(path).map(InputFile::Local),
+        }
+    }
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            InputFile::Local(file) => file.read(buf),
+            #[cfg(feature = "object-store")]
+            InputFile::Object(object) => object.read(buf),
+        }
+    }
+}
+
+/// Where a record came from: the line it starts on and the row as it was
+/// read. Used to point at records that could not be processed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Source {
+    pub line: Option<u64>,
+    pub raw: String,
+}
+
+/// How far a reader got into its input: the byte offset just past the last
+/// record it read, and the number of lines up to there. A reader over a
+/// file can later be moved back there to pick up where it left off, see
+/// `checkpoint`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputPosition {
+    pub byte: u64,
+    pub line: u64,
+}
+
+/// Implemented by the input readers, which can tell where the record they
+/// returned last came from.
+pub trait RecordSource: Iterator<Item = Result<InputRecord, Error>> {
+    /// The `Source` of the record last returned by `next`.
+    fn source(&self) -> Source;
+
+    /// Turns the reader into an iterator that pairs every record with its
+    /// `Source`.
+    fn with_source(self) -> WithSource<Self>
+    where
+        Self: Sized,
+    {
+        WithSource(self)
+    }
+}
+
+/// Pairs every record of a `RecordSource` with where it came from.
+pub struct WithSource<S>(S);
+
+impl<S: RecordSource> Iterator for WithSource<S> {
+    type Item = (Source, Result<InputRecord, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.0.next()?;
+        Some((self.0.source(), record))
+    }
+}
+
+/// A `CsvReader` wraps a `csv::Reader` and turns every row it reads into
+/// an `InputRecord`, one at a time.
+///
+/// By default the header row decides which column is which, so the columns
+/// may appear in any order and extra columns are ignored. A strict reader
+/// instead expects exactly the four columns `type, client, tx, amount` in
+/// that order and treats anything else as an invalid row. Either way, a
+/// `ColumnMapping` can point the reader at columns of other names or
+/// positions.
+///
+/// Rows are read as bytes, and those that are plain ASCII, as most are, are
+/// parsed straight from there, see `parse_fast`. Only other rows are made
+/// into text, and those out of the ordinary deserialized by name.
+pub struct CsvReader<R> {
+    reader: csv::Reader<R>,
+    b_record: ByteRecord,
+    strict: bool,
+    header: Option<Header>,
+    aliases: TransactionTypeAliases,
+    columns: ColumnMapping,
+    amounts: AmountFormat,
+}
+
+/// The header row of the input, along with the position of each of the
+/// columns we know about. Extracted once, when the first row is read.
+struct Header {
+    /// The header row, with the names of the columns we know about put in
+    /// place of whatever they are called in the input, and the names of any
+    /// other columns that happen to be called like one of them taken out,
+    /// so rows can be deserialized by name.
+    names: StringRecord,
+    positions: [Option<usize>; Column::ALL.len()],
+}
+
+impl<R: Read> CsvReader<R> {
+    pub fn new(reader: R) -> Self {
+        CsvReader::with_options(reader, &CsvOptions::default())
+    }
+
+    /// Creates a reader for input in the given dialect. Input without a
+    /// header is read strictly.
+    pub fn with_options(reader: R, options: &CsvOptions) -> Self {
+        CsvReader {
+            reader: options.reader_builder().from_reader(reader),
+            b_record: ByteRecord::new(),
+            strict: !options.has_headers,
+            header: None,
+            aliases: TransactionTypeAliases::default(),
+            columns: ColumnMapping::default(),
+            amounts: AmountFormat::default(),
+        }
+    }
+
+    /// Creates a reader that matches columns by position rather than by
+    /// header name.
+    pub fn strict(reader: R) -> Self {
+        CsvReader {
+            strict: true,
+            ..CsvReader::new(reader)
+        }
+    }
+
+    /// Makes the reader match columns by position, see `strict`.
+    pub fn with_strict_columns(self) -> Self {
+        CsvReader {
+            strict: true,
+            ..self
+        }
+    }
+
+    /// Makes the reader accept `aliases` for the transaction types.
+    pub fn with_aliases(self, aliases: TransactionTypeAliases) -> Self {
+        CsvReader { aliases, ..self }
+    }
+
+    /// Makes the reader find the columns where `columns` says.
+    pub fn with_columns(self, columns: ColumnMapping) -> Self {
+        CsvReader { columns, ..self }
+    }
+
+    /// Makes the reader handle amounts with too many decimal places as
+    /// `precision` says.
+    pub fn with_precision(self, precision: Precision) -> Self {
+        let amounts = AmountFormat {
+            precision,
+            ..self.amounts
+        };
+        CsvReader { amounts, ..self }
+    }
+
+    /// Makes the reader accept amounts such as `$1,234.56`, with
+    /// `separator` between the whole units and the fraction, see
+    /// `Amount::parse_lenient`.
+    pub fn with_lenient_amounts(self, separator: DecimalSeparator) -> Self {
+        let amounts = AmountFormat {
+            lenient: Some(separator),
+            ..self.amounts
+        };
+        CsvReader { amounts, ..self }
+    }
+
+    fn read_header(&mut self) -> Result<Header, Error> {
+        Header::new(self.reader.headers()?.clone(), &self.columns, &self.aliases)
+    }
+
+    /// How far the reader got, see `seek`.
+    pub fn position(&self) -> InputPosition {
+        let position = self.reader.position();
+        InputPosition {
+            byte: position.byte(),
+            // The CSV reader counts the line it is on, from 1.
+            line: position.line().saturating_sub(1),
+        }
+    }
+}
+
+impl<R: Read + Seek> CsvReader<R> {
+    /// Moves the reader to a position returned by `position`, so the next
+    /// record read is the one that followed. The header row is read first,
+    /// so columns are still matched by name.
+    pub fn seek(&mut self, position: InputPosition) -> Result<(), Error> {
+        let mut csv_position = Position::new();
+        csv_position.set_byte(position.byte);
+        csv_position.set_line(position.line + 1);
+        self.seek_to(csv_position)
+    }
+
+    /// Moves the reader to `position` of the CSV reader, which also counts
+    /// the rows up to there.
+    fn seek_to(&mut self, position: Position) -> Result<(), Error> {
+        self.reader.seek(position)?;
+        Ok(())
+    }
+}
+
+impl Header {
+    /// Looks up the position of every known column in the header row, by
+    /// its own name unless `columns` says otherwise. The type, client and tx
+    /// columns cannot be done without, and a column that is named almost
+    /// like one we know is taken as a mistake, see `check`.
+    fn new(
+        names: StringRecord,
+        columns: &ColumnMapping,
+        aliases: &TransactionTypeAliases,
+    ) -> Result<Self, Error> {
+        let find = |wanted: &str| {
+            names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(wanted))
+        };
+        let mut positions = [None; Column::ALL.len()];
+        for column in Column::ALL {
+            positions[column.index()] = match columns.get(column) {
+                Some(ColumnSource::Name(name)) => find(name),
+                Some(ColumnSource::Position(i)) => Some(*i).filter(|i| *i < names.len()),
+                None => find(column.name()),
+            };
+        }
+        Header::check(&names, &positions, columns, aliases)?;
+        let names = names
+            .iter()
+            .enumerate()
+            .map(
+                |(i, name)| match Column::ALL.iter().find(|c| positions[c.index()] == Some(i)) {
+                    Some(column) => column.name(),
+                    None if Column::ALL
+                        .iter()
+                        .any(|c| name.eq_ignore_ascii_case(c.name())) =>
+                    {
+                        ""
+                    }
+                    None => name,
+                },
+            )
+            .collect();
+        Ok(Header { names, positions })
+    }
+
+    /// Makes sure the columns found in the header row `names` can be read.
+    /// A missing column is blamed on the input having no header row at all
+    /// if the row holds a transaction type, or else on a column named almost
+    /// like it, if there is one. Such a column is a mistake even if the
+    /// column it stands for is not needed, as its values would be ignored.
+    fn check(
+        names: &StringRecord,
+        positions: &[Option<usize>; Column::ALL.len()],
+        columns: &ColumnMapping,
+        aliases: &TransactionTypeAliases,
+    ) -> Result<(), Error> {
+        let misspelt = |column: Column| {
+            let wanted = column.name();
+            names.iter().enumerate().find_map(|(i, name)| {
+                let name = name.to_lowercase();
+                let known =
+                    positions.contains(&Some(i)) || Column::ALL.iter().any(|c| c.name() == name);
+                let close = match edit_distance(&name, wanted) {
+                    0 => false,
+                    1 => true,
+                    n => n == 2 && wanted.len() > 5,
+                };
+                (!known && close).then(|| names[i].to_string())
+            })
+        };
+        let looks_like_data = || {
+            names
+                .iter()
+                .any(|name| aliases.get(name).is_some() || name.parse::<TransactionType>().is_ok())
+        };
+        let problem = Column::ALL.into_iter().find_map(|column| {
+            let mapped = columns.get(column).is_some();
+            if positions[column.index()].is_some() {
                 None
+            } else if column.is_required() && looks_like_data() {
+                Some(HeaderProblem::NoHeaderRow)
+            } else if let Some(name) = misspelt(column).filter(|_| !mapped) {
+                Some(HeaderProblem::Misspelt { column, name })
+            } else {
+                column
+                    .is_required()
+                    .then_some(HeaderProblem::MissingColumn(column))
+            }
+        });
+        let Some(problem) = problem else {
+            return Ok(());
+        };
+        let expected = Column::ALL[..4]
+            .iter()
+            .map(|column| match columns.get(*column) {
+                Some(ColumnSource::Name(name)) => name.clone(),
+                Some(ColumnSource::Position(i)) => format!("#{}", i + 1),
+                None => column.name().to_string(),
+            })
+            .collect();
+        Err(Error::InvalidHeader {
+            expected,
+            found: names.iter().map(str::to_string).collect(),
+            problem,
+        })
+    }
+}
+
+/// How many characters have to be inserted, removed or replaced to turn
+/// `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if a == *b {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+impl<R: Read> Iterator for CsvReader<R> {
+    type Item = Result<InputRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let done = self.reader.is_done();
+        match self.reader.read_byte_record(&mut self.b_record) {
+            Ok(true) => {
+                if self.header.is_none() && !self.strict {
+                    match self.read_header() {
+                        Ok(header) => self.header = Some(header),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                let header = self.header.as_ref();
+                // The CSV reader only trims ASCII whitespace off the bytes, so
+                // anything else is trimmed once it is text.
+                if self.b_record.as_slice().is_ascii() {
+                    if let Some(record) = parse_fast(
+                        &self.b_record,
+                        header,
+                        &self.aliases,
+                        &self.columns,
+                        self.amounts,
+                    ) {
+                        return Some(Ok(record));
+                    }
+                }
+                let mut s_record = match StringRecord::from_byte_record(self.b_record.clone()) {
+                    Ok(s_record) => s_record,
+                    Err(e) => return Some(Err(invalid_utf8(&self.b_record, e.utf8_error()))),
+                };
+                s_record.trim();
+                Some(parse_row(
+                    &s_record,
+                    header,
+                    &self.aliases,
+                    &self.columns,
+                    self.amounts,
+                ))
+            }
+            // A header row with nothing after it is checked all the same,
+            // as it may well be the only row of an input without one. That
+            // is done the first time the end is reached only.
+            Ok(false) if !done && self.header.is_none() && !self.strict => {
+                match self.reader.headers() {
+                    Ok(names) if names.is_empty() => None,
+                    Ok(_) => match self.read_header() {
+                        Ok(header) => {
+                            self.header = Some(header);
+                            None
+                        }
+                        Err(e) => Some(Err(e)),
+                    },
+                    Err(e) => Some(Err(e.into())),
+                }
             }
-            _ => return None,
-        },
+            Ok(false) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+/// Turns rows of CSV input into `InputRecord`s just like a `CsvReader`
+/// does, for rows that were read by someone else, such as the reader thread
+/// of a pipeline. It never changes, so it can be shared between threads.
+pub(crate) struct RowParser {
+    /// `None` if the columns are matched by position.
+    header: Option<Header>,
+    aliases: TransactionTypeAliases,
+    columns: ColumnMapping,
+    amounts: AmountFormat,
+}
+
+impl RowParser {
+    /// Creates a parser for rows that follow the header row `names`, or
+    /// for rows matched by position if there are no names. Fails like a
+    /// `CsvReader` does if a column that cannot be done without is missing.
+    pub(crate) fn new(
+        names: Option<StringRecord>,
+        aliases: TransactionTypeAliases,
+        columns: ColumnMapping,
+        amounts: AmountFormat,
+    ) -> Result<Self, Error> {
+        let header = match names {
+            Some(names) => Some(Header::new(names, &columns, &aliases)?),
+            None => None,
+        };
+        Ok(RowParser {
+            header,
+            aliases,
+            columns,
+            amounts,
+        })
+    }
+
+    pub(crate) fn parse(&self, s_record: &StringRecord) -> (Source, Result<InputRecord, Error>) {
+        let header = self.header.as_ref();
+        let fast = parse_fast(
+            s_record.as_byte_record(),
+            header,
+            &self.aliases,
+            &self.columns,
+            self.amounts,
+        );
+        let record = match fast {
+            Some(record) => Ok(record),
+            None => parse_row(s_record, header, &self.aliases, &self.columns, self.amounts),
+        };
+        (source_of(s_record), record)
+    }
+}
+
+/// Parses the rows most inputs are made of straight from their bytes,
+/// without making them text first or deserializing them by name: those of
+/// the built-in types, with numeric IDs, plain amounts and no reason code.
+/// Returns `None` for anything else, including any row that is invalid,
+/// which `parse_row` then deals with, so every row comes out just as it
+/// would from there.
+fn parse_fast(
+    b_record: &ByteRecord,
+    header: Option<&Header>,
+    aliases: &TransactionTypeAliases,
+    columns: &ColumnMapping,
+    amounts: AmountFormat,
+) -> Option<InputRecord> {
+    if amounts.lenient.is_some() {
+        return None;
+    }
+    let positions = match header {
+        Some(header) if b_record.len() == header.names.len() => header.positions,
+        None if columns.is_empty() && (4..=Column::ALL.len()).contains(&b_record.len()) => {
+            std::array::from_fn(Some)
+        }
+        _ => return None,
+    };
+    let field = |column: Column| {
+        positions[column.index()]
+            .and_then(|i| b_record.get(i))
+            .unwrap_or_default()
+    };
+
+    let type_name = std::str::from_utf8(field(Column::Type)).ok()?;
+    let r#type = match aliases.get(type_name) {
+        Some(transaction_type) => transaction_type,
+        None => type_name.parse().ok()?,
+    };
+    if r#type.is_custom() || r#type.is_adjustment() || !field(Column::Reason).is_empty() {
+        return None;
+    }
+    let amount = match field(Column::Amount) {
+        b"" if r#type.requires_amount() => return None,
+        b"" => None,
+        amount => Some(Amount::parse_ascii(amount, amounts.precision).ok()?),
+    };
+    let timestamp = match field(Column::Timestamp) {
+        b"" => None,
+        timestamp => Some(parse_timestamp(std::str::from_utf8(timestamp).ok()?).ok()?),
+    };
+    let currency = match field(Column::Currency) {
+        b"" => None,
+        currency => Some(std::str::from_utf8(currency).ok()?.parse().ok()?),
+    };
+    let tenant = match field(Column::Tenant) {
+        b"" => None,
+        tenant => Some(std::str::from_utf8(tenant).ok()?.parse().ok()?),
+    };
+    Some(InputRecord {
+        r#type,
+        client: ClientId::new(parse_u64(field(Column::Client))?),
+        tx: TxId::new(parse_u64(field(Column::Tx))?),
+        amount,
+        timestamp,
+        currency,
+        reason: None,
+        tenant,
+    })
+}
+
+/// Parses a number made up of nothing but digits, as IDs mostly are.
+fn parse_u64(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |n, b| {
+        let digit = b.checked_sub(b'0').filter(|d| *d <= 9)?;
+        n.checked_mul(10)?.checked_add(u64::from(digit))
+    })
+}
+
+/// The error for a row that is not valid UTF-8, which, like any other row
+/// the CSV reader cannot make sense of, ends the input.
+fn invalid_utf8(b_record: &ByteRecord, e: &csv::Utf8Error) -> Error {
+    Error::InvalidUtf8 {
+        line: b_record.position().map(|p| p.line()),
+        field: e.field() + 1,
+    }
+}
+
+/// Parses a row of CSV input by the names in `header`, or by position if
+/// there is none.
+fn parse_row(
+    s_record: &StringRecord,
+    header: Option<&Header>,
+    aliases: &TransactionTypeAliases,
+    columns: &ColumnMapping,
+    amounts: AmountFormat,
+) -> Result<InputRecord, Error> {
+    let Some(header) = header else {
+        return make_aliased_input_record(&columns.reorder(s_record), aliases, amounts);
+    };
+    let s_record = match header.positions[Column::Type.index()] {
+        Some(i) => aliases.resolve(s_record, i),
+        None => Cow::Borrowed(s_record),
+    };
+    deserialize_input_record(&s_record, header, aliases, amounts)
+}
+
+impl<R: Read> RecordSource for CsvReader<R> {
+    fn source(&self) -> Source {
+        source_of_bytes(&self.b_record)
+    }
+}
+
+/// Parses CSV records handed over as text, one at a time, for inputs that
+/// cannot be wrapped in a `CsvReader`, such as asynchronous ones. It works
+/// just like a `CsvReader`, including taking the first record as the header.
+#[cfg(feature = "async")]
+pub(crate) struct CsvTextParser {
+    strict: bool,
+    options: CsvOptions,
+    header: Option<Header>,
+    seen_header: bool,
+    aliases: TransactionTypeAliases,
+    columns: ColumnMapping,
+    amounts: AmountFormat,
+}
+
+#[cfg(feature = "async")]
+impl CsvTextParser {
+    pub(crate) fn new(
+        strict: bool,
+        options: CsvOptions,
+        aliases: TransactionTypeAliases,
+        columns: ColumnMapping,
+        amounts: AmountFormat,
+    ) -> Self {
+        CsvTextParser {
+            strict: strict || !options.has_headers,
+            options,
+            header: None,
+            seen_header: !options.has_headers,
+            aliases,
+            columns,
+            amounts,
+        }
+    }
+
+    /// Parses the complete text of a single record that starts on `line`.
+    /// Returns `None` for the header and for blank records.
+    pub(crate) fn parse(
+        &mut self,
+        text: &str,
+        line: u64,
+    ) -> Option<(Source, Result<InputRecord, Error>)> {
+        let mut reader = self
+            .options
+            .reader_builder()
+            .has_headers(false)
+            .from_reader(text.as_bytes());
+        let mut s_record = StringRecord::new();
+        let mut position = csv::Position::new();
+        position.set_line(line);
+        let source = Source {
+            line: Some(line),
+            raw: text.to_string(),
+        };
+        match reader.read_record(&mut s_record) {
+            Ok(true) => s_record.set_position(Some(position)),
+            Ok(false) => return None,
+            Err(e) => return Some((source, Err(e.into()))),
+        }
+
+        if !self.seen_header {
+            self.seen_header = true;
+            if self.strict {
+                return None;
+            }
+            return match Header::new(s_record, &self.columns, &self.aliases) {
+                Ok(header) => {
+                    self.header = Some(header);
+                    None
+                }
+                Err(e) => Some((source, Err(e))),
+            };
+        }
+
+        let record = match &self.header {
+            Some(header) if s_record.len() != header.names.len() && !self.options.flexible => {
+                Err(Error::ColumnCount {
+                    line: Some(line),
+                    expected: header.names.len(),
+                    found: s_record.len(),
+                })
+            }
+            Some(header) => {
+                let column = header.positions[Column::Type.index()].unwrap_or_default();
+                deserialize_input_record(
+                    &self.aliases.resolve(&s_record, column),
+                    header,
+                    &self.aliases,
+                    self.amounts,
+                )
+            }
+            None => make_aliased_input_record(
+                &self.columns.reorder(&s_record),
+                &self.aliases,
+                self.amounts,
+            ),
+        };
+        Some((source_of(&s_record), record))
+    }
+}
+
+/// The `Source` of a row. The raw row is rebuilt from its fields, quoting
+/// them where needed, so it can be written to another CSV file as is.
+pub(crate) fn source_of(s_record: &StringRecord) -> Source {
+    source_of_bytes(s_record.as_byte_record())
+}
+
+/// The `Source` of a row read as bytes. Anything that is not UTF-8 is
+/// replaced, which only ever happens to rows that cannot be parsed.
+fn source_of_bytes(b_record: &ByteRecord) -> Source {
+    let mut raw = String::new();
+    for (i, field) in b_record.iter().enumerate() {
+        if i > 0 {
+            raw.push(',');
+        }
+        let field = String::from_utf8_lossy(field);
+        if field.contains([',', '"', '\n', '\r']) {
+            raw.push('"');
+            raw.push_str(&field.replace('"', "\"\""));
+            raw.push('"');
+        } else {
+            raw.push_str(&field);
+        }
+    }
+    Source {
+        line: b_record.position().map(|p| p.line()),
+        raw,
+    }
+}
+
+/// Deserializes a row using the header names. Serde doesn't tell us which
+/// column a custom deserializer failed on, so a row it refuses is put back
+/// into the canonical column order and handed to `make_input_record`, which
+/// knows how to describe exactly what is wrong with it. So is a row of a
+/// custom type, which is only valid if it is one of the `aliases`.
+fn deserialize_input_record(
+    s_record: &StringRecord,
+    header: &Header,
+    aliases: &TransactionTypeAliases,
+    amounts: AmountFormat,
+) -> Result<InputRecord, Error> {
+    let line = s_record.position().map(|p| p.line());
+    // Only a flexible reader lets a row be longer than the header. Empty
+    // fields past the header, as left by trailing commas, are ignored, but
+    // anything else there cannot be told apart from a row that is off by a
+    // column.
+    let extra = s_record.iter().skip(header.names.len());
+    if extra.clone().any(|field| !field.is_empty()) {
+        return Err(Error::ColumnCount {
+            line,
+            expected: header.names.len(),
+            found: s_record.len(),
+        });
+    }
+    match s_record.deserialize::<InputRecord>(Some(&header.names)) {
+        Ok(mut record) if !record.r#type.is_custom() => {
+            // Serde rounds the amount and knows nothing of lenient amounts,
+            // so it is read again as written.
+            if amounts != AmountFormat::default() {
+                let column = header.positions[Column::Amount.index()];
+                let text = column.and_then(|i| s_record.get(i)).unwrap_or("").trim();
+                record.amount = parse_amount(text, &record.r#type, amounts, line)?;
+            }
+            check_amount(&record.r#type, record.amount, line)?;
+            check_reason(&record.r#type, record.reason, line)?;
+            Ok(record)
+        }
+        _ => {
+            let mut canonical: StringRecord = header
+                .positions
+                .iter()
+                .map(|i| i.and_then(|i| s_record.get(i)).unwrap_or(""))
+                .collect();
+            canonical.set_position(s_record.position().cloned());
+            make_aliased_input_record(&canonical, aliases, amounts)
+        }
+    }
+}
+
+/// This function processes each column in the incoming `StringRecord`,
+/// expecting them in the order `type, client, tx, amount`, optionally
+/// followed by a `timestamp`, a `currency`, a `reason` and a `tenant`. If any column
+/// cannot be read, we return an `Error` describing which
+/// column of which line was at fault, so the caller can decide whether to
+/// log it, skip the row, or abort.
+pub fn make_input_record(s_record: &StringRecord) -> Result<InputRecord, Error> {
+    make_input_record_with(s_record, Precision::default())
+}
+
+/// Like `make_input_record`, but handles amounts with too many decimal
+/// places as `precision` says.
+pub fn make_input_record_with(
+    s_record: &StringRecord,
+    precision: Precision,
+) -> Result<InputRecord, Error> {
+    let amounts = AmountFormat {
+        precision,
+        lenient: None,
+    };
+    make_aliased_input_record(s_record, &TransactionTypeAliases::default(), amounts)
+}
+
+/// Like `make_input_record_with`, but accepts `aliases` for the transaction
+/// types, which is the only way a custom type is accepted, and reads the
+/// amount as `amounts` says.
+pub(crate) fn make_aliased_input_record(
+    s_record: &StringRecord,
+    aliases: &TransactionTypeAliases,
+    amounts: AmountFormat,
+) -> Result<InputRecord, Error> {
+    let line = s_record.position().map(|p| p.line());
+
+    let transaction_type = match s_record.get(0).and_then(|s| aliases.get(s)) {
+        Some(transaction_type) => transaction_type,
+        None => parse_transaction_type(s_record.get(0), line)?,
+    };
+
+    // Check that the number of columns in the row
+    // is correct. We should have 4 columns, 5 with a
+    // timestamp, 6 with a currency, 7 with a reason
+    // and 8 with a tenant as well. A record that takes no amount,
+    // such as a dispute, may leave out the amount
+    // column, and empty columns past the last one,
+    // as left by trailing commas, do not count.
+    let least = match transaction_type.requires_amount() {
+        true => 4,
+        false => 3,
+    };
+    let used = s_record
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| !field.is_empty())
+        .last()
+        .map_or(0, |(i, _)| i + 1);
+    let expected = match s_record.len() {
+        found if found < least => Some(least),
+        _ if used > Column::ALL.len() => Some(Column::ALL.len()),
+        _ => None,
+    };
+    if let Some(expected) = expected {
+        return Err(Error::ColumnCount {
+            line,
+            expected,
+            found: s_record.len(),
+        });
+    }
+
+    // If the client ID could not be parsed as a `ClientId`, the
+    // column must have invalid data in it. The row cannot be
+    // processed any further.
+    let client_id = parse_column::<ClientId>(s_record, Column::Client)?;
+
+    // Same goes for the transaction ID, which must be a `TxId`.
+    let transaction_id = parse_column::<TxId>(s_record, Column::Tx)?;
+
+    let amount = parse_amount(
+        s_record.get(3).unwrap_or(""),
+        &transaction_type,
+        amounts,
+        line,
+    )?;
+    check_amount(&transaction_type, amount, line)?;
+
+    let timestamp = match s_record.get(Column::Timestamp.index()) {
+        Some(s) if !s.is_empty() => Some(parse_timestamp(s).map_err(|_| Error::Parse {
+            line,
+            column: Column::Timestamp,
+            value: s.to_string(),
+        })?),
+        _ => None,
+    };
+
+    let currency = match s_record.get(Column::Currency.index()) {
+        Some(s) if !s.is_empty() => Some(s.parse().map_err(|_| Error::Parse {
+            line,
+            column: Column::Currency,
+            value: s.to_string(),
+        })?),
+        _ => None,
+    };
+
+    let reason = match s_record.get(Column::Reason.index()) {
+        Some(s) if !s.is_empty() => Some(s.parse().map_err(|_| Error::Parse {
+            line,
+            column: Column::Reason,
+            value: s.to_string(),
+        })?),
+        _ => None,
+    };
+    check_reason(&transaction_type, reason, line)?;
+
+    let tenant = match s_record.get(Column::Tenant.index()) {
+        Some(s) if !s.is_empty() => Some(s.parse().map_err(|_| Error::Parse {
+            line,
+            column: Column::Tenant,
+            value: s.to_string(),
+        })?),
+        _ => None,
     };
 
     // If we've made it this far, all columns in the row
@@ -118,15 +1542,143 @@ pub fn make_input_record(s_record: &StringRecord) -> Option<InputRecord> {
         r#type: transaction_type,
         client: client_id,
         tx: transaction_id,
-        amount: amount,
+        amount,
+        timestamp,
+        currency,
+        reason,
+        tenant,
     };
 
-    Some(res)
+    Ok(res)
+}
+
+/// Parses a record that arrives on its own rather than as part of a file,
+/// such as a message from a queue. It is either a JSON object, as in the
+/// JSON Lines format, or a single CSV row without a header, with the columns
+/// in the order `make_input_record` expects.
+pub fn parse_message(text: &str, aliases: &TransactionTypeAliases) -> Result<InputRecord, Error> {
+    let text = text.trim();
+    if text.starts_with('{') {
+        return json::parse_json_record(text, None, aliases, AmountFormat::default());
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let mut s_record = StringRecord::new();
+    if !reader.read_record(&mut s_record)? {
+        return Err(Error::MissingField {
+            line: None,
+            column: Column::Type,
+        });
+    }
+    make_aliased_input_record(&s_record, aliases, AmountFormat::default())
+}
+
+/// Maps the transaction type column onto a `TransactionType`. This is shared
+/// by every input format so they all accept exactly the same vocabulary.
+pub(crate) fn parse_transaction_type(
+    value: Option<&str>,
+    line: Option<u64>,
+) -> Result<TransactionType, Error> {
+    match value {
+        // If the transaction type field is empty, this is an
+        // invalid row and cannot be further processed
+        Some("") | None => Err(Error::MissingField {
+            line,
+            column: Column::Type,
+        }),
+        // If this is none of the known transaction types, this is an
+        // invalid row and cannot be further processed
+        Some(s) => s.parse().map_err(|_| Error::UnknownTransactionType {
+            line,
+            value: s.to_string(),
+        }),
+    }
+}
+
+/// Parses the amount column of a row. If the amount could not be parsed as
+/// an `Amount`, it depends on the type of transaction: one that does not
+/// require an amount simply has none, anything else means the row is
+/// invalid and cannot be processed any further. An empty amount is left to
+/// `check_amount` to decide on.
+pub(crate) fn parse_amount(
+    s: &str,
+    transaction_type: &TransactionType,
+    amounts: AmountFormat,
+    line: Option<u64>,
+) -> Result<Option<Amount>, Error> {
+    if s.is_empty() {
+        return Ok(None);
+    }
+    match amounts.parse(s) {
+        Ok(amount) => Ok(Some(amount)),
+        Err(_) if !transaction_type.requires_amount() => Ok(None),
+        Err(e) if e.is_too_precise() => Err(Error::TooPrecise {
+            line,
+            value: s.to_string(),
+        }),
+        Err(_) => Err(Error::Parse {
+            line,
+            column: Column::Amount,
+            value: s.to_string(),
+        }),
+    }
+}
+
+/// Deposits and withdrawals move money, and a balance check states an
+/// amount, so they cannot do without one. Every other transaction type
+/// refers to an earlier transaction or manages the account, and doesn't
+/// need one.
+pub(crate) fn check_amount(
+    transaction_type: &TransactionType,
+    amount: Option<Amount>,
+    line: Option<u64>,
+) -> Result<(), Error> {
+    match amount {
+        None if transaction_type.requires_amount() => Err(Error::MissingAmount { line }),
+        _ => Ok(()),
+    }
+}
+
+/// Adjustments change a balance by hand, so they have to say why. Any other
+/// transaction type may carry a reason code or not, it is not looked at.
+pub(crate) fn check_reason(
+    transaction_type: &TransactionType,
+    reason: Option<ReasonCode>,
+    line: Option<u64>,
+) -> Result<(), Error> {
+    match reason {
+        None if transaction_type.is_adjustment() => Err(Error::MissingReason { line }),
+        _ => Ok(()),
+    }
+}
+
+/// Reads a single column of the given row and parses it into `T`. An empty
+/// column is reported as missing rather than as unparseable.
+fn parse_column<T: FromStr>(s_record: &StringRecord, column: Column) -> Result<T, Error> {
+    let line = s_record.position().map(|p| p.line());
+    match s_record.get(column.index()) {
+        Some("") | None => Err(Error::MissingField { line, column }),
+        Some(s) => s.parse::<T>().map_err(|_| Error::Parse {
+            line,
+            column,
+            value: s.to_string(),
+        }),
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use super::{make_input_record, InputRecord, TransactionType};
+    use super::{
+        is_object_url, make_input_record, parse_fast, parse_message, parse_row, ColumnMapping,
+        ColumnSource, CsvOptions, CsvReader, CustomType, Header, InputRecord, ReasonCode,
+        RecordSource, TransactionType, TransactionTypeAliases,
+    };
+    use crate::amount::AmountFormat;
+    use crate::amount::{Amount, DecimalSeparator, Precision};
+    use crate::error::{Column, Error, HeaderProblem};
+    use crate::id::{ClientId, TxId};
     use csv::StringRecord;
 
     #[test]
@@ -134,11 +1686,37 @@ pub mod tests {
         let record = StringRecord::from(vec!["deposit", "1", "1", "20.00"]);
         let test_record: InputRecord = InputRecord {
             r#type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(20.00),
+            client: ClientId::new(1),
+            tx: TxId::new(1),
+            amount: Some(Amount::from_scaled(200_000)),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
         };
-        assert_eq!(make_input_record(&record), Some(test_record));
+        assert_eq!(make_input_record(&record).unwrap(), test_record);
+    }
+
+    #[test]
+    fn test_wide_ids() {
+        let record = StringRecord::from(vec![
+            "deposit",
+            "5000000000",
+            "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "1.0",
+        ]);
+        let record = make_input_record(&record).unwrap();
+        assert_eq!(record.client, ClientId::new(5_000_000_000));
+        assert_eq!(
+            record.tx.to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+
+        let record = StringRecord::from(vec!["deposit", "1", "EU 17", "1.0"]);
+        match make_input_record(&record) {
+            Err(Error::Parse { column, .. }) => assert_eq!(column, Column::Tx),
+            other => panic!("unexpected {:?}", other),
+        }
     }
 
     #[test]
@@ -146,11 +1724,15 @@ pub mod tests {
         let record = StringRecord::from(vec!["deposit", "1", "1", "20.987654321"]);
         let test_record: InputRecord = InputRecord {
             r#type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(20.987654321),
+            client: ClientId::new(1),
+            tx: TxId::new(1),
+            amount: Some(Amount::from_scaled(209_877)),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
         };
-        assert_eq!(make_input_record(&record), Some(test_record));
+        assert_eq!(make_input_record(&record).unwrap(), test_record);
     }
 
     #[test]
@@ -158,11 +1740,15 @@ pub mod tests {
         let record = StringRecord::from(vec!["withdrawal", "1", "1", "20.00"]);
         let test_record: InputRecord = InputRecord {
             r#type: TransactionType::Withdrawal,
-            client: 1,
-            tx: 1,
-            amount: Some(20.00),
+            client: ClientId::new(1),
+            tx: TxId::new(1),
+            amount: Some(Amount::from_scaled(200_000)),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
         };
-        assert_eq!(make_input_record(&record), Some(test_record));
+        assert_eq!(make_input_record(&record).unwrap(), test_record);
     }
 
     #[test]
@@ -170,11 +1756,15 @@ pub mod tests {
         let record = StringRecord::from(vec!["dispute", "1", "1", ""]);
         let test_record: InputRecord = InputRecord {
             r#type: TransactionType::Dispute,
-            client: 1,
-            tx: 1,
+            client: ClientId::new(1),
+            tx: TxId::new(1),
             amount: None,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
         };
-        assert_eq!(make_input_record(&record), Some(test_record));
+        assert_eq!(make_input_record(&record).unwrap(), test_record);
     }
 
     #[test]
@@ -182,11 +1772,15 @@ pub mod tests {
         let record = StringRecord::from(vec!["resolve", "1", "1", ""]);
         let test_record: InputRecord = InputRecord {
             r#type: TransactionType::Resolve,
-            client: 1,
-            tx: 1,
+            client: ClientId::new(1),
+            tx: TxId::new(1),
             amount: None,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
         };
-        assert_eq!(make_input_record(&record), Some(test_record));
+        assert_eq!(make_input_record(&record).unwrap(), test_record);
     }
 
     #[test]
@@ -194,58 +1788,796 @@ pub mod tests {
         let record = StringRecord::from(vec!["chargeback", "1", "1", ""]);
         let test_record: InputRecord = InputRecord {
             r#type: TransactionType::Chargeback,
-            client: 1,
-            tx: 1,
+            client: ClientId::new(1),
+            tx: TxId::new(1),
             amount: None,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
         };
-        assert_eq!(make_input_record(&record), Some(test_record));
+        assert_eq!(make_input_record(&record).unwrap(), test_record);
     }
 
     #[test]
     fn test_record_empty_transaction_type_field() {
         let record = StringRecord::from(vec!["", "1", "1", "20.00"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::MissingField {
+                column: Column::Type,
+                ..
+            })
+        ));
     }
 
     #[test]
     fn test_record_empty_client_id_field() {
         let record = StringRecord::from(vec!["deposit", "", "1", "20.00"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::MissingField {
+                column: Column::Client,
+                ..
+            })
+        ));
     }
 
     #[test]
     fn test_record_empty_transaction_id_field() {
         let record = StringRecord::from(vec!["deposit", "1", "", "20.00"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::MissingField {
+                column: Column::Tx,
+                ..
+            })
+        ));
     }
 
     #[test]
     fn test_record_empty_amount_field() {
         let record = StringRecord::from(vec!["deposit", "1", "1", ""]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::MissingAmount { .. })
+        ));
     }
 
     #[test]
     fn test_record_missing_transaction_type_field() {
         let record = StringRecord::from(vec!["1", "1", "20.00"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::UnknownTransactionType { .. })
+        ));
     }
 
     #[test]
     fn test_record_missing_client_id_field() {
         let record = StringRecord::from(vec!["deposit", "1", "20.00"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::ColumnCount { found: 3, .. })
+        ));
     }
 
     #[test]
     fn test_record_missing_transaction_id_field() {
         let record = StringRecord::from(vec!["deposit", "1", "20.00"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::ColumnCount { found: 3, .. })
+        ));
     }
 
     #[test]
     fn test_record_missing_amount_field() {
         let record = StringRecord::from(vec!["deposit", "1", "1"]);
-        assert_eq!(make_input_record(&record), None);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::ColumnCount { found: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_record_invalid_amount_field() {
+        let record = StringRecord::from(vec!["deposit", "1", "1", "abc"]);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::Parse {
+                column: Column::Amount,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_record_error_has_line_number() {
+        let mut record = StringRecord::from(vec!["deposit", "x", "1", "20.00"]);
+        let mut pos = csv::Position::new();
+        pos.set_line(7);
+        record.set_position(Some(pos));
+        let err = make_input_record(&record).unwrap_err();
+        assert_eq!(err.line(), Some(7));
+        assert_eq!(err.column(), Some(Column::Client));
+    }
+
+    fn read_csv(reader: CsvReader<&[u8]>) -> Vec<Result<InputRecord, Error>> {
+        reader.collect()
+    }
+
+    #[test]
+    fn test_reader_columns_in_any_order() {
+        let data = "amount, tx, type, client\n20.5, 3, Deposit, 7\n, 3, dispute, 7\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert_eq!(
+            records[0].as_ref().unwrap(),
+            &InputRecord {
+                r#type: TransactionType::Deposit,
+                client: ClientId::new(7),
+                tx: TxId::new(3),
+                amount: Some(Amount::from_scaled(205_000)),
+                timestamp: None,
+                currency: None,
+                reason: None,
+                tenant: None,
+            }
+        );
+        assert_eq!(records[1].as_ref().unwrap().amount, None);
+    }
+
+    #[test]
+    fn test_reader_ignores_extra_columns() {
+        let data = "type,client,tx,amount,note\ndeposit,1,1,1.0,first deposit\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert_eq!(
+            records[0].as_ref().unwrap().amount,
+            Some(Amount::from_scaled(10_000))
+        );
+    }
+
+    #[test]
+    fn test_reader_column_mapping() {
+        let mut columns = ColumnMapping::new();
+        columns.insert(Column::Type, "TXN_KIND".parse().unwrap());
+        columns.insert(Column::Client, "cust_id".parse().unwrap());
+        columns.insert(Column::Tx, "transaction".parse().unwrap());
+        columns.insert(Column::Amount, "#5".parse().unwrap());
+        // The `amount` column is not the amount, and must not get in the way.
+        let data = "txn_kind,cust_id,transaction,amount,value
+\
+                    deposit,7,3,n/a,20.5
+\
+                    withdrawal,7,4,n/a,x
+";
+        let records = read_csv(CsvReader::new(data.as_bytes()).with_columns(columns.clone()));
+        let record = records[0].as_ref().unwrap();
+        assert_eq!(
+            (record.client, record.tx, record.amount),
+            (
+                ClientId::new(7),
+                TxId::new(3),
+                Some(Amount::from_scaled(205_000))
+            )
+        );
+        assert!(matches!(
+            records[1],
+            Err(Error::Parse {
+                column: Column::Amount,
+                line: Some(3),
+                ..
+            })
+        ));
+
+        let data = "kind,client,tx\ndeposit,1,1\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()).with_columns(columns));
+        assert_eq!(
+            records[0].as_ref().unwrap_err().to_string(),
+            "Invalid header: expected 'TXN_KIND, cust_id, transaction, #5', found 'kind, client, tx': \
+             there is no 'type' column"
+        );
+
+        // Without a header, only positions count.
+        let mut columns = ColumnMapping::new();
+        columns.insert(Column::Type, ColumnSource::Position(3));
+        columns.insert(Column::Amount, ColumnSource::Position(0));
+        let options = CsvOptions {
+            has_headers: false,
+            ..CsvOptions::default()
+        };
+        let data = "1.5,1,1,deposit\n";
+        let records =
+            read_csv(CsvReader::with_options(data.as_bytes(), &options).with_columns(columns));
+        let record = records[0].as_ref().unwrap();
+        assert_eq!(record.r#type, TransactionType::Deposit);
+        assert_eq!(record.amount, Some(Amount::from_scaled(15_000)));
+    }
+
+    #[test]
+    fn test_reader_without_amount_column() {
+        let data = "type,client,tx\ndispute,1,1\ndeposit,1,2\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert!(records[0].is_ok());
+        assert!(matches!(
+            records[1],
+            Err(Error::MissingAmount { line: Some(3) })
+        ));
+    }
+
+    #[test]
+    fn test_reader_missing_required_column() {
+        let data = "type,tx,amount\ndeposit,1,1.0\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert!(matches!(
+            records[0],
+            Err(Error::InvalidHeader {
+                problem: HeaderProblem::MissingColumn(Column::Client),
+                ..
+            })
+        ));
+        assert!(!records[0].as_ref().unwrap_err().is_record_error());
+    }
+
+    #[test]
+    fn test_reader_invalid_header() {
+        let problem = |data: &str| {
+            let records = read_csv(CsvReader::new(data.as_bytes()));
+            records[0].as_ref().unwrap_err().header_problem().cloned()
+        };
+        assert_eq!(
+            problem("deposit,1,1,1.0\ndeposit,1,2,1.0\n"),
+            Some(HeaderProblem::NoHeaderRow)
+        );
+        assert_eq!(
+            problem("Typ,client,tx,amount\ndeposit,1,1,1.0\n"),
+            Some(HeaderProblem::Misspelt {
+                column: Column::Type,
+                name: "Typ".to_string()
+            })
+        );
+        // A misspelt column is a mistake even if it could be done without.
+        assert_eq!(
+            problem("type,client,tx,amnt\ndeposit,1,1,1.0\n"),
+            Some(HeaderProblem::Misspelt {
+                column: Column::Amount,
+                name: "amnt".to_string()
+            })
+        );
+        assert!(read_csv(CsvReader::new("type,client,tx,note\n".as_bytes())).is_empty());
+
+        let data = "deposit,1,1,1.0\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert_eq!(
+            records[0].as_ref().unwrap_err().to_string(),
+            "Invalid header: expected 'type, client, tx, amount', found 'deposit, 1, 1, 1.0': \
+             that looks like a row of data rather than a header"
+        );
+    }
+
+    #[test]
+    fn test_reader_reports_column_of_invalid_field() {
+        let data = "client,type,tx,amount\n1,deposit,1,abc\nx,deposit,2,1.0\n1,refund,3,1.0\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert!(matches!(
+            records[0],
+            Err(Error::Parse {
+                line: Some(2),
+                column: Column::Amount,
+                ..
+            })
+        ));
+        assert!(matches!(
+            records[1],
+            Err(Error::Parse {
+                line: Some(3),
+                column: Column::Client,
+                ..
+            })
+        ));
+        assert!(matches!(
+            records[2],
+            Err(Error::UnknownTransactionType { line: Some(4), .. })
+        ));
+    }
+
+    #[test]
+    fn test_strict_reader_goes_by_position() {
+        let data = "amount,tx,type,client\ndeposit,1,1,1.0\n1.0,1,deposit,1\n";
+        let records = read_csv(CsvReader::strict(data.as_bytes()));
+        assert!(records[0].is_ok());
+        assert!(matches!(
+            records[1],
+            Err(Error::UnknownTransactionType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reader_dialect() {
+        let options = CsvOptions {
+            delimiter: b';',
+            quote: b'\'',
+            has_headers: false,
+            flexible: true,
+        };
+        let data = "deposit;1;1;'1,5'\ndispute;1;1;\nwithdrawal;1;2;0.5;;EUR\n";
+        let records = read_csv(CsvReader::with_options(data.as_bytes(), &options));
+        assert_eq!(records.len(), 3);
+        assert!(matches!(
+            records[0],
+            Err(Error::Parse {
+                column: Column::Amount,
+                ..
+            })
+        ));
+        assert_eq!(
+            records[1].as_ref().unwrap().r#type,
+            TransactionType::Dispute
+        );
+        assert!(records[2].as_ref().unwrap().currency.is_some());
+
+        // Rows of different lengths are an error unless flexible.
+        let options = CsvOptions {
+            flexible: false,
+            ..options
+        };
+        let records = read_csv(CsvReader::with_options(data.as_bytes(), &options));
+        assert!(records[1].is_ok());
+        assert!(matches!(records[2], Err(Error::Csv(_))));
+    }
+
+    #[test]
+    fn test_flexible_rows() {
+        let options = CsvOptions {
+            flexible: true,
+            ..CsvOptions::default()
+        };
+        let rows = "deposit,1,1,1.0\ndispute,1,1\ndeposit,1,2,1.0,,,,,\n\
+                    deposit,1,3,1.0,junk\ndeposit,1,4\nresolve,1\n";
+        for has_headers in [true, false] {
+            let header = if has_headers {
+                "type,client,tx,amount\n"
+            } else {
+                ""
+            };
+            let options = CsvOptions {
+                has_headers,
+                ..options
+            };
+            let data = format!("{}{}", header, rows);
+            let records = read_csv(CsvReader::with_options(data.as_bytes(), &options));
+            assert_eq!(records.len(), 6);
+            assert_eq!(records[1].as_ref().unwrap().amount, None);
+            assert!(records[2].is_ok());
+            // By name, a field past the header is one too many; by position,
+            // the fifth column is the timestamp.
+            match has_headers {
+                true => assert!(matches!(records[3], Err(Error::ColumnCount { .. }))),
+                false => assert!(matches!(
+                    records[3],
+                    Err(Error::Parse {
+                        column: Column::Timestamp,
+                        ..
+                    })
+                )),
+            }
+            assert!(records[4].is_err());
+            assert!(records[5].is_err());
+        }
+
+        let record = StringRecord::from(vec!["dispute", "1", "1"]);
+        assert!(make_input_record(&record).is_ok());
+        let record = StringRecord::from(vec!["deposit", "1", "1", "1.0", "", "", "", "", "x"]);
+        assert!(matches!(
+            make_input_record(&record),
+            Err(Error::ColumnCount {
+                expected: 8,
+                found: 9,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reader_source() {
+        let data = "type,client,tx,amount,note\ndeposit, 1, 1, 1.0,\"a, \"\"b\"\"\"\n";
+        let (source, record) = CsvReader::new(data.as_bytes())
+            .with_source()
+            .next()
+            .unwrap();
+        assert!(record.is_ok());
+        assert_eq!(source.line, Some(2));
+        assert_eq!(source.raw, "deposit,1,1,1.0,\"a, \"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_parse_transaction_type() {
+        assert_eq!("Deposit".parse(), Ok(TransactionType::Deposit));
+        assert_eq!("CHARGEBACK".parse(), Ok(TransactionType::Chargeback));
+        assert_eq!("unFreeze".parse(), Ok(TransactionType::Unfreeze));
+        assert_eq!("unlock".parse(), Ok(TransactionType::Unlock));
+        assert_eq!("Balance_Check".parse(), Ok(TransactionType::BalanceCheck));
+        assert_eq!(
+            serde_json::to_string(&TransactionType::BalanceCheck).unwrap(),
+            "\"balance_check\""
+        );
+        assert!("wd".parse::<TransactionType>().is_err());
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        for transaction_type in TransactionType::built_in() {
+            let name = transaction_type.name();
+            assert_eq!(name.parse(), Ok(transaction_type));
+            let json = serde_json::to_string(&transaction_type).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            assert_eq!(serde_json::from_str(&json).ok(), Some(transaction_type));
+        }
+        assert_eq!(TransactionType::built_in().count(), 14);
+        let error = serde_json::from_str::<TransactionType>("\"no way\"").unwrap_err();
+        assert!(error.to_string().contains("`balance_check`"));
+
+        let records = [
+            make_input_record(&StringRecord::from(vec![
+                "deposit",
+                "1",
+                "7",
+                "1.5",
+                "2024-01-01T10:00:00Z",
+                "EUR",
+            ]))
+            .unwrap(),
+            make_input_record(&StringRecord::from(vec![
+                "credit_adjustment",
+                "2",
+                "8",
+                "3",
+                "",
+                "",
+                "FX_CORRECTION",
+            ]))
+            .unwrap(),
+            InputRecord {
+                r#type: TransactionType::Custom("bonus".parse().unwrap()),
+                client: ClientId::new(3),
+                tx: TxId::new(9),
+                amount: Some(Amount::from_scaled(10_000)),
+                timestamp: None,
+                currency: None,
+                reason: None,
+                tenant: None,
+            },
+        ];
+        for record in records {
+            let json = serde_json::to_string(&record).unwrap();
+            assert_eq!(serde_json::from_str::<InputRecord>(&json).unwrap(), record);
+
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.serialize(&record).unwrap();
+            let csv = writer.into_inner().unwrap();
+            let mut reader = csv::Reader::from_reader(csv.as_slice());
+            let read: InputRecord = reader.deserialize().next().unwrap().unwrap();
+            assert_eq!(read, record);
+        }
+    }
+
+    #[test]
+    fn test_reader_aliases() {
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("wd", TransactionType::Withdrawal);
+        aliases.insert("Charge_Back", TransactionType::Chargeback);
+
+        let data = "client,type,tx,amount\n1,WD,1,1.0\n1,charge_back,1,\n1,refund,2,1.0\n";
+        let mut reader = CsvReader::new(data.as_bytes())
+            .with_aliases(aliases.clone())
+            .with_source();
+        let (source, record) = reader.next().unwrap();
+        assert_eq!(record.unwrap().r#type, TransactionType::Withdrawal);
+        // The row is reported as it was read, not with the alias resolved.
+        assert_eq!(source.raw, "1,WD,1,1.0");
+        let (_, record) = reader.next().unwrap();
+        assert_eq!(record.unwrap().r#type, TransactionType::Chargeback);
+        let (_, record) = reader.next().unwrap();
+        assert!(matches!(record, Err(Error::UnknownTransactionType { .. })));
+
+        let data = "type,client,tx,amount\nwd,1,1,1.0\n";
+        let records = read_csv(CsvReader::strict(data.as_bytes()).with_aliases(aliases));
+        assert_eq!(
+            records[0].as_ref().unwrap().r#type,
+            TransactionType::Withdrawal
+        );
+    }
+
+    #[test]
+    fn test_reader_custom_types() {
+        let bonus: CustomType = "bonus".parse().unwrap();
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("bonus", TransactionType::Custom(bonus));
+        aliases.insert("promo", TransactionType::Custom(bonus));
+
+        let data = "type,client,tx,amount\nBonus,1,1,2.0\npromo,1,2,\nadjustment,1,3,1.0\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()).with_aliases(aliases.clone()));
+        assert_eq!(
+            records[0].as_ref().unwrap().r#type,
+            TransactionType::Custom(bonus)
+        );
+        assert_eq!(
+            records[0].as_ref().unwrap().amount,
+            Some(Amount::from_scaled(20_000))
+        );
+        // A custom type does not need an amount.
+        assert_eq!(records[1].as_ref().unwrap().amount, None);
+        // Only registered custom types are read.
+        assert!(matches!(
+            records[2],
+            Err(Error::UnknownTransactionType { .. })
+        ));
+
+        let records = read_csv(CsvReader::strict(data.as_bytes()).with_aliases(aliases.clone()));
+        assert_eq!(
+            records[1].as_ref().unwrap().r#type,
+            TransactionType::Custom(bonus)
+        );
+        let record = parse_message("bonus,1,1,2.0", &aliases).unwrap();
+        assert_eq!(record.r#type, TransactionType::Custom(bonus));
+
+        // Records of custom types can be read back once written.
+        let json = serde_json::to_string(&TransactionType::Custom(bonus)).unwrap();
+        assert_eq!(json, "\"bonus\"");
+        assert_eq!(
+            serde_json::from_str::<TransactionType>(&json).unwrap(),
+            TransactionType::Custom(bonus)
+        );
+        assert!("bonus".parse::<TransactionType>().is_err());
+        for s in ["", "has space", "much_too_long_a_name"] {
+            assert!(s.parse::<CustomType>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn test_fast_path() {
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("wd", TransactionType::Withdrawal);
+        let names = StringRecord::from(vec!["tx", "type", "client", "amount", "currency"]);
+        let header = Header::new(names, &ColumnMapping::default(), &aliases).unwrap();
+        let rows = [
+            (vec!["1", "deposit", "2", "1.5", ""], true),
+            (vec!["2", "WD", "007", "1.00005", "EUR"], true),
+            (vec!["3", "dispute", "2", "", ""], true),
+            (vec!["4", "dispute", "2", "later", ""], false),
+            (vec!["5", "deposit", "+2", "1.5", ""], false),
+            (vec!["EU-6", "deposit", "2", "1.5", ""], false),
+            (vec!["7", "deposit", "2", "", ""], false),
+            (vec!["8", "bonus", "2", "1.5", ""], false),
+            (vec!["9", "deposit", "2", "1.5", "euro"], false),
+            (vec!["10", "deposit", "2", "1.5", "", ""], false),
+        ];
+        for (row, fast) in rows {
+            let s_record = StringRecord::from(row.clone());
+            let amounts = AmountFormat::default();
+            let res = parse_fast(
+                s_record.as_byte_record(),
+                Some(&header),
+                &aliases,
+                &ColumnMapping::default(),
+                amounts,
+            );
+            assert_eq!(res.is_some(), fast, "{:?}", row);
+            // Whatever the fast path parses comes out the same either way.
+            if let Some(record) = res {
+                let columns = ColumnMapping::default();
+                let slow = parse_row(&s_record, Some(&header), &aliases, &columns, amounts);
+                assert_eq!(slow.unwrap(), record);
+            }
+        }
+
+        // Only plain ASCII rows take the fast path, but others come out the
+        // same, and a field that is not UTF-8 still ends the input.
+        let data = "type,client,tx,amount,note\ndeposit,1,1,1.0,café\n\u{a0}deposit,1,2,1.0,\n";
+        let records: Vec<_> = CsvReader::new(data.as_bytes()).collect();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.is_ok()));
+        let data = b"type,client,tx,amount,note\ndeposit,1,1,1.0,\xff\n";
+        let records: Vec<_> = CsvReader::new(&data[..]).collect();
+        let error = records[0].as_ref().unwrap_err();
+        assert!(matches!(
+            error,
+            Error::InvalidUtf8 {
+                line: Some(2),
+                field: 5
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Invalid record on line 2: field 5 is not valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_reader_precision() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.00015\ndispute,1,1,0.00001\n";
+        let amounts = |precision| {
+            let by_name = read_csv(CsvReader::new(data.as_bytes()).with_precision(precision));
+            let by_position =
+                read_csv(CsvReader::strict(data.as_bytes()).with_precision(precision));
+            assert_eq!(by_name.len(), by_position.len());
+            by_name
+                .into_iter()
+                .zip(by_position)
+                .map(|(a, b)| {
+                    assert_eq!(a.as_ref().ok(), b.as_ref().ok());
+                    a.map(|r| r.amount)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let truncated = amounts(Precision::Truncate);
+        assert_eq!(
+            truncated[0].as_ref().unwrap(),
+            &Some(Amount::from_scaled(10_001))
+        );
+        let rounded = amounts(Precision::RoundHalfEven);
+        assert_eq!(
+            rounded[0].as_ref().unwrap(),
+            &Some(Amount::from_scaled(10_002))
+        );
+        let rejected = amounts(Precision::Reject);
+        assert!(matches!(
+            rejected[0],
+            Err(Error::TooPrecise { line: Some(2), .. })
+        ));
+        // A dispute has no use for an amount, so it is not held against it.
+        assert_eq!(rejected[1].as_ref().unwrap(), &None);
+    }
+
+    #[test]
+    fn test_reader_lenient_amounts() {
+        let amounts = |data: &str, separator| {
+            let by_name = read_csv(CsvReader::new(data.as_bytes()).with_lenient_amounts(separator));
+            let by_position =
+                read_csv(CsvReader::strict(data.as_bytes()).with_lenient_amounts(separator));
+            let scaled = |records: Vec<Result<InputRecord, Error>>| {
+                records
+                    .into_iter()
+                    .map(|r| r.ok().and_then(|r| r.amount).map(Amount::scaled))
+                    .collect::<Vec<_>>()
+            };
+            let amounts = scaled(by_name);
+            assert_eq!(amounts, scaled(by_position));
+            amounts
+        };
+
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,\"1,234.56\"\n\
+                    deposit,1,2,$20.00\n\
+                    withdrawal,1,3,\"1,5\"\n";
+        assert_eq!(
+            amounts(data, DecimalSeparator::Point),
+            [Some(12_345_600), Some(200_000), None]
+        );
+        // Serde would happily take this for one and a bit.
+        let data = "type,client,tx,amount\ndeposit,1,1,1.234\ndeposit,1,2,\"2,5 €\"\n";
+        assert_eq!(
+            amounts(data, DecimalSeparator::Comma),
+            [Some(12_340_000), Some(25_000)]
+        );
+        // Without leniency, the rows are rejected as before.
+        let records = read_csv(CsvReader::new(
+            "type,client,tx,amount\ndeposit,1,1,$20\n".as_bytes(),
+        ));
+        assert!(matches!(
+            records[0],
+            Err(Error::Parse {
+                column: Column::Amount,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_is_object_url() {
+        assert!(is_object_url("s3://bucket/2024/06/01.csv"));
+        assert!(is_object_url("gs://bucket/file.csv"));
+        assert!(!is_object_url("file.csv"));
+        assert!(!is_object_url("/tmp/s3://x.csv"));
+    }
+
+    #[cfg(not(feature = "object-store"))]
+    #[test]
+    fn test_open_object_needs_feature() {
+        let err = super::InputFile::open(std::path::Path::new("s3://bucket/file.csv"))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("wd", TransactionType::Withdrawal);
+
+        let record = parse_message(" wd, 1, 2, 1.5\n", &aliases).unwrap();
+        assert_eq!(record.r#type, TransactionType::Withdrawal);
+        assert_eq!((record.client, record.tx), (ClientId::new(1), TxId::new(2)));
+        let record = parse_message(
+            r#"{"type": "wd", "client": 1, "tx": 2, "amount": "1.5"}"#,
+            &aliases,
+        )
+        .unwrap();
+        assert_eq!(record.r#type, TransactionType::Withdrawal);
+        assert_eq!(record.amount, Some("1.5".parse().unwrap()));
+
+        assert!(parse_message("", &aliases).is_err());
+        assert!(parse_message("deposit,1", &aliases).is_err());
+        assert!(parse_message("{\"type\": \"deposit\"", &aliases).is_err());
+    }
+
+    #[test]
+    fn test_reader_adjustments() {
+        let data = "type,client,tx,amount,reason\n\
+                    credit_adjustment,1,1,1.0,fx_correction\n\
+                    debit_adjustment,1,2,1.0,\n\
+                    debit_adjustment,1,3,1.0,no spaces\n\
+                    deposit,1,4,1.0,\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        let record = records[0].as_ref().unwrap();
+        assert_eq!(record.r#type, TransactionType::CreditAdjustment);
+        assert_eq!(record.reason.unwrap().code(), "FX_CORRECTION");
+        assert!(matches!(
+            records[1],
+            Err(Error::MissingReason { line: Some(3) })
+        ));
+        assert!(matches!(
+            records[2],
+            Err(Error::Parse {
+                line: Some(4),
+                column: Column::Reason,
+                ..
+            })
+        ));
+        // Nothing else needs a reason.
+        assert_eq!(records[3].as_ref().unwrap().reason, None);
+
+        // By position, the reason comes last.
+        let data = "type,client,tx,amount,timestamp,currency,reason\n\
+                    debit_adjustment,1,1,1.0,,,BANK_ERROR\n";
+        let records = read_csv(CsvReader::strict(data.as_bytes()));
+        assert!(records[0].as_ref().unwrap().reason.is_some());
+        let record = parse_message(
+            r#"{"type":"credit_adjustment","client":1,"tx":1,"amount":"1","reason":"x"}"#,
+            &TransactionTypeAliases::default(),
+        )
+        .unwrap();
+        assert_eq!(record.reason.unwrap().code(), "X");
+        let too_long = "A".repeat(33);
+        for s in ["", "has space", too_long.as_str()] {
+            assert!(s.parse::<ReasonCode>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn test_reader_timestamp_column() {
+        let data = "timestamp,type,client,tx,amount\n\
+                    2024-06-30T12:00:00+02:00,deposit,1,1,1.0\n\
+                    ,deposit,1,2,1.0\n\
+                    yesterday,deposit,1,3,1.0\n";
+        let records = read_csv(CsvReader::new(data.as_bytes()));
+        assert_eq!(
+            records[0].as_ref().unwrap().timestamp.unwrap().to_rfc3339(),
+            "2024-06-30T10:00:00+00:00"
+        );
+        assert_eq!(records[1].as_ref().unwrap().timestamp, None);
+        assert!(matches!(
+            records[2],
+            Err(Error::Parse {
+                line: Some(4),
+                column: Column::Timestamp,
+                ..
+            })
+        ));
+
+        let data = "type,client,tx,amount,timestamp\ndeposit,1,1,1.0,2024-06-30T12:00:00Z\n";
+        let records = read_csv(CsvReader::strict(data.as_bytes()));
+        assert!(records[0].as_ref().unwrap().timestamp.is_some());
     }
 }
@@ -0,0 +1,106 @@
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, ObjectStoreExt};
+use std::io::{self, Read};
+use tokio::runtime::Runtime;
+
+/// Reads an object in S3 or Google Cloud Storage front to back as it
+/// streams in, so it never has to be downloaded as a whole. The readers in
+/// `input` are blocking, so the object store runs on a runtime of its own,
+/// which is only driven while waiting for the next chunk.
+pub struct ObjectReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    /// Starts reading the object at `url`, see `input::is_object_url`.
+    /// Credentials and the region are taken from the environment the way the
+    /// AWS and Google Cloud tools do, such as `AWS_ACCESS_KEY_ID`,
+    /// `AWS_REGION` or `GOOGLE_APPLICATION_CREDENTIALS`.
+    pub fn open(url: &str) -> io::Result<Self> {
+        let (bucket_url, key) = match url.find("://").map(|i| i + 3) {
+            Some(start) => match url[start..].find('/') {
+                Some(slash) => url.split_at(start + slash),
+                None => (url, ""),
+            },
+            None => (url, ""),
+        };
+        let path = ObjectPath::from_url_path(key.trim_start_matches('/')).map_err(to_io)?;
+        if path.as_ref().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} does not name an object in a bucket", url),
+            ));
+        }
+        let store: Box<dyn ObjectStore> = if url.starts_with("s3://") {
+            Box::new(
+                AmazonS3Builder::from_env()
+                    .with_url(bucket_url)
+                    .build()
+                    .map_err(to_io)?,
+            )
+        } else {
+            Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(bucket_url)
+                    .build()
+                    .map_err(to_io)?,
+            )
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let stream = runtime
+            .block_on(store.get(&path))
+            .map_err(to_io)?
+            .into_stream();
+        Ok(ObjectReader {
+            runtime,
+            stream,
+            chunk: Bytes::new(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(to_io)?,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+/// Object store errors are passed on as I/O errors, as far as the caller
+/// is concerned they are not any different, just like Kafka errors. A
+/// missing object is reported as a missing file would be.
+fn to_io(e: impl Into<object_store::Error>) -> io::Error {
+    match e.into() {
+        e @ object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::ObjectReader;
+
+    #[test]
+    fn test_open_needs_an_object() {
+        for url in ["s3://bucket", "gs://bucket/"] {
+            let err = ObjectReader::open(url).err().unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use encoding_rs::UTF_8;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str::FromStr;
+
+/// The character encoding of text input, such as `windows-1252` for files
+/// exported on Windows. Input in any other encoding than UTF-8 is turned
+/// into UTF-8 as it is read, so the readers never see the difference.
+///
+/// Whatever the encoding, input that starts with a byte order mark is read
+/// in the encoding the mark stands for, and the mark itself is dropped, so
+/// it does not end up in the name of the first column.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Encoding(&'static encoding_rs::Encoding);
+
+impl Encoding {
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    pub fn is_utf8(&self) -> bool {
+        self.0 == UTF_8
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding(UTF_8)
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Encodings are known by the labels of the WHATWG Encoding Standard, such
+/// as `utf-8`, `utf-16le`, `latin1` or `windows-1252`, in any case. Note
+/// that, just like in a browser, `latin1` stands for `windows-1252`, which
+/// it is a subset of.
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        encoding_rs::Encoding::for_label(s.trim().as_bytes())
+            .map(Encoding)
+            .ok_or_else(|| format!("unknown encoding '{}'", s))
+    }
+}
+
+/// Wraps `reader` so it reads as UTF-8 without a byte order mark. UTF-8
+/// input without a mark is passed through as is, invalid bytes and all, so
+/// it costs next to nothing and fails just like it did before.
+pub(crate) fn decode<R: Read>(reader: R, encoding: Encoding) -> DecodeReaderBytes<R, Vec<u8>> {
+    DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding.0).filter(|_| !encoding.is_utf8()))
+        .bom_override(true)
+        .strip_bom(true)
+        .build(reader)
+}
+
+/// Where the text of `data`, the start of the input, begins, if it can be
+/// read as it is rather than through `decode`: past a UTF-8 byte order
+/// mark, or right at the start without one. `None` if the input needs to be
+/// turned into UTF-8 first.
+pub(crate) fn utf8_start(data: &[u8], encoding: Encoding) -> Option<usize> {
+    if !encoding.is_utf8() {
+        return None;
+    }
+    match encoding_rs::Encoding::for_bom(data) {
+        None => Some(0),
+        Some((bom, len)) if bom == UTF_8 => Some(len),
+        Some(_) => None,
+    }
+}
+
+/// A reader over what comes after the first `offset` bytes of `inner`, as
+/// though that was where it started. Used to read a file with a byte order
+/// mark without it while still being able to seek, see `checkpoint`.
+pub(crate) struct Skipped<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read + Seek> Skipped<R> {
+    /// Skips the start of `inner` that `utf8_start` says is not text, or
+    /// fails if `inner` would have to be turned into UTF-8 to be read, as
+    /// positions in the text then no longer tell where to seek to.
+    pub(crate) fn utf8(mut inner: R, encoding: Encoding) -> io::Result<Self> {
+        let mut start = Vec::with_capacity(3);
+        inner.by_ref().take(3).read_to_end(&mut start)?;
+        let offset = utf8_start(&start, encoding).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "only UTF-8 input can be read from a checkpoint",
+            )
+        })? as u64;
+        inner.seek(SeekFrom::Start(offset))?;
+        Ok(Skipped { inner, offset })
+    }
+}
+
+impl<R: Read> Read for Skipped<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Skipped<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => SeekFrom::Start(n + self.offset),
+            pos => pos,
+        };
+        let n = self.inner.seek(pos)?;
+        Ok(n.saturating_sub(self.offset))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{decode, utf8_start, Encoding, Skipped};
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    fn decoded(data: &[u8], encoding: &str) -> Vec<u8> {
+        let mut text = Vec::new();
+        decode(data, encoding.parse().unwrap())
+            .read_to_end(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decoded(b"\xEF\xBB\xBFtype,client", "utf-8"), b"type,client");
+        assert_eq!(decoded(b"type,\xFF", "utf-8"), b"type,\xFF");
+        assert_eq!(decoded(b"caf\xE9", "latin1"), "café".as_bytes());
+        assert_eq!(decoded(b"\xFF\xFEt\0y\0", "utf-8"), b"ty");
+        // A byte order mark wins over the encoding asked for.
+        assert_eq!(
+            decoded(b"\xEF\xBB\xBFcaf\xC3\xA9", "latin1"),
+            "café".as_bytes()
+        );
+
+        assert_eq!(Encoding::default().name(), "UTF-8");
+        assert_eq!("Latin1".parse::<Encoding>().unwrap().name(), "windows-1252");
+        assert!("klingon".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn test_utf8_start() {
+        let utf8 = Encoding::default();
+        assert_eq!(utf8_start(b"type", utf8), Some(0));
+        assert_eq!(utf8_start(b"\xEF\xBB\xBFtype", utf8), Some(3));
+        assert_eq!(utf8_start(b"\xFE\xFF\0t", utf8), None);
+        assert_eq!(utf8_start(b"type", "latin1".parse().unwrap()), None);
+
+        let mut skipped = Skipped::utf8(Cursor::new(b"\xEF\xBB\xBFabc"), utf8).unwrap();
+        let mut text = String::new();
+        skipped.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "abc");
+        assert_eq!(skipped.seek(SeekFrom::Start(1)).unwrap(), 1);
+        text.clear();
+        skipped.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "bc");
+        assert!(Skipped::utf8(Cursor::new(b"\xFF\xFEa\0"), utf8).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use super::{
+    check_amount, check_reason, parse_amount, parse_timestamp, parse_transaction_type,
+    InputPosition, InputRecord, RecordSource, Source, TransactionTypeAliases,
+};
+use crate::amount::{AmountFormat, DecimalSeparator, Precision};
+use crate::error::{Column, Error};
+use crate::id::{ClientId, TxId};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::BufRead;
+
+/// The shape of a single line of JSON input, e.g.
+/// `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`. The transaction
+/// type is kept as a string so it goes through the same validation as the
+/// CSV input does, and so is the amount, which may be a string or a number,
+/// so it can be parsed with the `Precision` asked for.
+#[derive(Debug, Deserialize)]
+struct JsonRecord {
+    r#type: String,
+    client: ClientId,
+    tx: TxId,
+    #[serde(default)]
+    amount: Option<Value>,
+    #[serde(default)]
+    timestamp: Option<String>,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    tenant: Option<String>,
+}
+
+/// A `JsonLinesReader` reads newline-delimited JSON, one transaction per
+/// line, and turns every line into an `InputRecord`. Blank lines are
+/// skipped, which makes it forgiving of a trailing newline at the end of a
+/// feed.
+pub struct JsonLinesReader<R> {
+    reader: R,
+    line: u64,
+    byte: u64,
+    buf: String,
+    aliases: TransactionTypeAliases,
+    amounts: AmountFormat,
+}
+
+impl<R: BufRead> JsonLinesReader<R> {
+    pub fn new(reader: R) -> Self {
+        JsonLinesReader {
+            reader,
+            line: 0,
+            byte: 0,
+            buf: String::new(),
+            aliases: TransactionTypeAliases::default(),
+            amounts: AmountFormat::default(),
+        }
+    }
+
+    /// Makes the reader accept `aliases` for the transaction types.
+    pub fn with_aliases(self, aliases: TransactionTypeAliases) -> Self {
+        JsonLinesReader { aliases, ..self }
+    }
+
+    /// Makes the reader handle amounts with too many decimal places as
+    /// `precision` says.
+    pub fn with_precision(self, precision: Precision) -> Self {
+        let amounts = AmountFormat {
+            precision,
+            ..self.amounts
+        };
+        JsonLinesReader { amounts, ..self }
+    }
+
+    /// Makes the reader accept amounts given as strings such as
+    /// `"$1,234.56"`, with `separator` between the whole units and the
+    /// fraction, see `Amount::parse_lenient`. Amounts given as numbers are
+    /// plain decimal numbers regardless.
+    pub fn with_lenient_amounts(self, separator: DecimalSeparator) -> Self {
+        let amounts = AmountFormat {
+            lenient: Some(separator),
+            ..self.amounts
+        };
+        JsonLinesReader { amounts, ..self }
+    }
+
+    /// How far the reader got, see `starting_at`.
+    pub fn position(&self) -> InputPosition {
+        InputPosition {
+            byte: self.byte,
+            line: self.line,
+        }
+    }
+
+    /// Makes the reader count lines and bytes from `position`, for a reader
+    /// that has been moved there, such as a file seeked to `position.byte`.
+    pub fn starting_at(self, position: InputPosition) -> Self {
+        JsonLinesReader {
+            line: position.line,
+            byte: position.byte,
+            ..self
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesReader<R> {
+    type Item = Result<InputRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    self.line += 1;
+                    self.byte += n as u64;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+            if !self.buf.trim().is_empty() {
+                return Some(parse_json_record(
+                    &self.buf,
+                    Some(self.line),
+                    &self.aliases,
+                    self.amounts,
+                ));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> RecordSource for JsonLinesReader<R> {
+    fn source(&self) -> Source {
+        Source {
+            line: Some(self.line),
+            raw: self.buf.trim_end().to_string(),
+        }
+    }
+}
+
+/// Deserializes a single line of JSON into an `InputRecord`.
+pub fn make_json_record(s: &str, line: Option<u64>) -> Result<InputRecord, Error> {
+    parse_json_record(
+        s,
+        line,
+        &TransactionTypeAliases::default(),
+        AmountFormat::default(),
+    )
+}
+
+/// Deserializes a single line of JSON, accepting `aliases` for the
+/// transaction types and reading amounts as `amounts` says.
+pub(crate) fn parse_json_record(
+    s: &str,
+    line: Option<u64>,
+    aliases: &TransactionTypeAliases,
+    amounts: AmountFormat,
+) -> Result<InputRecord, Error> {
+    let record: JsonRecord =
+        serde_json::from_str(s).map_err(|source| Error::Json { line, source })?;
+    let transaction_type = match aliases.get(&record.r#type) {
+        Some(transaction_type) => transaction_type,
+        None => parse_transaction_type(Some(record.r#type.trim()), line)?,
+    };
+    let amount = match record.amount {
+        Some(Value::String(s)) => parse_amount(s.trim(), &transaction_type, amounts, line)?,
+        Some(Value::Number(n)) => {
+            // Written out like `Amount` does when it deserializes a number,
+            // which is never in scientific notation.
+            let text = match (n.as_u64(), n.as_i64(), n.as_f64()) {
+                (Some(v), _, _) => v.to_string(),
+                (_, Some(v), _) => v.to_string(),
+                (_, _, v) => v.unwrap_or_default().to_string(),
+            };
+            parse_amount(&text, &transaction_type, amounts.plain(), line)?
+        }
+        Some(Value::Null) | None => None,
+        Some(value) if transaction_type.requires_amount() => {
+            return Err(Error::Parse {
+                line,
+                column: Column::Amount,
+                value: value.to_string(),
+            })
+        }
+        Some(_) => None,
+    };
+    check_amount(&transaction_type, amount, line)?;
+    let timestamp = match record.timestamp {
+        Some(s) if !s.trim().is_empty() => {
+            Some(parse_timestamp(s.trim()).map_err(|_| Error::Parse {
+                line,
+                column: Column::Timestamp,
+                value: s,
+            })?)
+        }
+        _ => None,
+    };
+    let currency = match record.currency {
+        Some(s) if !s.trim().is_empty() => Some(s.trim().parse().map_err(|_| Error::Parse {
+            line,
+            column: Column::Currency,
+            value: s,
+        })?),
+        _ => None,
+    };
+    let reason = match record.reason {
+        Some(s) if !s.trim().is_empty() => Some(s.trim().parse().map_err(|_| Error::Parse {
+            line,
+            column: Column::Reason,
+            value: s,
+        })?),
+        _ => None,
+    };
+    check_reason(&transaction_type, reason, line)?;
+    let tenant = match record.tenant {
+        Some(s) if !s.trim().is_empty() => Some(s.trim().parse().map_err(|_| Error::Parse {
+            line,
+            column: Column::Tenant,
+            value: s,
+        })?),
+        _ => None,
+    };
+
+    Ok(InputRecord {
+        r#type: transaction_type,
+        client: record.client,
+        tx: record.tx,
+        amount,
+        timestamp,
+        currency,
+        reason,
+        tenant,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{make_json_record, JsonLinesReader};
+    use crate::amount::{Amount, DecimalSeparator, Precision};
+    use crate::error::Error;
+    use crate::id::{ClientId, TxId};
+    use crate::input::RecordSource;
+    use crate::input::{InputRecord, TransactionType, TransactionTypeAliases};
+
+    #[test]
+    fn test_valid_deposit_record() {
+        let record = make_json_record(
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}"#,
+            None,
+        );
+        let test_record = InputRecord {
+            r#type: TransactionType::Deposit,
+            client: ClientId::new(1),
+            tx: TxId::new(1),
+            amount: Some(Amount::from_scaled(15_000)),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
+        };
+        assert_eq!(record.unwrap(), test_record);
+    }
+
+    #[test]
+    fn test_valid_dispute_record_without_amount() {
+        let record = make_json_record(r#"{"type":"dispute","client":1,"tx":1}"#, None);
+        assert_eq!(record.unwrap().amount, None);
+    }
+
+    #[test]
+    fn test_deposit_without_amount() {
+        let record = make_json_record(r#"{"type":"deposit","client":1,"tx":1}"#, Some(4));
+        assert!(matches!(
+            record,
+            Err(Error::MissingAmount { line: Some(4) })
+        ));
+    }
+
+    #[test]
+    fn test_unknown_transaction_type() {
+        let record = make_json_record(r#"{"type":"refund","client":1,"tx":1,"amount":"1"}"#, None);
+        assert!(matches!(record, Err(Error::UnknownTransactionType { .. })));
+    }
+
+    #[test]
+    fn test_malformed_json() {
+        let record = make_json_record(r#"{"type":"deposit","client":"#, Some(2));
+        assert!(matches!(record, Err(Error::Json { line: Some(2), .. })));
+    }
+
+    #[test]
+    fn test_reader_counts_lines_and_skips_blanks() {
+        let data = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.0\"}\n\
+                    \n\
+                    {\"type\":\"withdrawal\",\"client\":1,\"tx\":2}\n";
+        let records: Vec<_> = JsonLinesReader::new(data.as_bytes()).collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].is_ok());
+        assert_eq!(records[1].as_ref().unwrap_err().line(), Some(3));
+    }
+
+    #[test]
+    fn test_reader_source() {
+        let data = "\n{\"type\":\"dispute\",\"client\":1,\"tx\":1}\r\n";
+        let mut reader = JsonLinesReader::new(data.as_bytes()).with_source();
+        let (source, _) = reader.next().unwrap();
+        assert_eq!(source.line, Some(2));
+        assert_eq!(source.raw, "{\"type\":\"dispute\",\"client\":1,\"tx\":1}");
+    }
+
+    #[test]
+    fn test_reader_precision() {
+        let data = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.00019\"}\n\
+                    {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1.00019}\n\
+                    {\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":2}\n";
+        let records: Vec<_> = JsonLinesReader::new(data.as_bytes())
+            .with_precision(Precision::Truncate)
+            .map(|r| r.unwrap().amount.unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                Amount::from_scaled(10_001),
+                Amount::from_scaled(10_001),
+                Amount::from_scaled(20_000)
+            ]
+        );
+        let records: Vec<_> = JsonLinesReader::new(data.as_bytes())
+            .with_precision(Precision::Reject)
+            .collect();
+        assert!(matches!(
+            records[1],
+            Err(Error::TooPrecise { line: Some(2), .. })
+        ));
+        assert!(records[2].is_ok());
+    }
+
+    #[test]
+    fn test_reader_lenient_amounts() {
+        let data = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.234,5 €\"}\n\
+                    {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1.234}\n";
+        let records: Vec<_> = JsonLinesReader::new(data.as_bytes())
+            .with_lenient_amounts(DecimalSeparator::Comma)
+            .map(|r| r.unwrap().amount.unwrap())
+            .collect();
+        // Numbers are never written with a decimal comma.
+        assert_eq!(
+            records,
+            vec![Amount::from_scaled(12_345_000), Amount::from_scaled(12_340)]
+        );
+    }
+
+    #[test]
+    fn test_reader_aliases() {
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("dep", TransactionType::Deposit);
+        let data = "{\"type\":\"dep\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n";
+        let records: Vec<_> = JsonLinesReader::new(data.as_bytes())
+            .with_aliases(aliases)
+            .collect();
+        assert_eq!(
+            records[0].as_ref().unwrap().r#type,
+            TransactionType::Deposit
+        );
+    }
+}
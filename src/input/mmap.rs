@@ -0,0 +1,284 @@
+use super::{CsvOptions, InputRecord, RecordSource, Source};
+use crate::error::Error;
+use crate::{csv_reader, pipeline, read_records, ProcessOptions};
+use csv::Position;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, Cursor};
+use std::ops::Range;
+
+/// CSV files of at least this many bytes are mapped into memory rather than
+/// read, unless `ProcessOptions::mmap_threshold` says otherwise. Below it,
+/// setting up the map costs more than it saves.
+pub const DEFAULT_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// The size of the chunks a mapped file is split into for the parser
+/// threads. A chunk ends at the end of a row, so it comes out a little
+/// larger. Every chunk is parsed in one go, so this, times the number of
+/// chunks parsed ahead, bounds the memory the parsed records take.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A run of whole rows of a mapped file: where it is, and the number of
+/// lines and of rows, the header included, that come before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    pub bytes: Range<usize>,
+    pub line: u64,
+    pub record: u64,
+}
+
+/// Maps `file` into memory if it is at least `threshold` bytes long.
+pub(crate) fn map_if_large(file: &File, threshold: u64) -> io::Result<Option<Mmap>> {
+    let len = file.metadata()?.len();
+    if len == 0 || len < threshold {
+        return Ok(None);
+    }
+    // SAFETY: the map is only ever read from. Should another process change
+    // the file while it is mapped, the rows read may come out garbled, just
+    // as they could when reading a file that is being written to.
+    unsafe { Mmap::map(file) }.map(Some)
+}
+
+/// Hands every record of a mapped CSV file to `apply`, in order. With parser
+/// threads, the file is split into chunks that are parsed in parallel, see
+/// `split_rows`, rather than read row by row. Either way the records, and
+/// where they came from, are exactly those a `CsvReader` would produce.
+pub(crate) fn for_each_record<F>(
+    data: &[u8],
+    options: &ProcessOptions,
+    mut apply: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Source, Result<InputRecord, Error>) -> Result<(), Error>,
+{
+    if options.parse_threads == 0 {
+        for (source, record) in read_records(data, options) {
+            apply(source, record)?;
+        }
+        return Ok(());
+    }
+    let chunks = split_rows(data, &options.csv, CHUNK_SIZE);
+    pipeline::for_each_chunk_record(data, chunks, options, options.parse_threads, apply)
+}
+
+/// Splits CSV data into chunks of about `size` bytes that each end at the
+/// end of a row, so they can be parsed independently of each other.
+///
+/// Only line breaks and quotes matter for this, which are found with
+/// `memchr`, many bytes at a time, rather than by running every byte
+/// through a CSV parser. A line break ends a row unless it is quoted, which
+/// it is after an odd number of quotes, as an escaped quote is written
+/// twice. A quote only starts a quoted field at the start of the field,
+/// though; anywhere else it is taken as is. Rather than keep track of
+/// fields, the rest of the data is left in a single chunk once there is
+/// such a quote, or a row that ends in a carriage return alone, so rows are
+/// never cut in half and are counted just like the CSV reader counts them.
+pub(crate) fn split_rows(data: &[u8], csv: &CsvOptions, size: usize) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut line = 0;
+    let mut record = 0;
+    let (mut lines, mut records) = (0, 0);
+    let mut row_start = 0;
+    let mut quoted = false;
+    let mut closed_at = None;
+    for i in memchr::memchr3_iter(b'\n', b'\r', csv.quote, data) {
+        match data[i] {
+            b'\n' => lines += 1,
+            b'\r' if quoted || data.get(i + 1) == Some(&b'\n') => continue,
+            b'\r' => break,
+            _ => (),
+        }
+        if data[i] == b'\n' {
+            if quoted {
+                continue;
+            }
+            // The CSV reader skips empty lines as part of the row after them,
+            // and stops right after the carriage return of a CRLF, so that
+            // is where the next chunk has to start for it to see the same
+            // positions.
+            let end = if i > row_start && data[i - 1] == b'\r' {
+                i - 1
+            } else {
+                i
+            };
+            let empty = end == row_start;
+            row_start = i + 1;
+            if empty {
+                continue;
+            }
+            records += 1;
+            let (cut, cut_lines) = if end < i {
+                (i, lines - 1)
+            } else {
+                (i + 1, lines)
+            };
+            if cut - start >= size {
+                chunks.push(Chunk {
+                    bytes: start..cut,
+                    line,
+                    record,
+                });
+                start = cut;
+                line = cut_lines;
+                record = records;
+            }
+            continue;
+        }
+        if !quoted {
+            let field_start = i == 0
+                || [csv.delimiter, b'\n', b'\r'].contains(&data[i - 1])
+                || closed_at == Some(i - 1);
+            if !field_start {
+                break;
+            }
+        } else {
+            closed_at = Some(i);
+        }
+        quoted = !quoted;
+    }
+    if start < data.len() || chunks.is_empty() {
+        chunks.push(Chunk {
+            bytes: start..data.len(),
+            line,
+            record,
+        });
+    }
+    chunks
+}
+
+/// Parses the rows of a single chunk of `data`. The reader sees all of the
+/// data up to the end of the chunk, so it reads the header row just like a
+/// reader of the whole file does, and then moves to the start of the chunk.
+pub(crate) fn parse_chunk(
+    data: &[u8],
+    chunk: &Chunk,
+    options: &ProcessOptions,
+) -> Vec<(Source, Result<InputRecord, Error>)> {
+    let mut reader = csv_reader(Cursor::new(&data[..chunk.bytes.end]), options);
+    if chunk.bytes.start > 0 {
+        let mut position = Position::new();
+        position
+            .set_byte(chunk.bytes.start as u64)
+            .set_line(chunk.line + 1)
+            .set_record(chunk.record);
+        if let Err(e) = reader.seek_to(position) {
+            return vec![(Source::default(), Err(e))];
+        }
+    }
+    reader.with_source().collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{for_each_record, map_if_large, split_rows, Chunk};
+    use crate::input::{CsvOptions, CsvReader, RecordSource};
+    use crate::{pipeline, ProcessOptions};
+    use std::fs::{self, File};
+
+    fn sample_input() -> String {
+        let mut data = String::from("type,client,tx,amount\n");
+        for tx in 1..=3000u32 {
+            match tx % 13 {
+                0 => data.push_str(&format!("\"deposit\",{},{},\"1.0\"\n", tx % 7, tx)),
+                1 => data.push_str(&format!("deposit,{},{},\"1,5\"\r\n", tx % 7, tx)),
+                2 => data.push_str(&format!("\"with\"\"drawal\",{},{},1\n", tx % 7, tx)),
+                3 => data.push_str(&format!("\"deposit\n\",{},{},1\n", tx % 7, tx)),
+                4 => data.push('\n'),
+                5 => data.push_str(&format!("deposit,{},{}\n", tx % 7, tx)),
+                _ => data.push_str(&format!("deposit,{},{},{}.5\n", tx % 7, tx, tx % 10)),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_split_rows() {
+        let csv = CsvOptions::default();
+        let data = b"a,b\n\"1\n2\",3\n\n4,5\n";
+        assert_eq!(
+            split_rows(data, &csv, 1),
+            [
+                Chunk {
+                    bytes: 0..4,
+                    line: 0,
+                    record: 0
+                },
+                Chunk {
+                    bytes: 4..12,
+                    line: 1,
+                    record: 1
+                },
+                Chunk {
+                    bytes: 12..17,
+                    line: 3,
+                    record: 2
+                },
+            ]
+        );
+        assert_eq!(split_rows(data, &csv, 100).len(), 1);
+        assert_eq!(split_rows(b"", &csv, 1)[0].bytes, 0..0);
+
+        // A quote within a field leaves the rest of the data in one chunk,
+        // and so does a carriage return on its own.
+        for data in [&b"a,b\n1,2\"\n3,4\n5,6\n"[..], b"a,b\n1,2\r3,4\n5,6\n"] {
+            let chunks = split_rows(data, &csv, 1);
+            assert_eq!(chunks.len(), 2);
+            assert_eq!(chunks[1].bytes, 4..data.len());
+        }
+    }
+
+    #[test]
+    fn test_chunks_match_csv_reader() {
+        let data = sample_input();
+        let expected: Vec<_> = CsvReader::new(data.as_bytes())
+            .with_source()
+            .map(|(source, record)| (source, format!("{:?}", record)))
+            .collect();
+
+        // Chunks of a single byte are cut at the end of every row.
+        let options = ProcessOptions::default();
+        for size in [1, 1000] {
+            let chunks = split_rows(data.as_bytes(), &options.csv, size);
+            assert!(chunks.len() > 10);
+            let mut actual = Vec::new();
+            pipeline::for_each_chunk_record(
+                data.as_bytes(),
+                chunks,
+                &options,
+                3,
+                |source, record| {
+                    actual.push((source, format!("{:?}", record)));
+                    Ok(())
+                },
+            )
+            .unwrap();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_map_if_large() {
+        let path = std::env::temp_dir().join(format!("payments-mmap-{}.csv", std::process::id()));
+        fs::write(&path, sample_input()).unwrap();
+        let file = File::open(&path).unwrap();
+        assert!(map_if_large(&file, u64::MAX).unwrap().is_none());
+        let map = map_if_large(&file, 0).unwrap().unwrap();
+        assert_eq!(&map[..], sample_input().as_bytes());
+
+        for parse_threads in [0, 2] {
+            let options = ProcessOptions {
+                parse_threads,
+                ..ProcessOptions::default()
+            };
+            let mut records = 0;
+            for_each_record(&map, &options, |_, _| {
+                records += 1;
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(records, CsvReader::new(&map[..]).count());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use super::{
+    make_aliased_input_record, source_of, InputRecord, RecordSource, Source, TransactionTypeAliases,
+};
+use crate::amount::{AmountFormat, DecimalSeparator, Precision};
+use crate::error::{Column, Error};
+use arrow::array::{Array, ArrayRef, RecordBatch, StringArray};
+use arrow::compute::cast;
+use arrow::datatypes::DataType;
+use bytes::Bytes;
+use csv::{Position, StringRecord};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::file::reader::ChunkReader;
+use std::io::Read;
+
+/// A `ParquetReader` reads transactions from a Parquet file, one row at a
+/// time, with the same columns a CSV file has. Columns are matched by name,
+/// so they may come in any order, and any type that can be turned into text
+/// will do: an amount can be a string, a float or a decimal, a timestamp a
+/// string or a timestamp without a time zone, which is taken as UTC.
+///
+/// Every row is turned into a CSV row and parsed as such, so it is checked
+/// exactly like one, and it is reported with its row number, counting from
+/// 1, where a CSV row would have its line number.
+pub struct ParquetReader {
+    batches: ParquetRecordBatchReader,
+    /// Where each of `Column::ALL` is in the file, if it is there at all.
+    positions: [Option<usize>; Column::ALL.len()],
+    /// The columns of the current batch, as text, in the order of
+    /// `Column::ALL`.
+    columns: Vec<Option<StringArray>>,
+    /// The next row of the current batch.
+    next: usize,
+    row: u64,
+    s_record: StringRecord,
+    aliases: TransactionTypeAliases,
+    amounts: AmountFormat,
+    /// Whether the amounts of the current batch are strings, rather than
+    /// numbers which are never written leniently.
+    text_amounts: bool,
+}
+
+/// Errors from `parquet` and `arrow` are passed on as I/O errors, just like
+/// the ones from the other optional dependencies.
+fn parquet_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+impl ParquetReader {
+    /// Opens a Parquet file held by anything `parquet` can read from, such
+    /// as a `File`. Fails if it is not a Parquet file or lacks the type,
+    /// client or tx column.
+    pub fn new<T: ChunkReader + 'static>(input: T) -> Result<Self, Error> {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(input).map_err(parquet_error)?;
+        let mut positions = [None; Column::ALL.len()];
+        for column in Column::ALL {
+            positions[column.index()] = builder
+                .schema()
+                .fields()
+                .iter()
+                .position(|field| field.name().eq_ignore_ascii_case(column.name()));
+            if positions[column.index()].is_none() && column.is_required() {
+                return Err(Error::MissingColumn { column });
+            }
+        }
+        Ok(ParquetReader {
+            batches: builder.build().map_err(parquet_error)?,
+            positions,
+            columns: Vec::new(),
+            next: 0,
+            row: 0,
+            s_record: StringRecord::new(),
+            aliases: TransactionTypeAliases::default(),
+            amounts: AmountFormat::default(),
+            text_amounts: false,
+        })
+    }
+
+    /// Reads a Parquet file from any reader. The metadata of a Parquet file
+    /// is at its very end, so the file is read into memory as a whole
+    /// first; use `new` with a `File` to avoid that.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        ParquetReader::new(Bytes::from(buf))
+    }
+
+    /// Makes the reader accept `aliases` for the transaction types.
+    pub fn with_aliases(self, aliases: TransactionTypeAliases) -> Self {
+        ParquetReader { aliases, ..self }
+    }
+
+    /// Makes the reader handle amounts with too many decimal places as
+    /// `precision` says. A decimal column is turned into text with all of
+    /// its decimal places, a float with as many as it takes.
+    pub fn with_precision(self, precision: Precision) -> Self {
+        let amounts = AmountFormat {
+            precision,
+            ..self.amounts
+        };
+        ParquetReader { amounts, ..self }
+    }
+
+    /// Makes the reader accept amounts given as strings such as
+    /// `"$1,234.56"`, with `separator` between the whole units and the
+    /// fraction, see `Amount::parse_lenient`. Numeric amount columns are
+    /// read as they are regardless.
+    pub fn with_lenient_amounts(self, separator: DecimalSeparator) -> Self {
+        let amounts = AmountFormat {
+            lenient: Some(separator),
+            ..self.amounts
+        };
+        ParquetReader { amounts, ..self }
+    }
+
+    /// Turns the columns of `batch` into text, so they can be parsed like
+    /// the columns of a CSV row.
+    fn read_batch(&mut self, batch: &RecordBatch) -> Result<(), Error> {
+        let mut columns = Vec::with_capacity(Column::ALL.len());
+        for position in self.positions {
+            let column = match position {
+                Some(i) => Some(as_text(batch.column(i))?),
+                None => None,
+            };
+            columns.push(column);
+        }
+        self.columns = columns;
+        self.text_amounts = self.positions[Column::Amount.index()].is_some_and(|i| {
+            matches!(
+                batch.column(i).data_type(),
+                DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View
+            )
+        });
+        self.next = 0;
+        Ok(())
+    }
+
+    /// The number of rows in the current batch.
+    fn batch_len(&self) -> usize {
+        self.columns
+            .iter()
+            .flatten()
+            .map(|c| c.len())
+            .next()
+            .unwrap_or(0)
+    }
+}
+
+/// Casts a column to strings. Timestamps without a time zone are taken as
+/// UTC, so they come out in RFC 3339 format.
+fn as_text(column: &ArrayRef) -> Result<StringArray, Error> {
+    let column = match column.data_type() {
+        DataType::Timestamp(unit, None) => {
+            cast(column, &DataType::Timestamp(*unit, Some("+00:00".into())))
+                .map_err(parquet_error)?
+        }
+        _ => column.clone(),
+    };
+    let text = cast(&column, &DataType::Utf8).map_err(parquet_error)?;
+    Ok(text
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("a cast to Utf8 yields a StringArray")
+        .clone())
+}
+
+impl Iterator for ParquetReader {
+    type Item = Result<InputRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next >= self.batch_len() {
+            let batch = match self.batches.next()? {
+                Ok(batch) => batch,
+                Err(e) => return Some(Err(parquet_error(e))),
+            };
+            if let Err(e) = self.read_batch(&batch) {
+                return Some(Err(e));
+            }
+        }
+
+        let i = self.next;
+        self.next += 1;
+        self.row += 1;
+        self.s_record.clear();
+        for column in &self.columns {
+            match column {
+                Some(c) if c.is_valid(i) => self.s_record.push_field(c.value(i)),
+                _ => self.s_record.push_field(""),
+            }
+        }
+        let mut position = Position::new();
+        position.set_line(self.row);
+        self.s_record.set_position(Some(position));
+        let amounts = match self.text_amounts {
+            true => self.amounts,
+            false => self.amounts.plain(),
+        };
+        Some(make_aliased_input_record(
+            &self.s_record,
+            &self.aliases,
+            amounts,
+        ))
+    }
+}
+
+impl RecordSource for ParquetReader {
+    fn source(&self) -> Source {
+        source_of(&self.s_record)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::ParquetReader;
+    use crate::amount::Amount;
+    use crate::error::Error;
+    use crate::id::ClientId;
+    use crate::input::{RecordSource, TransactionType, TransactionTypeAliases};
+    use arrow::array::{
+        ArrayRef, Decimal128Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt32Array,
+    };
+    use bytes::Bytes;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    fn parquet_file(columns: Vec<(&str, ArrayRef)>) -> Bytes {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        Bytes::from(buf)
+    }
+
+    #[test]
+    fn test_reader() {
+        let file = parquet_file(vec![
+            ("Client", Arc::new(UInt32Array::from(vec![1, 1, 2]))),
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "wd", "deposit"])),
+            ),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2, 3]))),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![Some(150), Some(50), None])
+                        .with_precision_and_scale(10, 2)
+                        .unwrap(),
+                ),
+            ),
+            (
+                "timestamp",
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    Some(0),
+                    None,
+                    Some(1_000),
+                ])),
+            ),
+        ]);
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("wd", TransactionType::Withdrawal);
+        let mut reader = ParquetReader::new(file).unwrap().with_aliases(aliases);
+
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.client, ClientId::new(1));
+        assert_eq!(record.amount, Some(Amount::from_scaled(15_000)));
+        assert_eq!(
+            record.timestamp.unwrap().to_rfc3339(),
+            "1970-01-01T00:00:00+00:00"
+        );
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.r#type, TransactionType::Withdrawal);
+        assert_eq!(record.timestamp, None);
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Error::MissingAmount { line: Some(3) }))
+        ));
+        assert_eq!(reader.source().raw, "deposit,2,3,,1970-01-01T00:00:01Z,,");
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let file = parquet_file(vec![(
+            "type",
+            Arc::new(StringArray::from(vec!["deposit"])) as ArrayRef,
+        )]);
+        assert!(matches!(
+            ParquetReader::new(file),
+            Err(Error::MissingColumn { .. })
+        ));
+        assert!(ParquetReader::from_reader(&b"type,client,tx"[..]).is_err());
+    }
+}
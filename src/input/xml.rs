@@ -0,0 +1,376 @@
+use super::{
+    make_aliased_input_record, source_of, InputRecord, RecordSource, Source, TransactionTypeAliases,
+};
+use crate::amount::{AmountFormat, Precision};
+use crate::error::Error;
+use csv::{Position, StringRecord};
+use quick_xml::escape::unescape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::io::{BufRead, ErrorKind};
+
+/// An `XmlReader` reads transactions from a bank statement in a simplified
+/// form of ISO 20022 camt.053, as some banking partners send them:
+///
+/// ```xml
+/// <Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+///   <BkToCstmrStmt>
+///     <Stmt>
+///       <Acct><Id><Othr><Id>1</Id></Othr></Id></Acct>
+///       <Ntry>
+///         <NtryRef>17</NtryRef>
+///         <Amt Ccy="EUR">25.00</Amt>
+///         <CdtDbtInd>CRDT</CdtDbtInd>
+///         <BookgDt><DtTm>2024-03-01T09:30:00Z</DtTm></BookgDt>
+///       </Ntry>
+///     </Stmt>
+///   </BkToCstmrStmt>
+/// </Document>
+/// ```
+///
+/// Every `Ntry` of a `Stmt` is a transaction of the client in the `Othr/Id`
+/// of the account of the statement. Its `NtryRef` is the transaction ID, its
+/// `Amt` the amount, in the currency of the `Ccy` attribute, and its booking
+/// date, either a `DtTm` or a `Dt`, which is taken as midnight UTC, the
+/// timestamp. A credit is a deposit and a debit a withdrawal. As camt.053 has
+/// no notion of disputes, an entry may instead name its transaction type in
+/// a `Tp` element of its own, such as `dispute`. Anything else in the
+/// document is ignored, as are namespace prefixes.
+///
+/// Like a Parquet row, every entry is turned into a CSV row and parsed as
+/// such, so it is checked exactly like one, and it is reported by its number,
+/// counting from 1. The document is read as it goes, so it never has to fit
+/// in memory. A document that is not well-formed XML ends the records with
+/// an error.
+pub struct XmlReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    state: State,
+    /// Set once the document ended, one way or another.
+    done: bool,
+    entries: u64,
+    s_record: StringRecord,
+    aliases: TransactionTypeAliases,
+    amounts: AmountFormat,
+}
+
+/// Where the reader is in the document, and what it found out so far.
+#[derive(Debug, Default)]
+struct State {
+    /// The local names of the elements the reader is in, innermost last.
+    path: Vec<String>,
+    /// The text of the innermost element so far.
+    text: String,
+    /// The client of the current statement.
+    client: String,
+    /// The current entry, if the reader is in one.
+    entry: Option<Entry>,
+}
+
+/// The parts of an `Ntry` that make up a record, as text.
+#[derive(Debug, Default)]
+struct Entry {
+    r#type: String,
+    indicator: String,
+    tx: String,
+    amount: String,
+    currency: String,
+    timestamp: String,
+}
+
+/// Errors from `quick-xml` are passed on as I/O errors, just like the ones
+/// from the other optional dependencies.
+fn xml_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Io(std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+impl<R: BufRead> XmlReader<R> {
+    pub fn new(reader: R) -> Self {
+        XmlReader {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            state: State::default(),
+            done: false,
+            entries: 0,
+            s_record: StringRecord::new(),
+            aliases: TransactionTypeAliases::default(),
+            amounts: AmountFormat::default(),
+        }
+    }
+
+    /// Makes the reader accept `aliases` for the transaction types named in
+    /// `Tp` elements.
+    pub fn with_aliases(self, aliases: TransactionTypeAliases) -> Self {
+        XmlReader { aliases, ..self }
+    }
+
+    /// Makes the reader handle amounts with too many decimal places as
+    /// `precision` says.
+    pub fn with_precision(self, precision: Precision) -> Self {
+        let amounts = AmountFormat {
+            precision,
+            ..self.amounts
+        };
+        XmlReader { amounts, ..self }
+    }
+
+    /// Turns a complete entry into a record, by way of a CSV row.
+    fn make_record(&mut self, entry: Entry) -> Result<InputRecord, Error> {
+        self.entries += 1;
+        let r#type = match (entry.r#type.as_str(), entry.indicator.as_str()) {
+            ("", "CRDT") => "deposit",
+            ("", "DBIT") => "withdrawal",
+            ("", indicator) => indicator,
+            (r#type, _) => r#type,
+        };
+        self.s_record.clear();
+        for field in [
+            r#type,
+            &self.state.client,
+            &entry.tx,
+            &entry.amount,
+            &entry.timestamp,
+            &entry.currency,
+        ] {
+            self.s_record.push_field(field);
+        }
+        let mut position = Position::new();
+        position.set_line(self.entries);
+        self.s_record.set_position(Some(position));
+        make_aliased_input_record(&self.s_record, &self.aliases, self.amounts)
+    }
+}
+
+impl State {
+    fn start(&mut self, element: &BytesStart) -> Result<(), Error> {
+        let name = element.local_name().as_ref().to_string();
+        match name.as_str() {
+            "Stmt" => self.client.clear(),
+            "Ntry" => self.entry = Some(Entry::default()),
+            "Amt" if self.parent() == Some("Ntry") => {
+                if let (Some(entry), Some(currency)) = (
+                    &mut self.entry,
+                    element.try_get_attribute("Ccy").map_err(xml_error)?,
+                ) {
+                    entry.currency = currency
+                        .normalized_value(quick_xml::XmlVersion::Implicit1_0)
+                        .map_err(xml_error)?
+                        .trim()
+                        .to_string();
+                }
+            }
+            _ => (),
+        }
+        self.path.push(name);
+        self.text.clear();
+        Ok(())
+    }
+
+    /// Handles the end of the innermost element, returning the entry it
+    /// completes, if any.
+    fn end(&mut self) -> Option<Entry> {
+        let name = self.path.pop().unwrap_or_default();
+        let text = std::mem::take(&mut self.text).trim().to_string();
+        if name == "Ntry" {
+            return self.entry.take();
+        }
+        let parent = self.path.last().map(String::as_str);
+        match &mut self.entry {
+            Some(entry) => match (name.as_str(), parent) {
+                ("NtryRef", Some("Ntry")) => entry.tx = text,
+                ("Amt", Some("Ntry")) => entry.amount = text,
+                ("CdtDbtInd", Some("Ntry")) => entry.indicator = text,
+                ("Tp", Some("Ntry")) => entry.r#type = text,
+                ("DtTm", Some("BookgDt")) => entry.timestamp = text,
+                ("Dt", Some("BookgDt")) if !text.is_empty() => {
+                    entry.timestamp = format!("{}T00:00:00Z", text)
+                }
+                _ => (),
+            },
+            None => {
+                if name == "Id" && parent == Some("Othr") && self.path.iter().any(|n| n == "Acct") {
+                    self.client = text;
+                }
+            }
+        }
+        None
+    }
+
+    fn parent(&self) -> Option<&str> {
+        self.path.last().map(String::as_str)
+    }
+}
+
+impl<R: BufRead> Iterator for XmlReader<R> {
+    type Item = Result<InputRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(event) => event,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(xml_error(e)));
+                }
+            };
+            let completed = match event {
+                Event::Start(element) => self.state.start(&element).map(|_| None),
+                Event::Empty(element) => self.state.start(&element).map(|_| self.state.end()),
+                Event::End(_) => Ok(self.state.end()),
+                Event::Text(text) => {
+                    self.state.text.push_str(&text.xml10_content());
+                    Ok(None)
+                }
+                Event::CData(data) => {
+                    self.state.text.push_str(&data.xml10_content());
+                    Ok(None)
+                }
+                Event::GeneralRef(reference) => {
+                    match unescape(&format!("&{};", reference.xml10_content())) {
+                        Ok(resolved) => {
+                            self.state.text.push_str(&resolved);
+                            Ok(None)
+                        }
+                        Err(e) => Err(xml_error(e)),
+                    }
+                }
+                Event::Eof if !self.state.path.is_empty() => Err(xml_error(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "the document ends in the middle of an element",
+                ))),
+                Event::Eof => {
+                    self.done = true;
+                    Ok(None)
+                }
+                _ => Ok(None),
+            };
+            match completed {
+                Ok(Some(entry)) => return Some(self.make_record(entry)),
+                Ok(None) => (),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<R: BufRead> RecordSource for XmlReader<R> {
+    fn source(&self) -> Source {
+        source_of(&self.s_record)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::XmlReader;
+    use crate::amount::Amount;
+    use crate::error::{Column, Error};
+    use crate::id::{ClientId, TxId};
+    use crate::input::{RecordSource, TransactionType, TransactionTypeAliases};
+
+    const STATEMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns:c="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <c:BkToCstmrStmt>
+    <c:GrpHdr><c:MsgId>statement &amp; more</c:MsgId></c:GrpHdr>
+    <c:Stmt>
+      <c:Acct><c:Id><c:Othr><c:Id>1</c:Id></c:Othr></c:Id></c:Acct>
+      <c:Ntry>
+        <c:NtryRef>10</c:NtryRef>
+        <c:Amt Ccy="EUR">25.50</c:Amt>
+        <c:CdtDbtInd>CRDT</c:CdtDbtInd>
+        <c:BookgDt><c:DtTm>2024-03-01T09:30:00Z</c:DtTm></c:BookgDt>
+        <c:NtryDtls><c:TxDtls><c:Amt Ccy="USD">99</c:Amt></c:TxDtls></c:NtryDtls>
+      </c:Ntry>
+      <c:Ntry>
+        <c:NtryRef>11</c:NtryRef>
+        <c:Amt Ccy="EUR">5</c:Amt>
+        <c:CdtDbtInd>DBIT</c:CdtDbtInd>
+        <c:BookgDt><c:Dt>2024-03-02</c:Dt></c:BookgDt>
+      </c:Ntry>
+    </c:Stmt>
+    <c:Stmt>
+      <c:Acct><c:Id><c:Othr><c:Id>2</c:Id></c:Othr></c:Id></c:Acct>
+      <c:Ntry><c:NtryRef>10</c:NtryRef><c:Tp>dispute</c:Tp><c:Amt Ccy="EUR"/></c:Ntry>
+      <c:Ntry><c:NtryRef>12</c:NtryRef><c:Tp>rev</c:Tp></c:Ntry>
+      <c:Ntry><c:NtryRef>13</c:NtryRef><c:Amt><![CDATA[1.0]]></c:Amt><c:CdtDbtInd>XXXX</c:CdtDbtInd></c:Ntry>
+    </c:Stmt>
+  </c:BkToCstmrStmt>
+</Document>
+"#;
+
+    #[test]
+    fn test_reader() {
+        let mut aliases = TransactionTypeAliases::new();
+        aliases.insert("rev", TransactionType::Resolve);
+        let mut reader = XmlReader::new(STATEMENT.as_bytes()).with_aliases(aliases);
+
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.r#type, TransactionType::Deposit);
+        assert_eq!(record.client, ClientId::new(1));
+        assert_eq!(record.tx, TxId::new(10));
+        assert_eq!(record.amount, Some(Amount::from_scaled(255_000)));
+        assert_eq!(record.currency, Some("EUR".parse().unwrap()));
+        assert_eq!(
+            record.timestamp.unwrap().to_rfc3339(),
+            "2024-03-01T09:30:00+00:00"
+        );
+        assert_eq!(
+            reader.source().raw,
+            "deposit,1,10,25.50,2024-03-01T09:30:00Z,EUR"
+        );
+
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.r#type, TransactionType::Withdrawal);
+        assert_eq!(
+            record.timestamp.unwrap().to_rfc3339(),
+            "2024-03-02T00:00:00+00:00"
+        );
+
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.r#type, TransactionType::Dispute);
+        assert_eq!(record.client, ClientId::new(2));
+        assert_eq!(record.amount, None);
+        let record = reader.next().unwrap().unwrap();
+        assert_eq!(record.r#type, TransactionType::Resolve);
+
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Error::UnknownTransactionType { line: Some(5), .. }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_missing_client() {
+        let data = "<Document><Stmt><Ntry><NtryRef>1</NtryRef><Amt>1</Amt>\
+                    <CdtDbtInd>CRDT</CdtDbtInd></Ntry></Stmt></Document>";
+        let records: Vec<_> = XmlReader::new(data.as_bytes()).collect();
+        assert!(matches!(
+            records[..],
+            [Err(Error::MissingField {
+                column: Column::Client,
+                ..
+            })]
+        ));
+    }
+
+    #[test]
+    fn test_reader_malformed_document() {
+        for data in [
+            "<Document><Stmt></Document>",
+            "<Document><Stmt>",
+            "<Document><Stmt><Ntry>&bogus;</Ntry></Stmt></Document>",
+        ] {
+            let records: Vec<_> = XmlReader::new(data.as_bytes()).collect();
+            assert!(
+                matches!(records[..], [Err(Error::Io(_))]),
+                "{} should not be read",
+                data
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use super::error::Error;
+use super::id::{ClientId, TxId};
+use super::input::{InputRecord, TransactionType};
+use super::output::write_atomically;
+use super::tenant::Tenant;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// A single applied record as far as the journal is concerned. The type is
+/// part of the entry since a dispute, resolve and chargeback share the ID of
+/// the transaction they refer to, and so is the tenant, as tenants pick
+/// their IDs independently of each other.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Entry {
+    r#type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    /// Only written out if any entry has a tenant, see `TenantEntry`.
+    #[serde(default, skip_serializing)]
+    tenant: Option<Tenant>,
+}
+
+impl From<&InputRecord> for Entry {
+    fn from(record: &InputRecord) -> Self {
+        Entry {
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            tenant: record.tenant,
+        }
+    }
+}
+
+/// An `Entry` with a tenant column. As soon as a single entry has a tenant,
+/// every entry is written this way, so all of them have the same columns,
+/// while the journal of a run without tenants keeps the original columns.
+#[derive(Serialize)]
+struct TenantEntry {
+    r#type: TransactionType,
+    client: ClientId,
+    tx: TxId,
+    tenant: Option<Tenant>,
+}
+
+impl From<&Entry> for TenantEntry {
+    fn from(entry: &Entry) -> Self {
+        TenantEntry {
+            r#type: entry.r#type,
+            client: entry.client,
+            tx: entry.tx,
+            tenant: entry.tenant,
+        }
+    }
+}
+
+/// A `Journal` remembers which records have been applied, across runs, so
+/// that feeding the same input in twice does not count everything twice.
+///
+/// Only records from earlier runs count as replays. Within a single run,
+/// a reused transaction ID is up to the engine and its `DuplicatePolicy`,
+/// exactly as without a journal.
+///
+/// On disk, a journal is a CSV file with the columns `type`, `client` and
+/// `tx`, and `tenant` if tenants are kept apart, one row per applied
+/// record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    /// What was applied in earlier runs.
+    earlier: HashSet<Entry>,
+    /// What was applied in this run.
+    applied: HashSet<Entry>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal::default()
+    }
+
+    /// Reads a journal written by `save`. A journal that does not exist yet
+    /// is simply empty, so the first run does not need any special care.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Journal, Error> {
+        match File::open(path) {
+            Ok(file) => Journal::load_from_reader(BufReader::new(file)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Journal::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads a journal from any reader.
+    pub fn load_from_reader<R: Read>(reader: R) -> Result<Journal, Error> {
+        let mut journal = Journal::new();
+        for entry in csv::Reader::from_reader(reader).deserialize() {
+            let entry: Entry = entry?;
+            // Custom types are never written, see `insert`.
+            if entry.r#type.is_custom() {
+                return Err(Error::UnknownTransactionType {
+                    line: None,
+                    value: entry.r#type.to_string(),
+                });
+            }
+            journal.earlier.insert(entry);
+        }
+        Ok(journal)
+    }
+
+    /// Writes every record applied in this or an earlier run to `path`. The
+    /// file is replaced atomically, so the journal of the previous run
+    /// survives a crash.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        write_atomically(path.as_ref(), |file| self.save_to_writer(file))
+    }
+
+    /// Writes the journal to any writer. Entries are sorted, so journals of
+    /// the same records are identical.
+    pub fn save_to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut entries: Vec<&Entry> = self.earlier.union(&self.applied).collect();
+        entries.sort_by_key(|e| (e.tenant, e.client, e.tx, e.r#type.name()));
+
+        let tenant = entries.iter().any(|e| e.tenant.is_some());
+        let mut writer = csv::Writer::from_writer(writer);
+        for entry in entries {
+            match tenant {
+                true => writer.serialize(TenantEntry::from(entry))?,
+                false => writer.serialize(entry)?,
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Whether `record` was already applied in an earlier run.
+    pub fn is_replay(&self, record: &InputRecord) -> bool {
+        self.earlier.contains(&Entry::from(record))
+    }
+
+    /// Notes that `record` has been applied, returning whether it was not
+    /// noted yet. Records of custom types are left out: a journal is read
+    /// back without knowing which custom types there are, so it could not
+    /// tell them from a corrupt entry.
+    pub fn insert(&mut self, record: &InputRecord) -> bool {
+        if record.r#type.is_custom() {
+            return false;
+        }
+        self.applied.insert(Entry::from(record))
+    }
+
+    /// Forgets that `record` was applied in this run, for a strict batch
+    /// that is undone, see `Engine::apply_batch`.
+    pub(crate) fn remove(&mut self, record: &InputRecord) {
+        self.applied.remove(&Entry::from(record));
+    }
+
+    /// The number of distinct records applied in this and earlier runs.
+    pub fn len(&self) -> usize {
+        self.earlier.union(&self.applied).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.earlier.is_empty() && self.applied.is_empty()
+    }
+
+    /// Moves the entries of clients whose ID modulo `shards` is `n` into the
+    /// `n`th journal, like `Engine::split` does with the accounts.
+    pub(crate) fn split(self, shards: usize) -> Vec<Journal> {
+        let mut journals = vec![Journal::new(); shards];
+        for entry in self.earlier {
+            journals[entry.client.shard(shards)].earlier.insert(entry);
+        }
+        for entry in self.applied {
+            journals[entry.client.shard(shards)].applied.insert(entry);
+        }
+        journals
+    }
+
+    /// Folds another journal into this one.
+    pub(crate) fn merge(&mut self, other: Journal) {
+        self.earlier.extend(other.earlier);
+        self.applied.extend(other.applied);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Journal;
+    use crate::input::{make_input_record, InputRecord};
+    use csv::StringRecord;
+
+    fn record(row: Vec<&str>) -> InputRecord {
+        make_input_record(&StringRecord::from(row)).unwrap()
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let deposit = record(vec!["deposit", "1", "1", "1.0"]);
+        let dispute = record(vec!["dispute", "1", "1", ""]);
+
+        let mut journal = Journal::new();
+        journal.insert(&deposit);
+        // Only earlier runs count as replays.
+        assert!(!journal.is_replay(&deposit));
+
+        let mut buf = Vec::new();
+        journal.save_to_writer(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            "type,client,tx\ndeposit,1,1\n"
+        );
+
+        let journal = Journal::load_from_reader(buf.as_slice()).unwrap();
+        assert!(journal.is_replay(&deposit));
+        assert!(!journal.is_replay(&dispute));
+        assert!(!journal.is_replay(&record(vec!["deposit", "2", "1", "1.0"])));
+        assert_eq!(journal.len(), 1);
+    }
+
+    #[test]
+    fn test_journal_tenants() {
+        let mut deposit = record(vec!["deposit", "1", "1", "1.0"]);
+        let mut journal = Journal::new();
+        journal.insert(&deposit);
+        deposit.tenant = "eu".parse().ok();
+        journal.insert(&deposit);
+        assert_eq!(journal.len(), 2);
+
+        let mut buf = Vec::new();
+        journal.save_to_writer(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            "type,client,tx,tenant\ndeposit,1,1,\ndeposit,1,1,eu\n"
+        );
+        let journal = Journal::load_from_reader(buf.as_slice()).unwrap();
+        assert!(journal.is_replay(&deposit));
+        deposit.tenant = "us".parse().ok();
+        assert!(!journal.is_replay(&deposit));
+    }
+
+    #[test]
+    fn test_load_invalid_journal() {
+        assert!(Journal::load_from_reader("type,client,tx\nbogus,1,1\n".as_bytes()).is_err());
+    }
+}
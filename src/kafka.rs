@@ -0,0 +1,140 @@
+use super::engine::Engine;
+use super::error::Error;
+use super::input::{parse_message, Source, TransactionTypeAliases};
+use super::{apply_logged, log_invalid};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+use std::time::{Duration, Instant};
+use tracing::{debug_span, info, warn};
+
+/// How long a single poll waits for a message before the consumer checks
+/// whether the state is due to be emitted.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Where to consume transactions from, and how often to emit the state of
+/// the engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumeOptions {
+    /// A comma separated list of `host:port` pairs.
+    pub brokers: String,
+    pub topic: String,
+    /// The consumer group. Consumers in the same group share the partitions
+    /// of the topic between them, and the committed offsets.
+    pub group: String,
+    /// How often `consume` hands the engine to `emit`.
+    pub interval: Duration,
+    /// Additional names the transaction types are known by.
+    pub aliases: TransactionTypeAliases,
+}
+
+/// Kafka errors are passed on as I/O errors, as far as the caller is
+/// concerned they are not any different.
+fn kafka_error(e: KafkaError) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+/// Applies every message of the topic to `engine` as it arrives. A message
+/// holds a single transaction, either as a JSON object or as a CSV row
+/// without a header, see `input::parse_message`. Messages that cannot be
+/// parsed or applied are logged and skipped, just like rows of a file.
+///
+/// Every `interval`, the engine is handed to `emit`, which would typically
+/// write out the balances or a snapshot, or save the engine to its store.
+/// Only once it succeeds are the
+/// offsets of the messages applied so far committed, so after a crash the
+/// messages since the last emit are consumed again. Restoring the engine
+/// from the last snapshot, or using a `Journal`, keeps them from being
+/// applied twice.
+///
+/// This only returns if the consumer cannot be set up, offsets cannot be
+/// committed or `emit` returns an error.
+pub fn consume<F>(engine: &mut Engine, options: &ConsumeOptions, mut emit: F) -> Result<(), Error>
+where
+    F: FnMut(&mut Engine) -> Result<(), Error>,
+{
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &options.brokers)
+        .set("group.id", &options.group)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .map_err(kafka_error)?;
+    consumer.subscribe(&[&options.topic]).map_err(kafka_error)?;
+    info!(topic = options.topic, "consuming transactions");
+
+    let mut last_emit = Instant::now();
+    let mut uncommitted = 0u64;
+    loop {
+        match consumer.poll(POLL_TIMEOUT) {
+            Some(Ok(message)) => {
+                let _span = debug_span!(
+                    "message",
+                    partition = message.partition(),
+                    offset = message.offset()
+                )
+                .entered();
+                let text = String::from_utf8_lossy(message.payload().unwrap_or_default());
+                apply_message(engine, &text, &options.aliases);
+                uncommitted += 1;
+            }
+            // The client reconnects by itself, so errors such as a broker
+            // going away are only worth a warning.
+            Some(Err(e)) => warn!(error = %e, "could not consume from Kafka"),
+            None => (),
+        }
+
+        if last_emit.elapsed() >= options.interval {
+            emit(engine)?;
+            // Committing without anything new to commit is an error.
+            if uncommitted > 0 {
+                consumer
+                    .commit_consumer_state(CommitMode::Sync)
+                    .map_err(kafka_error)?;
+                info!(messages = uncommitted, "committed offsets");
+                uncommitted = 0;
+            }
+            last_emit = Instant::now();
+        }
+    }
+}
+
+/// Parses and applies a single message, logging whatever goes wrong.
+fn apply_message(engine: &mut Engine, text: &str, aliases: &TransactionTypeAliases) {
+    let source = Source {
+        line: None,
+        raw: text.to_string(),
+    };
+    match parse_message(text, aliases) {
+        Ok(record) => {
+            let _ = apply_logged(engine, record, &source);
+        }
+        Err(e) => log_invalid(&e),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::apply_message;
+    use crate::amount::Amount;
+    use crate::engine::Engine;
+    use crate::input::TransactionTypeAliases;
+
+    #[test]
+    fn test_apply_message() {
+        let aliases = TransactionTypeAliases::new();
+        let mut engine = Engine::new();
+        for message in [
+            "deposit,1,1,2.0",
+            r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "0.5"}"#,
+            "withdrawal,1,3,100.0",
+            "bogus",
+        ] {
+            apply_message(&mut engine, message, &aliases);
+        }
+        let balances = engine.balances();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].available, Amount::from_scaled(15_000));
+    }
+}
@@ -1,21 +1,1587 @@
+pub mod account;
+pub mod amount;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod checkpoint;
+pub mod currency;
+pub mod dedup;
+pub mod diff;
+pub mod double_entry;
+pub mod emit;
+pub mod engine;
+pub mod error;
+pub mod events;
+pub mod fees;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod generate;
+pub mod golden;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handler;
+pub mod id;
 pub mod input;
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod limits;
+pub mod metrics;
+pub mod observer;
 pub mod output;
+mod parallel;
+mod pipeline;
+pub mod repl;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shared;
+pub mod tenant;
+pub mod validator;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
 
-use csv::StringRecord;
-use input::{make_input_record, InputRecord};
+use amount::{Amount, DecimalSeparator, Precision};
+#[cfg(feature = "async")]
+pub use asynchronous::{process_async, process_stream};
+use dedup::{DroppedDuplicate, FileDedup};
+pub use engine::{
+    Applied, BatchError, BatchResult, Engine, EngineConfig, ErrorMode, Ledger, RejectReason,
+};
+use engine::{NegativeBalance, NegativeBalancePolicy, Rejected, Volume, Warned};
+pub use error::Error;
+use input::encoding::{decode, Encoding};
+use input::json::JsonLinesReader;
+use input::{
+    ColumnMapping, CsvOptions, CsvReader, InputFile, InputFormat, InputRecord, RecordSource,
+    Source, TransactionTypeAliases,
+};
+use metrics::{Histogram, Metrics};
+use output::RejectedRecord;
+use serde::Serialize;
+pub use shared::SharedEngine;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tenant::Tenant;
+use tracing::{debug, info, info_span, warn};
 
-pub fn process_csv(fname: &str) -> Result<Vec<InputRecord>, Box<dyn std::error::Error>> {
-    let mut res: Vec<InputRecord> = Vec::new();
-    let mut reader = csv::Reader::from_path(fname)?;
-    for result in reader.records() {
-        let record = result?;
-        let pos = record.position().expect("Couldn't determine position");
-        let mut s_record = StringRecord::from(record.clone());
-        s_record.trim();
-        match make_input_record(&s_record) {
-            Some(r) => res.push(r),
-            None => eprintln!("Invalid record on line {}", pos.line()),
+/// The outcome of processing an input file: the `Engine` holding the state
+/// of every client, an `Error` for every row that could not be parsed, every
+/// record the engine refused to apply, and every record that was applied
+/// with a warning.
+///
+/// `records` counts every record read, whether it could be parsed or not.
+///
+/// On top of that, `rejects` lists every row that was either invalid or
+/// refused along with where it came from, ready to be written out with
+/// `output::dump_rejects_to_path` and fed in again once fixed.
+#[derive(Debug, Default)]
+pub struct Processed {
+    pub engine: Engine,
+    pub records: u64,
+    pub errors: Vec<Error>,
+    pub rejected: Vec<Rejected>,
+    pub warnings: Vec<Warned>,
+    pub rejects: Vec<RejectedRecord>,
+    /// How long the engine took to apply every record, if
+    /// `ProcessOptions::measure_latency` asked for it.
+    pub latency: Option<Histogram>,
+    /// Every row dropped for being in the file before as well, see
+    /// `ProcessOptions::dedup_files`. These are not counted as records.
+    pub duplicates: Vec<DroppedDuplicate>,
+}
+
+/// How many records were read and what became of them, as returned by
+/// `Processed::summary`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub records: u64,
+    pub invalid: usize,
+    pub rejected: usize,
+    pub warnings: usize,
+}
+
+impl Summary {
+    /// The number of records that were not applied, for whatever reason.
+    pub fn skipped(&self) -> usize {
+        self.invalid + self.rejected
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} records read, {} skipped ({} invalid, {} rejected), {} with warnings",
+            self.records,
+            self.skipped(),
+            self.invalid,
+            self.rejected,
+            self.warnings
+        )
+    }
+}
+
+/// Figures to reconcile a run against, as returned by
+/// `Processed::statistics`: what became of the records read, the money the
+/// applied ones moved, and the state the accounts ended up in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Statistics {
+    /// The number of clients with an account.
+    pub clients: usize,
+    pub records: u64,
+    pub applied: u64,
+    pub invalid: usize,
+    pub rejected: usize,
+    /// The number of rejected records for every reason there was.
+    pub rejected_by_reason: BTreeMap<String, usize>,
+    /// The number of rows dropped for being in the file before as well.
+    pub duplicates: usize,
+    /// The deposits and withdrawals that were applied, per currency.
+    pub volumes: Vec<Volume>,
+    pub open_disputes: usize,
+    /// The number of times an open dispute reached an age it is escalated
+    /// at, see `DisputeEscalation`.
+    pub escalations: usize,
+    pub locked_accounts: usize,
+    /// The number of balance checks applied, and how many of them found
+    /// another total than the one they state.
+    pub balance_checks: usize,
+    pub balance_mismatches: usize,
+    /// The number of records that took the funds of an account negative,
+    /// see `NegativeBalancePolicy`.
+    pub negative_balances: usize,
+    /// The number of disputes that never matched a transaction, see
+    /// `EngineConfig::pending_disputes`.
+    pub pending_disputes: usize,
+}
+
+/// How a run of the program ended, which is what its exit code tells.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Every record was applied.
+    #[default]
+    Clean,
+    /// The run went through, but some records were invalid or rejected.
+    CompletedWithRejections,
+    /// The run was cut short by an error.
+    Failed,
+}
+
+impl RunStatus {
+    /// The exit code the program ends with: 0 for a clean run, 1 for one
+    /// that failed, and 2 for one that completed with rejections.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            RunStatus::Clean => 0,
+            RunStatus::Failed => 1,
+            RunStatus::CompletedWithRejections => 2,
+        }
+    }
+}
+
+/// What became of a run, for a pipeline to check, as written by
+/// `output::dump_run_report_to_path`: how it ended, the number of records
+/// read and what became of them, and every row that was not applied along
+/// with why. Unlike `Statistics`, this is written even if the run failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunReport {
+    pub status: RunStatus,
+    /// The exit code that goes with the status.
+    pub exit_code: i32,
+    /// What made the run fail, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub records: u64,
+    pub applied: u64,
+    pub invalid: usize,
+    pub rejected: usize,
+    pub warnings: usize,
+    /// The number of rejected records for every reason there was.
+    pub rejected_by_reason: BTreeMap<String, usize>,
+    /// The number of rows dropped for being in the file before as well.
+    pub duplicates: usize,
+    /// Every row that was invalid or rejected, with its line and the reason.
+    pub errors: Vec<RejectedRecord>,
+    pub integrity: Integrity,
+}
+
+/// The integrity section of a `RunReport`: the records that took the funds
+/// of an account negative, see `NegativeBalancePolicy`, and how many
+/// accounts ended up with negative funds, whether they should have or not.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Integrity {
+    pub negative_balances: Vec<NegativeBalance>,
+    /// The number of accounts left with negative available or held funds,
+    /// such as by an overdraft or a disputed withdrawal.
+    pub negative_accounts: usize,
+}
+
+impl RunReport {
+    /// The report of a run that was cut short by `error`. Whatever was done
+    /// up to the error is not known.
+    pub fn failed(error: &dyn fmt::Display) -> Self {
+        RunReport {
+            status: RunStatus::Failed,
+            exit_code: RunStatus::Failed.exit_code(),
+            error: Some(error.to_string()),
+            ..RunReport::default()
+        }
+    }
+
+    /// Fills in the status from the number of rows that were not applied.
+    fn finish(mut self) -> Self {
+        if self.invalid + self.rejected > 0 {
+            self.status = RunStatus::CompletedWithRejections;
+        }
+        self.exit_code = self.status.exit_code();
+        self
+    }
+}
+
+/// The outcome of validating an input: how many rows were read, an `Error`
+/// for every row that could not be parsed, and those rows along with where
+/// they came from.
+#[derive(Debug, Default)]
+pub struct Validated {
+    pub rows: u64,
+    pub errors: Vec<Error>,
+    pub rejects: Vec<RejectedRecord>,
+}
+
+impl Validated {
+    /// Whether every row of the input could be parsed.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The report of the validation, see `RunReport`. Nothing is applied
+    /// when validating, so every row counts as read only.
+    pub fn run_report(&self) -> RunReport {
+        RunReport {
+            records: self.rows,
+            invalid: self.errors.len(),
+            errors: self.rejects.clone(),
+            ..RunReport::default()
+        }
+        .finish()
+    }
+}
+
+/// Everything that controls how input is read and processed. The defaults
+/// match what `process_csv` and `process_reader` do.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessOptions {
+    pub format: InputFormat,
+    /// Match CSV columns by position instead of by header name, expecting
+    /// exactly `type, client, tx, amount` in that order.
+    pub strict_columns: bool,
+    /// The dialect of CSV input.
+    pub csv: CsvOptions,
+    pub engine: EngineConfig,
+    /// The number of threads the records are processed on, sharded by
+    /// client. Anything below 2 processes them on the calling thread. Only
+    /// applies if `relaxed_tx_checks` accepts what that gives up.
+    pub threads: usize,
+    /// Accepts that on several `threads`, transaction IDs are only checked
+    /// within the clients of a thread: an ID reused by a client of another
+    /// thread is not rejected as a duplicate, and a dispute of a transaction
+    /// of such a client is rejected as an unknown transaction rather than
+    /// one of another client. Rejects, and so balances, may then differ from
+    /// those of a sequential run of the same input.
+    pub relaxed_tx_checks: bool,
+    /// Additional names the transaction types are known by in the input.
+    pub aliases: TransactionTypeAliases,
+    /// Where the columns of CSV input are found, if not by their own names.
+    pub columns: ColumnMapping,
+    /// The number of threads the rows of CSV files are parsed on, with
+    /// another one reading them while the calling thread applies the records
+    /// in order, see `process_files_from`. Anything below 1 parses every row
+    /// as it is read, on the calling thread.
+    pub parse_threads: usize,
+    /// What to do with amounts that have more than four decimal places.
+    pub precision: Precision,
+    /// Set to accept amounts such as `$1,234.56` rather than rejecting the
+    /// rows, with the given decimal separator, see `Amount::parse_lenient`.
+    /// XML statements always have plain amounts, and are read as such.
+    pub lenient_amounts: Option<DecimalSeparator>,
+    /// Time how long the engine takes to apply every record, see
+    /// `Processed::latency`. This is off by default, as reading the clock
+    /// twice for every record costs time of its own.
+    pub measure_latency: bool,
+    /// CSV files of at least this many bytes are mapped into memory rather
+    /// than read, and with `parse_threads`, split into chunks that are
+    /// parsed in parallel, see `input::mmap`. `None` leaves it at
+    /// `input::mmap::DEFAULT_THRESHOLD`. Only with the `mmap` feature.
+    pub mmap_threshold: Option<u64>,
+    /// The character encoding of text input. A byte order mark at the start
+    /// of the input is dropped either way, see `input::encoding::Encoding`.
+    /// Parquet input is binary, so this does not apply to it.
+    pub encoding: Encoding,
+    /// Drop the rows of an input file that were in the file before as well,
+    /// see `dedup::FileDedup`. Only applies to `process_files_from` and
+    /// `emit::process_files_emitting`.
+    pub dedup_files: bool,
+    /// The tenant of the records of an input file that do not name one
+    /// themselves, by the path the file is given as. Only applies if
+    /// `EngineConfig::tenants` keeps tenants apart, and only to
+    /// `process_files_from`.
+    pub file_tenants: BTreeMap<PathBuf, Tenant>,
+}
+
+impl ProcessOptions {
+    /// Whether the records are processed on several threads, see
+    /// `threads`.
+    pub(crate) fn parallel(&self) -> bool {
+        self.threads > 1 && self.relaxed_tx_checks
+    }
+
+    /// The tenant of the records of the file at `path` that do not name
+    /// one, see `file_tenants`.
+    pub(crate) fn file_tenant(&self, path: &Path) -> Option<Tenant> {
+        self.file_tenants
+            .get(path)
+            .copied()
+            .filter(|_| self.engine.tenants)
+    }
+
+    /// How the amounts of the input are to be read.
+    pub(crate) fn amounts(&self) -> amount::AmountFormat {
+        amount::AmountFormat {
+            precision: self.precision,
+            lenient: self.lenient_amounts,
+        }
+    }
+}
+
+/// Reads the given CSV file row by row and feeds every valid record straight
+/// into an `Engine`. This is a thin wrapper around `process_reader`, which
+/// should be used directly for anything that is not a file on disk.
+pub fn process_csv<P: AsRef<Path>>(fname: P) -> Result<Processed, Error> {
+    let file = File::open(fname)?;
+    process_reader(file)
+}
+
+/// Reads CSV data from any source implementing `Read`, such as a file,
+/// standard input, an in-memory buffer or a decompressing reader, and feeds
+/// every valid record straight into an `Engine`.
+pub fn process_reader<R: Read>(reader: R) -> Result<Processed, Error> {
+    process_reader_with(reader, &ProcessOptions::default())
+}
+
+/// Like `process_reader`, but with full control over the input format and
+/// the policies of the engine.
+pub fn process_reader_with<R: Read>(
+    reader: R,
+    options: &ProcessOptions,
+) -> Result<Processed, Error> {
+    let engine = Engine::with_config(options.engine.clone());
+    process_reader_from(engine, reader, options)
+}
+
+/// Like `process_reader_with`, but carries on from the state of an existing
+/// `Engine`, such as one restored from a snapshot, rather than starting out
+/// empty. The policies of `engine` apply, not those in `options`.
+pub fn process_reader_from<R: Read>(
+    engine: Engine,
+    reader: R,
+    options: &ProcessOptions,
+) -> Result<Processed, Error> {
+    process_records_with(read_records(reader, options), engine, options)
+}
+
+/// Processes several files as if they were one long input, in the order
+/// given, and produces a single set of client balances. This is meant for
+/// inputs that are split up over time, such as one file per day: a dispute
+/// in a later file can refer to a deposit in an earlier one.
+///
+/// Every file is opened before any record is processed, so a missing file
+/// is reported without doing any work. Errors are tagged with the file they
+/// occurred in.
+pub fn process_files_with<P: AsRef<Path>>(
+    paths: &[P],
+    options: &ProcessOptions,
+) -> Result<Processed, Error> {
+    let engine = Engine::with_config(options.engine.clone());
+    process_files_from(engine, paths, options)
+}
+
+/// Like `process_files_with`, but carries on from the state of an existing
+/// `Engine`, just like `process_reader_from`.
+///
+/// Unless they are processed on several threads, CSV files are read, parsed
+/// and applied in a pipeline if `ProcessOptions::parse_threads` asks for
+/// it: one thread reads the rows of a file while the parser threads turn
+/// them into records, which the calling thread applies in their original
+/// order. The outcome is the same as without, only sooner. With the `mmap`
+/// feature, large CSV files are mapped into memory instead, and parsed in
+/// chunks on the parser threads, see `ProcessOptions::mmap_threshold`.
+pub fn process_files_from<P: AsRef<Path>>(
+    engine: Engine,
+    paths: &[P],
+    options: &ProcessOptions,
+) -> Result<Processed, Error> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = path.as_ref();
+        match InputFile::open(path) {
+            Ok(file) => files.push((path.to_path_buf(), file)),
+            Err(e) => return Err(Error::Io(e).in_file(path)),
+        }
+    }
+
+    // The parallel workers don't know which file a record came from, so
+    // only a sequential run can log everything within the file's span.
+    let mut dedup = FileDedup::new(options.dedup_files);
+    if options.parallel() {
+        let records = read_files(files, options, &mut dedup);
+        let mut res = process_records_with(records, engine, options)?;
+        res.duplicates = dedup.dropped;
+        return Ok(res);
+    }
+
+    let mut res = Processed::new(engine, options);
+    let pipelined = options.parse_threads > 0 && options.format == InputFormat::Csv;
+    for (path, file) in files {
+        let _span = info_span!("file", path = %path.display()).entered();
+        let tenant = options.file_tenant(&path);
+        let tagged = |record: Result<InputRecord, Error>| {
+            record
+                .map(|r| r.or_tenant(tenant))
+                .map_err(|e| e.in_file(&path))
+        };
+        #[cfg(feature = "mmap")]
+        if let (InputFile::Local(local), InputFormat::Csv) = (&file, options.format) {
+            let threshold = options
+                .mmap_threshold
+                .unwrap_or(input::mmap::DEFAULT_THRESHOLD);
+            let map = input::mmap::map_if_large(local, threshold)
+                .map_err(|e| Error::Io(e).in_file(&path))?;
+            // Input that has to be turned into UTF-8 first is read instead.
+            let start = map
+                .as_ref()
+                .and_then(|map| input::encoding::utf8_start(map, options.encoding));
+            if let (Some(map), Some(start)) = (&map, start) {
+                input::mmap::for_each_record(&map[start..], options, |source, record| {
+                    if !dedup.keep(&path, &source, &record) {
+                        return Ok(());
+                    }
+                    res.push(source, tagged(record))
+                })?;
+                continue;
+            }
+        }
+        if pipelined {
+            pipeline::for_each_csv_record(
+                decode(file, options.encoding),
+                options,
+                options.parse_threads,
+                |source, record| {
+                    if !dedup.keep(&path, &source, &record) {
+                        return Ok(());
+                    }
+                    res.push(source, tagged(record))
+                },
+            )?;
+            continue;
+        }
+        for (source, record) in read_records(file, options) {
+            if dedup.keep(&path, &source, &record) {
+                res.push(source, tagged(record))?;
+            }
         }
     }
+    res.duplicates = dedup.dropped;
+    res.log_summary();
     Ok(res)
 }
+
+/// Reads the records of every file in turn, tagging errors with the file
+/// they occurred in and leaving out the rows `dedup` drops.
+pub(crate) fn read_files<'a>(
+    files: Vec<(PathBuf, InputFile)>,
+    options: &'a ProcessOptions,
+    dedup: &'a mut FileDedup,
+) -> impl Iterator<Item = (Source, Result<InputRecord, Error>)> + 'a {
+    files
+        .into_iter()
+        .flat_map(|(path, file)| {
+            info!(path = %path.display(), "reading file");
+            let tenant = options.file_tenant(&path);
+            let path: Arc<Path> = path.into();
+            read_records(file, options).map(move |(source, record)| {
+                let record = record.map(|r| r.or_tenant(tenant));
+                (path.clone(), source, record)
+            })
+        })
+        .filter(|(path, source, record)| dedup.keep(path, source, record))
+        .map(|(path, source, record)| (source, record.map_err(|e| e.in_file(&path))))
+}
+
+/// Parses the input exactly like `process_reader_with` does, but only checks
+/// that every row is well formed rather than computing any balances. This
+/// lets whoever provides the data check it before handing it over. Whether
+/// a transaction would be rejected depends on everything that came before
+/// it, so that is not checked.
+pub fn validate_reader_with<R: Read>(
+    reader: R,
+    options: &ProcessOptions,
+) -> Result<Validated, Error> {
+    validate_records(read_records(reader, options))
+}
+
+/// Validates several files in one go, see `validate_reader_with`.
+pub fn validate_files_with<P: AsRef<Path>>(
+    paths: &[P],
+    options: &ProcessOptions,
+) -> Result<Validated, Error> {
+    let mut res = Validated::default();
+    for path in paths {
+        let path = path.as_ref();
+        let file = InputFile::open(path).map_err(|e| Error::Io(e).in_file(path))?;
+        let records = read_records(file, options)
+            .map(|(source, record)| (source, record.map_err(|e| e.in_file(path))));
+        let validated = validate_records(records)?;
+        res.rows += validated.rows;
+        res.errors.extend(validated.errors);
+        res.rejects.extend(validated.rejects);
+    }
+    Ok(res)
+}
+
+fn validate_records<I>(records: I) -> Result<Validated, Error>
+where
+    I: IntoIterator<Item = (Source, Result<InputRecord, Error>)>,
+{
+    let mut res = Validated::default();
+    for (source, record) in records {
+        res.rows += 1;
+        match record {
+            Ok(_) => (),
+            Err(e) if e.is_record_error() => {
+                res.rejects.push(RejectedRecord::new(source, &e));
+                res.errors.push(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(res)
+}
+
+/// Picks the reader for the configured input format.
+pub(crate) fn read_records<'a, R: Read + 'a>(
+    reader: R,
+    options: &ProcessOptions,
+) -> Box<dyn Iterator<Item = (Source, Result<InputRecord, Error>)> + 'a> {
+    let aliases = options.aliases.clone();
+    match options.format {
+        InputFormat::Csv => {
+            let reader = decode(reader, options.encoding);
+            Box::new(csv_reader(reader, options).with_source())
+        }
+        InputFormat::JsonLines => {
+            let reader = decode(reader, options.encoding);
+            let mut reader = JsonLinesReader::new(BufReader::new(reader))
+                .with_aliases(aliases)
+                .with_precision(options.precision);
+            if let Some(separator) = options.lenient_amounts {
+                reader = reader.with_lenient_amounts(separator);
+            }
+            Box::new(reader.with_source())
+        }
+        #[cfg(feature = "parquet")]
+        InputFormat::Parquet => match input::parquet::ParquetReader::from_reader(reader) {
+            Ok(reader) => {
+                let mut reader = reader
+                    .with_aliases(aliases)
+                    .with_precision(options.precision);
+                if let Some(separator) = options.lenient_amounts {
+                    reader = reader.with_lenient_amounts(separator);
+                }
+                Box::new(reader.with_source())
+            }
+            Err(e) => Box::new(std::iter::once((Source::default(), Err(e)))),
+        },
+        #[cfg(feature = "xml")]
+        InputFormat::Xml => Box::new(
+            input::xml::XmlReader::new(BufReader::new(decode(reader, options.encoding)))
+                .with_aliases(aliases)
+                .with_precision(options.precision)
+                .with_source(),
+        ),
+    }
+}
+
+/// A `CsvReader` as configured in `options`.
+pub(crate) fn csv_reader<R: Read>(reader: R, options: &ProcessOptions) -> CsvReader<R> {
+    let mut reader = CsvReader::with_options(reader, &options.csv)
+        .with_aliases(options.aliases.clone())
+        .with_columns(options.columns.clone())
+        .with_precision(options.precision);
+    if let Some(separator) = options.lenient_amounts {
+        reader = reader.with_lenient_amounts(separator);
+    }
+    if options.strict_columns {
+        reader = reader.with_strict_columns();
+    }
+    reader
+}
+
+/// Hands the records to either the sequential or the parallel engine,
+/// depending on the number of threads asked for.
+fn process_records_with<I>(
+    records: I,
+    engine: Engine,
+    options: &ProcessOptions,
+) -> Result<Processed, Error>
+where
+    I: IntoIterator<Item = (Source, Result<InputRecord, Error>)>,
+{
+    let res = if options.parallel() {
+        parallel::process_records_parallel(records, engine, options)?
+    } else {
+        let mut res = Processed::new(engine, options);
+        for (source, record) in records {
+            res.push(source, record)?;
+        }
+        res
+    };
+    res.log_summary();
+    Ok(res)
+}
+
+/// Feeds the records produced by any of the input readers into the given
+/// `Engine`, one at a time. Only the engine state is kept in memory, so the
+/// size of the input is not a concern. Records that cannot be parsed are
+/// skipped and collected in `Processed::errors`, records the engine refuses
+/// end up in `Processed::rejected` and those it warns about in
+/// `Processed::warnings`, while problems with the input itself
+/// abort processing and are returned as an `Err`.
+pub fn process_records<I>(records: I, engine: Engine) -> Result<Processed, Error>
+where
+    I: IntoIterator<Item = Result<InputRecord, Error>>,
+{
+    let records = records.into_iter().map(|record| {
+        let line = record.as_ref().err().and_then(Error::line);
+        let source = Source {
+            line,
+            ..Source::default()
+        };
+        (source, record)
+    });
+    process_sourced_records(records, engine)
+}
+
+/// Like `process_records`, but with every record paired with the `Source`
+/// it came from, as produced by `RecordSource::with_source`. The sources
+/// end up in `Processed::rejects`.
+pub fn process_sourced_records<I>(records: I, engine: Engine) -> Result<Processed, Error>
+where
+    I: IntoIterator<Item = (Source, Result<InputRecord, Error>)>,
+{
+    let mut res = Processed {
+        engine,
+        ..Processed::default()
+    };
+    for (source, record) in records {
+        res.push(source, record)?;
+    }
+    Ok(res)
+}
+
+impl Processed {
+    /// Nothing processed yet, with records to be applied to `engine` as
+    /// `options` ask for.
+    pub(crate) fn new(engine: Engine, options: &ProcessOptions) -> Self {
+        Processed {
+            engine,
+            latency: options.measure_latency.then(Histogram::default),
+            ..Processed::default()
+        }
+    }
+
+    /// Applies a single record to the engine and files it under the right
+    /// list if it is invalid, rejected or raises a warning. Only problems
+    /// with the input itself are returned as an `Err`, and in the strict
+    /// `ErrorMode` invalid records as well, just like records that take the
+    /// funds of an account negative under `NegativeBalancePolicy::Abort`.
+    pub(crate) fn push(
+        &mut self,
+        source: Source,
+        record: Result<InputRecord, Error>,
+    ) -> Result<(), Error> {
+        self.records += 1;
+        match record {
+            Ok(r) => match self.apply(r.clone()) {
+                Ok(Applied { warning: None, .. }) => log_applied(&r),
+                Ok(Applied {
+                    warning: Some(warning),
+                    ..
+                }) => {
+                    log_warned(&r, &warning);
+                    self.warnings.push(Warned { record: r, warning });
+                }
+                Err(RejectReason::NegativeBalance) if self.aborts_on_negative_balance() => {
+                    return Err(Error::NegativeBalance {
+                        line: source.line,
+                        client: r.client,
+                        tx: r.tx,
+                    });
+                }
+                Err(reason) => {
+                    log_rejected(&r, &reason, &source);
+                    self.rejects.push(RejectedRecord::new(source, &reason));
+                    self.rejected.push(Rejected { record: r, reason });
+                }
+            },
+            Err(e) if e.is_record_error() && !self.is_strict() => {
+                log_invalid(&e);
+                self.rejects.push(RejectedRecord::new(source, &e));
+                self.errors.push(e);
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Applies a record to the engine, timing it if asked to.
+    fn apply(&mut self, record: InputRecord) -> Result<Applied, RejectReason> {
+        let Some(latency) = &mut self.latency else {
+            return self.engine.apply(record);
+        };
+        let started = Instant::now();
+        let res = self.engine.apply(record);
+        latency.observe(started.elapsed());
+        res
+    }
+
+    /// Whether processing stops at the first invalid record.
+    pub(crate) fn is_strict(&self) -> bool {
+        self.engine.config().error_mode == ErrorMode::Strict
+    }
+
+    /// Whether processing stops at the first record that takes the funds of
+    /// an account negative.
+    pub(crate) fn aborts_on_negative_balance(&self) -> bool {
+        self.engine.config().negative_balances == NegativeBalancePolicy::Abort
+    }
+
+    /// How many records were read and what became of them.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            records: self.records,
+            invalid: self.errors.len(),
+            rejected: self.rejected.len(),
+            warnings: self.warnings.len(),
+        }
+    }
+
+    /// The figures of the run, see `Statistics`. The volumes only cover what
+    /// was applied in this run, while everything about the accounts takes
+    /// in the state the engine started out with as well. Clients of
+    /// different tenants are different clients.
+    pub fn statistics(&self) -> Statistics {
+        let summary = self.summary();
+        let mut rejected_by_reason = BTreeMap::new();
+        for rejected in &self.rejected {
+            *rejected_by_reason
+                .entry(rejected.reason.to_string())
+                .or_default() += 1;
+        }
+        let balances = self.engine.balances();
+        let checks = self.engine.balance_checks();
+        Statistics {
+            clients: balances
+                .iter()
+                .map(|b| (b.tenant, b.client))
+                .collect::<HashSet<_>>()
+                .len(),
+            records: summary.records,
+            applied: summary.records - summary.skipped() as u64,
+            invalid: summary.invalid,
+            rejected: summary.rejected,
+            rejected_by_reason,
+            duplicates: self.duplicates.len(),
+            volumes: self.engine.volumes(),
+            open_disputes: self.engine.open_disputes(),
+            escalations: self.engine.escalations().len(),
+            locked_accounts: balances.iter().filter(|b| b.locked).count(),
+            balance_checks: checks.len(),
+            balance_mismatches: checks.iter().filter(|c| !c.matches()).count(),
+            negative_balances: self.engine.negative_balances().len(),
+            pending_disputes: self.engine.pending_disputes().len(),
+        }
+    }
+
+    /// The metrics of the run, see `Metrics`. The duration of the run is
+    /// left for the caller to fill in.
+    pub fn metrics(&self) -> Metrics {
+        let statistics = self.statistics();
+        Metrics {
+            records_processed: statistics.records,
+            records_applied: statistics.applied,
+            records_invalid: statistics.invalid as u64,
+            records_rejected: statistics
+                .rejected_by_reason
+                .into_iter()
+                .map(|(reason, count)| (reason, count as u64))
+                .collect(),
+            records_with_warnings: self.warnings.len() as u64,
+            disputes_open: statistics.open_disputes as u64,
+            accounts_locked: statistics.locked_accounts as u64,
+            clients: statistics.clients as u64,
+            run_duration: None,
+            apply_latency: self.latency.clone(),
+        }
+    }
+
+    /// The report of the run, see `RunReport`.
+    pub fn run_report(&self) -> RunReport {
+        let statistics = self.statistics();
+        RunReport {
+            records: statistics.records,
+            applied: statistics.applied,
+            invalid: statistics.invalid,
+            rejected: statistics.rejected,
+            warnings: self.warnings.len(),
+            rejected_by_reason: statistics.rejected_by_reason,
+            duplicates: statistics.duplicates,
+            errors: self.rejects.clone(),
+            integrity: Integrity {
+                negative_balances: self.engine.negative_balances(),
+                negative_accounts: self
+                    .engine
+                    .balances()
+                    .iter()
+                    .filter(|b| b.available < Amount::ZERO || b.held < Amount::ZERO)
+                    .count(),
+            },
+            ..RunReport::default()
+        }
+        .finish()
+    }
+
+    /// Logs how many records were read and what became of them.
+    pub(crate) fn log_summary(&self) {
+        let summary = self.summary();
+        info!(
+            records = summary.records,
+            invalid = summary.invalid,
+            rejected = summary.rejected,
+            warnings = summary.warnings,
+            duplicates = self.duplicates.len(),
+            clients = self.engine.balances().len(),
+            "finished processing"
+        );
+    }
+}
+
+pub(crate) fn log_applied(record: &InputRecord) {
+    debug!(
+        tx = %record.tx,
+        client = %record.client,
+        r#type = ?record.r#type,
+        "applied transaction"
+    );
+}
+
+pub(crate) fn log_warned(record: &InputRecord, warning: &engine::Warning) {
+    warn!(
+        tx = %record.tx,
+        client = %record.client,
+        %warning,
+        "applied transaction with a warning"
+    );
+}
+
+pub(crate) fn log_rejected(record: &InputRecord, reason: &engine::RejectReason, source: &Source) {
+    // Records past the cutoff are expected whenever there is one.
+    if *reason == engine::RejectReason::AfterCutoff {
+        debug!(
+            line = source.line,
+            tx = %record.tx,
+            client = %record.client,
+            "skipped transaction after the cutoff"
+        );
+        return;
+    }
+    if *reason == engine::RejectReason::Replayed {
+        warn!(
+            line = source.line,
+            tx = %record.tx,
+            client = %record.client,
+            "skipped transaction already applied in an earlier run"
+        );
+        return;
+    }
+    warn!(
+        line = source.line,
+        tx = %record.tx,
+        client = %record.client,
+        %reason,
+        "rejected transaction"
+    );
+}
+
+pub(crate) fn log_invalid(error: &Error) {
+    warn!(%error, "invalid record");
+}
+
+/// Applies a record that arrived on its own, such as a message, to `engine`
+/// and logs the outcome like the `process_*` functions do.
+#[cfg(any(feature = "kafka", feature = "server", feature = "grpc"))]
+pub(crate) fn apply_logged(
+    engine: &mut Engine,
+    record: InputRecord,
+    source: &Source,
+) -> Result<Applied, RejectReason> {
+    let res = engine.apply(record.clone());
+    match &res {
+        Ok(Applied { warning: None, .. }) => log_applied(&record),
+        Ok(Applied {
+            warning: Some(warning),
+            ..
+        }) => log_warned(&record, warning),
+        Err(reason) => log_rejected(&record, reason, source),
+    }
+    res
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        process_csv, process_files_with, process_reader, process_reader_from, process_reader_with,
+        validate_reader_with, EngineConfig, ErrorMode, ProcessOptions, RunReport, RunStatus,
+    };
+    use crate::amount::Amount;
+    use crate::engine::{NegativeBalancePolicy, RejectReason};
+    use crate::error::Error;
+    use crate::id::{ClientId, TxId};
+    use crate::input::InputFormat;
+    use crate::output::RejectedRecord;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_process_in_memory_buffer() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    deposit, 2, 2, 2.0\n\
+                    withdrawal, 1, 3, 0.25\n";
+        let processed = process_reader(data.as_bytes()).unwrap();
+        let mut balances = processed.engine.balances();
+        balances.sort_by_key(|r| r.client);
+
+        assert!(processed.errors.is_empty());
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].available, Amount::from_scaled(7_500));
+        assert_eq!(balances[1].available, Amount::from_scaled(20_000));
+    }
+
+    #[test]
+    fn test_process_collects_invalid_rows() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    bogus, 1, 2, 1.0\n\
+                    deposit, x, 3, 1.0\n";
+        let processed = process_reader(std::io::Cursor::new(data)).unwrap();
+
+        assert_eq!(processed.records, 3);
+        assert_eq!(processed.engine.balances().len(), 1);
+        assert_eq!(processed.errors.len(), 2);
+        assert!(matches!(
+            processed.errors[0],
+            Error::UnknownTransactionType { line: Some(3), .. }
+        ));
+        assert!(matches!(
+            processed.errors[1],
+            Error::Parse { line: Some(4), .. }
+        ));
+    }
+
+    #[test]
+    fn test_error_modes() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    bogus, 1, 2, 1.0\n\
+                    withdrawal, 1, 3, 5.0\n\
+                    deposit, 1, 4, 1.0\n";
+        let processed = process_reader(data.as_bytes()).unwrap();
+        let summary = processed.summary();
+        assert_eq!(
+            (summary.records, summary.invalid, summary.rejected),
+            (4, 1, 1)
+        );
+        assert_eq!(summary.skipped(), 2);
+
+        for threads in [1, 2] {
+            let mut options = ProcessOptions {
+                threads,
+                relaxed_tx_checks: true,
+                ..ProcessOptions::default()
+            };
+            options.engine.error_mode = ErrorMode::Strict;
+            let err = process_reader_with(data.as_bytes(), &options).unwrap_err();
+            assert!(matches!(
+                err,
+                Error::UnknownTransactionType { line: Some(3), .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_negative_balances() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 8.0\n\
+                    dispute, 1, 1,\n\
+                    deposit, 2, 3, 1.0\n";
+        let report = process_reader(data.as_bytes()).unwrap().run_report();
+        assert_eq!(report.status, RunStatus::Clean);
+        assert_eq!(report.warnings, 1);
+        assert_eq!(report.integrity.negative_balances.len(), 1);
+        assert_eq!(report.integrity.negative_accounts, 1);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json["integrity"]["negative_balances"][0]["available"],
+            "-8.0"
+        );
+        assert_eq!(json["integrity"]["negative_balances"][0]["type"], "dispute");
+
+        let mut options = ProcessOptions::default();
+        options.engine.negative_balances = NegativeBalancePolicy::Reject;
+        let report = process_reader_with(data.as_bytes(), &options)
+            .unwrap()
+            .run_report();
+        assert_eq!(report.rejected_by_reason["balance would go negative"], 1);
+        assert_eq!(report.integrity.negative_accounts, 0);
+
+        options.engine.negative_balances = NegativeBalancePolicy::Abort;
+        options.relaxed_tx_checks = true;
+        for threads in [1, 2] {
+            options.threads = threads;
+            let err = process_reader_with(data.as_bytes(), &options).unwrap_err();
+            assert!(matches!(
+                err,
+                Error::NegativeBalance {
+                    line: Some(4),
+                    client,
+                    tx,
+                } if client == ClientId::new(1) && tx == TxId::new(1)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_run_report() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    bogus, 1, 3, 1.0\n";
+        let report = process_reader(data.as_bytes()).unwrap().run_report();
+        assert_eq!(report.status, RunStatus::CompletedWithRejections);
+        assert_eq!(report.exit_code, 2);
+        assert_eq!(
+            (
+                report.records,
+                report.applied,
+                report.invalid,
+                report.rejected
+            ),
+            (3, 1, 1, 1)
+        );
+        let errors: Vec<_> = report
+            .errors
+            .iter()
+            .map(|e| (e.line, e.reason.as_str()))
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.contains(&(Some(3), "insufficient funds")));
+
+        let report = process_reader("type, client, tx, amount\ndeposit, 1, 1, 1.0\n".as_bytes())
+            .unwrap()
+            .run_report();
+        assert_eq!((report.status, report.exit_code), (RunStatus::Clean, 0));
+
+        let report = validate_reader_with(data.as_bytes(), &ProcessOptions::default())
+            .unwrap()
+            .run_report();
+        assert_eq!((report.invalid, report.exit_code), (1, 2));
+
+        let report = RunReport::failed(&"no such file");
+        assert_eq!(report.exit_code, 1);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "no such file");
+    }
+
+    #[test]
+    fn test_statistics() {
+        let data = "type, client, tx, amount, timestamp, currency\n\
+                    deposit, 1, 1, 10.0, , EUR\n\
+                    deposit, 2, 2, 5.0, ,\n\
+                    deposit, 3, 3, 1.0, ,\n\
+                    withdrawal, 1, 4, 2.5, , EUR\n\
+                    withdrawal, 2, 5, 50.0, ,\n\
+                    withdrawal, 4, 6, 1.0, ,\n\
+                    dispute, 2, 2, , ,\n\
+                    dispute, 3, 3, , ,\n\
+                    chargeback, 3, 3, , ,\n\
+                    bogus, 1, 7, 1.0, ,\n";
+        for threads in [1, 2] {
+            let options = ProcessOptions {
+                threads,
+                relaxed_tx_checks: true,
+                ..ProcessOptions::default()
+            };
+            let statistics = process_reader_with(data.as_bytes(), &options)
+                .unwrap()
+                .statistics();
+            assert_eq!(statistics.clients, 3);
+            assert_eq!(
+                (
+                    statistics.records,
+                    statistics.applied,
+                    statistics.invalid,
+                    statistics.rejected
+                ),
+                (10, 7, 1, 2)
+            );
+            assert_eq!(
+                statistics.rejected_by_reason,
+                BTreeMap::from([
+                    ("insufficient funds".to_string(), 1),
+                    ("unknown client".to_string(), 1)
+                ])
+            );
+            let volumes: Vec<_> = statistics
+                .volumes
+                .iter()
+                .map(|v| {
+                    (
+                        v.currency.map(|c| c.to_string()),
+                        v.deposits,
+                        v.deposited,
+                        v.withdrawn,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                volumes,
+                vec![
+                    (None, 2, Amount::from_scaled(60_000), Amount::ZERO),
+                    (
+                        Some("EUR".to_string()),
+                        1,
+                        Amount::from_scaled(100_000),
+                        Amount::from_scaled(25_000)
+                    ),
+                ]
+            );
+            assert_eq!(statistics.open_disputes, 1);
+            assert_eq!(statistics.locked_accounts, 1);
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let data = "type,client,tx,amount
+                    deposit,1,1,10.0
+                    withdrawal,1,2,50.0
+                    dispute,1,1,
+                    bogus,1,3,1.0
+";
+        let processed = process_reader_with(data.as_bytes(), &ProcessOptions::default()).unwrap();
+        assert_eq!(processed.metrics().apply_latency, None);
+
+        let options = ProcessOptions {
+            measure_latency: true,
+            ..ProcessOptions::default()
+        };
+        let metrics = process_reader_with(data.as_bytes(), &options)
+            .unwrap()
+            .metrics();
+        assert_eq!(
+            (
+                metrics.records_processed,
+                metrics.records_applied,
+                metrics.records_invalid,
+                metrics.records_rejected_total(),
+                metrics.disputes_open
+            ),
+            (4, 2, 1, 1, 1)
+        );
+        // The invalid record never made it to the engine.
+        assert_eq!(metrics.apply_latency.unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_process_json_lines() {
+        let data = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}\n\
+                    {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n\
+                    not json\n";
+        let options = ProcessOptions {
+            format: InputFormat::JsonLines,
+            ..ProcessOptions::default()
+        };
+        let processed = process_reader_with(data.as_bytes(), &options).unwrap();
+        let balances = processed.engine.balances();
+
+        assert_eq!(processed.errors.len(), 1);
+        assert_eq!(processed.errors[0].line(), Some(3));
+        assert_eq!(balances[0].held, Amount::from_scaled(15_000));
+    }
+
+    #[test]
+    fn test_process_collects_rejected_records() {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.0\n\
+                    withdrawal, 1, 2, 5.0\n\
+                    resolve, 1, 1,\n";
+        let processed = process_reader(data.as_bytes()).unwrap();
+
+        assert!(processed.errors.is_empty());
+        assert_eq!(processed.rejected.len(), 2);
+        assert_eq!(processed.rejected[0].record.tx, TxId::new(2));
+        assert_eq!(
+            processed.rejected[0].reason,
+            RejectReason::InsufficientFunds
+        );
+        assert_eq!(processed.rejected[1].reason, RejectReason::NotDisputed);
+    }
+
+    #[test]
+    fn test_process_collects_rejects_with_source() {
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,1.0\n\
+                    withdrawal,1,2,5.0\n\
+                    deposit,x,3,1.0\n";
+        let processed = process_reader(data.as_bytes()).unwrap();
+
+        assert_eq!(
+            processed.rejects,
+            vec![
+                RejectedRecord {
+                    line: Some(3),
+                    raw: "withdrawal,1,2,5.0".to_string(),
+                    reason: "insufficient funds".to_string(),
+                },
+                RejectedRecord {
+                    line: Some(4),
+                    raw: "deposit,x,3,1.0".to_string(),
+                    reason: "Invalid record on line 4: could not parse 'x' in column 'client'"
+                        .to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_only_looks_back_in_time() {
+        // Every reference to transaction 2 comes before it happens, so none
+        // of them may touch its funds once it does.
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,1.0\n\
+                    dispute,1,2,\n\
+                    resolve,1,2,\n\
+                    chargeback,1,2,\n\
+                    deposit,1,2,4.0\n";
+        for threads in [1, 2] {
+            let options = ProcessOptions {
+                threads,
+                relaxed_tx_checks: true,
+                ..ProcessOptions::default()
+            };
+            let processed = process_reader_with(data.as_bytes(), &options).unwrap();
+            let balances = processed.engine.balances();
+
+            let reasons: Vec<_> = processed.rejected.iter().map(|r| r.reason).collect();
+            assert_eq!(reasons, vec![RejectReason::UnknownTransaction; 3]);
+            assert_eq!(balances[0].available, Amount::from_scaled(50_000));
+            assert_eq!(balances[0].held, Amount::ZERO);
+            assert!(!balances[0].locked);
+        }
+    }
+
+    #[test]
+    fn test_threads_need_relaxed_tx_checks() {
+        // Clients 1 and 2 land on different threads, so only a sequential
+        // run sees that transaction 1 is used twice.
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,1,2.0\n";
+        let mut options = ProcessOptions {
+            threads: 2,
+            ..ProcessOptions::default()
+        };
+        let processed = process_reader_with(data.as_bytes(), &options).unwrap();
+        let reasons: Vec<_> = processed.rejected.iter().map(|r| r.reason).collect();
+        assert_eq!(reasons, [RejectReason::DuplicateTransaction]);
+
+        options.relaxed_tx_checks = true;
+        let processed = process_reader_with(data.as_bytes(), &options).unwrap();
+        assert!(processed.rejected.is_empty());
+    }
+
+    #[test]
+    fn test_process_from_existing_engine() {
+        let monday = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,3.0\n";
+        let tuesday = "type,client,tx,amount\ndispute,1,1,\nwithdrawal,2,3,1.0\n";
+        for threads in [1, 3] {
+            let options = ProcessOptions {
+                threads,
+                relaxed_tx_checks: true,
+                ..ProcessOptions::default()
+            };
+            let engine = process_reader_with(monday.as_bytes(), &options)
+                .unwrap()
+                .engine;
+            let processed = process_reader_from(engine, tuesday.as_bytes(), &options).unwrap();
+            let mut balances = processed.engine.balances();
+            balances.sort_by_key(|r| r.client);
+
+            assert!(processed.rejected.is_empty());
+            assert_eq!(balances[0].held, Amount::from_scaled(100_000));
+            assert_eq!(balances[1].available, Amount::from_scaled(20_000));
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,1.0\n\
+                    withdrawal,1,2,5.0\n\
+                    deposit,1,3,\n\
+                    bogus,1,4,1.0\n";
+        let validated = validate_reader_with(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        // The withdrawal would be rejected for lack of funds, but is valid.
+        assert!(!validated.is_valid());
+        assert_eq!(validated.rows, 4);
+        let lines: Vec<_> = validated.rejects.iter().map(|r| r.line).collect();
+        assert_eq!(lines, vec![Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn test_process_encodings() {
+        let data = b"type,client,tx,amount,note\ndeposit,1,1,1.0,caf\xE9\n";
+        // Read as UTF-8, the input cannot be read at all.
+        assert!(matches!(
+            process_reader(&data[..]),
+            Err(Error::InvalidUtf8 { .. })
+        ));
+
+        let options = ProcessOptions {
+            encoding: "latin1".parse().unwrap(),
+            ..ProcessOptions::default()
+        };
+        let processed = process_reader_with(&data[..], &options).unwrap();
+        assert!(processed.errors.is_empty());
+        assert_eq!(processed.engine.balances().len(), 1);
+
+        // JSON Lines with a byte order mark, as UTF-16.
+        let mut data = vec![0xFF, 0xFE];
+        for unit in r#"{"type":"deposit","client":1,"tx":1,"amount":"1.0"}"#.encode_utf16() {
+            data.extend(unit.to_le_bytes());
+        }
+        let options = ProcessOptions {
+            format: InputFormat::JsonLines,
+            ..ProcessOptions::default()
+        };
+        let processed = process_reader_with(&data[..], &options).unwrap();
+        assert!(processed.errors.is_empty());
+        assert_eq!(processed.engine.balances().len(), 1);
+    }
+
+    #[test]
+    fn test_process_missing_file() {
+        assert!(matches!(
+            process_csv("does/not/exist.csv"),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_process_files_as_one_ledger() {
+        let dir = std::env::temp_dir().join(format!("payments-files-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("day1.csv");
+        let second = dir.join("day2.csv");
+        // Whichever way a file is read, a byte order mark is dropped.
+        std::fs::write(&first, "\u{feff}type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+        std::fs::write(
+            &second,
+            "type,client,tx,amount\ndispute,1,1,\nbogus,1,2,1.0\n",
+        )
+        .unwrap();
+
+        let processed = process_files_with(&[&first, &second], &ProcessOptions::default()).unwrap();
+        let balances = processed.engine.balances();
+        assert_eq!(balances[0].held, Amount::from_scaled(100_000));
+        assert_eq!(processed.errors.len(), 1);
+        assert_eq!(processed.errors[0].line(), Some(3));
+        assert!(processed.errors[0]
+            .to_string()
+            .starts_with(&second.display().to_string()));
+
+        // Parsing in a pipeline makes no difference, and neither does
+        // mapping the files into memory.
+        for mmap_threshold in [None, Some(0)] {
+            let options = ProcessOptions {
+                parse_threads: 2,
+                mmap_threshold,
+                ..ProcessOptions::default()
+            };
+            let pipelined = process_files_with(&[&first, &second], &options).unwrap();
+            assert_eq!(pipelined.engine.balances(), balances);
+            assert_eq!(pipelined.records, processed.records);
+            assert_eq!(pipelined.rejects, processed.rejects);
+            assert_eq!(
+                pipelined.errors[0].to_string(),
+                processed.errors[0].to_string()
+            );
+        }
+
+        let missing = dir.join("day3.csv");
+        let err = process_files_with(&[&first, &missing], &ProcessOptions::default()).unwrap_err();
+        assert!(matches!(err, Error::InFile { .. }));
+        assert!(!err.is_record_error());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_process_files_dedup() {
+        let dir = std::env::temp_dir().join(format!("payments-dedup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("day1.csv");
+        let second = dir.join("day2.csv");
+        std::fs::write(
+            &first,
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "type,client,tx,amount\ndeposit,1,2,5.0\nwithdrawal,1,3,1.0\n",
+        )
+        .unwrap();
+
+        // Without dedup, the overlapping deposit is rejected as a duplicate.
+        let processed = process_files_with(&[&first, &second], &ProcessOptions::default()).unwrap();
+        assert_eq!(processed.rejected.len(), 1);
+        assert!(processed.duplicates.is_empty());
+
+        for (threads, parse_threads) in [(0, 0), (0, 2), (2, 0)] {
+            let options = ProcessOptions {
+                dedup_files: true,
+                threads,
+                relaxed_tx_checks: true,
+                parse_threads,
+                ..ProcessOptions::default()
+            };
+            let processed = process_files_with(&[&first, &second], &options).unwrap();
+            assert_eq!(processed.records, 3);
+            assert!(processed.rejected.is_empty());
+            assert_eq!(
+                processed.engine.balances()[0].available,
+                Amount::from_scaled(140_000)
+            );
+            assert_eq!(processed.duplicates.len(), 1);
+            assert_eq!(processed.duplicates[0].line, Some(2));
+            assert_eq!(processed.duplicates[0].raw, "deposit,1,2,5.0");
+            assert_eq!(processed.run_report().duplicates, 1);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_process_tenants() {
+        // The same clients and transactions, once for every tenant.
+        let data = "type,client,tx,amount,tenant\n\
+                    deposit,1,1,10.0,eu\n\
+                    deposit,1,1,7.0,us\n\
+                    dispute,1,1,,eu\n\
+                    withdrawal,1,2,3.0,us\n\
+                    deposit,2,3,1.0,\n";
+        for threads in [0, 2] {
+            let options = ProcessOptions {
+                engine: EngineConfig {
+                    tenants: true,
+                    ..EngineConfig::default()
+                },
+                threads,
+                relaxed_tx_checks: true,
+                ..ProcessOptions::default()
+            };
+            let processed = process_reader_with(data.as_bytes(), &options).unwrap();
+            assert!(processed.rejected.is_empty());
+            let balances = processed.engine.balances();
+            let balance = |tenant: &str| {
+                let tenant = tenant.parse().ok();
+                *balances.iter().find(|b| b.tenant == tenant).unwrap()
+            };
+            assert_eq!(
+                (balance("eu").available, balance("eu").held),
+                (Amount::ZERO, Amount::from_scaled(100_000))
+            );
+            assert_eq!(balance("us").available, Amount::from_scaled(40_000));
+            assert_eq!(balance("").client, ClientId::new(2));
+            assert_eq!(processed.statistics().clients, 3);
+        }
+
+        // Without tenants kept apart, the second deposit is a duplicate.
+        let processed = process_reader_with(data.as_bytes(), &ProcessOptions::default()).unwrap();
+        assert_eq!(processed.rejected.len(), 2);
+        assert!(processed
+            .engine
+            .balances()
+            .iter()
+            .all(|b| b.tenant.is_none()));
+    }
+
+    #[test]
+    fn test_process_file_tenants() {
+        let dir = std::env::temp_dir().join(format!("payments-tenants-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let eu = dir.join("eu.csv");
+        let us = dir.join("us.csv");
+        std::fs::write(&eu, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+        std::fs::write(
+            &us,
+            "type,client,tx,amount,tenant\ndeposit,1,1,5.0,\ndeposit,1,2,1.0,ca\n",
+        )
+        .unwrap();
+        let options = ProcessOptions {
+            engine: EngineConfig {
+                tenants: true,
+                ..EngineConfig::default()
+            },
+            file_tenants: BTreeMap::from([
+                (eu.clone(), "eu".parse().unwrap()),
+                (us.clone(), "us".parse().unwrap()),
+            ]),
+            ..ProcessOptions::default()
+        };
+        let processed = process_files_with(&[&eu, &us], &options).unwrap();
+        assert!(processed.rejected.is_empty());
+        let mut totals: Vec<(String, Amount)> = processed
+            .engine
+            .balances()
+            .iter()
+            .map(|b| (b.tenant.unwrap().to_string(), b.total))
+            .collect();
+        totals.sort();
+        assert_eq!(
+            totals,
+            [
+                ("ca".to_string(), Amount::from_scaled(10_000)),
+                ("eu".to_string(), Amount::from_scaled(100_000)),
+                ("us".to_string(), Amount::from_scaled(50_000)),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
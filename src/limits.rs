@@ -0,0 +1,81 @@
+use super::amount::Amount;
+use std::str::FromStr;
+
+/// The limits the `Engine` holds the deposits and withdrawals of every
+/// account in every currency to. A deposit or withdrawal that would break
+/// any of them is rejected, just like one the account cannot cover, so it
+/// shows up among the rejects rather than being applied. Limits that are
+/// not set do not apply.
+///
+/// * The largest amount of a single deposit or withdrawal.
+/// * The most money deposited and withdrawn, together, on a single day, in
+///   UTC, going by the timestamps. Records without a timestamp neither count
+///   towards it nor are held to it. Like the totals, what was moved on the
+///   day is not part of a snapshot, so a run that picks up from one starts
+///   the day afresh.
+/// * The most deposits and withdrawals an account takes, over its whole
+///   life. This one is kept in the account, so it carries over snapshots.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub amount: Option<Amount>,
+    pub daily: Option<Amount>,
+    pub transactions: Option<u64>,
+}
+
+/// Limits are given as a comma-separated list, e.g.
+/// `amount=10000,daily=25000,transactions=500`. Limits that are left out
+/// do not apply.
+impl FromStr for Limits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = Limits::default();
+        for limit in s.split(',') {
+            let (name, value) = limit
+                .split_once('=')
+                .ok_or_else(|| format!("invalid limit '{}', expected <name>=<value>", limit))?;
+            let name = name.trim().to_lowercase();
+            let value = value.trim();
+            let amount = |value: &str| match value.parse::<Amount>() {
+                Ok(amount) if !amount.is_negative() => Ok(Some(amount)),
+                _ => Err(format!("invalid {} limit '{}'", name, value)),
+            };
+            match name.as_str() {
+                "amount" => limits.amount = amount(value)?,
+                "daily" => limits.daily = amount(value)?,
+                "transactions" => {
+                    limits.transactions = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid transactions limit '{}'", value))?,
+                    )
+                }
+                _ => return Err(format!("unknown limit '{}'", name)),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Limits;
+    use crate::amount::Amount;
+
+    #[test]
+    fn test_parse_limits() {
+        let limits: Limits = "amount=100, Daily=250.5,transactions=3".parse().unwrap();
+        assert_eq!(
+            limits,
+            Limits {
+                amount: Some(Amount::from_scaled(1_000_000)),
+                daily: Some(Amount::from_scaled(2_505_000)),
+                transactions: Some(3),
+            }
+        );
+        assert_eq!("daily=1".parse::<Limits>().unwrap().amount, None);
+        for s in ["", "amount", "amount=-1", "transactions=1.5", "weekly=1"] {
+            assert!(s.parse::<Limits>().is_err(), "{} should not parse", s);
+        }
+    }
+}
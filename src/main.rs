@@ -1,19 +1,652 @@
-use payments::output::{dump_result, make_client_output_records};
-use payments::process_csv;
+mod cli;
+mod config;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = std::env::args().collect::<Vec<String>>();
+use chrono::{DateTime, Utc};
+use cli::{Command, Options};
+use payments::checkpoint::{process_files_checkpointed, CheckpointOptions};
+use payments::diff::{diff_balances, load_balances};
+use payments::emit::{process_files_emitting, process_reader_emitting};
+use payments::engine::{
+    Anomaly, DailyBalance, Engine, EngineConfig, Escalation, LockedAccount, TransactionState,
+};
+use payments::error::HeaderProblem;
+use payments::events::{read_events, EventLog};
+use payments::journal::Journal;
+use payments::metrics::{export_metrics, Metrics};
+use payments::output::compress::Compressed;
+use payments::output::{
+    dump_anomalies_to_writer, dump_balance_checks_to_path, dump_balances_to_writer,
+    dump_book_to_path, dump_diff_to_writer, dump_disputes_to_writer, dump_duplicates_to_path,
+    dump_escalations_to_writer, dump_history_to_writer, dump_locks_to_writer,
+    dump_pending_disputes_to_path, dump_query_to_writer, dump_rejects_to_path,
+    dump_run_report_to_path, dump_sharded_balances_to_path, dump_statement_to_writer,
+    dump_statistics_to_path, dump_totals_to_writer, write_to_path, BalanceCheckRecord,
+    DisputeRecord, TotalsRecord,
+};
+use payments::repl::{Command as ReplCommand, Repl};
+use payments::{
+    process_files_from, process_reader_from, validate_files_with, validate_reader_with, Error,
+    Processed, RunReport, RunStatus,
+};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::Instant;
+use tracing::{error, warn};
+use tracing_subscriber::EnvFilter;
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input csv file>", args[0]);
-        std::process::exit(1);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match config::load(&args).and_then(|config| cli::parse_args(config, args)) {
+        Ok(o) if o.help => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    init_logging(options.verbose);
+
+    // Consuming and serving only end once they are stopped, so there is
+    // nothing to report but whether they could run at all.
+    let served = |ok: bool| {
+        if ok {
+            RunReport::default()
+        } else {
+            RunReport::failed(&"not available in this build")
+        }
+    };
+    let res = match options.command {
+        Command::Process => run(&options),
+        Command::Report => report(&options),
+        Command::DisputeReport => report_disputes(&options),
+        Command::TotalsReport => report_totals(&options),
+        Command::HistoryReport => report_history(&options),
+        Command::LockReport => report_locks(&options),
+        Command::AnomalyReport => report_anomalies(&options),
+        Command::EscalationReport => report_escalations(&options),
+        Command::Validate => validate(&options),
+        Command::Consume => consume(&options).map(served),
+        Command::Serve => serve(&options).map(served),
+        Command::Watch => watch(&options).map(served),
+        Command::Replay => replay(&options).map(|_| RunReport::default()),
+        Command::Query => query(&options).map(|_| RunReport::default()),
+        Command::Diff => diff(&options).map(|_| RunReport::default()),
+        Command::Generate => generate(&options).map(|_| RunReport::default()),
+        Command::Repl => repl(&options).map(|_| RunReport::default()),
+        Command::PrintDefaultConfig => {
+            print!("{}", config::DEFAULT_CONFIG);
+            Ok(RunReport::default())
+        }
+    };
+    let report = res.unwrap_or_else(|e| {
+        error!("{}", e);
+        if e.header_problem() == Some(&HeaderProblem::NoHeaderRow) {
+            error!("Pass --no-headers to read input without a header row");
+        }
+        RunReport::failed(&e)
+    });
+    if let Some(path) = &options.run_report {
+        if let Err(e) = dump_run_report_to_path(path, &report) {
+            error!("{}", e);
+            std::process::exit(RunStatus::Failed.exit_code());
+        }
+    }
+    if report.status != RunStatus::Clean {
+        std::process::exit(report.exit_code);
+    }
+}
+
+/// Sends log output to standard error. Rejected rows are logged as
+/// warnings, so they show up by default. `RUST_LOG` takes precedence over
+/// `--verbose`, which raises the level to info, and then to debug to log
+/// every single record.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        _ => "debug",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false)
+        .init();
+}
+
+/// Lists every row of the input that cannot be parsed.
+fn validate(options: &Options) -> Result<RunReport, Error> {
+    let validated = if options.inputs.is_empty() {
+        validate_reader_with(std::io::stdin().lock(), &options.process)?
+    } else {
+        validate_files_with(&options.inputs, &options.process)?
+    };
+    for error in &validated.errors {
+        println!("{}", error);
+    }
+    println!(
+        "{} rows checked, {} invalid",
+        validated.rows,
+        validated.errors.len()
+    );
+    if let Some(path) = &options.rejects {
+        dump_rejects_to_path(path, &validated.rejects)?;
+    }
+    Ok(validated.run_report())
+}
+
+fn run(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    write_balances(options, &processed.engine)?;
+    Ok(processed.run_report())
+}
+
+/// Writes the output with `write` to the file of `--output`, or else to
+/// standard out, compressed as `--compress` or the name of the file asks.
+fn write_output<F>(options: &Options, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>,
+{
+    match &options.output {
+        Some(path) => write_to_path(Path::new(path), options.compress, write),
+        None => {
+            let mut stdout = Compressed::new(std::io::stdout().lock(), options.compress)?;
+            write(&mut stdout)?;
+            stdout.finish().map(drop).map_err(Error::from)
+        }
+    }
+}
+
+fn write_balances(options: &Options, engine: &Engine) -> Result<(), Error> {
+    let (schema, format) = (&options.output_schema, options.output_format);
+    match (&options.output, options.shard_output) {
+        (Some(path), Some(sharding)) => {
+            dump_sharded_balances_to_path(path, engine, schema, format, sharding).map(drop)
+        }
+        // The command line parser makes sure there is an output file to
+        // name the shards after.
+        _ => write_output(options, |out| {
+            dump_balances_to_writer(out, engine, schema, format)
+        }),
+    }
+}
+
+/// Rebuilds the state from the event logs given as input and writes out the
+/// balances, along with the snapshot if asked for.
+fn replay(options: &Options) -> Result<(), Error> {
+    let mut events = Vec::new();
+    if options.inputs.is_empty() {
+        events = read_events(std::io::stdin().lock())?;
+    }
+    for path in &options.inputs {
+        let file = std::fs::File::open(path).map_err(|e| Error::from(e).in_file(path))?;
+        events.extend(read_events(file).map_err(|e| e.in_file(path))?);
+    }
+    let mut engine = payments::events::replay(events, options.process.engine.clone())?;
+    if let Some(as_of) = options.process.engine.as_of {
+        engine.escalate_disputes(as_of);
+        engine.expire_disputes(as_of);
     }
+    save_state(options, &mut engine)?;
+    write_balances(options, &engine)
+}
+
+/// Looks up the balances and open disputes of the client asked for in a
+/// snapshot, without processing any input.
+fn query(options: &Options) -> Result<(), Error> {
+    // The command line parser makes sure a snapshot and a client are given.
+    let path = options.snapshot.as_deref().unwrap_or_default();
+    let client = options.client.unwrap_or_default();
+    let engine =
+        Engine::restore(path, options.process.engine.clone()).map_err(|e| e.in_file(path))?;
+    let now = options
+        .process
+        .engine
+        .as_of
+        .unwrap_or_else(|| DateTime::<Utc>::from(std::time::SystemTime::now()));
+    let disputes: Vec<DisputeRecord> = engine
+        .disputes()
+        .iter()
+        .filter(|d| d.client == client && d.status == TransactionState::Disputed)
+        .map(|d| DisputeRecord::new(d, now))
+        .collect();
+    let balances = engine.client_balances(client);
+    write_output(options, |out| {
+        dump_query_to_writer(out, balances, &disputes, options.output_format)
+    })
+}
+
+/// Compares the balances of the two files given and writes out every
+/// account that differs.
+fn diff(options: &Options) -> Result<(), Error> {
+    // The command line parser makes sure there are two files.
+    let old = load_balances(&options.inputs[0])?;
+    let new = load_balances(&options.inputs[1])?;
+    let diffs = diff_balances(&old, &new);
+    write_output(options, |out| {
+        dump_diff_to_writer(out, &diffs, options.output_format)
+    })
+}
+
+/// Makes up a file of random transactions.
+fn generate(options: &Options) -> Result<(), Error> {
+    write_output(options, |out| {
+        payments::generate::generate(out, &options.generate)
+    })
+}
+
+/// Loads the input files given, then runs the commands read from standard
+/// input, and saves the state as asked for once they end.
+fn repl(options: &Options) -> Result<(), Error> {
+    let mut repl = Repl::new(load_engine(options)?, options.process.clone());
+    let mut stdout = std::io::stdout().lock();
+    for path in &options.inputs {
+        repl.execute(ReplCommand::Load(path.into()), &mut stdout)?;
+    }
+    let stdin = std::io::stdin();
+    let prompt = stdin.is_terminal();
+    repl.run(stdin.lock(), &mut stdout, prompt)?;
+    let mut engine = repl.into_engine();
+    save_state(options, &mut engine)
+}
+
+/// Writes the statement of the client asked for instead of the balances,
+/// that of the client of every tenant one after the other.
+fn report(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    // The command line parser makes sure a client is given.
+    let client = options.client.unwrap_or_default();
+    // The balances of a client are sorted by tenant.
+    let mut tenants: Vec<_> = processed
+        .engine
+        .client_balances(client)
+        .iter()
+        .map(|b| b.tenant)
+        .collect();
+    tenants.dedup();
+    let statement: Vec<_> = tenants
+        .into_iter()
+        .flat_map(|tenant| processed.engine.statement(client, tenant))
+        .copied()
+        .collect();
+    write_output(options, |out| {
+        dump_statement_to_writer(out, &statement, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
+
+/// Processes the input and writes out every disputed transaction, of all
+/// clients or only the one asked for. Ages are taken at `--as-of`, so a
+/// report on past input does not age with the wall clock.
+fn report_disputes(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    let now = options
+        .process
+        .engine
+        .as_of
+        .unwrap_or_else(|| DateTime::<Utc>::from(std::time::SystemTime::now()));
+    let disputes: Vec<DisputeRecord> = processed
+        .engine
+        .disputes()
+        .iter()
+        .filter(|d| options.client.is_none_or(|client| d.client == client))
+        .map(|d| DisputeRecord::new(d, now))
+        .collect();
+    write_output(options, |out| {
+        dump_disputes_to_writer(out, &disputes, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
 
-    let input_file = &args[1];
+/// Processes the input and writes out what every client, or only the one
+/// asked for, took in and paid out during the period of the report.
+fn report_totals(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    let totals: Vec<TotalsRecord> = processed
+        .engine
+        .totals()
+        .iter()
+        .filter(|t| options.client.is_none_or(|client| t.client == client))
+        .map(TotalsRecord::from)
+        .collect();
+    write_output(options, |out| {
+        dump_totals_to_writer(out, &totals, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
+
+/// Processes the input and writes out the balances of every client, or only
+/// the one asked for, at the end of every day.
+fn report_history(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    let history: Vec<DailyBalance> = processed
+        .engine
+        .history()
+        .into_iter()
+        .filter(|d| options.client.is_none_or(|client| d.client == client))
+        .collect();
+    write_output(options, |out| {
+        dump_history_to_writer(out, &history, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
 
-    let processed = process_csv(&input_file)?;
-    let output = make_client_output_records(&processed);
-    dump_result(output)?;
+/// Processes the input and writes out every locked account, or only those
+/// of the client asked for, along with what locked them.
+fn report_locks(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    let locks: Vec<LockedAccount> = processed
+        .engine
+        .locks()
+        .into_iter()
+        .filter(|l| options.client.is_none_or(|client| l.client == client))
+        .collect();
+    write_output(options, |out| {
+        dump_locks_to_writer(out, &locks, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
+
+/// Processes the input and writes out every time an open dispute, of all
+/// clients or only the one asked for, reached an age it is escalated at.
+/// Time moves on to `--as-of`, or else now, at the end of the input, just
+/// like the ages of the dispute report.
+fn report_escalations(options: &Options) -> Result<RunReport, Error> {
+    let mut processed = process(options)?;
+    let now = options
+        .process
+        .engine
+        .as_of
+        .unwrap_or_else(|| DateTime::<Utc>::from(std::time::SystemTime::now()));
+    processed.engine.escalate_disputes(now);
+    let escalations: Vec<Escalation> = processed
+        .engine
+        .escalations()
+        .into_iter()
+        .filter(|e| options.client.is_none_or(|client| e.client == client))
+        .collect();
+    write_output(options, |out| {
+        dump_escalations_to_writer(out, &escalations, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
+
+/// Writes every account the anomaly heuristics flag instead of the
+/// balances.
+fn report_anomalies(options: &Options) -> Result<RunReport, Error> {
+    let processed = process(options)?;
+    let anomalies: Vec<Anomaly> = processed
+        .engine
+        .anomalies(&options.anomalies)
+        .into_iter()
+        .filter(|a| options.client.is_none_or(|client| a.client == client))
+        .collect();
+    write_output(options, |out| {
+        dump_anomalies_to_writer(out, &anomalies, options.output_format)
+    })?;
+    Ok(processed.run_report())
+}
 
+/// Applies transactions from Kafka as they arrive and writes out the
+/// balances, along with the snapshot and the journal if asked for, at every
+/// interval. It only ever returns with an error.
+#[cfg(feature = "kafka")]
+fn consume(options: &Options) -> Result<bool, Error> {
+    use payments::kafka::ConsumeOptions;
+    use std::time::Duration;
+
+    let mut engine = load_engine(options)?;
+    // The command line parser makes sure brokers and a topic are given.
+    let consume_options = ConsumeOptions {
+        brokers: options.brokers.clone().unwrap_or_default(),
+        topic: options.topic.clone().unwrap_or_default(),
+        group: options
+            .group
+            .clone()
+            .unwrap_or_else(|| "payments".to_string()),
+        interval: Duration::from_secs(options.interval.unwrap_or(60)),
+        aliases: options.process.aliases.clone(),
+    };
+    payments::kafka::consume(&mut engine, &consume_options, |engine| {
+        write_balances(options, engine)?;
+        save_state(options, engine)
+    })?;
+    Ok(true)
+}
+
+#[cfg(not(feature = "kafka"))]
+fn consume(_: &Options) -> Result<bool, Error> {
+    error!("consume needs payments to be built with the kafka feature");
+    Ok(false)
+}
+
+/// Processes the files dropped into the directory as they land, writing
+/// out the balances along with the snapshot and the journal if asked for
+/// after every one of them. It only ever returns with an error, leaving the
+/// file that caused it where it is and the state as it was saved after the
+/// file before.
+#[cfg(feature = "watch")]
+fn watch(options: &Options) -> Result<bool, Error> {
+    use payments::watch::WatchOptions;
+
+    let mut engine = load_engine(options)?;
+    // The command line parser makes sure a single directory is given.
+    let watch_options = WatchOptions::new(&options.inputs[0]);
+    payments::watch::watch(
+        &mut engine,
+        &watch_options,
+        &options.process,
+        |engine, _| {
+            write_balances(options, engine)?;
+            save_state(options, engine)
+        },
+    )?;
+    Ok(true)
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_: &Options) -> Result<bool, Error> {
+    error!("watch needs payments to be built with the watch feature");
+    Ok(false)
+}
+
+/// Serves the HTTP API, or the gRPC one with `--grpc`, until Ctrl-C is
+/// pressed, then writes out the balances along with the snapshot and the
+/// journal if asked for.
+#[cfg(any(feature = "server", feature = "grpc"))]
+fn serve(options: &Options) -> Result<bool, Error> {
+    use std::sync::{Arc, Mutex};
+
+    if options.grpc && !cfg!(feature = "grpc") {
+        error!("serve --grpc needs payments to be built with the grpc feature");
+        return Ok(false);
+    }
+    if !options.grpc && !cfg!(feature = "server") {
+        error!("serve needs payments to be built with the server feature");
+        return Ok(false);
+    }
+
+    let engine = Arc::new(Mutex::new(load_engine(options)?));
+    let address = match (&options.listen, options.grpc) {
+        (Some(address), _) => address.as_str(),
+        (None, true) => "127.0.0.1:50051",
+        (None, false) => "127.0.0.1:8080",
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        let shutdown = async {
+            let _ = tokio::signal::ctrl_c().await;
+        };
+        match options.grpc {
+            #[cfg(feature = "grpc")]
+            true => payments::grpc::serve(listener, engine.clone(), shutdown).await,
+            #[cfg(feature = "server")]
+            false => {
+                let aliases = options.process.aliases.clone();
+                payments::server::serve(listener, engine.clone(), aliases, shutdown).await
+            }
+            // Ruled out above.
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        }
+    })?;
+
+    let mut engine = engine.lock().unwrap_or_else(|e| e.into_inner());
+    write_balances(options, &engine)?;
+    save_state(options, &mut engine)?;
+    Ok(true)
+}
+
+#[cfg(not(any(feature = "server", feature = "grpc")))]
+fn serve(_: &Options) -> Result<bool, Error> {
+    error!("serve needs payments to be built with the server or grpc feature");
+    Ok(false)
+}
+
+/// Builds the engine, picking up the snapshot or SQLite database, and the
+/// journal, of an earlier run if asked to.
+fn load_engine(options: &Options) -> Result<Engine, Error> {
+    let config = options.process.engine.clone();
+    let mut engine = match (&options.load_snapshot, &options.sqlite) {
+        (Some(path), _) => Engine::restore(path, config)?,
+        (None, Some(path)) => restore_from_sqlite(path, config)?,
+        (None, None) => Engine::with_config(config),
+    };
+    if let Some(path) = &options.journal {
+        engine.set_journal(Journal::load(path)?);
+    }
+    if let Some(path) = &options.event_log {
+        engine.set_event_log(EventLog::open(path)?);
+    }
+    Ok(engine)
+}
+
+#[cfg(feature = "sqlite")]
+fn restore_from_sqlite(path: &str, config: EngineConfig) -> Result<Engine, Error> {
+    use payments::engine::sqlite::SqliteStore;
+
+    Engine::restore_from_store(SqliteStore::open(path)?, config)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn restore_from_sqlite(_: &str, _: EngineConfig) -> Result<Engine, Error> {
+    Err(Error::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--sqlite needs payments to be built with the sqlite feature",
+    )))
+}
+
+/// Saves the snapshot, the SQLite database and the journal for the next
+/// run, and appends the events recorded so far to the event log, if asked
+/// to.
+fn save_state(options: &Options, engine: &mut Engine) -> Result<(), Error> {
+    if let Some(path) = &options.save_snapshot {
+        engine.snapshot(path)?;
+    }
+    #[cfg(feature = "sqlite")]
+    engine.save_to_store()?;
+    if let (Some(path), Some(journal)) = (&options.journal, engine.journal()) {
+        journal.save(path)?;
+    }
+    if let (Some(path), Some(log)) = (&options.event_log, engine.event_log_mut()) {
+        log.append_to(path)?;
+    }
     Ok(())
 }
+
+/// Runs all input through the engine, which logs any problems, and saves
+/// whatever was asked for besides the output.
+fn process(options: &Options) -> Result<Processed, Error> {
+    let started = Instant::now();
+    let engine = load_engine(options)?;
+    // With no input file, or with `-` as the file name, the input is read
+    // from standard input so the program can sit at the end of a pipeline.
+    let mut processed = match &options.checkpoint {
+        // The command line parser makes sure there are input files.
+        Some(path) => {
+            let checkpoint = CheckpointOptions {
+                path: path.into(),
+                every: options.checkpoint_every.unwrap_or(1_000_000),
+                resume: options.resume,
+            };
+            process_files_checkpointed(engine, &options.inputs, &options.process, &checkpoint)?
+        }
+        None => match options.emit_every {
+            // The command line parser makes sure there is an output file,
+            // which the interim balances replace as they come in.
+            Some(every) => {
+                let emit = |engine: &Engine| write_balances(options, engine);
+                if options.inputs.is_empty() {
+                    let stdin = std::io::stdin().lock();
+                    process_reader_emitting(engine, stdin, &options.process, every, emit)?
+                } else {
+                    process_files_emitting(engine, &options.inputs, &options.process, every, emit)?
+                }
+            }
+            None if options.inputs.is_empty() => {
+                process_reader_from(engine, std::io::stdin().lock(), &options.process)?
+            }
+            None => process_files_from(engine, &options.inputs, &options.process)?,
+        },
+    };
+    // Disputes age even if no record comes along to tell the time.
+    if let Some(as_of) = options.process.engine.as_of {
+        processed.engine.escalate_disputes(as_of);
+        processed.engine.expire_disputes(as_of);
+    }
+    save_state(options, &mut processed.engine)?;
+    for escalation in processed.engine.escalations() {
+        warn!(
+            tenant = escalation.tenant.map(tracing::field::display),
+            client = %escalation.client,
+            tx = %escalation.tx,
+            days = escalation.days,
+            "dispute has been open for too long"
+        );
+    }
+    let summary = processed.summary();
+    if summary.skipped() > 0 {
+        warn!(%summary, "not every record was applied");
+    }
+    if let Some(path) = &options.rejects {
+        dump_rejects_to_path(path, &processed.rejects)?;
+    }
+    if let Some(path) = &options.dedup_report {
+        dump_duplicates_to_path(path, &processed.duplicates)?;
+    }
+    if let Some(path) = &options.summary {
+        dump_statistics_to_path(path, &processed.statistics())?;
+    }
+    if let Some(target) = &options.metrics {
+        let metrics = Metrics {
+            run_duration: Some(started.elapsed()),
+            ..processed.metrics()
+        };
+        export_metrics(&metrics, target)?;
+    }
+    if let Some(path) = &options.reconciliation {
+        let checks: Vec<BalanceCheckRecord> = processed
+            .engine
+            .balance_checks()
+            .iter()
+            .map(BalanceCheckRecord::from)
+            .collect();
+        dump_balance_checks_to_path(path, &checks, options.output_format)?;
+    }
+    if let Some(path) = &options.double_entry {
+        dump_book_to_path(path, processed.engine.book(), options.output_format)?;
+    }
+    if let Some(path) = &options.unmatched_disputes {
+        let pending = processed.engine.pending_disputes();
+        dump_pending_disputes_to_path(path, &pending, options.output_format)?;
+    }
+    Ok(processed)
+}
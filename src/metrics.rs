@@ -0,0 +1,496 @@
+use super::error::Error;
+use super::output::write_atomically;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The upper bounds of the buckets of the apply latency, in seconds.
+/// Applying a record usually takes a few microseconds, so the buckets are
+/// finest there.
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.000_001,
+    0.000_002_5,
+    0.000_005,
+    0.000_01,
+    0.000_025,
+    0.000_05,
+    0.000_1,
+    0.000_25,
+    0.000_5,
+    0.001,
+    0.01,
+    0.1,
+];
+
+/// The prefix of the name of every metric.
+const PREFIX: &str = "payments";
+
+/// statsd is sent over UDP, so the metrics are split into packets small
+/// enough not to be fragmented on any network.
+const MAX_PACKET: usize = 512;
+
+/// How long it took to apply records, counted in the buckets of
+/// `LATENCY_BUCKETS`, as kept in `Processed::latency`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// The number of observations per bucket, not cumulative, with the last
+    /// one for everything above the largest bound.
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: Duration,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += duration;
+    }
+
+    /// Adds the observations of another histogram, such as that of another
+    /// thread.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        self.sum += other.sum;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// The upper bound of every bucket along with the number of
+    /// observations up to it, ending with infinity and the total count.
+    pub fn cumulative(&self) -> Vec<(f64, u64)> {
+        let bounds = LATENCY_BUCKETS.iter().copied().chain([f64::INFINITY]);
+        let mut total = 0;
+        bounds
+            .zip(self.counts)
+            .map(|(bound, count)| {
+                total += count;
+                (bound, total)
+            })
+            .collect()
+    }
+
+    /// The upper bound of the bucket the `q` quantile falls in, such as
+    /// 0.99 for the 99th percentile. `None` if nothing was observed.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let rank = (q * self.count() as f64).ceil().max(1.0) as u64;
+        self.cumulative()
+            .into_iter()
+            .find(|(_, count)| *count >= rank)
+            .map(|(bound, _)| bound)
+    }
+}
+
+/// The metrics of a run, as returned by `Processed::metrics`, for
+/// operations to keep an eye on throughput and errors.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub records_processed: u64,
+    pub records_applied: u64,
+    pub records_invalid: u64,
+    /// The number of records the engine refused, for every reason there
+    /// was.
+    pub records_rejected: BTreeMap<String, u64>,
+    pub records_with_warnings: u64,
+    pub disputes_open: u64,
+    pub accounts_locked: u64,
+    pub clients: u64,
+    /// How long the run took, if whoever ran it kept track.
+    pub run_duration: Option<Duration>,
+    /// How long applying every record took, if it was measured, see
+    /// `ProcessOptions::measure_latency`.
+    pub apply_latency: Option<Histogram>,
+}
+
+impl Metrics {
+    pub fn records_rejected_total(&self) -> u64 {
+        self.records_rejected.values().sum()
+    }
+
+    /// The number of records processed per second, if the duration of the
+    /// run is known.
+    pub fn records_per_second(&self) -> Option<f64> {
+        let secs = self.run_duration?.as_secs_f64();
+        (secs > 0.0).then(|| self.records_processed as f64 / secs)
+    }
+}
+
+/// Where the metrics go: a file for the textfile collector of the
+/// Prometheus node exporter, or a statsd daemon, given as
+/// `statsd://<host>:<port>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricsTarget {
+    Textfile(PathBuf),
+    Statsd(String),
+}
+
+impl FromStr for MetricsTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("statsd://") {
+            Some("") => Err(format!("missing statsd address in '{}'", s)),
+            // statsd listens on port 8125 unless told otherwise.
+            Some(host) if !host.contains(':') => {
+                Ok(MetricsTarget::Statsd(format!("{}:8125", host)))
+            }
+            Some(address) => Ok(MetricsTarget::Statsd(address.to_string())),
+            None if s.is_empty() => Err("missing metrics file".to_string()),
+            None => Ok(MetricsTarget::Textfile(PathBuf::from(s))),
+        }
+    }
+}
+
+/// Writes the metrics to `target`. A textfile is replaced just like the
+/// reports are, so the exporter never picks up half of it.
+pub fn export_metrics(metrics: &Metrics, target: &MetricsTarget) -> Result<(), Error> {
+    match target {
+        MetricsTarget::Textfile(path) => {
+            write_atomically(path, |file| write_prometheus(file, metrics))
+        }
+        MetricsTarget::Statsd(address) => send_statsd(address, metrics),
+    }
+}
+
+/// Writes the metrics in the Prometheus text exposition format.
+pub fn write_prometheus<W: Write>(mut writer: W, metrics: &Metrics) -> Result<(), Error> {
+    let mut out = String::new();
+    let counters = [
+        (
+            "records_processed_total",
+            "Records read, whether they could be parsed or not.",
+            metrics.records_processed,
+        ),
+        (
+            "records_applied_total",
+            "Records applied to the accounts.",
+            metrics.records_applied,
+        ),
+        (
+            "records_invalid_total",
+            "Records that could not be parsed.",
+            metrics.records_invalid,
+        ),
+        (
+            "records_with_warnings_total",
+            "Records applied with a warning.",
+            metrics.records_with_warnings,
+        ),
+    ];
+    for (name, help, value) in counters {
+        family(&mut out, name, "counter", help);
+        let _ = writeln!(out, "{}_{} {}", PREFIX, name, value);
+    }
+    family(
+        &mut out,
+        "records_rejected_total",
+        "counter",
+        "Records the engine refused to apply, by reason.",
+    );
+    for (reason, count) in &metrics.records_rejected {
+        let _ = writeln!(
+            out,
+            "{}_records_rejected_total{{reason=\"{}\"}} {}",
+            PREFIX,
+            escape_label(reason),
+            count
+        );
+    }
+
+    let mut gauges = vec![
+        (
+            "disputes_open",
+            "Disputes still open at the end of the run.",
+            metrics.disputes_open as f64,
+        ),
+        (
+            "accounts_locked",
+            "Accounts locked by a chargeback.",
+            metrics.accounts_locked as f64,
+        ),
+        (
+            "clients",
+            "Clients with an account.",
+            metrics.clients as f64,
+        ),
+    ];
+    if let Some(duration) = metrics.run_duration {
+        gauges.push((
+            "run_duration_seconds",
+            "How long the run took.",
+            duration.as_secs_f64(),
+        ));
+    }
+    if let Some(rate) = metrics.records_per_second() {
+        gauges.push((
+            "records_per_second",
+            "Records processed per second over the whole run.",
+            rate,
+        ));
+    }
+    for (name, help, value) in gauges {
+        family(&mut out, name, "gauge", help);
+        let _ = writeln!(out, "{}_{} {}", PREFIX, name, value);
+    }
+
+    if let Some(latency) = &metrics.apply_latency {
+        family(
+            &mut out,
+            "apply_latency_seconds",
+            "histogram",
+            "How long the engine took to apply a record.",
+        );
+        for (bound, count) in latency.cumulative() {
+            let le = match bound.is_finite() {
+                true => bound.to_string(),
+                false => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_apply_latency_seconds_bucket{{le=\"{}\"}} {}",
+                PREFIX, le, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_apply_latency_seconds_sum {}",
+            PREFIX,
+            latency.sum().as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "{}_apply_latency_seconds_count {}",
+            PREFIX,
+            latency.count()
+        );
+    }
+    writer.write_all(out.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The metrics as statsd lines. Counters and gauges keep their names, with
+/// the reasons for rejecting records appended to theirs. statsd has no
+/// histograms, so the apply latency is sent as its count and sum along
+/// with a few percentiles, as gauges in seconds.
+pub fn statsd_lines(metrics: &Metrics) -> Vec<String> {
+    let counter = |name: &str, value: u64| format!("{}.{}:{}|c", PREFIX, name, value);
+    let gauge = |name: &str, value: f64| format!("{}.{}:{}|g", PREFIX, name, value);
+
+    let mut lines = vec![
+        counter("records_processed", metrics.records_processed),
+        counter("records_applied", metrics.records_applied),
+        counter("records_invalid", metrics.records_invalid),
+        counter("records_rejected", metrics.records_rejected_total()),
+    ];
+    for (reason, count) in &metrics.records_rejected {
+        lines.push(counter(
+            &format!("records_rejected.{}", statsd_name(reason)),
+            *count,
+        ));
+    }
+    lines.extend([
+        counter("records_with_warnings", metrics.records_with_warnings),
+        gauge("disputes_open", metrics.disputes_open as f64),
+        gauge("accounts_locked", metrics.accounts_locked as f64),
+        gauge("clients", metrics.clients as f64),
+    ]);
+    if let Some(duration) = metrics.run_duration {
+        lines.push(gauge("run_duration_seconds", duration.as_secs_f64()));
+    }
+    if let Some(rate) = metrics.records_per_second() {
+        lines.push(gauge("records_per_second", rate));
+    }
+    if let Some(latency) = &metrics.apply_latency {
+        lines.push(counter("apply_latency_seconds.count", latency.count()));
+        lines.push(gauge(
+            "apply_latency_seconds.sum",
+            latency.sum().as_secs_f64(),
+        ));
+        for (name, q) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+            // A percentile above the largest bucket is reported as that.
+            if let Some(bound) = latency.quantile(q) {
+                let bound = bound.min(LATENCY_BUCKETS[LATENCY_BUCKETS.len() - 1]);
+                lines.push(gauge(&format!("apply_latency_seconds.{}", name), bound));
+            }
+        }
+    }
+    lines
+}
+
+fn send_statsd(address: &str, metrics: &Metrics) -> Result<(), Error> {
+    let target = address.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("statsd address '{}' does not resolve", address),
+        )
+    })?;
+    let local = match target.is_ipv4() {
+        true => "0.0.0.0:0",
+        false => "[::]:0",
+    };
+    let socket = UdpSocket::bind(local)?;
+    let mut packet = String::new();
+    for line in statsd_lines(metrics) {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET {
+            socket.send_to(packet.as_bytes(), target)?;
+            packet.clear();
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(&line);
+    }
+    if !packet.is_empty() {
+        socket.send_to(packet.as_bytes(), target)?;
+    }
+    Ok(())
+}
+
+/// Writes the help and type lines that start a family of metrics.
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+}
+
+/// Escapes a label value of the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Turns a reason such as "insufficient funds" into a part of a statsd
+/// metric name, `insufficient_funds`.
+fn statsd_name(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        statsd_lines, write_prometheus, Histogram, Metrics, MetricsTarget, LATENCY_BUCKETS,
+    };
+    use std::net::UdpSocket;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn sample_metrics() -> Metrics {
+        let mut latency = Histogram::default();
+        for micros in [1, 2, 3, 3, 40, 500_000] {
+            latency.observe(Duration::from_micros(micros));
+        }
+        Metrics {
+            records_processed: 10,
+            records_applied: 7,
+            records_invalid: 1,
+            records_rejected: [("insufficient funds".to_string(), 2)].into(),
+            disputes_open: 1,
+            clients: 3,
+            run_duration: Some(Duration::from_millis(500)),
+            apply_latency: Some(latency),
+            ..Metrics::default()
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let latency = sample_metrics().apply_latency.unwrap();
+        assert_eq!(latency.count(), 6);
+        assert_eq!(latency.sum(), Duration::from_micros(500_049));
+        let cumulative = latency.cumulative();
+        assert_eq!(cumulative.len(), LATENCY_BUCKETS.len() + 1);
+        assert_eq!(cumulative[0], (0.000_001, 1));
+        assert_eq!(cumulative[1], (0.000_002_5, 2));
+        assert_eq!(cumulative[2], (0.000_005, 4));
+        assert_eq!(cumulative[LATENCY_BUCKETS.len()], (f64::INFINITY, 6));
+        assert_eq!(latency.quantile(0.5), Some(0.000_005));
+        assert_eq!(latency.quantile(0.99), Some(f64::INFINITY));
+        assert_eq!(Histogram::default().quantile(0.5), None);
+
+        let mut merged = latency.clone();
+        merged.merge(&latency);
+        assert_eq!(merged.count(), 12);
+    }
+
+    #[test]
+    fn test_write_prometheus() {
+        let mut buf = Vec::new();
+        write_prometheus(&mut buf, &sample_metrics()).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        for line in [
+            "# TYPE payments_records_processed_total counter",
+            "payments_records_processed_total 10",
+            "payments_records_rejected_total{reason=\"insufficient funds\"} 2",
+            "# TYPE payments_disputes_open gauge",
+            "payments_records_per_second 20",
+            "# TYPE payments_apply_latency_seconds histogram",
+            "payments_apply_latency_seconds_bucket{le=\"0.000001\"} 1",
+            "payments_apply_latency_seconds_bucket{le=\"+Inf\"} 6",
+            "payments_apply_latency_seconds_count 6",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{} is missing", line);
+        }
+    }
+
+    #[test]
+    fn test_statsd() {
+        let lines = statsd_lines(&sample_metrics());
+        assert!(lines.contains(&"payments.records_rejected:2|c".to_string()));
+        assert!(lines.contains(&"payments.records_rejected.insufficient_funds:2|c".to_string()));
+        assert!(lines.contains(&"payments.apply_latency_seconds.p50:0.000005|g".to_string()));
+        assert!(lines.contains(&"payments.apply_latency_seconds.p99:0.1|g".to_string()));
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let target = format!("statsd://{}", server.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        super::export_metrics(&sample_metrics(), &target).unwrap();
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let packet = String::from_utf8_lossy(&buf[..len]);
+        assert!(packet.starts_with("payments.records_processed:10|c\n"));
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            "metrics.prom".parse(),
+            Ok(MetricsTarget::Textfile(PathBuf::from("metrics.prom")))
+        );
+        assert_eq!(
+            "statsd://localhost".parse(),
+            Ok(MetricsTarget::Statsd("localhost:8125".to_string()))
+        );
+        assert_eq!(
+            "statsd://10.0.0.1:9125".parse(),
+            Ok(MetricsTarget::Statsd("10.0.0.1:9125".to_string()))
+        );
+        assert!("statsd://".parse::<MetricsTarget>().is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use super::amount::Amount;
+use super::currency::Currency;
+use super::engine::{Applied, RejectReason};
+use super::id::{ClientId, TxId};
+use super::input::InputRecord;
+use super::tenant::Tenant;
+use std::fmt;
+use std::sync::Arc;
+
+/// An `EngineObserver` hears of what the `Engine` does as it does it, such
+/// as to keep metrics of its own, raise alerts or write the changes to
+/// another system, without changing the engine. Observers are added with
+/// `Engine::add_observer`, and told in the order they were added.
+///
+/// Every method does nothing by default, so an observer only implements
+/// what it cares about. They take `&self` as an engine may be split into
+/// shards that run on several threads, such as with `--threads`, which
+/// all tell the same observers, so an observer that keeps state needs a
+/// `Mutex` or atomics for it.
+///
+/// Within a strict batch, see `Engine::apply_batch`, observers are only
+/// told once the batch went through, and of a batch that failed only of
+/// the record that failed it.
+pub trait EngineObserver: Send + Sync {
+    /// `record` was applied, leaving its account as `applied` says.
+    fn on_applied(&self, record: &InputRecord, applied: &Applied) {
+        let _ = (record, applied);
+    }
+
+    /// `record` was refused for `reason`, and changed nothing.
+    fn on_rejected(&self, record: &InputRecord, reason: &RejectReason) {
+        let _ = (record, reason);
+    }
+
+    /// A dispute of transaction `tx` was opened, holding `amount` of the
+    /// funds of the account of `client` of `tenant`. Told after
+    /// `on_applied` for the dispute.
+    fn on_dispute_opened(
+        &self,
+        client: ClientId,
+        tx: TxId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+        amount: Amount,
+    ) {
+        let _ = (client, tx, currency, tenant, amount);
+    }
+
+    /// The account of `client` of `tenant` was locked by a chargeback,
+    /// whether of a record or of a dispute that expired.
+    fn on_account_locked(
+        &self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) {
+        let _ = (client, currency, tenant);
+    }
+}
+
+/// An observer behind an `Arc` is an observer as well, so whoever adds it
+/// can keep a handle to it, such as to read the metrics it keeps.
+impl<O: EngineObserver + ?Sized> EngineObserver for Arc<O> {
+    fn on_applied(&self, record: &InputRecord, applied: &Applied) {
+        (**self).on_applied(record, applied)
+    }
+
+    fn on_rejected(&self, record: &InputRecord, reason: &RejectReason) {
+        (**self).on_rejected(record, reason)
+    }
+
+    fn on_dispute_opened(
+        &self,
+        client: ClientId,
+        tx: TxId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+        amount: Amount,
+    ) {
+        (**self).on_dispute_opened(client, tx, currency, tenant, amount)
+    }
+
+    fn on_account_locked(
+        &self,
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    ) {
+        (**self).on_account_locked(client, currency, tenant)
+    }
+}
+
+/// Something to tell the observers, which the `Engine` holds on to until a
+/// strict batch is done.
+#[derive(Debug, Clone)]
+pub(crate) enum Notification {
+    Applied(InputRecord, Applied),
+    Rejected(InputRecord, RejectReason),
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+        amount: Amount,
+    },
+    AccountLocked {
+        client: ClientId,
+        currency: Option<Currency>,
+        tenant: Option<Tenant>,
+    },
+}
+
+/// The observers added to an `Engine`, in the order they were added. Like
+/// `Validators`, they are shared rather than copied.
+#[derive(Clone, Default)]
+pub(crate) struct Observers(Vec<Arc<dyn EngineObserver>>);
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Arc<dyn EngineObserver>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn notify(&self, notification: &Notification) {
+        for observer in &self.0 {
+            match notification {
+                Notification::Applied(record, applied) => observer.on_applied(record, applied),
+                Notification::Rejected(record, reason) => observer.on_rejected(record, reason),
+                Notification::DisputeOpened {
+                    client,
+                    tx,
+                    currency,
+                    tenant,
+                    amount,
+                } => observer.on_dispute_opened(*client, *tx, *currency, *tenant, *amount),
+                Notification::AccountLocked {
+                    client,
+                    currency,
+                    tenant,
+                } => observer.on_account_locked(*client, *currency, *tenant),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::EngineObserver;
+    use crate::amount::Amount;
+    use crate::currency::Currency;
+    use crate::engine::{Applied, Engine, EngineConfig, ErrorMode, RejectReason};
+    use crate::id::{ClientId, TxId};
+    use crate::input::{make_input_record, InputRecord};
+    use crate::tenant::Tenant;
+    use csv::StringRecord;
+    use std::sync::{Arc, Mutex};
+
+    /// Writes down everything it is told.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl EngineObserver for Recorder {
+        fn on_applied(&self, record: &InputRecord, applied: &Applied) {
+            self.0.lock().unwrap().push(format!(
+                "applied {} {}, total {}",
+                record.r#type, record.tx, applied.balance.total
+            ));
+        }
+
+        fn on_rejected(&self, record: &InputRecord, reason: &RejectReason) {
+            self.0.lock().unwrap().push(format!(
+                "rejected {} {}: {}",
+                record.r#type, record.tx, reason
+            ));
+        }
+
+        fn on_dispute_opened(
+            &self,
+            client: ClientId,
+            tx: TxId,
+            _: Option<Currency>,
+            tenant: Option<Tenant>,
+            amount: Amount,
+        ) {
+            self.0.lock().unwrap().push(format!(
+                "disputed {} of {}{} for {}",
+                tx,
+                client,
+                of_tenant(tenant),
+                amount
+            ));
+        }
+
+        fn on_account_locked(&self, client: ClientId, _: Option<Currency>, tenant: Option<Tenant>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("locked {}{}", client, of_tenant(tenant)));
+        }
+    }
+
+    fn of_tenant(tenant: Option<Tenant>) -> String {
+        tenant.map(|t| format!(" of {}", t)).unwrap_or_default()
+    }
+
+    fn record(row: Vec<&str>) -> InputRecord {
+        make_input_record(&StringRecord::from(row)).unwrap()
+    }
+
+    #[test]
+    fn test_observer() {
+        let recorder = Arc::new(Recorder::default());
+        let mut engine = Engine::new();
+        engine.add_observer(recorder.clone());
+        for row in [
+            vec!["deposit", "1", "1", "10"],
+            vec!["withdrawal", "1", "2", "20"],
+            vec!["dispute", "1", "1", ""],
+            vec!["chargeback", "1", "1", ""],
+        ] {
+            let _ = engine.apply(record(row));
+        }
+        assert_eq!(
+            recorder.take(),
+            [
+                "applied deposit 1, total 10.0",
+                "rejected withdrawal 2: insufficient funds",
+                "applied dispute 1, total 10.0",
+                "disputed 1 of 1 for 10.0",
+                "applied chargeback 1, total 0.0",
+                "locked 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observer_hears_of_tenants() {
+        let recorder = Arc::new(Recorder::default());
+        let mut engine = Engine::with_config(EngineConfig {
+            tenants: true,
+            ..EngineConfig::default()
+        });
+        engine.add_observer(recorder.clone());
+        for row in [
+            vec!["deposit", "1", "1", "10", "", "", "", "eu"],
+            vec!["deposit", "1", "1", "5", "", "", "", "us"],
+            vec!["dispute", "1", "1", "", "", "", "", "us"],
+            vec!["chargeback", "1", "1", "", "", "", "", "us"],
+        ] {
+            engine.apply(record(row)).unwrap();
+        }
+        assert_eq!(
+            recorder.take(),
+            [
+                "applied deposit 1, total 10.0",
+                "applied deposit 1, total 5.0",
+                "applied dispute 1, total 5.0",
+                "disputed 1 of 1 of us for 5.0",
+                "applied chargeback 1, total 0.0",
+                "locked 1 of us",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observer_in_strict_batch() {
+        let recorder = Arc::new(Recorder::default());
+        let mut engine = Engine::with_config(EngineConfig {
+            error_mode: ErrorMode::Strict,
+            ..EngineConfig::default()
+        });
+        engine.add_observer(recorder.clone());
+        let failing = [
+            record(vec!["deposit", "1", "1", "10"]),
+            record(vec!["withdrawal", "1", "2", "20"]),
+        ];
+        assert!(engine.apply_batch(&failing).is_err());
+        assert_eq!(
+            recorder.take(),
+            ["rejected withdrawal 2: insufficient funds"]
+        );
+        engine.apply_batch(&failing[..1]).unwrap();
+        assert_eq!(recorder.take(), ["applied deposit 1, total 10.0"]);
+    }
+}
@@ -1,26 +1,62 @@
-use super::input::{InputRecord, TransactionType};
-use serde::{ser::Serializer, Serialize};
+use super::amount::{Amount, DecimalFormat, Formatted};
+use super::currency::Currency;
+use super::dedup::DroppedDuplicate;
+use super::diff::{BalanceDiff, Change};
+use super::double_entry::{BookAccount, Entry};
+use super::engine::{
+    Anomaly, BalanceCheck, ClientTotals, DailyBalance, Dispute, Engine, Escalation, LockedAccount,
+    PendingDispute, StatementLine, TransactionState,
+};
+use super::error::Error;
+use super::id::{ClientId, TxId};
+use super::input::{InputRecord, Source, TransactionType};
+use super::tenant::Tenant;
+use super::{RunReport, Statistics};
+use chrono::{DateTime, NaiveDate, Utc};
+use compress::{Compressed, Compression};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub mod compress;
+#[cfg(feature = "parquet")]
+pub mod parquet;
 
 /// An `OutputRecord` is used to store processed data from a
-/// single client.
+/// single client, or of a single client in one currency.
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize)]
 pub struct OutputRecord {
-    pub client: u16,
-    #[serde(serialize_with = "round_to_4_dp")]
-    pub available: f64,
-    #[serde(serialize_with = "round_to_4_dp")]
-    pub held: f64,
-    #[serde(serialize_with = "round_to_4_dp")]
-    pub total: f64,
+    pub client: ClientId,
+    /// Only written out if any account has a currency, see
+    /// `CurrencyOutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any account has a tenant, see `TenantRow`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
 }
 
 impl OutputRecord {
     // Convenience function to quickly build a new `OutputRecord` struct.
-    fn new(client: u16, available: f64, held: f64, total: f64, locked: bool) -> Self {
+    pub(crate) fn new(
+        client: ClientId,
+        available: Amount,
+        held: Amount,
+        total: Amount,
+        locked: bool,
+    ) -> Self {
         OutputRecord {
             client,
+            currency: None,
+            tenant: None,
             available,
             held,
             total,
@@ -29,124 +65,1682 @@ impl OutputRecord {
     }
 }
 
-/// Instead of importing more crates, I decided to create a simple serializer
-/// function for values of type `f64`. This function takes an f64 as input
-/// and serializes it to an f64 rounded to 4 decimal places.
-fn round_to_4_dp<S>(input: &f64, s: S) -> Result<S::Ok, S::Error>
+/// An `OutputRecord` with a currency column. As soon as a single account
+/// has a currency, every record is written this way, so all of them have
+/// the same columns. Inputs without currencies keep the original columns.
+#[derive(Serialize)]
+struct CurrencyOutputRecord {
+    client: ClientId,
+    currency: Option<Currency>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+impl From<&OutputRecord> for CurrencyOutputRecord {
+    fn from(record: &OutputRecord) -> Self {
+        CurrencyOutputRecord {
+            client: record.client,
+            currency: record.currency,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: record.locked,
+        }
+    }
+}
+
+impl ReportRow for OutputRecord {
+    type WithCurrency = CurrencyOutputRecord;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyOutputRecord {
+        self.into()
+    }
+}
+
+/// A row of the balances or of a report, which may be that of an account
+/// in a currency, and of a tenant. Just like the balances have a currency
+/// column only if any account has a currency, see `CurrencyOutputRecord`,
+/// a report has a tenant column only if any row has a tenant, in front of
+/// the other columns. See `dump_rows_to_writer`.
+trait ReportRow: Serialize {
+    /// The row with a currency column.
+    type WithCurrency: Serialize;
+
+    fn currency(&self) -> Option<Currency>;
+    fn tenant(&self) -> Option<Tenant>;
+    fn with_currency(&self) -> Self::WithCurrency;
+}
+
+/// A row with a tenant column in front of its own columns, see
+/// `ReportRow`.
+#[derive(Serialize)]
+struct TenantRow<T> {
+    tenant: Option<Tenant>,
+    #[serde(flatten)]
+    row: T,
+}
+
+/// The tenant column of a row of CSV, which cannot flatten a row into a
+/// `TenantRow`, but writes a tuple of this and the row as a single row.
+#[derive(Serialize)]
+struct TenantColumn {
+    tenant: Option<Tenant>,
+}
+
+/// A record that could not be processed, either because it was invalid or
+/// because the engine refused it. It keeps the row as it was read, so it can
+/// be fixed up and fed in again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RejectedRecord {
+    /// The line the row was read from, if known.
+    pub line: Option<u64>,
+    /// The row exactly as it was read, or rebuilt from its fields for CSV.
+    pub raw: String,
+    /// Why the row was rejected.
+    pub reason: String,
+}
+
+impl RejectedRecord {
+    pub(crate) fn new(source: Source, reason: &dyn fmt::Display) -> Self {
+        RejectedRecord {
+            line: source.line,
+            raw: source.raw,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// This function takes as input any collection of `InputRecord`s and computes
+/// the balance of each client. The records are fed one at a time into an
+/// `Engine`, so the input can be a lazily evaluated iterator and never has to
+/// be held in memory in its entirety.
+pub fn make_client_output_records<I>(input_records: I) -> Vec<OutputRecord>
+where
+    I: IntoIterator<Item = InputRecord>,
+{
+    let mut engine = Engine::new();
+    for record in input_records {
+        let _ = engine.apply(record);
+    }
+    engine.balances()
+}
+
+/// The formats the client balances can be written in.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single JSON array holding one object per client.
+    Json,
+    /// One JSON object per client, each on its own line.
+    JsonLines,
+    /// A Parquet file, see `parquet::write_parquet`.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("parquet output needs the parquet feature".to_string()),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::JsonLines => "jsonl",
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => "parquet",
+        })
+    }
+}
+
+/// This function simply dumps a vector of type `OutputRecord` to standard out.
+pub fn dump_result(values: Vec<OutputRecord>) -> Result<(), Error> {
+    dump_result_with_format(values, OutputFormat::Csv)
+}
+
+/// Dumps a vector of type `OutputRecord` to standard out in the given format.
+pub fn dump_result_with_format(
+    values: Vec<OutputRecord>,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_result_to_writer(std::io::stdout().lock(), values, format)
+}
+
+/// Dumps a vector of type `OutputRecord` to a file. The result is first
+/// written to a temporary file next to the destination, which is only
+/// renamed into place once everything has been written and synced. A crash
+/// half way through therefore never leaves a truncated report behind. A
+/// file named `*.gz` or `*.zst` is compressed, see `write_to_path`.
+pub fn dump_result_to_path<P: AsRef<Path>>(
+    path: P,
+    values: Vec<OutputRecord>,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_result_to_writer(file, values, format)
+    })
+}
+
+/// Writes rejected records to a CSV file with the columns `line`, `raw` and
+/// `reason`, replacing the file just like `dump_result_to_path` does.
+pub fn dump_rejects_to_path<P: AsRef<Path>>(
+    path: P,
+    rejects: &[RejectedRecord],
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_rejects_to_writer(file, rejects)
+    })
+}
+
+/// Writes rejected records as CSV to any writer.
+pub fn dump_rejects_to_writer<W: Write>(
+    writer: W,
+    rejects: &[RejectedRecord],
+) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for reject in rejects {
+        writer.serialize(reject)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the rows dropped for being in the file before as well, see
+/// `dedup::FileDedup`, to a CSV file with the columns `file`, `line`,
+/// `type`, `client`, `tx`, `first_file` and `raw`, replacing the file just
+/// like `dump_result_to_path` does.
+pub fn dump_duplicates_to_path<P: AsRef<Path>>(
+    path: P,
+    duplicates: &[DroppedDuplicate],
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        let mut writer = csv::Writer::from_writer(file);
+        for duplicate in duplicates {
+            writer.serialize(duplicate)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Writes a file of output, such as the balances or a report, to `path`
+/// just like `write_atomically`, compressed with `compression`, or else as
+/// the name of the file asks for, see `Compression::from_path`.
+pub fn write_to_path<F>(
+    path: &Path,
+    compression: Option<Compression>,
+    write: F,
+) -> Result<(), Error>
 where
-    S: Serializer,
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>,
 {
-    s.serialize_f64(format!("{:.4}", input).parse::<f64>().unwrap())
+    let compression = compression.or_else(|| Compression::from_path(path));
+    write_atomically(path, |file| {
+        let mut writer = Compressed::new(file, compression)?;
+        write(&mut writer)?;
+        writer.finish()?;
+        Ok(())
+    })
 }
 
-fn get_transaction_amount(
-    records: &Vec<InputRecord>,
-    client: u16,
-    transaction_id: u32,
-) -> Option<f64> {
-    for record in records {
-        if record.client == client && record.tx == transaction_id {
-            if let Some(amt) = record.amount {
-                return Some(amt);
-            };
+/// Creates `path` through a temporary file that is only renamed into place
+/// once `write` has succeeded and the data has been synced.
+pub(crate) fn write_atomically<F>(path: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), Error>,
+{
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a file name", path.display()),
+        )
+    })?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let res = (|| {
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        write(&mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Dumps a vector of type `OutputRecord` to any writer in the given format.
+pub fn dump_result_to_writer<W: Write>(
+    writer: W,
+    values: Vec<OutputRecord>,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, &values, format)
+}
+
+/// A column of the client balances, see `OutputSchema`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputColumn {
+    /// The tenant of the account, see `EngineConfig::tenants`.
+    Tenant,
+    Client,
+    Currency,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// The number of transactions of the account under dispute.
+    DisputedCount,
+    /// How much of those transactions is under dispute, see
+    /// `Dispute::amount`.
+    DisputedAmount,
+}
+
+impl OutputColumn {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputColumn::Tenant => "tenant",
+            OutputColumn::Client => "client",
+            OutputColumn::Currency => "currency",
+            OutputColumn::Available => "available",
+            OutputColumn::Held => "held",
+            OutputColumn::Total => "total",
+            OutputColumn::Locked => "locked",
+            OutputColumn::DisputedCount => "disputed_count",
+            OutputColumn::DisputedAmount => "disputed_amount",
         }
     }
-    None
 }
 
-/// This function checks whether a dispute exists for the given client and
-/// transaction ID. This is used when resolving a dispute.
-fn check_dispute(records: &Vec<InputRecord>, client: u16, transaction_id: u32) -> bool {
-    for record in records {
-        if record.r#type == TransactionType::Dispute
-            && record.client == client
-            && record.tx == transaction_id
-        {
-            return true;
+impl FromStr for OutputColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "tenant" => Ok(OutputColumn::Tenant),
+            "client" => Ok(OutputColumn::Client),
+            "currency" => Ok(OutputColumn::Currency),
+            "available" => Ok(OutputColumn::Available),
+            "held" => Ok(OutputColumn::Held),
+            "total" => Ok(OutputColumn::Total),
+            "locked" => Ok(OutputColumn::Locked),
+            "disputed_count" => Ok(OutputColumn::DisputedCount),
+            "disputed_amount" => Ok(OutputColumn::DisputedAmount),
+            _ => Err(format!("unknown output column '{}'", s)),
         }
     }
-    false
 }
 
-/// This function takes as input a vector of type `InputRecord` and performs the calculations
-/// necessary to compute the balance of each client. It uses a HashMap to store the given
-/// client ID and current client balances. The HashMap is mutated in-place to minimize
-/// the amount of memory manipulations.
-pub fn make_client_output_records(input_records: &Vec<InputRecord>) -> Vec<OutputRecord> {
-    let mut output: HashMap<u16, OutputRecord> = HashMap::new();
-    for record in input_records {
-        match record.r#type {
-            TransactionType::Deposit => {
-                if output.contains_key(&record.client) {
-                    output.get_mut(&record.client).unwrap().available += record.amount.unwrap();
-                    output.get_mut(&record.client).unwrap().total += record.amount.unwrap();
-                } else {
-                    let temp = OutputRecord::new(
-                        record.client,
-                        record.amount.unwrap(),
-                        0.0,
-                        record.amount.unwrap(),
-                        false,
-                    );
-
-                    output.insert(record.client, temp);
-                }
+/// Which columns of the client balances are written, in which order, and
+/// whether CSV output starts with a header row, for systems that only
+/// import files of a fixed layout. JSON output has the columns as keys in
+/// the same order. Parquet output has the same columns, but always in the
+/// usual order, as its columns are found by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSchema {
+    /// `None` for the usual columns, with a currency column only if any
+    /// account has a currency, and a tenant column only if any account has
+    /// a tenant.
+    pub columns: Option<Vec<OutputColumn>>,
+    pub header: bool,
+    /// Add the open disputes of every account, `disputed_count` and
+    /// `disputed_amount`, after the usual columns. Only applies if `columns`
+    /// is `None`, as those can be listed there as well.
+    pub extended: bool,
+    /// How the amounts are written, such as with a fixed number of decimal
+    /// places for importers that insist on it.
+    pub decimals: DecimalFormat,
+}
+
+impl Default for OutputSchema {
+    fn default() -> Self {
+        OutputSchema {
+            columns: None,
+            header: true,
+            extended: false,
+            decimals: DecimalFormat::default(),
+        }
+    }
+}
+
+impl OutputSchema {
+    /// Parses a comma separated list of columns, such as
+    /// `client,total,disputed_count`. A column may only be listed once.
+    pub fn parse_columns(s: &str) -> Result<Vec<OutputColumn>, String> {
+        let mut columns = Vec::new();
+        for name in s.split(',') {
+            let column: OutputColumn = name.parse()?;
+            if columns.contains(&column) {
+                return Err(format!("output column '{}' is listed twice", column.name()));
             }
-            TransactionType::Withdrawal => {
-                if output.contains_key(&record.client) {
-                    if record.amount.unwrap() <= output.get(&record.client).unwrap().available {
-                        output.get_mut(&record.client).unwrap().available -= record.amount.unwrap();
-                        output.get_mut(&record.client).unwrap().total -= record.amount.unwrap();
-                    }
+            columns.push(column);
+        }
+        Ok(columns)
+    }
+}
+
+/// The balances of an account as written with an `OutputSchema`.
+struct SchemaRow<'a> {
+    columns: &'a [OutputColumn],
+    decimals: DecimalFormat,
+    record: OutputRecord,
+    disputed: OpenDisputes,
+}
+
+/// The number of open disputes of an account, and how much they hold.
+#[derive(Debug, Copy, Clone, Default)]
+struct OpenDisputes {
+    count: u64,
+    amount: Amount,
+}
+
+impl SchemaRow<'_> {
+    fn field(&self, column: OutputColumn) -> String {
+        match column {
+            OutputColumn::Tenant => self
+                .record
+                .tenant
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+            OutputColumn::Client => self.record.client.to_string(),
+            OutputColumn::Currency => self
+                .record
+                .currency
+                .map(|c| c.to_string())
+                .unwrap_or_default(),
+            OutputColumn::Available => self.amount(self.record.available).to_string(),
+            OutputColumn::Held => self.amount(self.record.held).to_string(),
+            OutputColumn::Total => self.amount(self.record.total).to_string(),
+            OutputColumn::Locked => self.record.locked.to_string(),
+            OutputColumn::DisputedCount => self.disputed.count.to_string(),
+            OutputColumn::DisputedAmount => self.amount(self.disputed.amount).to_string(),
+        }
+    }
+
+    fn amount(&self, amount: Amount) -> Formatted {
+        amount.formatted(self.decimals)
+    }
+}
+
+/// Written as a map rather than a struct, so the columns come out in the
+/// order of the schema.
+impl Serialize for SchemaRow<'_> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = s.serialize_map(Some(self.columns.len()))?;
+        for column in self.columns {
+            let name = column.name();
+            match column {
+                OutputColumn::Tenant => map.serialize_entry(name, &self.record.tenant)?,
+                OutputColumn::Client => map.serialize_entry(name, &self.record.client)?,
+                OutputColumn::Currency => map.serialize_entry(name, &self.record.currency)?,
+                OutputColumn::Available => {
+                    map.serialize_entry(name, &self.amount(self.record.available))?
                 }
-            }
-            TransactionType::Dispute => {
-                if output.contains_key(&record.client) {
-                    if let Some(transaction) =
-                        get_transaction_amount(&input_records, record.client, record.tx)
-                    {
-                        output.get_mut(&record.client).unwrap().available -= transaction;
-                        output.get_mut(&record.client).unwrap().held += transaction;
-                    }
+                OutputColumn::Held => map.serialize_entry(name, &self.amount(self.record.held))?,
+                OutputColumn::Total => {
+                    map.serialize_entry(name, &self.amount(self.record.total))?
                 }
-            }
-            TransactionType::Resolve => {
-                if output.contains_key(&record.client) {
-                    if let Some(transaction) =
-                        get_transaction_amount(&input_records, record.client, record.tx)
-                    {
-                        if check_dispute(&input_records, record.client, record.tx) {
-                            output.get_mut(&record.client).unwrap().available += transaction;
-                            output.get_mut(&record.client).unwrap().held -= transaction;
-                        }
-                    }
+                OutputColumn::Locked => map.serialize_entry(name, &self.record.locked)?,
+                OutputColumn::DisputedCount => map.serialize_entry(name, &self.disputed.count)?,
+                OutputColumn::DisputedAmount => {
+                    map.serialize_entry(name, &self.amount(self.disputed.amount))?
                 }
             }
-            TransactionType::Chargeback => {
-                if output.contains_key(&record.client) {
-                    if let Some(transaction) =
-                        get_transaction_amount(&input_records, record.client, record.tx)
-                    {
-                        output.get_mut(&record.client).unwrap().total -= transaction;
-                        output.get_mut(&record.client).unwrap().held -= transaction;
-                        output.get_mut(&record.client).unwrap().locked = true;
-                    }
+        }
+        map.end()
+    }
+}
+
+/// Writes the balances of every account of `engine` to a file, with the
+/// columns `schema` asks for. The file is replaced just like
+/// `dump_result_to_path` does.
+pub fn dump_balances_to_path<P: AsRef<Path>>(
+    path: P,
+    engine: &Engine,
+    schema: &OutputSchema,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_balances_to_writer(file, engine, schema, format)
+    })
+}
+
+/// Writes the balances of every account of `engine` to any writer, with
+/// the columns `schema` asks for. With the default schema, this is the
+/// same as `dump_result_to_writer`. The accounts of tenants are listed by
+/// tenant, those without a tenant first.
+pub fn dump_balances_to_writer<W: Write>(
+    writer: W,
+    engine: &Engine,
+    schema: &OutputSchema,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    let mut balances = engine.balances();
+    let currency = balances.iter().any(|b| b.currency.is_some());
+    let tenant = balances.iter().any(|b| b.tenant.is_some());
+    if tenant {
+        balances.sort_by_key(|b| (b.tenant, b.client, b.currency));
+    }
+    write_balances(writer, balances, currency, tenant, engine, schema, format)
+}
+
+/// Writes `balances`, which are those of some or all accounts of `engine`,
+/// with the columns `schema` asks for, see `dump_balances_to_writer`. The
+/// usual columns include the currency if `currency` is set, and start with
+/// the tenant if `tenant` is, so all shards of the balances have the same
+/// columns.
+fn write_balances<W: Write>(
+    mut writer: W,
+    balances: Vec<OutputRecord>,
+    currency: bool,
+    tenant: bool,
+    engine: &Engine,
+    schema: &OutputSchema,
+    format: OutputFormat,
+) -> Result<(), Error> {
+    if *schema == OutputSchema::default() && !tenant {
+        if currency {
+            let values = balances.iter().map(CurrencyOutputRecord::from);
+            return dump_to_writer(writer, values, format);
+        }
+        return dump_to_writer(writer, balances, format);
+    }
+    let columns = match &schema.columns {
+        Some(columns) => columns.clone(),
+        None => {
+            let mut columns = vec![
+                OutputColumn::Client,
+                OutputColumn::Available,
+                OutputColumn::Held,
+                OutputColumn::Total,
+                OutputColumn::Locked,
+            ];
+            if currency {
+                columns.insert(1, OutputColumn::Currency);
+            }
+            if tenant {
+                columns.insert(0, OutputColumn::Tenant);
+            }
+            if schema.extended {
+                columns.extend([OutputColumn::DisputedCount, OutputColumn::DisputedAmount]);
+            }
+            columns
+        }
+    };
+
+    type AccountKey = (Option<Tenant>, ClientId, Option<Currency>);
+    let mut disputed: HashMap<AccountKey, OpenDisputes> = HashMap::new();
+    if columns.contains(&OutputColumn::DisputedCount)
+        || columns.contains(&OutputColumn::DisputedAmount)
+    {
+        for dispute in engine.disputes() {
+            if dispute.status == TransactionState::Disputed {
+                let open = disputed
+                    .entry((dispute.tenant, dispute.client, dispute.currency))
+                    .or_default();
+                open.count += 1;
+                open.amount = open
+                    .amount
+                    .checked_add(dispute.amount)
+                    .unwrap_or(open.amount);
+            }
+        }
+    }
+    let rows = balances.into_iter().map(|record| SchemaRow {
+        columns: &columns,
+        decimals: schema.decimals,
+        disputed: disputed
+            .get(&(record.tenant, record.client, record.currency))
+            .copied()
+            .unwrap_or_default(),
+        record,
+    });
+
+    if format != OutputFormat::Csv {
+        return dump_to_writer(writer, rows, format);
+    }
+    let mut csv = csv::Writer::from_writer(&mut writer);
+    if schema.header {
+        csv.write_record(columns.iter().map(|c| c.name()))?;
+    }
+    for row in rows {
+        csv.write_record(columns.iter().map(|c| row.field(*c)))?;
+    }
+    csv.flush()?;
+    drop(csv);
+    writer.flush()?;
+    Ok(())
+}
+
+/// How the balances are split over several files, see
+/// `dump_sharded_balances_to_path`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sharding {
+    /// Into this many files, every client going to the one its ID modulo
+    /// the number of files points at. Every file is written, even if no
+    /// client ends up in it.
+    Buckets(usize),
+    /// Into one file for every this many client IDs, the first one holding
+    /// the IDs from 0 up to one less than the number. Only the files that
+    /// hold a client are written.
+    Range(u64),
+}
+
+impl Sharding {
+    /// The number of the file the balances of `client` go to.
+    fn shard(self, client: ClientId) -> u64 {
+        match self {
+            Sharding::Buckets(shards) => client.shard(shards) as u64,
+            Sharding::Range(size) => client.get() / size,
+        }
+    }
+}
+
+impl FromStr for Sharding {
+    type Err = String;
+
+    /// Parses a number of files, such as `16`, or the number of client IDs
+    /// every file holds, such as `range:100000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, n) = match s.trim().strip_prefix("range:") {
+            Some(n) => (true, n),
+            None => (false, s.trim()),
+        };
+        match n.parse::<u64>() {
+            Ok(n) if n > 0 && range => Ok(Sharding::Range(n)),
+            Ok(n) if n > 0 => Ok(Sharding::Buckets(n as usize)),
+            _ => Err(format!(
+                "invalid sharding '{}', expected a number of files or range:<client IDs>",
+                s
+            )),
+        }
+    }
+}
+
+/// What `dump_sharded_balances_to_path` wrote, written next to the shards
+/// as `<name>.manifest.json` so downstream knows which files to pick up.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ShardManifest {
+    pub format: String,
+    /// The number of accounts in all shards together.
+    pub accounts: u64,
+    pub shards: Vec<ShardRecord>,
+}
+
+/// A single file of sharded balances.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShardRecord {
+    /// The name of the file, without the directory, which is the one of
+    /// the manifest.
+    pub file: String,
+    pub accounts: u64,
+    /// The lowest and highest client ID in the file, if it holds any.
+    pub first_client: Option<ClientId>,
+    pub last_client: Option<ClientId>,
+}
+
+/// Writes the balances of every account of `engine` split over several
+/// files as `sharding` says, with the columns `schema` asks for. The files
+/// are named after `path` with the number of the shard before the
+/// extension, so `balances.csv` becomes `balances_000.csv`,
+/// `balances_001.csv` and so on, each sorted by client, and are followed
+/// by the manifest, `balances.manifest.json`. Every file is replaced just
+/// like `dump_result_to_path` does, and the manifest is written last, so
+/// it only ever lists complete shards.
+pub fn dump_sharded_balances_to_path<P: AsRef<Path>>(
+    path: P,
+    engine: &Engine,
+    schema: &OutputSchema,
+    format: OutputFormat,
+    sharding: Sharding,
+) -> Result<ShardManifest, Error> {
+    let path = path.as_ref();
+    let balances = engine.balances();
+    let currency = balances.iter().any(|b| b.currency.is_some());
+    let tenant = balances.iter().any(|b| b.tenant.is_some());
+    let mut shards: std::collections::BTreeMap<u64, Vec<OutputRecord>> = match sharding {
+        Sharding::Buckets(n) => (0..n as u64).map(|shard| (shard, Vec::new())).collect(),
+        Sharding::Range(_) => Default::default(),
+    };
+    for balance in balances {
+        shards
+            .entry(sharding.shard(balance.client))
+            .or_default()
+            .push(balance);
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut manifest = ShardManifest {
+        format: format.to_string(),
+        ..ShardManifest::default()
+    };
+    for (shard, mut balances) in shards {
+        balances.sort_by_key(|b| (b.tenant, b.client, b.currency));
+        let file = format!("{}_{:03}{}", stem, shard, extension);
+        manifest.shards.push(ShardRecord {
+            file: file.clone(),
+            accounts: balances.len() as u64,
+            first_client: balances.iter().map(|b| b.client).min(),
+            last_client: balances.iter().map(|b| b.client).max(),
+        });
+        manifest.accounts += balances.len() as u64;
+        write_to_path(&path.with_file_name(file), None, |file| {
+            write_balances(file, balances, currency, tenant, engine, schema, format)
+        })?;
+    }
+    let manifest_path = path.with_file_name(format!("{}.manifest.json", stem));
+    write_atomically(&manifest_path, |file| {
+        serde_json::to_writer_pretty(&mut *file, &manifest).map_err(std::io::Error::from)?;
+        writeln!(file)?;
+        Ok(())
+    })?;
+    Ok(manifest)
+}
+
+/// Writes the statement of a client, as kept by `Engine::statement`, to a
+/// file. The file is replaced just like `dump_result_to_path` does.
+pub fn dump_statement_to_path<P: AsRef<Path>>(
+    path: P,
+    statement: &[StatementLine],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_statement_to_writer(file, statement, format)
+    })
+}
+
+/// Writes the statement of a client to any writer in the given format.
+pub fn dump_statement_to_writer<W: Write>(
+    writer: W,
+    statement: &[StatementLine],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    let tenant = statement.iter().any(|l| l.tenant.is_some());
+    let currency = statement.iter().any(|l| l.currency.is_some());
+    let reason = statement.iter().any(|l| l.reason.is_some());
+    if tenant || currency || reason {
+        let lines = statement.iter().map(|line| StatementRow {
+            line,
+            tenant,
+            currency,
+            reason,
+        });
+        return dump_to_writer(writer, lines, format);
+    }
+    dump_to_writer(writer, statement, format)
+}
+
+/// A `StatementLine` with a tenant column, see `ReportRow`, a currency
+/// column, see `CurrencyOutputRecord`, or a reason column, or any of them.
+/// Each is only written out if any line of the statement has one, so a
+/// statement without adjustments looks as it always did.
+struct StatementRow<'a> {
+    line: &'a StatementLine,
+    tenant: bool,
+    currency: bool,
+    reason: bool,
+}
+
+impl Serialize for StatementRow<'_> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let len =
+            6 + usize::from(self.tenant) + usize::from(self.currency) + usize::from(self.reason);
+        let mut row = s.serialize_struct("StatementRow", len)?;
+        if self.tenant {
+            row.serialize_field("tenant", &self.line.tenant)?;
+        }
+        row.serialize_field("tx", &self.line.tx)?;
+        row.serialize_field("type", &self.line.r#type)?;
+        if self.currency {
+            row.serialize_field("currency", &self.line.currency)?;
+        }
+        row.serialize_field("amount", &self.line.amount)?;
+        row.serialize_field("available", &self.line.available)?;
+        row.serialize_field("held", &self.line.held)?;
+        row.serialize_field("total", &self.line.total)?;
+        if self.reason {
+            row.serialize_field("reason", &self.line.reason)?;
+        }
+        row.end()
+    }
+}
+
+/// A line of the dispute report: a transaction that was disputed, what
+/// became of the dispute, and how long it has been open, in whole days, if
+/// the dispute had a timestamp.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct DisputeRecord {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub r#type: TransactionType,
+    /// Only written out if any dispute has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any dispute has a tenant, just like the tenant
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub amount: Amount,
+    pub status: TransactionState,
+    pub disputed_at: Option<DateTime<Utc>>,
+    pub age_days: Option<i64>,
+}
+
+impl DisputeRecord {
+    /// Describes `dispute` as it stands at `now`.
+    pub fn new(dispute: &Dispute, now: DateTime<Utc>) -> Self {
+        DisputeRecord {
+            client: dispute.client,
+            tx: dispute.tx,
+            r#type: dispute.r#type,
+            currency: dispute.currency,
+            tenant: dispute.tenant,
+            amount: dispute.amount,
+            status: dispute.status,
+            disputed_at: dispute.disputed_at,
+            age_days: dispute.age(now).map(|age| age.num_days()),
+        }
+    }
+}
+
+/// A `DisputeRecord` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyDisputeRecord {
+    client: ClientId,
+    tx: TxId,
+    r#type: TransactionType,
+    currency: Option<Currency>,
+    amount: Amount,
+    status: TransactionState,
+    disputed_at: Option<DateTime<Utc>>,
+    age_days: Option<i64>,
+}
+
+impl From<&DisputeRecord> for CurrencyDisputeRecord {
+    fn from(record: &DisputeRecord) -> Self {
+        CurrencyDisputeRecord {
+            client: record.client,
+            tx: record.tx,
+            r#type: record.r#type,
+            currency: record.currency,
+            amount: record.amount,
+            status: record.status,
+            disputed_at: record.disputed_at,
+            age_days: record.age_days,
+        }
+    }
+}
+
+impl ReportRow for DisputeRecord {
+    type WithCurrency = CurrencyDisputeRecord;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyDisputeRecord {
+        self.into()
+    }
+}
+
+/// Writes the dispute report to a file. The file is replaced just like
+/// `dump_result_to_path` does.
+pub fn dump_disputes_to_path<P: AsRef<Path>>(
+    path: P,
+    disputes: &[DisputeRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_disputes_to_writer(file, disputes, format)
+    })
+}
+
+/// Writes the dispute report to any writer in the given format.
+pub fn dump_disputes_to_writer<W: Write>(
+    writer: W,
+    disputes: &[DisputeRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, disputes, format)
+}
+
+/// Writes what `payments query` found out about a client to a file, see
+/// `dump_query_to_writer`. The file is replaced just like
+/// `dump_result_to_path` does.
+pub fn dump_query_to_path<P: AsRef<Path>>(
+    path: P,
+    balances: Vec<OutputRecord>,
+    disputes: &[DisputeRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_query_to_writer(file, balances, disputes, format)
+    })
+}
+
+/// Writes the balances of the accounts of a client, followed by its open
+/// disputes, to any writer. In CSV, the disputes are a second table after
+/// an empty line, left out if there are none, and in JSON Lines they simply
+/// follow the balances. A JSON document has to be a single value, so in
+/// JSON both are written as one object with a `balances` and a `disputes`
+/// array. A Parquet file only holds a single table, so it cannot hold both.
+pub fn dump_query_to_writer<W: Write>(
+    mut writer: W,
+    balances: Vec<OutputRecord>,
+    disputes: &[DisputeRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Json => {
+            // Both arrays are written just like on their own, which ends
+            // them with a line break, and put together as they are so the
+            // fields keep their order.
+            let (mut b, mut d) = (Vec::new(), Vec::new());
+            dump_result_to_writer(&mut b, balances, format)?;
+            dump_disputes_to_writer(&mut d, disputes, format)?;
+            writer.write_all(b"{\"balances\":")?;
+            writer.write_all(b.trim_ascii_end())?;
+            writer.write_all(b",\"disputes\":")?;
+            writer.write_all(d.trim_ascii_end())?;
+            writer.write_all(b"}\n")?;
+            writer.flush()?;
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "a query cannot be written as Parquet",
+            )
+            .into())
+        }
+        OutputFormat::Csv | OutputFormat::JsonLines => {
+            dump_result_to_writer(&mut writer, balances, format)?;
+            if !disputes.is_empty() {
+                if format == OutputFormat::Csv {
+                    writeln!(writer)?;
                 }
+                dump_disputes_to_writer(&mut writer, disputes, format)?;
             }
         }
     }
+    Ok(())
+}
 
-    // Dump the values of each client's balance as
-    // a vector.
-    let res = output.values().cloned().collect();
-    res
+/// A line of the reconciliation report: a balance check, the total it
+/// states, the total the account held, and whether the two match.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceCheckRecord {
+    pub client: ClientId,
+    pub tx: TxId,
+    /// Only written out if any check has a currency, just like the currency
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any check has a tenant, just like the tenant of
+    /// an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub expected: Amount,
+    pub actual: Amount,
+    /// How much more the account held than expected.
+    pub difference: Amount,
+    pub matched: bool,
 }
 
-/// This function simply dumps a vector of type `OutputRecord` to standard out.
-pub fn dump_result(values: Vec<OutputRecord>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
-    for val in values {
-        writer.serialize(val)?;
+impl From<&BalanceCheck> for BalanceCheckRecord {
+    fn from(check: &BalanceCheck) -> Self {
+        BalanceCheckRecord {
+            client: check.client,
+            tx: check.tx,
+            currency: check.currency,
+            tenant: check.tenant,
+            timestamp: check.timestamp,
+            expected: check.expected,
+            actual: check.actual,
+            difference: check.difference(),
+            matched: check.matches(),
+        }
+    }
+}
+
+/// A `BalanceCheckRecord` with a currency column, see
+/// `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyBalanceCheckRecord {
+    client: ClientId,
+    tx: TxId,
+    currency: Option<Currency>,
+    timestamp: Option<DateTime<Utc>>,
+    expected: Amount,
+    actual: Amount,
+    difference: Amount,
+    matched: bool,
+}
+
+impl From<&BalanceCheckRecord> for CurrencyBalanceCheckRecord {
+    fn from(record: &BalanceCheckRecord) -> Self {
+        CurrencyBalanceCheckRecord {
+            client: record.client,
+            tx: record.tx,
+            currency: record.currency,
+            timestamp: record.timestamp,
+            expected: record.expected,
+            actual: record.actual,
+            difference: record.difference,
+            matched: record.matched,
+        }
+    }
+}
+
+impl ReportRow for BalanceCheckRecord {
+    type WithCurrency = CurrencyBalanceCheckRecord;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyBalanceCheckRecord {
+        self.into()
+    }
+}
+
+/// Writes the reconciliation report to a file. The file is replaced just
+/// like `dump_result_to_path` does.
+pub fn dump_balance_checks_to_path<P: AsRef<Path>>(
+    path: P,
+    checks: &[BalanceCheckRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_balance_checks_to_writer(file, checks, format)
+    })
+}
+
+/// Writes the reconciliation report to any writer in the given format.
+pub fn dump_balance_checks_to_writer<W: Write>(
+    writer: W,
+    checks: &[BalanceCheckRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, checks, format)
+}
+
+/// A line of the totals report: what the account of a client took in and
+/// paid out during the period of the report, for tax and compliance tooling.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct TotalsRecord {
+    pub client: ClientId,
+    /// Only written out if any account has a currency, just like the
+    /// currency of an `OutputRecord`.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
+    /// Only written out if any account has a tenant, just like the tenant
+    /// of an `OutputRecord`.
+    #[serde(skip)]
+    pub tenant: Option<Tenant>,
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    pub charged_back: Amount,
+}
+
+impl From<&ClientTotals> for TotalsRecord {
+    fn from(totals: &ClientTotals) -> Self {
+        TotalsRecord {
+            client: totals.client,
+            currency: totals.currency,
+            tenant: totals.tenant,
+            deposited: totals.deposited,
+            withdrawn: totals.withdrawn,
+            charged_back: totals.charged_back,
+        }
+    }
+}
+
+/// A `TotalsRecord` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyTotalsRecord {
+    client: ClientId,
+    currency: Option<Currency>,
+    deposited: Amount,
+    withdrawn: Amount,
+    charged_back: Amount,
+}
+
+impl From<&TotalsRecord> for CurrencyTotalsRecord {
+    fn from(record: &TotalsRecord) -> Self {
+        CurrencyTotalsRecord {
+            client: record.client,
+            currency: record.currency,
+            deposited: record.deposited,
+            withdrawn: record.withdrawn,
+            charged_back: record.charged_back,
+        }
+    }
+}
+
+impl ReportRow for TotalsRecord {
+    type WithCurrency = CurrencyTotalsRecord;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyTotalsRecord {
+        self.into()
+    }
+}
+
+/// Writes the totals report to a file. The file is replaced just like
+/// `dump_result_to_path` does.
+pub fn dump_totals_to_path<P: AsRef<Path>>(
+    path: P,
+    totals: &[TotalsRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_totals_to_writer(file, totals, format)
+    })
+}
+
+/// Writes the totals report to any writer in the given format.
+pub fn dump_totals_to_writer<W: Write>(
+    writer: W,
+    totals: &[TotalsRecord],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, totals, format)
+}
+
+/// A `DailyBalance` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyDailyBalance {
+    client: ClientId,
+    currency: Option<Currency>,
+    date: NaiveDate,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+impl From<&DailyBalance> for CurrencyDailyBalance {
+    fn from(balance: &DailyBalance) -> Self {
+        CurrencyDailyBalance {
+            client: balance.client,
+            currency: balance.currency,
+            date: balance.date,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: balance.locked,
+        }
+    }
+}
+
+impl ReportRow for DailyBalance {
+    type WithCurrency = CurrencyDailyBalance;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyDailyBalance {
+        self.into()
+    }
+}
+
+/// Writes the balance history, see `Engine::history`, to a file. The file
+/// is replaced just like `dump_result_to_path` does.
+pub fn dump_history_to_path<P: AsRef<Path>>(
+    path: P,
+    history: &[DailyBalance],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_history_to_writer(file, history, format)
+    })
+}
+
+/// Writes the balance history to any writer in the given format.
+pub fn dump_history_to_writer<W: Write>(
+    writer: W,
+    history: &[DailyBalance],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, history, format)
+}
+
+/// A `LockedAccount` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyLockedAccount {
+    client: ClientId,
+    currency: Option<Currency>,
+    chargeback: Option<TxId>,
+    locked_at: Option<DateTime<Utc>>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+}
+
+impl From<&LockedAccount> for CurrencyLockedAccount {
+    fn from(lock: &LockedAccount) -> Self {
+        CurrencyLockedAccount {
+            client: lock.client,
+            currency: lock.currency,
+            chargeback: lock.chargeback,
+            locked_at: lock.locked_at,
+            available: lock.available,
+            held: lock.held,
+            total: lock.total,
+        }
+    }
+}
+
+impl ReportRow for LockedAccount {
+    type WithCurrency = CurrencyLockedAccount;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyLockedAccount {
+        self.into()
+    }
+}
+
+/// An `Escalation` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyEscalation {
+    client: ClientId,
+    tx: TxId,
+    currency: Option<Currency>,
+    amount: Amount,
+    disputed_at: DateTime<Utc>,
+    days: u32,
+    escalated_at: DateTime<Utc>,
+}
+
+impl From<&Escalation> for CurrencyEscalation {
+    fn from(escalation: &Escalation) -> Self {
+        CurrencyEscalation {
+            client: escalation.client,
+            tx: escalation.tx,
+            currency: escalation.currency,
+            amount: escalation.amount,
+            disputed_at: escalation.disputed_at,
+            days: escalation.days,
+            escalated_at: escalation.escalated_at,
+        }
+    }
+}
+
+impl ReportRow for Escalation {
+    type WithCurrency = CurrencyEscalation;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyEscalation {
+        self.into()
+    }
+}
+
+/// Writes the disputes escalated for their age, see `Engine::escalations`,
+/// to a file. The file is replaced just like `dump_result_to_path` does.
+pub fn dump_escalations_to_path<P: AsRef<Path>>(
+    path: P,
+    escalations: &[Escalation],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_escalations_to_writer(file, escalations, format)
+    })
+}
+
+/// Writes the escalated disputes to any writer in the given format.
+pub fn dump_escalations_to_writer<W: Write>(
+    writer: W,
+    escalations: &[Escalation],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, escalations, format)
+}
+
+/// A `PendingDispute` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyPendingDispute {
+    client: ClientId,
+    tx: TxId,
+    currency: Option<Currency>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl From<&PendingDispute> for CurrencyPendingDispute {
+    fn from(pending: &PendingDispute) -> Self {
+        CurrencyPendingDispute {
+            client: pending.client,
+            tx: pending.tx,
+            currency: pending.currency,
+            timestamp: pending.timestamp,
+        }
+    }
+}
+
+impl ReportRow for PendingDispute {
+    type WithCurrency = CurrencyPendingDispute;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyPendingDispute {
+        self.into()
+    }
+}
+
+/// Writes the disputes that never matched a transaction, see
+/// `Engine::pending_disputes`, to a file. The file is replaced just like
+/// `dump_result_to_path` does.
+pub fn dump_pending_disputes_to_path<P: AsRef<Path>>(
+    path: P,
+    pending: &[PendingDispute],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_pending_disputes_to_writer(file, pending, format)
+    })
+}
+
+/// Writes the disputes that never matched to any writer in the given
+/// format.
+pub fn dump_pending_disputes_to_writer<W: Write>(
+    writer: W,
+    pending: &[PendingDispute],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, pending, format)
+}
+
+/// An `Entry` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyEntry {
+    tx: TxId,
+    r#type: TransactionType,
+    client: ClientId,
+    currency: Option<Currency>,
+    account: BookAccount,
+    debit: Amount,
+    credit: Amount,
+}
+
+impl From<&Entry> for CurrencyEntry {
+    fn from(entry: &Entry) -> Self {
+        CurrencyEntry {
+            tx: entry.tx,
+            r#type: entry.r#type,
+            client: entry.client,
+            currency: entry.currency,
+            account: entry.account,
+            debit: entry.debit,
+            credit: entry.credit,
+        }
+    }
+}
+
+impl ReportRow for Entry {
+    type WithCurrency = CurrencyEntry;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyEntry {
+        self.into()
+    }
+}
+
+/// Writes the double-entry journal, see `Engine::book`, to a file for the
+/// accounting system. The file is replaced just like `dump_result_to_path`
+/// does.
+pub fn dump_book_to_path<P: AsRef<Path>>(
+    path: P,
+    entries: &[Entry],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_book_to_writer(file, entries, format)
+    })
+}
+
+/// Writes the double-entry journal to any writer in the given format.
+pub fn dump_book_to_writer<W: Write>(
+    writer: W,
+    entries: &[Entry],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, entries, format)
+}
+
+/// Writes the locked accounts, see `Engine::locks`, to a file. The file is
+/// replaced just like `dump_result_to_path` does.
+pub fn dump_locks_to_path<P: AsRef<Path>>(
+    path: P,
+    locks: &[LockedAccount],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_locks_to_writer(file, locks, format)
+    })
+}
+
+/// Writes the locked accounts to any writer in the given format.
+pub fn dump_locks_to_writer<W: Write>(
+    writer: W,
+    locks: &[LockedAccount],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, locks, format)
+}
+
+/// An `Anomaly` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyAnomaly {
+    client: ClientId,
+    currency: Option<Currency>,
+    deposited: Amount,
+    withdrawn: Amount,
+    disputes: u64,
+    flags: String,
+}
+
+impl From<&Anomaly> for CurrencyAnomaly {
+    fn from(anomaly: &Anomaly) -> Self {
+        let flags: Vec<String> = anomaly.flags.iter().map(ToString::to_string).collect();
+        CurrencyAnomaly {
+            client: anomaly.client,
+            currency: anomaly.currency,
+            deposited: anomaly.deposited,
+            withdrawn: anomaly.withdrawn,
+            disputes: anomaly.disputes,
+            flags: flags.join(" "),
+        }
+    }
+}
+
+impl ReportRow for Anomaly {
+    type WithCurrency = CurrencyAnomaly;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyAnomaly {
+        self.into()
+    }
+}
+
+/// Writes the accounts flagged as anomalies, see `Engine::anomalies`, to a
+/// file. The file is replaced just like `dump_result_to_path` does.
+pub fn dump_anomalies_to_path<P: AsRef<Path>>(
+    path: P,
+    anomalies: &[Anomaly],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_anomalies_to_writer(file, anomalies, format)
+    })
+}
+
+/// Writes the accounts flagged as anomalies to any writer in the given
+/// format.
+pub fn dump_anomalies_to_writer<W: Write>(
+    writer: W,
+    anomalies: &[Anomaly],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, anomalies, format)
+}
+
+/// A `BalanceDiff` with a currency column, see `CurrencyOutputRecord`.
+#[derive(Serialize)]
+struct CurrencyBalanceDiff {
+    client: ClientId,
+    currency: Option<Currency>,
+    change: Change,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    was_locked: bool,
+    locked: bool,
+}
+
+impl From<&BalanceDiff> for CurrencyBalanceDiff {
+    fn from(diff: &BalanceDiff) -> Self {
+        CurrencyBalanceDiff {
+            client: diff.client,
+            currency: diff.currency,
+            change: diff.change,
+            available: diff.available,
+            held: diff.held,
+            total: diff.total,
+            was_locked: diff.was_locked,
+            locked: diff.locked,
+        }
+    }
+}
+
+impl ReportRow for BalanceDiff {
+    type WithCurrency = CurrencyBalanceDiff;
+
+    fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    fn with_currency(&self) -> CurrencyBalanceDiff {
+        self.into()
+    }
+}
+
+/// Writes how two sets of balances differ, see `diff::diff_balances`, to a
+/// file. The file is replaced just like `dump_result_to_path` does.
+pub fn dump_diff_to_path<P: AsRef<Path>>(
+    path: P,
+    diffs: &[BalanceDiff],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        dump_diff_to_writer(file, diffs, format)
+    })
+}
+
+/// Writes how two sets of balances differ to any writer in the given
+/// format.
+pub fn dump_diff_to_writer<W: Write>(
+    writer: W,
+    diffs: &[BalanceDiff],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    dump_rows_to_writer(writer, diffs, format)
+}
+
+/// Writes the rows of the balances or of a report in the given format,
+/// with a currency column and a tenant column only if any row has one, see
+/// `ReportRow`.
+fn dump_rows_to_writer<W, T>(writer: W, rows: &[T], format: OutputFormat) -> Result<(), Error>
+where
+    W: Write,
+    T: ReportRow,
+{
+    let currency = rows.iter().any(|r| r.currency().is_some());
+    if !rows.iter().any(|r| r.tenant().is_some()) {
+        if currency {
+            return dump_to_writer(writer, rows.iter().map(T::with_currency), format);
+        }
+        return dump_to_writer(writer, rows, format);
+    }
+    if currency {
+        let rows = rows.iter().map(|r| (r.tenant(), r.with_currency()));
+        return dump_tenant_rows_to_writer(writer, rows, format);
+    }
+    dump_tenant_rows_to_writer(writer, rows.iter().map(|r| (r.tenant(), r)), format)
+}
+
+/// Writes rows along with their tenants in the given format, see
+/// `TenantRow`.
+fn dump_tenant_rows_to_writer<W, I, T>(
+    writer: W,
+    rows: I,
+    format: OutputFormat,
+) -> Result<(), Error>
+where
+    W: Write,
+    I: IntoIterator<Item = (Option<Tenant>, T)>,
+    T: Serialize,
+{
+    let rows = rows.into_iter();
+    if format == OutputFormat::Csv {
+        let rows = rows.map(|(tenant, row)| (TenantColumn { tenant }, row));
+        return dump_to_writer(writer, rows, format);
+    }
+    dump_to_writer(
+        writer,
+        rows.map(|(tenant, row)| TenantRow { tenant, row }),
+        format,
+    )
+}
+
+/// Writes the figures of a run to a file as a JSON object, replacing the
+/// file just like `dump_result_to_path` does.
+pub fn dump_statistics_to_path<P: AsRef<Path>>(
+    path: P,
+    statistics: &Statistics,
+) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        serde_json::to_writer_pretty(&mut *file, statistics).map_err(std::io::Error::from)?;
+        writeln!(file)?;
+        Ok(())
+    })
+}
+
+/// Writes the report of a run to a file as a JSON object, replacing the
+/// file just like `dump_statistics_to_path` does.
+pub fn dump_run_report_to_path<P: AsRef<Path>>(path: P, report: &RunReport) -> Result<(), Error> {
+    write_to_path(path.as_ref(), None, |file| {
+        serde_json::to_writer_pretty(&mut *file, report).map_err(std::io::Error::from)?;
+        writeln!(file)?;
+        Ok(())
+    })
+}
+
+/// Writes any list of serializable values in the given format.
+fn dump_to_writer<W, I>(mut writer: W, values: I, format: OutputFormat) -> Result<(), Error>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut writer);
+            for val in values {
+                writer.serialize(val)?;
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            let values: Vec<I::Item> = values.into_iter().collect();
+            serde_json::to_writer(&mut writer, &values).map_err(std::io::Error::from)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            for val in values {
+                serde_json::to_writer(&mut writer, &val).map_err(std::io::Error::from)?;
+                writeln!(writer)?;
+            }
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => parquet::write_parquet(&mut writer, values)?,
     }
     writer.flush()?;
     Ok(())
@@ -154,52 +1748,739 @@ pub fn dump_result(values: Vec<OutputRecord>) -> Result<(), Box<dyn std::error::
 
 #[cfg(test)]
 pub mod tests {
-    use super::super::input::make_input_record;
-    use super::{check_dispute, get_transaction_amount, OutputRecord};
+    use super::super::input::{make_input_record, parse_timestamp, InputRecord};
+    use super::{
+        dump_anomalies_to_writer, dump_balance_checks_to_writer, dump_balances_to_writer,
+        dump_book_to_writer, dump_diff_to_writer, dump_disputes_to_writer, dump_history_to_writer,
+        dump_locks_to_writer, dump_pending_disputes_to_writer, dump_query_to_writer,
+        dump_rejects_to_writer, dump_result_to_path, dump_result_to_writer,
+        dump_sharded_balances_to_path, dump_statement_to_writer, dump_totals_to_writer,
+        make_client_output_records, BalanceCheckRecord, DisputeRecord, OutputFormat, OutputRecord,
+        OutputSchema, RejectedRecord, Sharding, TotalsRecord,
+    };
+    use crate::amount::{Amount, DecimalFormat};
+    use crate::diff::diff_balances;
+    use crate::engine::{AnomalyThresholds, Engine, EngineConfig, StatementPolicy, TotalsPeriod};
+    use crate::id::ClientId;
     use csv::StringRecord;
 
     #[test]
     fn test_new_valid_output_record() {
         let test_record = OutputRecord {
-            client: 1,
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
+            client: ClientId::new(1),
+            currency: None,
+            tenant: None,
+            available: Amount::from_scaled(10_000),
+            held: Amount::ZERO,
+            total: Amount::from_scaled(10_000),
             locked: false,
         };
-        assert_eq!(OutputRecord::new(1, 1.0, 0.0, 1.0, false), test_record);
+        assert_eq!(
+            OutputRecord::new(
+                ClientId::new(1),
+                Amount::from_scaled(10_000),
+                Amount::ZERO,
+                Amount::from_scaled(10_000),
+                false
+            ),
+            test_record
+        );
     }
 
     #[test]
-    fn test_valid_client_transaction() {
-        let record = vec![make_input_record(&StringRecord::from(vec![
-            "deposit", "1", "1", "20.00",
-        ]))
-        .unwrap()];
-        assert_eq!(get_transaction_amount(&record, 1, 1), Some(20.00));
+    fn test_client_output_records() {
+        let records = vec![
+            vec!["deposit", "1", "1", "20.00"],
+            vec!["deposit", "2", "2", "5.00"],
+            vec!["withdrawal", "1", "3", "7.50"],
+            vec!["dispute", "2", "2", ""],
+        ]
+        .into_iter()
+        .map(|r| make_input_record(&StringRecord::from(r)).unwrap());
+
+        let mut output = make_client_output_records(records);
+        output.sort_by_key(|r| r.client);
+        assert_eq!(
+            output,
+            vec![
+                OutputRecord::new(
+                    ClientId::new(1),
+                    Amount::from_scaled(125_000),
+                    Amount::ZERO,
+                    Amount::from_scaled(125_000),
+                    false
+                ),
+                OutputRecord::new(
+                    ClientId::new(2),
+                    Amount::ZERO,
+                    Amount::from_scaled(50_000),
+                    Amount::from_scaled(50_000),
+                    false
+                ),
+            ]
+        );
+    }
+
+    fn output_records() -> Vec<OutputRecord> {
+        vec![
+            OutputRecord::new(
+                ClientId::new(1),
+                Amount::from_scaled(15_000),
+                Amount::ZERO,
+                Amount::from_scaled(15_000),
+                false,
+            ),
+            OutputRecord::new(
+                ClientId::new(2),
+                Amount::ZERO,
+                Amount::ZERO,
+                Amount::ZERO,
+                true,
+            ),
+        ]
+    }
+
+    fn write_to_string(format: OutputFormat) -> String {
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, output_records(), format).unwrap();
+        String::from_utf8(buf).unwrap()
     }
 
     #[test]
-    fn test_valid_dispute() {
-        let record =
-            vec![make_input_record(&StringRecord::from(vec!["dispute", "1", "1", ""])).unwrap()];
+    fn test_write_csv() {
+        assert_eq!(
+            write_to_string(OutputFormat::Csv),
+            "client,available,held,total,locked\n\
+             1,1.5,0.0,1.5,false\n\
+             2,0.0,0.0,0.0,true\n"
+        );
+    }
 
-        assert_eq!(check_dispute(&record, 1, 1), true);
+    #[test]
+    fn test_write_json() {
+        assert_eq!(
+            write_to_string(OutputFormat::Json),
+            "[{\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false},\
+             {\"client\":2,\"available\":\"0.0\",\"held\":\"0.0\",\"total\":\"0.0\",\"locked\":true}]\n"
+        );
     }
 
     #[test]
-    fn test_invalid_dispute_client() {
-        let record =
-            vec![make_input_record(&StringRecord::from(vec!["dispute", "1", "1", ""])).unwrap()];
+    fn test_write_json_lines() {
+        assert_eq!(
+            write_to_string(OutputFormat::JsonLines),
+            "{\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"0.0\",\"held\":\"0.0\",\"total\":\"0.0\",\"locked\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_result_to_path() {
+        let dir = std::env::temp_dir().join(format!("payments-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.csv");
+        std::fs::write(&path, "stale").unwrap();
+
+        dump_result_to_path(&path, output_records(), OutputFormat::Csv).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            write_to_string(OutputFormat::Csv)
+        );
+        // Only the report itself should be left behind, no temporary files.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dump_result_to_missing_directory() {
+        let path = std::env::temp_dir().join("payments-missing-dir/report.csv");
+        assert!(dump_result_to_path(&path, output_records(), OutputFormat::Csv).is_err());
+    }
+
+    #[test]
+    fn test_write_rejects() {
+        let rejects = vec![
+            RejectedRecord {
+                line: Some(3),
+                raw: "withdrawal,1,2,5.0".to_string(),
+                reason: "insufficient funds".to_string(),
+            },
+            RejectedRecord {
+                line: None,
+                raw: "bogus,\"1,2\"".to_string(),
+                reason: "unknown transaction type 'bogus'".to_string(),
+            },
+        ];
+        let mut buf = Vec::new();
+        dump_rejects_to_writer(&mut buf, &rejects).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "line,raw,reason\n\
+             3,\"withdrawal,1,2,5.0\",insufficient funds\n\
+             ,\"bogus,\"\"1,2\"\"\",unknown transaction type 'bogus'\n"
+        );
+    }
+
+    #[test]
+    fn test_write_statement() {
+        let mut engine = Engine::with_config(EngineConfig {
+            statements: StatementPolicy::All,
+            ..EngineConfig::default()
+        });
+        for row in [["deposit", "7", "1", "2.5"], ["withdrawal", "7", "2", "1"]] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row.to_vec())).unwrap())
+                .unwrap();
+        }
+        let mut buf = Vec::new();
+        dump_statement_to_writer(
+            &mut buf,
+            engine.statement(ClientId::new(7), None),
+            OutputFormat::Csv,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "tx,type,amount,available,held,total\n\
+             1,deposit,2.5,2.5,0.0,2.5\n\
+             2,withdrawal,1.0,1.5,0.0,1.5\n"
+        );
 
-        assert_eq!(check_dispute(&record, 100, 1), false);
+        // Once there is an adjustment, every line gets a reason column.
+        let row = ["credit_adjustment", "7", "3", "0.5", "", "", "BANK_ERROR"];
+        engine
+            .apply(make_input_record(&StringRecord::from(row.to_vec())).unwrap())
+            .unwrap();
+        let mut buf = Vec::new();
+        dump_statement_to_writer(
+            &mut buf,
+            engine.statement(ClientId::new(7), None),
+            OutputFormat::Csv,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "tx,type,amount,available,held,total,reason\n\
+             1,deposit,2.5,2.5,0.0,2.5,\n\
+             2,withdrawal,1.0,1.5,0.0,1.5,\n\
+             3,credit_adjustment,0.5,2.0,0.0,2.0,BANK_ERROR\n"
+        );
     }
 
     #[test]
-    fn test_invalid_dispute_transaction() {
-        let record =
-            vec![make_input_record(&StringRecord::from(vec!["dispute", "1", "1", ""])).unwrap()];
+    fn test_write_disputes() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.5"],
+            vec!["deposit", "1", "2", "1.0"],
+            vec!["deposit", "2", "3", "4.0"],
+            vec!["dispute", "2", "3", "", "2024-01-01T00:00:00Z"],
+            vec!["dispute", "1", "2", ""],
+            vec!["resolve", "1", "2", ""],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let now = parse_timestamp("2024-01-31T12:00:00Z").unwrap();
+        let disputes: Vec<_> = engine
+            .disputes()
+            .iter()
+            .map(|d| DisputeRecord::new(d, now))
+            .collect();
+        let mut buf = Vec::new();
+        dump_disputes_to_writer(&mut buf, &disputes, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,type,amount,status,disputed_at,age_days\n\
+             1,2,deposit,1.0,resolved,,\n\
+             2,3,deposit,4.0,disputed,2024-01-01T00:00:00Z,30\n"
+        );
+    }
+
+    #[test]
+    fn test_write_balances_with_schema() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.5"],
+            vec!["deposit", "1", "2", "1.0"],
+            vec!["deposit", "1", "3", "0.5"],
+            vec!["dispute", "1", "2", ""],
+            vec!["dispute", "1", "3", ""],
+            vec!["resolve", "1", "3", ""],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let write = |schema: &OutputSchema, format| {
+            let mut buf = Vec::new();
+            dump_balances_to_writer(&mut buf, &engine, schema, format).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        let schema = OutputSchema::default();
+        assert_eq!(
+            write(&schema, OutputFormat::Csv),
+            "client,available,held,total,locked\n1,3.0,1.0,4.0,false\n"
+        );
+
+        let schema = OutputSchema {
+            columns: Some(OutputSchema::parse_columns("total,client,disputed_count").unwrap()),
+            ..OutputSchema::default()
+        };
+        assert_eq!(
+            write(&schema, OutputFormat::Csv),
+            "total,client,disputed_count\n4.0,1,1\n"
+        );
+        assert_eq!(
+            write(&schema, OutputFormat::JsonLines),
+            "{\"total\":\"4.0\",\"client\":1,\"disputed_count\":1}\n"
+        );
+
+        let schema = OutputSchema {
+            header: false,
+            ..OutputSchema::default()
+        };
+        assert_eq!(write(&schema, OutputFormat::Csv), "1,3.0,1.0,4.0,false\n");
+
+        let schema = OutputSchema {
+            extended: true,
+            ..OutputSchema::default()
+        };
+        assert_eq!(
+            write(&schema, OutputFormat::Csv),
+            "client,available,held,total,locked,disputed_count,disputed_amount\n\
+             1,3.0,1.0,4.0,false,1,1.0\n"
+        );
+
+        let schema = OutputSchema {
+            decimals: DecimalFormat {
+                places: 4,
+                fixed: true,
+            },
+            ..OutputSchema::default()
+        };
+        assert_eq!(
+            write(&schema, OutputFormat::Csv),
+            "client,available,held,total,locked\n1,3.0000,1.0000,4.0000,false\n"
+        );
+        assert_eq!(
+            write(&schema, OutputFormat::JsonLines),
+            "{\"client\":1,\"available\":\"3.0000\",\"held\":\"1.0000\",\
+             \"total\":\"4.0000\",\"locked\":false}\n"
+        );
 
-        assert_eq!(check_dispute(&record, 1, 100), false);
+        assert!(OutputSchema::parse_columns("client,bogus").is_err());
+        assert!(OutputSchema::parse_columns("client,total,client").is_err());
+    }
+
+    #[test]
+    fn test_dump_tenant_balances() {
+        let record = |row: Vec<&str>, tenant: &str| InputRecord {
+            tenant: tenant.parse().ok(),
+            ..make_input_record(&StringRecord::from(row)).unwrap()
+        };
+        let mut engine = Engine::with_config(EngineConfig {
+            tenants: true,
+            ..EngineConfig::default()
+        });
+        for (tenant, amount) in [("us", "2.0"), ("eu", "3.0")] {
+            engine
+                .apply(record(vec!["deposit", "1", "1", amount], tenant))
+                .unwrap();
+            engine
+                .apply(record(vec!["dispute", "1", "1", ""], tenant))
+                .unwrap();
+        }
+        engine
+            .apply(record(vec!["deposit", "2", "1", "1.0"], ""))
+            .unwrap();
+        let mut balances = engine.balances();
+        balances.sort_by_key(|b| b.tenant);
+
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, balances.clone(), OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "tenant,client,available,held,total,locked\n\
+             ,2,1.0,0.0,1.0,false\n\
+             eu,1,0.0,3.0,3.0,false\n\
+             us,1,0.0,2.0,2.0,false\n"
+        );
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, balances[1..2].to_vec(), OutputFormat::JsonLines).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "{\"tenant\":\"eu\",\"client\":1,\"available\":\"0.0\",\"held\":\"3.0\",\
+             \"total\":\"3.0\",\"locked\":false}\n"
+        );
+
+        // Every account of a tenant is told apart, down to its disputes.
+        let schema = OutputSchema {
+            columns: Some(OutputSchema::parse_columns("client,disputed_amount,tenant").unwrap()),
+            ..OutputSchema::default()
+        };
+        let mut buf = Vec::new();
+        dump_balances_to_writer(&mut buf, &engine, &schema, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,disputed_amount,tenant\n2,0.0,\n1,3.0,eu\n1,2.0,us\n"
+        );
+
+        // Reports have a tenant column in front as well, along with the
+        // currency if any row has one.
+        let now = parse_timestamp("2024-01-01T00:00:00Z").unwrap();
+        let mut disputes: Vec<DisputeRecord> = engine
+            .disputes()
+            .iter()
+            .map(|d| DisputeRecord::new(d, now))
+            .collect();
+        disputes[0].currency = "EUR".parse().ok();
+        let mut buf = Vec::new();
+        dump_disputes_to_writer(&mut buf, &disputes, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "tenant,client,tx,type,currency,amount,status,disputed_at,age_days\n\
+             eu,1,1,deposit,EUR,3.0,disputed,,\n\
+             us,1,1,deposit,,2.0,disputed,,\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_sharded_balances() {
+        let dir = std::env::temp_dir().join(format!("payments-shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut engine = Engine::new();
+        for (client, tx) in [("1", "1"), ("2", "2"), ("5", "3"), ("250", "4")] {
+            let row = StringRecord::from(vec!["deposit", client, tx, "1.0"]);
+            engine.apply(make_input_record(&row).unwrap()).unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        let schema = OutputSchema::default();
+
+        let manifest = dump_sharded_balances_to_path(
+            dir.join("balances.csv"),
+            &engine,
+            &schema,
+            OutputFormat::Csv,
+            Sharding::Buckets(3),
+        )
+        .unwrap();
+        assert_eq!(manifest.accounts, 4);
+        assert_eq!(manifest.shards.len(), 3);
+        assert_eq!(manifest.shards[0].file, "balances_000.csv");
+        assert_eq!(manifest.shards[0].accounts, 0);
+        assert_eq!(manifest.shards[0].first_client, None);
+        assert_eq!(manifest.shards[2].first_client, Some(ClientId::new(2)));
+        assert_eq!(manifest.shards[2].last_client, Some(ClientId::new(5)));
+        assert_eq!(read("balances_000.csv"), "");
+        assert_eq!(
+            read("balances_001.csv"),
+            "client,available,held,total,locked
+1,1.0,0.0,1.0,false
+250,1.0,0.0,1.0,false
+"
+        );
+        assert!(read("balances.manifest.json").contains("\"file\": \"balances_002.csv\""));
+
+        let manifest = dump_sharded_balances_to_path(
+            dir.join("ranges.jsonl"),
+            &engine,
+            &schema,
+            OutputFormat::JsonLines,
+            Sharding::Range(100),
+        )
+        .unwrap();
+        let files: Vec<&str> = manifest.shards.iter().map(|s| s.file.as_str()).collect();
+        assert_eq!(files, ["ranges_000.jsonl", "ranges_002.jsonl"]);
+        assert_eq!(manifest.format, "jsonl");
+        assert_eq!(read("ranges_002.jsonl").lines().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!("16".parse(), Ok(Sharding::Buckets(16)));
+        assert_eq!("range:100000".parse(), Ok(Sharding::Range(100_000)));
+        assert!("range:0".parse::<Sharding>().is_err());
+        assert!("many".parse::<Sharding>().is_err());
+    }
+
+    #[test]
+    fn test_write_balance_checks() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.5"],
+            vec!["balance_check", "1", "2", "2.5"],
+            vec!["balance_check", "2", "3", "1.0", "2024-01-01T00:00:00Z"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let checks: Vec<_> = engine
+            .balance_checks()
+            .iter()
+            .map(BalanceCheckRecord::from)
+            .collect();
+        let mut buf = Vec::new();
+        dump_balance_checks_to_writer(&mut buf, &checks, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,timestamp,expected,actual,difference,matched\n\
+             1,2,,2.5,2.5,0.0,true\n\
+             2,3,2024-01-01T00:00:00Z,1.0,0.0,-1.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_book() {
+        let mut engine = Engine::with_config(EngineConfig {
+            double_entry: true,
+            ..EngineConfig::default()
+        });
+        for row in [
+            vec!["deposit", "1", "1", "10"],
+            vec!["withdrawal", "1", "2", "4"],
+            vec!["dispute", "1", "1", ""],
+            vec!["chargeback", "1", "1", ""],
+            vec!["withdrawal", "1", "3", "100"],
+        ] {
+            let _ = engine.apply(make_input_record(&StringRecord::from(row)).unwrap());
+        }
+        let mut buf = Vec::new();
+        dump_book_to_writer(&mut buf, engine.book(), OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "tx,type,client,account,debit,credit\n\
+             1,deposit,1,client_available,0.0,10.0\n\
+             1,deposit,1,bank_clearing,10.0,0.0\n\
+             2,withdrawal,1,client_available,4.0,0.0\n\
+             2,withdrawal,1,bank_clearing,0.0,4.0\n\
+             1,dispute,1,client_available,10.0,0.0\n\
+             1,dispute,1,client_held,0.0,10.0\n\
+             1,chargeback,1,client_held,10.0,0.0\n\
+             1,chargeback,1,bank_clearing,0.0,10.0\n"
+        );
+    }
+
+    #[test]
+    fn test_write_pending_disputes() {
+        let mut engine = Engine::with_config(EngineConfig {
+            pending_disputes: true,
+            ..EngineConfig::default()
+        });
+        for row in [
+            vec!["dispute", "1", "1", "", "2024-01-01T00:00:00Z"],
+            vec!["dispute", "2", "2", ""],
+            vec!["deposit", "1", "1", "10"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let mut buf = Vec::new();
+        dump_pending_disputes_to_writer(&mut buf, &engine.pending_disputes(), OutputFormat::Csv)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,tx,timestamp\n2,2,\n"
+        );
+    }
+
+    #[test]
+    fn test_write_totals() {
+        let mut engine = Engine::with_config(EngineConfig {
+            totals: Some(TotalsPeriod::default()),
+            ..EngineConfig::default()
+        });
+        for row in [
+            vec!["deposit", "1", "1", "2.5", "", "EUR"],
+            vec!["withdrawal", "1", "2", "1.0", "", "EUR"],
+            vec!["deposit", "2", "3", "4.0"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let totals: Vec<_> = engine.totals().iter().map(TotalsRecord::from).collect();
+        let mut buf = Vec::new();
+        dump_totals_to_writer(&mut buf, &totals, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,deposited,withdrawn,charged_back\n\
+             1,EUR,2.5,1.0,0.0\n\
+             2,,4.0,0.0,0.0\n"
+        );
+    }
+
+    #[test]
+    fn test_write_history() {
+        let mut engine = Engine::with_config(EngineConfig {
+            history: true,
+            ..EngineConfig::default()
+        });
+        for row in [
+            vec!["deposit", "1", "1", "2.5", "2024-01-01T10:00:00Z", "EUR"],
+            vec!["deposit", "2", "2", "4.0", "2024-01-02T10:00:00Z"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let mut buf = Vec::new();
+        dump_history_to_writer(&mut buf, &engine.history(), OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,date,available,held,total,locked\n\
+             1,EUR,2024-01-01,2.5,0.0,2.5,false\n\
+             1,EUR,2024-01-02,2.5,0.0,2.5,false\n\
+             2,,2024-01-02,4.0,0.0,4.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_locks() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.5"],
+            vec!["deposit", "1", "2", "1.0"],
+            vec!["dispute", "1", "1", ""],
+            vec!["chargeback", "1", "1", "", "2024-01-01T10:00:00Z"],
+            vec!["deposit", "2", "3", "4.0"],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let mut buf = Vec::new();
+        dump_locks_to_writer(&mut buf, &engine.locks(), OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,chargeback,locked_at,available,held,total\n\
+             1,1,2024-01-01T10:00:00Z,1.0,0.0,1.0\n"
+        );
+    }
+
+    #[test]
+    fn test_write_anomalies() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.0"],
+            vec!["withdrawal", "1", "2", "1.5"],
+            vec!["deposit", "2", "3", "4.0", "", "EUR"],
+            vec!["dispute", "2", "3", ""],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let thresholds = AnomalyThresholds {
+            withdrawal_ratio: Some("0.5".parse().unwrap()),
+            max_disputes: Some(0),
+        };
+        let mut buf = Vec::new();
+        let anomalies = engine.anomalies(&thresholds);
+        dump_anomalies_to_writer(&mut buf, &anomalies, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,deposited,withdrawn,disputes,flags\n\
+             1,,2.0,1.5,0,withdrawals\n\
+             2,EUR,4.0,0.0,1,disputes\n"
+        );
+    }
+
+    #[test]
+    fn test_write_query() {
+        let mut engine = Engine::new();
+        for row in [
+            vec!["deposit", "1", "1", "2.5"],
+            vec!["deposit", "1", "2", "1.0"],
+            vec!["dispute", "1", "2", ""],
+        ] {
+            engine
+                .apply(make_input_record(&StringRecord::from(row)).unwrap())
+                .unwrap();
+        }
+        let now = parse_timestamp("2024-01-31T12:00:00Z").unwrap();
+        let disputes: Vec<_> = engine
+            .disputes()
+            .iter()
+            .map(|d| DisputeRecord::new(d, now))
+            .collect();
+        let query = |disputes: &[DisputeRecord], format| {
+            let mut buf = Vec::new();
+            let balances = engine.client_balances(ClientId::new(1));
+            dump_query_to_writer(&mut buf, balances, disputes, format).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(
+            query(&disputes, OutputFormat::Csv),
+            "client,available,held,total,locked\n\
+             1,2.5,1.0,3.5,false\n\
+             \n\
+             client,tx,type,amount,status,disputed_at,age_days\n\
+             1,2,deposit,1.0,disputed,,\n"
+        );
+        assert_eq!(
+            query(&[], OutputFormat::Csv),
+            "client,available,held,total,locked\n1,2.5,1.0,3.5,false\n"
+        );
+        assert_eq!(
+            query(&disputes, OutputFormat::Json),
+            "{\"balances\":[{\"client\":1,\"available\":\"2.5\",\"held\":\"1.0\",\
+             \"total\":\"3.5\",\"locked\":false}],\"disputes\":[{\"client\":1,\"tx\":2,\
+             \"type\":\"deposit\",\"amount\":\"1.0\",\"status\":\"disputed\",\
+             \"disputed_at\":null,\"age_days\":null}]}\n"
+        );
+    }
+
+    #[test]
+    fn test_write_diff() {
+        let old = vec![OutputRecord::new(
+            ClientId::new(1),
+            Amount::from_scaled(10_000),
+            Amount::ZERO,
+            Amount::from_scaled(10_000),
+            false,
+        )];
+        let mut new = old.clone();
+        new[0].currency = Some("EUR".parse().unwrap());
+        let mut buf = Vec::new();
+        dump_diff_to_writer(&mut buf, &diff_balances(&old, &new), OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,change,available,held,total,was_locked,locked\n\
+             1,,removed,-1.0,0.0,-1.0,false,false\n\
+             1,EUR,added,1.0,0.0,1.0,false,false\n"
+        );
+    }
+
+    #[test]
+    fn test_write_currency_column() {
+        let records: Vec<_> = [
+            vec!["deposit", "1", "1", "1.5", "", "USD"],
+            vec!["deposit", "2", "2", "2", "", ""],
+        ]
+        .into_iter()
+        .map(|r| make_input_record(&StringRecord::from(r)).unwrap())
+        .collect();
+        let mut values = make_client_output_records(records);
+        values.sort_by_key(|r| r.client);
+
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, values, OutputFormat::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,USD,1.5,0.0,1.5,false\n\
+             2,,2.0,0.0,2.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        assert_eq!("JSON".parse(), Ok(OutputFormat::Json));
+        assert_eq!("jsonl".parse(), Ok(OutputFormat::JsonLines));
+        assert!("xml".parse::<OutputFormat>().is_err());
     }
 }
@@ -0,0 +1,187 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How output is compressed as it is written, see `Compressed`. Writing
+/// compressed output needs the `compress` feature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression the name of `path` asks for: gzip for a name ending
+    /// in `.gz`, zstd for one ending in `.zst`, and none otherwise.
+    pub fn from_path(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "compress")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "compress")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+/// A writer that compresses what is written to it, if asked to, before
+/// handing it on to another one. It has to be `finish`ed to write out the
+/// end of the compressed stream, which also tells whether that worked,
+/// rather than leaving it to a drop that cannot.
+pub struct Compressed<W: Write> {
+    encoder: Encoder<W>,
+}
+
+impl<W: Write> Compressed<W> {
+    /// Compresses what is written to `writer` with `compression`, or just
+    /// writes it through for `None`. Fails if payments was built without
+    /// the `compress` feature.
+    pub fn new(writer: W, compression: Option<Compression>) -> io::Result<Self> {
+        let encoder = match compression {
+            None => Encoder::Plain(writer),
+            #[cfg(feature = "compress")]
+            Some(Compression::Gzip) => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "compress")]
+            Some(Compression::Zstd) => Encoder::Zstd(zstd::Encoder::new(writer, 0)?),
+            #[cfg(not(feature = "compress"))]
+            Some(compression) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "{} output needs payments to be built with the compress feature",
+                        compression
+                    ),
+                ))
+            }
+        };
+        Ok(Compressed { encoder })
+    }
+
+    /// Writes out the end of the compressed stream and flushes it, handing
+    /// back the writer it went to.
+    // Without the feature there is only the plain writer to match.
+    #[cfg_attr(
+        not(feature = "compress"),
+        allow(clippy::infallible_destructuring_match)
+    )]
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = match self.encoder {
+            Encoder::Plain(writer) => writer,
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "compress")]
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for Compressed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "compress")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(writer) => writer.flush(),
+            #[cfg(feature = "compress")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "compress")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Compressed, Compression};
+    use std::io::Write;
+    use std::path::Path;
+
+    #[test]
+    fn test_compression_from_path() {
+        let from_path = |p: &str| Compression::from_path(Path::new(p));
+        assert_eq!(from_path("report.csv.gz"), Some(Compression::Gzip));
+        assert_eq!(from_path("report.csv.zst"), Some(Compression::Zstd));
+        assert_eq!(from_path("report.csv"), None);
+        assert_eq!(from_path("gz"), None);
+        assert_eq!("GZIP".parse(), Ok(Compression::Gzip));
+        assert!("bzip2".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_plain() {
+        let mut writer = Compressed::new(Vec::new(), None).unwrap();
+        writer.write_all(b"client\n").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"client\n");
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn test_compressed() {
+        use std::io::Read;
+
+        let data = "client,available,held,total,locked\n".repeat(100);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut writer = Compressed::new(Vec::new(), Some(compression)).unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+            let compressed = writer.finish().unwrap();
+            assert!(compressed.len() < data.len());
+            let mut read = String::new();
+            match compression {
+                Compression::Gzip => flate2::read::GzDecoder::new(&compressed[..])
+                    .read_to_string(&mut read)
+                    .unwrap(),
+                Compression::Zstd => zstd::Decoder::new(&compressed[..])
+                    .unwrap()
+                    .read_to_string(&mut read)
+                    .unwrap(),
+            };
+            assert_eq!(read, data);
+        }
+    }
+
+    #[cfg(not(feature = "compress"))]
+    #[test]
+    fn test_needs_feature() {
+        assert!(Compressed::new(Vec::new(), Some(Compression::Gzip)).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::amount::DECIMAL_PLACES;
+use crate::error::Error;
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::json::ReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Every column the output can have, in the order they are written in, and
+/// the type they are written as. Amounts are decimals with as many places
+/// as an `Amount` has, so they are exact and can be summed up. Transaction
+/// IDs are text, as not all of them are numbers.
+fn columns() -> [(&'static str, DataType); 22] {
+    let amount = DataType::Decimal128(38, DECIMAL_PLACES as i8);
+    [
+        ("tenant", DataType::Utf8),
+        ("tx", DataType::Utf8),
+        ("type", DataType::Utf8),
+        ("client", DataType::UInt64),
+        ("currency", DataType::Utf8),
+        ("change", DataType::Utf8),
+        ("amount", amount.clone()),
+        ("available", amount.clone()),
+        ("held", amount.clone()),
+        ("total", amount.clone()),
+        ("deposited", amount.clone()),
+        ("withdrawn", amount.clone()),
+        ("was_locked", DataType::Boolean),
+        ("locked", DataType::Boolean),
+        ("disputed_count", DataType::UInt64),
+        ("disputed_amount", amount),
+        ("disputes", DataType::UInt64),
+        ("flags", DataType::Utf8),
+        ("status", DataType::Utf8),
+        ("disputed_at", DataType::Utf8),
+        ("age_days", DataType::Int64),
+        ("reason", DataType::Utf8),
+    ]
+}
+
+/// The columns that may be empty.
+fn is_nullable(name: &str) -> bool {
+    matches!(
+        name,
+        "tenant" | "currency" | "disputed_at" | "age_days" | "reason"
+    )
+}
+
+fn parquet_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+/// Writes any list of serializable values, such as `OutputRecord`s, as a
+/// Parquet file with a single row group. The columns are the fields of the
+/// first value, so an empty list makes a file without any columns.
+///
+/// Parquet cannot be written as a stream, so the file is put together in
+/// memory before it is written out.
+pub(crate) fn write_parquet<W, I>(mut writer: W, values: I) -> Result<(), Error>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    let rows = values
+        .into_iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<Value>, _>>()
+        .map_err(std::io::Error::from)?;
+    let fields: Vec<Field> = match rows.first() {
+        Some(Value::Object(row)) => columns()
+            .into_iter()
+            .filter(|(name, _)| row.contains_key(*name))
+            .map(|(name, data_type)| Field::new(name, data_type, is_nullable(name)))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut buf = Vec::new();
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut parquet =
+        ArrowWriter::try_new(&mut buf, schema.clone(), Some(properties)).map_err(parquet_error)?;
+    if !rows.is_empty() {
+        // Numeric transaction IDs are serialized as numbers.
+        let mut decoder = ReaderBuilder::new(schema)
+            .with_batch_size(rows.len())
+            .with_coerce_primitive(true)
+            .build_decoder()
+            .map_err(parquet_error)?;
+        decoder.serialize(&rows).map_err(parquet_error)?;
+        if let Some(batch) = decoder.flush().map_err(parquet_error)? {
+            parquet.write(&batch).map_err(parquet_error)?;
+        }
+    }
+    parquet.close().map_err(parquet_error)?;
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::amount::Amount;
+    use crate::currency::Currency;
+    use crate::id::ClientId;
+    use crate::output::{dump_result_to_writer, OutputFormat, OutputRecord};
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::Decimal128Type;
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_write_parquet() {
+        let values = vec![
+            OutputRecord {
+                client: ClientId::new(1),
+                currency: None,
+                tenant: None,
+                available: Amount::from_scaled(15_000),
+                held: Amount::ZERO,
+                total: Amount::from_scaled(15_000),
+                locked: false,
+            },
+            OutputRecord {
+                client: ClientId::new(2),
+                currency: Some("EUR".parse::<Currency>().unwrap()),
+                tenant: None,
+                available: Amount::from_scaled(-1),
+                held: Amount::from_scaled(2),
+                total: Amount::from_scaled(1),
+                locked: true,
+            },
+        ];
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, values, OutputFormat::Parquet).unwrap();
+
+        let mut batches = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(buf))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = batches.next().unwrap().unwrap();
+        let names: Vec<_> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(
+            names,
+            ["client", "currency", "available", "held", "total", "locked"]
+        );
+        assert!(batch.column(1).is_null(0));
+        let available = batch.column(2).as_primitive::<Decimal128Type>();
+        assert_eq!(available.value(0), 15_000);
+        assert_eq!(available.value(1), -1);
+        assert!(batch.column(5).as_boolean().value(1));
+        assert!(batches.next().is_none());
+    }
+
+    #[test]
+    fn test_write_empty_parquet() {
+        let mut buf = Vec::new();
+        dump_result_to_writer(&mut buf, Vec::new(), OutputFormat::Parquet).unwrap();
+        assert!(buf.starts_with(b"PAR1"));
+    }
+}
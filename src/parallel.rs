@@ -0,0 +1,257 @@
+use super::engine::{Applied, Engine, NegativeBalancePolicy, RejectReason, Rejected, Warned};
+use super::error::Error;
+use super::input::{InputRecord, Source};
+use super::metrics::Histogram;
+use super::output::RejectedRecord;
+use super::{log_applied, log_invalid, log_rejected, log_warned, ProcessOptions, Processed};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+use std::time::Instant;
+
+/// The number of records handed to a worker at once. Sending records one by
+/// one would make the channels the bottleneck.
+const BATCH_SIZE: usize = 1024;
+
+/// The number of batches that may be queued up for a single worker before
+/// the reader has to wait for it to catch up.
+const QUEUE_DEPTH: usize = 16;
+
+/// Everything a single worker produces: its `Engine` plus the records it
+/// rejected or warned about, how long applying them took if that was
+/// measured, and the error it stopped at, if any.
+type Shard = (
+    Engine,
+    Vec<Rejected>,
+    Vec<Warned>,
+    Vec<RejectedRecord>,
+    Option<Histogram>,
+    Option<Error>,
+);
+
+/// A batch of records for a single worker, along with where they came from.
+type Batch = Vec<(Source, InputRecord)>;
+
+/// Processes records on `ProcessOptions::threads` worker threads. Transactions of different
+/// clients never affect each other, so the records are sharded by client ID
+/// and every worker runs its own `Engine` over its shard. The records of a
+/// single client always end up on the same worker, in their original order,
+/// so the result is the same as that of a sequential run, with the caveats
+/// `ProcessOptions::relaxed_tx_checks` accepts, and two more:
+///
+/// * a transaction ID reused by a *different* client is not detected as a
+///   duplicate, as each shard only knows the IDs of its own clients,
+/// * for the same reason, a dispute of a transaction of a client in another
+///   shard is rejected as an unknown transaction rather than one of another
+///   client,
+/// * rejected records and warnings are grouped by shard rather than listed
+///   in input order, and
+/// * under `NegativeBalancePolicy::Abort`, only the worker that came across
+///   the record stops, while the others see their shards through, and the
+///   error of the earliest such record is returned.
+pub(crate) fn process_records_parallel<I>(
+    records: I,
+    engine: Engine,
+    options: &ProcessOptions,
+) -> Result<Processed, Error>
+where
+    I: IntoIterator<Item = (Source, Result<InputRecord, Error>)>,
+{
+    let threads = options.threads;
+    let config = engine.config().clone();
+    let abort = config.negative_balances == NegativeBalancePolicy::Abort;
+    // Every worker picks up the existing state of the clients it owns.
+    let shards = engine.split(threads);
+
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+        for mut engine in shards {
+            let (tx, rx) = sync_channel::<Batch>(QUEUE_DEPTH);
+            senders.push(tx);
+            workers.push(scope.spawn(move || {
+                let mut rejected = Vec::new();
+                let mut warnings = Vec::new();
+                let mut rejects = Vec::new();
+                let mut latency = options.measure_latency.then(Histogram::default);
+                let mut aborted = None;
+                'batches: for batch in rx {
+                    for (source, r) in batch {
+                        let started = latency.is_some().then(Instant::now);
+                        let applied = engine.apply(r.clone());
+                        if let (Some(latency), Some(started)) = (&mut latency, started) {
+                            latency.observe(started.elapsed());
+                        }
+                        match applied {
+                            Err(RejectReason::NegativeBalance) if abort => {
+                                aborted = Some(Error::NegativeBalance {
+                                    line: source.line,
+                                    client: r.client,
+                                    tx: r.tx,
+                                });
+                                // Hanging up makes the reader drop whatever
+                                // else comes along for this worker.
+                                break 'batches;
+                            }
+                            Ok(Applied { warning: None, .. }) => log_applied(&r),
+                            Ok(Applied {
+                                warning: Some(warning),
+                                ..
+                            }) => {
+                                log_warned(&r, &warning);
+                                warnings.push(Warned { record: r, warning });
+                            }
+                            Err(reason) => {
+                                log_rejected(&r, &reason, &source);
+                                rejects.push(RejectedRecord::new(source, &reason));
+                                rejected.push(Rejected { record: r, reason });
+                            }
+                        }
+                    }
+                }
+                (engine, rejected, warnings, rejects, latency, aborted)
+            }));
+        }
+
+        let mut res = Processed::new(Engine::with_config(config), options);
+        let read = dispatch(records, &senders, &mut res);
+        // Hanging up the channels tells the workers there is nothing left.
+        drop(senders);
+
+        let shards: Vec<Shard> = workers
+            .into_iter()
+            .map(|w| w.join().expect("worker thread panicked"))
+            .collect();
+        read?;
+
+        let mut aborted = Vec::new();
+        for (engine, rejected, warnings, rejects, latency, error) in shards {
+            aborted.extend(error);
+            if let (Some(total), Some(latency)) = (&mut res.latency, latency) {
+                total.merge(&latency);
+            }
+            res.engine.merge(engine);
+            res.rejected.extend(rejected);
+            res.warnings.extend(warnings);
+            res.rejects.extend(rejects);
+        }
+        match aborted.into_iter().min_by_key(Error::line) {
+            Some(error) => Err(error),
+            None => Ok(res),
+        }
+    })
+}
+
+/// Reads every record and sends it, in batches, to the worker owning its
+/// client. Records that could not be parsed are collected in `res`, unless
+/// the `ErrorMode` is strict.
+fn dispatch<I>(records: I, senders: &[SyncSender<Batch>], res: &mut Processed) -> Result<(), Error>
+where
+    I: IntoIterator<Item = (Source, Result<InputRecord, Error>)>,
+{
+    let mut batches: Vec<Batch> = vec![Vec::with_capacity(BATCH_SIZE); senders.len()];
+    for (source, record) in records {
+        res.records += 1;
+        match record {
+            Ok(r) => {
+                let shard = r.client.shard(senders.len());
+                batches[shard].push((source, r));
+                if batches[shard].len() == BATCH_SIZE {
+                    let batch =
+                        std::mem::replace(&mut batches[shard], Vec::with_capacity(BATCH_SIZE));
+                    // A worker only hangs up by panicking, which is
+                    // reported when it is joined.
+                    let _ = senders[shard].send(batch);
+                }
+            }
+            Err(e) if e.is_record_error() && !res.is_strict() => {
+                log_invalid(&e);
+                res.rejects.push(RejectedRecord::new(source, &e));
+                res.errors.push(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    for (sender, batch) in senders.iter().zip(batches) {
+        if !batch.is_empty() {
+            let _ = sender.send(batch);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::process_records_parallel;
+    use crate::engine::Engine;
+    use crate::input::{CsvReader, RecordSource};
+    use crate::{process_records, ProcessOptions};
+
+    fn sample_input() -> String {
+        let mut data = String::from("type,client,tx,amount\n");
+        for tx in 1..=5000u32 {
+            let client = tx % 37;
+            match tx % 7 {
+                0 => data.push_str(&format!("dispute,{},{},\n", client, tx.saturating_sub(37))),
+                1 => data.push_str(&format!("withdrawal,{},{},2.5\n", client, tx)),
+                3 => data.push_str(&format!("resolve,{},{},\n", client, tx.saturating_sub(74))),
+                5 => data.push_str(&format!(
+                    "chargeback,{},{},\n",
+                    client,
+                    tx.saturating_sub(37 * 4)
+                )),
+                _ => data.push_str(&format!("deposit,{},{},{}.125\n", client, tx, tx % 10)),
+            }
+        }
+        data.push_str("bogus,1,1,1\n");
+        data
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let data = sample_input();
+        let sequential = process_records(CsvReader::new(data.as_bytes()), Engine::new()).unwrap();
+        let options = ProcessOptions {
+            threads: 4,
+            measure_latency: true,
+            ..ProcessOptions::default()
+        };
+        let parallel = process_records_parallel(
+            CsvReader::new(data.as_bytes()).with_source(),
+            Engine::new(),
+            &options,
+        )
+        .unwrap();
+
+        let mut expected = sequential.engine.balances();
+        let mut actual = parallel.engine.balances();
+        expected.sort_by_key(|r| r.client);
+        actual.sort_by_key(|r| r.client);
+
+        assert_eq!(actual, expected);
+        assert_eq!(parallel.records, sequential.records);
+        assert_eq!(parallel.errors.len(), 1);
+        assert_eq!(parallel.rejected.len(), sequential.rejected.len());
+        assert_eq!(parallel.warnings.len(), sequential.warnings.len());
+        assert_eq!(parallel.rejects.len(), sequential.rejects.len());
+        // Every record but the invalid one was timed, whichever worker
+        // applied it.
+        assert_eq!(parallel.latency.unwrap().count(), 5000);
+        assert_eq!(sequential.latency, None);
+    }
+
+    #[test]
+    fn test_parallel_fatal_error() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\n\"unterminated";
+        let bytes = [data.as_bytes(), &[0xff, 0xfe]].concat();
+        let options = ProcessOptions {
+            threads: 2,
+            ..ProcessOptions::default()
+        };
+        let res = process_records_parallel(
+            CsvReader::new(&bytes[..]).with_source(),
+            Engine::new(),
+            &options,
+        );
+        assert!(res.is_err());
+    }
+}
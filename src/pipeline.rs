@@ -0,0 +1,330 @@
+use super::error::Error;
+#[cfg(feature = "mmap")]
+use super::input::mmap::{parse_chunk, Chunk};
+use super::input::{source_of, InputRecord, RowParser, Source};
+use super::ProcessOptions;
+use csv::StringRecord;
+use std::io::Read;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The number of rows handed to a parser at once. Sending rows one by one
+/// would make the channels the bottleneck.
+const BATCH_SIZE: usize = 1024;
+
+/// The number of batches that may be read ahead of the one being applied
+/// before the reader has to wait for the parsers and the engine to catch
+/// up. This bounds the memory the pipeline takes, however fast the input
+/// can be read.
+const QUEUE_DEPTH: usize = 16;
+
+/// The records of a batch, parsed, in the order they were read.
+type Parsed = Vec<(Source, Result<InputRecord, Error>)>;
+
+/// A row as read, or what went wrong reading it.
+type Row = Result<StringRecord, (Source, Error)>;
+
+/// A batch of rows for a parser, along with where to send it once parsed.
+struct Job {
+    rows: Vec<Row>,
+    parser: Arc<RowParser>,
+    done: SyncSender<Parsed>,
+}
+
+/// Reads CSV input in stages, each on threads of its own: one thread reads
+/// the rows, `parsers` threads turn them into records, and the calling
+/// thread hands every record to `apply`, in the order the rows were read.
+/// Reading and parsing thereby overlap with applying the records, which
+/// pays off on slow disks as much as on slow parsing.
+///
+/// The records come out exactly as a `CsvReader` would have produced them,
+/// so `apply` sees no difference with a sequential run. The channels
+/// between the stages are bounded, so a slow stage holds up the ones before
+/// it rather than letting batches pile up. An error returned by `apply`
+/// stops the pipeline and is returned.
+pub(crate) fn for_each_csv_record<R, F>(
+    reader: R,
+    options: &ProcessOptions,
+    parsers: usize,
+    mut apply: F,
+) -> Result<(), Error>
+where
+    R: Read + Send,
+    F: FnMut(Source, Result<InputRecord, Error>) -> Result<(), Error>,
+{
+    let (job_tx, job_rx) = sync_channel::<Job>(QUEUE_DEPTH);
+    let (order_tx, order_rx) = sync_channel::<Receiver<Parsed>>(QUEUE_DEPTH);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+
+    thread::scope(|scope| {
+        scope.spawn(move || read_rows(reader, options, job_tx, order_tx));
+        for _ in 0..parsers.max(1) {
+            let job_rx = Arc::clone(&job_rx);
+            scope.spawn(move || run_jobs(&job_rx, parse_rows));
+        }
+        drop(job_rx);
+        apply_in_order(order_rx, &mut apply)
+    })
+}
+
+/// Like `for_each_csv_record`, but for a CSV file mapped into memory and
+/// split into `chunks`, see `input::mmap`. There is no reading to be done,
+/// so the parsers take a chunk each and the calling thread applies the
+/// records chunk by chunk, in order.
+#[cfg(feature = "mmap")]
+pub(crate) fn for_each_chunk_record<F>(
+    data: &[u8],
+    chunks: Vec<Chunk>,
+    options: &ProcessOptions,
+    parsers: usize,
+    mut apply: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Source, Result<InputRecord, Error>) -> Result<(), Error>,
+{
+    let (job_tx, job_rx) = sync_channel::<(Chunk, SyncSender<Parsed>)>(QUEUE_DEPTH);
+    let (order_tx, order_rx) = sync_channel::<Receiver<Parsed>>(QUEUE_DEPTH);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for chunk in chunks {
+                let (done, parsed) = sync_channel(1);
+                if job_tx.send((chunk, done)).is_err() || order_tx.send(parsed).is_err() {
+                    return;
+                }
+            }
+        });
+        for _ in 0..parsers.max(1) {
+            let job_rx = Arc::clone(&job_rx);
+            scope.spawn(move || {
+                run_jobs(&job_rx, |(chunk, done)| {
+                    let _ = done.send(parse_chunk(data, &chunk, options));
+                })
+            });
+        }
+        drop(job_rx);
+        apply_in_order(order_rx, &mut apply)
+    })
+}
+
+/// Reads the rows in batches, hands every batch to the parsers and tells
+/// the engine which batch comes next. Stops once the input ends or the
+/// engine hangs up.
+fn read_rows<R: Read>(
+    reader: R,
+    options: &ProcessOptions,
+    jobs: SyncSender<Job>,
+    order: SyncSender<Receiver<Parsed>>,
+) {
+    let strict = options.strict_columns || !options.csv.has_headers;
+    let mut reader = options.csv.reader_builder().from_reader(reader);
+    let mut parser: Option<Arc<RowParser>> = None;
+    loop {
+        let mut rows = Vec::with_capacity(BATCH_SIZE);
+        let mut ended = false;
+        while rows.len() < BATCH_SIZE {
+            let mut s_record = StringRecord::new();
+            match reader.read_record(&mut s_record) {
+                Ok(true) => rows.push(Ok(s_record)),
+                Ok(false) => {
+                    ended = true;
+                    break;
+                }
+                Err(e) => rows.push(Err((source_of(&s_record), e.into()))),
+            }
+        }
+        if rows.is_empty() {
+            return;
+        }
+
+        // The header is only looked at once there is a row, just like a
+        // `CsvReader` does.
+        let (done, parsed) = sync_channel(1);
+        let parser = match &parser {
+            Some(parser) => Arc::clone(parser),
+            None => {
+                let names = match strict {
+                    true => Ok(None),
+                    false => reader.headers().map(|names| Some(names.clone())),
+                };
+                let created = names.map_err(Error::from).and_then(|names| {
+                    RowParser::new(
+                        names,
+                        options.aliases.clone(),
+                        options.columns.clone(),
+                        options.amounts(),
+                    )
+                });
+                match created {
+                    Ok(created) => parser.insert(Arc::new(created)).clone(),
+                    Err(e) => {
+                        // Nothing can be parsed without the columns, so
+                        // this is as far as the input goes.
+                        let source = match &rows[0] {
+                            Ok(s_record) => source_of(s_record),
+                            Err((source, _)) => source.clone(),
+                        };
+                        let _ = done.send(vec![(source, Err(e))]);
+                        let _ = order.send(parsed);
+                        return;
+                    }
+                }
+            }
+        };
+        let job = Job { rows, parser, done };
+        if jobs.send(job).is_err() || order.send(parsed).is_err() || ended {
+            return;
+        }
+    }
+}
+
+/// Runs jobs until whoever hands them out runs out of them.
+fn run_jobs<J>(jobs: &Mutex<Receiver<J>>, mut run: impl FnMut(J)) {
+    loop {
+        // The lock is only held while waiting for a job, not while running
+        // it.
+        let job = match jobs.lock() {
+            Ok(jobs) => jobs.recv(),
+            Err(_) => return,
+        };
+        let Ok(job) = job else {
+            return;
+        };
+        run(job);
+    }
+}
+
+/// Parses a batch of rows.
+fn parse_rows(job: Job) {
+    let Job { rows, parser, done } = job;
+    let parsed = rows
+        .into_iter()
+        .map(|row| match row {
+            Ok(s_record) => parser.parse(&s_record),
+            Err((source, e)) => (source, Err(e)),
+        })
+        .collect();
+    // The engine only hangs up when it stops, and so do the others.
+    let _ = done.send(parsed);
+}
+
+/// Applies the batches in the order they were read, waiting for each to be
+/// parsed in turn. Returning hangs up on the reader and the parsers, which
+/// makes them stop too.
+fn apply_in_order<F>(order: Receiver<Receiver<Parsed>>, apply: &mut F) -> Result<(), Error>
+where
+    F: FnMut(Source, Result<InputRecord, Error>) -> Result<(), Error>,
+{
+    for parsed in order {
+        // A parser only hangs up without sending by panicking, which is
+        // reported when the threads are joined.
+        let Ok(parsed) = parsed.recv() else {
+            break;
+        };
+        for (source, record) in parsed {
+            apply(source, record)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::for_each_csv_record;
+    use crate::error::{Column, Error, HeaderProblem};
+    use crate::input::{CsvReader, RecordSource, Source};
+    use crate::ProcessOptions;
+
+    fn sample_input() -> String {
+        let mut data = String::from("client,type,amount,tx\n");
+        for tx in 1..=5000u32 {
+            match tx % 11 {
+                0 => data.push_str(&format!("{},bogus,1.0,{}\n", tx % 37, tx)),
+                1 => data.push_str(&format!("{},withdrawal,2.5,{}\n", tx % 37, tx)),
+                2 => data.push_str(&format!("x,deposit,1.0,{}\n", tx)),
+                _ => data.push_str(&format!("{},deposit,{}.125,{}\n", tx % 37, tx % 10, tx)),
+            }
+        }
+        data
+    }
+
+    /// Runs the pipeline over `data`, describing every record as text so
+    /// errors can be compared too.
+    fn pipelined(
+        data: &str,
+        options: &ProcessOptions,
+        parsers: usize,
+    ) -> Result<Vec<(Source, String)>, Error> {
+        let mut records = Vec::new();
+        for_each_csv_record(data.as_bytes(), options, parsers, |source, record| {
+            records.push((source, format!("{:?}", record)));
+            Ok(())
+        })?;
+        Ok(records)
+    }
+
+    #[test]
+    fn test_pipeline_matches_csv_reader() {
+        let data = sample_input();
+        let expected: Vec<_> = CsvReader::new(data.as_bytes())
+            .with_source()
+            .map(|(source, record)| (source, format!("{:?}", record)))
+            .collect();
+        assert_eq!(expected.len(), 5000);
+        for parsers in [1, 4] {
+            let actual = pipelined(&data, &ProcessOptions::default(), parsers).unwrap();
+            assert_eq!(actual, expected);
+        }
+
+        // By position, the header is just an invalid row.
+        let options = ProcessOptions {
+            strict_columns: true,
+            ..ProcessOptions::default()
+        };
+        let expected: Vec<_> = CsvReader::strict(data.as_bytes())
+            .with_source()
+            .map(|(source, record)| (source, format!("{:?}", record)))
+            .collect();
+        assert_eq!(pipelined(&data, &options, 3).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_pipeline_missing_column() {
+        let data = "type,client,amount\ndeposit,1,1.0\ndeposit,1,2.0\n";
+        let records = pipelined(data, &ProcessOptions::default(), 2).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].1,
+            format!(
+                "{:?}",
+                Err::<(), _>(Error::InvalidHeader {
+                    expected: ["type", "client", "tx", "amount"]
+                        .map(String::from)
+                        .to_vec(),
+                    found: ["type", "client", "amount"].map(String::from).to_vec(),
+                    problem: HeaderProblem::MissingColumn(Column::Tx),
+                })
+            )
+        );
+        assert!(pipelined("", &ProcessOptions::default(), 2)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_stops_on_error() {
+        let data = sample_input();
+        let mut applied = 0;
+        let res = for_each_csv_record(data.as_bytes(), &ProcessOptions::default(), 4, |_, _| {
+            applied += 1;
+            match applied {
+                2000 => Err(std::io::Error::other("disk full").into()),
+                _ => Ok(()),
+            }
+        });
+        assert!(res.is_err());
+        assert_eq!(applied, 2000);
+    }
+}
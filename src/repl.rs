@@ -0,0 +1,326 @@
+use super::engine::{Applied, Engine};
+use super::error::Error;
+use super::id::{ClientId, TxId};
+use super::input::{make_input_record, InputFile, InputRecord};
+use super::output::{dump_balances_to_path, OutputFormat, OutputRecord, OutputSchema};
+use super::{read_records, ProcessOptions, Processed};
+use csv::StringRecord;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What `help` prints.
+const HELP: &str = "\
+load <file>                          Process a file, on top of what was loaded before
+balance <client>                     Show the balances of a client
+tx <tx>                              Show a deposit or withdrawal, and whether it is disputed
+apply <type> <client> <tx> [amount]  Apply a single record, such as apply dispute 42 1001
+export <file>                        Write the balances of every client to a CSV file
+help                                 Show this list
+quit                                 Leave";
+
+/// A command of the REPL, one per line, see `Repl`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Load(PathBuf),
+    Balance(ClientId),
+    Tx(TxId),
+    Apply(InputRecord),
+    Export(PathBuf),
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or_default().to_lowercase();
+        let args: Vec<&str> = words.collect();
+        let command = match (name.as_str(), args.as_slice()) {
+            ("load", [path]) => Command::Load(PathBuf::from(path)),
+            ("balance", [client]) => Command::Balance(
+                client
+                    .parse()
+                    .map_err(|_| format!("invalid client '{}'", client))?,
+            ),
+            ("tx", [tx]) => Command::Tx(tx.parse().map_err(|_| format!("invalid tx '{}'", tx))?),
+            // The fields go in the order of the columns of a CSV row, so the
+            // record is parsed just like one.
+            ("apply", [_, _, _, ..]) => {
+                let mut fields = args.clone();
+                fields.resize(fields.len().max(4), "");
+                let record =
+                    make_input_record(&StringRecord::from(fields)).map_err(|e| e.to_string())?;
+                Command::Apply(record)
+            }
+            ("export", [path]) => Command::Export(PathBuf::from(path)),
+            ("help", []) => Command::Help,
+            ("quit" | "exit", []) => Command::Quit,
+            ("load" | "balance" | "tx" | "apply" | "export" | "help" | "quit" | "exit", _) => {
+                return Err(format!("wrong arguments for {}, see help", name))
+            }
+            _ => return Err(format!("unknown command '{}', see help", name)),
+        };
+        Ok(command)
+    }
+}
+
+/// An interactive session on an `Engine`, for an operator to look into a
+/// case, such as a disputed transaction, by loading files, looking at
+/// balances and transactions, and applying records by hand to see what
+/// they do. Files are read and records applied just like `process_*` does
+/// with the same `ProcessOptions`, so the engine ends up the same.
+pub struct Repl {
+    engine: Engine,
+    options: ProcessOptions,
+}
+
+impl Repl {
+    /// Starts a session on `engine`, which may already hold some state,
+    /// reading files as `options` ask for.
+    pub fn new(engine: Engine, options: ProcessOptions) -> Self {
+        Repl { engine, options }
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Ends the session, handing back the engine with whatever was loaded
+    /// and applied.
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+
+    /// Runs the commands read from `input`, one per line, until it ends or
+    /// one of them is `quit`, writing what they have to say to `output`. If
+    /// `prompt` is set, a prompt is written before every line. A command
+    /// that fails, such as a `load` of a missing file, only writes out its
+    /// error, so only failing to write ends the session early.
+    pub fn run<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+        prompt: bool,
+    ) -> Result<(), Error> {
+        let mut lines = input.lines();
+        loop {
+            if prompt {
+                write!(output, "> ")?;
+                output.flush()?;
+            }
+            let Some(line) = lines.next().transpose()? else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = match line.parse::<Command>() {
+                Ok(Command::Quit) => break,
+                Ok(command) => self.execute(command, &mut output),
+                Err(e) => Ok(writeln!(output, "{}", e)?),
+            };
+            if let Err(e) = result {
+                writeln!(output, "{}", e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a single command, writing what it has to say to `output`.
+    pub fn execute<W: Write>(&mut self, command: Command, output: &mut W) -> Result<(), Error> {
+        match command {
+            Command::Load(path) => {
+                let processed = self.load(&path)?;
+                writeln!(output, "{}", processed.summary())?;
+            }
+            Command::Balance(client) => {
+                let balances = self.engine.client_balances(client);
+                if balances.is_empty() {
+                    writeln!(output, "client {} has no account", client)?;
+                }
+                for balance in balances {
+                    writeln!(output, "{}", Balance(&balance))?;
+                }
+            }
+            Command::Tx(tx) => match self.engine.ledger().lookup_tx(tx, None) {
+                Some(t) => {
+                    write!(
+                        output,
+                        "tx {}: {} of {} by client {}",
+                        tx,
+                        t.kind(),
+                        t.amount(),
+                        t.client()
+                    )?;
+                    if let Some(currency) = t.currency() {
+                        write!(output, " in {}", currency)?;
+                    }
+                    write!(output, ", {}", t.state().name())?;
+                    if let Some(at) = t.disputed_at() {
+                        write!(output, " since {}", at.to_rfc3339())?;
+                    }
+                    writeln!(output)?;
+                }
+                None => writeln!(output, "tx {} is not known", tx)?,
+            },
+            Command::Apply(record) => match self.engine.apply(record) {
+                Ok(Applied { balance, warning }) => {
+                    writeln!(output, "applied, {}", Balance(&balance))?;
+                    if let Some(warning) = warning {
+                        writeln!(output, "warning: {}", warning)?;
+                    }
+                }
+                Err(reason) => writeln!(output, "rejected: {}", reason)?,
+            },
+            Command::Export(path) => {
+                dump_balances_to_path(
+                    &path,
+                    &self.engine,
+                    &OutputSchema::default(),
+                    OutputFormat::Csv,
+                )?;
+                writeln!(
+                    output,
+                    "wrote {} accounts to {}",
+                    self.engine.balances().len(),
+                    path.display()
+                )?;
+            }
+            Command::Help => writeln!(output, "{}", HELP)?,
+            Command::Quit => (),
+        }
+        Ok(())
+    }
+
+    /// Processes the file at `path` into the engine. Should the file turn
+    /// out to be unreadable halfway, the records before that stay applied,
+    /// just like they would in a run.
+    fn load(&mut self, path: &Path) -> Result<Processed, Error> {
+        let file = InputFile::open(path).map_err(|e| Error::Io(e).in_file(path))?;
+        let engine = std::mem::take(&mut self.engine);
+        let mut processed = Processed::new(engine, &self.options);
+        let result = read_records(file, &self.options).try_for_each(|(source, record)| {
+            processed.push(source, record.map_err(|e| e.in_file(path)))
+        });
+        self.engine = std::mem::take(&mut processed.engine);
+        result.map(|_| processed)
+    }
+}
+
+/// The balances of an account, as shown by `balance` and `apply`.
+struct Balance<'a>(&'a OutputRecord);
+
+impl fmt::Display for Balance<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = self.0;
+        write!(f, "client {}", b.client)?;
+        if let Some(currency) = b.currency {
+            write!(f, " in {}", currency)?;
+        }
+        write!(
+            f,
+            ": available {}, held {}, total {}",
+            b.available, b.held, b.total
+        )?;
+        if b.locked {
+            f.write_str(", locked")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Command, Repl};
+    use crate::engine::Engine;
+    use crate::id::{ClientId, TxId};
+    use crate::ProcessOptions;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            "balance 42".parse::<Command>(),
+            Ok(Command::Balance(ClientId::new(42)))
+        );
+        assert_eq!(
+            "TX 1001".parse::<Command>(),
+            Ok(Command::Tx(TxId::new(1001)))
+        );
+        match "apply deposit 42 1001 10.5".parse::<Command>() {
+            Ok(Command::Apply(record)) => {
+                assert_eq!(
+                    (record.client, record.tx),
+                    (ClientId::new(42), TxId::new(1001))
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            "apply dispute 42 1001".parse::<Command>(),
+            Ok(Command::Apply(_))
+        ));
+        assert!("apply deposit 42 1001".parse::<Command>().is_err());
+        assert!("apply bogus 42 1001 1".parse::<Command>().is_err());
+        assert!("balance".parse::<Command>().is_err());
+        assert!("balance x".parse::<Command>().is_err());
+        assert!("bogus".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn test_repl() {
+        let dir = std::env::temp_dir().join(format!("payments-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.csv");
+        let mut file = std::fs::File::create(&input).unwrap();
+        writeln!(
+            file,
+            "type,client,tx,amount\ndeposit,42,1001,10\nwithdrawal,42,1002,4"
+        )
+        .unwrap();
+        let export = dir.join("out.csv");
+
+        let commands = format!(
+            "load {}\nbalance 42\n\napply dispute 42 1001\ntx 1001\napply resolve 42 9\n\
+             balance 7\nbogus\nload {}\nexport {}\nquit\nbalance 42\n",
+            input.display(),
+            dir.join("missing.csv").display(),
+            export.display()
+        );
+        let mut repl = Repl::new(Engine::new(), ProcessOptions::default());
+        let mut output = Vec::new();
+        repl.run(commands.as_bytes(), &mut output, false).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 10, "{}", output);
+        assert_eq!(
+            lines[0],
+            "2 records read, 0 skipped (0 invalid, 0 rejected), 0 with warnings"
+        );
+        assert_eq!(lines[1], "client 42: available 6.0, held 0.0, total 6.0");
+        assert_eq!(
+            lines[2],
+            "applied, client 42: available -4.0, held 10.0, total 6.0"
+        );
+        assert!(lines[3].starts_with("warning: "));
+        assert_eq!(lines[4], "tx 1001: deposit of 10.0 by client 42, disputed");
+        assert_eq!(lines[5], "rejected: unknown transaction");
+        assert_eq!(lines[6], "client 7 has no account");
+        assert!(lines[7].starts_with("unknown command"));
+        assert!(lines[8].contains("missing.csv"));
+        assert_eq!(
+            lines[9],
+            format!("wrote 1 accounts to {}", export.display())
+        );
+        assert_eq!(
+            std::fs::read_to_string(&export).unwrap(),
+            "client,available,held,total,locked\n42,-4.0,10.0,6.0,false\n"
+        );
+        assert_eq!(repl.engine().balances().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use super::engine::Engine;
+use super::error::Error;
+use super::id::ClientId;
+use super::input::{parse_message, Source, TransactionTypeAliases};
+use super::output::{dump_result_to_writer, OutputFormat, OutputRecord};
+use super::{apply_logged, log_invalid};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::net::TcpListener;
+use tracing::info;
+
+/// What every request handler gets to see. The engine is shared by all of
+/// them and only ever locked for the duration of a single transaction or
+/// query, never across an `.await`.
+#[derive(Clone)]
+struct AppState {
+    engine: Arc<Mutex<Engine>>,
+    aliases: Arc<TransactionTypeAliases>,
+}
+
+impl AppState {
+    fn engine(&self) -> MutexGuard<'_, Engine> {
+        // Applying a record either completes or changes nothing, so the
+        // engine is consistent even if another handler panicked.
+        self.engine.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The HTTP API in front of `engine`:
+///
+/// * `POST /transactions` applies a single transaction, given as a JSON
+///   object or a CSV row without a header, see `input::parse_message`, and
+///   responds with the balances of the account afterwards. A transaction
+///   that cannot be parsed gets a 400, one that is rejected a 422.
+/// * `GET /accounts/{client}` responds with the balances of a client, one
+///   for every currency, or a 404 for an unknown client.
+/// * `GET /report` responds with the balances of every client, as CSV by
+///   default or as `?format=json` or `?format=jsonl`.
+///
+/// Errors come as a JSON object with an `error` message.
+pub fn router(engine: Arc<Mutex<Engine>>, aliases: TransactionTypeAliases) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts/{client}", get(get_account))
+        .route("/report", get(get_report))
+        .with_state(AppState {
+            engine,
+            aliases: Arc::new(aliases),
+        })
+}
+
+/// Serves the API of `router` on `listener` until `shutdown` completes.
+/// Requests still in flight are finished first, so once this returns the
+/// engine is no longer touched and its state can be saved.
+pub async fn serve<F>(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine>>,
+    aliases: TransactionTypeAliases,
+    shutdown: F,
+) -> Result<(), Error>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    info!(address = %listener.local_addr()?, "serving the HTTP API");
+    axum::serve(listener, router(engine, aliases))
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+fn error_response(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn balance_json(balance: &OutputRecord) -> Value {
+    json!({
+        "client": balance.client,
+        "currency": balance.currency,
+        "available": balance.available,
+        "held": balance.held,
+        "total": balance.total,
+        "locked": balance.locked,
+    })
+}
+
+async fn submit_transaction(State(state): State<AppState>, body: String) -> Response {
+    let record = match parse_message(&body, &state.aliases) {
+        Ok(record) => record,
+        Err(e) => {
+            log_invalid(&e);
+            return error_response(StatusCode::BAD_REQUEST, e.to_string());
+        }
+    };
+    let source = Source {
+        line: None,
+        raw: body,
+    };
+    let res = apply_logged(&mut state.engine(), record, &source);
+    match res {
+        Ok(applied) => {
+            let mut response = balance_json(&applied.balance);
+            response["warning"] = json!(applied.warning.map(|w| w.to_string()));
+            Json(response).into_response()
+        }
+        Err(reason) => error_response(StatusCode::UNPROCESSABLE_ENTITY, reason.to_string()),
+    }
+}
+
+async fn get_account(State(state): State<AppState>, Path(client): Path<ClientId>) -> Response {
+    let balances = state.engine().client_balances(client);
+    if balances.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, "unknown client".to_string());
+    }
+    Json(Value::Array(balances.iter().map(balance_json).collect())).into_response()
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    format: Option<String>,
+}
+
+async fn get_report(State(state): State<AppState>, Query(query): Query<ReportQuery>) -> Response {
+    let format = match query.format.as_deref().map(str::parse).transpose() {
+        Ok(format) => format.unwrap_or_default(),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let balances = state.engine().balances();
+    let mut body = Vec::new();
+    if let Err(e) = dump_result_to_writer(&mut body, balances, format) {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    let content_type = match format {
+        OutputFormat::Csv => "text/csv",
+        OutputFormat::Json => "application/json",
+        OutputFormat::JsonLines => "application/x-ndjson",
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => "application/vnd.apache.parquet",
+    };
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{get_account, get_report, submit_transaction, AppState, ReportQuery};
+    use crate::engine::Engine;
+    use crate::id::ClientId;
+    use crate::input::TransactionTypeAliases;
+    use axum::body::to_bytes;
+    use axum::extract::{Path, Query, State};
+    use axum::http::StatusCode;
+    use axum::response::Response;
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn body(response: Response) -> String {
+        let bytes = block_on(to_bytes(response.into_body(), usize::MAX)).unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_api() {
+        let state = AppState {
+            engine: Arc::new(Mutex::new(Engine::new())),
+            aliases: Arc::new(TransactionTypeAliases::new()),
+        };
+        let submit = |body: &str| block_on(submit_transaction(State(state.clone()), body.into()));
+
+        let response = submit(r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body(response),
+            r#"{"available":"2.5","client":1,"currency":null,"held":"0.0","locked":false,"total":"2.5","warning":null}"#
+        );
+        assert_eq!(
+            submit("withdrawal,1,2,10").status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(submit("withdrawal,1").status(), StatusCode::BAD_REQUEST);
+
+        let response = block_on(get_account(State(state.clone()), Path(ClientId::new(1))));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(response).contains(r#""available":"2.5""#));
+        let response = block_on(get_account(State(state.clone()), Path(ClientId::new(2))));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let report = |format: Option<&str>| {
+            let query = ReportQuery {
+                format: format.map(str::to_string),
+            };
+            block_on(get_report(State(state.clone()), Query(query)))
+        };
+        assert_eq!(
+            body(report(None)),
+            "client,available,held,total,locked\n1,2.5,0.0,2.5,false\n"
+        );
+        assert!(body(report(Some("jsonl"))).starts_with(r#"{"client":1,"#));
+        assert_eq!(report(Some("xml")).status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,300 @@
+use super::engine::{Applied, Engine, EngineConfig, RejectReason, StatementLine};
+use super::id::ClientId;
+use super::input::InputRecord;
+use super::output::OutputRecord;
+use super::tenant::Tenant;
+use chrono::{DateTime, Utc};
+use std::sync::{Mutex, MutexGuard};
+
+/// A `SharedEngine` is an `Engine` that many threads can apply records to
+/// at once, through a shared reference, e.g. from behind an `Arc`.
+///
+/// By default, the whole engine is behind a single lock, so records are
+/// applied one after the other, in the order their `apply` calls took it,
+/// with exactly the outcome of a plain `Engine`. Built with
+/// `with_relaxed_tx_checks`, the engine is split into shards by client ID
+/// instead, each behind a lock of its own, just like a parallel run.
+/// Applying a record then only locks the shard of its client, so records of
+/// clients in different shards are applied side by side, while the records
+/// of a single client are still applied one after the other. That comes with
+/// three caveats, as for a parallel run:
+///
+/// * a transaction ID reused by a client of another shard is not detected
+///   as a duplicate,
+/// * a dispute of a transaction of a client in another shard is rejected as
+///   an unknown transaction rather than one of another client, and
+/// * queries that span clients, such as `balances`, lock one shard after the
+///   other, so they are consistent for every client but not a snapshot of
+///   all clients at a single point in time.
+#[derive(Debug)]
+pub struct SharedEngine {
+    config: EngineConfig,
+    shards: Vec<Mutex<Engine>>,
+}
+
+impl SharedEngine {
+    /// Shares `engine`, along with the state it already holds, behind a
+    /// single lock.
+    pub fn new(engine: Engine) -> Self {
+        SharedEngine::with_relaxed_tx_checks(engine, 1)
+    }
+
+    /// Shares a new `Engine` that applies the policies in `config`.
+    pub fn with_config(config: EngineConfig) -> Self {
+        SharedEngine::new(Engine::with_config(config))
+    }
+
+    /// Shares `engine`, along with the state it already holds, split into
+    /// `shards` shards, accepting the caveats above. More shards let more
+    /// clients be served at once, at the cost of some memory per shard. At
+    /// least one shard is used, and a single one has no caveats.
+    pub fn with_relaxed_tx_checks(engine: Engine, shards: usize) -> Self {
+        SharedEngine {
+            config: engine.config().clone(),
+            shards: engine
+                .split(shards.max(1))
+                .into_iter()
+                .map(Mutex::new)
+                .collect(),
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    fn shard(&self, client: ClientId) -> MutexGuard<'_, Engine> {
+        // Applying a record either completes or changes nothing, so a shard
+        // is consistent even if another thread panicked while holding it.
+        self.shards[client.shard(self.shards.len())]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies a single record, like `Engine::apply`, only locking the shard
+    /// of its client.
+    pub fn apply(&self, record: InputRecord) -> Result<Applied, RejectReason> {
+        self.shard(record.client).apply(record)
+    }
+
+    /// The current balances of a single client, like
+    /// `Engine::client_balances`.
+    pub fn client_balances(&self, client: ClientId) -> Vec<OutputRecord> {
+        self.shard(client).client_balances(client)
+    }
+
+    /// The statement of a single client, like `Engine::statement`.
+    pub fn statement(&self, client: ClientId, tenant: Option<Tenant>) -> Vec<StatementLine> {
+        self.shard(client).statement(client, tenant).to_vec()
+    }
+
+    /// The current balances of every client, like `Engine::balances`.
+    pub fn balances(&self) -> Vec<OutputRecord> {
+        self.each_shard(|engine| engine.balances())
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The number of disputes still open.
+    pub fn open_disputes(&self) -> usize {
+        self.each_shard(|engine| engine.open_disputes())
+            .into_iter()
+            .sum()
+    }
+
+    /// Closes the disputes of every shard that expired by `now`, like
+    /// `Engine::expire_disputes`. As a record only locks the shard of its
+    /// client, it only expires the disputes of that shard when it is applied.
+    pub fn expire_disputes(&self, now: DateTime<Utc>) -> usize {
+        self.each_shard(|engine| engine.expire_disputes(now))
+            .into_iter()
+            .sum()
+    }
+
+    fn each_shard<T>(&self, f: impl Fn(&mut Engine) -> T) -> Vec<T> {
+        self.shards
+            .iter()
+            .map(|shard| f(&mut shard.lock().unwrap_or_else(|e| e.into_inner())))
+            .collect()
+    }
+
+    /// Puts the shards back together into a single `Engine`, e.g. to write a
+    /// snapshot of it once no other thread uses the engine any more.
+    pub fn into_engine(self) -> Engine {
+        let mut shards = self
+            .shards
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(|e| e.into_inner()));
+        let mut engine = shards.next().unwrap_or_default();
+        for shard in shards {
+            engine.merge(shard);
+        }
+        engine
+    }
+}
+
+impl From<Engine> for SharedEngine {
+    fn from(engine: Engine) -> Self {
+        SharedEngine::new(engine)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::SharedEngine;
+    use crate::amount::Amount;
+    use crate::engine::{Engine, EngineConfig, OverdraftPolicy, RejectReason};
+    use crate::id::{ClientId, TxId};
+    use crate::input::{InputRecord, TransactionType};
+    use crate::output::OutputRecord;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    fn record(r#type: TransactionType, client: u64, tx: u64, amount: Option<i64>) -> InputRecord {
+        InputRecord {
+            r#type,
+            client: ClientId::new(client),
+            tx: TxId::new(tx),
+            amount: amount.map(Amount::from_scaled),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            tenant: None,
+        }
+    }
+
+    fn sorted(mut balances: Vec<OutputRecord>) -> Vec<OutputRecord> {
+        balances.sort_by_key(|b| (b.client, b.currency));
+        balances
+    }
+
+    #[test]
+    fn test_concurrent_clients_match_sequential_run() {
+        // Every thread owns a few clients, and deposits, withdraws and
+        // disputes on their behalf, four records per client at a time.
+        let records = |thread: u64| -> Vec<InputRecord> {
+            let mut records = Vec::new();
+            for n in 0..200 {
+                let client = thread * 10 + n / 4 % 10;
+                let tx = thread * 1_000 + n;
+                records.push(match n % 4 {
+                    0 | 1 => record(TransactionType::Deposit, client, tx, Some(10_000)),
+                    2 => record(TransactionType::Withdrawal, client, tx, Some(5_000)),
+                    _ => record(TransactionType::Dispute, client, tx - 3, None),
+                });
+            }
+            records
+        };
+
+        let shared = SharedEngine::with_relaxed_tx_checks(Engine::new(), 4);
+        thread::scope(|scope| {
+            for thread in 0..8 {
+                let shared = &shared;
+                scope.spawn(move || {
+                    for r in records(thread) {
+                        let _ = shared.apply(r);
+                    }
+                });
+            }
+        });
+
+        let mut engine = Engine::new();
+        for thread in 0..8 {
+            for r in records(thread) {
+                let _ = engine.apply(r);
+            }
+        }
+        assert_eq!(sorted(shared.balances()), sorted(engine.balances()));
+        assert_eq!(shared.open_disputes(), engine.open_disputes());
+        let merged = shared.into_engine();
+        assert_eq!(sorted(merged.balances()), sorted(engine.balances()));
+    }
+
+    #[test]
+    fn test_concurrent_withdrawals_never_overdraw() {
+        let engine = Engine::with_config(EngineConfig {
+            overdraft: OverdraftPolicy::Reject,
+            ..EngineConfig::default()
+        });
+        let shared = SharedEngine::with_relaxed_tx_checks(engine, 2);
+        shared
+            .apply(record(TransactionType::Deposit, 1, 0, Some(1_000_000)))
+            .unwrap();
+        // 100 withdrawals of 1 race for 100, and only 100 can succeed.
+        let succeeded = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for thread in 0..8u64 {
+                let (shared, succeeded) = (&shared, &succeeded);
+                scope.spawn(move || {
+                    for n in 0..25 {
+                        let r = record(
+                            TransactionType::Withdrawal,
+                            1,
+                            1 + thread * 25 + n,
+                            Some(10_000),
+                        );
+                        match shared.apply(r) {
+                            Ok(_) => {
+                                succeeded.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(reason) => assert_eq!(reason, RejectReason::InsufficientFunds),
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(succeeded.load(Ordering::Relaxed), 100);
+        let balances = shared.client_balances(ClientId::new(1));
+        assert_eq!(balances[0].available, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_shares_existing_state() {
+        let mut engine = Engine::new();
+        for client in 1..=3 {
+            engine
+                .apply(record(
+                    TransactionType::Deposit,
+                    client,
+                    client,
+                    Some(10_000),
+                ))
+                .unwrap();
+        }
+        let shared = SharedEngine::with_relaxed_tx_checks(engine, 2);
+        shared
+            .apply(record(TransactionType::Dispute, 2, 2, None))
+            .unwrap();
+        assert_eq!(shared.balances().len(), 3);
+        assert_eq!(
+            shared.client_balances(ClientId::new(2))[0].held,
+            Amount::from_scaled(10_000)
+        );
+        assert!(shared.client_balances(ClientId::new(4)).is_empty());
+    }
+
+    #[test]
+    fn test_only_relaxed_tx_checks_miss_duplicates_of_other_shards() {
+        // Clients 1 and 2 are in different shards.
+        let deposits = || {
+            [
+                record(TransactionType::Deposit, 1, 1, Some(10_000)),
+                record(TransactionType::Deposit, 2, 1, Some(10_000)),
+            ]
+        };
+        let shared = SharedEngine::new(Engine::new());
+        let [first, second] = deposits();
+        shared.apply(first).unwrap();
+        assert_eq!(
+            shared.apply(second).unwrap_err(),
+            RejectReason::DuplicateTransaction
+        );
+
+        let shared = SharedEngine::with_relaxed_tx_checks(Engine::new(), 2);
+        for r in deposits() {
+            shared.apply(r).unwrap();
+        }
+        assert_eq!(shared.balances().len(), 2);
+    }
+}
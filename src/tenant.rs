@@ -0,0 +1,102 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// The longest name a `Tenant` can have.
+pub const MAX_TENANT_LEN: usize = 16;
+
+/// A `Tenant` is a business unit that shares a run with others, such as
+/// `eu` or `retail-us`, whose client and transaction IDs may well overlap
+/// with theirs. Kept apart, see `EngineConfig::tenants`, every tenant has
+/// accounts and transactions of its own, so client 1 of `eu` and client 1
+/// of `us` are two different clients.
+///
+/// Names are up to `MAX_TENANT_LEN` ASCII letters, digits, `-` and `_`, and
+/// are matched as written, so `EU` and `eu` are two tenants. Like a
+/// `Currency`, a `Tenant` is held inline and can be copied around freely.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tenant([u8; MAX_TENANT_LEN], u8);
+
+/// Returned when a string is not a valid tenant name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTenantError(String);
+
+impl fmt::Display for ParseTenantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid tenant '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseTenantError {}
+
+impl Tenant {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII ever makes it in, see `from_str`.
+        std::str::from_utf8(&self.0[..usize::from(self.1)]).unwrap()
+    }
+}
+
+impl FromStr for Tenant {
+    type Err = ParseTenantError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = |b: u8| b.is_ascii_alphanumeric() || b == b'-' || b == b'_';
+        if s.is_empty() || s.len() > MAX_TENANT_LEN || !s.bytes().all(valid) {
+            return Err(ParseTenantError(s.to_string()));
+        }
+        let mut bytes = [0; MAX_TENANT_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Tenant(bytes, s.len() as u8))
+    }
+}
+
+impl fmt::Display for Tenant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Tenant {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Tenant {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct TenantVisitor;
+
+        impl Visitor<'_> for TenantVisitor {
+            type Value = Tenant;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a tenant name")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Tenant, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        d.deserialize_str(TenantVisitor)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Tenant;
+
+    #[test]
+    fn test_parse_tenant() {
+        let tenant: Tenant = "retail-us_2".parse().unwrap();
+        assert_eq!(tenant.to_string(), "retail-us_2");
+        assert_ne!("EU".parse::<Tenant>(), "eu".parse::<Tenant>());
+        assert!("".parse::<Tenant>().is_err());
+        assert!("eu west".parse::<Tenant>().is_err());
+        assert!("a-very-long-tenant-name".parse::<Tenant>().is_err());
+        let tenants: Vec<Tenant> = serde_json::from_str(r#"["us","eu"]"#).unwrap();
+        assert!(tenants[1] < tenants[0]);
+        assert_eq!(serde_json::to_string(&tenants).unwrap(), r#"["us","eu"]"#);
+    }
+}
@@ -0,0 +1,243 @@
+use super::amount::Amount;
+use super::engine::RejectReason;
+use super::id::ClientId;
+use super::input::{InputRecord, TransactionType};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A `Validator` decides whether the `Engine` takes a record at all, before
+/// any of its policies are looked at, so a deployment can hold its input to
+/// stricter rules than the readers in `input` do without changing them.
+/// Validators are added with `Engine::add_validator`, after the `Rules` of
+/// the configuration, and the first one to refuse a record rejects it.
+///
+/// A validator only sees the record, not the state of the engine, so the
+/// outcome is the same wherever the record comes from.
+pub trait Validator: Send + Sync {
+    fn validate(&self, record: &InputRecord) -> Result<(), RejectReason>;
+}
+
+/// Any function with the right signature is a validator, just like a
+/// `TransactionHandler`.
+impl<F> Validator for F
+where
+    F: Fn(&InputRecord) -> Result<(), RejectReason> + Send + Sync,
+{
+    fn validate(&self, record: &InputRecord) -> Result<(), RejectReason> {
+        self(record)
+    }
+}
+
+/// One of the built-in rules, see `Rules`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Only records of these types are taken.
+    Types(Vec<TransactionType>),
+    /// Only clients from `min` up to and including `max` are taken.
+    Clients {
+        min: Option<ClientId>,
+        max: Option<ClientId>,
+    },
+    /// Amounts have to be from `min` up to and including `max`, for records
+    /// of the given type, or of any type if none is given. Records without
+    /// an amount are left alone.
+    Amount {
+        r#type: Option<TransactionType>,
+        min: Option<Amount>,
+        max: Option<Amount>,
+    },
+}
+
+impl Validator for Rule {
+    fn validate(&self, record: &InputRecord) -> Result<(), RejectReason> {
+        match self {
+            Rule::Types(types) if !types.contains(&record.r#type) => {
+                Err(RejectReason::TypeNotAllowed)
+            }
+            Rule::Clients { min, max }
+                if min.is_some_and(|min| record.client < min)
+                    || max.is_some_and(|max| record.client > max) =>
+            {
+                Err(RejectReason::ClientOutOfRange)
+            }
+            Rule::Amount { r#type, min, max } => {
+                let (Some(amount), true) =
+                    (record.amount, r#type.is_none_or(|t| t == record.r#type))
+                else {
+                    return Ok(());
+                };
+                if min.is_some_and(|min| amount < min) || max.is_some_and(|max| amount > max) {
+                    return Err(RejectReason::AmountOutOfRange);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The chain of built-in rules the `Engine` holds every record to, in
+/// order, see `EngineConfig::rules`. None by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rules(pub Vec<Rule>);
+
+impl Validator for Rules {
+    fn validate(&self, record: &InputRecord) -> Result<(), RejectReason> {
+        self.0.iter().try_for_each(|rule| rule.validate(record))
+    }
+}
+
+/// Rules are given as a comma-separated list, e.g.
+/// `types=deposit+withdrawal,clients=1..5000,amount:withdrawal=..1000`.
+/// A range may leave out either end, and holds both of them.
+impl FromStr for Rules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for rule in s.split(',') {
+            let (name, value) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule '{}', expected <name>=<value>", rule))?;
+            let name = name.trim().to_lowercase();
+            let value = value.trim();
+            let (name, r#type) = match name.split_once(':') {
+                Some((name, r#type)) => (name, Some(r#type.parse::<TransactionType>()?)),
+                None => (name.as_str(), None),
+            };
+            let rule = match (name, r#type) {
+                ("types", None) => Rule::Types(
+                    value
+                        .split('+')
+                        .map(|t| t.trim().parse())
+                        .collect::<Result<_, _>>()?,
+                ),
+                ("clients", None) => {
+                    let (min, max) = parse_range(value)
+                        .ok_or_else(|| format!("invalid client range '{}'", value))?;
+                    Rule::Clients { min, max }
+                }
+                ("amount", r#type) => {
+                    let (min, max) = parse_range(value)
+                        .ok_or_else(|| format!("invalid amount range '{}'", value))?;
+                    Rule::Amount { r#type, min, max }
+                }
+                _ => return Err(format!("unknown rule '{}'", rule.trim())),
+            };
+            rules.push(rule);
+        }
+        Ok(Rules(rules))
+    }
+}
+
+/// Parses a range written as `<min>..<max>`, where either end may be left
+/// out, but not both.
+fn parse_range<T: FromStr + PartialOrd>(s: &str) -> Option<(Option<T>, Option<T>)> {
+    let (min, max) = s.split_once("..")?;
+    let bound = |s: &str| match s.trim() {
+        "" => Some(None),
+        s => s.parse().ok().map(Some),
+    };
+    match (bound(min)?, bound(max)?) {
+        (None, None) => None,
+        (Some(min), Some(max)) if min > max => None,
+        range => Some(range),
+    }
+}
+
+/// The validators added to an `Engine`, in the order they were added. Like
+/// `Handlers`, they are shared rather than copied.
+#[derive(Clone, Default)]
+pub(crate) struct Validators(Vec<Arc<dyn Validator>>);
+
+impl Validators {
+    pub(crate) fn push(&mut self, validator: Arc<dyn Validator>) {
+        self.0.push(validator);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Validator for Validators {
+    fn validate(&self, record: &InputRecord) -> Result<(), RejectReason> {
+        self.0.iter().try_for_each(|v| v.validate(record))
+    }
+}
+
+impl fmt::Debug for Validators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Validators({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Rule, Rules, Validator};
+    use crate::amount::Amount;
+    use crate::engine::RejectReason;
+    use crate::id::ClientId;
+    use crate::input::{make_input_record, TransactionType};
+    use csv::StringRecord;
+
+    #[test]
+    fn test_parse_rules() {
+        let rules: Rules = "types=deposit+Withdrawal, clients=1..5000,amount:withdrawal=..100.5"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rules.0,
+            [
+                Rule::Types(vec![TransactionType::Deposit, TransactionType::Withdrawal]),
+                Rule::Clients {
+                    min: Some(ClientId::new(1)),
+                    max: Some(ClientId::new(5000)),
+                },
+                Rule::Amount {
+                    r#type: Some(TransactionType::Withdrawal),
+                    min: None,
+                    max: Some(Amount::from_scaled(1_005_000)),
+                },
+            ]
+        );
+        for s in [
+            "",
+            "types",
+            "types=bogus",
+            "clients=5",
+            "clients=..",
+            "clients=9..1",
+            "amount:bogus=1..",
+            "clients:deposit=1..",
+            "tx=1..2",
+        ] {
+            assert!(s.parse::<Rules>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules: Rules = "types=deposit+withdrawal+dispute,clients=..99,amount:deposit=1..10"
+            .parse()
+            .unwrap();
+        let validate =
+            |row: Vec<&str>| rules.validate(&make_input_record(&StringRecord::from(row)).unwrap());
+        assert_eq!(validate(vec!["deposit", "1", "1", "10"]), Ok(()));
+        assert_eq!(validate(vec!["withdrawal", "1", "2", "50"]), Ok(()));
+        assert_eq!(validate(vec!["dispute", "1", "1", ""]), Ok(()));
+        assert_eq!(
+            validate(vec!["deposit", "1", "3", "0.5"]),
+            Err(RejectReason::AmountOutOfRange)
+        );
+        assert_eq!(
+            validate(vec!["deposit", "100", "4", "1"]),
+            Err(RejectReason::ClientOutOfRange)
+        );
+        assert_eq!(
+            validate(vec!["chargeback", "1", "1", ""]),
+            Err(RejectReason::TypeNotAllowed)
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use super::error::Error;
+use super::output::{dump_result_to_writer, OutputFormat, RejectedRecord};
+use super::{process_reader_with, validate_reader_with, ProcessOptions};
+use serde::Serialize;
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+/// What `validate_csv` hands back to JavaScript: how many rows there were,
+/// how many of them could be parsed, and every row that could not be.
+#[derive(Debug, Serialize)]
+pub struct Validation {
+    pub rows: u64,
+    pub valid: u64,
+    pub errors: Vec<RejectedRecord>,
+}
+
+/// What `process_csv` hands back to JavaScript: the balances of every
+/// client, exactly as `--format json` writes them, and every row that was
+/// either invalid or rejected by the engine.
+#[derive(Debug, Serialize)]
+pub struct Preview {
+    pub records: u64,
+    pub balances: Value,
+    pub rejects: Vec<RejectedRecord>,
+}
+
+/// Checks that every row of a CSV file is well formed, see
+/// `validate_reader_with`.
+pub fn validate(bytes: &[u8]) -> Result<Validation, Error> {
+    let validated = validate_reader_with(bytes, &ProcessOptions::default())?;
+    Ok(Validation {
+        rows: validated.rows,
+        valid: validated.rows - validated.errors.len() as u64,
+        errors: validated.rejects,
+    })
+}
+
+/// Processes a CSV file with the default policies and returns the balances
+/// it comes to, along with whatever could not be applied.
+pub fn preview(bytes: &[u8]) -> Result<Preview, Error> {
+    let processed = process_reader_with(bytes, &ProcessOptions::default())?;
+    let mut buf = Vec::new();
+    dump_result_to_writer(&mut buf, processed.engine.balances(), OutputFormat::Json)?;
+    let balances = serde_json::from_slice(&buf).map_err(std::io::Error::from)?;
+    Ok(Preview {
+        records: processed.records,
+        balances,
+        rejects: processed.rejects,
+    })
+}
+
+/// Hands a result over to JavaScript as a plain object. Amounts are kept as
+/// the strings they are written out as, and maps become objects rather than
+/// `Map`s.
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Validates the bytes of a CSV file, such as an upload read in the
+/// browser, without processing it. Returns an object with `rows`,
+/// `valid` and `errors`, the latter holding the `line`, `raw` row and
+/// `reason` of every invalid row.
+#[wasm_bindgen(js_name = validateCsv)]
+pub fn validate_csv(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let validation = validate(bytes).map_err(|e| JsError::new(&e.to_string()))?;
+    to_js(&validation)
+}
+
+/// Processes the bytes of a CSV file and returns an object with the
+/// number of `records` read, the `balances` of every client and the
+/// `rejects`, so the outcome of an upload can be previewed before it is
+/// submitted.
+#[wasm_bindgen(js_name = processCsv)]
+pub fn process_csv(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let preview = preview(bytes).map_err(|e| JsError::new(&e.to_string()))?;
+    to_js(&preview)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{preview, validate};
+
+    const INPUT: &[u8] = b"type,client,tx,amount\n\
+        deposit,1,1,2.5\n\
+        withdrawal,1,2,x\n\
+        withdrawal,2,3,1.0\n";
+
+    #[test]
+    fn test_validate() {
+        let validation = validate(INPUT).unwrap();
+        assert_eq!(validation.rows, 3);
+        assert_eq!(validation.valid, 2);
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].line, Some(3));
+    }
+
+    #[test]
+    fn test_preview() {
+        let preview = preview(INPUT).unwrap();
+        assert_eq!(preview.records, 3);
+        let balances = preview.balances.as_array().unwrap();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0]["client"], 1);
+        // One invalid row and one withdrawal without the funds for it.
+        assert_eq!(preview.rejects.len(), 2);
+    }
+}
@@ -0,0 +1,281 @@
+use super::engine::Engine;
+use super::error::Error;
+use super::input::InputFormat;
+use super::output::dump_run_report_to_path;
+use super::{read_records, ProcessOptions, Processed, RunReport};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tracing::{info, info_span, warn};
+
+/// Where to look for input files, and where to put them once they are
+/// processed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// The directory files are dropped into. Only files right in it are
+    /// picked up, not those in directories below it.
+    pub dir: PathBuf,
+    /// Where processed files are moved to, along with their run reports.
+    pub archive: PathBuf,
+    /// How long the directory has to be left alone before the files in it
+    /// are picked up, so a file that is still being written is not read
+    /// halfway.
+    pub settle: Duration,
+}
+
+impl WatchOptions {
+    /// Watches `dir`, archiving files to `archive` within it.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        let dir = dir.into();
+        WatchOptions {
+            archive: dir.join("archive"),
+            dir,
+            settle: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Notify errors are passed on as I/O errors, just like Kafka ones.
+fn notify_error(e: notify::Error) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+/// Processes every input file dropped into the directory, one after the
+/// other and in the order of their names, applying them all to `engine`
+/// just as if they had been given in a single run. A file is picked up once
+/// nothing in the directory changed for `settle`, so files are best written
+/// elsewhere and moved in when they are done. Files that are already there
+/// are processed right away. Only files with the extension of the input
+/// format, such as `.csv`, are picked up.
+///
+/// Once a file is processed, the engine is handed to `done` along with the
+/// report of the file, which would typically save the engine to its store
+/// or to a snapshot. Only once it succeeds is the file moved to the archive,
+/// with its report next to it as `<file>.report.json`, so after a crash the
+/// file is processed again. Using a `Journal` keeps its transactions from
+/// being applied twice.
+///
+/// A file that cannot be processed, such as one with an invalid row in the
+/// strict `ErrorMode`, is left where it is, its report is written to the
+/// archive all the same, and the error is returned, with `engine` holding
+/// whatever of the file was applied before it. So is an error of `done`.
+/// Other than that, this never returns.
+pub fn watch<F>(
+    engine: &mut Engine,
+    options: &WatchOptions,
+    process: &ProcessOptions,
+    mut done: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&mut Engine, &RunReport) -> Result<(), Error>,
+{
+    let (sender, changes) = mpsc::channel();
+    let mut watcher: RecommendedWatcher =
+        notify::recommended_watcher(sender).map_err(notify_error)?;
+    watcher
+        .watch(&options.dir, RecursiveMode::NonRecursive)
+        .map_err(notify_error)?;
+    info!(dir = %options.dir.display(), "watching for input files");
+
+    process_pending(engine, options, process, &mut done)?;
+    loop {
+        // Wait for anything to change, then for the directory to settle.
+        match changes.recv() {
+            Ok(Err(e)) => warn!(error = %e, "could not watch the directory"),
+            Ok(Ok(_)) => (),
+            Err(_) => return Err(notify_error(notify::Error::generic("the watcher stopped"))),
+        }
+        loop {
+            match changes.recv_timeout(options.settle) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(notify_error(notify::Error::generic("the watcher stopped")))
+                }
+            }
+        }
+        process_pending(engine, options, process, &mut done)?;
+    }
+}
+
+/// Processes and archives every input file in the directory right now,
+/// see `watch`, and returns how many there were.
+fn process_pending<F>(
+    engine: &mut Engine,
+    options: &WatchOptions,
+    process: &ProcessOptions,
+    done: &mut F,
+) -> Result<usize, Error>
+where
+    F: FnMut(&mut Engine, &RunReport) -> Result<(), Error>,
+{
+    let files = pending_files(&options.dir, extension(process.format))?;
+    for path in &files {
+        let _span = info_span!("file", path = %path.display()).entered();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        fs::create_dir_all(&options.archive)
+            .map_err(|e| Error::from(e).in_file(&options.archive))?;
+        let report_path = options.archive.join(format!("{}.report.json", name));
+
+        let (processed, outcome) = process_file(std::mem::take(engine), path, process);
+        let report = processed.run_report();
+        *engine = processed.engine;
+        if let Err(e) = outcome {
+            dump_run_report_to_path(&report_path, &RunReport::failed(&e))?;
+            return Err(e);
+        }
+        done(engine, &report)?;
+        fs::rename(path, archive_path(&options.archive, &name))
+            .map_err(|e| Error::from(e).in_file(path))?;
+        dump_run_report_to_path(&report_path, &report)?;
+        info!(
+            records = report.records,
+            rejected = report.rejected,
+            invalid = report.invalid,
+            "processed file"
+        );
+    }
+    Ok(files.len())
+}
+
+/// Applies every record of the file at `path` to `engine`. Unlike
+/// `process_files_from`, the engine comes back even if the file cannot be
+/// processed, along with the error.
+fn process_file(
+    engine: Engine,
+    path: &Path,
+    options: &ProcessOptions,
+) -> (Processed, Result<(), Error>) {
+    let mut processed = Processed::new(engine, options);
+    let outcome = File::open(path)
+        .map_err(|e| Error::from(e).in_file(path))
+        .and_then(|file| {
+            for (source, record) in read_records(file, options) {
+                processed.push(source, record.map_err(|e| e.in_file(path)))?;
+            }
+            Ok(())
+        });
+    (processed, outcome)
+}
+
+/// The extension of the files of `format`.
+fn extension(format: InputFormat) -> &'static str {
+    match format {
+        InputFormat::Csv => "csv",
+        InputFormat::JsonLines => "jsonl",
+        #[cfg(feature = "parquet")]
+        InputFormat::Parquet => "parquet",
+        #[cfg(feature = "xml")]
+        InputFormat::Xml => "xml",
+    }
+}
+
+/// The files in `dir` with the extension given, in any case, sorted by
+/// name.
+fn pending_files(dir: &Path, extension: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| Error::from(e).in_file(dir))? {
+        let path = entry.map_err(|e| Error::from(e).in_file(dir))?.path();
+        let matches = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case(extension));
+        if matches && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where to archive a file called `name`, without replacing one archived
+/// before under the same name: a later one gets a number added, such as
+/// `day.csv.1`.
+fn archive_path(archive: &Path, name: &str) -> PathBuf {
+    let mut path = archive.join(name);
+    let mut n = 0;
+    while path.exists() {
+        n += 1;
+        path = archive.join(format!("{}.{}", name, n));
+    }
+    path
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{process_pending, WatchOptions};
+    use crate::amount::Amount;
+    use crate::engine::{Engine, EngineConfig, ErrorMode};
+    use crate::ProcessOptions;
+    use std::fs;
+
+    #[test]
+    fn test_process_pending() {
+        let dir = std::env::temp_dir().join(format!("payments-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("2.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,1.5\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("1.csv"),
+            "type,client,tx,amount\ndeposit,1,1,4\nbogus,1,9,1\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a transaction").unwrap();
+        let options = WatchOptions::new(&dir);
+
+        let mut engine = Engine::new();
+        let mut reports = Vec::new();
+        let processed = process_pending(
+            &mut engine,
+            &options,
+            &ProcessOptions::default(),
+            &mut |engine, report| {
+                reports.push((engine.balances()[0].available, report.invalid));
+                Ok(())
+            },
+        );
+        assert_eq!(processed.unwrap(), 2);
+        assert_eq!(
+            reports,
+            [
+                (Amount::from_scaled(40_000), 1),
+                (Amount::from_scaled(25_000), 0)
+            ]
+        );
+        assert!(dir.join("archive/1.csv").is_file());
+        assert!(dir.join("archive/2.csv.report.json").is_file());
+        assert!(dir.join("notes.txt").is_file());
+
+        // A file of the same name is archived next to the first one, and a
+        // file that fails is left where it is.
+        fs::write(
+            dir.join("1.csv"),
+            "type,client,tx,amount\ndeposit,1,3,1\nbogus,1,9,1\n",
+        )
+        .unwrap();
+        let strict = ProcessOptions {
+            engine: EngineConfig {
+                error_mode: ErrorMode::Strict,
+                ..EngineConfig::default()
+            },
+            ..ProcessOptions::default()
+        };
+        let mut engine = Engine::with_config(strict.engine.clone());
+        let res = process_pending(&mut engine, &options, &strict, &mut |_, _| Ok(()));
+        assert!(res.is_err());
+        assert!(dir.join("1.csv").is_file());
+        let report = fs::read_to_string(dir.join("archive/1.csv.report.json")).unwrap();
+        assert!(report.contains("\"status\": \"failed\""));
+
+        fs::write(dir.join("1.csv"), "type,client,tx,amount\ndeposit,1,3,1\n").unwrap();
+        let res = process_pending(&mut engine, &options, &strict, &mut |_, _| Ok(()));
+        assert_eq!(res.unwrap(), 1);
+        assert!(dir.join("archive/1.csv.1").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+use payments::golden;
+use payments::ProcessOptions;
+
+/// Every scenario in `tests/golden`, see `payments::golden`. To add one,
+/// drop in an input file and run the tests with `UPDATE_GOLDEN=1` to have
+/// the expected files written, then check that they are right.
+#[test]
+fn test_golden_scenarios() {
+    golden::assert_dir("tests/golden", &ProcessOptions::default());
+}
+
+/// The same scenarios come out the same when processed in parallel.
+#[test]
+fn test_golden_scenarios_in_parallel() {
+    let options = ProcessOptions {
+        threads: 4,
+        relaxed_tx_checks: true,
+        ..ProcessOptions::default()
+    };
+    golden::assert_dir("tests/golden", &options);
+}